@@ -0,0 +1,85 @@
+//! Compares uploading all the documentation of a rebuilt release with uploading only the files
+//! that changed since its previous build.
+//!
+//! The files are stored in the storage configured by the environment, like the builds do, so
+//! this needs the database of `CRATESFYI_DATABASE_URL` to be migrated first.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use docs_rs::db::{add_changed_path_into_database, add_path_into_database, delete_version_files};
+use docs_rs::db::{Pool, PoolClient};
+use docs_rs::{Config, Metrics, Storage};
+use std::path::Path;
+use std::sync::Arc;
+
+const NAME: &str = "docs-rs-build-cache-bench";
+const VERSION: &str = "0.1.0";
+const FILES: usize = 200;
+/// One file out of `CHANGED_EVERY` changes between the builds
+const CHANGED_EVERY: usize = 10;
+
+/// Writes the documentation of a mid-sized crate, made of slightly different copies of the same
+/// page. The files changing between the builds are different for each `build`.
+fn write_docs(dir: &Path, html: &str, build: usize) {
+    for i in 0..FILES {
+        let build = if i % CHANGED_EVERY == 0 { build } else { 0 };
+        let content = format!("{}<!-- {} {} -->", html, i, build);
+        std::fs::write(dir.join(format!("struct.Item{}.html", i)), content).unwrap();
+    }
+}
+
+fn clean_up(conn: &mut PoolClient, storage: &Storage) {
+    delete_version_files(storage, NAME, VERSION).unwrap();
+    conn.execute("DELETE FROM build_cache WHERE crate_name = $1", &[&NAME])
+        .unwrap();
+}
+
+pub fn full_vs_incremental_upload(c: &mut Criterion) {
+    let config = Config::from_env().unwrap();
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let pool = Pool::new(&config, metrics.clone()).unwrap();
+    let storage = Storage::new(pool.clone(), metrics, &config).unwrap();
+    let mut conn = pool.get().unwrap();
+    clean_up(&mut conn, &storage);
+
+    let html = std::fs::read_to_string("benches/struct.CaptureMatches.html").unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    write_docs(dir.path(), &html, 0);
+    let prefix = format!("rustdoc/{}/{}", NAME, VERSION);
+    let total_size = html.len() * FILES;
+
+    let mut build = 0;
+    c.benchmark_group("build cache")
+        .throughput(Throughput::Bytes(total_size as u64))
+        .sample_size(10)
+        .bench_function("full", |b| {
+            b.iter(|| add_path_into_database(&storage, &prefix, dir.path()).unwrap());
+        })
+        .bench_function("incremental", |b| {
+            // The cache of the previous build
+            add_changed_path_into_database(&mut conn, &storage, NAME, VERSION, &prefix, dir.path())
+                .unwrap();
+            b.iter_batched(
+                || {
+                    build += 1;
+                    write_docs(dir.path(), &html, build);
+                },
+                |()| {
+                    add_changed_path_into_database(
+                        &mut conn,
+                        &storage,
+                        NAME,
+                        VERSION,
+                        &prefix,
+                        dir.path(),
+                    )
+                    .unwrap()
+                },
+                BatchSize::PerIteration,
+            );
+        });
+
+    clean_up(&mut conn, &storage);
+}
+
+criterion_group!(build_cache, full_vs_incremental_upload);
+criterion_main!(build_cache);
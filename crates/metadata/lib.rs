@@ -37,7 +37,7 @@
 //! # }
 //! ```
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::io;
 use std::path::Path;
 
@@ -95,6 +95,7 @@ pub enum MetadataError {
 /// targets = [ "x86_64-apple-darwin", "x86_64-pc-windows-msvc" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// math = true
 /// ```
 ///
 /// You can define one or more fields in your `Cargo.toml`.
@@ -128,6 +129,10 @@ pub struct Metadata {
     /// List of command line arguments for `rustdoc`.
     #[serde(default)]
     rustdoc_args: Vec<String>,
+
+    /// Whether to render the `$inline$` and `$$display$$` math of the documentation.
+    #[serde(default)]
+    math: bool,
 }
 
 /// The targets that should be built for a crate.
@@ -194,6 +199,19 @@ impl Metadata {
     /// [`DEFAULT_TARGETS`]. Otherwise, if `include_default_targets` is `false` and `targets`
     /// is unset, `other_targets` will be empty.
     pub fn targets(&self, include_default_targets: bool) -> BuildTargets<'_> {
+        self.targets_with_additional(include_default_targets, &[])
+    }
+
+    /// Return the targets that should be built, like [`Metadata::targets`], with
+    /// `additional_targets` built as well whenever [`DEFAULT_TARGETS`] are.
+    ///
+    /// Crates setting `targets` opted into specific targets, so they don't get the additional
+    /// targets either.
+    pub fn targets_with_additional<'a>(
+        &'a self,
+        include_default_targets: bool,
+        additional_targets: &'a [String],
+    ) -> BuildTargets<'a> {
         let default_target = self
             .default_target
             .as_deref()
@@ -211,7 +229,13 @@ impl Metadata {
             .map(|targets| targets.iter().map(String::as_str).collect());
         // Let people opt-in to only having specific targets
         let mut targets: HashSet<_> = if include_default_targets {
-            crate_targets.unwrap_or_else(|| DEFAULT_TARGETS.iter().copied().collect())
+            crate_targets.unwrap_or_else(|| {
+                DEFAULT_TARGETS
+                    .iter()
+                    .copied()
+                    .chain(additional_targets.iter().map(String::as_str))
+                    .collect()
+            })
         } else {
             crate_targets.unwrap_or_default()
         };
@@ -277,6 +301,53 @@ impl Metadata {
         map.insert("DOCS_RS", "1".into());
         map
     }
+
+    /// Return whether the math of the documentation should be rendered.
+    pub fn math(&self) -> bool {
+        self.math
+    }
+
+    /// Return the features of the crate that are enabled when building its documentation,
+    /// sorted by name.
+    ///
+    /// `features` is the `[features]` table of the crate, mapping each feature to what it
+    /// enables. Optional dependencies can be included as features enabling nothing, so they're
+    /// reported as enabled too.
+    pub fn enabled_features(&self, features: &HashMap<String, Vec<String>>) -> Vec<String> {
+        let mut queue: Vec<&str> = if self.all_features {
+            features.keys().map(String::as_str).collect()
+        } else {
+            let mut requested: Vec<&str> = self
+                .features
+                .iter()
+                .flatten()
+                .flat_map(|feature| feature.split(&[' ', ','][..]))
+                .filter(|feature| !feature.is_empty())
+                .collect();
+            if !self.no_default_features {
+                requested.push("default");
+            }
+            requested
+        };
+
+        let mut enabled = BTreeSet::new();
+        while let Some(feature) = queue.pop() {
+            // `dep:name` and `name/feature` enable the `name` dependency or feature, while
+            // `name?/feature` only enables a feature of `name` if it's already enabled.
+            let name = match feature.find('/') {
+                Some(idx) if feature[..idx].ends_with('?') => continue,
+                Some(idx) => &feature[..idx],
+                None => feature.strip_prefix("dep:").unwrap_or(feature),
+            };
+            if let Some(enables) = features.get(name) {
+                if enabled.insert(name) {
+                    queue.extend(enables.iter().map(String::as_str));
+                }
+            }
+        }
+
+        enabled.into_iter().map(String::from).collect()
+    }
 }
 
 impl std::str::FromStr for Metadata {
@@ -335,11 +406,13 @@ mod test_parsing {
             targets = [ "x86_64-apple-darwin", "x86_64-pc-windows-msvc" ]
             rustc-args = [ "--example-rustc-arg" ]
             rustdoc-args = [ "--example-rustdoc-arg" ]
+            math = true
         "#;
 
         let metadata = Metadata::from_str(manifest).unwrap();
 
         assert!(metadata.features.is_some());
+        assert!(metadata.math());
         assert!(metadata.all_features);
         assert!(metadata.no_default_features);
         assert!(metadata.default_target.is_some());
@@ -533,6 +606,40 @@ mod test_targets {
         } = metadata.targets(false);
         assert!(others.is_empty(), "{:?}", others);
     }
+
+    #[test]
+    fn additional_targets() {
+        let additional = vec![
+            "aarch64-unknown-linux-gnu".to_string(),
+            "x86_64-pc-windows-msvc".to_string(),
+        ];
+
+        // added to the default targets, without duplicates
+        let mut metadata = Metadata::default();
+        let BuildTargets {
+            default_target: default,
+            other_targets: others,
+        } = metadata.targets_with_additional(true, &additional);
+        assert_eq!(default, HOST_TARGET);
+        assert_eq!(others.len(), DEFAULT_TARGETS.len());
+        assert!(others.contains(&"aarch64-unknown-linux-gnu"));
+
+        // but not when the default targets aren't built
+        let BuildTargets {
+            other_targets: others,
+            ..
+        } = metadata.targets_with_additional(false, &additional);
+        assert!(others.is_empty(), "{:?}", others);
+
+        // or when the crate picked its own targets
+        metadata.targets = Some(vec!["i686-pc-windows-msvc".into()]);
+        let BuildTargets {
+            default_target: default,
+            other_targets: others,
+        } = metadata.targets_with_additional(true, &additional);
+        assert_eq!(default, "i686-pc-windows-msvc");
+        assert!(others.is_empty(), "{:?}", others);
+    }
 }
 
 #[cfg(test)]
@@ -678,4 +785,68 @@ mod test_calculations {
         ];
         assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
     }
+
+    #[test]
+    fn test_enabled_features() {
+        let features: HashMap<String, Vec<String>> = vec![
+            ("default", vec!["std", "derive"]),
+            ("std", vec!["alloc", "serde?/std"]),
+            ("alloc", vec![]),
+            ("derive", vec!["dep:serde", "serde_derive/deep"]),
+            ("full", vec!["std", "async"]),
+            ("async", vec!["tokio/rt"]),
+            ("serde", vec![]),
+            ("serde_derive", vec![]),
+            ("tokio", vec![]),
+            // features enabling each other
+            ("a", vec!["b"]),
+            ("b", vec!["a"]),
+        ]
+        .into_iter()
+        .map(|(name, enables)| {
+            (
+                name.to_string(),
+                enables.into_iter().map(String::from).collect(),
+            )
+        })
+        .collect();
+
+        let enabled = |metadata: Metadata| metadata.enabled_features(&features);
+
+        assert_eq!(
+            enabled(Metadata::default()),
+            vec!["alloc", "default", "derive", "serde", "serde_derive", "std"]
+        );
+        assert_eq!(
+            enabled(Metadata {
+                no_default_features: true,
+                ..Metadata::default()
+            }),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            enabled(Metadata {
+                features: Some(vec!["async alloc".into(), "a".into()]),
+                no_default_features: true,
+                ..Metadata::default()
+            }),
+            vec!["a", "alloc", "async", "b", "tokio"]
+        );
+        assert_eq!(
+            enabled(Metadata {
+                features: Some(vec!["std".into()]),
+                no_default_features: true,
+                ..Metadata::default()
+            }),
+            vec!["alloc", "std"]
+        );
+        assert_eq!(
+            enabled(Metadata {
+                all_features: true,
+                ..Metadata::default()
+            })
+            .len(),
+            features.len()
+        );
+    }
 }
@@ -2,12 +2,14 @@ use std::env;
 use std::fmt::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use docs_rs::db::{self, add_path_into_database, Pool, PoolClient};
-use docs_rs::utils::{remove_crate_priority, set_crate_priority};
+use docs_rs::db::{self, add_path_into_database, InstanceLock, InstanceRole, Pool, PoolClient};
+use docs_rs::utils::output::{BlacklistOutput, BuildStatsOutput, CrateUpdateOutput, ErrorOutput};
+use docs_rs::utils::{remove_crate_priority, set_crate_priority, CrateUpdate};
 use docs_rs::{
-    BuildQueue, Config, Context, DocBuilder, Index, Metrics, PackageKind, RustwideBuilder, Server,
-    Storage,
+    BuildQueue, Cdn, Config, Context, DocBuilder, Index, Metrics, PackageKind, RustwideBuilder,
+    Server, Storage,
 };
 use failure::{err_msg, Error, ResultExt};
 use once_cell::sync::OnceCell;
@@ -18,7 +20,18 @@ pub fn main() {
     let _ = dotenv::dotenv();
     logger_init();
 
-    if let Err(err) = CommandLine::from_args().handle_args() {
+    let args = CommandLine::from_args();
+    let json = args.prints_json();
+    if let Err(err) = args.handle_args() {
+        // Scripts reading the JSON output get the error as JSON too
+        if json {
+            eprintln!(
+                "{}",
+                serde_json::to_string(&ErrorOutput::new(&err)).unwrap()
+            );
+            std::process::exit(1);
+        }
+
         let mut msg = format!("Error: {}", err);
         for cause in err.iter_causes() {
             write!(msg, "\n\nCaused by:\n    {}", cause).unwrap();
@@ -104,6 +117,23 @@ enum CommandLine {
         #[structopt(subcommand)]
         subcommand: QueueSubcommand,
     },
+
+    /// Statistics about docs.rs. Without a subcommand, prints an overview of the instance:
+    /// crates, builds, queue, storage, coverage and the last runs of the periodic jobs
+    Stats {
+        /// Print the overview as JSON
+        #[structopt(long = "json")]
+        json: bool,
+
+        #[structopt(subcommand)]
+        subcommand: Option<StatsSubcommand>,
+    },
+
+    /// The build limits of crates
+    Limits {
+        #[structopt(subcommand)]
+        subcommand: LimitsSubcommand,
+    },
 }
 
 impl CommandLine {
@@ -116,6 +146,9 @@ impl CommandLine {
                 socket_addr,
                 reload_templates,
             } => {
+                // Only one web server runs on its own at a time, and the lock is held until it
+                // stops
+                let _lock = InstanceLock::acquire(&ctx.pool()?, InstanceRole::Web)?;
                 // Blocks indefinitely
                 let _ = Server::start(Some(&socket_addr), reload_templates, &ctx)?;
             }
@@ -131,6 +164,96 @@ impl CommandLine {
             }
             Self::Database { subcommand } => subcommand.handle_args(ctx)?,
             Self::Queue { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Stats {
+                subcommand: Some(subcommand),
+                ..
+            } => subcommand.handle_args(ctx)?,
+            Self::Stats {
+                subcommand: None,
+                json,
+            } => print_overview(ctx, json)?,
+            Self::Limits { subcommand } => subcommand.handle_args(ctx)?,
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the command prints its output as JSON, in which case its errors are
+    /// printed as JSON too.
+    fn prints_json(&self) -> bool {
+        match self {
+            Self::Database {
+                subcommand: DatabaseSubcommand::UpdateGithubFields { json, .. },
+            } => *json,
+            Self::Database {
+                subcommand:
+                    DatabaseSubcommand::Blacklist {
+                        command: BlacklistSubcommand::List { json },
+                    },
+            } => *json,
+            Self::Queue {
+                subcommand: QueueSubcommand::List { json, .. },
+            } => *json,
+            Self::Stats {
+                subcommand: Some(StatsSubcommand::Builds { json, .. }),
+                ..
+            } => *json,
+            Self::Stats {
+                subcommand: None,
+                json,
+            } => *json,
+            Self::Limits {
+                subcommand: LimitsSubcommand::List { json },
+            } => *json,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum StatsSubcommand {
+    /// Prints the median and 95th percentile of the duration and output size of recent builds
+    Builds {
+        /// How many days of builds to summarize
+        #[structopt(long = "days", default_value = "7")]
+        days: i32,
+
+        /// Print the statistics as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+}
+
+impl StatsSubcommand {
+    pub fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+        match self {
+            Self::Builds { days, json } => {
+                let summary = db::summarize_builds(&mut *ctx.conn()?, days)?;
+                if json {
+                    let output = BuildStatsOutput::new(days, &summary);
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+                    return Ok(());
+                }
+                println!("{} builds in the last {} days", summary.builds, days);
+
+                let format = |value: Option<f64>| {
+                    value.map_or_else(|| "-".to_string(), |value| format!("{:.0}", value))
+                };
+                println!("{:<20} {:>15} {:>15}", "", "median", "p95");
+                for (name, percentiles) in &[
+                    ("duration (ms)", summary.duration_ms),
+                    ("peak memory (bytes)", summary.peak_memory_bytes),
+                    ("files", summary.files_count),
+                    ("size (bytes)", summary.files_size_bytes),
+                ] {
+                    println!(
+                        "{:<20} {:>15} {:>15}",
+                        name,
+                        format(percentiles.median),
+                        format(percentiles.p95)
+                    );
+                }
+            }
         }
 
         Ok(())
@@ -157,11 +280,49 @@ enum QueueSubcommand {
         build_priority: i32,
     },
 
+    /// List the builds waiting in the queue, in the order they're built
+    List {
+        /// List the builds that failed all their attempts instead
+        #[structopt(long)]
+        failed: bool,
+        /// Only list the first builds
+        #[structopt(long)]
+        limit: Option<i64>,
+        /// Print the builds as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+
     /// Interactions with build queue priorities
     DefaultPriority {
         #[structopt(subcommand)]
         subcommand: PrioritySubcommand,
     },
+
+    /// Stop the daemons from starting new builds, letting the builds in progress finish
+    Lock {
+        /// Why the queue is locked, shown on the about page and the health endpoint
+        #[structopt(long)]
+        reason: Option<String>,
+    },
+
+    /// Let the daemons start new builds again
+    Unlock,
+
+    /// Lock the queue and wait for the builds in progress to finish, exiting with an error if
+    /// they're still in progress after the timeout, so that deploys can wait for the builders
+    /// to be idle
+    Drain {
+        /// How long to wait for the builds in progress to finish, in seconds
+        #[structopt(long, default_value = "3600")]
+        timeout: u64,
+        /// Why the queue is locked, shown on the about page and the health endpoint
+        #[structopt(long)]
+        reason: Option<String>,
+        /// Unlock the queue once the builds in progress finished, unless it was already locked
+        #[structopt(long)]
+        unlock_after: bool,
+    },
 }
 
 impl QueueSubcommand {
@@ -178,7 +339,92 @@ impl QueueSubcommand {
                 ctx.config()?.registry_url.as_deref(),
             )?,
 
+            Self::List {
+                failed: false,
+                limit,
+                json,
+            } => {
+                let pending = ctx.build_queue()?.pending_builds(limit)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&pending)?);
+                    return Ok(());
+                }
+                for build in pending {
+                    println!(
+                        "{}. {} {} (priority: {}, added: {}, attempts: {}{})",
+                        build.position,
+                        build.krate.name,
+                        build.krate.version,
+                        build.krate.priority,
+                        build.added_at.to_rfc3339(),
+                        build.attempts,
+                        if build.ready { "" } else { ", not ready yet" },
+                    );
+                }
+            }
+
+            Self::List {
+                failed: true,
+                limit,
+                json,
+            } => {
+                let mut failed = ctx.build_queue()?.failed_crates()?;
+                if let Some(limit) = limit {
+                    failed.truncate(limit.max(0) as usize);
+                }
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&failed)?);
+                    return Ok(());
+                }
+                for krate in failed {
+                    let last_attempt = krate
+                        .last_attempt
+                        .map_or_else(|| "never".into(), |time| time.to_rfc3339());
+                    println!(
+                        "{} {} ({} attempts, last attempt: {})",
+                        krate.name, krate.version, krate.attempts, last_attempt
+                    );
+                }
+            }
+
             Self::DefaultPriority { subcommand } => subcommand.handle_args(ctx)?,
+
+            Self::Lock { reason } => ctx.build_queue()?.lock(reason.as_deref())?,
+
+            Self::Unlock => {
+                if !ctx.build_queue()?.unlock()? {
+                    println!("The build queue was not locked");
+                }
+            }
+
+            Self::Drain {
+                timeout,
+                reason,
+                unlock_after,
+            } => {
+                let queue = ctx.build_queue()?;
+                let drain = queue.drain(
+                    reason.as_deref(),
+                    Duration::from_secs(timeout),
+                    Duration::from_secs(5),
+                )?;
+                if drain.lock.is_none() {
+                    println!("The build queue was already locked, and stays locked");
+                }
+                if !drain.idle {
+                    return Err(err_msg(format!(
+                        "{} builds still in progress after {} seconds, the queue stays locked",
+                        queue.builds_in_progress()?,
+                        timeout
+                    )));
+                }
+                // Only the lock taken by the drain is released, not one taken before or since
+                if let (true, Some(lock)) = (unlock_after, &drain.lock) {
+                    if !queue.release_lock(lock)? {
+                        println!("The build queue was locked again since, and stays locked");
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -233,13 +479,19 @@ struct Build {
     #[structopt(name = "SKIP_IF_EXISTS", short = "s", long = "skip")]
     skip_if_exists: bool,
 
+    /// Skips the releases whose sources didn't change since their last successful build, and
+    /// keeps the build directories to reuse the compiled dependencies in the next builds
+    #[structopt(long = "incremental")]
+    incremental: bool,
+
     #[structopt(subcommand)]
     subcommand: BuildSubcommand,
 }
 
 impl Build {
     pub fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
-        self.subcommand.handle_args(ctx, self.skip_if_exists)
+        self.subcommand
+            .handle_args(ctx, self.skip_if_exists, self.incremental)
     }
 }
 
@@ -267,6 +519,17 @@ enum BuildSubcommand {
         local: Option<PathBuf>,
     },
 
+    /// Builds documentation for the crate in a local directory, to preview it
+    LocalCrate {
+        /// Path of the crate
+        #[structopt(name = "PATH")]
+        path: PathBuf,
+
+        /// Keep the build directory of the crate after building it
+        #[structopt(long = "keep-build-dir")]
+        keep_build_dir: bool,
+    },
+
     /// update the currently installed rustup toolchain
     UpdateToolchain {
         /// Update the toolchain only if no toolchain is currently installed
@@ -285,11 +548,20 @@ enum BuildSubcommand {
 }
 
 impl BuildSubcommand {
-    pub fn handle_args(self, ctx: BinContext, skip_if_exists: bool) -> Result<(), Error> {
+    pub fn handle_args(
+        self,
+        ctx: BinContext,
+        skip_if_exists: bool,
+        incremental: bool,
+    ) -> Result<(), Error> {
         let docbuilder = DocBuilder::new(ctx.config()?, ctx.pool()?, ctx.build_queue()?);
 
         let rustwide_builder = || -> Result<RustwideBuilder, Error> {
-            let mut builder = RustwideBuilder::init(&ctx)?;
+            let mut builder = if incremental {
+                RustwideBuilder::init_incremental(&ctx)?
+            } else {
+                RustwideBuilder::init(&ctx)?
+            };
             builder.set_skip_build_if_exists(skip_if_exists);
             Ok(builder)
         };
@@ -328,6 +600,28 @@ impl BuildSubcommand {
                 }
             }
 
+            Self::LocalCrate {
+                path,
+                keep_build_dir,
+            } => {
+                let mut builder = rustwide_builder()?;
+                builder.set_keep_build_dir(keep_build_dir);
+
+                let release = builder
+                    .build_local_package(&path)
+                    .context("Building documentation failed")?;
+                if !release.successful {
+                    return Err(err_msg(format!(
+                        "failed to build {} {}, see the build log at http://localhost:3000/crate/{}/{}/builds",
+                        release.name, release.version, release.name, release.version,
+                    )));
+                }
+                println!(
+                    "Documentation built at http://localhost:3000/{}/{}/",
+                    release.name, release.version
+                );
+            }
+
             Self::UpdateToolchain { only_first_time } => {
                 if only_first_time {
                     let mut conn = ctx
@@ -372,10 +666,31 @@ enum DatabaseSubcommand {
     },
 
     /// Updates github stats for crates.
-    UpdateGithubFields,
+    UpdateGithubFields {
+        /// Only log the summary of the update, not every repository
+        #[structopt(long = "quiet", short = "q")]
+        quiet: bool,
+
+        /// Only fill the columns of the repositories that are NULL, like after adding a column,
+        /// leaving the other stats untouched
+        #[structopt(long = "only-null")]
+        only_null: bool,
+
+        /// Only update the repositories of these crates, however recently they were updated
+        #[structopt(long = "crate", name = "CRATE_NAME", conflicts_with = "only-null")]
+        crates: Vec<String>,
+
+        /// Print the updates of the crates passed with `--crate` as JSON
+        #[structopt(long = "json", requires = "CRATE_NAME")]
+        json: bool,
+    },
 
     /// Backfill GitHub stats for crates.
-    BackfillGithubStats,
+    BackfillGithubStats {
+        /// Only log the summary of the backfill, not every release
+        #[structopt(long = "quiet", short = "q")]
+        quiet: bool,
+    },
 
     /// Updates info for a crate from the registry's API
     UpdateCrateRegistryFields {
@@ -395,6 +710,27 @@ enum DatabaseSubcommand {
     /// Updates monthly release activity chart
     UpdateReleaseActivity,
 
+    /// Copies the files stored in the database to S3
+    MoveToS3 {
+        /// Only copy the files whose path starts with this prefix
+        #[structopt(long = "prefix", default_value = "")]
+        prefix: String,
+    },
+
+    /// Compresses the files stored uncompressed in the database
+    CompressFiles {
+        /// How many files to compress in a single transaction
+        #[structopt(long = "batch-size", default_value = "1000")]
+        batch_size: i64,
+    },
+
+    /// Stores the content of the identical files stored in the database only once
+    DeduplicateFiles {
+        /// How many files to deduplicate in a single transaction
+        #[structopt(long = "batch-size", default_value = "1000")]
+        batch_size: i64,
+    },
+
     /// Remove documentation from the database
     Delete {
         #[structopt(subcommand)]
@@ -413,6 +749,10 @@ enum DatabaseSubcommand {
         /// Don't actually resolve the inconsistencies, just log them
         #[structopt(long)]
         dry_run: bool,
+
+        /// Only synchronize this crate, instead of walking the whole index
+        #[structopt(long = "crate", name = "CRATE_NAME")]
+        crate_name: Option<String>,
     },
 }
 
@@ -424,16 +764,55 @@ impl DatabaseSubcommand {
                     .context("Failed to run database migrations")?;
             }
 
-            Self::UpdateGithubFields => {
-                docs_rs::utils::GithubUpdater::new(ctx.config()?, ctx.pool()?)?
-                    .ok_or_else(|| failure::format_err!("missing GitHub token"))?
-                    .update_all_crates()?;
+            Self::UpdateGithubFields {
+                quiet,
+                only_null,
+                crates,
+                json,
+            } => {
+                let pool = ctx.pool()?;
+                let updater = github_updater(ctx, quiet)?;
+                if !crates.is_empty() {
+                    let names: Vec<&str> = crates.iter().map(String::as_str).collect();
+                    let updates = updater.update_crates(&mut *pool.get()?, &names);
+                    if json {
+                        let output: Vec<_> = names
+                            .iter()
+                            .zip(&updates)
+                            .map(|(name, update)| CrateUpdateOutput::new(name, update))
+                            .collect();
+                        println!("{}", serde_json::to_string_pretty(&output)?);
+                    } else {
+                        for (name, result) in names.iter().zip(&updates) {
+                            match result {
+                                CrateUpdate::Updated { repository } => {
+                                    println!("{}: updated {}", name, repository)
+                                }
+                                CrateUpdate::NoRepository => println!("{}: no repository", name),
+                                CrateUpdate::UnknownCrate => println!("{}: unknown crate", name),
+                                CrateUpdate::Failed(err) => println!("{}: failed: {}", name, err),
+                            }
+                        }
+                    }
+
+                    let failed = updates
+                        .iter()
+                        .filter(|update| {
+                            matches!(update, CrateUpdate::UnknownCrate | CrateUpdate::Failed(_))
+                        })
+                        .count();
+                    if failed > 0 {
+                        failure::bail!("failed to update {} of the {} crates", failed, names.len());
+                    }
+                } else if only_null {
+                    updater.only_null().update_all_crates()?;
+                } else {
+                    updater.update_all_crates()?;
+                }
             }
 
-            Self::BackfillGithubStats => {
-                docs_rs::utils::GithubUpdater::new(ctx.config()?, ctx.pool()?)?
-                    .ok_or_else(|| failure::format_err!("missing GitHub token"))?
-                    .backfill_repositories()?;
+            Self::BackfillGithubStats { quiet } => {
+                github_updater(ctx, quiet)?.backfill_repositories()?;
             }
 
             Self::UpdateCrateRegistryFields { name } => {
@@ -457,29 +836,147 @@ impl DatabaseSubcommand {
                     .context("Failed to update release activity")?
             }
 
+            Self::MoveToS3 { prefix } => {
+                let s3 = Storage::new_s3(ctx.pool()?, ctx.metrics()?, &*ctx.config()?)?;
+                let copied = s3
+                    .copy_from_database(&mut *ctx.conn()?, &prefix)
+                    .context("Failed to copy the files to S3")?;
+                println!("copied {} files to S3", copied);
+            }
+
+            Self::CompressFiles { batch_size } => {
+                let compressed = ctx
+                    .storage()?
+                    .compress_files(batch_size)
+                    .context("Failed to compress the files")?;
+                println!("compressed {} files", compressed);
+            }
+
+            Self::DeduplicateFiles { batch_size } => {
+                let stats = ctx
+                    .storage()?
+                    .deduplicate_files(batch_size)
+                    .context("Failed to deduplicate the files")?;
+                let percent = if stats.size_before > 0 {
+                    stats.saved_bytes() as f64 * 100.0 / stats.size_before as f64
+                } else {
+                    0.0
+                };
+                println!(
+                    "deduplicated {} files: {} bytes are now stored in {} bytes ({:.1}% saved)",
+                    stats.files, stats.size_before, stats.size_after, percent
+                );
+            }
+
             Self::Delete {
                 command: DeleteSubcommand::Version { name, version },
-            } => db::delete_version(&mut *ctx.conn()?, &*ctx.storage()?, &name, &version)
-                .context("failed to delete the crate")?,
+            } => {
+                db::delete_version(&mut *ctx.conn()?, &*ctx.storage()?, &name, &version)
+                    .context("failed to delete the crate")?;
+                ctx.cdn()?.purge_release(&name, &version);
+            }
             Self::Delete {
                 command: DeleteSubcommand::Crate { name },
-            } => db::delete_crate(&mut *ctx.conn()?, &*ctx.storage()?, &name)
-                .context("failed to delete the crate")?,
+            } => {
+                db::delete_crate(&mut *ctx.conn()?, &*ctx.storage()?, &name)
+                    .context("failed to delete the crate")?;
+                ctx.cdn()?.purge_crate(&name);
+            }
             Self::Blacklist { command } => command.handle_args(ctx)?,
 
             #[cfg(feature = "consistency_check")]
-            Self::Synchronize { dry_run } => {
-                docs_rs::utils::consistency::run_check(&mut *ctx.conn()?, &*ctx.index()?, dry_run)?;
+            Self::Synchronize {
+                dry_run,
+                crate_name,
+            } => {
+                let report = docs_rs::utils::consistency::run_check(
+                    &mut *ctx.conn()?,
+                    &*ctx.index()?,
+                    &*ctx.build_queue()?,
+                    crate_name.as_deref(),
+                    dry_run,
+                )?;
+                println!(
+                    "{} {} releases, {} {} yanked flags, {} crates and {} releases no longer in the index",
+                    if dry_run { "would queue" } else { "queued" },
+                    report.queued.len(),
+                    if dry_run { "would fix" } else { "fixed" },
+                    report.yanked.len(),
+                    report.crates_missing_upstream.len(),
+                    report.releases_missing_upstream.len(),
+                );
             }
         }
         Ok(())
     }
 }
 
+/// Prints an overview of the instance, for `cratesfyi stats`.
+fn print_overview(ctx: BinContext, json: bool) -> Result<(), Error> {
+    let overview = db::instance_overview(&mut *ctx.conn()?, &*ctx.config()?)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&overview)?);
+        return Ok(());
+    }
+
+    println!("crates: {}", overview.crates);
+    println!("releases: {}", overview.releases);
+    println!(
+        "builds: {} successful, {} failed",
+        overview.successful_builds, overview.failed_builds
+    );
+    println!("queue:");
+    for depth in &overview.queue {
+        println!("  priority {}: {} builds", depth.priority, depth.builds);
+    }
+    println!("storage:");
+    for backend in &overview.storage {
+        println!(
+            "  {}: {} files, {} bytes",
+            backend.backend, backend.files, backend.bytes
+        );
+    }
+    let percent = |value: Option<f64>| {
+        value.map_or_else(|| "-".to_string(), |value| format!("{:.1}%", value))
+    };
+    println!(
+        "coverage: {} documented, {} with examples",
+        percent(overview.coverage.documented),
+        percent(overview.coverage.with_examples)
+    );
+    for (name, last_run) in &[
+        ("last registry poll", overview.last_registry_poll),
+        ("last GitHub sweep", overview.last_github_sweep),
+        ("last toolchain update", overview.last_toolchain_update),
+    ] {
+        match last_run {
+            Some(run) => println!("{}: {} ({}s ago)", name, run.at, run.age_secs),
+            None => println!("{}: never", name),
+        }
+    }
+
+    Ok(())
+}
+
+fn github_updater(ctx: BinContext, quiet: bool) -> Result<docs_rs::utils::GithubUpdater, Error> {
+    let config = ctx.config()?;
+    let mut updater = docs_rs::utils::GithubUpdater::new(config.clone(), ctx.pool()?)?
+        .ok_or_else(|| failure::format_err!("missing GitHub token"))?;
+    // Avoid opening the registry index when it's not needed
+    if config.github_updater_registry_fallback {
+        updater = updater.registry_fallback(&*ctx.index()?);
+    }
+    Ok(if quiet { updater.quiet() } else { updater })
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum BlacklistSubcommand {
     /// List all crates on the blacklist
-    List,
+    List {
+        /// Print the crates as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
 
     /// Add a crate to the blacklist
     Add {
@@ -500,11 +997,16 @@ impl BlacklistSubcommand {
     fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
         let mut conn = &mut *ctx.conn()?;
         match self {
-            Self::List => {
+            Self::List { json } => {
                 let crates = db::blacklist::list_crates(&mut conn)
                     .context("failed to list crates on blacklist")?;
 
-                println!("{}", crates.join("\n"));
+                if json {
+                    let output = BlacklistOutput { crates };
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+                } else {
+                    println!("{}", crates.join("\n"));
+                }
             }
 
             Self::Add { crate_name } => db::blacklist::add_crate(&mut conn, &crate_name)
@@ -517,6 +1019,50 @@ impl BlacklistSubcommand {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum LimitsSubcommand {
+    /// List the crates whose build limits are overridden
+    List {
+        /// Print the overrides as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+}
+
+impl LimitsSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+        match self {
+            Self::List { json } => {
+                let overrides = docs_rs::docbuilder::list_sandbox_overrides(&mut *ctx.conn()?)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&overrides)?);
+                    return Ok(());
+                }
+                let format = |value: Option<String>| value.unwrap_or_else(|| "default".into());
+                for krate in overrides {
+                    println!(
+                        "{}: memory {}, timeout {}, targets {}, docs size {}",
+                        krate.krate,
+                        format(
+                            krate
+                                .max_memory_bytes
+                                .map(|bytes| format!("{} bytes", bytes))
+                        ),
+                        format(krate.timeout_seconds.map(|secs| format!("{}s", secs))),
+                        format(krate.max_targets.map(|targets| targets.to_string())),
+                        format(
+                            krate
+                                .max_docs_size_bytes
+                                .map(|bytes| format!("{} bytes", bytes))
+                        ),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum DeleteSubcommand {
     /// Delete a whole crate
@@ -544,6 +1090,7 @@ struct BinContext {
     pool: OnceCell<Pool>,
     metrics: OnceCell<Arc<Metrics>>,
     index: OnceCell<Arc<Index>>,
+    cdn: OnceCell<Arc<Cdn>>,
 }
 
 impl BinContext {
@@ -555,6 +1102,7 @@ impl BinContext {
             pool: OnceCell::new(),
             metrics: OnceCell::new(),
             index: OnceCell::new(),
+            cdn: OnceCell::new(),
         }
     }
 
@@ -626,4 +1174,11 @@ impl Context for BinContext {
             })?
             .clone())
     }
+
+    fn cdn(&self) -> Result<Arc<Cdn>, Error> {
+        Ok(self
+            .cdn
+            .get_or_try_init::<_, Error>(|| Ok(Arc::new(Cdn::new(&*self.config()?)?)))?
+            .clone())
+    }
 }
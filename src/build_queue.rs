@@ -1,17 +1,104 @@
-use crate::db::Pool;
+use crate::db::{delete_version_files, Pool};
 use crate::error::Result;
-use crate::{Config, Metrics};
-use log::error;
+use crate::utils::REBUILD_PRIORITY;
+use crate::{Config, Metrics, Storage};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{debug, error, warn};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the worker building a release records that it's still alive, which has to be well
+/// under the configured time after which builds are considered abandoned.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Queues a release, taking `$1` as the name, `$2` the version, `$3` the priority, `$4` the
+/// registry and `$5` the maximum number of attempts.
+///
+/// Each release has at most one pending build: queueing it again instead raises the priority of
+/// the pending build to the highest of the two, and retries it from scratch if it failed all its
+/// attempts. Builds in progress aren't pending, so queueing a release while it's being built
+/// results in one more build afterwards. Returns whether a new row was inserted.
+const QUEUE_RELEASE: &str = "
+    INSERT INTO queue (name, version, priority, registry)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (name, version) WHERE started_at IS NULL DO UPDATE
+        SET priority = LEAST(queue.priority, EXCLUDED.priority),
+            attempt = CASE WHEN queue.attempt >= $5 THEN 0 ELSE queue.attempt END,
+            last_attempt = CASE WHEN queue.attempt >= $5 THEN NULL ELSE queue.last_attempt END
+    -- updated rows have their xmax set by the update
+    RETURNING xmax = 0 AS inserted;";
+
+/// Whether a pending build can be claimed: failed builds wait for the retry delay, `$3` seconds
+/// doubled after each failed attempt, and a release queued again while it's being built waits for
+/// the build to finish.
+const BUILD_IS_READY: &str = "
+    (
+        last_attempt IS NULL
+        OR last_attempt <= NOW() - make_interval(secs => $3 * 2 ^ LEAST(attempt - 1, 20))
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM queue AS building
+        WHERE building.name = queue.name
+            AND building.version = queue.version
+            AND building.id != queue.id
+            AND building.started_at IS NOT NULL
+    )";
+
+/// The order the ready builds are claimed in.
+const DEQUEUE_ORDER: &str = "priority ASC, attempt ASC, id ASC";
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
-pub(crate) struct QueuedCrate {
+pub struct QueuedCrate {
     #[serde(skip)]
-    id: i32,
-    pub(crate) name: String,
-    pub(crate) version: String,
-    pub(crate) priority: i32,
-    pub(crate) registry: Option<String>,
+    pub(crate) id: i32,
+    pub name: String,
+    pub version: String,
+    pub priority: i32,
+    pub registry: Option<String>,
+}
+
+/// Why the build queue was locked, and since when. Stored in the `queue_locked` row of `config`
+/// while the queue is locked.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct QueueLock {
+    pub reason: Option<String>,
+    pub locked_at: DateTime<Utc>,
+}
+
+/// The outcome of draining the queue with `BuildQueue::drain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueDrain {
+    /// Whether the builds in progress all finished
+    pub idle: bool,
+    /// The lock taken to drain the queue, or `None` if it was already locked
+    pub lock: Option<QueueLock>,
+}
+
+/// A build that failed all its attempts, and stays in the queue until the release is queued
+/// again.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+pub struct FailedCrate {
+    pub name: String,
+    pub version: String,
+    pub attempts: i32,
+    pub last_attempt: Option<DateTime<Utc>>,
+}
+
+/// A build waiting in the queue, as listed by `cratesfyi queue list`. It's serialized as the
+/// `QueuedCrate` it builds, with the state of the build alongside.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PendingBuild {
+    /// The position of the build in the order the daemons claim the builds, starting from 1
+    pub position: usize,
+    #[serde(flatten)]
+    pub krate: QueuedCrate,
+    pub added_at: DateTime<Utc>,
+    pub attempts: i32,
+    /// Whether the build can be claimed now, rather than waiting for its retry delay, for the
+    /// build in progress of the same release or for the queue to be unlocked
+    pub ready: bool,
 }
 
 #[derive(Debug)]
@@ -19,14 +106,28 @@ pub struct BuildQueue {
     db: Pool,
     metrics: Arc<Metrics>,
     max_attempts: i32,
+    /// How long to wait before retrying a failed build in seconds, doubled after each attempt.
+    retry_delay: f64,
+    /// Identifies this process in the `worker_id` column of the builds it claims.
+    worker_id: String,
+    /// How long a build can go without a heartbeat before it's considered abandoned, in seconds.
+    stale_build_timeout: f64,
 }
 
 impl BuildQueue {
     pub fn new(db: Pool, metrics: Arc<Metrics>, config: &Config) -> Self {
+        let worker_id = match std::env::var("HOSTNAME") {
+            Ok(hostname) => format!("{}-{}", hostname, std::process::id()),
+            Err(_) => std::process::id().to_string(),
+        };
+
         BuildQueue {
             db,
             metrics,
             max_attempts: config.build_attempts.into(),
+            retry_delay: config.build_retry_delay as f64,
+            worker_id,
+            stale_build_timeout: config.build_stale_timeout as f64,
         }
     }
 
@@ -38,12 +139,152 @@ impl BuildQueue {
         registry: Option<&str>,
     ) -> Result<()> {
         self.db.get()?.execute(
-            "INSERT INTO queue (name, version, priority, registry) VALUES ($1, $2, $3, $4);",
-            &[&name, &version, &priority, &registry],
+            QUEUE_RELEASE,
+            &[&name, &version, &priority, &registry, &self.max_attempts],
         )?;
         Ok(())
     }
 
+    /// Adds multiple releases to the queue in a single transaction, in the order they're given.
+    /// Releases that are already queued keep their pending build, with the highest of the two
+    /// priorities. Returns the number of releases added.
+    pub(crate) fn add_crates(
+        &self,
+        crates: &[(&str, &str, i32)],
+        registry: Option<&str>,
+    ) -> Result<usize> {
+        let mut conn = self.db.get()?;
+        let mut transaction = conn.transaction()?;
+        let mut added = 0;
+        for (name, version, priority) in crates {
+            let inserted: bool = transaction
+                .query_one(
+                    QUEUE_RELEASE,
+                    &[name, version, priority, &registry, &self.max_attempts],
+                )?
+                .get("inserted");
+            if inserted {
+                added += 1;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(added as usize)
+    }
+
+    /// Stops the workers from starting new builds until the queue is unlocked, without stopping
+    /// the builds in progress nor the daemon. Locking the queue again replaces the reason.
+    pub fn lock(&self, reason: Option<&str>) -> Result<()> {
+        let lock = QueueLock {
+            reason: reason.map(String::from),
+            locked_at: Utc::now(),
+        };
+        self.db.get()?.execute(
+            "INSERT INTO config (name, value) VALUES ('queue_locked', $1)
+             ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value;",
+            &[&serde_json::to_value(&lock)?],
+        )?;
+        Ok(())
+    }
+
+    /// Locks the queue unless it's already locked, returning the lock it took.
+    fn try_lock(&self, reason: Option<&str>) -> Result<Option<QueueLock>> {
+        let lock = QueueLock {
+            reason: reason.map(String::from),
+            locked_at: Utc::now(),
+        };
+        let inserted = self.db.get()?.execute(
+            "INSERT INTO config (name, value) VALUES ('queue_locked', $1)
+             ON CONFLICT (name) DO NOTHING;",
+            &[&serde_json::to_value(&lock)?],
+        )?;
+        Ok(if inserted > 0 { Some(lock) } else { None })
+    }
+
+    /// Lets the workers start new builds again. Returns whether the queue was locked.
+    pub fn unlock(&self) -> Result<bool> {
+        let deleted = self
+            .db
+            .get()?
+            .execute("DELETE FROM config WHERE name = 'queue_locked';", &[])?;
+        Ok(deleted > 0)
+    }
+
+    /// Unlocks the queue if it's still locked by `lock`, and not by a lock taken since. Returns
+    /// whether the queue was unlocked.
+    pub fn release_lock(&self, lock: &QueueLock) -> Result<bool> {
+        let deleted = self.db.get()?.execute(
+            "DELETE FROM config WHERE name = 'queue_locked' AND value::jsonb = $1::jsonb;",
+            &[&serde_json::to_value(lock)?],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Returns why the queue is locked, if it is.
+    pub fn lock_state(&self) -> Result<Option<QueueLock>> {
+        let row = self
+            .db
+            .get()?
+            .query_opt("SELECT value FROM config WHERE name = 'queue_locked';", &[])?;
+        match row {
+            Some(row) => Ok(Some(serde_json::from_value(row.get(0))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of builds in progress. Builds whose worker stopped recording heartbeats
+    /// for longer than the stale build timeout don't count, as they will never finish.
+    pub fn builds_in_progress(&self) -> Result<usize> {
+        let row = self.db.get()?.query_one(
+            "SELECT COUNT(*)
+             FROM queue
+             WHERE started_at IS NOT NULL
+                 AND COALESCE(heartbeat_at, started_at) >= NOW() - make_interval(secs => $1);",
+            &[&self.stale_build_timeout],
+        )?;
+        Ok(row.get::<_, i64>(0) as usize)
+    }
+
+    /// Locks the queue, unless it's already locked, and waits for the builds in progress to
+    /// finish, checking them every `poll_interval`, so that the builders can be stopped without
+    /// interrupting a build.
+    ///
+    /// A worker may claim a build in a transaction started right before the queue was locked, so
+    /// once no build is in progress, they're checked again after another `poll_interval`. The
+    /// queue isn't idle if builds are still in progress after `timeout`, and stays locked either
+    /// way.
+    pub fn drain(
+        &self,
+        reason: Option<&str>,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<QueueDrain> {
+        let lock = self.try_lock(reason)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut rechecked = false;
+        let idle = loop {
+            let in_progress = self.builds_in_progress()?;
+            if in_progress == 0 {
+                if rechecked {
+                    break true;
+                }
+                rechecked = true;
+                thread::sleep(poll_interval);
+                continue;
+            }
+            rechecked = false;
+
+            let now = Instant::now();
+            if now >= deadline {
+                break false;
+            }
+            debug!("waiting for {} builds in progress to finish", in_progress);
+            thread::sleep(poll_interval.min(deadline - now));
+        };
+        Ok(QueueDrain { idle, lock })
+    }
+
     pub(crate) fn pending_count(&self) -> Result<usize> {
         let res = self.db.get()?.query(
             "SELECT COUNT(*) FROM queue WHERE attempt < $1;",
@@ -68,7 +309,242 @@ impl BuildQueue {
         Ok(res[0].get::<_, i64>(0) as usize)
     }
 
-    pub(crate) fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
+    /// Returns the builds that failed all their attempts, the most recent failures first.
+    pub fn failed_crates(&self) -> Result<Vec<FailedCrate>> {
+        let query = self.db.get()?.query(
+            "SELECT name, version, attempt, last_attempt
+             FROM queue
+             WHERE attempt >= $1
+             ORDER BY last_attempt DESC NULLS LAST, id ASC",
+            &[&self.max_attempts],
+        )?;
+
+        Ok(query
+            .into_iter()
+            .map(|row| FailedCrate {
+                name: row.get("name"),
+                version: row.get("version"),
+                attempts: row.get("attempt"),
+                last_attempt: row
+                    .get::<_, Option<NaiveDateTime>>("last_attempt")
+                    .map(|time| DateTime::from_utc(time, Utc)),
+            })
+            .collect())
+    }
+
+    /// Returns whether the given release is waiting in the queue to be built, not counting the
+    /// builds in progress.
+    pub(crate) fn has_build_queued(&self, name: &str, version: &str) -> Result<bool> {
+        Ok(self
+            .db
+            .get()?
+            .query_opt(
+                "SELECT 1
+                 FROM queue
+                 WHERE name = $1 AND version = $2 AND attempt < $3 AND started_at IS NULL;",
+                &[&name, &version, &self.max_attempts],
+            )?
+            .is_some())
+    }
+
+    /// Returns whether the release is in the queue, including releases that failed all their
+    /// attempts, or was already built.
+    pub(crate) fn is_queued_or_built(&self, name: &str, version: &str) -> Result<bool> {
+        Ok(self
+            .db
+            .get()?
+            .query_one(
+                "SELECT
+                     EXISTS(SELECT 1 FROM queue WHERE name = $1 AND version = $2)
+                     OR EXISTS(
+                         SELECT 1
+                         FROM releases
+                         INNER JOIN crates ON crates.id = releases.crate_id
+                         WHERE crates.name = $1 AND releases.version = $2
+                     );",
+                &[&name, &version],
+            )?
+            .get(0))
+    }
+
+    #[cfg(feature = "consistency_check")]
+    /// Returns the releases among `releases` that are in the queue, including releases that
+    /// failed all their attempts, or were already built.
+    pub(crate) fn queued_or_built(
+        &self,
+        releases: &[(String, String)],
+    ) -> Result<std::collections::HashSet<(String, String)>> {
+        let (names, versions): (Vec<&str>, Vec<&str>) = releases
+            .iter()
+            .map(|(name, version)| (name.as_str(), version.as_str()))
+            .unzip();
+        Ok(self
+            .db
+            .get()?
+            .query(
+                "SELECT wanted.name, wanted.version
+                 FROM unnest($1::TEXT[], $2::TEXT[]) AS wanted(name, version)
+                 WHERE EXISTS(
+                         SELECT 1
+                         FROM queue
+                         WHERE queue.name = wanted.name AND queue.version = wanted.version
+                     )
+                     OR EXISTS(
+                         SELECT 1
+                         FROM releases
+                         INNER JOIN crates ON crates.id = releases.crate_id
+                         WHERE crates.name = wanted.name AND releases.version = wanted.version
+                     );",
+                &[&names, &versions],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    /// Returns the position of the release in the queue, starting from 1, if it's waiting to be
+    /// built.
+    pub(crate) fn queue_position(&self, name: &str, version: &str) -> Result<Option<usize>> {
+        Ok(self
+            .queued_crates()?
+            .iter()
+            .position(|krate| krate.name == name && krate.version == version)
+            .map(|idx| idx + 1))
+    }
+
+    /// Queues again the releases that failed to build with `old_rustc_version` in the last
+    /// `window_days` days, after the toolchain was updated to `new_rustc_version`.
+    ///
+    /// Releases of crates that have a successful build newer than the failure are skipped, as
+    /// are releases already in the queue. At most `limit` releases are queued, with the most
+    /// recent failures first, and each of them is recorded in the `toolchain_requeues` table.
+    /// The releases queued by someone else since they were selected are left as they are, and
+    /// aren't recorded.
+    ///
+    /// Returns the number of queued releases.
+    pub(crate) fn requeue_toolchain_failures(
+        &self,
+        old_rustc_version: &str,
+        new_rustc_version: &str,
+        window_days: u32,
+        limit: u32,
+    ) -> Result<usize> {
+        let mut conn = self.db.get()?;
+        let mut transaction = conn.transaction()?;
+
+        let failures = transaction.query(
+            "SELECT name, version FROM (
+                 SELECT DISTINCT ON (crates.name, releases.version)
+                     crates.name, releases.version, builds.build_time
+                 FROM builds
+                 INNER JOIN releases ON releases.id = builds.rid
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE builds.build_status = FALSE
+                     AND builds.rustc_version = $1
+                     AND builds.build_time > NOW() - make_interval(days => $2)
+                     AND NOT EXISTS (
+                         SELECT 1
+                         FROM builds AS newer_builds
+                         INNER JOIN releases AS newer_releases
+                             ON newer_releases.id = newer_builds.rid
+                         WHERE newer_releases.crate_id = crates.id
+                             AND newer_builds.build_status = TRUE
+                             AND newer_builds.build_time > builds.build_time
+                     )
+                     AND NOT EXISTS (
+                         SELECT 1 FROM queue
+                         WHERE queue.name = crates.name
+                             AND queue.version = releases.version
+                             AND queue.attempt < $3
+                     )
+                 ORDER BY crates.name, releases.version, builds.build_time DESC
+             ) AS failures
+             ORDER BY build_time DESC
+             LIMIT $4;",
+            &[
+                &old_rustc_version,
+                &(window_days as i32),
+                &self.max_attempts,
+                &(limit as i64),
+            ],
+        )?;
+
+        let mut requeued = 0;
+        for row in &failures {
+            let name: String = row.get("name");
+            let version: String = row.get("version");
+
+            // Only the builds that failed all their attempts are retried, the pending ones are
+            // already going to use the new toolchain
+            let queued = transaction.query_opt(
+                "INSERT INTO queue (name, version, priority)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (name, version) WHERE started_at IS NULL DO UPDATE
+                     SET priority = LEAST(queue.priority, EXCLUDED.priority),
+                         attempt = 0,
+                         last_attempt = NULL
+                     WHERE queue.attempt >= $4
+                 RETURNING id;",
+                &[&name, &version, &REBUILD_PRIORITY, &self.max_attempts],
+            )?;
+            if queued.is_none() {
+                continue;
+            }
+            requeued += 1;
+            transaction.execute(
+                "INSERT INTO toolchain_requeues (name, version, old_rustc_version, new_rustc_version)
+                 VALUES ($1, $2, $3, $4);",
+                &[&name, &version, &old_rustc_version, &new_rustc_version],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(requeued)
+    }
+
+    /// Returns up to `limit` of the builds waiting in the queue, in the order the daemons claim
+    /// them as of now: the ready builds first, followed by the ones that aren't ready yet in the
+    /// order they'd be claimed once they are. The builds in progress aren't listed.
+    ///
+    /// While the queue is locked no build is ready, but they're listed in the order they'll be
+    /// claimed once it's unlocked.
+    pub fn pending_builds(&self, limit: Option<i64>) -> Result<Vec<PendingBuild>> {
+        let query = format!(
+            "SELECT
+                 id, name, version, priority, registry, date_added, attempt,
+                 {} AS ready,
+                 EXISTS (SELECT 1 FROM config WHERE name = 'queue_locked') AS locked
+             FROM queue
+             WHERE attempt < $1 AND started_at IS NULL
+             ORDER BY ready DESC, {}
+             LIMIT $2",
+            BUILD_IS_READY, DEQUEUE_ORDER,
+        );
+        let rows = self.db.get()?.query(
+            query.as_str(),
+            &[&self.max_attempts, &limit, &self.retry_delay],
+        )?;
+
+        Ok(rows
+            .into_iter()
+            .enumerate()
+            .map(|(idx, row)| PendingBuild {
+                position: idx + 1,
+                krate: QueuedCrate {
+                    id: row.get("id"),
+                    name: row.get("name"),
+                    version: row.get("version"),
+                    priority: row.get("priority"),
+                    registry: row.get("registry"),
+                },
+                added_at: DateTime::from_utc(row.get::<_, NaiveDateTime>("date_added"), Utc),
+                attempts: row.get("attempt"),
+                ready: row.get::<_, bool>("ready") && !row.get::<_, bool>("locked"),
+            })
+            .collect())
+    }
+
+    pub fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
         let query = self.db.get()?.query(
             "SELECT id, name, version, priority, registry
              FROM queue
@@ -89,34 +565,220 @@ impl BuildQueue {
             .collect())
     }
 
+    /// Claims the next build of the queue for the worker, returning `None` if there is nothing
+    /// left to build.
+    ///
+    /// Failed builds are retried once the retry delay, doubled after each failed attempt, has
+    /// passed since their last attempt. Builds claimed by other workers are skipped, including the
+    /// ones abandoned by workers that died, until `recover_stale_builds` releases them. The row is
+    /// locked while it's claimed, so concurrent workers never claim the same build, and a release
+    /// queued again while it's being built waits for the build to finish. Nothing is claimed while
+    /// the queue is locked.
+    pub(crate) fn dequeue_next_build(&self, worker_id: &str) -> Result<Option<QueuedCrate>> {
+        let query = format!(
+            "UPDATE queue
+             SET started_at = NOW(), heartbeat_at = NOW(), worker_id = $2
+             WHERE id = (
+                 SELECT id
+                 FROM queue
+                 WHERE attempt < $1
+                     AND started_at IS NULL
+                     AND NOT EXISTS (SELECT 1 FROM config WHERE name = 'queue_locked')
+                     AND {}
+                 ORDER BY {}
+                 LIMIT 1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, name, version, priority, registry;",
+            BUILD_IS_READY, DEQUEUE_ORDER,
+        );
+        let row = self.db.get()?.query_opt(
+            query.as_str(),
+            &[&self.max_attempts, &worker_id, &self.retry_delay],
+        )?;
+
+        Ok(row.map(|row| QueuedCrate {
+            id: row.get("id"),
+            name: row.get("name"),
+            version: row.get("version"),
+            priority: row.get("priority"),
+            registry: row.get("registry"),
+        }))
+    }
+
+    /// Releases the builds claimed by this process, so other workers can build them right away
+    /// instead of waiting for the claims to time out. Returns the number of released builds.
+    pub(crate) fn release_claimed_builds(&self) -> Result<u64> {
+        let mut conn = self.db.get()?;
+        let mut transaction = conn.transaction()?;
+
+        // Releases queued again during their build already have a pending build
+        let superseded = transaction.execute(
+            "DELETE FROM queue
+             WHERE worker_id = $1
+                 AND EXISTS (
+                     SELECT 1 FROM queue AS pending
+                     WHERE pending.name = queue.name
+                         AND pending.version = queue.version
+                         AND pending.started_at IS NULL
+                 );",
+            &[&self.worker_id],
+        )?;
+        let released = transaction.execute(
+            "UPDATE queue SET started_at = NULL, heartbeat_at = NULL, worker_id = NULL
+             WHERE worker_id = $1;",
+            &[&self.worker_id],
+        )?;
+
+        transaction.commit()?;
+        Ok(superseded + released)
+    }
+
+    /// Releases the builds abandoned by workers that died, which haven't recorded a heartbeat in
+    /// the configured timeout, so they're built again. Returns the recovered builds.
+    ///
+    /// Abandoned builds count as a failed attempt, so a release crashing its builder doesn't keep
+    /// crashing the next ones, and the files they stored are deleted if the release wasn't built
+    /// before. A worker recording a heartbeat at the same time keeps its build: the row is only
+    /// released if it's still claimed by the same worker with an old heartbeat once locked.
+    pub(crate) fn recover_stale_builds(&self, storage: &Storage) -> Result<Vec<QueuedCrate>> {
+        self.recover_builds(storage, false)
+    }
+
+    /// Like `recover_stale_builds`, but also recovers the builds claimed under the identity of
+    /// this process, which were abandoned by a previous process with the same identity, as can
+    /// happen with the PIDs of containers. Only meant to be called before starting to build.
+    pub(crate) fn recover_builds_at_startup(&self, storage: &Storage) -> Result<Vec<QueuedCrate>> {
+        self.recover_builds(storage, true)
+    }
+
+    fn recover_builds(&self, storage: &Storage, own_builds: bool) -> Result<Vec<QueuedCrate>> {
+        let own_worker = if own_builds {
+            Some(self.worker_id.as_str())
+        } else {
+            None
+        };
+        let mut conn = self.db.get()?;
+        let mut transaction = conn.transaction()?;
+
+        // Releases queued again during their build already have a pending build
+        let superseded = transaction.query(
+            "DELETE FROM queue
+             WHERE started_at IS NOT NULL
+                 AND (
+                     COALESCE(heartbeat_at, started_at)
+                         < NOW() - make_interval(secs => $1)
+                     OR worker_id = $2
+                 )
+                 AND EXISTS (
+                     SELECT 1 FROM queue AS pending
+                     WHERE pending.name = queue.name
+                         AND pending.version = queue.version
+                         AND pending.started_at IS NULL
+                 )
+             RETURNING id, name, version, priority, registry, attempt;",
+            &[&self.stale_build_timeout, &own_worker],
+        )?;
+        let released = transaction.query(
+            "UPDATE queue
+             SET started_at = NULL,
+                 heartbeat_at = NULL,
+                 worker_id = NULL,
+                 attempt = attempt + 1,
+                 last_attempt = NOW()
+             WHERE started_at IS NOT NULL
+                 AND (
+                     COALESCE(heartbeat_at, started_at)
+                         < NOW() - make_interval(secs => $1)
+                     OR worker_id = $2
+                 )
+             RETURNING id, name, version, priority, registry, attempt;",
+            &[&self.stale_build_timeout, &own_worker],
+        )?;
+        transaction.commit()?;
+
+        let mut recovered = Vec::new();
+        for row in superseded.iter().chain(&released) {
+            let krate = QueuedCrate {
+                id: row.get("id"),
+                name: row.get("name"),
+                version: row.get("version"),
+                priority: row.get("priority"),
+                registry: row.get("registry"),
+            };
+            warn!(
+                "recovered the abandoned build of {} {}",
+                krate.name, krate.version
+            );
+            if row.get::<_, i32>("attempt") >= self.max_attempts {
+                self.metrics.failed_builds.inc();
+            }
+
+            let built_before = conn
+                .query_opt(
+                    "SELECT 1
+                     FROM releases
+                     INNER JOIN crates ON crates.id = releases.crate_id
+                     WHERE crates.name = $1 AND releases.version = $2;",
+                    &[&krate.name, &krate.version],
+                )?
+                .is_some();
+            if !built_before {
+                delete_version_files(storage, &krate.name, &krate.version)?;
+            }
+            recovered.push(krate);
+        }
+
+        Ok(recovered)
+    }
+
     pub(crate) fn process_next_crate(
         &self,
         f: impl FnOnce(&QueuedCrate) -> Result<()>,
     ) -> Result<()> {
-        let mut conn = self.db.get()?;
-
-        let queued = self.queued_crates()?;
-        let to_process = match queued.get(0) {
+        let to_process = match self.dequeue_next_build(&self.worker_id)? {
             Some(krate) => krate,
             None => return Ok(()),
         };
 
-        let res = f(&to_process);
+        let res = self.with_heartbeat(&to_process, || f(&to_process));
         self.metrics.total_builds.inc();
+
+        let mut conn = self.db.get()?;
         match res {
             Ok(()) => {
                 conn.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
             }
             Err(e) => {
-                // Increase attempt count
+                // Increase attempt count, and release the build so it can be retried after the
+                // backoff, unless the release was queued again during the build and already has a
+                // pending build.
                 let rows = conn.query(
-                    "UPDATE queue SET attempt = attempt + 1 WHERE id = $1 RETURNING attempt;",
-                    &[&to_process.id],
+                    "UPDATE queue
+                     SET attempt = attempt + 1,
+                         last_attempt = NOW(),
+                         started_at = NULL,
+                         heartbeat_at = NULL,
+                         worker_id = NULL
+                     WHERE id = $1
+                         AND NOT EXISTS (
+                             SELECT 1 FROM queue AS pending
+                             WHERE pending.name = $2
+                                 AND pending.version = $3
+                                 AND pending.started_at IS NULL
+                         )
+                     RETURNING attempt;",
+                    &[&to_process.id, &to_process.name, &to_process.version],
                 )?;
-                let attempt: i32 = rows[0].get(0);
-
-                if attempt >= self.max_attempts {
-                    self.metrics.failed_builds.inc();
+                match rows.first() {
+                    Some(row) => {
+                        if row.get::<_, i32>(0) >= self.max_attempts {
+                            self.metrics.failed_builds.inc();
+                        }
+                    }
+                    None => {
+                        conn.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
+                    }
                 }
 
                 error!(
@@ -131,11 +793,41 @@ impl BuildQueue {
 
         Ok(())
     }
+
+    /// Runs `f`, recording a heartbeat of the build of `krate` every `HEARTBEAT_INTERVAL` in the
+    /// meantime, so that it isn't considered abandoned.
+    fn with_heartbeat<T>(&self, krate: &QueuedCrate, f: impl FnOnce() -> T) -> T {
+        let (done, wait) = mpsc::channel::<()>();
+        let (db, id, worker_id) = (self.db.clone(), krate.id, self.worker_id.clone());
+        let heartbeat = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = wait.recv_timeout(HEARTBEAT_INTERVAL) {
+                if let Err(err) = record_heartbeat(&db, id, &worker_id) {
+                    warn!("failed to record the heartbeat of a build: {}", err);
+                }
+            }
+        });
+
+        let result = f();
+        drop(done);
+        if heartbeat.join().is_err() {
+            error!("the heartbeat of a build panicked");
+        }
+        result
+    }
+}
+
+fn record_heartbeat(db: &Pool, id: i32, worker_id: &str) -> Result<()> {
+    db.get()?.execute(
+        "UPDATE queue SET heartbeat_at = NOW() WHERE id = $1 AND worker_id = $2;",
+        &[&id, &worker_id],
+    )?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::Blob;
 
     #[test]
     fn test_add_and_process_crates() {
@@ -216,6 +908,659 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_has_build_queued() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            queue.add_crate("dummy", "0.1.1", 0, None)?;
+            assert!(queue.has_build_queued("dummy", "0.1.1")?);
+            assert!(!queue.has_build_queued("dummy", "0.1.0")?);
+            assert!(!queue.has_build_queued("other", "0.1.1")?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_is_queued_or_built() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            env.fake_release().name("built").version("0.1.0").create()?;
+            queue.add_crate("queued", "0.1.0", 0, None)?;
+
+            assert!(queue.is_queued_or_built("built", "0.1.0")?);
+            assert!(queue.is_queued_or_built("queued", "0.1.0")?);
+            assert!(!queue.is_queued_or_built("built", "0.2.0")?);
+            assert!(!queue.is_queued_or_built("new", "0.1.0")?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "consistency_check")]
+    fn test_queued_or_built() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            env.fake_release().name("built").version("0.1.0").create()?;
+            queue.add_crate("queued", "0.1.0", 0, None)?;
+
+            let releases = [
+                ("built".to_string(), "0.1.0".to_string()),
+                ("queued".to_string(), "0.1.0".to_string()),
+                ("built".to_string(), "0.2.0".to_string()),
+                ("new".to_string(), "0.1.0".to_string()),
+            ];
+            assert_eq!(
+                queue.queued_or_built(&releases)?,
+                releases[..2].iter().cloned().collect()
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_queue_release_again() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 10, None)?;
+
+            // Queueing it again only raises the priority of the pending build
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("foo", "1.0.0", 20, None)?;
+            assert_eq!(
+                queue.add_crates(&[("foo", "1.0.0", 5), ("bar", "1.0.0", 0)], None)?,
+                1
+            );
+
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 2);
+            assert_eq!((queued[0].name.as_str(), queued[0].priority), ("foo", 0));
+            assert_eq!((queued[1].name.as_str(), queued[1].priority), ("bar", 0));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_queue_failed_release_again() {
+        const OLD: &str = "rustc 1.50.0-nightly (000000000 2020-12-01)";
+
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.build_attempts = 1);
+            let queue = env.build_queue();
+
+            for name in &["foo", "bar"] {
+                env.fake_release()
+                    .name(name)
+                    .rustc_version(OLD)
+                    .build_result_successful(false)
+                    .create()?;
+                queue.add_crate(name, "1.0.0", 0, None)?;
+                queue.process_next_crate(|_| failure::bail!("simulate a failure"))?;
+            }
+            assert_eq!(queue.failed_count()?, 2);
+
+            // Releases that failed all their attempts are retried when queued again
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            assert_eq!(queue.requeue_toolchain_failures(OLD, "new", 7, 100)?, 1);
+            assert_eq!(queue.failed_count()?, 0);
+            assert_eq!(queue.pending_count()?, 2);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_queue_release_during_build() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+
+            queue.process_next_crate(|_| {
+                // The release is built once more after this build, and not at the same time
+                queue.add_crate("foo", "1.0.0", 0, None)?;
+                assert_eq!(queue.dequeue_next_build("another-worker")?, None);
+                Ok(())
+            })?;
+            assert_eq!(queue.pending_count()?, 1);
+
+            queue.process_next_crate(|_| {
+                queue.add_crate("foo", "1.0.0", 0, None)?;
+                failure::bail!("simulate a failure");
+            })?;
+            // The failed build is replaced by the pending one
+            assert_eq!(queue.pending_count()?, 1);
+            assert!(queue.has_build_queued("foo", "1.0.0")?);
+            queue.process_next_crate(|_| Ok(()))?;
+            assert_eq!(queue.pending_count()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_dequeue_skips_claimed_builds() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("first", "1.0.0", 0, None)?;
+            queue.add_crate("second", "1.0.0", 0, None)?;
+
+            let first = queue.dequeue_next_build("worker-1")?.unwrap();
+            assert_eq!(first.name, "first");
+            let second = queue.dequeue_next_build("worker-2")?.unwrap();
+            assert_eq!(second.name, "second");
+            assert!(queue.dequeue_next_build("worker-3")?.is_none());
+
+            // Builds claimed by workers that died are only claimed again once recovered
+            env.db().conn().execute(
+                "UPDATE queue SET heartbeat_at = NOW() - INTERVAL '2 hours' WHERE name = 'first';",
+                &[],
+            )?;
+            assert!(queue.dequeue_next_build("worker-3")?.is_none());
+            queue.recover_stale_builds(&env.storage())?;
+            let reclaimed = queue.dequeue_next_build("worker-3")?.unwrap();
+            assert_eq!(reclaimed.name, "first");
+
+            let row = env
+                .db()
+                .conn()
+                .query_one("SELECT worker_id FROM queue WHERE name = 'first';", &[])?;
+            assert_eq!(row.get::<_, String>(0), "worker-3");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_recover_stale_builds() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            let storage = env.storage();
+            queue.add_crate("crashed", "1.0.0", 0, None)?;
+            queue.add_crate("alive", "1.0.0", 0, None)?;
+            let crashed = queue.dequeue_next_build("dead-worker")?.unwrap();
+            assert_eq!(crashed.name, "crashed");
+            queue.dequeue_next_build("live-worker")?.unwrap();
+
+            // The crashed build stored some of its files, and stopped recording heartbeats
+            let blob = |path: &str| Blob {
+                path: path.into(),
+                mime: "text/plain".into(),
+                date_updated: Utc::now(),
+                content: b"partial".to_vec(),
+                compression: None,
+            };
+            storage.store_blobs(vec![
+                blob("rustdoc/crashed/1.0.0/crashed/index.html"),
+                blob("sources/crashed/1.0.0/src/lib.rs"),
+                blob("rustdoc/alive/1.0.0/alive/index.html"),
+            ])?;
+            env.db().conn().execute(
+                "UPDATE queue SET heartbeat_at = NOW() - INTERVAL '1 hour' WHERE name = 'crashed';",
+                &[],
+            )?;
+
+            assert_eq!(queue.recover_stale_builds(&storage)?, vec![crashed.clone()]);
+            assert!(!storage.exists("rustdoc/crashed/1.0.0/crashed/index.html")?);
+            assert!(!storage.exists("sources/crashed/1.0.0/src/lib.rs")?);
+            // The build still recording heartbeats is left alone
+            assert!(storage.exists("rustdoc/alive/1.0.0/alive/index.html")?);
+
+            let row = env.db().conn().query_one(
+                "SELECT started_at IS NULL, worker_id, heartbeat_at IS NULL, attempt
+                 FROM queue WHERE name = 'crashed';",
+                &[],
+            )?;
+            assert!(row.get::<_, bool>(0));
+            assert_eq!(row.get::<_, Option<String>>(1), None);
+            assert!(row.get::<_, bool>(2));
+            assert_eq!(row.get::<_, i32>(3), 1);
+            let row = env.db().conn().query_one(
+                "SELECT worker_id, attempt FROM queue WHERE name = 'alive';",
+                &[],
+            )?;
+            assert_eq!(
+                row.get::<_, Option<String>>(0).as_deref(),
+                Some("live-worker")
+            );
+            assert_eq!(row.get::<_, i32>(1), 0);
+
+            assert!(queue.recover_stale_builds(&storage)?.is_empty());
+            // The recovered build is picked up by the next worker
+            let retried = queue.dequeue_next_build("new-worker")?.unwrap();
+            assert_eq!(retried.name, "crashed");
+            assert!(queue.dequeue_next_build("new-worker")?.is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_recover_builds_at_startup() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "1.0.0", 0, None)?;
+            env.fake_release().name("foo").version("1.0.0").create()?;
+
+            // A previous process with the same identity crashed just after claiming its build
+            let claimed = queue.dequeue_next_build(&queue.worker_id)?.unwrap();
+            queue.dequeue_next_build("another-worker")?.unwrap();
+            assert_eq!(queue.recover_stale_builds(&env.storage())?, vec![]);
+            assert_eq!(
+                queue.recover_builds_at_startup(&env.storage())?,
+                vec![claimed.clone()]
+            );
+            // The files of releases built before aren't deleted
+            assert!(env.storage().exists("rustdoc/foo/1.0.0/foo/index.html")?);
+            assert_eq!(queue.dequeue_next_build("third-worker")?, Some(claimed));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_lock_queue() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "1.0.0", 0, None)?;
+            assert_eq!(queue.lock_state()?, None);
+
+            // The build in progress when the queue is locked finishes, and no other starts
+            let mut built = Vec::new();
+            queue.process_next_crate(|krate| {
+                queue.lock(Some("storage outage"))?;
+                built.push(krate.name.clone());
+                Ok(())
+            })?;
+            queue.process_next_crate(|krate| {
+                built.push(krate.name.clone());
+                Ok(())
+            })?;
+            assert_eq!(built, vec!["foo"]);
+            assert_eq!(queue.pending_count()?, 1);
+            assert_eq!(queue.dequeue_next_build("another-worker")?, None);
+            let lock = queue.lock_state()?.expect("the queue isn't locked");
+            assert_eq!(lock.reason.as_deref(), Some("storage outage"));
+
+            // Locking again only replaces the reason
+            queue.lock(None)?;
+            assert_eq!(queue.lock_state()?.unwrap().reason, None);
+
+            assert!(queue.unlock()?);
+            assert!(!queue.unlock()?);
+            queue.process_next_crate(|krate| {
+                built.push(krate.name.clone());
+                Ok(())
+            })?;
+            assert_eq!(built, vec!["foo", "bar"]);
+            assert_eq!(queue.pending_count()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_drain_idle_queue() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+
+            // Pending builds don't need to be built for the queue to be idle
+            let drain = queue.drain(
+                Some("deploy"),
+                Duration::from_secs(0),
+                Duration::from_millis(10),
+            )?;
+            assert!(drain.idle);
+            let lock = queue.lock_state()?.expect("the queue isn't locked");
+            assert_eq!(lock.reason.as_deref(), Some("deploy"));
+            assert_eq!(drain.lock.as_ref(), Some(&lock));
+            assert_eq!(queue.dequeue_next_build("another-worker")?, None);
+            assert_eq!(queue.pending_count()?, 1);
+
+            assert!(queue.release_lock(&lock)?);
+            assert!(queue.lock_state()?.is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_drain_waits_for_builds_in_progress() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("slow", "1.0.0", 0, None)?;
+            queue.add_crate("next", "1.0.0", 0, None)?;
+
+            // The build only finishes a while after the queue is drained
+            let started = std::sync::Barrier::new(2);
+            let (drained, built) = std::thread::scope(|scope| {
+                let (queue, started) = (&queue, &started);
+                let builder = scope.spawn(move || {
+                    let mut built = Vec::new();
+                    queue.process_next_crate(|krate| {
+                        started.wait();
+                        std::thread::sleep(Duration::from_millis(500));
+                        built.push(krate.name.clone());
+                        Ok(())
+                    })?;
+                    Result::Ok(built)
+                });
+
+                started.wait();
+                assert_eq!(queue.builds_in_progress().unwrap(), 1);
+                let drained = queue.drain(None, Duration::from_secs(30), Duration::from_millis(20));
+                (drained, builder.join().unwrap())
+            });
+            assert!(drained?.idle);
+            assert_eq!(built?, vec!["slow"]);
+            assert_eq!(queue.builds_in_progress()?, 0);
+
+            // The next build only starts once the queue is unlocked
+            assert_eq!(queue.dequeue_next_build("another-worker")?, None);
+            assert!(queue.unlock()?);
+            assert_eq!(
+                queue.dequeue_next_build("another-worker")?.unwrap().name,
+                "next"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.dequeue_next_build("another-worker")?.unwrap();
+
+            let start = Instant::now();
+            let drain = queue.drain(None, Duration::from_millis(200), Duration::from_millis(50))?;
+            assert!(!drain.idle);
+            assert!(start.elapsed() >= Duration::from_millis(200));
+            assert!(queue.lock_state()?.is_some());
+
+            // Abandoned builds will never finish, so they aren't waited for
+            env.db().conn().execute(
+                "UPDATE queue SET heartbeat_at = NOW() - INTERVAL '2 hours' WHERE name = 'foo';",
+                &[],
+            )?;
+            assert_eq!(queue.builds_in_progress()?, 0);
+            let drain = queue.drain(None, Duration::from_millis(200), Duration::from_millis(50))?;
+            assert!(drain.idle);
+            // The queue was still locked by the first drain
+            assert_eq!(drain.lock, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_drain_keeps_existing_lock() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.lock(Some("storage outage"))?;
+            let existing = queue.lock_state()?.unwrap();
+
+            let drain = queue.drain(
+                Some("deploy"),
+                Duration::from_secs(0),
+                Duration::from_millis(10),
+            )?;
+            assert!(drain.idle);
+            assert_eq!(drain.lock, None);
+            assert_eq!(queue.lock_state()?, Some(existing));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_release_lock_taken_since() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            let drain = queue.drain(None, Duration::from_secs(0), Duration::from_millis(10))?;
+            let lock = drain.lock.unwrap();
+
+            // Locked again by someone else while the queue was drained
+            queue.lock(Some("storage outage"))?;
+            assert!(!queue.release_lock(&lock)?);
+            assert_eq!(
+                queue.lock_state()?.unwrap().reason.as_deref(),
+                Some("storage outage")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_drain_rechecks_idle_queue() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+
+            // A build claimed right after the queue looked idle is still waited for
+            let pool = env.db().pool();
+            let drain = std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    std::thread::sleep(Duration::from_millis(100));
+                    pool.get()
+                        .unwrap()
+                        .execute(
+                            "UPDATE queue SET started_at = NOW() WHERE name = 'foo';",
+                            &[],
+                        )
+                        .unwrap();
+                });
+                queue.drain(None, Duration::from_secs(0), Duration::from_millis(400))
+            })?;
+            assert!(!drain.idle);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_concurrent_dequeue() {
+        const BUILDS: usize = 50;
+        const WORKERS: usize = 4;
+
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            for idx in 0..BUILDS {
+                queue.add_crate(&format!("krate-{}", idx), "1.0.0", 0, None)?;
+            }
+
+            let dequeued = std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..WORKERS)
+                    .map(|worker| {
+                        let queue = &queue;
+                        scope.spawn(move || {
+                            let worker_id = format!("worker-{}", worker);
+                            let mut dequeued = Vec::new();
+                            while let Some(krate) = queue.dequeue_next_build(&worker_id)? {
+                                dequeued.push(krate.name);
+                            }
+                            Result::Ok(dequeued)
+                        })
+                    })
+                    .collect();
+
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().unwrap())
+                    .collect::<Result<Vec<_>>>()
+            })?;
+
+            let mut names: Vec<_> = dequeued.into_iter().flatten().collect();
+            assert_eq!(names.len(), BUILDS);
+            names.sort();
+            names.dedup();
+            assert_eq!(names.len(), BUILDS);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_concurrent_builds() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "1.0.0", 0, None)?;
+            queue.add_crate("baz", "1.0.0", 0, None)?;
+
+            // Both consumers wait for each other inside their build, so the first two builds are
+            // in progress at the same time
+            let barrier = std::sync::Barrier::new(2);
+            let built = std::thread::scope(|scope| {
+                let consumers: Vec<_> = (0..2)
+                    .map(|_| {
+                        let (queue, barrier) = (&queue, &barrier);
+                        scope.spawn(move || {
+                            let mut built = None;
+                            queue.process_next_crate(|krate| {
+                                built = Some(krate.name.clone());
+                                barrier.wait();
+                                Ok(())
+                            })?;
+                            Result::Ok(built)
+                        })
+                    })
+                    .collect();
+
+                consumers
+                    .into_iter()
+                    .map(|consumer| consumer.join().unwrap())
+                    .collect::<Result<Vec<_>>>()
+            })?;
+
+            assert_eq!(built.len(), 2);
+            assert!(built.iter().all(Option::is_some));
+            assert_ne!(built[0], built[1]);
+            assert_eq!(queue.pending_count()?, 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_release_claimed_builds() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "1.0.0", 0, None)?;
+
+            let claimed = queue.dequeue_next_build(&queue.worker_id)?.unwrap();
+            queue.dequeue_next_build("another-worker")?.unwrap();
+            assert_eq!(queue.dequeue_next_build("third-worker")?, None);
+
+            // Only the builds claimed by this process are released
+            assert_eq!(queue.release_claimed_builds()?, 1);
+            assert_eq!(queue.dequeue_next_build("third-worker")?, Some(claimed));
+            assert_eq!(queue.release_claimed_builds()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_requeue_toolchain_failures() {
+        const OLD: &str = "rustc 1.50.0-nightly (000000000 2020-12-01)";
+        const NEW: &str = "rustc 1.50.0-nightly (111111111 2020-12-02)";
+
+        crate::test::wrapper(|env| {
+            let failed = |name: &str, version: &str, rustc_version: &str| {
+                env.fake_release()
+                    .name(name)
+                    .version(version)
+                    .rustc_version(rustc_version)
+                    .build_result_successful(false)
+                    .create()
+            };
+
+            // Failed with the old toolchain: requeued.
+            failed("broken", "1.0.0", OLD)?;
+            // Failed with an even older toolchain: not requeued.
+            failed("older-toolchain", "1.0.0", "rustc 1.49.0")?;
+            // Fixed by a newer successful release: not requeued.
+            failed("fixed", "1.0.0", OLD)?;
+            env.fake_release().name("fixed").version("1.0.1").create()?;
+            // Already in the queue: not requeued again.
+            failed("queued", "1.0.0", OLD)?;
+            env.build_queue().add_crate("queued", "1.0.0", 0, None)?;
+            // In the queue, but failed all its attempts: retried.
+            failed("gave-up", "1.0.0", OLD)?;
+            env.build_queue().add_crate("gave-up", "1.0.0", 100, None)?;
+            env.db().conn().execute(
+                "UPDATE queue SET attempt = $1 WHERE name = 'gave-up';",
+                &[&(env.config().build_attempts as i32)],
+            )?;
+            // Failed outside of the window: not requeued.
+            let old_release = failed("ancient", "1.0.0", OLD)?;
+            env.db().conn().execute(
+                "UPDATE builds SET build_time = NOW() - INTERVAL '8 days' WHERE rid = $1;",
+                &[&old_release],
+            )?;
+
+            let queue = env.build_queue();
+            assert_eq!(queue.requeue_toolchain_failures(OLD, NEW, 7, 100)?, 2);
+
+            let queued = queue.queued_crates()?;
+            for name in &["broken", "gave-up"] {
+                let requeued = queued.iter().find(|krate| krate.name == *name).unwrap();
+                assert_eq!(requeued.version, "1.0.0");
+                assert_eq!(requeued.priority, REBUILD_PRIORITY);
+            }
+            assert_eq!(queued.len(), 3);
+
+            let rows = env.db().conn().query(
+                "SELECT name, old_rustc_version, new_rustc_version
+                 FROM toolchain_requeues
+                 ORDER BY name;",
+                &[],
+            )?;
+            assert_eq!(rows.len(), 2);
+            assert_eq!(rows[0].get::<_, String>(0), "broken");
+            assert_eq!(rows[0].get::<_, String>(1), OLD);
+            assert_eq!(rows[0].get::<_, String>(2), NEW);
+            assert_eq!(rows[1].get::<_, String>(0), "gave-up");
+
+            // Running it again doesn't queue the same release twice.
+            assert_eq!(queue.requeue_toolchain_failures(OLD, NEW, 7, 100)?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_requeue_toolchain_failures_limit() {
+        const OLD: &str = "rustc 1.50.0-nightly (000000000 2020-12-01)";
+
+        crate::test::wrapper(|env| {
+            for name in &["a", "b", "c"] {
+                env.fake_release()
+                    .name(name)
+                    .rustc_version(OLD)
+                    .build_result_successful(false)
+                    .create()?;
+            }
+
+            let queue = env.build_queue();
+            assert_eq!(queue.requeue_toolchain_failures(OLD, "new", 7, 2)?, 2);
+            assert_eq!(queue.pending_count()?, 2);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_pending_count() {
         crate::test::wrapper(|env| {
@@ -293,6 +1638,68 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_retry_failed_builds() {
+        const MAX_ATTEMPTS: u16 = 3;
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = MAX_ATTEMPTS;
+                config.build_retry_delay = 3600;
+            });
+            let queue = env.build_queue();
+            let mut conn = env.db().conn();
+            let mut last_attempt_ago = |seconds: f64| {
+                conn.execute(
+                    "UPDATE queue SET last_attempt = NOW() - make_interval(secs => $1);",
+                    &[&seconds],
+                )
+            };
+            let fail = || queue.process_next_crate(|_| failure::bail!("simulate a failure"));
+
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            fail()?;
+
+            // The failed build waits for the retry delay, doubled after each attempt
+            assert_eq!(queue.dequeue_next_build("worker")?, None);
+            assert_eq!(queue.pending_count()?, 1);
+            last_attempt_ago(3601.0)?;
+            fail()?;
+            last_attempt_ago(3601.0)?;
+            assert_eq!(queue.dequeue_next_build("worker")?, None);
+            last_attempt_ago(7201.0)?;
+            assert_eq!(queue.failed_crates()?, vec![]);
+            fail()?;
+
+            // After its last attempt the build failed permanently
+            last_attempt_ago(1_000_000.0)?;
+            assert_eq!(queue.dequeue_next_build("worker")?, None);
+            assert_eq!(queue.pending_count()?, 0);
+            let failed = queue.failed_crates()?;
+            assert_eq!(failed.len(), 1);
+            assert_eq!(
+                (failed[0].name.as_str(), failed[0].version.as_str()),
+                ("foo", "1.0.0")
+            );
+            assert_eq!(failed[0].attempts, 3);
+            assert!(failed[0].last_attempt.is_some());
+            assert_eq!(env.metrics().failed_builds.get(), 1);
+
+            // Queueing it again resets its attempts, and it's built right away
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            assert_eq!(queue.failed_crates()?, vec![]);
+            let mut built = false;
+            queue.process_next_crate(|krate| {
+                assert_eq!(krate.name, "foo");
+                built = true;
+                Ok(())
+            })?;
+            assert!(built);
+            assert_eq!(queue.pending_count()?, 0);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_queued_crates() {
         crate::test::wrapper(|env| {
@@ -323,4 +1730,92 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn test_pending_builds_in_claim_order() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_retry_delay = 3600;
+            });
+            let queue = env.build_queue();
+
+            queue.add_crate("low", "1.0.0", 10, None)?;
+            queue.add_crate("high", "1.0.0", -10, None)?;
+            queue.add_crate("default", "1.0.0", 0, None)?;
+            queue.add_crate("default", "2.0.0", 0, None)?;
+            queue.add_crate("retried", "1.0.0", 0, None)?;
+            queue.add_crate("failed", "1.0.0", -20, None)?;
+            let mut conn = env.db().conn();
+            // A retried build comes after the first attempts of the same priority
+            conn.execute(
+                "UPDATE queue SET attempt = 1, last_attempt = NOW() - INTERVAL '2 hours'
+                 WHERE name = 'retried'",
+                &[],
+            )?;
+            // A failed build waiting for its retry delay comes last despite its priority
+            conn.execute(
+                "UPDATE queue SET attempt = 1, last_attempt = NOW() WHERE name = 'failed'",
+                &[],
+            )?;
+
+            let pending = queue.pending_builds(None)?;
+            let listed: Vec<_> = pending
+                .iter()
+                .map(|build| {
+                    (
+                        build.position,
+                        build.krate.name.as_str(),
+                        build.krate.version.as_str(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                listed,
+                vec![
+                    (1, "high", "1.0.0"),
+                    (2, "default", "1.0.0"),
+                    (3, "default", "2.0.0"),
+                    (4, "retried", "1.0.0"),
+                    (5, "low", "1.0.0"),
+                    (6, "failed", "1.0.0"),
+                ]
+            );
+            assert!(pending[..5].iter().all(|build| build.ready));
+            assert!(!pending[5].ready);
+            assert_eq!(pending[3].attempts, 1);
+            assert_eq!(
+                queue
+                    .pending_builds(Some(2))?
+                    .iter()
+                    .map(|build| build.krate.name.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["high", "default"]
+            );
+
+            // Nothing can be claimed while the queue is locked, but the order stays the same
+            queue.lock(None)?;
+            let locked = queue.pending_builds(None)?;
+            assert!(locked.iter().all(|build| !build.ready));
+            assert_eq!(
+                locked.iter().map(|build| &build.krate).collect::<Vec<_>>(),
+                pending.iter().map(|build| &build.krate).collect::<Vec<_>>()
+            );
+            queue.unlock()?;
+
+            // The daemons claim the ready builds in the listed order
+            let mut claimed = Vec::new();
+            while let Some(krate) = queue.dequeue_next_build("worker")? {
+                claimed.push((krate.name, krate.version));
+            }
+            let ready: Vec<_> = pending[..5]
+                .iter()
+                .map(|build| (build.krate.name.clone(), build.krate.version.clone()))
+                .collect();
+            assert_eq!(claimed, ready);
+            // The builds in progress aren't pending anymore
+            assert_eq!(queue.pending_builds(None)?.len(), 1);
+
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,371 @@
+//! Invalidation of the pages cached by the CDN in front of docs.rs, after their content changes
+
+use crate::Config;
+use failure::{bail, Error};
+use log::{debug, error, warn};
+use rusoto_cloudfront::{
+    CloudFront, CloudFrontClient, CreateInvalidationRequest, InvalidationBatch, Paths,
+};
+use rusoto_core::region::Region;
+use rusoto_credential::DefaultCredentialsProvider;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Time to wait before retrying a failed invalidation, multiplied by the number of attempts.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// The most paths CloudFront accepts in a single invalidation.
+const CLOUDFRONT_MAX_PATHS: usize = 3000;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid CDN backend")]
+pub(crate) struct InvalidCdnBackendError;
+
+#[derive(Debug)]
+pub(crate) enum CdnKind {
+    /// Nothing is invalidated, for the instances not fronted by a CDN
+    None,
+    CloudFront,
+    /// Sends the paths to invalidate to an endpoint, like the purge API of a CDN
+    Endpoint,
+    /// Records the invalidated paths, only available in tests
+    #[cfg(test)]
+    Mock,
+}
+
+impl std::str::FromStr for CdnKind {
+    type Err = InvalidCdnBackendError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "none" => Ok(CdnKind::None),
+            "cloudfront" => Ok(CdnKind::CloudFront),
+            "endpoint" => Ok(CdnKind::Endpoint),
+            _ => Err(InvalidCdnBackendError),
+        }
+    }
+}
+
+/// A CDN whose cached copies of the pages can be invalidated.
+pub trait CdnInvalidator: Send + Sync {
+    /// The most paths invalidated by a single call to `invalidate`.
+    fn max_paths(&self) -> usize;
+
+    /// Invalidates the cached copies of `paths`, which start with `/` and can end with a `*`
+    /// wildcard.
+    fn invalidate(&self, paths: &[String]) -> Result<(), Error>;
+}
+
+/// Invalidates the pages cached by the configured CDN, if any.
+pub struct Cdn {
+    invalidator: Arc<dyn CdnInvalidator>,
+    max_attempts: u32,
+    retry_delay: Duration,
+    #[cfg(test)]
+    mock: Option<Arc<MockInvalidator>>,
+}
+
+impl Cdn {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        #[cfg(test)]
+        let mut mock = None;
+        let invalidator: Arc<dyn CdnInvalidator> = match config.cdn_backend {
+            CdnKind::None => Arc::new(NoopInvalidator),
+            CdnKind::CloudFront => Arc::new(CloudFrontInvalidator::new(config)?),
+            CdnKind::Endpoint => Arc::new(EndpointInvalidator::new(config)?),
+            #[cfg(test)]
+            CdnKind::Mock => {
+                let invalidator = Arc::new(MockInvalidator::default());
+                mock = Some(invalidator.clone());
+                invalidator
+            }
+        };
+
+        Ok(Self {
+            invalidator,
+            max_attempts: config.cdn_purge_attempts.max(1),
+            retry_delay: if cfg!(test) {
+                Duration::from_millis(0)
+            } else {
+                RETRY_DELAY
+            },
+            #[cfg(test)]
+            mock,
+        })
+    }
+
+    /// Invalidates the pages of a release, after it was rebuilt or deleted. The pages of the
+    /// latest release and of the crate are also invalidated, as the release can be the latest one.
+    pub fn purge_release(&self, name: &str, version: &str) {
+        self.purge(&[
+            format!("/{}", name),
+            format!("/{}/", name),
+            format!("/{}/{}/*", name, version),
+            format!("/{}/latest/*", name),
+            format!("/crate/{}", name),
+            format!("/crate/{}/", name),
+            format!("/crate/{}/{}/*", name, version),
+            format!("/crate/{}/latest/*", name),
+        ]);
+    }
+
+    /// Invalidates all the pages of a crate, after it was deleted.
+    pub fn purge_crate(&self, name: &str) {
+        self.purge(&[
+            format!("/{}", name),
+            format!("/{}/*", name),
+            format!("/crate/{}", name),
+            format!("/crate/{}/*", name),
+        ]);
+    }
+
+    /// Invalidates `paths`, in as many batches as the CDN requires.
+    ///
+    /// Failed invalidations are retried a few times, and then only logged: a stale page isn't
+    /// worth failing the build or the deletion which changed it.
+    pub fn purge(&self, paths: &[String]) {
+        for batch in paths.chunks(self.invalidator.max_paths().max(1)) {
+            let mut attempt = 1;
+            loop {
+                match self.invalidator.invalidate(batch) {
+                    Ok(()) => {
+                        debug!("invalidated {} paths in the CDN", batch.len());
+                        break;
+                    }
+                    Err(err) if attempt < self.max_attempts => {
+                        warn!(
+                            "failed to invalidate {} paths in the CDN (attempt {}/{}): {}",
+                            batch.len(),
+                            attempt,
+                            self.max_attempts,
+                            err
+                        );
+                        thread::sleep(self.retry_delay * attempt);
+                        attempt += 1;
+                    }
+                    Err(err) => {
+                        error!(
+                            "failed to invalidate {} paths in the CDN, giving up: {}",
+                            batch.len(),
+                            err
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the batches of paths invalidated since the CDN was created.
+    #[cfg(test)]
+    pub(crate) fn purged(&self) -> Vec<Vec<String>> {
+        self.mock
+            .as_ref()
+            .expect("the CDN isn't mocked")
+            .purged
+            .lock()
+            .unwrap()
+            .clone()
+    }
+
+    /// Makes the next `count` invalidations fail.
+    #[cfg(test)]
+    pub(crate) fn fail_next(&self, count: usize) {
+        let mock = self.mock.as_ref().expect("the CDN isn't mocked");
+        *mock.failures.lock().unwrap() = count;
+    }
+}
+
+struct NoopInvalidator;
+
+impl CdnInvalidator for NoopInvalidator {
+    fn max_paths(&self) -> usize {
+        usize::MAX
+    }
+
+    fn invalidate(&self, _paths: &[String]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+struct CloudFrontInvalidator {
+    client: CloudFrontClient,
+    runtime: Runtime,
+    distribution_id: String,
+}
+
+impl CloudFrontInvalidator {
+    fn new(config: &Config) -> Result<Self, Error> {
+        let distribution_id = match &config.cloudfront_distribution_id {
+            Some(id) => id.clone(),
+            None => bail!("DOCSRS_CLOUDFRONT_DISTRIBUTION_ID is required by the CloudFront CDN"),
+        };
+        // CloudFront is a global service, only available in this region
+        let client = CloudFrontClient::new_with(
+            rusoto_core::request::HttpClient::new()?,
+            DefaultCredentialsProvider::new()?,
+            Region::UsEast1,
+        );
+
+        Ok(Self {
+            client,
+            runtime: Runtime::new()?,
+            distribution_id,
+        })
+    }
+}
+
+impl CdnInvalidator for CloudFrontInvalidator {
+    fn max_paths(&self) -> usize {
+        CLOUDFRONT_MAX_PATHS
+    }
+
+    fn invalidate(&self, paths: &[String]) -> Result<(), Error> {
+        let req = CreateInvalidationRequest {
+            distribution_id: self.distribution_id.clone(),
+            invalidation_batch: InvalidationBatch {
+                // Identifies the invalidation, so that retried requests aren't applied twice
+                caller_reference: format!(
+                    "docsrs-{}-{}",
+                    chrono::Utc::now().timestamp(),
+                    rand::random::<u32>()
+                ),
+                paths: Paths {
+                    quantity: paths.len() as i64,
+                    items: Some(paths.to_vec()),
+                },
+            },
+        };
+        self.runtime
+            .handle()
+            .block_on(self.client.create_invalidation(req))?;
+        Ok(())
+    }
+}
+
+/// Sends the paths to invalidate to an endpoint, as a JSON `{"paths": [...]}` POST request.
+struct EndpointInvalidator {
+    client: reqwest::blocking::Client,
+    url: String,
+    token: Option<String>,
+    max_paths: usize,
+}
+
+impl EndpointInvalidator {
+    fn new(config: &Config) -> Result<Self, Error> {
+        let url = match &config.cdn_purge_endpoint {
+            Some(url) => url.clone(),
+            None => bail!("DOCSRS_CDN_PURGE_ENDPOINT is required by the endpoint CDN"),
+        };
+
+        Ok(Self {
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+            url,
+            token: config.cdn_purge_token.clone(),
+            max_paths: config.cdn_purge_batch_size,
+        })
+    }
+}
+
+impl CdnInvalidator for EndpointInvalidator {
+    fn max_paths(&self) -> usize {
+        self.max_paths
+    }
+
+    fn invalidate(&self, paths: &[String]) -> Result<(), Error> {
+        let mut req = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({ "paths": paths }));
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        req.send()?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockInvalidator {
+    purged: std::sync::Mutex<Vec<Vec<String>>>,
+    /// How many of the next invalidations fail
+    failures: std::sync::Mutex<usize>,
+}
+
+#[cfg(test)]
+impl CdnInvalidator for MockInvalidator {
+    fn max_paths(&self) -> usize {
+        3
+    }
+
+    fn invalidate(&self, paths: &[String]) -> Result<(), Error> {
+        let mut failures = self.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            bail!("mocked failure");
+        }
+        self.purged.lock().unwrap().push(paths.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+
+    #[test]
+    fn purge_in_batches() {
+        wrapper(|env| {
+            let cdn = env.cdn();
+            cdn.purge_crate("foo");
+            assert_eq!(
+                cdn.purged(),
+                vec![
+                    vec!["/foo".to_string(), "/foo/*".into(), "/crate/foo".into()],
+                    vec!["/crate/foo/*".to_string()],
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn retry_failed_purges() {
+        wrapper(|env| {
+            env.override_config(|config| config.cdn_purge_attempts = 3);
+            let cdn = env.cdn();
+
+            cdn.fail_next(2);
+            cdn.purge(&["/foo".into()]);
+            assert_eq!(cdn.purged(), vec![vec!["/foo".to_string()]]);
+
+            // Failing every attempt is only logged
+            cdn.fail_next(3);
+            cdn.purge(&["/bar".into(), "/baz".into()]);
+            assert_eq!(cdn.purged().len(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn purge_release() {
+        wrapper(|env| {
+            let cdn = env.cdn();
+            cdn.purge_release("foo", "1.0.0");
+            let purged: Vec<String> = cdn.purged().into_iter().flatten().collect();
+            assert!(purged.contains(&"/foo/1.0.0/*".to_string()));
+            assert!(purged.contains(&"/crate/foo/1.0.0/*".to_string()));
+            assert!(purged.contains(&"/foo/latest/*".to_string()));
+            assert!(!purged.iter().any(|path| path == "/foo/*"));
+
+            Ok(())
+        });
+    }
+}
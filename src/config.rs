@@ -1,9 +1,16 @@
+use crate::cdn::CdnKind;
 use crate::storage::StorageKind;
+use crate::utils::candidate_priority::CandidatePriority;
+use crate::utils::github_fields::FieldSet;
+use crate::utils::retry_policy::RetryPolicies;
+use crate::utils::schedule::Schedule;
 use failure::{bail, format_err, Error, Fail, ResultExt};
 use rusoto_core::Region;
+use std::collections::HashMap;
 use std::env::VarError;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Config {
@@ -18,6 +25,8 @@ pub struct Config {
 
     // Storage params
     pub(crate) storage_backend: StorageKind,
+    // How many files are uploaded together, each batch in its own transaction
+    pub(crate) upload_batch_size: usize,
 
     // S3 params
     pub(crate) s3_bucket: String,
@@ -26,34 +35,215 @@ pub struct Config {
     #[cfg(test)]
     pub(crate) s3_bucket_is_temporary: bool,
 
+    // CDN params
+    pub(crate) cdn_backend: CdnKind,
+    pub(crate) cloudfront_distribution_id: Option<String>,
+    // Endpoint the paths to invalidate are sent to with the `endpoint` backend, with the token
+    // as a bearer token, in batches of at most `cdn_purge_batch_size` paths
+    pub(crate) cdn_purge_endpoint: Option<String>,
+    pub(crate) cdn_purge_token: Option<String>,
+    pub(crate) cdn_purge_batch_size: usize,
+    // How many times an invalidation is attempted before giving up
+    pub(crate) cdn_purge_attempts: u32,
+
+    // API keys allowed to use the admin endpoints
+    pub(crate) admin_api_keys: Vec<String>,
+    // Secret the registry sends to the webhook queueing new releases, which is disabled if unset
+    pub(crate) queue_webhook_secret: Option<String>,
+
+    // SMTP server the email notifications of failed builds are sent through, which are disabled
+    // if the host is unset. The connection is encrypted with STARTTLS, or with TLS from the start
+    // on port 465.
+    pub(crate) smtp_host: Option<String>,
+    pub(crate) smtp_port: u16,
+    pub(crate) smtp_username: Option<String>,
+    pub(crate) smtp_password: Option<String>,
+    // The sender of the email notifications, like `docs.rs <noreply@docs.rs>`
+    pub(crate) notifications_sender: String,
+
     // Github authentication
     pub(crate) github_accesstoken: Option<String>,
+    // Base URL of the GitHub API, which can be a caching proxy in front of it
+    pub(crate) github_api_base: String,
+    // Whether to send the requests to `github_api_base` without authentication, for proxies
+    // adding the credentials themselves
+    pub(crate) github_api_no_auth: bool,
     pub(crate) github_updater_min_rate_limit: u32,
+    // How many chunks of repositories the GitHub updater fetches in parallel
+    pub(crate) github_updater_concurrency: usize,
+    // Which repositories needing an update are updated first
+    pub(crate) github_updater_priority: CandidatePriority,
+    // Whether to ask GitHub for gzip compressed responses
+    pub(crate) github_updater_gzip: bool,
+    // Whether the GitHub updater only logs the summary of each run, like for scheduled runs
+    pub(crate) github_updater_quiet: bool,
+    // How long a run of the GitHub updater can last before stopping, leaving the remaining
+    // repositories for the next run
+    pub(crate) github_updater_max_run_duration: Option<Duration>,
+    // Whether the GitHub updater asks the registry API for the repository of the crates whose
+    // releases don't have one, before fetching its stats
+    pub github_updater_registry_fallback: bool,
+    // Whether the GitHub updater also fetches the README of the repositories, shown on the crate
+    // page of the releases without one, and the most bytes of each README it stores
+    pub(crate) github_updater_readme: bool,
+    pub(crate) github_readme_max_size: usize,
+    // Which optional fields of the repository stats the GitHub updater asks for, as each of them
+    // makes the GraphQL queries more expensive
+    pub(crate) github_fields: FieldSet,
+    // Which column of `github_repos` each field of the repository stats is stored in, for
+    // databases with a different schema. Fields that aren't mapped aren't stored.
+    pub(crate) github_column_mapping: Option<HashMap<String, String>>,
+    // How many times the GitHub updater retries each kind of failure, and whether it marks the
+    // repositories as updated anyway
+    pub(crate) github_retry_policies: RetryPolicies,
+    // Secret of the GitHub webhook sending the events of repositories, which is disabled if unset
+    pub(crate) github_webhook_secret: Option<String>,
+    // Base URLs of the APIs of the hosts repositories moved to from GitHub
+    pub(crate) gitlab_api_base: String,
+    pub(crate) codeberg_api_base: String,
 
     // Max size of the files served by the docs.rs frontend
     pub(crate) max_file_size: usize,
     pub(crate) max_file_size_html: usize,
     // The most memory that can be used to parse an HTML file
     pub(crate) max_parse_memory: usize,
+    // Max total size of the documentation of a release downloadable as an archive
+    pub(crate) max_archive_size: usize,
     // Time between 'git gc --auto' calls in seconds
     pub(crate) registry_gc_interval: u64,
+    // Random delay of up to this many seconds before checking the registry index for new
+    // releases, so that multiple instances don't check it at the same time
+    pub(crate) registry_poll_jitter: u64,
+    // Number of new releases above which they're queued in batches, with a summary logged
+    // instead of a line per release
+    pub(crate) registry_catch_up_threshold: usize,
+    // How many crates get their owners refreshed from the registry API every hour
+    pub(crate) owners_update_batch_size: i64,
+
+    // Content-Security-Policy directives added to documentation responses
+    pub(crate) csp_default_src: String,
+    pub(crate) csp_script_src: String,
+    pub(crate) csp_style_src: String,
+    pub(crate) csp_img_src: String,
+    pub(crate) csp_report_uri: Option<String>,
+
+    // Render the `mermaid` code blocks of the documentation as diagrams, with the Mermaid.js
+    // script at this URL, which is also allowed by the Content-Security-Policy. The scripts from
+    // other origins are checked against their Subresource Integrity hash, which is required.
+    pub(crate) enable_mermaid: bool,
+    pub(crate) mermaid_script_url: String,
+    pub(crate) mermaid_script_integrity: Option<String>,
+
+    // Render the math of the documentation of the crates opting in with KaTeX, loaded from this
+    // URL, which is also allowed by the Content-Security-Policy. Like for Mermaid.js, the script
+    // and the stylesheet from other origins are checked against their integrity hash.
+    pub(crate) enable_math: bool,
+    pub(crate) katex_url: String,
+    pub(crate) katex_script_integrity: Option<String>,
+    pub(crate) katex_stylesheet_integrity: Option<String>,
 
     // Build params
     pub(crate) build_attempts: u16,
+    // Time to wait before retrying a failed build in seconds, doubled after each failed attempt
+    pub(crate) build_retry_delay: u64,
     pub(crate) rustwide_workspace: PathBuf,
     pub(crate) inside_docker: bool,
     pub(crate) local_docker_image: Option<String>,
     pub(crate) toolchain: String,
     pub(crate) build_cpu_limit: Option<u32>,
     pub(crate) include_default_targets: bool,
+    // Targets built in addition to the default ones, for the crates not choosing their targets
+    pub(crate) additional_targets: Vec<String>,
+    // Whether to also store the rustdoc JSON output of the default target
+    pub(crate) build_rustdoc_json: bool,
+    // Whether to also compile the crates to record how long each of their dependencies takes
+    pub(crate) build_compile_timings: bool,
+    // Whether to also expand the macros used by the examples of the crates using procedural
+    // macros, which requires installing `cargo-expand`
+    pub(crate) build_macro_expansions: bool,
+    // Whether to also audit the dependencies of the crates for security advisories, which
+    // requires installing `cargo-audit`
+    pub(crate) build_audit: bool,
+    // Time between checks for new security advisories in seconds, after which the audited
+    // releases are audited again
+    pub(crate) audit_update_interval: u64,
+    // How far back to look for failed builds to requeue after a toolchain update, in days
+    pub(crate) toolchain_requeue_window: u32,
+    // The maximum number of failed builds requeued after a toolchain update
+    pub(crate) toolchain_requeue_limit: u32,
+    // Time between checks for a newer nightly in seconds, when building with the nightly channel
+    pub(crate) nightly_update_interval: u64,
+    // Number of builds running at the same time. Each worker has its own rustwide workspace, and
+    // the CPU and memory limits apply to each of them.
+    pub(crate) build_workers: usize,
+    // How long to wait for the running builds and web requests to finish when shutting down, in
+    // seconds
+    pub(crate) build_shutdown_grace_period: u64,
+    // How long a build can go without a heartbeat from its worker before it's considered
+    // abandoned and queued again, in seconds
+    pub(crate) build_stale_timeout: u64,
+    // When the periodic jobs of the daemon run, as `off`, an interval like `1h` or a cron
+    // expression like `55 23 * * *`
+    pub(crate) registry_watcher_schedule: Schedule,
+    pub(crate) release_activity_schedule: Schedule,
+    pub(crate) cooccurrence_schedule: Schedule,
+    pub(crate) health_score_schedule: Schedule,
+    pub(crate) owners_updater_schedule: Schedule,
+    pub(crate) github_updater_schedule: Schedule,
+    pub(crate) github_webhook_schedule: Schedule,
+    pub(crate) notifications_schedule: Schedule,
+    pub(crate) build_recovery_schedule: Schedule,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, Error> {
         let prefix: PathBuf = require_env("CRATESFYI_PREFIX")?;
+        // Time between checks of the registry index for new releases in seconds, unless the
+        // watcher has its own schedule
+        let registry_poll_interval = env("DOCSRS_REGISTRY_POLL_INTERVAL", 60)?;
+        let enable_mermaid = env("CRATESFYI_ENABLE_MERMAID", false)?;
+        let mermaid_script_url = env(
+            "DOCSRS_MERMAID_SCRIPT_URL",
+            "https://cdn.jsdelivr.net/npm/mermaid@10.6.1/dist/mermaid.min.js".to_string(),
+        )?;
+        let mermaid_script_integrity = maybe_env("DOCSRS_MERMAID_SCRIPT_INTEGRITY")?;
+        if enable_mermaid
+            && !is_same_origin(&mermaid_script_url)
+            && mermaid_script_integrity.is_none()
+        {
+            bail!(
+                "DOCSRS_MERMAID_SCRIPT_INTEGRITY must be set to the hash of {}, or \
+                 DOCSRS_MERMAID_SCRIPT_URL to a script served by docs.rs",
+                mermaid_script_url
+            );
+        }
+        let enable_math = env("DOCSRS_ENABLE_MATH", false)?;
+        let katex_url = env(
+            "DOCSRS_KATEX_URL",
+            "https://cdn.jsdelivr.net/npm/katex@0.16.9/dist/".to_string(),
+        )?;
+        let katex_script_integrity = maybe_env("DOCSRS_KATEX_SCRIPT_INTEGRITY")?;
+        let katex_stylesheet_integrity = maybe_env("DOCSRS_KATEX_STYLESHEET_INTEGRITY")?;
+        if enable_math
+            && !is_same_origin(&katex_url)
+            && (katex_script_integrity.is_none() || katex_stylesheet_integrity.is_none())
+        {
+            bail!(
+                "DOCSRS_KATEX_SCRIPT_INTEGRITY and DOCSRS_KATEX_STYLESHEET_INTEGRITY must be set \
+                 to the hashes of katex.min.js and katex.min.css at {}, or DOCSRS_KATEX_URL to \
+                 files served by docs.rs",
+                katex_url
+            );
+        }
+        // Whether to also count the open issues labeled as good first issues or help wanted,
+        // unless the fields are listed in `DOCSRS_GITHUB_FIELDS`
+        let github_good_first_issues = env("DOCSRS_GITHUB_GOOD_FIRST_ISSUES", false)?;
+        let mut github_fields = FieldSet::default();
+        github_fields.set(FieldSet::GOOD_FIRST_ISSUES, github_good_first_issues);
 
         Ok(Self {
             build_attempts: env("DOCSRS_BUILD_ATTEMPTS", 5)?,
+            build_retry_delay: env("DOCSRS_BUILD_RETRY_DELAY", 60)?,
 
             prefix: prefix.clone(),
             registry_index_path: env("REGISTRY_INDEX_PATH", prefix.join("crates.io-index"))?,
@@ -64,6 +254,7 @@ impl Config {
             min_pool_idle: env("DOCSRS_MIN_POOL_IDLE", 10)?,
 
             storage_backend: env("DOCSRS_STORAGE_BACKEND", StorageKind::Database)?,
+            upload_batch_size: env("DOCSRS_UPLOAD_BATCH_SIZE", 1000)?,
 
             s3_bucket: env("DOCSRS_S3_BUCKET", "rust-docs-rs".to_string())?,
             s3_region: env("S3_REGION", Region::UsWest1)?,
@@ -74,15 +265,96 @@ impl Config {
             #[cfg(test)]
             s3_bucket_is_temporary: false,
 
+            cdn_backend: env("DOCSRS_CDN_BACKEND", CdnKind::None)?,
+            cloudfront_distribution_id: maybe_env("DOCSRS_CLOUDFRONT_DISTRIBUTION_ID")?,
+            cdn_purge_endpoint: maybe_env("DOCSRS_CDN_PURGE_ENDPOINT")?,
+            cdn_purge_token: maybe_env("DOCSRS_CDN_PURGE_TOKEN")?,
+            cdn_purge_batch_size: env("DOCSRS_CDN_PURGE_BATCH_SIZE", 100)?,
+            cdn_purge_attempts: env("DOCSRS_CDN_PURGE_ATTEMPTS", 3)?,
+
+            admin_api_keys: env("DOCSRS_ADMIN_API_KEYS", String::new())?
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(String::from)
+                .collect(),
+            queue_webhook_secret: maybe_env("DOCSRS_QUEUE_WEBHOOK_SECRET")?,
+
+            smtp_host: maybe_env("DOCSRS_SMTP_HOST")?,
+            smtp_port: env("DOCSRS_SMTP_PORT", 587)?,
+            smtp_username: maybe_env("DOCSRS_SMTP_USERNAME")?,
+            smtp_password: maybe_env("DOCSRS_SMTP_PASSWORD")?,
+            notifications_sender: env(
+                "DOCSRS_NOTIFICATIONS_SENDER",
+                "docs.rs <noreply@docs.rs>".to_string(),
+            )?,
+
             github_accesstoken: maybe_env("CRATESFYI_GITHUB_ACCESSTOKEN")?,
+            github_api_base: env(
+                "DOCSRS_GITHUB_API_BASE",
+                "https://api.github.com".to_string(),
+            )?,
+            github_api_no_auth: env("DOCSRS_GITHUB_API_NO_AUTH", false)?,
             github_updater_min_rate_limit: env("DOCSRS_GITHUB_UPDATER_MIN_RATE_LIMIT", 2500)?,
+            github_updater_concurrency: env("DOCSRS_GITHUB_UPDATER_CONCURRENCY", 1)?,
+            github_updater_priority: env(
+                "DOCSRS_GITHUB_UPDATER_PRIORITY",
+                CandidatePriority::default(),
+            )?,
+            github_updater_gzip: env("DOCSRS_GITHUB_UPDATER_GZIP", true)?,
+            github_updater_quiet: env("DOCSRS_GITHUB_UPDATER_QUIET", false)?,
+            github_updater_max_run_duration: maybe_env("DOCSRS_GITHUB_UPDATER_MAX_RUN_DURATION")?
+                .map(Duration::from_secs),
+            github_updater_registry_fallback: env(
+                "DOCSRS_GITHUB_UPDATER_REGISTRY_FALLBACK",
+                false,
+            )?,
+            github_updater_readme: env("DOCSRS_GITHUB_UPDATER_README", false)?,
+            github_readme_max_size: env("DOCSRS_GITHUB_README_MAX_SIZE", 100 * 1024)?,
+            github_fields: env("DOCSRS_GITHUB_FIELDS", github_fields)?,
+            github_column_mapping: maybe_env::<String>("DOCSRS_GITHUB_COLUMN_MAPPING")?
+                .map(|mapping| parse_column_mapping(&mapping))
+                .transpose()
+                .context("failed to parse configuration variable DOCSRS_GITHUB_COLUMN_MAPPING")?,
+            github_retry_policies: env("DOCSRS_GITHUB_RETRY_POLICIES", RetryPolicies::default())?,
+            github_webhook_secret: maybe_env("CRATESFYI_GITHUB_WEBHOOK_SECRET")?,
+            gitlab_api_base: env(
+                "DOCSRS_GITLAB_API_BASE",
+                "https://gitlab.com/api/v4".to_string(),
+            )?,
+            codeberg_api_base: env(
+                "DOCSRS_CODEBERG_API_BASE",
+                "https://codeberg.org/api/v1".to_string(),
+            )?,
 
             max_file_size: env("DOCSRS_MAX_FILE_SIZE", 50 * 1024 * 1024)?,
             max_file_size_html: env("DOCSRS_MAX_FILE_SIZE_HTML", 50 * 1024 * 1024)?,
             // LOL HTML only uses as much memory as the size of the start tag!
             // https://github.com/rust-lang/docs.rs/pull/930#issuecomment-667729380
             max_parse_memory: env("DOCSRS_MAX_PARSE_MEMORY", 5 * 1024 * 1024)?,
+            max_archive_size: env("DOCSRS_MAX_ARCHIVE_SIZE", 100 * 1024 * 1024)?,
             registry_gc_interval: env("DOCSRS_REGISTRY_GC_INTERVAL", 60 * 60)?,
+            registry_poll_jitter: env("DOCSRS_REGISTRY_POLL_JITTER", 10)?,
+            registry_catch_up_threshold: env("DOCSRS_REGISTRY_CATCH_UP_THRESHOLD", 100)?,
+            owners_update_batch_size: env("DOCSRS_OWNERS_UPDATE_BATCH_SIZE", 1000)?,
+
+            csp_default_src: env("DOCSRS_CSP_DEFAULT_SRC", "'self'".to_string())?,
+            // 'unsafe-inline' is required by the Playground integration
+            csp_script_src: env(
+                "DOCSRS_CSP_SCRIPT_SRC",
+                "'self' 'unsafe-inline'".to_string(),
+            )?,
+            csp_style_src: env("DOCSRS_CSP_STYLE_SRC", "'self' 'unsafe-inline'".to_string())?,
+            csp_img_src: env("DOCSRS_CSP_IMG_SRC", "'self' data: https:".to_string())?,
+            csp_report_uri: maybe_env("CRATESFYI_CSP_REPORT_URI")?,
+
+            enable_mermaid,
+            mermaid_script_url,
+            mermaid_script_integrity,
+            enable_math,
+            katex_url,
+            katex_script_integrity,
+            katex_stylesheet_integrity,
 
             rustwide_workspace: env("CRATESFYI_RUSTWIDE_WORKSPACE", PathBuf::from(".workspace"))?,
             inside_docker: env("DOCS_RS_DOCKER", false)?,
@@ -90,10 +362,89 @@ impl Config {
             toolchain: env("CRATESFYI_TOOLCHAIN", "nightly".to_string())?,
             build_cpu_limit: maybe_env("DOCS_RS_BUILD_CPU_LIMIT")?,
             include_default_targets: env("DOCSRS_INCLUDE_DEFAULT_TARGETS", true)?,
+            additional_targets: env("DOCSRS_ADDITIONAL_TARGETS", String::new())?
+                .split(',')
+                .map(str::trim)
+                .filter(|target| !target.is_empty())
+                .map(String::from)
+                .collect(),
+            build_rustdoc_json: env("DOCSRS_BUILD_RUSTDOC_JSON", true)?,
+            build_compile_timings: env("DOCSRS_BUILD_COMPILE_TIMINGS", false)?,
+            build_macro_expansions: env("DOCSRS_BUILD_MACRO_EXPANSIONS", false)?,
+            build_audit: env("DOCSRS_BUILD_AUDIT", false)?,
+            audit_update_interval: env("DOCSRS_AUDIT_UPDATE_INTERVAL", 6 * 60 * 60)?,
+            toolchain_requeue_window: env("DOCSRS_TOOLCHAIN_REQUEUE_WINDOW", 7)?,
+            toolchain_requeue_limit: env("DOCSRS_TOOLCHAIN_REQUEUE_LIMIT", 1000)?,
+            nightly_update_interval: env("DOCSRS_NIGHTLY_UPDATE_INTERVAL", 6 * 60 * 60)?,
+            build_workers: env("DOCSRS_BUILD_WORKERS", 1)?,
+            build_shutdown_grace_period: env("DOCSRS_BUILD_SHUTDOWN_GRACE_PERIOD", 15 * 60)?,
+            build_stale_timeout: env("DOCSRS_BUILD_STALE_TIMEOUT", 10 * 60)?,
+
+            registry_watcher_schedule: env(
+                "DOCSRS_REGISTRY_WATCHER_SCHEDULE",
+                Schedule::Every(Duration::from_secs(registry_poll_interval)),
+            )?,
+            release_activity_schedule: env(
+                "DOCSRS_RELEASE_ACTIVITY_SCHEDULE",
+                "55 23 * * *".parse()?,
+            )?,
+            cooccurrence_schedule: env("DOCSRS_COOCCURRENCE_SCHEDULE", "15 0 * * *".parse()?)?,
+            health_score_schedule: env("DOCSRS_HEALTH_SCORE_SCHEDULE", "30 1 * * 0".parse()?)?,
+            owners_updater_schedule: env(
+                "DOCSRS_OWNERS_UPDATER_SCHEDULE",
+                Schedule::Every(Duration::from_secs(60 * 60)),
+            )?,
+            github_updater_schedule: env(
+                "DOCSRS_GITHUB_UPDATER_SCHEDULE",
+                Schedule::Every(Duration::from_secs(60 * 60)),
+            )?,
+            github_webhook_schedule: env(
+                "DOCSRS_GITHUB_WEBHOOK_SCHEDULE",
+                Schedule::Every(Duration::from_secs(60)),
+            )?,
+            notifications_schedule: env(
+                "DOCSRS_NOTIFICATIONS_SCHEDULE",
+                Schedule::Every(Duration::from_secs(60)),
+            )?,
+            build_recovery_schedule: env(
+                "DOCSRS_BUILD_RECOVERY_SCHEDULE",
+                Schedule::Every(Duration::from_secs(5 * 60)),
+            )?,
         })
     }
 }
 
+/// Parses a comma separated list of `field=column` pairs.
+fn parse_column_mapping(mapping: &str) -> Result<HashMap<String, String>, Error> {
+    let mut columns = HashMap::new();
+    for pair in mapping
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (field, column) = match pair.split_once('=') {
+            Some((field, column)) => (field.trim(), column.trim()),
+            None => bail!("expected `field=column`, found `{}`", pair),
+        };
+        // The columns are part of the queries, so they must be plain identifiers
+        let is_identifier = column.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+            && column
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !is_identifier {
+            bail!("invalid column name `{}`", column);
+        }
+        columns.insert(field.to_string(), column.to_string());
+    }
+    Ok(columns)
+}
+
+/// Whether `url` is a path on docs.rs itself, like `/-/static/mermaid.min.js`, which doesn't need
+/// an integrity hash.
+fn is_same_origin(url: &str) -> bool {
+    url.starts_with('/') && !url.starts_with("//")
+}
+
 fn env<T>(var: &str, default: T) -> Result<T, Error>
 where
     T: FromStr,
@@ -1,5 +1,5 @@
 use crate::db::Pool;
-use crate::{BuildQueue, Config, Index, Metrics, Storage};
+use crate::{BuildQueue, Cdn, Config, Index, Metrics, Storage};
 use failure::Error;
 use std::sync::Arc;
 
@@ -10,4 +10,5 @@ pub trait Context {
     fn pool(&self) -> Result<Pool, Error>;
     fn metrics(&self) -> Result<Arc<Metrics>, Error>;
     fn index(&self) -> Result<Arc<Index>, Error>;
+    fn cdn(&self) -> Result<Arc<Cdn>, Error>;
 }
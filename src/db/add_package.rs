@@ -6,16 +6,20 @@ use std::{
 
 use crate::{
     db::types::Feature,
-    docbuilder::{BuildResult, DocCoverage},
+    docbuilder::{
+        AuditFinding, BuildResult, CompileTiming, DocCoverage, DocWarnings, FunctionSignature,
+        InlineTests, TypeGraph,
+    },
     error::Result,
     index::api::{CrateData, CrateOwner, ReleaseData},
     storage::CompressionAlgorithm,
     utils::MetadataPackage,
 };
+use docsrs_metadata::Metadata;
 use log::{debug, info};
-use postgres::Client;
-use regex::Regex;
+use postgres::{Client, GenericClient};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use slug::slugify;
 
 /// Adds a package into database.
@@ -33,6 +37,7 @@ pub(crate) fn add_package_into_database(
     default_target: &str,
     source_files: Value,
     doc_targets: Vec<String>,
+    failed_targets: Vec<String>,
     registry_data: &ReleaseData,
     has_docs: bool,
     has_examples: bool,
@@ -45,7 +50,11 @@ pub(crate) fn add_package_into_database(
     let rustdoc = get_rustdoc(metadata_pkg, source_dir).unwrap_or(None);
     let readme = get_readme(metadata_pkg, source_dir).unwrap_or(None);
     let features = get_features(metadata_pkg);
+    let enabled_features = get_enabled_features(&features, source_dir);
     let is_library = metadata_pkg.is_library();
+    let math = Metadata::from_crate_root(source_dir)
+        .map(|metadata| metadata.math())
+        .unwrap_or(false);
 
     let rows = conn.query(
         "INSERT INTO releases (
@@ -55,12 +64,14 @@ pub(crate) fn add_package_into_database(
             homepage_url, description, description_long, readme,
             authors, keywords, have_examples, downloads, files,
             doc_targets, is_library, doc_rustc_version,
-            documentation_url, default_target, features, github_repo
+            documentation_url, default_target, features, github_repo,
+            enabled_features, failed_targets, is_proc_macro, math
          )
          VALUES (
             $1,  $2,  $3,  $4,  $5,  $6,  $7,  $8,  $9,
             $10, $11, $12, $13, $14, $15, $16, $17, $18,
-            $19, $20, $21, $22, $23, $24, $25, $26, $27
+            $19, $20, $21, $22, $23, $24, $25, $26, $27,
+            $28, $29, $30, $31
          )
          ON CONFLICT (crate_id, version) DO UPDATE
             SET release_time = $3,
@@ -87,7 +98,11 @@ pub(crate) fn add_package_into_database(
                 documentation_url = $24,
                 default_target = $25,
                 features = $26,
-                github_repo = $27
+                github_repo = $27,
+                enabled_features = $28,
+                failed_targets = $29,
+                is_proc_macro = $30,
+                math = $31
          RETURNING id",
         &[
             &crate_id,
@@ -117,6 +132,10 @@ pub(crate) fn add_package_into_database(
             &default_target,
             &features,
             &github_repo,
+            &enabled_features,
+            &failed_targets,
+            &metadata_pkg.is_proc_macro(),
+            &math,
         ],
     )?;
 
@@ -133,10 +152,30 @@ pub(crate) fn add_package_into_database(
          WHERE id = $1",
         &[&crate_id, &release_id],
     )?;
+    update_crate_dependents(conn, crate_id)?;
 
     Ok(release_id)
 }
 
+/// Records the dependencies of the latest release of the crate in `crate_dependents`, which the
+/// graphs of the dependents are walked from.
+pub(crate) fn update_crate_dependents(conn: &mut impl GenericClient, crate_id: i32) -> Result<()> {
+    conn.execute(
+        "DELETE FROM crate_dependents WHERE dependent_id = $1",
+        &[&crate_id],
+    )?;
+    conn.execute(
+        "INSERT INTO crate_dependents (dependency, dependent_id)
+         SELECT DISTINCT dependency->>0, crates.id
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id
+         CROSS JOIN json_array_elements(releases.dependencies) AS dependency
+         WHERE crates.id = $1 AND dependency->>0 IS NOT NULL",
+        &[&crate_id],
+    )?;
+    Ok(())
+}
+
 pub(crate) fn add_doc_coverage(
     conn: &mut Client,
     release_id: i32,
@@ -167,6 +206,20 @@ pub(crate) fn add_doc_coverage(
     Ok(rows[0].get(0))
 }
 
+/// Stores how long compiling each package of the dependency graph of a release took.
+pub(crate) fn add_compile_timings(
+    conn: &mut Client,
+    release_id: i32,
+    compile_timings: &[CompileTiming],
+) -> Result<()> {
+    debug!("Adding compile timings into database");
+    conn.execute(
+        "UPDATE releases SET compile_timings = $2 WHERE id = $1",
+        &[&release_id, &serde_json::to_value(compile_timings)?],
+    )?;
+    Ok(())
+}
+
 /// Adds a build into database
 pub(crate) fn add_build_into_database(
     conn: &mut Client,
@@ -174,11 +227,14 @@ pub(crate) fn add_build_into_database(
     res: &BuildResult,
 ) -> Result<i32> {
     debug!("Adding build into database");
+    let stats = &res.stats;
     let rows = conn.query(
         "INSERT INTO builds (rid, rustc_version,
                                                     cratesfyi_version,
-                                                    build_status, output)
-                                VALUES ($1, $2, $3, $4, $5)
+                                                    build_status, output,
+                                                    duration_ms, peak_memory_bytes,
+                                                    files_count, files_size_bytes)
+                                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
                                 RETURNING id",
         &[
             &release_id,
@@ -186,11 +242,287 @@ pub(crate) fn add_build_into_database(
             &res.docsrs_version,
             &res.successful,
             &res.build_log,
+            &(stats.duration.as_millis() as i64),
+            &stats.peak_memory.map(|bytes| bytes as i64),
+            &(stats.files as i64),
+            &(stats.size as i64),
         ],
     )?;
+    super::crate_metrics::record_build_metrics(conn, release_id, res)?;
     Ok(rows[0].get(0))
 }
 
+/// Adds the examples of a release into database, replacing the examples of previous builds.
+///
+/// Examples are the `examples/*.rs` and `examples/*/main.rs` files of the crate, and their
+/// description is the `//!` doc comment of the file.
+pub(crate) fn add_examples_into_database(
+    conn: &mut Client,
+    release_id: i32,
+    source_dir: &Path,
+) -> Result<()> {
+    debug!("Adding examples into database");
+    let examples = get_examples(&source_dir.join("examples"))?;
+
+    let mut transaction = conn.transaction()?;
+    transaction.execute("DELETE FROM examples WHERE release_id = $1", &[&release_id])?;
+    for (name, source_code) in examples {
+        transaction.execute(
+            "INSERT INTO examples (release_id, name, description, source_code)
+             VALUES ($1, $2, $3, $4)",
+            &[
+                &release_id,
+                &name,
+                &example_description(&source_code),
+                &source_code,
+            ],
+        )?;
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Adds the test functions written in the sources of a release into database, replacing the ones
+/// of previous builds, and records how many there are to flag the releases without any, and whether
+/// some were left out.
+pub(crate) fn add_inline_tests(
+    conn: &mut Client,
+    release_id: i32,
+    tests: &InlineTests,
+) -> Result<()> {
+    debug!("Adding inline tests into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM inline_tests WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    for test in &tests.tests {
+        transaction.execute(
+            "INSERT INTO inline_tests (release_id, module_path, test_name, source_code)
+             VALUES ($1, $2, $3, $4)",
+            &[
+                &release_id,
+                &test.module_path,
+                &test.name,
+                &test.source_code,
+            ],
+        )?;
+    }
+    transaction.execute(
+        "UPDATE releases
+         SET inline_test_count = $2, inline_tests_truncated = $3
+         WHERE id = $1",
+        &[&release_id, &(tests.tests.len() as i32), &tests.truncated],
+    )?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Adds the warnings of rustdoc about the documentation of a release into database, replacing
+/// the ones of previous builds, and records how many there are to find the releases without any.
+/// The warnings of previous builds are only removed for builds without documentation, `None`.
+pub(crate) fn add_doc_warnings(
+    conn: &mut Client,
+    release_id: i32,
+    warnings: Option<&DocWarnings>,
+) -> Result<()> {
+    debug!("Adding documentation warnings into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM doc_warnings WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    for warning in warnings.iter().flat_map(|warnings| &warnings.warnings) {
+        transaction.execute(
+            "INSERT INTO doc_warnings (release_id, warning_type, item_path, message, location)
+             VALUES ($1, $2, $3, $4, $5)",
+            &[
+                &release_id,
+                &warning.warning_type,
+                &warning.item_path,
+                &warning.message,
+                &warning.location,
+            ],
+        )?;
+    }
+    transaction.execute(
+        "UPDATE releases SET doc_warning_count = $2 WHERE id = $1",
+        &[&release_id, &warnings.map(|warnings| warnings.total as i32)],
+    )?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Adds the signatures of the public functions of a release into database, replacing the ones
+/// of previous builds.
+pub(crate) fn add_function_signatures(
+    conn: &mut Client,
+    release_id: i32,
+    signatures: &[FunctionSignature],
+) -> Result<()> {
+    debug!("Adding function signatures into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM function_signatures WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    for signature in signatures {
+        transaction.execute(
+            "INSERT INTO function_signatures
+                (crate_id, release_id, item_path, param_types, return_type)
+             SELECT crate_id, id, $2, $3, $4 FROM releases WHERE id = $1",
+            &[
+                &release_id,
+                &signature.path,
+                &serde_json::to_value(&signature.param_types)?,
+                &signature.return_type,
+            ],
+        )?;
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Stores the type graph of a release, built from its rustdoc JSON output.
+pub(crate) fn add_type_graph(
+    conn: &mut Client,
+    release_id: i32,
+    type_graph: &TypeGraph,
+) -> Result<()> {
+    debug!("Adding type graph into database");
+    conn.execute(
+        "UPDATE releases SET type_graph = $2 WHERE id = $1",
+        &[&release_id, &serde_json::to_value(type_graph)?],
+    )?;
+
+    Ok(())
+}
+
+/// Adds the security advisories affecting the dependencies of a release into database, replacing
+/// the ones of previous audits, and records which commit of the advisory database it was
+/// audited against.
+pub(crate) fn add_audit_results(
+    conn: &mut Client,
+    release_id: i32,
+    findings: &[AuditFinding],
+    advisory_db_commit: Option<&str>,
+) -> Result<()> {
+    debug!("Adding audit results into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM audit_results WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    for finding in findings {
+        transaction.execute(
+            "INSERT INTO audit_results
+                (release_id, advisory_id, severity, affected_crate, affected_version,
+                 patched_versions)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &release_id,
+                &finding.advisory_id,
+                &finding.severity,
+                &finding.affected_crate,
+                &finding.affected_version,
+                &finding.patched_versions,
+            ],
+        )?;
+    }
+    transaction.execute(
+        "INSERT INTO audit_runs (release_id, advisory_db_commit, audited_at)
+         VALUES ($1, $2, NOW())
+         ON CONFLICT (release_id) DO UPDATE
+            SET advisory_db_commit = EXCLUDED.advisory_db_commit,
+                audited_at = EXCLUDED.audited_at",
+        &[&release_id, &advisory_db_commit],
+    )?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Returns the names of the examples of the crate in `source_dir`, sorted.
+pub(crate) fn example_names(source_dir: &Path) -> Result<Vec<String>> {
+    Ok(get_examples(&source_dir.join("examples"))?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// Adds the expansion of the macros used by the examples of a release into database, by name
+/// of the example, replacing the expansions of previous builds.
+pub(crate) fn add_macro_expansions_into_database(
+    conn: &mut Client,
+    release_id: i32,
+    expansions: &[(String, String)],
+) -> Result<()> {
+    debug!("Adding macro expansions into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM macro_expansions WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    for (example, expansion) in expansions {
+        transaction.execute(
+            "INSERT INTO macro_expansions (release_id, example_name, expansion)
+             VALUES ($1, $2, $3)",
+            &[&release_id, example, expansion],
+        )?;
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Returns the name and source code of all the examples in the directory, sorted by name.
+fn get_examples(examples_dir: &Path) -> Result<Vec<(String, String)>> {
+    let mut examples = Vec::new();
+    if !examples_dir.is_dir() {
+        return Ok(examples);
+    }
+
+    for entry in fs::read_dir(examples_dir)? {
+        let path = entry?.path();
+        let (name, file) = if path.is_dir() {
+            (path.file_name(), path.join("main.rs"))
+        } else if path.extension() == Some("rs".as_ref()) {
+            (path.file_stem(), path.clone())
+        } else {
+            continue;
+        };
+
+        if let (Some(name), true) = (name.and_then(|name| name.to_str()), file.is_file()) {
+            examples.push((name.to_string(), fs::read_to_string(&file)?));
+        }
+    }
+    examples.sort();
+
+    Ok(examples)
+}
+
+/// Extracts the `//!` doc comment of an example
+fn example_description(source_code: &str) -> Option<String> {
+    let mut description = String::new();
+    for line in source_code.lines() {
+        if let Some(line) = line.trim_start().strip_prefix("//!") {
+            // some lines may or may not have a space between the `//!` and the start of the text
+            description.push_str(line.trim_start());
+            description.push('\n');
+        }
+    }
+
+    if description.trim().is_empty() {
+        None
+    } else {
+        Some(description)
+    }
+}
+
 fn initialize_package_in_database(conn: &mut Client, pkg: &MetadataPackage) -> Result<i32> {
     let mut rows = conn.query("SELECT id FROM crates WHERE name = $1", &[&pkg.name])?;
     // insert crate into database if it is not exists
@@ -251,6 +583,19 @@ fn get_optional_dependencies(pkg: &MetadataPackage) -> Vec<Feature> {
         .collect()
 }
 
+/// Returns the features enabled by the docs.rs metadata of the crate, which are the ones its
+/// documentation is built with.
+fn get_enabled_features(features: &[Feature], source_dir: &Path) -> Vec<String> {
+    let features = features
+        .iter()
+        .map(|feature| (feature.name.clone(), feature.subfeatures.clone()))
+        .collect();
+
+    Metadata::from_crate_root(source_dir)
+        .unwrap_or_default()
+        .enabled_features(&features)
+}
+
 /// Reads readme if there is any read defined in Cargo.toml of a Package
 fn get_readme(pkg: &MetadataPackage, source_dir: &Path) -> Result<Option<String>> {
     let readme_path = source_dir.join(pkg.readme.as_deref().unwrap_or("README.md"));
@@ -347,52 +692,87 @@ fn add_keywords_into_database(
     Ok(())
 }
 
+/// Role of the authors listed in the `authors` field of `Cargo.toml`.
+const CARGO_AUTHOR_ROLE: &str = "author";
+
 /// Adds authors into database
+///
+/// Only the names of the authors are stored, alongside a hash of their email.
 fn add_authors_into_database(
     conn: &mut Client,
     pkg: &MetadataPackage,
     release_id: i32,
 ) -> Result<()> {
-    let author_capture_re = Regex::new("^([^><]+)<*(.*?)>*$").unwrap();
     for author in &pkg.authors {
-        if let Some(author_captures) = author_capture_re.captures(&author[..]) {
-            let author = author_captures
-                .get(1)
-                .map(|m| m.as_str())
-                .unwrap_or("")
-                .trim();
-            let email = author_captures
-                .get(2)
-                .map(|m| m.as_str())
-                .unwrap_or("")
-                .trim();
-            let slug = slugify(&author);
-
-            let author_id: i32 = {
-                let rows = conn.query("SELECT id FROM authors WHERE slug = $1", &[&slug])?;
-                if !rows.is_empty() {
-                    rows[0].get(0)
-                } else {
-                    conn.query(
-                        "INSERT INTO authors (name, email, slug) VALUES ($1, $2, $3)
-                                     RETURNING id",
-                        &[&author, &email, &slug],
-                    )?[0]
-                        .get(0)
-                }
-            };
-
-            // add relationship
-            let _ = conn.query(
-                "INSERT INTO author_rels (rid, aid) VALUES ($1, $2)",
-                &[&release_id, &author_id],
-            );
+        let (name, email) = match parse_author(author) {
+            Some(author) => author,
+            None => continue,
+        };
+        let slug = slugify(name);
+        if slug.is_empty() {
+            continue;
         }
+        let email_hash = email.map(hash_email);
+
+        let author_id: i32 = conn
+            .query_one(
+                "INSERT INTO authors (name, email_hash, slug) VALUES ($1, $2, $3)
+                 ON CONFLICT (slug) DO UPDATE
+                     SET email_hash = COALESCE(authors.email_hash, EXCLUDED.email_hash)
+                 RETURNING id",
+                &[&name, &email_hash, &slug],
+            )?
+            .get(0);
+
+        // add relationships
+        conn.execute(
+            "INSERT INTO author_rels (rid, aid) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            &[&release_id, &author_id],
+        )?;
+        conn.execute(
+            "INSERT INTO crate_authors (crate_id, author_id, role)
+             SELECT crate_id, $2, $3 FROM releases WHERE id = $1
+             ON CONFLICT DO NOTHING",
+            &[&release_id, &author_id, &CARGO_AUTHOR_ROLE],
+        )?;
     }
 
     Ok(())
 }
 
+/// Splits an author from `Cargo.toml`, formatted as `Name <email>`, into its name and email.
+///
+/// Returns `None` if the author has no name, as emails are never displayed.
+fn parse_author(author: &str) -> Option<(&str, Option<&str>)> {
+    let (name, email) = match author.find('<') {
+        Some(start) => {
+            let email = &author[start + 1..];
+            let email = match email.find('>') {
+                Some(end) => &email[..end],
+                None => email,
+            };
+            (&author[..start], Some(email.trim()))
+        }
+        None => (author, None),
+    };
+
+    let name = name.trim();
+    // Authors listed only by their email, without the angle brackets
+    if name.is_empty() || (email.is_none() && name.contains('@') && !name.contains(' ')) {
+        return None;
+    }
+
+    Some((name, email.filter(|email| !email.is_empty())))
+}
+
+/// Hashes an email, so authors can be told apart without storing their email.
+fn hash_email(email: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(email.trim().to_lowercase().as_bytes())
+    )
+}
+
 pub fn update_crate_data_in_database(
     conn: &mut Client,
     name: &str,
@@ -402,6 +782,10 @@ pub fn update_crate_data_in_database(
     let crate_id = conn.query("SELECT id FROM crates WHERE crates.name = $1", &[&name])?[0].get(0);
 
     update_owners_in_database(conn, &registry_data.owners, crate_id)?;
+    conn.execute(
+        "UPDATE crates SET owners_updated_at = NOW() WHERE id = $1",
+        &[&crate_id],
+    )?;
 
     Ok(())
 }
@@ -432,16 +816,23 @@ fn update_owners_in_database(
         let owner_id: i32 = {
             conn.query(
                 "
-                    INSERT INTO owners (login, avatar, name, email)
-                    VALUES ($1, $2, $3, $4)
+                    INSERT INTO owners (login, avatar, name, email, kind)
+                    VALUES ($1, $2, $3, $4, $5)
                     ON CONFLICT (login) DO UPDATE
                         SET
                             avatar = $2,
                             name = $3,
-                            email = $4
+                            email = $4,
+                            kind = $5
                     RETURNING id
                 ",
-                &[&owner.login, &owner.avatar, &owner.name, &owner.email],
+                &[
+                    &owner.login,
+                    &owner.avatar,
+                    &owner.name,
+                    &owner.email,
+                    &owner.kind,
+                ],
             )?[0]
                 .get(0)
         };
@@ -489,3 +880,100 @@ where
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn test_parse_author() {
+        assert_eq!(
+            parse_author("Fake Person <fake@example.com>"),
+            Some(("Fake Person", Some("fake@example.com")))
+        );
+        assert_eq!(parse_author("Fake Person"), Some(("Fake Person", None)));
+        assert_eq!(
+            parse_author("  Fake   Person  <  fake@example.com >  "),
+            Some(("Fake   Person", Some("fake@example.com")))
+        );
+        // Missing or unclosed emails
+        assert_eq!(parse_author("Fake Person <>"), Some(("Fake Person", None)));
+        assert_eq!(
+            parse_author("Fake Person <fake@example.com"),
+            Some(("Fake Person", Some("fake@example.com")))
+        );
+        // Anything after the email is ignored
+        assert_eq!(
+            parse_author("Fake Person <fake@example.com> (https://example.com)"),
+            Some(("Fake Person", Some("fake@example.com")))
+        );
+        assert_eq!(
+            parse_author("Fake Person (https://example.com)"),
+            Some(("Fake Person (https://example.com)", None))
+        );
+
+        // Authors without a name aren't stored
+        assert_eq!(parse_author(""), None);
+        assert_eq!(parse_author("   "), None);
+        assert_eq!(parse_author("<fake@example.com>"), None);
+        assert_eq!(parse_author("fake@example.com"), None);
+    }
+
+    #[test]
+    fn test_hash_email() {
+        assert_eq!(hash_email("fake@example.com").len(), 64);
+        assert_eq!(
+            hash_email("fake@example.com"),
+            hash_email(" Fake@Example.com ")
+        );
+        assert_ne!(
+            hash_email("fake@example.com"),
+            hash_email("other@example.com")
+        );
+    }
+
+    #[test]
+    fn test_authors_stored_without_emails() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .author("Fake Person <fake@example.com>")
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .author("Fake Person <fake@example.com>")
+                .create()?;
+            env.fake_release()
+                .name("other")
+                .version("0.1.0")
+                .author("fake@example.com")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let rows = conn.query("SELECT name, email_hash, slug FROM authors", &[])?;
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].get::<_, String>("name"), "Fake Person");
+            assert_eq!(
+                rows[0].get::<_, Option<String>>("email_hash"),
+                Some(hash_email("fake@example.com"))
+            );
+
+            let crate_authors: Vec<(String, String)> = conn
+                .query(
+                    "SELECT crates.name, crate_authors.role
+                     FROM crate_authors
+                     INNER JOIN crates ON crates.id = crate_authors.crate_id",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(crate_authors, vec![("dummy".into(), "author".into())]);
+
+            Ok(())
+        })
+    }
+}
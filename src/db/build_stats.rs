@@ -0,0 +1,134 @@
+//! Statistics about recent builds, used for capacity planning
+
+use crate::error::Result;
+use postgres::Client;
+use serde::Serialize;
+
+/// The median and 95th percentile of a statistic, missing if no build recorded it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub median: Option<f64>,
+    pub p95: Option<f64>,
+}
+
+/// Statistics about the builds of a period, see [`summarize_builds`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildStatsSummary {
+    pub builds: i64,
+    pub duration_ms: Percentiles,
+    pub peak_memory_bytes: Percentiles,
+    pub files_count: Percentiles,
+    pub files_size_bytes: Percentiles,
+}
+
+/// Summarizes the builds of the last `days` days. Builds from before the statistics were
+/// recorded are ignored.
+pub fn summarize_builds(conn: &mut Client, days: i32) -> Result<BuildStatsSummary> {
+    let row = conn.query_one(
+        "SELECT
+             COUNT(*),
+             percentile_cont(0.5) WITHIN GROUP (ORDER BY duration_ms),
+             percentile_cont(0.95) WITHIN GROUP (ORDER BY duration_ms),
+             percentile_cont(0.5) WITHIN GROUP (ORDER BY peak_memory_bytes),
+             percentile_cont(0.95) WITHIN GROUP (ORDER BY peak_memory_bytes),
+             percentile_cont(0.5) WITHIN GROUP (ORDER BY files_count),
+             percentile_cont(0.95) WITHIN GROUP (ORDER BY files_count),
+             percentile_cont(0.5) WITHIN GROUP (ORDER BY files_size_bytes),
+             percentile_cont(0.95) WITHIN GROUP (ORDER BY files_size_bytes)
+         FROM builds
+         WHERE duration_ms IS NOT NULL
+             AND build_time > NOW() - make_interval(days => $1)",
+        &[&days],
+    )?;
+    let percentiles = |idx| Percentiles {
+        median: row.get(idx),
+        p95: row.get(idx + 1),
+    };
+
+    Ok(BuildStatsSummary {
+        builds: row.get(0),
+        duration_ms: percentiles(1),
+        peak_memory_bytes: percentiles(3),
+        files_count: percentiles(5),
+        files_size_bytes: percentiles(7),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docbuilder::BuildStats;
+    use crate::test::wrapper;
+    use std::time::Duration;
+
+    #[test]
+    fn test_build_stats() {
+        wrapper(|env| {
+            let release_id = env
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .build_stats(BuildStats {
+                    duration: Duration::from_secs(42),
+                    peak_memory: None,
+                    files: 12,
+                    size: 3456,
+                })
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let row = conn.query_one(
+                "SELECT duration_ms, peak_memory_bytes, files_count, files_size_bytes
+                 FROM builds WHERE rid = $1",
+                &[&release_id],
+            )?;
+            assert_eq!(row.get::<_, Option<i64>>(0), Some(42_000));
+            assert_eq!(row.get::<_, Option<i64>>(1), None);
+            assert_eq!(row.get::<_, Option<i64>>(2), Some(12));
+            assert_eq!(row.get::<_, Option<i64>>(3), Some(3456));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_summarize_builds() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert_eq!(
+                summarize_builds(&mut conn, 7)?,
+                BuildStatsSummary::default()
+            );
+
+            for seconds in 1..=20 {
+                env.fake_release()
+                    .name("dummy")
+                    .version(&format!("0.1.{}", seconds))
+                    .build_result_successful(seconds % 2 == 0)
+                    .build_stats(BuildStats {
+                        duration: Duration::from_secs(seconds),
+                        peak_memory: None,
+                        files: 10,
+                        size: seconds * 1000,
+                    })
+                    .create()?;
+            }
+            // Builds older than the summarized period are ignored
+            conn.execute(
+                "UPDATE builds SET build_time = NOW() - INTERVAL '30 days'
+                 WHERE rid = (SELECT id FROM releases WHERE version = '0.1.20')",
+                &[],
+            )?;
+
+            let summary = summarize_builds(&mut conn, 7)?;
+            assert_eq!(summary.builds, 19);
+            assert_eq!(summary.duration_ms.median, Some(10_000.0));
+            assert_eq!(summary.duration_ms.p95.map(f64::round), Some(18_100.0));
+            assert_eq!(summary.peak_memory_bytes, Percentiles::default());
+            assert_eq!(summary.files_count.median, Some(10.0));
+            assert_eq!(summary.files_size_bytes.median, Some(10_000.0));
+
+            Ok(())
+        });
+    }
+}
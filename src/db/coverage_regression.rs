@@ -0,0 +1,259 @@
+//! Detection of releases documenting much less of their crate than the previous version
+
+use crate::error::Result;
+use postgres::Client;
+use semver::Version;
+use serde::Serialize;
+
+/// How many percentage points the documentation coverage has to drop to be a regression.
+const REGRESSION_THRESHOLD: f32 = 10.0;
+
+/// A release whose documentation coverage dropped compared to the previous version of its crate.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct CoverageRegression {
+    pub(crate) previous_version: String,
+    pub(crate) previous_percent: f32,
+    pub(crate) new_version: String,
+    pub(crate) new_percent: f32,
+}
+
+impl CoverageRegression {
+    /// The number of percentage points the coverage dropped by.
+    pub(crate) fn percent_drop(&self) -> f32 {
+        self.previous_percent - self.new_percent
+    }
+}
+
+/// Compares the documentation coverage of `new_version` with the one of the closest lower version
+/// of the crate that has coverage data, and returns the regression if it dropped by more than
+/// `REGRESSION_THRESHOLD` percentage points.
+pub(crate) fn detect_coverage_regression(
+    conn: &mut Client,
+    crate_id: i32,
+    new_version: &str,
+) -> Result<Option<CoverageRegression>> {
+    let rows = conn.query(
+        "SELECT releases.version,
+                (doc_coverage.documented_items * 100.0 / doc_coverage.total_items)::REAL
+         FROM releases
+         INNER JOIN doc_coverage ON doc_coverage.release_id = releases.id
+         WHERE releases.crate_id = $1 AND doc_coverage.total_items > 0",
+        &[&crate_id],
+    )?;
+
+    let mut new_percent = None;
+    let mut previous: Option<(Version, String, f32)> = None;
+    let new_semver = Version::parse(new_version)?;
+    for row in rows {
+        let version: String = row.get(0);
+        let percent: f32 = row.get(1);
+        if version == new_version {
+            new_percent = Some(percent);
+            continue;
+        }
+
+        // Versions that aren't valid semver can't be ordered, so they're never compared
+        let semver = match Version::parse(&version) {
+            Ok(semver) => semver,
+            Err(_) => continue,
+        };
+        if semver < new_semver
+            && previous
+                .as_ref()
+                .map_or(true, |(prev, _, _)| semver > *prev)
+        {
+            previous = Some((semver, version, percent));
+        }
+    }
+
+    Ok(match (previous, new_percent) {
+        (Some((_, previous_version, previous_percent)), Some(new_percent))
+            if previous_percent - new_percent > REGRESSION_THRESHOLD =>
+        {
+            Some(CoverageRegression {
+                previous_version,
+                previous_percent,
+                new_version: new_version.into(),
+                new_percent,
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Checks whether the release with `release_id` regressed its documentation coverage, replacing
+/// the regression previously recorded for it.
+pub(crate) fn record_coverage_regression(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<Option<CoverageRegression>> {
+    let row = conn.query_one(
+        "SELECT crate_id, version FROM releases WHERE id = $1",
+        &[&release_id],
+    )?;
+    let version: String = row.get(1);
+    let regression = detect_coverage_regression(conn, row.get(0), &version)?;
+
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM coverage_regressions WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    if let Some(regression) = &regression {
+        transaction.execute(
+            "INSERT INTO coverage_regressions
+                 (release_id, previous_version, previous_percent, new_percent, detected_at)
+             VALUES ($1, $2, $3, $4, NOW())",
+            &[
+                &release_id,
+                &regression.previous_version,
+                &regression.previous_percent,
+                &regression.new_percent,
+            ],
+        )?;
+    }
+    transaction.commit()?;
+
+    Ok(regression)
+}
+
+/// Returns the documentation coverage regression recorded for the release with `release_id`.
+pub(crate) fn release_coverage_regression(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<Option<CoverageRegression>> {
+    Ok(conn
+        .query_opt(
+            "SELECT coverage_regressions.previous_version,
+                    coverage_regressions.previous_percent,
+                    releases.version,
+                    coverage_regressions.new_percent
+             FROM coverage_regressions
+             INNER JOIN releases ON releases.id = coverage_regressions.release_id
+             WHERE coverage_regressions.release_id = $1",
+            &[&release_id],
+        )?
+        .map(|row| CoverageRegression {
+            previous_version: row.get(0),
+            previous_percent: row.get(1),
+            new_version: row.get(2),
+            new_percent: row.get(3),
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn crate_id(conn: &mut Client, name: &str) -> Result<i32> {
+        Ok(conn
+            .query_one("SELECT id FROM crates WHERE name = $1", &[&name])?
+            .get(0))
+    }
+
+    #[test]
+    fn detect_drops_above_threshold() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .coverage(90, 100, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .coverage(85, 100, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.3.0")
+                .coverage(30, 60, 0, 0)
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let id = crate_id(&mut conn, "dummy")?;
+
+            // A drop of 5 points is tolerated
+            assert_eq!(detect_coverage_regression(&mut conn, id, "0.2.0")?, None);
+            assert_eq!(detect_coverage_regression(&mut conn, id, "0.1.0")?, None);
+
+            let regression = detect_coverage_regression(&mut conn, id, "0.3.0")?.unwrap();
+            assert_eq!(regression.previous_version, "0.2.0");
+            assert!((regression.previous_percent - 85.0).abs() < 0.01);
+            assert_eq!(regression.new_version, "0.3.0");
+            assert!((regression.new_percent - 50.0).abs() < 0.01);
+            assert!((regression.percent_drop() - 35.0).abs() < 0.01);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn detect_compares_with_the_closest_lower_version() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.9.0")
+                .coverage(100, 100, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("1.0.0")
+                .coverage(50, 100, 0, 0)
+                .create()?;
+            // Published after 1.0.0, but still the previous version of 1.0.0
+            env.fake_release()
+                .name("dummy")
+                .version("0.10.0")
+                .coverage(55, 100, 0, 0)
+                .create()?;
+            // Versions without coverage data are skipped
+            env.fake_release().name("dummy").version("0.9.5").create()?;
+
+            let mut conn = env.db().conn();
+            let id = crate_id(&mut conn, "dummy")?;
+            assert_eq!(detect_coverage_regression(&mut conn, id, "1.0.0")?, None);
+
+            let regression = detect_coverage_regression(&mut conn, id, "0.10.0")?.unwrap();
+            assert_eq!(regression.previous_version, "0.9.0");
+
+            // Releases without coverage data can't regress
+            assert_eq!(detect_coverage_regression(&mut conn, id, "0.9.5")?, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn regressions_are_recorded_and_replaced() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .coverage(10, 10, 0, 0)
+                .create()?;
+            let release_id = env
+                .fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .coverage(1, 10, 0, 0)
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let regression = release_coverage_regression(&mut conn, release_id)?.unwrap();
+            assert_eq!(regression.previous_version, "0.1.0");
+            assert_eq!(regression.new_version, "0.2.0");
+
+            // A rebuild documenting the crate again clears the regression
+            conn.execute(
+                "UPDATE doc_coverage SET documented_items = 10 WHERE release_id = $1",
+                &[&release_id],
+            )?;
+            assert_eq!(record_coverage_regression(&mut conn, release_id)?, None);
+            assert_eq!(release_coverage_regression(&mut conn, release_id)?, None);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,299 @@
+//! Daily snapshots of the metrics of the crates, to chart how they evolve
+
+use crate::docbuilder::BuildResult;
+use crate::error::Result;
+use chrono::NaiveDate;
+use postgres::Client;
+use serde::Serialize;
+
+/// How the snapshots are grouped into a time series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Granularity {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Granularity {
+    /// The field passed to `date_trunc` to group the snapshots.
+    fn date_field(self) -> &'static str {
+        match self {
+            Granularity::Daily => "day",
+            Granularity::Weekly => "week",
+            Granularity::Monthly => "month",
+        }
+    }
+}
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid granularity, expected daily, weekly or monthly")]
+pub(crate) struct InvalidGranularityError;
+
+impl std::str::FromStr for Granularity {
+    type Err = InvalidGranularityError;
+
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        match input {
+            "daily" => Ok(Granularity::Daily),
+            "weekly" => Ok(Granularity::Weekly),
+            "monthly" => Ok(Granularity::Monthly),
+            _ => Err(InvalidGranularityError),
+        }
+    }
+}
+
+/// The metrics of a crate over time, with one value per period in every array.
+///
+/// The stars, forks, open issues and documentation coverage of a period are the latest ones
+/// recorded during it, while the build time is the average of the builds of the period. Values
+/// that weren't recorded during a period are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct CrateMetrics {
+    pub(crate) granularity: Granularity,
+    /// The first day of every period
+    pub(crate) dates: Vec<NaiveDate>,
+    pub(crate) stars: Vec<Option<i32>>,
+    pub(crate) forks: Vec<Option<i32>>,
+    pub(crate) open_issues: Vec<Option<i32>>,
+    pub(crate) doc_coverage_percent: Vec<Option<f32>>,
+    pub(crate) build_time_seconds: Vec<Option<f32>>,
+}
+
+/// Records today's stars, forks and open issues of the crates hosted in a GitHub repository.
+pub(crate) fn record_repository_metrics(
+    conn: &mut Client,
+    repository_id: &str,
+    stars: i32,
+    forks: i32,
+    open_issues: i32,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO crate_metrics_snapshots (crate_id, date, stars, forks, open_issues)
+         SELECT DISTINCT releases.crate_id, CURRENT_DATE, $2::INT, $3::INT, $4::INT
+         FROM releases
+         WHERE releases.github_repo = $1
+         ON CONFLICT (crate_id, date) DO UPDATE
+             SET stars = EXCLUDED.stars,
+                 forks = EXCLUDED.forks,
+                 open_issues = EXCLUDED.open_issues",
+        &[&repository_id, &stars, &forks, &open_issues],
+    )?;
+
+    Ok(())
+}
+
+/// Records the documentation coverage and the duration of a build as today's metrics of its
+/// crate. Durations that weren't measured aren't recorded.
+pub(crate) fn record_build_metrics(
+    conn: &mut Client,
+    release_id: i32,
+    res: &BuildResult,
+) -> Result<()> {
+    let doc_coverage_percent = res
+        .doc_coverage
+        .filter(|coverage| coverage.total_items > 0)
+        .map(|coverage| coverage.documented_items as f32 * 100.0 / coverage.total_items as f32);
+    let build_time_seconds = Some(res.stats.duration.as_secs_f32()).filter(|secs| *secs > 0.0);
+    if doc_coverage_percent.is_none() && build_time_seconds.is_none() {
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT INTO crate_metrics_snapshots
+             (crate_id, date, doc_coverage_percent, build_time_seconds)
+         SELECT releases.crate_id, CURRENT_DATE, $2, $3
+         FROM releases
+         WHERE releases.id = $1
+         ON CONFLICT (crate_id, date) DO UPDATE
+             SET doc_coverage_percent =
+                     COALESCE(EXCLUDED.doc_coverage_percent,
+                              crate_metrics_snapshots.doc_coverage_percent),
+                 build_time_seconds =
+                     COALESCE(EXCLUDED.build_time_seconds,
+                              crate_metrics_snapshots.build_time_seconds)",
+        &[&release_id, &doc_coverage_percent, &build_time_seconds],
+    )?;
+
+    Ok(())
+}
+
+/// Returns the metrics of the crate between `from` and `to` included, grouped by `granularity`.
+/// Periods without any snapshot are skipped.
+pub(crate) fn crate_metrics(
+    conn: &mut Client,
+    name: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    granularity: Granularity,
+) -> Result<CrateMetrics> {
+    let rows = conn.query(
+        "SELECT
+             date_trunc($2, snapshots.date::TIMESTAMP)::DATE AS period,
+             (array_agg(snapshots.stars ORDER BY snapshots.date DESC)
+                 FILTER (WHERE snapshots.stars IS NOT NULL))[1],
+             (array_agg(snapshots.forks ORDER BY snapshots.date DESC)
+                 FILTER (WHERE snapshots.forks IS NOT NULL))[1],
+             (array_agg(snapshots.open_issues ORDER BY snapshots.date DESC)
+                 FILTER (WHERE snapshots.open_issues IS NOT NULL))[1],
+             (array_agg(snapshots.doc_coverage_percent ORDER BY snapshots.date DESC)
+                 FILTER (WHERE snapshots.doc_coverage_percent IS NOT NULL))[1],
+             AVG(snapshots.build_time_seconds)::REAL
+         FROM crate_metrics_snapshots AS snapshots
+         INNER JOIN crates ON crates.id = snapshots.crate_id
+         WHERE crates.name = $1 AND snapshots.date BETWEEN $3 AND $4
+         GROUP BY period
+         ORDER BY period",
+        &[&name, &granularity.date_field(), &from, &to],
+    )?;
+
+    let mut metrics = CrateMetrics {
+        granularity,
+        dates: Vec::with_capacity(rows.len()),
+        stars: Vec::with_capacity(rows.len()),
+        forks: Vec::with_capacity(rows.len()),
+        open_issues: Vec::with_capacity(rows.len()),
+        doc_coverage_percent: Vec::with_capacity(rows.len()),
+        build_time_seconds: Vec::with_capacity(rows.len()),
+    };
+    for row in rows {
+        metrics.dates.push(row.get(0));
+        metrics.stars.push(row.get(1));
+        metrics.forks.push(row.get(2));
+        metrics.open_issues.push(row.get(3));
+        metrics.doc_coverage_percent.push(row.get(4));
+        metrics.build_time_seconds.push(row.get(5));
+    }
+
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docbuilder::{BuildStats, DocCoverage};
+    use crate::test::wrapper;
+    use std::time::Duration;
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    fn insert_snapshot(
+        conn: &mut Client,
+        name: &str,
+        day: &str,
+        stars: Option<i32>,
+        build_time_seconds: Option<f32>,
+    ) -> Result<()> {
+        conn.execute(
+            "INSERT INTO crate_metrics_snapshots (crate_id, date, stars, build_time_seconds)
+             SELECT id, $2, $3, $4 FROM crates WHERE name = $1",
+            &[&name, &date(day), &stars, &build_time_seconds],
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregation() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").create()?;
+            env.fake_release().name("other").create()?;
+            let mut conn = env.db().conn();
+            // 2024-01-01 and 2024-01-08 are Mondays
+            insert_snapshot(&mut conn, "dummy", "2024-01-01", Some(10), Some(20.0))?;
+            insert_snapshot(&mut conn, "dummy", "2024-01-03", Some(12), None)?;
+            insert_snapshot(&mut conn, "dummy", "2024-01-07", None, Some(40.0))?;
+            insert_snapshot(&mut conn, "dummy", "2024-01-09", Some(15), None)?;
+            insert_snapshot(&mut conn, "dummy", "2024-02-01", Some(20), Some(10.0))?;
+            insert_snapshot(&mut conn, "dummy", "2025-01-01", Some(30), None)?;
+            insert_snapshot(&mut conn, "other", "2024-01-02", Some(99), None)?;
+
+            let (from, to) = (date("2024-01-01"), date("2024-12-31"));
+            let weekly = crate_metrics(&mut conn, "dummy", from, to, Granularity::Weekly)?;
+            assert_eq!(
+                weekly.dates,
+                vec![date("2024-01-01"), date("2024-01-08"), date("2024-01-29")]
+            );
+            // The latest stars of the week are used, even if they weren't recorded on its last day
+            assert_eq!(weekly.stars, vec![Some(12), Some(15), Some(20)]);
+            assert_eq!(
+                weekly.build_time_seconds,
+                vec![Some(30.0), None, Some(10.0)]
+            );
+            assert_eq!(weekly.forks, vec![None, None, None]);
+
+            let monthly = crate_metrics(&mut conn, "dummy", from, to, Granularity::Monthly)?;
+            assert_eq!(monthly.dates, vec![date("2024-01-01"), date("2024-02-01")]);
+            assert_eq!(monthly.stars, vec![Some(15), Some(20)]);
+            assert_eq!(monthly.build_time_seconds, vec![Some(30.0), Some(10.0)]);
+
+            let daily = crate_metrics(
+                &mut conn,
+                "dummy",
+                date("2024-01-02"),
+                date("2024-01-07"),
+                Granularity::Daily,
+            )?;
+            assert_eq!(daily.dates, vec![date("2024-01-03"), date("2024-01-07")]);
+            assert_eq!(daily.stars, vec![Some(12), None]);
+
+            let missing = crate_metrics(&mut conn, "missing", from, to, Granularity::Daily)?;
+            assert!(missing.dates.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_record_metrics() {
+        wrapper(|env| {
+            let release_id = env
+                .fake_release()
+                .name("dummy")
+                .github_stats("rust-lang/dummy", 10, 2, 3)
+                .create()?;
+            let mut conn = env.db().conn();
+            let repository_id: String = conn
+                .query_one(
+                    "SELECT github_repo FROM releases WHERE id = $1",
+                    &[&release_id],
+                )?
+                .get(0);
+            record_repository_metrics(&mut conn, &repository_id, 11, 3, 4)?;
+
+            let mut res = crate::docbuilder::BuildResult {
+                rustc_version: String::new(),
+                docsrs_version: String::new(),
+                build_log: String::new(),
+                successful: true,
+                doc_coverage: Some(DocCoverage {
+                    total_items: 8,
+                    documented_items: 6,
+                    total_items_needing_examples: 0,
+                    items_with_examples: 0,
+                }),
+                stats: BuildStats {
+                    duration: Duration::from_secs(42),
+                    ..BuildStats::default()
+                },
+            };
+            record_build_metrics(&mut conn, release_id, &res)?;
+            // Builds without coverage nor duration don't overwrite the recorded metrics
+            res.doc_coverage = None;
+            res.stats.duration = Duration::from_secs(0);
+            record_build_metrics(&mut conn, release_id, &res)?;
+
+            let today = conn.query_one("SELECT CURRENT_DATE", &[])?.get(0);
+            let metrics = crate_metrics(&mut conn, "dummy", today, today, Granularity::Daily)?;
+            assert_eq!(metrics.dates, vec![today]);
+            assert_eq!(metrics.stars, vec![Some(11)]);
+            assert_eq!(metrics.forks, vec![Some(3)]);
+            assert_eq!(metrics.open_issues, vec![Some(4)]);
+            assert_eq!(metrics.doc_coverage_percent, vec![Some(75.0)]);
+            assert_eq!(metrics.build_time_seconds, vec![Some(42.0)]);
+
+            Ok(())
+        });
+    }
+}
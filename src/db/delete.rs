@@ -1,10 +1,12 @@
+use super::update_crate_dependents;
+use crate::storage::delete_files;
 use crate::Storage;
 use failure::{Error, Fail};
 use postgres::Client;
 
 /// List of directories in docs.rs's underlying storage (either the database or S3) containing a
 /// subdirectory named after the crate. Those subdirectories will be deleted.
-static STORAGE_PATHS_TO_DELETE: &[&str] = &["rustdoc", "sources"];
+static STORAGE_PATHS_TO_DELETE: &[&str] = &["rustdoc", "rustdoc-json", "sources", "archives"];
 
 #[derive(Debug, Fail)]
 enum CrateDeletionError {
@@ -30,7 +32,12 @@ pub fn delete_version(
     version: &str,
 ) -> Result<(), Error> {
     delete_version_from_database(conn, name, version)?;
+    delete_version_files(storage, name, version)
+}
 
+/// Deletes the files of a version from the storage, leaving the database untouched, like the
+/// files stored by a build that didn't finish.
+pub fn delete_version_files(storage: &Storage, name: &str, version: &str) -> Result<(), Error> {
     for prefix in STORAGE_PATHS_TO_DELETE {
         storage.delete_prefix(&format!("{}/{}/{}/", prefix, name, version))?;
     }
@@ -55,6 +62,14 @@ const METADATA: &[(&str, &str)] = &[
     ("builds", "rid"),
     ("compression_rels", "release"),
     ("doc_coverage", "release_id"),
+    ("examples", "release_id"),
+    ("macro_expansions", "release_id"),
+    ("function_signatures", "release_id"),
+    ("audit_results", "release_id"),
+    ("audit_runs", "release_id"),
+    ("coverage_regressions", "release_id"),
+    ("inline_tests", "release_id"),
+    ("doc_warnings", "release_id"),
 ];
 
 fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) -> Result<(), Error> {
@@ -70,6 +85,20 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
         "DELETE FROM releases WHERE crate_id = $1 AND version = $2",
         &[&crate_id, &version],
     )?;
+    // The files are deleted from the storage, so they have to be uploaded again on rebuilds
+    transaction.execute(
+        "DELETE FROM build_cache WHERE crate_name = $1 AND version = $2",
+        &[&name, &version],
+    )?;
+    transaction.execute(
+        "DELETE FROM crate_authors WHERE crate_id = $1 AND author_id NOT IN (
+            SELECT author_rels.aid
+            FROM author_rels
+            INNER JOIN releases ON releases.id = author_rels.rid
+            WHERE releases.crate_id = $1
+        )",
+        &[&crate_id],
+    )?;
     transaction.execute(
         "UPDATE crates SET latest_version_id = (
             SELECT id FROM releases WHERE release_time = (
@@ -78,11 +107,12 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
         ) WHERE id = $1",
         &[&crate_id],
     )?;
+    update_crate_dependents(&mut transaction, crate_id)?;
 
     for prefix in STORAGE_PATHS_TO_DELETE {
-        transaction.execute(
-            "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{}/{}/{}/%", prefix, name, version)],
+        delete_files(
+            &mut transaction,
+            &format!("{}/{}/{}/", prefix, name, version),
         )?;
     }
 
@@ -96,6 +126,7 @@ fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> R
         "DELETE FROM sandbox_overrides WHERE crate_name = $1",
         &[&name],
     )?;
+    transaction.execute("DELETE FROM build_cache WHERE crate_name = $1", &[&name])?;
     for &(table, column) in METADATA {
         transaction.execute(
             format!(
@@ -107,6 +138,18 @@ fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> R
         )?;
     }
     transaction.execute("DELETE FROM owner_rels WHERE cid = $1;", &[&crate_id])?;
+    transaction.execute(
+        "DELETE FROM notifications WHERE crate_id = $1;",
+        &[&crate_id],
+    )?;
+    transaction.execute(
+        "DELETE FROM crate_metrics_snapshots WHERE crate_id = $1;",
+        &[&crate_id],
+    )?;
+    transaction.execute(
+        "DELETE FROM crate_authors WHERE crate_id = $1;",
+        &[&crate_id],
+    )?;
     transaction.execute("DELETE FROM releases WHERE crate_id = $1;", &[&crate_id])?;
     transaction.execute("DELETE FROM crates WHERE id = $1;", &[&crate_id])?;
 
@@ -227,11 +270,88 @@ mod tests {
                 authors(&mut db.conn(), crate_id)?,
                 vec!["Peter Rabbit".to_string()]
             );
+            let crate_authors: Vec<String> = db
+                .conn()
+                .query(
+                    "SELECT authors.name FROM authors
+                    INNER JOIN crate_authors ON authors.id = crate_authors.author_id
+                    WHERE crate_authors.crate_id = $1",
+                    &[&crate_id],
+                )?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(crate_authors, vec!["Peter Rabbit".to_string()]);
 
             let web = env.frontend();
             assert_success("/a/2.0.0/a/", web)?;
             assert_eq!(web.get("/a/1.0.0/a/").send()?.status(), 404);
 
+            Ok(())
+        })
+    }
+    #[test]
+    fn test_delete_version_keeps_shared_files() {
+        wrapper(|env| {
+            fn refcount(conn: &mut Client, path: &str) -> Result<Option<i32>, Error> {
+                Ok(conn
+                    .query_opt(
+                        "SELECT blobs.refcount
+                         FROM files
+                         INNER JOIN blobs ON blobs.hash = files.content_hash
+                         WHERE files.path = $1",
+                        &[&path],
+                    )?
+                    .map(|row| row.get(0)))
+            }
+
+            for version in &["1.0.0", "2.0.0"] {
+                env.fake_release()
+                    .name("a")
+                    .version(version)
+                    .rustdoc_file_with("a/shared.html", b"shared")
+                    .rustdoc_file_with("a/changed.html", version.as_bytes())
+                    .create()?;
+            }
+            let mut conn = env.db().conn();
+            assert_eq!(
+                refcount(&mut conn, "rustdoc/a/2.0.0/a/shared.html")?,
+                Some(2)
+            );
+            assert_eq!(
+                refcount(&mut conn, "rustdoc/a/2.0.0/a/changed.html")?,
+                Some(1)
+            );
+
+            delete_version(&mut conn, &*env.storage(), "a", "1.0.0")?;
+            assert_eq!(
+                refcount(&mut conn, "rustdoc/a/2.0.0/a/shared.html")?,
+                Some(1)
+            );
+            assert_eq!(
+                refcount(&mut conn, "rustdoc/a/2.0.0/a/changed.html")?,
+                Some(1)
+            );
+            let changed_in_1_0_0: i64 = conn
+                .query_one(
+                    "SELECT COUNT(*) FROM blobs WHERE content = $1",
+                    &[&crate::storage::compress(
+                        &b"1.0.0"[..],
+                        crate::storage::CompressionAlgorithm::default(),
+                    )?],
+                )?
+                .get(0);
+            assert_eq!(changed_in_1_0_0, 0);
+            // The web server needs all the connections of the pool
+            drop(conn);
+            assert_success("/a/2.0.0/a/shared.html", env.frontend())?;
+
+            let mut conn = env.db().conn();
+            delete_version(&mut conn, &*env.storage(), "a", "2.0.0")?;
+            let blobs: i64 = conn.query_one("SELECT COUNT(*) FROM blobs", &[])?.get(0);
+            let files: i64 = conn.query_one("SELECT COUNT(*) FROM files", &[])?.get(0);
+            assert_eq!((blobs, files), (0, 0));
+
             Ok(())
         })
     }
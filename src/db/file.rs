@@ -8,9 +8,11 @@
 //! However, postgres is still available for testing and backwards compatibility.
 
 use crate::error::Result;
-use crate::storage::{CompressionAlgorithms, Storage};
+use crate::storage::{hash_files, CompressionAlgorithms, Storage};
 
+use postgres::Client;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 /// Store all files in a directory and return [[mimetype, filename]] as Json
@@ -34,6 +36,134 @@ pub fn add_path_into_database<P: AsRef<Path>>(
     ))
 }
 
+/// How many files [`add_changed_path_into_database`] skipped and uploaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// Like [`add_path_into_database`], but only uploads the files that changed since the previous
+/// build of the release.
+///
+/// The SHA-256 hash of every uploaded file is stored in the `build_cache` table, and rebuilds
+/// of the release skip the files whose hash didn't change. This mostly happens for the sources,
+/// and for the documentation of local crates rebuilt during development.
+///
+/// The paths the previous build of the release stored under `prefix` that aren't in `path`
+/// anymore are returned as well, to be deleted with [`delete_stale_paths`] once the new build is
+/// committed.
+pub fn add_changed_path_into_database<P: AsRef<Path>>(
+    conn: &mut Client,
+    storage: &Storage,
+    name: &str,
+    version: &str,
+    prefix: &str,
+    path: P,
+) -> Result<(Value, CompressionAlgorithms, CacheStats, Vec<String>)> {
+    let previous_hashes: HashMap<String, String> = conn
+        .query(
+            "SELECT path, sha256 FROM build_cache WHERE crate_name = $1 AND version = $2",
+            &[&name, &version],
+        )?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    let stored = storage.store_changed(prefix, path.as_ref(), &previous_hashes)?;
+
+    let (paths, hashes): (Vec<&str>, Vec<&str>) = stored
+        .hashes
+        .iter()
+        .filter(|(path, hash)| previous_hashes.get(*path) != Some(hash))
+        .map(|(path, hash)| (path.as_str(), hash.as_str()))
+        .unzip();
+    conn.execute(
+        "INSERT INTO build_cache (crate_name, version, path, sha256)
+         SELECT $1, $2, path, sha256 FROM UNNEST($3::TEXT[], $4::TEXT[]) AS files (path, sha256)
+         ON CONFLICT (crate_name, version, path) DO UPDATE SET sha256 = EXCLUDED.sha256",
+        &[&name, &version, &paths, &hashes],
+    )?;
+
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let mut stale_paths: Vec<String> = previous_hashes
+        .into_iter()
+        .map(|(path, _)| path)
+        .filter(|path| path.starts_with(&prefix) && !stored.hashes.contains_key(path))
+        .collect();
+    stale_paths.sort();
+
+    let stats = CacheStats {
+        hits: stored.unchanged,
+        misses: stored.hashes.len() - stored.unchanged,
+    };
+    Ok((
+        file_list_to_json(stored.mimes.into_iter().collect())?,
+        stored.algorithms,
+        stats,
+        stale_paths,
+    ))
+}
+
+/// Checks whether the last build of the release succeeded, and whether the files in `path` are
+/// the ones it stored under `prefix` with [`add_changed_path_into_database`].
+///
+/// Incremental builds skip the releases whose sources didn't change since they were last built.
+pub(crate) fn is_unchanged_since_last_build<P: AsRef<Path>>(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+    prefix: &str,
+    path: P,
+) -> Result<bool> {
+    let successful = conn
+        .query_opt(
+            "SELECT releases.build_status
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )?
+        .map(|row| row.get(0));
+    if successful != Some(true) {
+        return Ok(false);
+    }
+
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let previous_hashes: HashMap<String, String> = conn
+        .query(
+            "SELECT path, sha256 FROM build_cache WHERE crate_name = $1 AND version = $2",
+            &[&name, &version],
+        )?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .filter(|(path, _): &(String, String)| path.starts_with(&prefix))
+        .collect();
+
+    Ok(!previous_hashes.is_empty() && hash_files(&prefix, path.as_ref())? == previous_hashes)
+}
+
+/// Deletes the files a previous build of the release stored that the latest build didn't, as
+/// returned by [`add_changed_path_into_database`], like the pages of removed modules or the
+/// documentation of targets that aren't built anymore.
+///
+/// The files identical to the files of other releases are only stored once, so removing them
+/// from this release doesn't affect the others.
+pub(crate) fn delete_stale_paths(
+    conn: &mut Client,
+    storage: &Storage,
+    name: &str,
+    version: &str,
+    paths: &[String],
+) -> Result<()> {
+    storage.delete_paths(paths)?;
+    conn.execute(
+        "DELETE FROM build_cache WHERE crate_name = $1 AND version = $2 AND path = ANY($3)",
+        &[&name, &version, &paths],
+    )?;
+    Ok(())
+}
+
 fn file_list_to_json(file_list: Vec<(PathBuf, String)>) -> Result<Value> {
     let file_list: Vec<_> = file_list
         .into_iter()
@@ -47,3 +177,155 @@ fn file_list_to_json(file_list: Vec<(PathBuf, String)>) -> Result<Value> {
 
     Ok(Value::Array(file_list))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use std::fs;
+
+    #[test]
+    fn test_only_changed_files_are_uploaded() {
+        wrapper(|env| {
+            let dir = tempfile::Builder::new()
+                .prefix("docs.rs-build-cache-test")
+                .tempdir()?;
+            fs::write(dir.path().join("index.html"), "<html>foo</html>")?;
+            fs::write(dir.path().join("main.js"), "foo();")?;
+
+            let mut conn = env.db().conn();
+            let storage = env.storage();
+            let upload = |conn: &mut Client, version| {
+                add_changed_path_into_database(
+                    conn,
+                    &storage,
+                    "foo",
+                    version,
+                    &format!("rustdoc/foo/{}", version),
+                    dir.path(),
+                )
+            };
+
+            let (files, algs, stats, _) = upload(&mut conn, "0.1.0")?;
+            assert_eq!(files.as_array().unwrap().len(), 2);
+            assert_eq!(algs.len(), 1);
+            assert_eq!(stats, CacheStats { hits: 0, misses: 2 });
+
+            fs::write(dir.path().join("index.html"), "<html>bar</html>")?;
+            let (files, _, stats, _) = upload(&mut conn, "0.1.0")?;
+            assert_eq!(files.as_array().unwrap().len(), 2);
+            assert_eq!(stats, CacheStats { hits: 1, misses: 1 });
+            let blob = storage.get("rustdoc/foo/0.1.0/index.html", usize::MAX)?;
+            assert_eq!(blob.content, b"<html>bar</html>");
+
+            // Other versions don't share the cache
+            let (_, _, stats, _) = upload(&mut conn, "0.2.0")?;
+            assert_eq!(stats, CacheStats { hits: 0, misses: 2 });
+
+            // Deleted versions have to be uploaded again
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            crate::db::delete_version(&mut conn, &storage, "foo", "0.1.0")?;
+            let (_, _, stats, _) = upload(&mut conn, "0.1.0")?;
+            assert_eq!(stats, CacheStats { hits: 0, misses: 2 });
+            assert!(storage.exists("rustdoc/foo/0.1.0/main.js")?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_stale_files_are_deleted_after_rebuilds() {
+        wrapper(|env| {
+            let dir = tempfile::Builder::new()
+                .prefix("docs.rs-stale-files-test")
+                .tempdir()?;
+            fs::create_dir_all(dir.path().join("foo/removed"))?;
+            fs::write(dir.path().join("foo/index.html"), "<html>foo</html>")?;
+            fs::write(
+                dir.path().join("foo/removed/index.html"),
+                "<html>removed</html>",
+            )?;
+
+            let mut conn = env.db().conn();
+            let storage = env.storage();
+            let upload = |conn: &mut Client, version| {
+                add_changed_path_into_database(
+                    conn,
+                    &storage,
+                    "foo",
+                    version,
+                    &format!("rustdoc/foo/{}", version),
+                    dir.path(),
+                )
+            };
+
+            let (_, _, _, stale) = upload(&mut conn, "0.1.0")?;
+            assert!(stale.is_empty());
+            // Another release with the same pages
+            upload(&mut conn, "0.2.0")?;
+
+            // Rebuild the release without the `removed` module
+            fs::remove_dir_all(dir.path().join("foo/removed"))?;
+            let (_, _, _, stale) = upload(&mut conn, "0.1.0")?;
+            assert_eq!(stale, vec!["rustdoc/foo/0.1.0/foo/removed/index.html"]);
+            // Nothing is deleted until the build is committed
+            assert!(storage.exists("rustdoc/foo/0.1.0/foo/removed/index.html")?);
+
+            delete_stale_paths(&mut conn, &storage, "foo", "0.1.0", &stale)?;
+            assert!(!storage.exists("rustdoc/foo/0.1.0/foo/removed/index.html")?);
+            assert!(storage.exists("rustdoc/foo/0.1.0/foo/index.html")?);
+            let shared = storage.get("rustdoc/foo/0.2.0/foo/removed/index.html", usize::MAX)?;
+            assert_eq!(shared.content, b"<html>removed</html>");
+
+            // The deleted files aren't stale anymore for the next rebuilds
+            let (_, _, _, stale) = upload(&mut conn, "0.1.0")?;
+            assert!(stale.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_unchanged_since_last_build() {
+        wrapper(|env| {
+            let dir = tempfile::Builder::new()
+                .prefix("docs.rs-unchanged-sources-test")
+                .tempdir()?;
+            fs::create_dir(dir.path().join("src"))?;
+            fs::write(dir.path().join("src/lib.rs"), "pub fn foo() {}")?;
+
+            let mut conn = env.db().conn();
+            let storage = env.storage();
+            let unchanged = |conn: &mut Client| {
+                is_unchanged_since_last_build(conn, "foo", "0.1.0", "sources/foo/0.1.0", dir.path())
+            };
+
+            // Never built
+            assert!(!unchanged(&mut conn)?);
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            add_changed_path_into_database(
+                &mut conn,
+                &storage,
+                "foo",
+                "0.1.0",
+                "sources/foo/0.1.0",
+                dir.path(),
+            )?;
+            assert!(unchanged(&mut conn)?);
+
+            fs::write(dir.path().join("src/main.rs"), "fn main() {}")?;
+            assert!(!unchanged(&mut conn)?);
+            fs::remove_file(dir.path().join("src/main.rs"))?;
+            fs::write(dir.path().join("src/lib.rs"), "pub fn bar() {}")?;
+            assert!(!unchanged(&mut conn)?);
+            fs::write(dir.path().join("src/lib.rs"), "pub fn foo() {}")?;
+            assert!(unchanged(&mut conn)?);
+
+            // Failed builds are retried
+            conn.execute("UPDATE releases SET build_status = FALSE", &[])?;
+            assert!(!unchanged(&mut conn)?);
+
+            Ok(())
+        });
+    }
+}
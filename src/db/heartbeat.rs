@@ -0,0 +1,93 @@
+//! When the periodic jobs of the daemon last started and last succeeded
+
+use crate::error::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use postgres::Client;
+use serde::Serialize;
+
+/// The last runs of a periodic job.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Heartbeat {
+    pub(crate) job: String,
+    pub(crate) last_start: Option<DateTime<Utc>>,
+    pub(crate) last_success: Option<DateTime<Utc>>,
+}
+
+/// Records that a run of `job` started.
+pub(crate) fn record_job_start(conn: &mut Client, job: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO heartbeats (job, last_start) VALUES ($1, NOW())
+         ON CONFLICT (job) DO UPDATE SET last_start = EXCLUDED.last_start",
+        &[&job],
+    )?;
+    Ok(())
+}
+
+/// Records that a run of `job` succeeded.
+pub(crate) fn record_job_success(conn: &mut Client, job: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO heartbeats (job, last_success) VALUES ($1, NOW())
+         ON CONFLICT (job) DO UPDATE SET last_success = EXCLUDED.last_success",
+        &[&job],
+    )?;
+    Ok(())
+}
+
+/// Returns the last runs of every job that ever ran, by name.
+pub(crate) fn heartbeats(conn: &mut Client) -> Result<Vec<Heartbeat>> {
+    Ok(conn
+        .query(
+            "SELECT job, last_start, last_success FROM heartbeats ORDER BY job",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| {
+            let time = |idx| {
+                row.get::<_, Option<NaiveDateTime>>(idx)
+                    .map(|time| DateTime::from_utc(time, Utc))
+            };
+            Heartbeat {
+                job: row.get(0),
+                last_start: time(1),
+                last_success: time(2),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn start_and_success() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert!(heartbeats(&mut conn)?.is_empty());
+
+            record_job_start(&mut conn, "updater")?;
+            let started = heartbeats(&mut conn)?;
+            assert_eq!(started.len(), 1);
+            assert_eq!(started[0].job, "updater");
+            assert!(started[0].last_start.is_some());
+            assert_eq!(started[0].last_success, None);
+
+            record_job_success(&mut conn, "updater")?;
+            record_job_start(&mut conn, "updater")?;
+            record_job_start(&mut conn, "other")?;
+            let beats = heartbeats(&mut conn)?;
+            assert_eq!(
+                beats
+                    .iter()
+                    .map(|beat| beat.job.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["other", "updater"]
+            );
+            assert!(beats[1].last_success.is_some());
+            assert!(beats[1].last_start >= started[0].last_start);
+
+            Ok(())
+        });
+    }
+}
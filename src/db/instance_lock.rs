@@ -0,0 +1,307 @@
+//! Advisory locks keeping two instances of the same role from running against one database
+//!
+//! The locks are held by a connection for as long as the instance runs, so they're released when
+//! the instance shuts down and, as Postgres releases the advisory locks of closed sessions, when
+//! it crashes. The holder of each lock records its host and process in `instance_locks`, which is
+//! only informational: the advisory lock is the source of truth.
+
+use super::{Pool, PoolClient};
+use crate::error::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::warn;
+use std::fmt;
+
+/// The start of the first keys of the advisory locks taken by docs.rs, so they don't clash with
+/// other locks.
+const LOCK_NAMESPACE: i32 = 0x646f_6300; // "doc\0"
+
+/// The roles only one instance should run at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceRole {
+    /// The daemon, building crates and running the periodic jobs
+    Builder,
+    /// The web server started on its own
+    Web,
+    /// A run of the GitHub updater, so overlapping runs don't update the same repositories
+    GithubUpdater,
+}
+
+impl InstanceRole {
+    fn key(self) -> i32 {
+        match self {
+            InstanceRole::Builder => 1,
+            InstanceRole::Web => 2,
+            InstanceRole::GithubUpdater => 3,
+        }
+    }
+
+    /// The first key of the advisory lock of the role.
+    fn lock_key(self) -> i32 {
+        LOCK_NAMESPACE + self.key()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            InstanceRole::Builder => "builder",
+            InstanceRole::Web => "web",
+            InstanceRole::GithubUpdater => "github-updater",
+        }
+    }
+}
+
+impl fmt::Display for InstanceRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The instance holding a lock, as it recorded itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub hostname: String,
+    pub pid: i32,
+    pub acquired_at: DateTime<Utc>,
+    pub heartbeat_at: DateTime<Utc>,
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (pid {}) since {}, last seen {}",
+            self.hostname, self.pid, self.acquired_at, self.heartbeat_at
+        )
+    }
+}
+
+#[derive(Debug, failure::Fail)]
+pub enum InstanceLockError {
+    #[fail(display = "another {} instance is already running: {}", _1, _0)]
+    Held(LockHolder, InstanceRole),
+    #[fail(
+        display = "another {} instance is already running, but it didn't record itself",
+        _0
+    )]
+    HeldByUnknown(InstanceRole),
+}
+
+/// The advisory lock of a role, released when dropped.
+pub struct InstanceLock {
+    conn: PoolClient,
+    role: InstanceRole,
+    hostname: String,
+    pid: i32,
+    locked: bool,
+}
+
+impl InstanceLock {
+    /// Takes the lock of `role`, failing with an `InstanceLockError` if another instance holds
+    /// it.
+    ///
+    /// The lock is keyed by the role and by the schema, which is always the same outside of the
+    /// tests, where every test has its own schema.
+    pub fn acquire(pool: &Pool, role: InstanceRole) -> Result<Self> {
+        let mut conn = pool.get()?;
+        let locked: bool = conn
+            .query_one(
+                "SELECT pg_try_advisory_lock($1, hashtext(current_schema()))",
+                &[&role.lock_key()],
+            )?
+            .get(0);
+        if !locked {
+            return Err(match lock_holder(&mut conn, role)? {
+                Some(holder) => InstanceLockError::Held(holder, role),
+                None => InstanceLockError::HeldByUnknown(role),
+            }
+            .into());
+        }
+
+        let mut lock = InstanceLock {
+            conn,
+            role,
+            hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".into()),
+            pid: std::process::id() as i32,
+            locked: true,
+        };
+        lock.conn.execute(
+            "INSERT INTO instance_locks (role, hostname, pid) VALUES ($1, $2, $3)
+             ON CONFLICT (role) DO UPDATE
+                 SET hostname = EXCLUDED.hostname,
+                     pid = EXCLUDED.pid,
+                     acquired_at = NOW(),
+                     heartbeat_at = NOW()",
+            &[&role.name(), &lock.hostname, &lock.pid],
+        )?;
+        Ok(lock)
+    }
+
+    /// Records that the instance holding the lock is still alive.
+    pub fn heartbeat(&mut self) -> Result<()> {
+        self.conn.execute(
+            "UPDATE instance_locks SET heartbeat_at = NOW() WHERE role = $1 AND pid = $2",
+            &[&self.role.name(), &self.pid],
+        )?;
+        Ok(())
+    }
+
+    /// Releases the lock, which is also done when it's dropped.
+    pub fn release(mut self) -> Result<()> {
+        self.unlock()
+    }
+
+    fn unlock(&mut self) -> Result<()> {
+        if !self.locked {
+            return Ok(());
+        }
+        self.locked = false;
+        self.conn.execute(
+            "DELETE FROM instance_locks WHERE role = $1 AND hostname = $2 AND pid = $3",
+            &[&self.role.name(), &self.hostname, &self.pid],
+        )?;
+        // The connection goes back to the pool rather than being closed, so the lock has to be
+        // released explicitly
+        self.conn.query_one(
+            "SELECT pg_advisory_unlock($1, hashtext(current_schema()))",
+            &[&self.role.lock_key()],
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.unlock() {
+            warn!("failed to release the {} instance lock: {}", self.role, err);
+        }
+    }
+}
+
+fn lock_holder(conn: &mut PoolClient, role: InstanceRole) -> Result<Option<LockHolder>> {
+    Ok(conn
+        .query_opt(
+            "SELECT hostname, pid, acquired_at, heartbeat_at FROM instance_locks WHERE role = $1",
+            &[&role.name()],
+        )?
+        .map(|row| {
+            let time = |idx| DateTime::from_utc(row.get::<_, NaiveDateTime>(idx), Utc);
+            LockHolder {
+                hostname: row.get(0),
+                pid: row.get(1),
+                acquired_at: time(2),
+                heartbeat_at: time(3),
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use postgres::{Client, NoTls};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn one_instance_per_role() {
+        wrapper(|env| {
+            // Every lock holds a connection
+            env.override_config(|config| config.max_pool_size = 4);
+            let pool = env.db().pool();
+
+            // Only one of two instances starting at once gets the lock
+            let attempts: Vec<_> = (0..2)
+                .map(|_| {
+                    let pool = pool.clone();
+                    thread::spawn(move || InstanceLock::acquire(&pool, InstanceRole::Builder))
+                })
+                .collect();
+            let mut results: Vec<_> = attempts
+                .into_iter()
+                .map(|attempt| attempt.join().unwrap())
+                .collect();
+            results.sort_by_key(|result| result.is_err());
+            let err = results
+                .pop()
+                .unwrap()
+                .err()
+                .expect("the lock was taken twice");
+            let builder = results.pop().unwrap()?;
+            // The holder might not have recorded itself yet when the other attempt failed
+            match err.downcast_ref::<InstanceLockError>() {
+                Some(InstanceLockError::Held(holder, InstanceRole::Builder)) => {
+                    assert_eq!(holder.pid, std::process::id() as i32);
+                }
+                Some(InstanceLockError::HeldByUnknown(InstanceRole::Builder)) => {}
+                other => panic!("unexpected error: {:?}", other),
+            }
+            match InstanceLock::acquire(&pool, InstanceRole::Builder)
+                .err()
+                .and_then(|err| err.downcast::<InstanceLockError>().ok())
+            {
+                Some(InstanceLockError::Held(holder, InstanceRole::Builder)) => {
+                    assert_eq!(holder.pid, std::process::id() as i32);
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+
+            // The roles are locked independently
+            let mut web = InstanceLock::acquire(&pool, InstanceRole::Web)?;
+            web.heartbeat()?;
+            assert!(InstanceLock::acquire(&pool, InstanceRole::Web).is_err());
+            let updater = InstanceLock::acquire(&pool, InstanceRole::GithubUpdater)?;
+            assert!(InstanceLock::acquire(&pool, InstanceRole::GithubUpdater).is_err());
+            drop(updater);
+
+            builder.release()?;
+            let builder = InstanceLock::acquire(&pool, InstanceRole::Builder)?;
+            // Dropping the lock releases it too
+            drop(builder);
+            drop(web);
+            InstanceLock::acquire(&pool, InstanceRole::Builder)?;
+            InstanceLock::acquire(&pool, InstanceRole::Web)?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn released_when_the_connection_closes() {
+        wrapper(|env| {
+            let pool = env.db().pool();
+            let schema: String = env
+                .db()
+                .conn()
+                .query_one("SELECT current_schema()", &[])?
+                .get(0);
+
+            // A crashed instance, which took the lock without recording itself
+            let mut crashed = Client::connect(&env.config().database_url, NoTls)?;
+            let locked: bool = crashed
+                .query_one(
+                    "SELECT pg_try_advisory_lock($1, hashtext($2))",
+                    &[&InstanceRole::Builder.lock_key(), &schema],
+                )?
+                .get(0);
+            assert!(locked);
+            let err = InstanceLock::acquire(&pool, InstanceRole::Builder)
+                .err()
+                .expect("the lock was taken twice");
+            assert!(matches!(
+                err.downcast_ref::<InstanceLockError>(),
+                Some(InstanceLockError::HeldByUnknown(InstanceRole::Builder))
+            ));
+
+            // The server releases the lock once it notices the connection closed
+            drop(crashed);
+            for attempt in 0.. {
+                match InstanceLock::acquire(&pool, InstanceRole::Builder) {
+                    Ok(_) => break,
+                    Err(_) if attempt < 50 => thread::sleep(Duration::from_millis(100)),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -556,6 +556,828 @@ pub fn migrate(version: Option<Version>, conn: &mut Client) -> CratesfyiResult<(
                     ADD COLUMN github_last_update TIMESTAMP;
             "
         ),
+        migration!(
+            context,
+            24,
+            // description
+            "Add a per-crate override for the maximum documentation size",
+            // upgrade query
+            "ALTER TABLE sandbox_overrides ADD COLUMN max_docs_size_bytes BIGINT;",
+            // downgrade query
+            "ALTER TABLE sandbox_overrides DROP COLUMN max_docs_size_bytes;"
+        ),
+        migration!(
+            context,
+            25,
+            // description
+            "Record the releases requeued after a toolchain update",
+            // upgrade query
+            "
+                CREATE TABLE toolchain_requeues (
+                    id SERIAL PRIMARY KEY,
+                    name VARCHAR NOT NULL,
+                    version VARCHAR NOT NULL,
+                    old_rustc_version VARCHAR NOT NULL,
+                    new_rustc_version VARCHAR NOT NULL,
+                    requeued_at TIMESTAMP NOT NULL DEFAULT NOW()
+                );
+                CREATE INDEX toolchain_requeues_release_idx ON toolchain_requeues (name, version);
+            ",
+            // downgrade query
+            "DROP TABLE toolchain_requeues;"
+        ),
+        migration!(
+            context,
+            26,
+            // description
+            "Store the examples shipped with each release",
+            // upgrade query
+            "
+                CREATE TABLE examples (
+                    id SERIAL PRIMARY KEY,
+                    release_id INT NOT NULL REFERENCES releases(id),
+                    name VARCHAR NOT NULL,
+                    description TEXT,
+                    source_code TEXT NOT NULL,
+                    UNIQUE (release_id, name)
+                );
+            ",
+            // downgrade query
+            "DROP TABLE examples;"
+        ),
+        migration!(
+            context,
+            27,
+            // description
+            "Store the license GitHub detected for each repository",
+            // upgrade query
+            "
+                ALTER TABLE github_repos
+                    ADD COLUMN license VARCHAR,
+                    ADD COLUMN license_url VARCHAR;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE github_repos
+                    DROP COLUMN license,
+                    DROP COLUMN license_url;
+            "
+        ),
+        migration!(
+            context,
+            28,
+            // description
+            "Record which worker is building each release of the queue",
+            // upgrade query
+            "
+                ALTER TABLE queue
+                    ADD COLUMN started_at TIMESTAMP,
+                    ADD COLUMN worker_id VARCHAR;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE queue
+                    DROP COLUMN started_at,
+                    DROP COLUMN worker_id;
+            "
+        ),
+        migration!(
+            context,
+            29,
+            // description
+            "Record when GitHub repositories were skipped because of the rate limit",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN rate_limited_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN rate_limited_at;"
+        ),
+        migration!(
+            context,
+            30,
+            // description
+            "Store the approximate size of GitHub repositories",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN size_kb BIGINT NOT NULL DEFAULT 0;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN size_kb;"
+        ),
+        migration!(
+            context,
+            31,
+            // description
+            "Record which features the documentation of each release is built with",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN enabled_features TEXT[];",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN enabled_features;"
+        ),
+        migration!(
+            context,
+            32,
+            // description
+            "Record the targets whose documentation failed to build",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN failed_targets TEXT[] NOT NULL DEFAULT '{}';",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN failed_targets;"
+        ),
+        migration!(
+            context,
+            33,
+            // description
+            "Only allow one pending build of each release in the queue",
+            // upgrade query
+            "
+                ALTER TABLE queue DROP CONSTRAINT queue_name_version_key;
+                CREATE UNIQUE INDEX queue_pending_name_version_idx
+                    ON queue (name, version) WHERE started_at IS NULL;
+            ",
+            // downgrade query
+            "
+                DROP INDEX queue_pending_name_version_idx;
+                DELETE FROM queue
+                    USING queue AS duplicates
+                    WHERE queue.name = duplicates.name
+                        AND queue.version = duplicates.version
+                        AND queue.id > duplicates.id;
+                ALTER TABLE queue ADD CONSTRAINT queue_name_version_key UNIQUE (name, version);
+            "
+        ),
+        migration!(
+            context,
+            34,
+            // description
+            "Store hashes of the authors' emails, and which crates each author worked on",
+            // upgrade query
+            "
+                ALTER TABLE authors ADD COLUMN email_hash VARCHAR(64);
+                UPDATE authors
+                    SET email_hash = encode(sha256(convert_to(lower(trim(email)), 'UTF8')), 'hex')
+                    WHERE trim(email) <> '';
+                ALTER TABLE authors DROP COLUMN email;
+
+                CREATE TABLE crate_authors (
+                    crate_id INT NOT NULL REFERENCES crates(id),
+                    author_id INT NOT NULL REFERENCES authors(id),
+                    role VARCHAR(32) NOT NULL DEFAULT 'author',
+                    PRIMARY KEY (crate_id, author_id)
+                );
+                INSERT INTO crate_authors (crate_id, author_id)
+                    SELECT DISTINCT releases.crate_id, author_rels.aid
+                    FROM author_rels
+                    INNER JOIN releases ON releases.id = author_rels.rid;
+            ",
+            // downgrade query
+            "
+                DROP TABLE crate_authors;
+                ALTER TABLE authors ADD COLUMN email VARCHAR(255);
+                ALTER TABLE authors DROP COLUMN email_hash;
+            "
+        ),
+        migration!(
+            context,
+            35,
+            // description
+            "Record whether owners are users or teams, and when the owners of crates were updated",
+            // upgrade query
+            "
+                ALTER TABLE owners ADD COLUMN kind VARCHAR(10) NOT NULL DEFAULT 'user';
+                ALTER TABLE crates ADD COLUMN owners_updated_at TIMESTAMP;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE owners DROP COLUMN kind;
+                ALTER TABLE crates DROP COLUMN owners_updated_at;
+            "
+        ),
+        migration!(
+            context,
+            36,
+            // description
+            "Record how long builds take and how much documentation they generate",
+            // upgrade query
+            "
+                ALTER TABLE builds
+                    ADD COLUMN duration_ms BIGINT,
+                    ADD COLUMN peak_memory_bytes BIGINT,
+                    ADD COLUMN files_count BIGINT,
+                    ADD COLUMN files_size_bytes BIGINT;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE builds
+                    DROP COLUMN duration_ms,
+                    DROP COLUMN peak_memory_bytes,
+                    DROP COLUMN files_count,
+                    DROP COLUMN files_size_bytes;
+            "
+        ),
+        migration!(
+            context,
+            37,
+            // description
+            "Remember the hashes of the uploaded files to only upload the changed ones on rebuilds",
+            // upgrade query
+            "
+                CREATE TABLE build_cache (
+                    crate_name VARCHAR(255) NOT NULL,
+                    version VARCHAR(100) NOT NULL,
+                    path TEXT NOT NULL,
+                    sha256 CHAR(64) NOT NULL,
+                    PRIMARY KEY (crate_name, version, path)
+                );
+            ",
+            // downgrade query
+            "DROP TABLE build_cache;"
+        ),
+        migration!(
+            context,
+            38,
+            // description
+            "Record whether GitHub repositories are forks, and of which repository",
+            // upgrade query
+            "
+                ALTER TABLE github_repos
+                    ADD COLUMN is_fork BOOLEAN NOT NULL DEFAULT FALSE,
+                    ADD COLUMN fork_parent VARCHAR;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE github_repos
+                    DROP COLUMN is_fork,
+                    DROP COLUMN fork_parent;
+            "
+        ),
+        migration!(
+            context,
+            39,
+            // description
+            "Record whether releases are procedural macros",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN is_proc_macro BOOLEAN NOT NULL DEFAULT FALSE;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN is_proc_macro;"
+        ),
+        migration!(
+            context,
+            40,
+            // description
+            "Record which storage backend holds the files stored in the database",
+            // upgrade query
+            "ALTER TABLE files ADD COLUMN backend VARCHAR(16) NOT NULL DEFAULT 'database';",
+            // downgrade query
+            "ALTER TABLE files DROP COLUMN backend;"
+        ),
+        migration!(
+            context,
+            41,
+            // description
+            "Record daily snapshots of the metrics of the crates",
+            // upgrade query
+            "
+                CREATE TABLE crate_metrics_snapshots (
+                    crate_id INT NOT NULL REFERENCES crates(id),
+                    date DATE NOT NULL,
+                    stars INT,
+                    forks INT,
+                    open_issues INT,
+                    doc_coverage_percent REAL,
+                    build_time_seconds REAL,
+                    PRIMARY KEY (crate_id, date)
+                );
+            ",
+            // downgrade query
+            "DROP TABLE crate_metrics_snapshots;"
+        ),
+        migration!(
+            context,
+            42,
+            // description
+            "Record how long compiling each dependency of releases takes",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN compile_timings JSONB;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN compile_timings;"
+        ),
+        migration!(
+            context,
+            43,
+            // description
+            "Store the content of identical files only once, keyed by its hash",
+            // upgrade query
+            "
+                CREATE TABLE blobs (
+                    hash CHAR(64) PRIMARY KEY,
+                    content BYTEA NOT NULL,
+                    refcount INT NOT NULL
+                );
+                ALTER TABLE files ADD COLUMN content_hash CHAR(64);
+            ",
+            // downgrade query
+            "
+                UPDATE files SET content = blobs.content
+                    FROM blobs
+                    WHERE blobs.hash = files.content_hash;
+                ALTER TABLE files DROP COLUMN content_hash;
+                DROP TABLE blobs;
+            "
+        ),
+        migration!(
+            context,
+            44,
+            // description
+            "Store the expansion of the macros used by the examples of releases",
+            // upgrade query
+            "
+                CREATE TABLE macro_expansions (
+                    release_id INT NOT NULL REFERENCES releases(id),
+                    example_name VARCHAR(255) NOT NULL,
+                    expansion TEXT NOT NULL,
+                    PRIMARY KEY (release_id, example_name)
+                );
+            ",
+            // downgrade query
+            "DROP TABLE macro_expansions;"
+        ),
+        migration!(
+            context,
+            45,
+            // description
+            "Index the signatures of the public functions of releases, to search them by type",
+            // upgrade query
+            "
+                CREATE TABLE function_signatures (
+                    crate_id INT NOT NULL REFERENCES crates(id),
+                    release_id INT NOT NULL REFERENCES releases(id),
+                    item_path TEXT NOT NULL,
+                    param_types JSONB NOT NULL,
+                    return_type TEXT NOT NULL,
+                    PRIMARY KEY (release_id, item_path)
+                );
+                CREATE INDEX function_signatures_param_types_idx
+                    ON function_signatures USING GIN (param_types);
+                CREATE INDEX function_signatures_return_type_idx
+                    ON function_signatures (return_type);
+            ",
+            // downgrade query
+            "DROP TABLE function_signatures;"
+        ),
+        migration!(
+            context,
+            46,
+            // description
+            "Store how many open issues of repositories are labeled as good first issues",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN good_first_issues INT;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN good_first_issues;"
+        ),
+        migration!(
+            context,
+            47,
+            // description
+            "Mark the repositories unavailable for legal reasons",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN github_status TEXT;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN github_status;"
+        ),
+        migration!(
+            context,
+            48,
+            // description
+            "Store the security advisories affecting the dependencies of releases",
+            // upgrade query
+            "
+                CREATE TABLE audit_results (
+                    id SERIAL PRIMARY KEY,
+                    release_id INT NOT NULL REFERENCES releases(id),
+                    advisory_id TEXT NOT NULL,
+                    severity TEXT,
+                    affected_crate TEXT NOT NULL,
+                    affected_version TEXT NOT NULL,
+                    patched_versions TEXT[] NOT NULL
+                );
+                CREATE INDEX audit_results_release_id_idx ON audit_results (release_id);
+                CREATE TABLE audit_runs (
+                    release_id INT PRIMARY KEY REFERENCES releases(id),
+                    advisory_db_commit TEXT,
+                    audited_at TIMESTAMP NOT NULL
+                );
+            ",
+            // downgrade query
+            "
+                DROP TABLE audit_runs;
+                DROP TABLE audit_results;
+            "
+        ),
+        migration!(
+            context,
+            49,
+            // description
+            "Record the releases whose documentation coverage regressed",
+            // upgrade query
+            "
+                CREATE TABLE coverage_regressions (
+                    release_id INT PRIMARY KEY REFERENCES releases(id),
+                    previous_version TEXT NOT NULL,
+                    previous_percent REAL NOT NULL,
+                    new_percent REAL NOT NULL,
+                    detected_at TIMESTAMP NOT NULL
+                );
+            ",
+            // downgrade query
+            "DROP TABLE coverage_regressions;"
+        ),
+        migration!(
+            context,
+            50,
+            // description
+            "Record when the metadata of repositories last changed, apart from their pushes",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN github_updated_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN github_updated_at;"
+        ),
+        migration!(
+            context,
+            51,
+            // description
+            "Store the relationships between the public types of releases",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN type_graph JSONB;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN type_graph;"
+        ),
+        migration!(
+            context,
+            52,
+            // description
+            "Record when the registry was last asked for the repository of crates lacking one",
+            // upgrade query
+            "ALTER TABLE crates ADD COLUMN registry_repository_checked_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE crates DROP COLUMN registry_repository_checked_at;"
+        ),
+        migration!(
+            context,
+            53,
+            // description
+            "Record when the periodic jobs of the daemon last started and last succeeded",
+            // upgrade query
+            "CREATE TABLE heartbeats (
+                job TEXT PRIMARY KEY,
+                last_start TIMESTAMP,
+                last_success TIMESTAMP
+            );",
+            // downgrade query
+            "DROP TABLE heartbeats;"
+        ),
+        migration!(
+            context,
+            54,
+            // description
+            "Record the totals of the runs of the GitHub updater, to compare each run to the last",
+            // upgrade query
+            "CREATE TABLE github_update_runs (
+                id SERIAL PRIMARY KEY,
+                finished_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                updated INT NOT NULL,
+                not_found INT NOT NULL,
+                rate_limited INT NOT NULL,
+                blocked INT NOT NULL,
+                failed INT NOT NULL,
+                timed_out BOOL NOT NULL,
+                interrupted BOOL NOT NULL
+            );",
+            // downgrade query
+            "DROP TABLE github_update_runs;"
+        ),
+        migration!(
+            context,
+            55,
+            // description
+            "Store the test functions written in the sources of releases",
+            // upgrade query
+            "
+                CREATE TABLE inline_tests (
+                    id SERIAL PRIMARY KEY,
+                    release_id INT NOT NULL REFERENCES releases(id),
+                    module_path TEXT NOT NULL,
+                    test_name TEXT NOT NULL,
+                    source_code TEXT NOT NULL
+                );
+                CREATE INDEX inline_tests_release_id_idx ON inline_tests (release_id);
+                ALTER TABLE releases ADD COLUMN inline_test_count INT;
+            ",
+            // downgrade query
+            "
+                DROP TABLE inline_tests;
+                ALTER TABLE releases DROP COLUMN inline_test_count;
+            "
+        ),
+        migration!(
+            context,
+            56,
+            // description
+            "Record when builds in the queue were last attempted, to back off before retrying them",
+            // upgrade query
+            "ALTER TABLE queue ADD COLUMN last_attempt TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN last_attempt;"
+        ),
+        migration!(
+            context,
+            57,
+            // description
+            "Notify the owners of crates subscribed to the failures of their builds",
+            // upgrade query
+            "
+                CREATE TYPE notification_method AS ENUM ('email', 'webhook');
+                CREATE TABLE notifications (
+                    id SERIAL PRIMARY KEY,
+                    user_id INT NOT NULL REFERENCES owners(id),
+                    crate_id INT NOT NULL REFERENCES crates(id),
+                    method notification_method NOT NULL,
+                    endpoint TEXT NOT NULL,
+                    events JSONB NOT NULL,
+                    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                    UNIQUE (crate_id, method, endpoint)
+                );
+                CREATE TABLE pending_notifications (
+                    id SERIAL PRIMARY KEY,
+                    notification_id INT NOT NULL REFERENCES notifications(id) ON DELETE CASCADE,
+                    event TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    version TEXT NOT NULL,
+                    attempts INT NOT NULL DEFAULT 0,
+                    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+                );
+            ",
+            // downgrade query
+            "
+                DROP TABLE pending_notifications;
+                DROP TABLE notifications;
+                DROP TYPE notification_method;
+            "
+        ),
+        migration!(
+            context,
+            58,
+            // description
+            "Record which process holds the lock of each role of the daemon",
+            // upgrade query
+            "CREATE TABLE instance_locks (
+                role TEXT PRIMARY KEY,
+                hostname TEXT NOT NULL,
+                pid INT NOT NULL,
+                acquired_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                heartbeat_at TIMESTAMP NOT NULL DEFAULT NOW()
+            );",
+            // downgrade query
+            "DROP TABLE instance_locks;"
+        ),
+        migration!(
+            context,
+            59,
+            // description
+            "Count how many crates depend on each pair of crates",
+            // upgrade query
+            "CREATE TABLE crate_cooccurrence (
+                name TEXT NOT NULL,
+                other TEXT NOT NULL,
+                count INT NOT NULL,
+                PRIMARY KEY (name, other)
+            );",
+            // downgrade query
+            "DROP TABLE crate_cooccurrence;"
+        ),
+        migration!(
+            context,
+            60,
+            // description
+            "Store the events GitHub sends to the webhook, to update their repositories",
+            // upgrade query
+            "
+                CREATE TABLE webhook_events (
+                    id SERIAL PRIMARY KEY,
+                    delivery_id TEXT,
+                    event TEXT NOT NULL,
+                    repository_id TEXT NOT NULL,
+                    repository_name TEXT NOT NULL,
+                    received_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                    processed_at TIMESTAMP
+                );
+                CREATE INDEX webhook_events_pending_idx
+                    ON webhook_events (received_at) WHERE processed_at IS NULL;
+            ",
+            // downgrade query
+            "DROP TABLE webhook_events;"
+        ),
+        migration!(
+            context,
+            61,
+            // description
+            "Record when the workers building releases were last alive",
+            // upgrade query
+            "ALTER TABLE queue ADD COLUMN heartbeat_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN heartbeat_at;"
+        ),
+        migration!(
+            context,
+            62,
+            // description
+            "Store how many open issues of repositories are labeled as bugs",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN github_open_bugs INT;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN github_open_bugs;"
+        ),
+        migration!(
+            context,
+            63,
+            // description
+            "Store the health score of crates, and whether their repositories are maintained",
+            // upgrade query
+            "
+                ALTER TABLE github_repos
+                    ADD COLUMN is_archived BOOLEAN NOT NULL DEFAULT FALSE,
+                    ADD COLUMN closed_issues INT;
+                ALTER TABLE crates
+                    ADD COLUMN health_score FLOAT,
+                    ADD COLUMN health_score_breakdown JSONB;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE github_repos
+                    DROP COLUMN is_archived,
+                    DROP COLUMN closed_issues;
+                ALTER TABLE crates
+                    DROP COLUMN health_score,
+                    DROP COLUMN health_score_breakdown;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            64,
+            // description
+            "Store the last successful update of repositories apart from the last attempt",
+            // upgrade query
+            "
+                ALTER TABLE github_repos RENAME COLUMN updated_at TO github_last_attempt;
+                ALTER TABLE github_repos ADD COLUMN github_last_success TIMESTAMP;
+                UPDATE github_repos
+                    SET github_last_success = github_last_attempt
+                    WHERE github_status IS NULL;
+            ",
+            // downgrade query
+            "
+                ALTER TABLE github_repos DROP COLUMN github_last_success;
+                ALTER TABLE github_repos RENAME COLUMN github_last_attempt TO updated_at;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            65,
+            // description
+            "Store the README of the GitHub repositories",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN github_readme TEXT;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN github_readme;"
+        ),
+        migration!(
+            context,
+            // version
+            66,
+            // description
+            "Store the topics of the GitHub repositories",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN topics TEXT[];",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN topics;"
+        ),
+        migration!(
+            context,
+            // version
+            67,
+            // description
+            "Store whether the math of the documentation of releases is rendered",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN math BOOLEAN NOT NULL DEFAULT FALSE;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN math;"
+        ),
+        migration!(
+            context,
+            // version
+            68,
+            // description
+            "Store the warnings of rustdoc about the documentation of releases",
+            // upgrade query
+            "
+                CREATE TABLE doc_warnings (
+                    id SERIAL PRIMARY KEY,
+                    release_id INT NOT NULL REFERENCES releases(id),
+                    warning_type TEXT NOT NULL,
+                    item_path TEXT NOT NULL,
+                    message TEXT NOT NULL,
+                    location TEXT
+                );
+                CREATE INDEX doc_warnings_release_id_idx ON doc_warnings (release_id);
+                ALTER TABLE releases ADD COLUMN doc_warning_count INT;
+            ",
+            // downgrade query
+            "
+                DROP TABLE doc_warnings;
+                ALTER TABLE releases DROP COLUMN doc_warning_count;
+            "
+        ),
+        migration!(
+            context,
+            // version
+            69,
+            // description
+            "Record when the NULL columns of the GitHub repositories were last filled",
+            // upgrade query
+            "ALTER TABLE github_repos ADD COLUMN null_columns_checked_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE github_repos DROP COLUMN null_columns_checked_at;"
+        ),
+        migration!(
+            context,
+            // version
+            70,
+            // description
+            "Record whether some test functions of releases were left out",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN inline_tests_truncated BOOL NOT NULL DEFAULT FALSE;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN inline_tests_truncated;"
+        ),
+        migration!(
+            context,
+            // version
+            71,
+            // description
+            "Index the releases by GitHub repository, to find the newest release of each",
+            // upgrade query
+            "CREATE INDEX releases_github_repo_idx ON releases (github_repo, release_time);",
+            // downgrade query
+            "DROP INDEX releases_github_repo_idx;"
+        ),
+        migration!(
+            context,
+            // version
+            72,
+            // description
+            "Count the files stored by every storage backend, and their size",
+            // upgrade query
+            "
+                CREATE TABLE storage_usage (
+                    backend VARCHAR PRIMARY KEY,
+                    files BIGINT NOT NULL,
+                    bytes BIGINT NOT NULL
+                );
+                INSERT INTO storage_usage (backend, files, bytes)
+                    SELECT
+                        'database',
+                        COUNT(*),
+                        COALESCE(SUM(LENGTH(COALESCE(blobs.content, files.content))), 0)
+                    FROM files
+                    LEFT JOIN blobs ON blobs.hash = files.content_hash;
+                DELETE FROM config WHERE name = 'storage_size';
+            ",
+            // downgrade query
+            "DROP TABLE storage_usage;"
+        ),
+        migration!(
+            context,
+            // version
+            73,
+            // description
+            "Record the crates whose latest release depends on each crate",
+            // upgrade query
+            "
+                CREATE TABLE crate_dependents (
+                    dependency TEXT NOT NULL,
+                    dependent_id INT NOT NULL REFERENCES crates(id) ON DELETE CASCADE,
+                    PRIMARY KEY (dependency, dependent_id)
+                );
+                CREATE INDEX crate_dependents_dependent_id_idx ON crate_dependents (dependent_id);
+                INSERT INTO crate_dependents (dependency, dependent_id)
+                    SELECT DISTINCT dependency->>0, crates.id
+                    FROM crates
+                    INNER JOIN releases ON releases.id = crates.latest_version_id
+                    CROSS JOIN json_array_elements(releases.dependencies) AS dependency
+                    WHERE dependency->>0 IS NOT NULL;
+            ",
+            // downgrade query
+            "DROP TABLE crate_dependents;"
+        ),
     ];
 
     for migration in migrations {
@@ -2,17 +2,31 @@
 
 pub use self::add_package::update_crate_data_in_database;
 pub(crate) use self::add_package::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_audit_results, add_build_into_database, add_compile_timings, add_doc_coverage,
+    add_doc_warnings, add_examples_into_database, add_function_signatures, add_inline_tests,
+    add_macro_expansions_into_database, add_package_into_database, add_type_graph, example_names,
+    update_crate_dependents,
 };
-pub use self::delete::{delete_crate, delete_version};
-pub use self::file::add_path_into_database;
+pub use self::build_stats::{summarize_builds, BuildStatsSummary, Percentiles};
+pub use self::delete::{delete_crate, delete_version, delete_version_files};
+pub use self::file::{add_changed_path_into_database, add_path_into_database, CacheStats};
+pub use self::instance_lock::{InstanceLock, InstanceLockError, InstanceRole, LockHolder};
 pub use self::migrate::migrate;
+pub use self::overview::{
+    instance_overview, BackendSize, CoverageAverages, InstanceOverview, LastRun, QueueDepth,
+};
 pub use self::pool::{Pool, PoolClient, PoolError};
 
 mod add_package;
 pub mod blacklist;
+mod build_stats;
+pub(crate) mod coverage_regression;
+pub(crate) mod crate_metrics;
 mod delete;
 pub(crate) mod file;
+pub(crate) mod heartbeat;
+mod instance_lock;
 mod migrate;
+mod overview;
 mod pool;
 pub(crate) mod types;
@@ -0,0 +1,284 @@
+//! An overview of the instance, from a handful of aggregate queries
+
+use crate::error::Result;
+use crate::Config;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use postgres::Client;
+use serde::Serialize;
+
+/// An overview of the instance, see [`instance_overview`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceOverview {
+    pub crates: i64,
+    pub releases: i64,
+    pub successful_builds: i64,
+    pub failed_builds: i64,
+    /// The builds waiting in the queue, by priority
+    pub queue: Vec<QueueDepth>,
+    /// The files stored by each storage backend
+    pub storage: Vec<BackendSize>,
+    pub coverage: CoverageAverages,
+    /// The last successful poll of the registry index
+    pub last_registry_poll: Option<LastRun>,
+    /// The last run of the GitHub updater going through all the repositories
+    pub last_github_sweep: Option<LastRun>,
+    /// When the current toolchain started being used
+    pub last_toolchain_update: Option<LastRun>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueDepth {
+    pub priority: i32,
+    pub builds: i64,
+}
+
+/// The files of a storage backend and their size as stored, from the counters the storage keeps
+/// up to date. The files stored in S3 before the counters were added aren't counted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BackendSize {
+    pub backend: String,
+    pub files: i64,
+    pub bytes: i64,
+}
+
+/// The average documentation coverage of the latest releases of the crates, in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CoverageAverages {
+    pub documented: Option<f64>,
+    pub with_examples: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LastRun {
+    pub at: DateTime<Utc>,
+    pub age_secs: i64,
+}
+
+impl LastRun {
+    fn new(at: NaiveDateTime) -> Self {
+        let at = DateTime::from_utc(at, Utc);
+        LastRun {
+            at,
+            age_secs: (Utc::now() - at).num_seconds(),
+        }
+    }
+}
+
+/// Returns an overview of the instance.
+pub fn instance_overview(conn: &mut Client, config: &Config) -> Result<InstanceOverview> {
+    let row = conn.query_one(
+        "SELECT
+             (SELECT COUNT(*) FROM crates),
+             (SELECT COUNT(*) FROM releases),
+             (SELECT COUNT(*) FROM builds WHERE build_status),
+             (SELECT COUNT(*) FROM builds WHERE NOT build_status),
+             (SELECT last_success FROM heartbeats WHERE job = 'registry index reader'),
+             (SELECT MAX(finished_at) FROM github_update_runs
+              WHERE NOT timed_out AND NOT interrupted),
+             (SELECT (value #>> '{}')::TIMESTAMP FROM config
+              WHERE name = 'toolchain_updated_at')",
+        &[],
+    )?;
+    let last_run = |idx| row.get::<_, Option<NaiveDateTime>>(idx).map(LastRun::new);
+
+    let queue = conn
+        .query(
+            "SELECT priority, COUNT(*)
+             FROM queue
+             WHERE attempt < $1
+             GROUP BY priority
+             ORDER BY priority",
+            &[&i32::from(config.build_attempts)],
+        )?
+        .into_iter()
+        .map(|row| QueueDepth {
+            priority: row.get(0),
+            builds: row.get(1),
+        })
+        .collect();
+
+    let coverage = conn.query_one(
+        "SELECT
+             AVG(
+                 doc_coverage.documented_items::FLOAT8
+                     / NULLIF(doc_coverage.total_items, 0)
+             ) * 100,
+             AVG(
+                 doc_coverage.items_with_examples::FLOAT8
+                     / NULLIF(doc_coverage.total_items_needing_examples, 0)
+             ) * 100
+         FROM crates
+         INNER JOIN doc_coverage ON doc_coverage.release_id = crates.latest_version_id",
+        &[],
+    )?;
+
+    let storage = conn
+        .query(
+            "SELECT backend, files, bytes FROM storage_usage ORDER BY backend",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| BackendSize {
+            backend: row.get(0),
+            files: row.get(1),
+            bytes: row.get(2),
+        })
+        .collect();
+
+    Ok(InstanceOverview {
+        crates: row.get(0),
+        releases: row.get(1),
+        successful_builds: row.get(2),
+        failed_builds: row.get(3),
+        queue,
+        storage,
+        coverage: CoverageAverages {
+            documented: coverage.get(0),
+            with_examples: coverage.get(1),
+        },
+        last_registry_poll: last_run(4),
+        last_github_sweep: last_run(5),
+        last_toolchain_update: last_run(6),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Blob;
+    use crate::test::wrapper;
+    use serde_json::Value;
+
+    #[test]
+    fn test_instance_overview() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .coverage(5, 10, 4, 1)
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .coverage(8, 10, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("1.0.0")
+                .build_result_successful(false)
+                .create()?;
+            let queue = env.build_queue();
+            queue.add_crate("queued", "1.0.0", 0, None)?;
+            queue.add_crate("queued", "1.1.0", 0, None)?;
+            queue.add_crate("deprioritized", "1.0.0", 10, None)?;
+
+            let mut conn = env.db().conn();
+            conn.execute(
+                "INSERT INTO heartbeats (job, last_success)
+                 VALUES ('registry index reader', NOW() - INTERVAL '5 minutes')",
+                &[],
+            )?;
+
+            let overview = instance_overview(&mut conn, &env.config())?;
+            assert_eq!(overview.crates, 2);
+            assert_eq!(overview.releases, 3);
+            assert_eq!(overview.successful_builds, 2);
+            assert_eq!(overview.failed_builds, 1);
+            assert_eq!(
+                overview.queue,
+                vec![
+                    QueueDepth {
+                        priority: 0,
+                        builds: 2
+                    },
+                    QueueDepth {
+                        priority: 10,
+                        builds: 1
+                    },
+                ]
+            );
+            // Only the latest releases with coverage count
+            assert_eq!(overview.coverage.documented, Some(80.0));
+            assert_eq!(overview.coverage.with_examples, None);
+            let poll = overview.last_registry_poll.unwrap();
+            assert!((299..310).contains(&poll.age_secs));
+            assert_eq!(overview.last_github_sweep, None);
+            assert_eq!(overview.last_toolchain_update, None);
+
+            let json = serde_json::to_value(&overview)?;
+            let keys: Vec<&str> = json
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect();
+            assert_eq!(
+                keys,
+                vec![
+                    "coverage",
+                    "crates",
+                    "failed_builds",
+                    "last_github_sweep",
+                    "last_registry_poll",
+                    "last_toolchain_update",
+                    "queue",
+                    "releases",
+                    "storage",
+                    "successful_builds",
+                ]
+            );
+            assert_eq!(
+                json["queue"][0],
+                serde_json::json!({"priority": 0, "builds": 2})
+            );
+            assert!(json["last_registry_poll"]["at"].is_string());
+            assert_eq!(json["last_github_sweep"], Value::Null);
+            assert!(json["storage"].is_array());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_storage_usage() {
+        wrapper(|env| {
+            let storage = env.storage();
+            let store = |path: &str, content: &[u8]| {
+                storage.store_blobs(vec![Blob {
+                    path: path.into(),
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                    content: content.into(),
+                    compression: None,
+                }])
+            };
+            let usage = || -> Result<Vec<BackendSize>> {
+                Ok(instance_overview(&mut env.db().conn(), &env.config())?.storage)
+            };
+            let database = |files, bytes| {
+                vec![BackendSize {
+                    backend: "database".into(),
+                    files,
+                    bytes,
+                }]
+            };
+            assert_eq!(usage()?, database(0, 0));
+
+            store("a/1.txt", b"abc")?;
+            store("a/2.txt", b"de")?;
+            store("b/3.txt", b"f")?;
+            assert_eq!(usage()?, database(3, 6));
+
+            // Replacing a file doesn't count it twice
+            store("a/2.txt", b"defg")?;
+            assert_eq!(usage()?, database(3, 8));
+
+            storage.delete_prefix("a/")?;
+            assert_eq!(usage()?, database(1, 1));
+            storage.delete_paths(&["b/3.txt".into(), "b/missing.txt".into()])?;
+            assert_eq!(usage()?, database(0, 0));
+
+            Ok(())
+        });
+    }
+}
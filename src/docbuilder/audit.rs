@@ -0,0 +1,378 @@
+//! The known vulnerabilities of the dependencies of releases, from `cargo audit`
+
+use crate::error::Result;
+use postgres::Client;
+use serde::{Deserialize, Serialize};
+
+/// Returns the hash of the latest commit of the RustSec advisory database.
+const ADVISORY_DB_HEAD_URL: &str = "https://api.github.com/repos/rustsec/advisory-db/commits/main";
+
+/// The results of auditing the lockfile of a release.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AuditReport {
+    /// The commit of the advisory database the lockfile was audited against
+    pub(crate) advisory_db_commit: Option<String>,
+    pub(crate) findings: Vec<AuditFinding>,
+}
+
+/// A dependency of a release affected by a security advisory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct AuditFinding {
+    pub(crate) advisory_id: String,
+    /// `none`, `low`, `medium`, `high` or `critical`, from the CVSS score of the advisory if it
+    /// has one
+    pub(crate) severity: Option<String>,
+    pub(crate) affected_crate: String,
+    pub(crate) affected_version: String,
+    /// The version requirements of the releases of the crate fixing the vulnerability
+    pub(crate) patched_versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Report {
+    database: Option<Database>,
+    vulnerabilities: Vulnerabilities,
+}
+
+#[derive(Deserialize)]
+struct Database {
+    #[serde(rename = "last-commit")]
+    last_commit: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Vulnerabilities {
+    list: Vec<Vulnerability>,
+}
+
+#[derive(Deserialize)]
+struct Vulnerability {
+    advisory: Advisory,
+    versions: Versions,
+    package: Package,
+}
+
+#[derive(Deserialize)]
+struct Advisory {
+    id: String,
+    cvss: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Versions {
+    #[serde(default)]
+    patched: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+}
+
+/// Parses the report printed by `cargo audit --json`.
+pub(crate) fn parse_audit_report(json: &str) -> Result<AuditReport> {
+    let report: Report = serde_json::from_str(json)?;
+
+    Ok(AuditReport {
+        advisory_db_commit: report.database.and_then(|database| database.last_commit),
+        findings: report
+            .vulnerabilities
+            .list
+            .into_iter()
+            .map(|vulnerability| AuditFinding {
+                severity: vulnerability
+                    .advisory
+                    .cvss
+                    .as_deref()
+                    .and_then(cvss_severity)
+                    .map(String::from),
+                advisory_id: vulnerability.advisory.id,
+                affected_crate: vulnerability.package.name,
+                affected_version: vulnerability.package.version,
+                patched_versions: vulnerability.versions.patched,
+            })
+            .collect(),
+    })
+}
+
+/// Returns the qualitative severity rating of the base score of a CVSS 3.x vector, like
+/// `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`, or `None` if the vector is invalid.
+pub(crate) fn cvss_severity(vector: &str) -> Option<&'static str> {
+    let mut metrics = vector.split('/');
+    if !metrics.next()?.starts_with("CVSS:3.") {
+        return None;
+    }
+    let metrics: Vec<(&str, &str)> = metrics
+        .map(|metric| {
+            let mut parts = metric.splitn(2, ':');
+            Some((parts.next()?, parts.next()?))
+        })
+        .collect::<Option<_>>()?;
+    let metric = |name: &str| {
+        metrics
+            .iter()
+            .find(|(metric, _)| *metric == name)
+            .map(|(_, value)| *value)
+    };
+
+    let scope_changed = match metric("S")? {
+        "U" => false,
+        "C" => true,
+        _ => return None,
+    };
+    let attack_vector = match metric("AV")? {
+        "N" => 0.85,
+        "A" => 0.62,
+        "L" => 0.55,
+        "P" => 0.2,
+        _ => return None,
+    };
+    let attack_complexity = match metric("AC")? {
+        "L" => 0.77,
+        "H" => 0.44,
+        _ => return None,
+    };
+    let privileges_required = match (metric("PR")?, scope_changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let user_interaction = match metric("UI")? {
+        "N" => 0.85,
+        "R" => 0.62,
+        _ => return None,
+    };
+    let impact_metric = |name| match metric(name)? {
+        "H" => Some(0.56),
+        "L" => Some(0.22),
+        "N" => Some(0.0),
+        _ => None,
+    };
+    let (confidentiality, integrity, availability) = (
+        impact_metric("C")?,
+        impact_metric("I")?,
+        impact_metric("A")?,
+    );
+
+    let impact_sub_score = 1.0 - (1.0 - confidentiality) * (1.0 - integrity) * (1.0 - availability);
+    let impact = if scope_changed {
+        7.52 * (impact_sub_score - 0.029) - 3.25 * f64::powi(impact_sub_score - 0.02, 15)
+    } else {
+        6.42 * impact_sub_score
+    };
+    let exploitability =
+        8.22 * attack_vector * attack_complexity * privileges_required * user_interaction;
+
+    if impact <= 0.0 {
+        return Some("none");
+    }
+    let score = if scope_changed {
+        round_up(f64::min(1.08 * (impact + exploitability), 10.0))
+    } else {
+        round_up(f64::min(impact + exploitability, 10.0))
+    };
+
+    Some(if score < 4.0 {
+        "low"
+    } else if score < 7.0 {
+        "medium"
+    } else if score < 9.0 {
+        "high"
+    } else {
+        "critical"
+    })
+}
+
+/// Rounds up to one decimal, as specified by CVSS 3.1 to avoid floating point errors.
+fn round_up(value: f64) -> f64 {
+    let int_value = (value * 100_000.0).round() as i64;
+    if int_value % 10_000 == 0 {
+        int_value as f64 / 100_000.0
+    } else {
+        (int_value / 10_000 + 1) as f64 / 10.0
+    }
+}
+
+/// Returns the hash of the latest commit of the RustSec advisory database.
+pub(crate) fn latest_advisory_db_commit(user_agent: &str) -> Result<String> {
+    let commit = reqwest::blocking::Client::new()
+        .get(ADVISORY_DB_HEAD_URL)
+        .header(reqwest::header::USER_AGENT, user_agent)
+        .header(reqwest::header::ACCEPT, "application/vnd.github.v3.sha")
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let commit = commit.trim();
+    if commit.len() != 40 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+        failure::bail!("invalid advisory database commit: {}", commit);
+    }
+
+    Ok(commit.to_string())
+}
+
+/// Returns the id, crate name and version of up to `limit` releases last audited against another
+/// commit of the advisory database than `advisory_db_commit`, the least recently audited first.
+pub(crate) fn releases_to_reaudit(
+    conn: &mut Client,
+    advisory_db_commit: &str,
+    limit: i64,
+) -> Result<Vec<(i32, String, String)>> {
+    Ok(conn
+        .query(
+            "SELECT releases.id, crates.name, releases.version
+             FROM audit_runs
+             INNER JOIN releases ON releases.id = audit_runs.release_id
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE audit_runs.advisory_db_commit IS DISTINCT FROM $1
+             ORDER BY audit_runs.audited_at
+             LIMIT $2",
+            &[&advisory_db_commit, &limit],
+        )?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::add_audit_results;
+    use crate::test::wrapper;
+
+    const SAMPLE_REPORT: &str = r#"{
+        "database": {
+            "advisory-count": 312,
+            "last-commit": "0123456789abcdef0123456789abcdef01234567",
+            "last-updated": "2020-12-01T12:00:00+01:00"
+        },
+        "lockfile": {"dependency-count": 42},
+        "vulnerabilities": {
+            "found": true,
+            "count": 2,
+            "list": [
+                {
+                    "advisory": {
+                        "id": "RUSTSEC-2020-0071",
+                        "package": "time",
+                        "title": "Potential segfault in the time crate",
+                        "cvss": "CVSS:3.1/AV:L/AC:H/PR:N/UI:N/S:U/C:N/I:N/A:H"
+                    },
+                    "versions": {"patched": [">=0.2.23"], "unaffected": ["=0.2.0"]},
+                    "affected": null,
+                    "package": {"name": "time", "version": "0.1.44", "source": "registry+https://github.com/rust-lang/crates.io-index"}
+                },
+                {
+                    "advisory": {
+                        "id": "RUSTSEC-2020-0036",
+                        "package": "failure",
+                        "title": "failure is officially deprecated/unmaintained",
+                        "cvss": null
+                    },
+                    "versions": {"patched": []},
+                    "affected": null,
+                    "package": {"name": "failure", "version": "0.1.8"}
+                }
+            ]
+        },
+        "warnings": {}
+    }"#;
+
+    #[test]
+    fn parse_report() {
+        let report = parse_audit_report(SAMPLE_REPORT).unwrap();
+        assert_eq!(
+            report.advisory_db_commit.as_deref(),
+            Some("0123456789abcdef0123456789abcdef01234567")
+        );
+        assert_eq!(
+            report.findings,
+            vec![
+                AuditFinding {
+                    advisory_id: "RUSTSEC-2020-0071".into(),
+                    severity: Some("medium".into()),
+                    affected_crate: "time".into(),
+                    affected_version: "0.1.44".into(),
+                    patched_versions: vec![">=0.2.23".into()],
+                },
+                AuditFinding {
+                    advisory_id: "RUSTSEC-2020-0036".into(),
+                    severity: None,
+                    affected_crate: "failure".into(),
+                    affected_version: "0.1.8".into(),
+                    patched_versions: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_report_without_vulnerabilities() {
+        let report = parse_audit_report(
+            r#"{"database": {}, "vulnerabilities": {"found": false, "count": 0, "list": []}}"#,
+        )
+        .unwrap();
+        assert_eq!(report.advisory_db_commit, None);
+        assert!(report.findings.is_empty());
+
+        assert!(parse_audit_report("error: couldn't fetch the advisory database").is_err());
+    }
+
+    #[test]
+    fn severity_of_cvss_vectors() {
+        let vectors = [
+            (
+                "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H",
+                Some("critical"),
+            ),
+            ("CVSS:3.0/AV:N/AC:L/PR:L/UI:N/S:U/C:H/I:H/A:N", Some("high")),
+            (
+                "CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N",
+                Some("medium"),
+            ),
+            ("CVSS:3.1/AV:P/AC:H/PR:H/UI:R/S:U/C:L/I:N/A:N", Some("low")),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N", Some("none")),
+            // The order of the metrics doesn't matter
+            (
+                "CVSS:3.1/S:U/AV:N/AC:L/PR:N/UI:N/C:H/I:H/A:H",
+                Some("critical"),
+            ),
+            ("CVSS:2.0/AV:N/AC:L/Au:N/C:P/I:P/A:P", None),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H", None),
+            ("CVSS:3.1/AV:X/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H", None),
+            ("", None),
+        ];
+        for (vector, expected) in &vectors {
+            assert_eq!(cvss_severity(vector), *expected, "{}", vector);
+        }
+    }
+
+    #[test]
+    fn reaudit_releases_audited_against_older_advisories() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            let old = env.fake_release().name("old").version("1.0.0").create()?;
+            let current = env
+                .fake_release()
+                .name("current")
+                .version("1.0.0")
+                .create()?;
+            env.fake_release().name("never").version("1.0.0").create()?;
+
+            let findings = parse_audit_report(SAMPLE_REPORT)?.findings;
+            add_audit_results(&mut conn, old, &findings, Some("old-commit"))?;
+            add_audit_results(&mut conn, current, &[], Some("new-commit"))?;
+
+            assert_eq!(
+                releases_to_reaudit(&mut conn, "new-commit", 10)?,
+                vec![(old, "old".to_string(), "1.0.0".to_string())]
+            );
+            assert!(releases_to_reaudit(&mut conn, "new-commit", 0)?.is_empty());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,185 @@
+//! How long compiling a crate and each of its dependencies takes, from `cargo build --timings`
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The time spent compiling one package of the dependency graph of a release.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CompileTiming {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// The features the package was compiled with
+    pub(crate) features: Vec<String>,
+    /// The time spent on all the units of the package, including running its build script
+    pub(crate) duration_secs: f64,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    package_id: String,
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    features: Option<Vec<String>>,
+}
+
+/// Collects the timings of the packages from the JSON messages cargo prints when invoked with
+/// `--timings=json --message-format=json`, one per line.
+#[derive(Debug, Default)]
+pub(crate) struct TimingsCollector {
+    timings: HashMap<String, CompileTiming>,
+}
+
+impl TimingsCollector {
+    /// Records the timing of the message on `line`, ignoring the lines that aren't timings.
+    pub(crate) fn process_line(&mut self, line: &str) {
+        if !line.starts_with('{') {
+            return;
+        }
+        let message: Message = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        if message.reason != "timing-info" && message.reason != "compiler-artifact" {
+            return;
+        }
+        let (name, version) = match parse_package_id(&message.package_id) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+
+        let timing = self
+            .timings
+            .entry(message.package_id)
+            .or_insert_with(|| CompileTiming {
+                name,
+                version,
+                features: Vec::new(),
+                duration_secs: 0.0,
+            });
+        if let Some(duration) = message.duration {
+            timing.duration_secs += duration;
+        }
+        if let Some(features) = message.features {
+            if timing.features.is_empty() {
+                timing.features = features;
+            }
+        }
+    }
+
+    /// Returns the timings of the packages, slowest first, or `None` if cargo reported none.
+    pub(crate) fn finish(self) -> Option<Vec<CompileTiming>> {
+        let mut timings: Vec<_> = self
+            .timings
+            .into_values()
+            .filter(|timing| timing.duration_secs > 0.0)
+            .collect();
+        if timings.is_empty() {
+            return None;
+        }
+        timings.sort_by(|a, b| {
+            b.duration_secs
+                .partial_cmp(&a.duration_secs)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Some(timings)
+    }
+}
+
+/// Extracts the name and version of a package from its cargo package id, which is either
+/// `name version (source)` or `source#name@version` depending on the cargo version.
+fn parse_package_id(id: &str) -> Option<(String, String)> {
+    if let Some((source, spec)) = id.rsplit_once('#') {
+        return match spec.split_once('@') {
+            Some((name, version)) => Some((name.into(), version.into())),
+            // Only the version is given when it matches the last segment of the source
+            None => {
+                let name = source.trim_end_matches('/').rsplit('/').next()?;
+                Some((name.into(), spec.into()))
+            }
+        };
+    }
+
+    let mut parts = id.split(' ');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(version)) if !name.is_empty() => Some((name.into(), version.into())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_OUTPUT: &str = r#"   Compiling libc v0.2.80
+{"reason":"compiler-artifact","package_id":"libc 0.2.80 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["custom-build"],"crate_types":["bin"],"name":"build-script-build","src_path":"/build.rs","edition":"2015","doctest":false,"test":false},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":["default","std"],"filenames":[],"executable":null,"fresh":false}
+{"reason":"timing-info","package_id":"libc 0.2.80 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["custom-build"],"crate_types":["bin"],"name":"build-script-build","src_path":"/build.rs","edition":"2015","doctest":false,"test":false},"mode":"build","duration":0.5,"rmeta_time":null}
+{"reason":"build-script-executed","package_id":"libc 0.2.80 (registry+https://github.com/rust-lang/crates.io-index)","linked_libs":[],"linked_paths":[],"cfgs":[],"env":[],"out_dir":"/out"}
+{"reason":"timing-info","package_id":"libc 0.2.80 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"crate_types":["lib"],"name":"libc","src_path":"/src/lib.rs","edition":"2015","doctest":true,"test":true},"mode":"build","duration":2.25,"rmeta_time":1.5}
+{"reason":"timing-info","package_id":"registry+https://github.com/rust-lang/crates.io-index#serde@1.0.117","target":{"kind":["lib"],"crate_types":["lib"],"name":"serde","src_path":"/src/lib.rs","edition":"2015","doctest":true,"test":true},"mode":"build","duration":4.0,"rmeta_time":2.0}
+{"reason":"compiler-artifact","package_id":"registry+https://github.com/rust-lang/crates.io-index#serde@1.0.117","target":{"kind":["lib"],"crate_types":["lib"],"name":"serde","src_path":"/src/lib.rs","edition":"2015","doctest":true,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":["derive","std"],"filenames":[],"executable":null,"fresh":false}
+{"reason":"timing-info","package_id":"path+file:///opt/rustwide/workdir#0.1.0","target":{"kind":["lib"],"crate_types":["lib"],"name":"workdir","src_path":"/src/lib.rs","edition":"2018","doctest":true,"test":true},"mode":"build","duration":0.75,"rmeta_time":0.25}
+{"reason":"build-finished","success":true}
+    Finished dev [unoptimized + debuginfo] target(s) in 7.50s"#;
+
+    #[test]
+    fn parses_timings_output() {
+        let mut collector = TimingsCollector::default();
+        for line in SAMPLE_OUTPUT.lines() {
+            collector.process_line(line);
+        }
+
+        assert_eq!(
+            collector.finish(),
+            Some(vec![
+                CompileTiming {
+                    name: "serde".into(),
+                    version: "1.0.117".into(),
+                    features: vec!["derive".into(), "std".into()],
+                    duration_secs: 4.0,
+                },
+                CompileTiming {
+                    name: "libc".into(),
+                    version: "0.2.80".into(),
+                    features: vec!["default".into(), "std".into()],
+                    duration_secs: 2.75,
+                },
+                CompileTiming {
+                    name: "workdir".into(),
+                    version: "0.1.0".into(),
+                    features: Vec::new(),
+                    duration_secs: 0.75,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn no_timings() {
+        let mut collector = TimingsCollector::default();
+        collector.process_line("error: could not compile `dummy`");
+        collector.process_line(r#"{"reason":"build-finished","success":false}"#);
+        assert_eq!(collector.finish(), None);
+    }
+
+    #[test]
+    fn test_parse_package_id() {
+        for (id, expected) in &[
+            (
+                "serde 1.0.117 (registry+https://github.com/rust-lang/crates.io-index)",
+                Some(("serde", "1.0.117")),
+            ),
+            (
+                "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.117",
+                Some(("serde", "1.0.117")),
+            ),
+            ("path+file:///tmp/dummy#0.1.0", Some(("dummy", "0.1.0"))),
+            ("", None),
+        ] {
+            let expected = expected.map(|(name, version)| (name.to_string(), version.to_string()));
+            assert_eq!(parse_package_id(id), expected, "{}", id);
+        }
+    }
+}
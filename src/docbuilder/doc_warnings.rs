@@ -0,0 +1,226 @@
+//! The warnings of rustdoc about the intra-doc links of a crate, parsed from its output
+
+/// The most warnings recorded for a release, as some crates have thousands of them.
+const MAX_WARNINGS: usize = 1000;
+
+/// The warnings recorded, by the start of their message, with the rustdoc lint they come from.
+const WARNING_TYPES: &[(&str, &str)] = &[
+    ("unresolved link to ", "broken_intra_doc_links"),
+    ("public documentation for ", "private_intra_doc_links"),
+];
+
+/// A warning of rustdoc about the documentation of a crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DocWarning {
+    /// The rustdoc lint the warning comes from, like `broken_intra_doc_links`
+    pub(crate) warning_type: String,
+    /// The path the link points to, like `crate::foo::Bar`
+    pub(crate) item_path: String,
+    pub(crate) message: String,
+    /// The file, line and column of the link, like `src/lib.rs:3:7`, if rustdoc knows it
+    pub(crate) location: Option<String>,
+}
+
+/// The warnings about intra-doc links collected from the output of rustdoc.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct DocWarnings {
+    /// The first `MAX_WARNINGS` warnings, in the order rustdoc reported them
+    pub(crate) warnings: Vec<DocWarning>,
+    /// How many warnings rustdoc reported, including the ones left out of `warnings`
+    pub(crate) total: usize,
+}
+
+/// Collects the warnings about intra-doc links from the output of rustdoc, one line at a time, so
+/// that it can run while the documentation is built.
+///
+/// The other warnings, like the ones of the compiler, are ignored. With `--cap-lints warn`, the
+/// links denied by the crate are reported as warnings too.
+#[derive(Debug, Default)]
+pub(crate) struct DocWarningParser {
+    warnings: DocWarnings,
+    /// Whether the next line may be the location of the last warning
+    expects_location: bool,
+}
+
+impl DocWarningParser {
+    pub(crate) fn push_line(&mut self, line: &str) {
+        let expects_location = std::mem::replace(&mut self.expects_location, false);
+        if let Some(message) = line.strip_prefix("warning: ") {
+            if let Some(warning) = parse_warning(message) {
+                self.warnings.total += 1;
+                if self.warnings.warnings.len() < MAX_WARNINGS {
+                    self.warnings.warnings.push(warning);
+                    self.expects_location = true;
+                }
+            }
+        } else if expects_location {
+            // The location directly follows the message, unless rustdoc doesn't know it, as
+            // for the documentation generated by macros
+            if let Some(location) = line.trim_start().strip_prefix("--> ") {
+                if let Some(warning) = self.warnings.warnings.last_mut() {
+                    warning.location = Some(location.trim().into());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> DocWarnings {
+        self.warnings
+    }
+}
+
+/// Parses the message of a warning, or returns `None` if it isn't about an intra-doc link.
+fn parse_warning(message: &str) -> Option<DocWarning> {
+    let message = message.trim();
+    let (_, warning_type) = WARNING_TYPES
+        .iter()
+        .find(|(prefix, _)| message.starts_with(prefix))?;
+    // The link is the last path quoted in the message, as in "public documentation for `foo`
+    // links to private item `Bar`"
+    let item_path = message.split('`').skip(1).step_by(2).last()?;
+
+    Some(DocWarning {
+        warning_type: (*warning_type).into(),
+        item_path: item_path.into(),
+        message: message.into(),
+        location: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output of building the documentation of `tests/crates/broken-links`.
+    const OUTPUT: &str = r#"   Documenting broken-links v0.1.0 (/opt/rustwide/workdir)
+warning: unresolved link to `Gadget`
+ --> src/lib.rs:3:66
+  |
+3 | //! Links to [`Vec`] and [`Widget`] resolve, unlike the link to [`Gadget`].
+  |                                                                  ^^^^^^^^ no item named `Gadget` in scope
+  |
+  = help: to escape `[` and `]` characters, add '\' before them like `\[` or `\]`
+  = note: `#[warn(rustdoc::broken_intra_doc_links)]` on by default
+
+warning: unresolved link to `crate::shop::sell`
+ --> src/lib.rs:5:55
+  |
+5 | /// A widget, built by [`Widget::build`] and sold by [`crate::shop::sell`].
+  |                                                       ^^^^^^^^^^^^^^^^^^^ no item named `shop` in module `broken_links`
+
+warning: unresolved link to `Widget::new`
+ --> src/lib.rs:9:32
+  |
+9 |     /// Builds a widget, like [`Widget::new`] would.
+  |                                ^^^^^^^^^^^^^ the struct `Widget` has no field or associated item named `new`
+
+warning: public documentation for `work` links to private item `Helper`
+  --> src/lib.rs:15:15
+   |
+15 | /// Uses the [`Helper`] to do its job.
+   |               ^^^^^^^^ this item is private
+   |
+   = note: this link will resolve properly if you pass `--document-private-items`
+   = note: `#[warn(rustdoc::private_intra_doc_links)]` on by default
+
+warning: `broken-links` (lib doc) generated 4 warnings
+    Finished dev [unoptimized + debuginfo] target(s) in 0.52s
+"#;
+
+    fn parse(output: &str) -> Vec<DocWarning> {
+        let mut parser = DocWarningParser::default();
+        for line in output.lines() {
+            parser.push_line(line);
+        }
+        parser.finish().warnings
+    }
+
+    fn warning(warning_type: &str, item_path: &str, message: &str, location: &str) -> DocWarning {
+        DocWarning {
+            warning_type: warning_type.into(),
+            item_path: item_path.into(),
+            message: message.into(),
+            location: Some(location.into()),
+        }
+    }
+
+    #[test]
+    fn broken_links() {
+        assert_eq!(
+            parse(OUTPUT),
+            vec![
+                warning(
+                    "broken_intra_doc_links",
+                    "Gadget",
+                    "unresolved link to `Gadget`",
+                    "src/lib.rs:3:66"
+                ),
+                warning(
+                    "broken_intra_doc_links",
+                    "crate::shop::sell",
+                    "unresolved link to `crate::shop::sell`",
+                    "src/lib.rs:5:55"
+                ),
+                warning(
+                    "broken_intra_doc_links",
+                    "Widget::new",
+                    "unresolved link to `Widget::new`",
+                    "src/lib.rs:9:32"
+                ),
+                warning(
+                    "private_intra_doc_links",
+                    "Helper",
+                    "public documentation for `work` links to private item `Helper`",
+                    "src/lib.rs:15:15"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_warnings_are_ignored() {
+        let output = "warning: unused variable: `x`
+ --> src/lib.rs:2:9
+  |
+2 |     let x = 1;
+  |         ^ help: if this is intentional, prefix it with an underscore: `_x`
+
+warning: this URL is not a hyperlink
+ --> src/lib.rs:1:5
+warning: 2 warnings emitted
+";
+        assert_eq!(parse(output), Vec::new());
+    }
+
+    #[test]
+    fn links_without_location() {
+        let output = "warning: unresolved link to `Missing`
+  |
+  = note: the link appears in this line:
+          see [`Missing`]
+";
+        assert_eq!(
+            parse(output),
+            vec![DocWarning {
+                warning_type: "broken_intra_doc_links".into(),
+                item_path: "Missing".into(),
+                message: "unresolved link to `Missing`".into(),
+                location: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn warnings_are_capped() {
+        let output = "warning: unresolved link to `Missing`\n --> src/lib.rs:1:5\n"
+            .repeat(MAX_WARNINGS + 10);
+        let mut parser = DocWarningParser::default();
+        for line in output.lines() {
+            parser.push_line(line);
+        }
+        let warnings = parser.finish();
+        assert_eq!(warnings.warnings.len(), MAX_WARNINGS);
+        // The warnings left out are still counted
+        assert_eq!(warnings.total, MAX_WARNINGS + 10);
+    }
+}
@@ -0,0 +1,303 @@
+//! Extraction of the `#[test]` functions written in the sources of a crate
+
+use crate::error::Result;
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::visit::{self, Visit};
+use walkdir::WalkDir;
+
+/// The most tests extracted from a release, as some crates generate thousands of them.
+const MAX_TESTS: usize = 1000;
+/// The largest source file parsed, as generated files can be huge and rarely contain tests.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// A test function of a crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InlineTest {
+    /// The path of the module containing the test, like `dummy::parser::tests`
+    pub(crate) module_path: String,
+    pub(crate) name: String,
+    /// The source of the test function, with its attributes and without its indentation
+    pub(crate) source_code: String,
+}
+
+/// The test functions extracted from a release.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct InlineTests {
+    pub(crate) tests: Vec<InlineTest>,
+    /// Whether the crate has more than `MAX_TESTS` tests, of which only the first ones were kept
+    pub(crate) truncated: bool,
+}
+
+/// Extracts the test functions of the library in the `src/` directory of `source_dir`.
+///
+/// The module of each file is guessed from its path, like `crate::foo::bar` for both
+/// `src/foo/bar.rs` and `src/foo/bar/mod.rs`, so the modules loaded with `#[path]` get the wrong
+/// path. The binaries and the files that aren't valid Rust are skipped.
+pub(crate) fn extract_inline_tests(source_dir: &Path, crate_name: &str) -> Result<InlineTests> {
+    let src = source_dir.join("src");
+    let mut files: Vec<PathBuf> = WalkDir::new(&src)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_type().is_file() && entry.path().extension() == Some("rs".as_ref())
+        })
+        .map(|entry| entry.into_path())
+        .collect();
+    files.sort();
+
+    let crate_name = crate_name.replace('-', "_");
+    let mut tests = Vec::new();
+    let mut truncated = false;
+    for file in files {
+        let relative = file.strip_prefix(&src)?;
+        let mut modules: Vec<String> = relative
+            .with_extension("")
+            .iter()
+            .map(|segment| segment.to_string_lossy().into_owned())
+            .collect();
+        match modules.first().map(String::as_str) {
+            Some("main") | Some("bin") => continue,
+            _ => {}
+        }
+        if matches!(
+            modules.last().map(String::as_str),
+            Some("lib") | Some("mod")
+        ) {
+            modules.pop();
+        }
+        modules.insert(0, crate_name.clone());
+
+        if fs::metadata(&file)?.len() > MAX_FILE_SIZE {
+            continue;
+        }
+        let source = fs::read_to_string(&file)?;
+        match tests_in_source(&source, &modules.join("::")) {
+            Some(file_tests) => tests.extend(file_tests),
+            None => debug!("skipping {}, which isn't valid Rust", file.display()),
+        }
+        if tests.len() > MAX_TESTS {
+            tests.truncate(MAX_TESTS);
+            truncated = true;
+            break;
+        }
+    }
+
+    Ok(InlineTests { tests, truncated })
+}
+
+/// Returns the test functions of the file with `source`, which is the module `module_path`, or
+/// `None` if it can't be parsed.
+fn tests_in_source(source: &str, module_path: &str) -> Option<Vec<InlineTest>> {
+    let file = syn::parse_file(source).ok()?;
+    let mut visitor = TestVisitor {
+        lines: source.lines().collect(),
+        modules: vec![module_path.to_string()],
+        tests: Vec::new(),
+    };
+    visitor.visit_file(&file);
+    Some(visitor.tests)
+}
+
+struct TestVisitor<'a> {
+    lines: Vec<&'a str>,
+    modules: Vec<String>,
+    tests: Vec<InlineTest>,
+}
+
+impl TestVisitor<'_> {
+    /// Returns the lines between the first and last ones of a function, without their common
+    /// indentation.
+    fn source_code(&self, first_line: usize, last_line: usize) -> String {
+        // The lines of spans start at 1
+        let lines = &self.lines[first_line - 1..last_line.min(self.lines.len())];
+        let indentation = lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        lines
+            .iter()
+            .map(|line| line.get(indentation..).unwrap_or("").trim_end())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl<'ast> Visit<'ast> for TestVisitor<'_> {
+    fn visit_item_mod(&mut self, module: &'ast syn::ItemMod) {
+        // The modules in other files are found by walking the directory
+        if module.content.is_some() {
+            self.modules.push(module.ident.to_string());
+            visit::visit_item_mod(self, module);
+            self.modules.pop();
+        }
+    }
+
+    fn visit_item_fn(&mut self, function: &'ast syn::ItemFn) {
+        // Both `#[test]` and the attributes of async runtimes like `#[tokio::test]`
+        let is_test = function.attrs.iter().any(|attr| {
+            attr.path
+                .segments
+                .last()
+                .map_or(false, |segment| segment.ident == "test")
+        });
+        if is_test {
+            let first_line = function
+                .attrs
+                .iter()
+                .map(|attr| attr.pound_token.spans[0].start().line)
+                .min()
+                .unwrap_or_else(|| function.sig.fn_token.span.start().line);
+            let last_line = function.block.brace_token.span.end().line;
+            self.tests.push(InlineTest {
+                module_path: self.modules.join("::"),
+                name: function.sig.ident.to_string(),
+                source_code: self.source_code(first_line, last_line),
+            });
+        }
+        visit::visit_item_fn(self, function);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIB: &str = r#"//! A crate
+
+pub mod parser;
+
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds() {
+        assert_eq!(add(1, 2), 3);
+    }
+
+    /// Negative numbers work too
+    #[test]
+    #[should_panic]
+    fn overflows() {
+        add(i32::MAX, 1);
+    }
+
+    fn helper() {}
+
+    mod nested {
+        #[tokio::test]
+        async fn runs_async() {}
+    }
+}
+"#;
+
+    const PARSER: &str = "pub fn parse() {}\n\n#[test]\nfn parses() {\n    parse();\n}\n";
+
+    fn names(tests: &[InlineTest]) -> Vec<(&str, &str)> {
+        tests
+            .iter()
+            .map(|test| (test.module_path.as_str(), test.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn tests_of_a_file() {
+        let tests = tests_in_source(LIB, "dummy").unwrap();
+        assert_eq!(
+            names(&tests),
+            vec![
+                ("dummy::tests", "adds"),
+                ("dummy::tests", "overflows"),
+                ("dummy::tests::nested", "runs_async"),
+            ]
+        );
+        assert_eq!(
+            tests[0].source_code,
+            "#[test]\nfn adds() {\n    assert_eq!(add(1, 2), 3);\n}"
+        );
+        // The doc comments are attributes too
+        assert_eq!(
+            tests[1].source_code,
+            "/// Negative numbers work too\n#[test]\n#[should_panic]\nfn overflows() {\n    \
+             add(i32::MAX, 1);\n}"
+        );
+        assert_eq!(
+            tests[2].source_code,
+            "#[tokio::test]\nasync fn runs_async() {}"
+        );
+    }
+
+    #[test]
+    fn files_without_tests() {
+        assert_eq!(
+            tests_in_source("pub fn untested() {}", "dummy"),
+            Some(vec![])
+        );
+        // `#[cfg(test)]` isn't a test
+        assert_eq!(
+            tests_in_source("#[cfg(test)]\nfn helper() {}", "dummy"),
+            Some(vec![])
+        );
+        assert_eq!(tests_in_source("fn broken( {", "dummy"), None);
+    }
+
+    #[test]
+    fn tests_of_a_crate() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path();
+        fs::create_dir_all(source.join("src/parser"))?;
+        fs::create_dir_all(source.join("src/bin"))?;
+        fs::write(source.join("src/lib.rs"), LIB)?;
+        fs::write(source.join("src/parser/mod.rs"), PARSER)?;
+        fs::write(source.join("src/parser/lexer.rs"), PARSER)?;
+        fs::write(source.join("src/bin/tool.rs"), PARSER)?;
+        fs::write(source.join("src/main.rs"), PARSER)?;
+        fs::write(source.join("src/invalid.rs"), "fn broken( {")?;
+
+        let tests = extract_inline_tests(source, "dummy-crate")?;
+        assert!(!tests.truncated);
+        assert_eq!(
+            names(&tests.tests),
+            vec![
+                ("dummy_crate::tests", "adds"),
+                ("dummy_crate::tests", "overflows"),
+                ("dummy_crate::tests::nested", "runs_async"),
+                ("dummy_crate::parser::lexer", "parses"),
+                ("dummy_crate::parser", "parses"),
+            ]
+        );
+
+        assert_eq!(
+            extract_inline_tests(&source.join("missing"), "dummy")?,
+            InlineTests::default()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tests_are_capped() -> Result<()> {
+        let test = "#[test]\nfn works() {}\n";
+        let dir = tempfile::tempdir()?;
+        let source = dir.path();
+        fs::create_dir_all(source.join("src"))?;
+
+        fs::write(source.join("src/lib.rs"), test.repeat(MAX_TESTS))?;
+        let tests = extract_inline_tests(source, "dummy")?;
+        assert_eq!((tests.tests.len(), tests.truncated), (MAX_TESTS, false));
+
+        fs::write(source.join("src/more.rs"), test)?;
+        let tests = extract_inline_tests(source, "dummy")?;
+        assert_eq!((tests.tests.len(), tests.truncated), (MAX_TESTS, true));
+
+        Ok(())
+    }
+}
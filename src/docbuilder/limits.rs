@@ -1,4 +1,4 @@
-use crate::error::Result;
+use crate::error::{DocsTooLarge, Result};
 use postgres::Client;
 use serde::Serialize;
 use std::time::Duration;
@@ -10,6 +10,7 @@ pub(crate) struct Limits {
     timeout: Duration,
     networking: bool,
     max_log_size: usize,
+    max_docs_size: u64,
 }
 
 impl Default for Limits {
@@ -19,7 +20,8 @@ impl Default for Limits {
             timeout: Duration::from_secs(15 * 60), // 15 minutes
             targets: 10,
             networking: false,
-            max_log_size: 100 * 1024, // 100 KB
+            max_log_size: 100 * 1024,              // 100 KB
+            max_docs_size: 5 * 1024 * 1024 * 1024, // 5 GB
         }
     }
 }
@@ -46,6 +48,9 @@ impl Limits {
             } else if timeout.is_some() {
                 limits.targets = 1;
             }
+            if let Some(max_docs_size) = row.get::<_, Option<i64>>("max_docs_size_bytes") {
+                limits.max_docs_size = max_docs_size as u64;
+            }
         }
 
         Ok(limits)
@@ -70,6 +75,50 @@ impl Limits {
     pub(crate) fn targets(&self) -> usize {
         self.targets
     }
+
+    /// Checks the total size of the generated documentation against the limit.
+    pub(crate) fn check_docs_size(&self, size: u64) -> std::result::Result<(), DocsTooLarge> {
+        if size > self.max_docs_size {
+            Err(DocsTooLarge {
+                size,
+                limit: self.max_docs_size,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A crate whose build limits are overridden, printed by `cratesfyi limits list`. The limits
+/// that aren't overridden are the default ones.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SandboxOverride {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub max_memory_bytes: Option<i64>,
+    pub timeout_seconds: Option<i32>,
+    pub max_targets: Option<i32>,
+    pub max_docs_size_bytes: Option<i64>,
+}
+
+/// Returns the crates whose limits are overridden, sorted by name.
+pub fn list_sandbox_overrides(conn: &mut Client) -> Result<Vec<SandboxOverride>> {
+    Ok(conn
+        .query(
+            "SELECT crate_name, max_memory_bytes, timeout_seconds, max_targets, max_docs_size_bytes
+             FROM sandbox_overrides
+             ORDER BY crate_name;",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| SandboxOverride {
+            krate: row.get("crate_name"),
+            max_memory_bytes: row.get("max_memory_bytes"),
+            timeout_seconds: row.get("timeout_seconds"),
+            max_targets: row.get("max_targets"),
+            max_docs_size_bytes: row.get("max_docs_size_bytes"),
+        })
+        .collect())
 }
 
 #[cfg(test)]
@@ -134,4 +183,59 @@ mod test {
             Ok(())
         });
     }
+
+    #[test]
+    fn docs_size_limit() {
+        wrapper(|env| {
+            let db = env.db();
+            let krate = "hexponent";
+            db.conn().query(
+                "INSERT INTO sandbox_overrides (crate_name, max_docs_size_bytes) VALUES ($1, 1024);",
+                &[&krate],
+            )?;
+            let limits = Limits::for_crate(&mut db.conn(), krate)?;
+            assert_eq!(limits.max_docs_size, 1024);
+
+            assert!(limits.check_docs_size(1024).is_ok());
+            let err = limits.check_docs_size(3 * 1024 * 1024 * 1024).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "documentation too large (3.00 GiB, limit 1.00 KiB)"
+            );
+            let err = limits.check_docs_size(1536 * 1024).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "documentation too large (1.50 MiB, limit 1.00 KiB)"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn list_overrides() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert!(list_sandbox_overrides(&mut conn)?.is_empty());
+
+            conn.execute(
+                "INSERT INTO sandbox_overrides (crate_name, timeout_seconds, max_targets)
+                 VALUES ('regex', 1200, 1), ('hexponent', NULL, 15)",
+                &[],
+            )?;
+            let overrides = list_sandbox_overrides(&mut conn)?;
+            assert_eq!(
+                overrides
+                    .iter()
+                    .map(|o| (o.krate.as_str(), o.timeout_seconds, o.max_targets))
+                    .collect::<Vec<_>>(),
+                vec![
+                    ("hexponent", None, Some(15)),
+                    ("regex", Some(1200), Some(1))
+                ]
+            );
+
+            Ok(())
+        });
+    }
 }
@@ -1,11 +1,30 @@
+pub(crate) mod audit;
+pub(crate) mod compile_timings;
 mod crates;
+pub(crate) mod doc_warnings;
+pub(crate) mod inline_tests;
 mod limits;
 mod queue;
+pub(crate) mod rustdoc_json;
 mod rustwide_builder;
+mod source_links;
+mod toolchain_cache;
+pub(crate) mod type_graph;
 
+pub(crate) use self::audit::AuditFinding;
+pub(crate) use self::compile_timings::CompileTiming;
+#[cfg(test)]
+pub(crate) use self::doc_warnings::DocWarning;
+pub(crate) use self::doc_warnings::DocWarnings;
+pub(crate) use self::inline_tests::InlineTests;
 pub(crate) use self::limits::Limits;
-pub(crate) use self::rustwide_builder::{BuildResult, DocCoverage};
+pub use self::limits::{list_sandbox_overrides, SandboxOverride};
+pub(crate) use self::rustdoc_json::{rustdoc_json_path, FunctionSignature};
+#[cfg(test)]
+pub(crate) use self::rustwide_builder::BuildStats;
+pub(crate) use self::rustwide_builder::{essential_file_names, BuildResult, DocCoverage};
 pub use self::rustwide_builder::{PackageKind, RustwideBuilder};
+pub(crate) use self::type_graph::TypeGraph;
 
 use crate::db::Pool;
 use crate::error::Result;
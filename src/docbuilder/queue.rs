@@ -2,14 +2,18 @@
 
 use super::{DocBuilder, PackageKind, RustwideBuilder};
 use crate::error::Result;
-use crate::utils::get_crate_priority;
+use crate::utils::{get_crate_priority, systemd};
 use crate::Index;
 use crates_index_diff::ChangeKind;
-use log::{debug, error};
+use log::{debug, error, info};
+use postgres::Client;
 
 impl DocBuilder {
     /// Updates registry index repository and adds new crates into build queue.
     /// Returns the number of crates added
+    ///
+    /// The last seen reference of the index is only updated once all the new crates are queued,
+    /// so if this fails the same changes are processed again on the next call.
     pub fn get_new_crates(&mut self, index: &Index) -> Result<usize> {
         let mut conn = self.db.get()?;
         let diff = index.diff()?;
@@ -19,6 +23,7 @@ impl DocBuilder {
         // I believe this will fix ordering of queue if we get more than one crate from changes
         changes.reverse();
 
+        let mut new_releases = Vec::new();
         for krate in &changes {
             match krate.kind {
                 ChangeKind::Yanked => {
@@ -42,24 +47,34 @@ impl DocBuilder {
                     }
                 }
 
-                ChangeKind::Added => {
-                    let priority = get_crate_priority(&mut conn, &krate.name)?;
-
-                    match self.build_queue.add_crate(
-                        &krate.name,
-                        &krate.version,
-                        priority,
-                        index.repository_url(),
-                    ) {
-                        Ok(()) => {
-                            debug!("{}-{} added into build queue", krate.name, krate.version);
-                            crates_added += 1;
-                        }
-                        Err(err) => error!(
-                            "failed adding {}-{} into build queue: {}",
-                            krate.name, krate.version, err
-                        ),
+                ChangeKind::Added => new_releases.push((&krate.name, &krate.version)),
+            }
+        }
+
+        if new_releases.len() > self.config.registry_catch_up_threshold {
+            crates_added = self.catch_up(&mut conn, index, new_releases)?;
+        } else {
+            for (name, version) in new_releases {
+                // The release might have been queued through the webhook already
+                if self.build_queue.is_queued_or_built(name, version)? {
+                    debug!("{}-{} already queued or built", name, version);
+                    continue;
+                }
+
+                let priority = get_crate_priority(&mut conn, name)?;
+
+                match self
+                    .build_queue
+                    .add_crate(name, version, priority, index.repository_url())
+                {
+                    Ok(()) => {
+                        debug!("{}-{} added into build queue", name, version);
+                        crates_added += 1;
                     }
+                    Err(err) => error!(
+                        "failed adding {}-{} into build queue: {}",
+                        name, version, err
+                    ),
                 }
             }
         }
@@ -69,6 +84,50 @@ impl DocBuilder {
         Ok(crates_added)
     }
 
+    /// Queues a large number of new releases, like the ones published while the registry watcher
+    /// wasn't running, in batches of `registry_catch_up_threshold` releases.
+    ///
+    /// The releases are sorted by name and version, so that retrying after a failure queues them
+    /// in the same order, skipping the batches that were already queued.
+    fn catch_up(
+        &self,
+        conn: &mut Client,
+        index: &Index,
+        mut new_releases: Vec<(&String, &String)>,
+    ) -> Result<usize> {
+        new_releases.sort();
+
+        let mut crates_added = 0;
+        let mut skipped = 0;
+        for batch in new_releases.chunks(self.config.registry_catch_up_threshold.max(1)) {
+            let mut queued = Vec::with_capacity(batch.len());
+            for (name, version) in batch {
+                if self.build_queue.is_queued_or_built(name, version)? {
+                    skipped += 1;
+                    continue;
+                }
+                queued.push((
+                    name.as_str(),
+                    version.as_str(),
+                    get_crate_priority(conn, name)?,
+                ));
+            }
+
+            crates_added += self
+                .build_queue
+                .add_crates(&queued, index.repository_url())?;
+        }
+
+        info!(
+            "caught up with the registry index: queued {} of {} new releases, {} already queued or built",
+            crates_added,
+            new_releases.len(),
+            skipped,
+        );
+
+        Ok(crates_added)
+    }
+
     /// Builds the top package from the queue. Returns whether there was a package in the queue.
     ///
     /// Note that this will return `Ok(true)` even if the package failed to build.
@@ -87,6 +146,7 @@ impl DocBuilder {
                 .map(|r| PackageKind::Registry(r.as_str()))
                 .unwrap_or(PackageKind::CratesIo);
 
+            systemd::notify_status(&format!("building {} {}", krate.name, krate.version));
             builder.build_package(&krate.name, &krate.version, kind)?;
             Ok(())
         })?;
@@ -94,3 +154,85 @@ impl DocBuilder {
         Ok(processed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{wrapper, FakeIndex, TestEnvironment};
+    use crate::DocBuilder;
+    use failure::Error;
+
+    fn setup(env: &TestEnvironment, catch_up_threshold: usize) -> Result<FakeIndex, Error> {
+        let index = FakeIndex::new()?;
+        env.override_config(|config| {
+            index.configure(config);
+            config.registry_catch_up_threshold = catch_up_threshold;
+        });
+
+        Ok(index)
+    }
+
+    fn get_new_crates(env: &TestEnvironment) -> Result<usize, Error> {
+        let mut builder = DocBuilder::new(env.config(), env.db().pool(), env.build_queue());
+        builder.get_new_crates(&env.index())
+    }
+
+    fn queued(env: &TestEnvironment) -> Result<Vec<(String, String)>, Error> {
+        Ok(env
+            .build_queue()
+            .queued_crates()?
+            .into_iter()
+            .map(|krate| (krate.name, krate.version))
+            .collect())
+    }
+
+    #[test]
+    fn test_get_new_crates() {
+        wrapper(|env| {
+            let index = setup(env, 10)?;
+            index.publish(&[("foo", "0.1.0"), ("bar", "1.0.0")])?;
+            env.build_queue().add_crate("bar", "1.0.0", 0, None)?;
+
+            assert_eq!(get_new_crates(env)?, 1);
+            assert!(env.build_queue().has_build_queued("foo", "0.1.0")?);
+            assert_eq!(get_new_crates(env)?, 0);
+
+            index.publish(&[("foo", "0.2.0")])?;
+            assert_eq!(get_new_crates(env)?, 1);
+            assert_eq!(env.build_queue().pending_count()?, 3);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_catch_up_after_large_gap() {
+        wrapper(|env| {
+            let index = setup(env, 10)?;
+            env.fake_release().name("built").version("0.1.0").create()?;
+            env.build_queue().add_crate("aaa", "0.1.0", 0, None)?;
+
+            // Releases published over multiple commits while the watcher wasn't running
+            let names = ["zeta", "alpha", "mid", "beta", "omega", "gamma", "delta"];
+            let mut expected = Vec::new();
+            for version in 0..5 {
+                let version = format!("0.{}.0", version);
+                let releases: Vec<_> = names.iter().map(|name| (*name, version.as_str())).collect();
+                index.publish(&releases)?;
+                expected.extend(names.iter().map(|name| (name.to_string(), version.clone())));
+            }
+            index.publish(&[("aaa", "0.1.0"), ("built", "0.1.0")])?;
+
+            assert_eq!(get_new_crates(env)?, 35);
+
+            expected.sort();
+            expected.insert(0, ("aaa".into(), "0.1.0".into()));
+            assert_eq!(queued(env)?, expected);
+
+            // The last seen reference was updated, so nothing is queued again
+            assert_eq!(get_new_crates(env)?, 0);
+            assert_eq!(env.build_queue().pending_count()?, 36);
+
+            Ok(())
+        });
+    }
+}
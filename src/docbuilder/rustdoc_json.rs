@@ -0,0 +1,438 @@
+//! The rustdoc JSON output of the builds, describing the public API of the crates
+
+use serde_json::Value;
+
+type FieldValidator = fn(&Value) -> bool;
+
+/// The top-level fields of the rustdoc JSON format, present in every version of it.
+const REQUIRED_FIELDS: &[(&str, FieldValidator)] = &[
+    ("root", Value::is_string),
+    ("crate_version", |value| {
+        value.is_string() || value.is_null()
+    }),
+    ("includes_private", Value::is_boolean),
+    ("index", Value::is_object),
+    ("paths", Value::is_object),
+    ("external_crates", Value::is_object),
+    ("format_version", Value::is_u64),
+];
+
+/// Returns the path in the storage of the rustdoc JSON output of a release.
+pub(crate) fn rustdoc_json_path(name: &str, version: &str) -> String {
+    format!("rustdoc-json/{}/{}/rustdoc.json", name, version)
+}
+
+/// Checks whether `content` follows the rustdoc JSON format, so that garbage generated by old or
+/// broken nightlies isn't served as such.
+pub(crate) fn is_rustdoc_json(content: &[u8]) -> bool {
+    let json: Value = match serde_json::from_slice(content) {
+        Ok(json) => json,
+        Err(_) => return false,
+    };
+
+    REQUIRED_FIELDS
+        .iter()
+        .all(|(field, is_valid)| matches!(json.get(field), Some(value) if is_valid(value)))
+}
+
+/// A public function of a crate, with the types of its parameters and of its return value in
+/// the form returned by [`normalize_type`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FunctionSignature {
+    pub(crate) path: String,
+    pub(crate) param_types: Vec<String>,
+    pub(crate) return_type: String,
+}
+
+/// Returns the signatures of the public functions defined by the crate documented by `content`,
+/// which must be valid rustdoc JSON.
+pub(crate) fn function_signatures(content: &[u8]) -> Vec<FunctionSignature> {
+    let json: Value = match serde_json::from_slice(content) {
+        Ok(json) => json,
+        Err(_) => return Vec::new(),
+    };
+    let index = match json["index"].as_object() {
+        Some(index) => index,
+        None => return Vec::new(),
+    };
+
+    let mut signatures: Vec<_> = index
+        .iter()
+        .filter(|(_, item)| item["crate_id"] == 0 && item["visibility"] == "public")
+        .filter_map(|(id, item)| {
+            let decl = match item_kind(item)? {
+                ("function", function) => function_decl(function)?,
+                _ => return None,
+            };
+            let path = json["paths"][id]["path"]
+                .as_array()?
+                .iter()
+                .map(|segment| segment.as_str())
+                .collect::<Option<Vec<_>>>()?
+                .join("::");
+
+            let param_types = decl["inputs"]
+                .as_array()?
+                .iter()
+                .map(|input| normalize_type(&render_type(&input[1])))
+                .collect();
+            let return_type = match &decl["output"] {
+                Value::Null => "()".into(),
+                output => normalize_type(&render_type(output)),
+            };
+            Some(FunctionSignature {
+                path,
+                param_types,
+                return_type,
+            })
+        })
+        .collect();
+    signatures.sort_by(|a, b| a.path.cmp(&b.path));
+    signatures
+}
+
+/// Returns the kind of an item of the index, like `function` or `struct`, with the fields specific
+/// to that kind.
+pub(crate) fn item_kind(item: &Value) -> Option<(&str, &Value)> {
+    match item["kind"].as_str() {
+        Some(kind) => Some((kind, &item["inner"])),
+        // Newer versions of the format tag the inner item with its kind instead
+        None => {
+            let inner = item["inner"].as_object()?;
+            if inner.len() != 1 {
+                return None;
+            }
+            inner
+                .iter()
+                .next()
+                .map(|(kind, inner)| (kind.as_str(), inner))
+        }
+    }
+}
+
+/// Returns the parameters and return type of a function or method, named `sig` by newer versions
+/// of the format.
+pub(crate) fn function_decl(function: &Value) -> Option<&Value> {
+    function.get("decl").or_else(|| function.get("sig"))
+}
+
+/// Renders a type of the rustdoc JSON format as Rust source. The types that can't be searched
+/// for, like trait objects, are rendered as `_`.
+fn render_type(ty: &Value) -> String {
+    let (kind, inner) = match ty.as_object() {
+        Some(ty) if ty.contains_key("kind") => (ty["kind"].as_str().unwrap_or(""), &ty["inner"]),
+        // Newer versions of the format tag the type with its kind instead
+        Some(ty) if ty.len() == 1 => {
+            let (kind, inner) = ty.iter().next().unwrap();
+            (kind.as_str(), inner)
+        }
+        _ => return "_".into(),
+    };
+    let render_all = |types: &Value| -> Vec<String> {
+        types
+            .as_array()
+            .map(|types| types.iter().map(render_type).collect())
+            .unwrap_or_default()
+    };
+
+    match kind {
+        "resolved_path" => {
+            let name = inner["name"]
+                .as_str()
+                .or_else(|| inner["path"].as_str())
+                .unwrap_or("_");
+            let args: Vec<_> = inner["args"]["angle_bracketed"]["args"]
+                .as_array()
+                .map(|args| {
+                    args.iter()
+                        .filter_map(|arg| arg.get("type"))
+                        .map(render_type)
+                        .collect()
+                })
+                .unwrap_or_default();
+            if args.is_empty() {
+                name.into()
+            } else {
+                format!("{}<{}>", name, args.join(", "))
+            }
+        }
+        "generic" | "primitive" => inner.as_str().unwrap_or("_").into(),
+        "borrowed_ref" => format!(
+            "&{}{}",
+            if inner["mutable"] == true { "mut " } else { "" },
+            render_type(&inner["type"])
+        ),
+        "raw_pointer" => format!(
+            "*{}{}",
+            if inner["mutable"] == true {
+                "mut "
+            } else {
+                "const "
+            },
+            render_type(&inner["type"])
+        ),
+        "tuple" => format!("({})", render_all(inner).join(", ")),
+        "slice" => format!("[{}]", render_type(inner)),
+        "array" => format!(
+            "[{}; {}]",
+            render_type(&inner["type"]),
+            inner["len"].as_str().unwrap_or("_")
+        ),
+        "impl_trait" => {
+            let bounds: Vec<_> = inner
+                .as_array()
+                .map(|bounds| {
+                    bounds
+                        .iter()
+                        .filter_map(|bound| bound["trait_bound"].get("trait"))
+                        .map(|tr| match tr.get("name").or_else(|| tr.get("path")) {
+                            // Newer versions of the format store the path of the trait directly
+                            Some(_) => render_type(&serde_json::json!({ "resolved_path": tr })),
+                            None => render_type(tr),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            format!("impl {}", bounds.join(" + "))
+        }
+        _ => "_".into(),
+    }
+}
+
+/// Normalizes the Rust type `ty`, so that the same type is always written the same way:
+///
+/// * paths are reduced to their last segment, like `String` for `std::string::String`,
+/// * lifetimes are removed,
+/// * type parameters, written as a single uppercase letter optionally followed by digits, are
+///   replaced by `_`, as any type can be used for them,
+/// * whitespace is only kept where it's needed.
+pub(crate) fn normalize_type(ty: &str) -> String {
+    let tokens = tokenize_type(ty);
+    let mut normalized = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).map(String::as_str);
+        match token.as_str() {
+            "::" => {}
+            // The leading segments of the paths
+            _ if next == Some("::") && token.chars().all(|c| c.is_alphanumeric() || c == '_') => {}
+            "," | ";" => {
+                normalized.push_str(token);
+                normalized.push(' ');
+            }
+            "+" | "->" => {
+                normalized.push(' ');
+                normalized.push_str(token);
+                normalized.push(' ');
+            }
+            "mut" | "const" | "dyn" | "impl" => {
+                normalized.push_str(token);
+                normalized.push(' ');
+            }
+            _ if is_type_parameter(token) && next != Some("<") => normalized.push('_'),
+            _ => normalized.push_str(token),
+        }
+    }
+    normalized.trim().to_string()
+}
+
+fn tokenize_type(ty: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut after_lifetime = false;
+    let mut chars = ty.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = if c.is_alphanumeric() || c == '_' || c == '\'' {
+            let mut token = c.to_string();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            token
+        } else if (c == ':' && chars.peek() == Some(&':'))
+            || (c == '-' && chars.peek() == Some(&'>'))
+        {
+            format!("{}{}", c, chars.next().unwrap())
+        } else if c.is_whitespace() {
+            continue;
+        } else {
+            c.to_string()
+        };
+
+        // Lifetimes are removed along with the comma separating them from the next argument
+        if token.starts_with('\'') {
+            after_lifetime = true;
+        } else if !(after_lifetime && token == ",") {
+            after_lifetime = false;
+            tokens.push(token);
+        } else {
+            after_lifetime = false;
+        }
+    }
+    tokens
+}
+
+fn is_type_parameter(token: &str) -> bool {
+    let mut chars = token.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_uppercase()) && chars.all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The rustdoc JSON output of an empty crate.
+    pub(crate) fn sample_rustdoc_json() -> Value {
+        json!({
+            "root": "0:0",
+            "crate_version": "0.1.0",
+            "includes_private": false,
+            "index": {
+                "0:0": {
+                    "id": "0:0",
+                    "crate_id": 0,
+                    "name": "dummy",
+                    "visibility": "public",
+                    "docs": "A dummy crate",
+                    "links": {},
+                    "attrs": [],
+                    "deprecation": null,
+                    "kind": "module",
+                    "inner": {"is_crate": true, "items": []},
+                },
+            },
+            "paths": {
+                "0:0": {"crate_id": 0, "path": ["dummy"], "kind": "module"},
+            },
+            "external_crates": {},
+            "format_version": 1,
+        })
+    }
+
+    #[test]
+    fn test_is_rustdoc_json() {
+        let sample = sample_rustdoc_json();
+        assert!(is_rustdoc_json(sample.to_string().as_bytes()));
+
+        let mut no_version = sample.clone();
+        no_version["crate_version"] = Value::Null;
+        assert!(is_rustdoc_json(no_version.to_string().as_bytes()));
+
+        for field in &["root", "index", "format_version"] {
+            let mut missing = sample.clone();
+            missing.as_object_mut().unwrap().remove(*field);
+            assert!(
+                !is_rustdoc_json(missing.to_string().as_bytes()),
+                "{}",
+                field
+            );
+        }
+        let mut wrong_type = sample;
+        wrong_type["format_version"] = json!("1");
+        assert!(!is_rustdoc_json(wrong_type.to_string().as_bytes()));
+
+        assert!(!is_rustdoc_json(b"{\"dummy\": {\"total\": 1}}"));
+        assert!(!is_rustdoc_json(b"not json"));
+    }
+
+    #[test]
+    fn test_normalize_type() {
+        for (ty, expected) in &[
+            ("String", "String"),
+            ("std::string::String", "String"),
+            ("Option < String >", "Option<String>"),
+            ("Vec<T>", "Vec<_>"),
+            ("HashMap<K, V2>", "HashMap<_, _>"),
+            ("&'a mut [u8]", "&mut [u8]"),
+            ("Cow<'static, str>", "Cow<str>"),
+            ("Result<(), ::std::io::Error>", "Result<(), Error>"),
+            ("Vec<::std::string::String>", "Vec<String>"),
+            ("[u8; 4]", "[u8; 4]"),
+            ("*const T", "*const _"),
+            ("impl Iterator<Item = T>", "impl Iterator<Item=_>"),
+            ("Box<dyn Fn(A) -> B + Send>", "Box<dyn Fn(_) -> _ + Send>"),
+            ("IO", "IO"),
+        ] {
+            assert_eq!(normalize_type(ty), *expected, "{}", ty);
+        }
+    }
+
+    #[test]
+    fn test_function_signatures() {
+        let resolved = |name: &str, args: Vec<Value>| {
+            json!({
+                "kind": "resolved_path",
+                "inner": {
+                    "name": name,
+                    "id": "0:1",
+                    "args": {"angle_bracketed": {
+                        "args": args.into_iter().map(|ty| json!({"type": ty})).collect::<Vec<_>>(),
+                        "bindings": [],
+                    }},
+                    "param_names": [],
+                },
+            })
+        };
+        let function = |inputs: Vec<Value>, output: Value| {
+            json!({
+                "crate_id": 0,
+                "visibility": "public",
+                "kind": "function",
+                "inner": {
+                    "decl": {
+                        "inputs": inputs.into_iter().enumerate().map(|(i, ty)| json!([format!("arg{}", i), ty])).collect::<Vec<_>>(),
+                        "output": output,
+                        "c_variadic": false,
+                    },
+                    "generics": {"params": [], "where_predicates": []},
+                    "header": [],
+                    "abi": "Rust",
+                },
+            })
+        };
+
+        let mut sample = sample_rustdoc_json();
+        sample["index"]["0:2"] = function(
+            vec![json!({"kind": "borrowed_ref", "inner": {
+                "lifetime": null,
+                "mutable": false,
+                "type": {"kind": "primitive", "inner": "str"},
+            }})],
+            resolved("Option", vec![resolved("String", vec![])]),
+        );
+        sample["index"]["0:3"] = function(
+            vec![
+                resolved("Vec", vec![json!({"kind": "generic", "inner": "T"})]),
+                json!({"kind": "primitive", "inner": "usize"}),
+            ],
+            Value::Null,
+        );
+        let mut private = function(vec![], Value::Null);
+        private["visibility"] = json!("crate");
+        sample["index"]["0:4"] = private;
+        sample["paths"]["0:2"] =
+            json!({"crate_id": 0, "path": ["dummy", "parse"], "kind": "function"});
+        sample["paths"]["0:3"] =
+            json!({"crate_id": 0, "path": ["dummy", "vec", "nth"], "kind": "function"});
+        sample["paths"]["0:4"] =
+            json!({"crate_id": 0, "path": ["dummy", "private"], "kind": "function"});
+
+        assert_eq!(
+            function_signatures(sample.to_string().as_bytes()),
+            vec![
+                FunctionSignature {
+                    path: "dummy::parse".into(),
+                    param_types: vec!["&str".into()],
+                    return_type: "Option<String>".into(),
+                },
+                FunctionSignature {
+                    path: "dummy::vec::nth".into(),
+                    param_types: vec!["Vec<_>".into(), "usize".into()],
+                    return_type: "()".into(),
+                },
+            ]
+        );
+    }
+}
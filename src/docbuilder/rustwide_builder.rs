@@ -1,27 +1,47 @@
-use crate::db::file::add_path_into_database;
+use crate::db::coverage_regression::record_coverage_regression;
+use crate::db::file::{
+    add_changed_path_into_database, delete_stale_paths, is_unchanged_since_last_build, CacheStats,
+};
 use crate::db::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_audit_results, add_build_into_database, add_compile_timings, add_doc_coverage,
+    add_doc_warnings, add_examples_into_database, add_function_signatures, add_inline_tests,
+    add_macro_expansions_into_database, add_package_into_database, add_type_graph, example_names,
     update_crate_data_in_database, Pool,
 };
-use crate::docbuilder::{crates::crates_from_path, Limits};
+use crate::docbuilder::{
+    audit::{latest_advisory_db_commit, parse_audit_report, releases_to_reaudit, AuditReport},
+    compile_timings::{CompileTiming, TimingsCollector},
+    crates::crates_from_path,
+    doc_warnings::{DocWarningParser, DocWarnings},
+    inline_tests::extract_inline_tests,
+    rustdoc_json::{function_signatures, is_rustdoc_json, rustdoc_json_path, FunctionSignature},
+    source_links::{rewrite_source_links, SourceRepository},
+    toolchain_cache::ToolchainCache,
+    type_graph::{type_graph, TypeGraph},
+    Limits,
+};
 use crate::error::Result;
 use crate::index::api::ReleaseData;
-use crate::storage::CompressionAlgorithms;
-use crate::utils::{copy_doc_dir, parse_rustc_version, CargoMetadata, GithubUpdater};
+use crate::notifications::queue_build_failure;
+use crate::storage::{CompressionAlgorithm, CompressionAlgorithms, PathNotFoundError};
+use crate::utils::{
+    copy_doc_dir, parse_rustc_version, queue_repository_refresh, CargoMetadata, GithubUpdater,
+};
 use crate::{db::blacklist::is_blacklisted, utils::MetadataPackage};
-use crate::{Config, Context, Index, Metrics, Storage};
-use docsrs_metadata::{Metadata, DEFAULT_TARGETS, HOST_TARGET};
-use failure::ResultExt;
+use crate::{BuildQueue, Cdn, Config, Context, Index, Metrics, Storage};
+use docsrs_metadata::{BuildTargets, Metadata, DEFAULT_TARGETS, HOST_TARGET};
 use log::{debug, info, warn, LevelFilter};
 use postgres::Client;
-use rustwide::cmd::{Command, SandboxBuilder, SandboxImage};
+use rustwide::cmd::{Command, ProcessLinesActions, SandboxBuilder, SandboxImage};
 use rustwide::logging::{self, LogStorage};
 use rustwide::toolchain::ToolchainError;
 use rustwide::{Build, Crate, Toolchain, Workspace, WorkspaceBuilder};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 const ESSENTIAL_FILES_VERSIONED: &[&str] = &[
@@ -53,52 +73,120 @@ const ESSENTIAL_FILES_UNVERSIONED: &[&str] = &[
     "SourceSerifPro-It.ttf.woff",
 ];
 
+/// Date of the latest nightly, updated when a new nightly is published.
+const NIGHTLY_DATE_URL: &str = "https://static.rust-lang.org/dist/channel-rust-nightly-date.txt";
+
+/// Directory of the rustwide workspace caching the essential files of the toolchain, shared by
+/// all the workers.
+const TOOLCHAIN_CACHE_DIR: &str = "docsrs-toolchain-cache";
+
+/// The most releases audited again against new security advisories at once.
+const REAUDIT_BATCH_SIZE: i64 = 20;
+
 const DUMMY_CRATE_NAME: &str = "empty-library";
 const DUMMY_CRATE_VERSION: &str = "1.0.0";
 
+/// Pre-release identifier added to the version of crates built from a local directory.
+const LOCAL_VERSION_SUFFIX: &str = "local";
+
 pub enum PackageKind<'a> {
     Local(&'a Path),
     CratesIo,
     Registry(&'a str),
 }
 
+/// A release built from a local crate directory with [`RustwideBuilder::build_local_package`].
+#[derive(Debug)]
+pub struct LocalRelease {
+    pub name: String,
+    /// The version the documentation is stored under, which isn't the one of the crate
+    pub version: String,
+    pub successful: bool,
+}
+
 pub struct RustwideBuilder {
     workspace: Workspace,
     toolchain: Toolchain,
     config: Arc<Config>,
     db: Pool,
+    build_queue: Arc<BuildQueue>,
     storage: Arc<Storage>,
     metrics: Arc<Metrics>,
     index: Arc<Index>,
+    cdn: Arc<Cdn>,
     rustc_version: String,
+    toolchain_cache: ToolchainCache,
     skip_build_if_exists: bool,
+    keep_build_dir: bool,
+    incremental: bool,
+    /// The latest commit of the advisory database, and when it was last checked
+    advisory_db_commit: Option<String>,
+    advisory_db_checked: Option<Instant>,
 }
 
 impl RustwideBuilder {
     pub fn init(context: &dyn Context) -> Result<Self> {
+        Self::init_worker(context, 0)
+    }
+
+    /// Initializes a builder for incremental builds, which skip the releases whose sources didn't
+    /// change since their last successful build.
+    ///
+    /// The build directories of the crates are kept between builds, so that cargo reuses the
+    /// dependencies compiled by the previous build of a release.
+    pub fn init_incremental(context: &dyn Context) -> Result<Self> {
+        Self::init_inner(context, 0, true)
+    }
+
+    /// Initializes the builder of one of the build workers of the daemon.
+    ///
+    /// Workers don't share their workspace, so they can build crates and update their toolchain
+    /// without interfering with each other. The first worker uses the configured workspace, and
+    /// the other ones a `worker-{n}` directory inside it.
+    pub fn init_worker(context: &dyn Context, worker: usize) -> Result<Self> {
+        Self::init_inner(context, worker, false)
+    }
+
+    fn init_inner(context: &dyn Context, worker: usize, incremental: bool) -> Result<Self> {
         let config = context.config()?;
 
-        let mut builder = WorkspaceBuilder::new(&config.rustwide_workspace, USER_AGENT)
+        let workspace_path = if worker == 0 {
+            config.rustwide_workspace.clone()
+        } else {
+            config.rustwide_workspace.join(format!("worker-{}", worker))
+        };
+        let mut builder = WorkspaceBuilder::new(&workspace_path, USER_AGENT)
             .running_inside_docker(config.inside_docker);
         if let Some(custom_image) = &config.local_docker_image {
             builder = builder.sandbox_image(SandboxImage::local(&custom_image)?);
         }
 
         let workspace = builder.init()?;
-        workspace.purge_all_build_dirs()?;
+        if !incremental {
+            workspace.purge_all_build_dirs()?;
+        }
 
         let toolchain = Toolchain::dist(&config.toolchain);
+        let toolchain_cache =
+            ToolchainCache::new(config.rustwide_workspace.join(TOOLCHAIN_CACHE_DIR));
 
         Ok(RustwideBuilder {
             workspace,
             toolchain,
             config,
             db: context.pool()?,
+            build_queue: context.build_queue()?,
             storage: context.storage()?,
             metrics: context.metrics()?,
             index: context.index()?,
+            cdn: context.cdn()?,
             rustc_version: String::new(),
+            toolchain_cache,
             skip_build_if_exists: false,
+            keep_build_dir: false,
+            incremental,
+            advisory_db_commit: None,
+            advisory_db_checked: None,
         })
     }
 
@@ -106,6 +194,11 @@ impl RustwideBuilder {
         self.skip_build_if_exists = should;
     }
 
+    /// Keeps the sources and build artifacts of the crates after building them, to inspect them.
+    pub fn set_keep_build_dir(&mut self, should: bool) {
+        self.keep_build_dir = should;
+    }
+
     fn prepare_sandbox(&self, limits: &Limits) -> SandboxBuilder {
         SandboxBuilder::new()
             .cpu_limit(self.config.build_cpu_limit.map(|limit| limit as f32))
@@ -117,12 +210,117 @@ impl RustwideBuilder {
         // Ignore errors if detection fails.
         let old_version = self.detect_rustc_version().ok();
 
+        self.install_toolchain(&self.toolchain)?;
+
+        self.rustc_version = self.detect_rustc_version()?;
+        if old_version.as_deref() != Some(&self.rustc_version) {
+            self.add_essential_files()?;
+
+            if let Some(old_version) = &old_version {
+                self.requeue_toolchain_failures(old_version);
+            }
+        }
+        if self.config.build_macro_expansions {
+            self.install_cargo_subcommand("cargo-expand");
+        }
+        if self.config.build_audit {
+            self.install_cargo_subcommand("cargo-audit");
+        }
+
+        Ok(())
+    }
+
+    /// Installs a cargo subcommand like `cargo-expand`, used by the optional steps of the builds.
+    /// Failing to install it only disables the steps using it, so the error is logged instead of
+    /// being returned.
+    fn install_cargo_subcommand(&self, name: &str) {
+        info!("installing {}...", name);
+        let res = Command::new(&self.workspace, self.toolchain.cargo())
+            .args(&["install", "--locked", name])
+            .run();
+        if let Err(err) = res {
+            warn!("failed to install {}: {}", name, err);
+        }
+    }
+
+    /// How often to check for a newer nightly, if the builder follows the nightly channel.
+    pub(crate) fn nightly_update_interval(&self) -> Option<Duration> {
+        if self.config.toolchain == "nightly" && self.config.nightly_update_interval > 0 {
+            Some(Duration::from_secs(self.config.nightly_update_interval))
+        } else {
+            None
+        }
+    }
+
+    /// Switches to the latest nightly if it's newer than the current toolchain, returning
+    /// whether the toolchain changed.
+    ///
+    /// The essential files of the new nightly are added before it's used for any build. If that
+    /// fails the previous toolchain keeps being used, so that the documentation never references
+    /// missing files.
+    pub fn update_nightly(&mut self) -> Result<bool> {
+        let name = format!("nightly-{}", latest_nightly_date()?);
+        if self.toolchain.as_dist().map(|dist| dist.name()) == Some(name.as_str()) {
+            return Ok(false);
+        }
+
+        info!("switching to {}", name);
+        let old_version = self.detect_rustc_version().ok();
+        let candidate = Toolchain::dist(&name);
+        self.install_toolchain(&candidate)?;
+
+        let old_toolchain = std::mem::replace(&mut self.toolchain, candidate);
+        let old_rustc_version = self.rustc_version.clone();
+        if let Err(err) = self.add_new_essential_files() {
+            let candidate = std::mem::replace(&mut self.toolchain, old_toolchain);
+            self.rustc_version = old_rustc_version;
+            if let Err(err) = candidate.uninstall(&self.workspace) {
+                warn!("failed to uninstall {}: {}", name, err);
+            }
+
+            return Err(err
+                .context(format!(
+                    "failed to add the essential files of {}, keeping the previous toolchain",
+                    name
+                ))
+                .into());
+        }
+
+        // Only the nightlies installed by previous updates are removed, not the channel itself
+        if old_toolchain.as_dist().map(|dist| dist.name()) != Some(self.config.toolchain.as_str()) {
+            if let Err(err) = old_toolchain.uninstall(&self.workspace) {
+                warn!("failed to uninstall the previous toolchain: {}", err);
+            }
+        }
+        if let Some(old_version) = old_version.filter(|old| *old != self.rustc_version) {
+            self.requeue_toolchain_failures(&old_version);
+        }
+
+        Ok(true)
+    }
+
+    /// Adds the essential files of the current toolchain, unless they're the ones that were
+    /// added last, like after restarting with the same nightly.
+    fn add_new_essential_files(&mut self) -> Result<()> {
+        let rustc_version = self.detect_rustc_version()?;
+        if recorded_rustc_version(&mut *self.db.get()?)?.as_deref() == Some(&rustc_version) {
+            self.rustc_version = rustc_version;
+            Ok(())
+        } else {
+            self.add_essential_files()
+        }
+    }
+
+    /// Installs the toolchain with the default and additional targets and rustfmt, removing the
+    /// other targets.
+    fn install_toolchain(&self, toolchain: &Toolchain) -> Result<()> {
         let mut targets_to_install = DEFAULT_TARGETS
             .iter()
             .map(|&t| t.to_string()) // &str has a specialized ToString impl, while &&str goes through Display
+            .chain(self.config.additional_targets.iter().cloned())
             .collect::<HashSet<_>>();
 
-        let installed_targets = match self.toolchain.installed_targets(&self.workspace) {
+        let installed_targets = match toolchain.installed_targets(&self.workspace) {
             Ok(targets) => targets,
             Err(err) => {
                 if let Some(&ToolchainError::NotInstalled) = err.downcast_ref::<ToolchainError>() {
@@ -147,32 +345,44 @@ impl RustwideBuilder {
         // and will not be reinstalled until explicitly requested by a crate.
         for target in installed_targets {
             if !targets_to_install.remove(&target) {
-                self.toolchain.remove_target(&self.workspace, &target)?;
+                toolchain.remove_target(&self.workspace, &target)?;
             }
         }
 
-        self.toolchain.install(&self.workspace)?;
+        toolchain.install(&self.workspace)?;
 
         for target in &targets_to_install {
-            self.toolchain.add_target(&self.workspace, target)?;
+            toolchain.add_target(&self.workspace, target)?;
         }
         // NOTE: rustup will automatically refuse to update the toolchain
         // if `rustfmt` is not available in the newer version
         // NOTE: this ignores the error so that you can still run a build without rustfmt.
         // This should only happen if you run a build for the first time when rustfmt isn't available.
-        if let Err(err) = self.toolchain.add_component(&self.workspace, "rustfmt") {
+        if let Err(err) = toolchain.add_component(&self.workspace, "rustfmt") {
             log::warn!("failed to install rustfmt: {}", err);
             log::info!("continuing anyway, since this must be the first build");
         }
 
-        self.rustc_version = self.detect_rustc_version()?;
-        if old_version.as_deref() != Some(&self.rustc_version) {
-            self.add_essential_files()?;
-        }
-
         Ok(())
     }
 
+    /// Queues again the recent builds that failed with the previous toolchain, as they might have
+    /// been caused by a broken nightly rather than by the crate itself.
+    fn requeue_toolchain_failures(&self, old_version: &str) {
+        match self.build_queue.requeue_toolchain_failures(
+            old_version,
+            &self.rustc_version,
+            self.config.toolchain_requeue_window,
+            self.config.toolchain_requeue_limit,
+        ) {
+            Ok(count) => info!("requeued {} builds that failed with {}", count, old_version),
+            Err(err) => warn!(
+                "failed to requeue builds that failed with {}: {}",
+                old_version, err
+            ),
+        }
+    }
+
     fn detect_rustc_version(&self) -> Result<String> {
         info!("detecting rustc's version...");
         let res = Command::new(&self.workspace, self.toolchain.rustc())
@@ -194,9 +404,29 @@ impl RustwideBuilder {
         self.rustc_version = self.detect_rustc_version()?;
         let rustc_version = parse_rustc_version(&self.rustc_version)?;
 
+        let mut conn = self.db.get()?;
+        if let Some(files_dir) = self.toolchain_cache.get(&self.rustc_version)? {
+            info!(
+                "using the cached essential files for {}",
+                self.rustc_version
+            );
+            let start = Instant::now();
+            upload_cached_essential_files(
+                &self.storage,
+                &mut conn,
+                &files_dir,
+                &self.rustc_version,
+            )?;
+            info!(
+                "uploaded the cached essential files in {:?} without building the dummy crate",
+                start.elapsed()
+            );
+            return self.purge_essential_files();
+        }
+
         info!("building a dummy crate to get essential files");
+        let start = Instant::now();
 
-        let mut conn = self.db.get()?;
         let limits = Limits::for_crate(&mut conn, DUMMY_CRATE_NAME)?;
 
         let mut build_dir = self
@@ -219,45 +449,28 @@ impl RustwideBuilder {
                 }
 
                 info!("copying essential files for {}", self.rustc_version);
-                let source = build.host_target_dir().join("doc");
-                let dest = tempfile::Builder::new()
-                    .prefix("essential-files")
-                    .tempdir()?;
-
-                let files = ESSENTIAL_FILES_VERSIONED
-                    .iter()
-                    .map(|f| (f, true))
-                    .chain(ESSENTIAL_FILES_UNVERSIONED.iter().map(|f| (f, false)));
-                for (&file, versioned) in files {
-                    let segments = file.rsplitn(2, '.').collect::<Vec<_>>();
-                    let file_name = if versioned {
-                        format!("{}-{}.{}", segments[1], rustc_version, segments[0])
-                    } else {
-                        file.to_string()
-                    };
-                    let source_path = source.join(&file_name);
-                    let dest_path = dest.path().join(&file_name);
-                    ::std::fs::copy(&source_path, &dest_path).with_context(|_| {
-                        format!(
-                            "couldn't copy '{}' to '{}'",
-                            source_path.display(),
-                            dest_path.display()
-                        )
-                    })?;
-                }
-
-                add_path_into_database(&self.storage, "", &dest)?;
-                conn.query(
-                    "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
-                     ON CONFLICT (name) DO UPDATE SET value = $1;",
-                    &[&Value::String(self.rustc_version.clone())],
-                )?;
-
-                Ok(())
+                upload_essential_files(
+                    &self.storage,
+                    &mut conn,
+                    &self.toolchain_cache,
+                    &build.host_target_dir().join("doc"),
+                    &self.rustc_version,
+                )
             })?;
 
         build_dir.purge()?;
         krate.purge_from_cache(&self.workspace)?;
+        info!("added the essential files in {:?}", start.elapsed());
+        self.purge_essential_files()
+    }
+
+    /// Invalidates the essential files cached by the CDN, as the unversioned ones are replaced.
+    fn purge_essential_files(&self) -> Result<()> {
+        let paths: Vec<String> = essential_file_names(&self.rustc_version)?
+            .iter()
+            .map(|file| format!("/{}", file))
+            .collect();
+        self.cdn.purge(&paths);
         Ok(())
     }
 
@@ -277,14 +490,26 @@ impl RustwideBuilder {
         )
     }
 
-    pub fn build_local_package(&mut self, path: &Path) -> Result<bool> {
+    /// Builds the documentation of the crate in a local directory, exactly like releases from
+    /// the registry.
+    ///
+    /// The release is stored under a pre-release of the version of the crate, so it never
+    /// replaces the documentation of a published release.
+    pub fn build_local_package(&mut self, path: &Path) -> Result<LocalRelease> {
         self.update_toolchain()?;
         let metadata =
             CargoMetadata::load(&self.workspace, &self.toolchain, path).map_err(|err| {
                 err.context(format!("failed to load local package {}", path.display()))
             })?;
         let package = metadata.root();
-        self.build_package(&package.name, &package.version, PackageKind::Local(path))
+        let version = local_version(&package.version)?;
+        let successful = self.build_package(&package.name, &version, PackageKind::Local(path))?;
+
+        Ok(LocalRelease {
+            name: package.name.clone(),
+            version,
+            successful,
+        })
     }
 
     pub fn build_package(
@@ -311,8 +536,11 @@ impl RustwideBuilder {
         let limits = Limits::for_crate(&mut conn, name)?;
 
         let mut build_dir = self.workspace.build_dir(&format!("{}-{}", name, version));
-        build_dir.purge()?;
+        if !self.incremental {
+            build_dir.purge()?;
+        }
 
+        let is_local = matches!(kind, PackageKind::Local(_));
         let krate = match kind {
             PackageKind::Local(path) => Crate::local(path),
             PackageKind::CratesIo => Crate::crates_io(name, version),
@@ -322,21 +550,63 @@ impl RustwideBuilder {
 
         let local_storage = tempfile::Builder::new().prefix("docsrs-docs").tempdir()?;
 
+        let build_start = Instant::now();
         let res = build_dir
             .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
             .run(|build| {
-                use docsrs_metadata::BuildTargets;
+                let sources_prefix = format!("sources/{}/{}", name, version);
+                if self.incremental
+                    && is_unchanged_since_last_build(
+                        &mut conn,
+                        name,
+                        version,
+                        &sources_prefix,
+                        build.host_source_dir(),
+                    )?
+                {
+                    info!(
+                        "skipping build of {} {}, its sources didn't change since its last build",
+                        name, version
+                    );
+                    self.metrics.skipped_incremental_builds.inc();
+                    return Ok(None);
+                }
 
                 let mut has_docs = false;
                 let mut successful_targets = Vec::new();
+                let mut failed_targets = Vec::new();
                 let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
+                let is_proc_macro =
+                    CargoMetadata::load(&self.workspace, &self.toolchain, &build.host_source_dir())?
+                        .root()
+                        .is_proc_macro();
                 let BuildTargets {
                     default_target,
                     other_targets,
-                } = metadata.targets(self.config.include_default_targets);
+                } = build_targets(
+                    &metadata,
+                    is_proc_macro,
+                    self.config.include_default_targets,
+                    &self.config.additional_targets,
+                );
+
+                if self.incremental {
+                    // Only the compiled dependencies are reused, as rustdoc doesn't remove the
+                    // pages of the items removed since the previous build
+                    let targets = other_targets.iter().chain(Some(&default_target));
+                    remove_docs(&build.host_target_dir(), targets.copied())?;
+                }
 
                 // Perform an initial build
-                let res = self.execute_build(default_target, true, &build, &limits, &metadata)?;
+                let mut res =
+                    self.execute_build(default_target, true, &build, &limits, &metadata)?;
+                if is_proc_macro {
+                    res.result.build_log.push_str(&format!(
+                        "\nproc-macro crates are compiled for the host, so the documentation is only \
+                         built for {}, whatever the requested targets are\n",
+                        HOST_TARGET
+                    ));
+                }
                 if res.result.successful {
                     if let Some(name) = res.cargo_metadata.root().library_name() {
                         let host_target = build.host_target_dir();
@@ -345,15 +615,43 @@ impl RustwideBuilder {
                 }
 
                 let mut algs = HashSet::new();
+                let mut compile_timings = None;
+                let mut macro_expansions = Vec::new();
+                let mut stale_paths = Vec::new();
+                let mut signatures = Vec::new();
+                let mut types = None;
                 if has_docs {
                     debug!("adding documentation for the default target to the database");
                     self.copy_docs(&build.host_target_dir(), local_storage.path(), "", true)?;
+                    if let Some((alg, functions, graph)) = self.upload_rustdoc_json(
+                        build,
+                        default_target,
+                        &limits,
+                        &metadata,
+                        name,
+                        version,
+                        &res.cargo_metadata,
+                    ) {
+                        algs.insert(alg);
+                        signatures = functions;
+                        types = Some(graph);
+                    }
+                    compile_timings =
+                        self.get_compile_timings(build, default_target, &limits, &metadata);
+                    if res.cargo_metadata.uses_proc_macros() {
+                        macro_expansions = self.expand_examples(build, &limits, &metadata);
+                    }
 
                     successful_targets.push(res.target.clone());
 
                     // Then build the documentation for all the targets
                     // Limit the number of targets so that no one can try to build all 200000 possible targets
+                    let mut docs_size = limits.check_docs_size(dir_size(local_storage.path())?);
                     for target in other_targets.into_iter().take(limits.targets()) {
+                        // Stop building more targets as soon as the limit is exceeded
+                        if docs_size.is_err() {
+                            break;
+                        }
                         debug!("building package {} {} for {}", name, version, target);
                         self.build_target(
                             target,
@@ -361,21 +659,58 @@ impl RustwideBuilder {
                             &limits,
                             &local_storage.path(),
                             &mut successful_targets,
+                            &mut failed_targets,
                             &metadata,
                         )?;
+                        docs_size = limits.check_docs_size(dir_size(local_storage.path())?);
+                    }
+
+                    match docs_size {
+                        Ok(()) => {
+                            self.link_source_files(
+                                res.cargo_metadata.root(),
+                                &build.host_source_dir(),
+                                local_storage.path(),
+                            );
+                            let (new_algs, stale_docs) =
+                                self.upload_docs(&mut conn, name, version, local_storage.path())?;
+                            algs.extend(new_algs);
+                            stale_paths.extend(stale_docs);
+                        }
+                        Err(err) => {
+                            // Nothing was uploaded yet, so the documentation is simply dropped
+                            // and the release is recorded as a failed build.
+                            warn!("failed to build {} {}: {}", name, version, err);
+                            has_docs = false;
+                            successful_targets.clear();
+                            failed_targets.clear();
+                            res.result.successful = false;
+                            res.result.build_log.push_str(&format!("\n{}\n", err));
+                        }
                     }
-                    let new_algs = self.upload_docs(name, version, local_storage.path())?;
-                    algs.extend(new_algs);
                 };
 
                 // Store the sources even if the build fails
                 debug!("adding sources into database");
-                let prefix = format!("sources/{}/{}", name, version);
-                let (files_list, new_algs) =
-                    add_path_into_database(&self.storage, &prefix, build.host_source_dir())?;
+                let (files_list, new_algs, cache_stats, stale_sources) =
+                    add_changed_path_into_database(
+                    &mut conn,
+                    &self.storage,
+                    name,
+                    version,
+                    &sources_prefix,
+                    build.host_source_dir(),
+                )?;
+                self.record_cache_stats(cache_stats);
                 algs.extend(new_algs);
+                stale_paths.extend(stale_sources);
 
                 let has_examples = build.host_source_dir().join("examples").is_dir();
+                let audit = if self.config.build_audit {
+                    self.audit_lockfile(&build.host_source_dir().join("Cargo.lock"))
+                } else {
+                    None
+                };
                 if res.result.successful {
                     self.metrics.successful_builds.inc();
                 } else if res.cargo_metadata.root().is_library() {
@@ -384,15 +719,24 @@ impl RustwideBuilder {
                     self.metrics.non_library_builds.inc();
                 }
 
-                let release_data = match self.index.api().get_release_data(name, version) {
-                    Ok(data) => data,
-                    Err(err) => {
-                        warn!("{:#?}", err);
-                        ReleaseData::default()
+                // Local crates aren't published, even if a crate with the same name is
+                let release_data = if is_local {
+                    ReleaseData::default()
+                } else {
+                    match self.index.api().get_release_data(name, version) {
+                        Ok(data) => data,
+                        Err(err) => {
+                            warn!("{:#?}", err);
+                            ReleaseData::default()
+                        }
                     }
                 };
 
-                let cargo_metadata = res.cargo_metadata.root();
+                // The release is recorded under the version it's built as, which differs from
+                // the version in `Cargo.toml` for local crates
+                let mut cargo_metadata = res.cargo_metadata.root().clone();
+                cargo_metadata.version = version.to_string();
+                let cargo_metadata = &cargo_metadata;
                 let github_repo = self.get_github_repo(&mut conn, cargo_metadata)?;
 
                 let release_id = add_package_into_database(
@@ -403,32 +747,143 @@ impl RustwideBuilder {
                     &res.target,
                     files_list,
                     successful_targets,
+                    failed_targets,
                     &release_data,
                     has_docs,
                     has_examples,
                     algs,
-                    github_repo,
+                    github_repo.clone(),
+                )?;
+                // The stats of the repository are refreshed by the daemon, so that the build
+                // doesn't wait for GitHub
+                if let (false, Some(github_repo)) = (is_local, &github_repo) {
+                    if let Err(err) = queue_repository_refresh(&mut conn, github_repo) {
+                        warn!("failed to queue the refresh of {}: {}", github_repo, err);
+                    }
+                }
+                add_examples_into_database(&mut conn, release_id, &build.host_source_dir())?;
+                // The tests are a bonus, so a release whose sources can't be read is still added
+                match extract_inline_tests(&build.host_source_dir(), name) {
+                    Ok(inline_tests) => add_inline_tests(&mut conn, release_id, &inline_tests)?,
+                    Err(err) => warn!(
+                        "failed to extract the tests of {} {}: {}",
+                        name, version, err
+                    ),
+                }
+                // The warnings of a previous build don't apply to a rebuild without docs
+                add_doc_warnings(
+                    &mut conn,
+                    release_id,
+                    if has_docs {
+                        Some(&res.doc_warnings)
+                    } else {
+                        None
+                    },
                 )?;
 
                 if let Some(doc_coverage) = res.result.doc_coverage {
                     add_doc_coverage(&mut conn, release_id, doc_coverage)?;
+                    if let Some(regression) = record_coverage_regression(&mut conn, release_id)? {
+                        info!(
+                            "documentation coverage of {} {} dropped by {:.1} points, from {:.1}% in {} to {:.1}%",
+                            name,
+                            version,
+                            regression.percent_drop(),
+                            regression.previous_percent,
+                            regression.previous_version,
+                            regression.new_percent,
+                        );
+                    }
+                }
+                if let Some(compile_timings) = &compile_timings {
+                    add_compile_timings(&mut conn, release_id, compile_timings)?;
+                }
+                if !macro_expansions.is_empty() {
+                    add_macro_expansions_into_database(&mut conn, release_id, &macro_expansions)?;
+                }
+                if !signatures.is_empty() {
+                    add_function_signatures(&mut conn, release_id, &signatures)?;
+                }
+                if let Some(types) = &types {
+                    add_type_graph(&mut conn, release_id, types)?;
+                }
+                if let Some(audit) = &audit {
+                    add_audit_results(
+                        &mut conn,
+                        release_id,
+                        &audit.findings,
+                        audit.advisory_db_commit.as_deref(),
+                    )?;
                 }
 
+                // Recorded even if the documentation was dropped, to know how much was generated
+                let (files, size) = dir_stats(local_storage.path())?;
+                res.result.stats = BuildStats {
+                    duration: build_start.elapsed(),
+                    peak_memory: None,
+                    files,
+                    size,
+                };
                 add_build_into_database(&mut conn, release_id, &res.result)?;
 
+                if !res.result.successful && !is_local && res.cargo_metadata.root().is_library() {
+                    // The build itself is recorded, so failing to notify its owners doesn't fail it
+                    match queue_build_failure(&mut conn, name, version) {
+                        Ok(0) => {}
+                        Ok(queued) => debug!("queued {} notifications of the failed build", queued),
+                        Err(err) => warn!(
+                            "failed to queue the notifications of the failed build of {} {}: {}",
+                            name, version, err
+                        ),
+                    }
+                }
+
                 // Some crates.io crate data is mutable, so we proactively update it during a release
-                match self.index.api().get_crate_data(name) {
-                    Ok(crate_data) => update_crate_data_in_database(&mut conn, name, &crate_data)?,
-                    Err(err) => warn!("{:#?}", err),
+                if !is_local {
+                    match self.index.api().get_crate_data(name) {
+                        Ok(crate_data) => {
+                            update_crate_data_in_database(&mut conn, name, &crate_data)?
+                        }
+                        Err(err) => warn!("{:#?}", err),
+                    }
+                }
+
+                // The files of the previous build missing from this one are only deleted once
+                // this build is recorded, so that the release never references missing files
+                if res.result.successful && !stale_paths.is_empty() {
+                    debug!("deleting {} stale files", stale_paths.len());
+                    if let Err(err) =
+                        delete_stale_paths(&mut conn, &self.storage, name, version, &stale_paths)
+                    {
+                        warn!(
+                            "failed to delete the stale files of {} {}: {}",
+                            name, version, err
+                        );
+                    }
                 }
 
-                Ok(res)
+                // The pages of rebuilt releases are cached by the CDN
+                self.cdn.purge_release(name, version);
+
+                if self.keep_build_dir {
+                    info!(
+                        "kept the build directory of {} {} at {}",
+                        name,
+                        version,
+                        build.host_source_dir().display()
+                    );
+                }
+
+                Ok(Some(res))
             })?;
 
-        build_dir.purge()?;
-        krate.purge_from_cache(&self.workspace)?;
+        if !self.keep_build_dir && !self.incremental {
+            build_dir.purge()?;
+            krate.purge_from_cache(&self.workspace)?;
+        }
         local_storage.close()?;
-        Ok(res.result.successful)
+        // The releases skipped by incremental builds were built successfully before
+        Ok(res.map(|res| res.result.successful).unwrap_or(true))
     }
 
     fn build_target(
@@ -438,6 +893,7 @@ impl RustwideBuilder {
         limits: &Limits,
         local_storage: &Path,
         successful_targets: &mut Vec<String>,
+        failed_targets: &mut Vec<String>,
         metadata: &Metadata,
     ) -> Result<()> {
         let target_res = self.execute_build(target, false, build, limits, metadata)?;
@@ -450,6 +906,8 @@ impl RustwideBuilder {
                 self.copy_docs(&build.host_target_dir(), local_storage, target, false)?;
                 successful_targets.push(target.to_string());
             }
+        } else {
+            failed_targets.push(target.to_string());
         }
         Ok(())
     }
@@ -530,9 +988,17 @@ impl RustwideBuilder {
         let mut storage = LogStorage::new(LevelFilter::Info);
         storage.set_max_size(limits.max_log_size());
 
+        let mut doc_warnings = DocWarningParser::default();
+        let mut parse_warnings =
+            |line: &str, _: &mut ProcessLinesActions| doc_warnings.push_line(line);
         let successful = logging::capture(&storage, || {
             self.prepare_command(build, target, metadata, limits, rustdoc_flags)
-                .and_then(|command| command.run().map_err(failure::Error::from))
+                .and_then(|command| {
+                    command
+                        .process_lines(&mut parse_warnings)
+                        .run()
+                        .map_err(failure::Error::from)
+                })
                 .is_ok()
         });
         let doc_coverage = if successful {
@@ -561,9 +1027,11 @@ impl RustwideBuilder {
                 docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
                 successful,
                 doc_coverage,
+                stats: BuildStats::default(),
             },
             cargo_metadata,
             target: target.to_string(),
+            doc_warnings: doc_warnings.finish(),
         })
     }
 
@@ -609,6 +1077,10 @@ impl RustwideBuilder {
             .cargo()
             .timeout(Some(limits.timeout()))
             .no_output_timeout(None);
+        if self.incremental {
+            // Even if the profile of the crate disables it
+            command = command.env("CARGO_INCREMENTAL", "1");
+        }
 
         for (key, val) in metadata.environment_variables() {
             command = command.env(key, val);
@@ -617,6 +1089,322 @@ impl RustwideBuilder {
         Ok(command.args(&cargo_args))
     }
 
+    /// Generates and uploads the rustdoc JSON output of the crate for `target`, returning the
+    /// compression algorithm used, the signatures of the public functions and the type graph if it
+    /// was uploaded.
+    ///
+    /// The JSON output is unstable, so failing to generate it doesn't fail the build.
+    #[allow(clippy::too_many_arguments)]
+    fn upload_rustdoc_json(
+        &self,
+        build: &Build,
+        target: &str,
+        limits: &Limits,
+        metadata: &Metadata,
+        name: &str,
+        version: &str,
+        cargo_metadata: &CargoMetadata,
+    ) -> Option<(CompressionAlgorithm, Vec<FunctionSignature>, TypeGraph)> {
+        if !self.config.build_rustdoc_json {
+            return None;
+        }
+        let library_name = cargo_metadata.root().library_name()?;
+
+        let rustdoc_flags = vec!["--output-format".to_string(), "json".to_string()];
+        let res = self
+            .prepare_command(build, target, metadata, limits, rustdoc_flags)
+            .and_then(|command| command.log_output(false).run().map_err(Into::into));
+        if let Err(err) = res {
+            warn!(
+                "failed to generate the rustdoc JSON of {} {}: {}",
+                name, version, err
+            );
+            return None;
+        }
+
+        let mut output = build.host_target_dir();
+        if target != HOST_TARGET {
+            output = output.join(target);
+        }
+        let output = output.join("doc").join(format!("{}.json", library_name));
+        let content = match std::fs::read(&output) {
+            Ok(content) => content,
+            Err(err) => {
+                warn!("failed to read {}: {}", output.display(), err);
+                return None;
+            }
+        };
+        if content.len() > self.config.max_file_size {
+            warn!(
+                "the rustdoc JSON of {} {} is too big to be served ({} bytes)",
+                name,
+                version,
+                content.len()
+            );
+            return None;
+        }
+        if !is_rustdoc_json(&content) {
+            warn!(
+                "the toolchain generated invalid rustdoc JSON for {} {}",
+                name, version
+            );
+            return None;
+        }
+
+        match self.storage.store_one(
+            &rustdoc_json_path(name, version),
+            "application/json",
+            &content,
+        ) {
+            // The signatures are indexed to search the functions by type
+            Ok(alg) => Some((alg, function_signatures(&content), type_graph(&content))),
+            Err(err) => {
+                warn!(
+                    "failed to upload the rustdoc JSON of {} {}: {}",
+                    name, version, err
+                );
+                None
+            }
+        }
+    }
+
+    /// Compiles the crate for `target` with `cargo build --timings`, returning how long each
+    /// package of its dependency graph took to compile.
+    ///
+    /// The timings are unstable, so failing to get them doesn't fail the build.
+    fn get_compile_timings(
+        &self,
+        build: &Build,
+        target: &str,
+        limits: &Limits,
+        metadata: &Metadata,
+    ) -> Option<Vec<CompileTiming>> {
+        if !self.config.build_compile_timings {
+            return None;
+        }
+
+        let mut cargo_args = vec![
+            "-Zunstable-options".to_string(),
+            "--timings=json".to_string(),
+            "--message-format=json".to_string(),
+        ];
+        if let Some(cpu_limit) = self.config.build_cpu_limit {
+            cargo_args.push(format!("-j{}", cpu_limit));
+        }
+        if target != HOST_TARGET {
+            cargo_args.push("--target".into());
+            cargo_args.push(target.into());
+        }
+        // Reuse the features and flags of the documentation build, but compile the crate
+        // instead of documenting it. The rustdoc arguments after `--` don't apply to builds.
+        let mut args = metadata.cargo_args(&cargo_args, &[]);
+        args[0] = "build".into();
+        if let Some(separator) = args.iter().position(|arg| arg == "--") {
+            args.truncate(separator);
+        }
+
+        let mut collector = TimingsCollector::default();
+        let mut command = build
+            .cargo()
+            .timeout(Some(limits.timeout()))
+            .no_output_timeout(None);
+        for (key, val) in metadata.environment_variables() {
+            command = command.env(key, val);
+        }
+        let res = command
+            .args(&args)
+            .process_lines(&mut |line, _| collector.process_line(line))
+            .log_output(false)
+            .run();
+        if let Err(err) = res {
+            warn!("failed to get the compile timings for {}: {}", target, err);
+            return None;
+        }
+
+        collector.finish()
+    }
+
+    /// Expands the macros of each example of the crate with `cargo expand`, returning the
+    /// formatted source of the examples by name.
+    ///
+    /// Examples failing to expand are skipped, as they don't affect the documentation. All the
+    /// examples share the build timeout, and the ones left once it's over aren't expanded.
+    fn expand_examples(
+        &self,
+        build: &Build,
+        limits: &Limits,
+        metadata: &Metadata,
+    ) -> Vec<(String, String)> {
+        if !self.config.build_macro_expansions {
+            return Vec::new();
+        }
+
+        let examples = match example_names(&build.host_source_dir()) {
+            Ok(examples) => examples,
+            Err(err) => {
+                warn!("failed to list the examples to expand: {}", err);
+                return Vec::new();
+            }
+        };
+        let deadline = Instant::now() + limits.timeout();
+        let mut expansions = Vec::new();
+        for example in examples {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                warn!(
+                    "timed out expanding the macros of the examples, skipping {}",
+                    example
+                );
+                break;
+            }
+            let mut command = build
+                .cargo()
+                .timeout(Some(remaining))
+                .no_output_timeout(None);
+            for (key, val) in metadata.environment_variables() {
+                command = command.env(key, val);
+            }
+            let res = command
+                .args(&["expand", "--example", &example, "--color", "never"])
+                .log_output(false)
+                .run_capture();
+            match res {
+                Ok(output) => expansions.push((example, output.stdout_lines().join("\n"))),
+                Err(err) => warn!(
+                    "failed to expand the macros of example {}: {}",
+                    example, err
+                ),
+            }
+        }
+
+        expansions
+    }
+
+    /// Audits the dependencies locked by `lockfile` with `cargo audit`, returning `None` if the
+    /// audit failed.
+    ///
+    /// The audit only reads the lockfile and the advisory database, so it runs on the host rather
+    /// than in the sandbox.
+    fn audit_lockfile(&self, lockfile: &Path) -> Option<AuditReport> {
+        if !lockfile.is_file() {
+            debug!("no lockfile to audit at {}", lockfile.display());
+            return None;
+        }
+
+        // `cargo audit` fails when vulnerabilities are found, so the report is collected even
+        // when the command fails
+        let mut report = None;
+        let res = Command::new(&self.workspace, self.toolchain.cargo())
+            .args(&["audit", "--json", "--file"])
+            .args(&[lockfile])
+            .process_lines(&mut |line, _| {
+                if line.starts_with('{') {
+                    report = Some(line.to_string());
+                }
+            })
+            .log_output(false)
+            .run();
+        let report = match (report, res) {
+            (Some(report), _) => report,
+            (None, Err(err)) => {
+                warn!("failed to audit {}: {}", lockfile.display(), err);
+                return None;
+            }
+            (None, Ok(())) => {
+                warn!(
+                    "cargo audit didn't print a report for {}",
+                    lockfile.display()
+                );
+                return None;
+            }
+        };
+
+        match parse_audit_report(&report) {
+            Ok(report) => Some(report),
+            Err(err) => {
+                warn!(
+                    "failed to parse the audit of {}: {}",
+                    lockfile.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Audits again the releases last audited against an older advisory database than the
+    /// latest one, up to `REAUDIT_BATCH_SIZE` at a time, returning how many were audited.
+    ///
+    /// The latest commit of the advisory database is checked every `audit_update_interval`, so
+    /// the releases are audited again shortly after new advisories are published. Their lockfile
+    /// is read from their stored sources.
+    pub fn reaudit_releases(&mut self) -> Result<usize> {
+        if !self.config.build_audit || self.config.audit_update_interval == 0 {
+            return Ok(0);
+        }
+
+        let interval = Duration::from_secs(self.config.audit_update_interval);
+        if !matches!(self.advisory_db_checked, Some(checked) if checked.elapsed() < interval) {
+            self.advisory_db_checked = Some(Instant::now());
+            self.advisory_db_commit = Some(latest_advisory_db_commit(USER_AGENT)?);
+        }
+        let commit = match &self.advisory_db_commit {
+            Some(commit) => commit.clone(),
+            None => return Ok(0),
+        };
+
+        let mut conn = self.db.get()?;
+        let releases = releases_to_reaudit(&mut conn, &commit, REAUDIT_BATCH_SIZE)?;
+        if releases.is_empty() {
+            return Ok(0);
+        }
+
+        info!(
+            "auditing {} releases against advisory database {}",
+            releases.len(),
+            commit
+        );
+        let dir = tempfile::Builder::new().prefix("docsrs-audit").tempdir()?;
+        let lockfile = dir.path().join("Cargo.lock");
+        for (release_id, name, version) in &releases {
+            let path = format!("sources/{}/{}/Cargo.lock", name, version);
+            let report = match self.storage.get(&path, self.config.max_file_size) {
+                Ok(blob) => {
+                    fs::write(&lockfile, &blob.content)?;
+                    self.audit_lockfile(&lockfile)
+                }
+                // Nothing to audit, the release is only recorded as audited
+                Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+                    Some(AuditReport {
+                        advisory_db_commit: Some(commit.clone()),
+                        findings: Vec::new(),
+                    })
+                }
+                Err(err) => return Err(err),
+            };
+
+            match report {
+                // The commit recorded is the one `cargo audit` used, which can be older than the
+                // latest one if its copy of the advisory database wasn't updated yet
+                Some(report) => add_audit_results(
+                    &mut conn,
+                    *release_id,
+                    &report.findings,
+                    report.advisory_db_commit.as_deref(),
+                )?,
+                None => {
+                    // Keep the previous results, but audit the other releases before retrying
+                    conn.execute(
+                        "UPDATE audit_runs SET audited_at = NOW() WHERE release_id = $1",
+                        &[release_id],
+                    )?;
+                }
+            }
+        }
+
+        Ok(releases.len())
+    }
+
     fn copy_docs(
         &self,
         target_dir: &Path,
@@ -640,19 +1428,53 @@ impl RustwideBuilder {
         copy_doc_dir(source, dest)
     }
 
+    /// Points the `[src]` links of the documentation to the files in the crate's repository, if
+    /// the commit the release was published from is known.
+    fn link_source_files(&self, metadata: &MetadataPackage, source_dir: &Path, doc_dir: &Path) {
+        let repository = match metadata
+            .repository
+            .as_deref()
+            .and_then(|url| SourceRepository::new(url, source_dir))
+        {
+            Some(repository) => repository,
+            None => {
+                debug!("did not link the sources to the repository as its commit is unknown");
+                return;
+            }
+        };
+
+        if let Err(err) = rewrite_source_links(doc_dir, &repository, self.config.max_parse_memory) {
+            warn!("failed to link the sources to the repository: {}", err);
+        }
+    }
+
     fn upload_docs(
         &self,
+        conn: &mut Client,
         name: &str,
         version: &str,
         local_storage: &Path,
-    ) -> Result<CompressionAlgorithms> {
+    ) -> Result<(CompressionAlgorithms, Vec<String>)> {
         debug!("Adding documentation into database");
-        add_path_into_database(
+        let (_, algs, cache_stats, stale_paths) = add_changed_path_into_database(
+            conn,
             &self.storage,
+            name,
+            version,
             &format!("rustdoc/{}/{}", name, version),
             local_storage,
-        )
-        .map(|t| t.1)
+        )?;
+        self.record_cache_stats(cache_stats);
+        Ok((algs, stale_paths))
+    }
+
+    fn record_cache_stats(&self, stats: CacheStats) {
+        debug!(
+            "{} files were unchanged, {} were uploaded",
+            stats.hits, stats.misses
+        );
+        self.metrics.build_cache_hits.inc_by(stats.hits as i64);
+        self.metrics.build_cache_misses.inc_by(stats.misses as i64);
     }
 
     fn should_build(&self, conn: &mut Client, name: &str, version: &str) -> Result<bool> {
@@ -701,10 +1523,169 @@ impl RustwideBuilder {
     }
 }
 
+/// Uploads the essential files generated by rustdoc in `doc_dir`, and records `rustc_version` as
+/// the version of the files the documentation should use. The files are cached for the next
+/// uploads of the same version.
+///
+/// Nothing is uploaded or recorded if any of the files is missing.
+fn upload_essential_files(
+    storage: &Storage,
+    conn: &mut Client,
+    cache: &ToolchainCache,
+    doc_dir: &Path,
+    rustc_version: &str,
+) -> Result<CacheStats> {
+    let files = essential_file_names(rustc_version)?;
+    let files_dir = cache.insert(rustc_version, doc_dir, &files)?;
+
+    upload_cached_essential_files(storage, conn, &files_dir, rustc_version)
+}
+
+/// Returns the names of the essential files generated by `rustc_version`, which are stored at
+/// the root of the storage.
+pub(crate) fn essential_file_names(rustc_version: &str) -> Result<Vec<String>> {
+    let parsed_version = parse_rustc_version(rustc_version)?;
+
+    Ok(ESSENTIAL_FILES_VERSIONED
+        .iter()
+        .map(|f| (f, true))
+        .chain(ESSENTIAL_FILES_UNVERSIONED.iter().map(|f| (f, false)))
+        .map(|(&file, versioned)| {
+            if versioned {
+                let segments = file.rsplitn(2, '.').collect::<Vec<_>>();
+                format!("{}-{}.{}", segments[1], parsed_version, segments[0])
+            } else {
+                file.to_string()
+            }
+        })
+        .collect())
+}
+
+/// Uploads the essential files of `rustc_version` cached in `files_dir`, skipping the ones that
+/// are already stored, and records it as the version the documentation should use, along with
+/// when it changed.
+fn upload_cached_essential_files(
+    storage: &Storage,
+    conn: &mut Client,
+    files_dir: &Path,
+    rustc_version: &str,
+) -> Result<CacheStats> {
+    // The essential files are shared by all the crates, and crate names can't be empty
+    let (_, _, stats, _) = add_changed_path_into_database(conn, storage, "", "", "", files_dir)?;
+    debug!(
+        "{} essential files were already stored, {} were uploaded",
+        stats.hits, stats.misses
+    );
+    if recorded_rustc_version(conn)?.as_deref() != Some(rustc_version) {
+        conn.execute(
+            "INSERT INTO config (name, value) VALUES ('toolchain_updated_at', to_json(NOW()::TIMESTAMP))
+             ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value;",
+            &[],
+        )?;
+    }
+    conn.query(
+        "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
+         ON CONFLICT (name) DO UPDATE SET value = $1;",
+        &[&Value::String(rustc_version.to_string())],
+    )?;
+
+    Ok(stats)
+}
+
+/// Returns the version of the essential files the documentation currently uses.
+fn recorded_rustc_version(conn: &mut Client) -> Result<Option<String>> {
+    Ok(conn
+        .query_opt(
+            "SELECT value FROM config WHERE name = 'rustc_version';",
+            &[],
+        )?
+        .and_then(|row| row.get::<_, Value>(0).as_str().map(String::from)))
+}
+
+/// Returns the date of the latest nightly published by the Rust project.
+fn latest_nightly_date() -> Result<String> {
+    let date = reqwest::blocking::Client::new()
+        .get(NIGHTLY_DATE_URL)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let date = date.trim();
+    if date.len() != 10 || !date.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        failure::bail!("invalid nightly date: {}", date);
+    }
+
+    Ok(date.to_string())
+}
+
+/// Returns the targets to build the documentation of a crate for.
+///
+/// Procedural macros are compiled for the host whatever the target is, so building them for other
+/// targets only generates empty documentation. They're always built for the host alone.
+fn build_targets<'a>(
+    metadata: &'a Metadata,
+    is_proc_macro: bool,
+    include_default_targets: bool,
+    additional_targets: &'a [String],
+) -> BuildTargets<'a> {
+    if is_proc_macro {
+        BuildTargets {
+            default_target: HOST_TARGET,
+            other_targets: HashSet::new(),
+        }
+    } else {
+        metadata.targets_with_additional(include_default_targets, additional_targets)
+    }
+}
+
+/// Computes the total size of all the files inside `path`.
+fn dir_size(path: &Path) -> Result<u64> {
+    Ok(dir_stats(path)?.1)
+}
+
+/// Counts the files inside `path`, and computes their total size.
+fn dir_stats(path: &Path) -> Result<(u64, u64)> {
+    let (mut files, mut size) = (0, 0);
+    for entry in walkdir::WalkDir::new(path) {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            files += 1;
+            size += metadata.len();
+        }
+    }
+
+    Ok((files, size))
+}
+
+/// Removes the documentation generated by a previous build in `target_dir`, for the host and for
+/// each of `targets`.
+fn remove_docs<'a>(target_dir: &Path, targets: impl Iterator<Item = &'a str>) -> Result<()> {
+    let dirs = std::iter::once(target_dir.join("doc"))
+        .chain(targets.map(|target| target_dir.join(target).join("doc")));
+    for dir in dirs {
+        if dir.is_dir() {
+            std::fs::remove_dir_all(dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the version a local crate is built as, `{version}-local`.
+fn local_version(version: &str) -> Result<String> {
+    let mut version = semver::Version::parse(version)?;
+    version.pre.push(semver::Identifier::AlphaNumeric(
+        LOCAL_VERSION_SUFFIX.into(),
+    ));
+
+    Ok(version.to_string())
+}
+
 struct FullBuildResult {
     result: BuildResult,
     target: String,
     cargo_metadata: CargoMetadata,
+    /// The warnings of rustdoc about the intra-doc links of the crate
+    doc_warnings: DocWarnings,
 }
 
 #[derive(Clone, Copy)]
@@ -721,10 +1702,422 @@ pub(crate) struct DocCoverage {
     pub(crate) items_with_examples: i32,
 }
 
+/// How long a build took and how much documentation it generated, for capacity planning.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct BuildStats {
+    /// Wall-clock duration of the whole build, including all the targets
+    pub(crate) duration: Duration,
+    /// The most memory used by the build, only known when its memory usage is watched
+    pub(crate) peak_memory: Option<u64>,
+    /// Number of files generated by rustdoc
+    pub(crate) files: u64,
+    /// Total size of the files generated by rustdoc, in bytes
+    pub(crate) size: u64,
+}
+
 pub(crate) struct BuildResult {
     pub(crate) rustc_version: String,
     pub(crate) docsrs_version: String,
     pub(crate) build_log: String,
     pub(crate) successful: bool,
     pub(crate) doc_coverage: Option<DocCoverage>,
+    pub(crate) stats: BuildStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{assert_success, wrapper};
+
+    const RUSTC_VERSION: &str = "rustc 1.50.0-nightly (1c389ffef 2020-11-24)";
+
+    /// Creates a directory with the essential files generated by rustdoc, except `skip`.
+    fn rustdoc_output(rustc_version: &str, skip: Option<&str>) -> Result<tempfile::TempDir> {
+        let dir = tempfile::tempdir()?;
+        let version = parse_rustc_version(rustc_version)?;
+        for file in ESSENTIAL_FILES_VERSIONED {
+            if Some(*file) != skip {
+                let segments = file.rsplitn(2, '.').collect::<Vec<_>>();
+                let name = format!("{}-{}.{}", segments[1], version, segments[0]);
+                std::fs::write(dir.path().join(name), file)?;
+            }
+        }
+        for file in ESSENTIAL_FILES_UNVERSIONED {
+            std::fs::write(dir.path().join(file), file)?;
+        }
+
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_upload_essential_files() {
+        wrapper(|env| {
+            let doc_dir = rustdoc_output(RUSTC_VERSION, None)?;
+            let cache_dir = tempfile::tempdir()?;
+            let cache = ToolchainCache::new(cache_dir.path().to_path_buf());
+            let mut conn = env.db().conn();
+            upload_essential_files(
+                &env.storage(),
+                &mut conn,
+                &cache,
+                doc_dir.path(),
+                RUSTC_VERSION,
+            )?;
+
+            let storage = env.storage();
+            assert!(storage.exists("rustdoc-20201124-1.50.0-nightly-1c389ffef.css")?);
+            assert!(storage.exists("main-20201124-1.50.0-nightly-1c389ffef.js")?);
+            assert!(storage.exists("FiraSans-Regular.woff")?);
+            assert_eq!(
+                recorded_rustc_version(&mut conn)?.as_deref(),
+                Some(RUSTC_VERSION)
+            );
+            assert!(cache.get(RUSTC_VERSION)?.is_some());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_upload_essential_files_missing_file() {
+        wrapper(|env| {
+            let cache_dir = tempfile::tempdir()?;
+            let cache = ToolchainCache::new(cache_dir.path().to_path_buf());
+            let mut conn = env.db().conn();
+            upload_essential_files(
+                &env.storage(),
+                &mut conn,
+                &cache,
+                rustdoc_output(RUSTC_VERSION, None)?.path(),
+                RUSTC_VERSION,
+            )?;
+
+            let new_version = "rustc 1.50.0-nightly (e37f25aa3 2020-11-26)";
+            let doc_dir = rustdoc_output(new_version, Some("main.js"))?;
+            let res = upload_essential_files(
+                &env.storage(),
+                &mut conn,
+                &cache,
+                doc_dir.path(),
+                new_version,
+            );
+            assert!(res.is_err());
+
+            // The files of the previous version are still the ones in use
+            let storage = env.storage();
+            assert!(!storage.exists("rustdoc-20201126-1.50.0-nightly-e37f25aa3.css")?);
+            assert!(!storage.exists("main-20201126-1.50.0-nightly-e37f25aa3.js")?);
+            assert!(storage.exists("rustdoc-20201124-1.50.0-nightly-1c389ffef.css")?);
+            assert_eq!(
+                recorded_rustc_version(&mut conn)?.as_deref(),
+                Some(RUSTC_VERSION)
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_upload_essential_files_skips_stored_files() {
+        wrapper(|env| {
+            let cache_dir = tempfile::tempdir()?;
+            let cache = ToolchainCache::new(cache_dir.path().to_path_buf());
+            let mut conn = env.db().conn();
+            let files_count = ESSENTIAL_FILES_VERSIONED.len() + ESSENTIAL_FILES_UNVERSIONED.len();
+
+            let doc_dir = rustdoc_output(RUSTC_VERSION, None)?;
+            let stats = upload_essential_files(
+                &env.storage(),
+                &mut conn,
+                &cache,
+                doc_dir.path(),
+                RUSTC_VERSION,
+            )?;
+            assert_eq!(stats.hits, 0);
+            assert_eq!(stats.misses, files_count);
+
+            // Uploading the cached files again doesn't need the rustdoc output
+            let files_dir = cache.get(RUSTC_VERSION)?.unwrap();
+            let stats = upload_cached_essential_files(
+                &env.storage(),
+                &mut conn,
+                &files_dir,
+                RUSTC_VERSION,
+            )?;
+            assert_eq!(stats.hits, files_count);
+            assert_eq!(stats.misses, 0);
+
+            // Only the versioned files change with the toolchain
+            let new_version = "rustc 1.50.0-nightly (e37f25aa3 2020-11-26)";
+            let doc_dir = rustdoc_output(new_version, None)?;
+            let stats = upload_essential_files(
+                &env.storage(),
+                &mut conn,
+                &cache,
+                doc_dir.path(),
+                new_version,
+            )?;
+            assert_eq!(stats.hits, ESSENTIAL_FILES_UNVERSIONED.len());
+            assert_eq!(stats.misses, ESSENTIAL_FILES_VERSIONED.len());
+            assert!(cache.get(RUSTC_VERSION)?.is_none());
+            assert!(env
+                .storage()
+                .exists("main-20201126-1.50.0-nightly-e37f25aa3.js")?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_local_version() -> Result<()> {
+        assert_eq!(local_version("0.1.0")?, "0.1.0-local");
+        assert_eq!(local_version("1.0.0-alpha.1")?, "1.0.0-alpha.1.local");
+        assert_eq!(local_version("1.0.0+build")?, "1.0.0-local+build");
+        assert!(local_version("not a version").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_targets_of_proc_macros() -> Result<()> {
+        let additional_targets = vec!["aarch64-unknown-linux-gnu".to_string()];
+
+        let plain: Metadata = "[package]\nname = \"plain\"".parse()?;
+        let targets = build_targets(&plain, false, true, &additional_targets);
+        assert_eq!(targets.default_target, HOST_TARGET);
+        assert!(targets.other_targets.contains("aarch64-unknown-linux-gnu"));
+        let targets = build_targets(&plain, true, true, &additional_targets);
+        assert_eq!(targets.default_target, HOST_TARGET);
+        assert!(targets.other_targets.is_empty());
+
+        let requested: Metadata = "[package]\nname = \"requested\"\n\
+                                   [package.metadata.docs.rs]\n\
+                                   default-target = \"x86_64-pc-windows-msvc\"\n\
+                                   targets = [\"i686-unknown-linux-gnu\"]"
+            .parse()?;
+        let targets = build_targets(&requested, false, true, &additional_targets);
+        assert_eq!(targets.default_target, "x86_64-pc-windows-msvc");
+        let targets = build_targets(&requested, true, true, &additional_targets);
+        assert_eq!(targets.default_target, HOST_TARGET);
+        assert!(targets.other_targets.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore] // needs docker and network access to build the crate
+    fn test_build_local_package() {
+        wrapper(|env| {
+            let mut builder = RustwideBuilder::init(env)?;
+            let release = builder.build_local_package(Path::new("tests/crates/local-crate"))?;
+            assert!(release.successful);
+            assert_eq!(release.name, "local-crate");
+            assert_eq!(release.version, "0.1.0-local");
+
+            let web = env.frontend();
+            assert_success("/local-crate/0.1.0-local/local_crate/", web)?;
+            // The features from `package.metadata.docs.rs` are enabled
+            assert_success("/local-crate/0.1.0-local/local_crate/fn.extra.html", web)?;
+            assert_eq!(web.get("/crate/local-crate/0.1.0").send()?.status(), 404);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[ignore] // needs docker and network access to build the crate
+    fn test_rebuild_deletes_stale_files() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            let src = dir.path().join("src");
+            std::fs::create_dir(&src)?;
+            std::fs::copy(
+                "tests/crates/local-crate/Cargo.toml",
+                dir.path().join("Cargo.toml"),
+            )?;
+            let lib = std::fs::read_to_string("tests/crates/local-crate/src/lib.rs")?;
+            std::fs::write(
+                src.join("lib.rs"),
+                format!("{}\npub mod removed {{}}\n", lib),
+            )?;
+
+            let mut builder = RustwideBuilder::init(env)?;
+            assert!(builder.build_local_package(dir.path())?.successful);
+            let web = env.frontend();
+            assert_success(
+                "/local-crate/0.1.0-local/local_crate/removed/index.html",
+                web,
+            )?;
+
+            // Rebuild the crate without the module
+            std::fs::write(src.join("lib.rs"), lib)?;
+            assert!(builder.build_local_package(dir.path())?.successful);
+
+            let storage = env.storage();
+            assert!(!storage
+                .exists("rustdoc/local-crate/0.1.0-local/local_crate/removed/index.html")?);
+            assert!(storage.exists("rustdoc/local-crate/0.1.0-local/local_crate/index.html")?);
+            assert_success("/local-crate/0.1.0-local/local_crate/fn.always.html", web)?;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[ignore] // needs docker and network access to build the crate
+    fn test_incremental_build_skips_unchanged_sources() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            let src = dir.path().join("src");
+            std::fs::create_dir(&src)?;
+            std::fs::copy(
+                "tests/crates/local-crate/Cargo.toml",
+                dir.path().join("Cargo.toml"),
+            )?;
+            std::fs::copy("tests/crates/local-crate/src/lib.rs", src.join("lib.rs"))?;
+
+            let mut builder = RustwideBuilder::init_incremental(env)?;
+            let builds = || -> Result<i64> {
+                Ok(env
+                    .db()
+                    .conn()
+                    .query_one(
+                        "SELECT COUNT(*)
+                         FROM builds
+                         INNER JOIN releases ON releases.id = builds.rid
+                         INNER JOIN crates ON crates.id = releases.crate_id
+                         WHERE crates.name = 'local-crate'",
+                        &[],
+                    )?
+                    .get(0))
+            };
+            assert!(builder.build_local_package(dir.path())?.successful);
+            assert!(builder.build_local_package(dir.path())?.successful);
+            assert_eq!(builds()?, 1);
+
+            std::fs::write(src.join("extra.rs"), "pub fn extra() {}\n")?;
+            assert!(builder.build_local_package(dir.path())?.successful);
+            assert_eq!(builds()?, 2);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[ignore] // needs docker and network access to build the crate
+    fn test_docs_too_large() {
+        wrapper(|env| {
+            env.db().conn().execute(
+                "INSERT INTO sandbox_overrides (crate_name, max_docs_size_bytes)
+                 VALUES ('local-crate', 1)",
+                &[],
+            )?;
+
+            let mut builder = RustwideBuilder::init(env)?;
+            let release = builder.build_local_package(Path::new("tests/crates/local-crate"))?;
+            assert!(!release.successful);
+
+            let mut conn = env.db().conn();
+            let row = conn.query_one(
+                "SELECT releases.rustdoc_status, builds.build_status, builds.output
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 INNER JOIN builds ON builds.rid = releases.id
+                 WHERE crates.name = 'local-crate'",
+                &[],
+            )?;
+            assert!(!row.get::<_, bool>(0));
+            assert!(!row.get::<_, bool>(1));
+            assert!(row.get::<_, String>(2).contains("documentation too large"));
+            // Nothing was uploaded
+            assert!(env
+                .storage()
+                .list_prefix("rustdoc/local-crate/0.1.0-local/")?
+                .is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[ignore] // needs docker and network access to build the crate
+    fn test_build_proc_macro() {
+        wrapper(|env| {
+            let mut builder = RustwideBuilder::init(env)?;
+            let release =
+                builder.build_local_package(Path::new("tests/crates/proc-macro-crate"))?;
+            assert!(release.successful);
+
+            // The requested targets are ignored for procedural macros
+            let mut conn = env.db().conn();
+            let row = conn.query_one(
+                "SELECT releases.default_target, releases.doc_targets, releases.is_proc_macro,
+                        builds.output
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 INNER JOIN builds ON builds.rid = releases.id
+                 WHERE crates.name = 'proc-macro-crate'",
+                &[],
+            )?;
+            assert_eq!(row.get::<_, String>(0), HOST_TARGET);
+            assert_eq!(row.get::<_, Value>(1), serde_json::json!([HOST_TARGET]));
+            assert!(row.get::<_, bool>(2));
+            assert!(row
+                .get::<_, String>(3)
+                .contains("proc-macro crates are compiled for the host"));
+
+            assert_success(
+                "/proc-macro-crate/0.1.0-local/proc_macro_crate/macro.identity.html",
+                env.frontend(),
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[ignore] // needs docker and network access to build the crate
+    fn test_record_broken_intra_doc_links() {
+        wrapper(|env| {
+            let mut builder = RustwideBuilder::init(env)?;
+            let release = builder.build_local_package(Path::new("tests/crates/broken-links"))?;
+            assert!(release.successful);
+
+            let mut conn = env.db().conn();
+            let warnings: Vec<(String, String)> = conn
+                .query(
+                    "SELECT doc_warnings.warning_type, doc_warnings.item_path
+                     FROM doc_warnings
+                     INNER JOIN releases ON releases.id = doc_warnings.release_id
+                     INNER JOIN crates ON crates.id = releases.crate_id
+                     WHERE crates.name = 'broken-links'
+                     ORDER BY doc_warnings.id",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(
+                warnings,
+                vec![
+                    ("broken_intra_doc_links".into(), "Gadget".into()),
+                    ("broken_intra_doc_links".into(), "crate::shop::sell".into()),
+                    ("broken_intra_doc_links".into(), "Widget::new".into()),
+                    ("private_intra_doc_links".into(), "Helper".into()),
+                ]
+            );
+            let count: Option<i32> = conn
+                .query_one(
+                    "SELECT releases.doc_warning_count
+                     FROM releases
+                     INNER JOIN crates ON crates.id = releases.crate_id
+                     WHERE crates.name = 'broken-links'",
+                    &[],
+                )?
+                .get(0);
+            assert_eq!(count, Some(4));
+
+            Ok(())
+        })
+    }
 }
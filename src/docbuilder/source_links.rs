@@ -0,0 +1,271 @@
+//! Point the `[src]` links of the documentation to the repository of the crate
+
+use crate::error::Result;
+use lol_html::{element, HtmlRewriter, MemorySettings, Settings};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::{fs, path::Path};
+use walkdir::WalkDir;
+
+/// The `.cargo_vcs_info.json` file cargo adds to crates published from a git repository.
+#[derive(Deserialize)]
+struct VcsInfo {
+    git: GitInfo,
+    /// Directory of the crate in the repository, only recorded by recent versions of cargo
+    #[serde(default)]
+    path_in_vcs: String,
+}
+
+#[derive(Deserialize)]
+struct GitInfo {
+    sha1: String,
+}
+
+/// Location of the sources of a release in its GitHub or GitLab repository.
+#[derive(Debug, PartialEq)]
+pub(super) struct SourceRepository {
+    /// URL of the crate's directory, at the commit the release was published from
+    base_url: String,
+}
+
+impl SourceRepository {
+    /// Returns where the sources of the release are in the repository, if it's hosted on GitHub
+    /// or GitLab and the commit the release was published from is known.
+    pub(super) fn new(repository_url: &str, source_dir: &Path) -> Option<Self> {
+        let vcs_info = fs::read(source_dir.join(".cargo_vcs_info.json")).ok()?;
+        let vcs_info: VcsInfo = serde_json::from_slice(&vcs_info).ok()?;
+
+        Self::from_commit(repository_url, &vcs_info.git.sha1, &vcs_info.path_in_vcs)
+    }
+
+    fn from_commit(repository_url: &str, sha: &str, path_in_vcs: &str) -> Option<Self> {
+        static RE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(
+                r"^https?://(www\.)?(?P<host>github\.com|gitlab\.com)/(?P<owner>[\w\._-]+)/(?P<repo>[\w\._-]+)",
+            )
+            .unwrap()
+        });
+
+        let cap = RE.captures(repository_url)?;
+        let host = &cap["host"];
+        let owner = &cap["owner"];
+        let repo = cap["repo"].trim_end_matches(".git");
+        if sha.is_empty() || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let mut base_url = match host {
+            "github.com" => format!("https://github.com/{}/{}/blob/{}", owner, repo, sha),
+            _ => format!("https://gitlab.com/{}/{}/-/blob/{}", owner, repo, sha),
+        };
+        let path_in_vcs = path_in_vcs.trim_matches('/');
+        if !path_in_vcs.is_empty() {
+            base_url.push('/');
+            base_url.push_str(path_in_vcs);
+        }
+
+        Some(Self { base_url })
+    }
+
+    /// Returns the repository URL of the file a `[src]` link generated by rustdoc points to.
+    ///
+    /// Rustdoc links to `src/{crate}/{file}.html#{lines}`, where `file` is relative to the
+    /// `src` directory of the crate. Files outside of it aren't linked to the repository.
+    fn file_url(&self, href: &str) -> Option<String> {
+        let (path, lines) = match href.find('#') {
+            Some(idx) => (&href[..idx], Some(&href[idx + 1..])),
+            None => (href, None),
+        };
+
+        let mut path = path;
+        while let Some(rest) = path.strip_prefix("../") {
+            path = rest;
+        }
+        let path = path.strip_prefix("src/")?;
+        let file = path[path.find('/')? + 1..].strip_suffix(".html")?;
+        if file.is_empty() || file.split('/').any(|part| part == "up" || part == "..") {
+            return None;
+        }
+
+        let mut url = format!("{}/src/{}", self.base_url, file);
+        if let Some(lines) = lines.filter(|lines| !lines.is_empty()) {
+            url.push_str("#L");
+            url.push_str(&lines.replace('-', "-L"));
+        }
+
+        Some(url)
+    }
+}
+
+/// Rewrites the `[src]` links of all the documentation pages in `doc_dir` to point to the files
+/// in the repository instead of the source pages generated by rustdoc.
+pub(super) fn rewrite_source_links(
+    doc_dir: &Path,
+    repository: &SourceRepository,
+    max_allowed_memory_usage: usize,
+) -> Result<()> {
+    for entry in WalkDir::new(doc_dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension() == Some("html".as_ref()) {
+            let html = fs::read(entry.path())?;
+            let rewritten = rewrite_page(&html, repository, max_allowed_memory_usage)?;
+            fs::write(entry.path(), rewritten)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn rewrite_page(
+    html: &[u8],
+    repository: &SourceRepository,
+    max_allowed_memory_usage: usize,
+) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut rewriter = HtmlRewriter::try_new(
+        Settings {
+            element_content_handlers: vec![element!("a.srclink[href]", |link| {
+                let href = link.get_attribute("href").unwrap_or_default();
+                if let Some(url) = repository.file_url(&href) {
+                    link.set_attribute("href", &url)?;
+                }
+
+                Ok(())
+            })],
+            memory_settings: MemorySettings {
+                max_allowed_memory_usage,
+                ..MemorySettings::default()
+            },
+            ..Settings::default()
+        },
+        |bytes: &[u8]| buffer.extend_from_slice(bytes),
+    )
+    .expect("utf8 is a valid encoding");
+
+    // rewriting errors aren't `Send`, so they can't be converted to `failure::Error`
+    rewriter
+        .write(html)
+        .and_then(|()| rewriter.end())
+        .map_err(|err| failure::err_msg(err.to_string()))?;
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "3a9b5e0c4f0d3be1c7a0e2d1f5e4c3b2a1908f7e";
+
+    fn github() -> SourceRepository {
+        SourceRepository::from_commit("https://github.com/rust-lang/docs.rs", SHA, "").unwrap()
+    }
+
+    #[test]
+    fn test_repository_urls() {
+        assert_eq!(
+            github().base_url,
+            format!("https://github.com/rust-lang/docs.rs/blob/{}", SHA)
+        );
+        assert_eq!(
+            SourceRepository::from_commit("https://gitlab.com/owner/repo.git", SHA, "")
+                .unwrap()
+                .base_url,
+            format!("https://gitlab.com/owner/repo/-/blob/{}", SHA)
+        );
+        assert_eq!(
+            SourceRepository::from_commit("https://github.com/owner/repo", SHA, "crates/foo/")
+                .unwrap()
+                .base_url,
+            format!("https://github.com/owner/repo/blob/{}/crates/foo", SHA)
+        );
+
+        assert_eq!(
+            SourceRepository::from_commit("https://example.com/owner/repo", SHA, ""),
+            None
+        );
+        assert_eq!(
+            SourceRepository::from_commit("https://github.com/owner/repo", "", ""),
+            None
+        );
+    }
+
+    #[test]
+    fn test_file_urls() {
+        let repository = github();
+        let base = format!("https://github.com/rust-lang/docs.rs/blob/{}", SHA);
+
+        assert_eq!(
+            repository.file_url("../src/docs_rs/lib.rs.html#1-20"),
+            Some(format!("{}/src/lib.rs#L1-L20", base))
+        );
+        assert_eq!(
+            repository.file_url("../../../src/docs_rs/web/page/mod.rs.html#42"),
+            Some(format!("{}/src/web/page/mod.rs#L42", base))
+        );
+        assert_eq!(
+            repository.file_url("../../src/docs_rs/utils/html.rs.html"),
+            Some(format!("{}/src/utils/html.rs", base))
+        );
+
+        // Files outside of the `src` directory, like generated code
+        assert_eq!(
+            repository.file_url("../src/docs_rs/up/build.rs.html#1"),
+            None
+        );
+        assert_eq!(repository.file_url("https://example.com/lib.rs"), None);
+        assert_eq!(repository.file_url("../src/docs_rs/lib.rs"), None);
+    }
+
+    #[test]
+    fn test_rewrite_source_links() -> Result<()> {
+        let doc_dir = tempfile::tempdir()?;
+        let page = doc_dir.path().join("docs_rs").join("web");
+        fs::create_dir_all(&page)?;
+        let html = r##"<html><body>
+            <a class="srclink" href="../../src/docs_rs/web/mod.rs.html#1-10" title="goto source code">[src]</a>
+            <a href="../../src/docs_rs/web/mod.rs.html#12">not a source link</a>
+        </body></html>"##;
+        fs::write(page.join("index.html"), html)?;
+        fs::write(
+            doc_dir.path().join("search-index.js"),
+            "var searchIndex = {};",
+        )?;
+
+        let repository = SourceRepository {
+            base_url: "https://github.com/rust-lang/docs.rs/blob/abc123".into(),
+        };
+        rewrite_source_links(doc_dir.path(), &repository, 5 * 1024 * 1024)?;
+
+        let rewritten = fs::read_to_string(page.join("index.html"))?;
+        assert!(rewritten.contains(
+            r#"<a class="srclink" href="https://github.com/rust-lang/docs.rs/blob/abc123/src/web/mod.rs#L1-L10""#
+        ));
+        assert!(rewritten.contains(r#"<a href="../../src/docs_rs/web/mod.rs.html#12">"#));
+        assert_eq!(
+            fs::read_to_string(doc_dir.path().join("search-index.js"))?,
+            "var searchIndex = {};"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vcs_info() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        let url = "https://github.com/rust-lang/docs.rs";
+        assert_eq!(SourceRepository::new(url, source_dir.path()), None);
+
+        fs::write(
+            source_dir.path().join(".cargo_vcs_info.json"),
+            format!(r#"{{"git": {{"sha1": "{}"}}}}"#, SHA),
+        )?;
+        assert_eq!(
+            SourceRepository::new(url, source_dir.path()),
+            Some(github())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Cache of the essential files generated by rustdoc, shared by the builds using the same
+//! toolchain.
+//!
+//! Getting the essential files of a toolchain requires building a dummy crate, which is slow. The
+//! files are cached on disk the first time they're generated, so that uploading them again (like
+//! when another worker switches to the toolchain) doesn't need another build. Only the files of the
+//! latest toolchain are cached.
+
+use crate::error::Result;
+use failure::ResultExt;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FILES_DIR: &str = "files";
+const MANIFEST: &str = "hashes.json";
+const TMP_PREFIX: &str = ".tmp-";
+
+pub(crate) struct ToolchainCache {
+    root: PathBuf,
+}
+
+impl ToolchainCache {
+    pub(crate) fn new(root: PathBuf) -> Self {
+        ToolchainCache { root }
+    }
+
+    /// Returns the directory containing the cached files of `rustc_version`, if they were cached
+    /// and none of them changed since.
+    pub(crate) fn get(&self, rustc_version: &str) -> Result<Option<PathBuf>> {
+        let entry = self.root.join(cache_key(rustc_version));
+        let manifest = match fs::read(entry.join(MANIFEST)) {
+            Ok(manifest) => manifest,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("no essential files cached for {}", rustc_version);
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let hashes: HashMap<String, String> = serde_json::from_slice(&manifest)?;
+
+        let files_dir = entry.join(FILES_DIR);
+        for (file, hash) in &hashes {
+            let valid = match fs::read(files_dir.join(file)) {
+                Ok(content) => hash_content(&content) == *hash,
+                Err(_) => false,
+            };
+            if !valid {
+                warn!(
+                    "the cached essential file {} of {} changed, ignoring the cache",
+                    file, rustc_version
+                );
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(files_dir))
+    }
+
+    /// Caches `files` from `source_dir` as the files of `rustc_version`, removing the files of the
+    /// other toolchains. Returns the directory containing the cached files.
+    pub(crate) fn insert(
+        &self,
+        rustc_version: &str,
+        source_dir: &Path,
+        files: &[String],
+    ) -> Result<PathBuf> {
+        let key = cache_key(rustc_version);
+        fs::create_dir_all(&self.root)?;
+
+        // The files are copied in a temporary directory first, so that a failed copy doesn't
+        // leave an incomplete entry behind.
+        let tmp = tempfile::Builder::new()
+            .prefix(TMP_PREFIX)
+            .tempdir_in(&self.root)?;
+        let files_dir = tmp.path().join(FILES_DIR);
+        fs::create_dir(&files_dir)?;
+
+        let mut hashes = HashMap::new();
+        for file in files {
+            let source_path = source_dir.join(file);
+            let content = fs::read(&source_path)
+                .with_context(|_| format!("couldn't copy '{}'", source_path.display()))?;
+            fs::write(files_dir.join(file), &content)?;
+            hashes.insert(file.clone(), hash_content(&content));
+        }
+        fs::write(tmp.path().join(MANIFEST), serde_json::to_vec(&hashes)?)?;
+
+        // Other workers might be inserting files at the same time, so their temporary
+        // directories are kept.
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let is_tmp = entry.file_name().to_string_lossy().starts_with(TMP_PREFIX);
+            if !is_tmp && entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        let entry = self.root.join(key);
+        if let Err(err) = fs::rename(tmp.path(), &entry) {
+            // Another worker cached the same files in the meantime
+            if !entry.join(MANIFEST).is_file() {
+                return Err(err.into());
+            }
+        }
+
+        Ok(entry.join(FILES_DIR))
+    }
+}
+
+fn cache_key(rustc_version: &str) -> String {
+    hash_content(rustc_version.as_bytes())
+}
+
+fn hash_content(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_VERSION: &str = "rustc 1.50.0-nightly (1c389ffef 2020-11-24)";
+    const NEW_VERSION: &str = "rustc 1.50.0-nightly (e37f25aa3 2020-11-26)";
+
+    fn doc_dir() -> Result<(tempfile::TempDir, Vec<String>)> {
+        let dir = tempfile::tempdir()?;
+        let files = vec!["main.js".to_string(), "rustdoc.css".to_string()];
+        for file in &files {
+            fs::write(dir.path().join(file), file)?;
+        }
+        fs::write(dir.path().join("index.html"), "not essential")?;
+
+        Ok((dir, files))
+    }
+
+    #[test]
+    fn test_cache_hit_and_miss() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = ToolchainCache::new(root.path().join("cache"));
+        let (doc_dir, files) = doc_dir().unwrap();
+
+        assert_eq!(cache.get(OLD_VERSION).unwrap(), None);
+
+        let cached = cache.insert(OLD_VERSION, doc_dir.path(), &files).unwrap();
+        assert_eq!(cache.get(OLD_VERSION).unwrap(), Some(cached.clone()));
+        assert_eq!(
+            fs::read_to_string(cached.join("main.js")).unwrap(),
+            "main.js"
+        );
+        assert!(!cached.join("index.html").exists());
+
+        assert_eq!(cache.get(NEW_VERSION).unwrap(), None);
+    }
+
+    #[test]
+    fn test_cache_invalidated_on_toolchain_change() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = ToolchainCache::new(root.path().join("cache"));
+        let (doc_dir, files) = doc_dir().unwrap();
+
+        let old = cache.insert(OLD_VERSION, doc_dir.path(), &files).unwrap();
+        let new = cache.insert(NEW_VERSION, doc_dir.path(), &files).unwrap();
+        assert_eq!(cache.get(OLD_VERSION).unwrap(), None);
+        assert!(!old.exists());
+        assert_eq!(cache.get(NEW_VERSION).unwrap(), Some(new));
+    }
+
+    #[test]
+    fn test_cache_invalidated_on_changed_file() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = ToolchainCache::new(root.path().join("cache"));
+        let (doc_dir, files) = doc_dir().unwrap();
+
+        let cached = cache.insert(OLD_VERSION, doc_dir.path(), &files).unwrap();
+        fs::write(cached.join("main.js"), "corrupted").unwrap();
+        assert_eq!(cache.get(OLD_VERSION).unwrap(), None);
+
+        let cached = cache.insert(OLD_VERSION, doc_dir.path(), &files).unwrap();
+        fs::remove_file(cached.join("rustdoc.css")).unwrap();
+        assert_eq!(cache.get(OLD_VERSION).unwrap(), None);
+    }
+
+    #[test]
+    fn test_failed_insert_keeps_the_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = ToolchainCache::new(root.path().join("cache"));
+        let (doc_dir, mut files) = doc_dir().unwrap();
+
+        let cached = cache.insert(OLD_VERSION, doc_dir.path(), &files).unwrap();
+        files.push("missing.js".into());
+        assert!(cache.insert(NEW_VERSION, doc_dir.path(), &files).is_err());
+        assert_eq!(cache.get(OLD_VERSION).unwrap(), Some(cached));
+        assert_eq!(cache.get(NEW_VERSION).unwrap(), None);
+    }
+}
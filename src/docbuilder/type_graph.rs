@@ -0,0 +1,699 @@
+//! The relationships between the public types of a crate, extracted from its rustdoc JSON output
+
+use crate::docbuilder::rustdoc_json::{function_decl, item_kind, normalize_type};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+/// The keys under which structs, enums and their variants list their fields and variants, in the
+/// different versions of the format.
+const MEMBER_KEYS: &[&str] = &[
+    "fields",
+    "variants",
+    "kind",
+    "plain",
+    "tuple",
+    "struct",
+    "variant_inner",
+];
+
+/// A public struct or enum of a crate, with its edges to the other types and functions.
+///
+/// Types and functions are referred to by their path, like `dummy::Foo`, while the traits are
+/// only named.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TypeNode {
+    /// `struct` or `enum`
+    pub(crate) kind: String,
+    /// The traits implemented by the type, apart from the auto traits and blanket implementations
+    pub(crate) implements: Vec<String>,
+    /// The public types of the crate used in the fields or variants of the type
+    pub(crate) contains: Vec<String>,
+    /// The functions and methods returning the type
+    pub(crate) produced_by: Vec<String>,
+    /// The functions and methods taking the type as parameter
+    pub(crate) consumed_by: Vec<String>,
+}
+
+/// The adjacency list of the public types of a crate, stored as JSONB with the release.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct TypeGraph {
+    pub(crate) nodes: BTreeMap<String, TypeNode>,
+}
+
+impl TypeGraph {
+    /// Returns the path of the type named `name`, either its full path or its name if it's
+    /// unambiguous.
+    pub(crate) fn resolve(&self, name: &str) -> Option<&str> {
+        if let Some((path, _)) = self.nodes.get_key_value(name) {
+            return Some(path);
+        }
+        let suffix = format!("::{}", name);
+        let mut matches = self.nodes.keys().filter(|path| path.ends_with(&suffix));
+        match (matches.next(), matches.next()) {
+            (Some(path), None) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the types at most `depth` edges away from `root` in either direction of the
+    /// `contains` edges, which only keep the edges between those types.
+    pub(crate) fn subgraph(&self, root: &str, depth: usize) -> TypeGraph {
+        let mut contained_by: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (path, node) in &self.nodes {
+            for contained in &node.contains {
+                contained_by
+                    .entry(contained.as_str())
+                    .or_default()
+                    .push(path.as_str());
+            }
+        }
+
+        let mut distances: HashMap<&str, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        if self.nodes.contains_key(root) {
+            distances.insert(root, 0);
+            queue.push_back(root);
+        }
+        while let Some(path) = queue.pop_front() {
+            let distance = distances[path];
+            if distance == depth {
+                continue;
+            }
+            let neighbours = self.nodes[path]
+                .contains
+                .iter()
+                .map(String::as_str)
+                .chain(contained_by.get(path).into_iter().flatten().copied());
+            for neighbour in neighbours {
+                // Types already reached, including the ones of cycles, aren't visited again
+                if self.nodes.contains_key(neighbour) && !distances.contains_key(neighbour) {
+                    distances.insert(neighbour, distance + 1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        let nodes = distances
+            .keys()
+            .map(|path| {
+                let mut node = self.nodes[*path].clone();
+                node.contains
+                    .retain(|contained| distances.contains_key(contained.as_str()));
+                (path.to_string(), node)
+            })
+            .collect();
+        TypeGraph { nodes }
+    }
+
+    /// Returns the groups of types containing each other, directly or through other types, like
+    /// the nodes of a linked list. Every group is sorted, as well as the groups themselves.
+    pub(crate) fn cycles(&self) -> Vec<Vec<String>> {
+        let paths: Vec<&String> = self.nodes.keys().collect();
+        let indices: HashMap<&str, usize> = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (path.as_str(), i))
+            .collect();
+        let edges: Vec<Vec<usize>> = self
+            .nodes
+            .values()
+            .map(|node| {
+                node.contains
+                    .iter()
+                    .filter_map(|contained| indices.get(contained.as_str()).copied())
+                    .collect()
+            })
+            .collect();
+
+        // Tarjan's algorithm for strongly connected components, without recursion as the graphs
+        // of big crates would overflow the stack
+        let mut index = vec![None; paths.len()];
+        let mut lowlink = vec![0; paths.len()];
+        let mut on_stack = vec![false; paths.len()];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut cycles = Vec::new();
+        for start in 0..paths.len() {
+            if index[start].is_some() {
+                continue;
+            }
+            // The type being visited, with the position of the next edge to follow
+            let mut work = vec![(start, 0)];
+            while let Some((node, mut edge)) = work.pop() {
+                if edge == 0 {
+                    index[node] = Some(next_index);
+                    lowlink[node] = next_index;
+                    next_index += 1;
+                    stack.push(node);
+                    on_stack[node] = true;
+                } else {
+                    let visited = edges[node][edge - 1];
+                    lowlink[node] = lowlink[node].min(lowlink[visited]);
+                }
+
+                let mut descended = false;
+                while edge < edges[node].len() {
+                    let next = edges[node][edge];
+                    edge += 1;
+                    match index[next] {
+                        None => {
+                            work.push((node, edge));
+                            work.push((next, 0));
+                            descended = true;
+                            break;
+                        }
+                        Some(reached) if on_stack[next] => {
+                            lowlink[node] = lowlink[node].min(reached);
+                        }
+                        Some(_) => {}
+                    }
+                }
+                if descended || Some(lowlink[node]) != index[node] {
+                    continue;
+                }
+
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                if component.len() > 1 || edges[node].contains(&node) {
+                    let mut cycle: Vec<_> =
+                        component.iter().map(|&i| paths[i].to_string()).collect();
+                    cycle.sort();
+                    cycles.push(cycle);
+                }
+            }
+        }
+        cycles.sort();
+        cycles
+    }
+}
+
+/// Returns the id of an item referenced in the rustdoc JSON, which is a string in older versions
+/// of the format and an integer in newer ones.
+fn item_id(id: &Value) -> Option<String> {
+    match id {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns whether `ty` is the `Self` type of a method.
+fn is_self_type(ty: &Map<String, Value>) -> bool {
+    (ty.get("kind").and_then(Value::as_str) == Some("generic")
+        && ty.get("inner").and_then(Value::as_str) == Some("Self"))
+        || ty.get("generic").and_then(Value::as_str) == Some("Self")
+}
+
+/// Collects the ids of the items referenced by the type `ty`, including the ones of its generic
+/// arguments. `Self` is replaced by `self_id`, for the methods of a type.
+fn referenced_ids(ty: &Value, self_id: Option<&str>, ids: &mut BTreeSet<String>) {
+    match ty {
+        Value::Object(object) => {
+            if is_self_type(object) {
+                ids.extend(self_id.map(String::from));
+            }
+            let path = match object.get("kind").and_then(Value::as_str) {
+                Some("resolved_path") => object.get("inner"),
+                _ => object.get("resolved_path"),
+            };
+            if let Some(id) = path.and_then(|path| path.get("id")).and_then(item_id) {
+                ids.insert(id);
+            }
+            for value in object.values() {
+                referenced_ids(value, self_id, ids);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                referenced_ids(value, self_id, ids);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collects the types of the fields of a struct or enum, through the items of its fields and
+/// variants.
+fn member_types<'a>(index: &'a Map<String, Value>, value: &'a Value, types: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                if MEMBER_KEYS.contains(&key.as_str()) {
+                    member_types(index, value, types);
+                }
+            }
+        }
+        Value::Array(members) => {
+            for member in members {
+                match item_id(member).and_then(|id| index.get(&id)) {
+                    Some(item) => match item_kind(item) {
+                        Some(("struct_field", ty)) => types.push(ty),
+                        Some(("variant", variant)) => member_types(index, variant, types),
+                        _ => {}
+                    },
+                    // Older versions of the format list the types of tuple variants directly
+                    None if member.is_object() => types.push(member),
+                    None => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the name of the trait implemented by an impl block, unless it's an inherent impl, an
+/// auto trait or a blanket implementation.
+fn implemented_trait(implementation: &Value) -> Option<String> {
+    if implementation["synthetic"] == true
+        || implementation["is_synthetic"] == true
+        || !implementation["blanket_impl"].is_null()
+    {
+        return None;
+    }
+    let trait_ = &implementation["trait"];
+    // Older versions of the format store the trait as a type
+    let path = trait_.get("inner").unwrap_or(trait_);
+    let name = path
+        .get("name")
+        .or_else(|| path.get("path"))
+        .and_then(Value::as_str)?;
+    Some(normalize_type(name))
+}
+
+/// Builds the type graph of the crate documented by `content`, which must be valid rustdoc JSON.
+pub(crate) fn type_graph(content: &[u8]) -> TypeGraph {
+    let json: Value = match serde_json::from_slice(content) {
+        Ok(json) => json,
+        Err(_) => return TypeGraph::default(),
+    };
+    let index = match json["index"].as_object() {
+        Some(index) => index,
+        None => return TypeGraph::default(),
+    };
+    let path_of = |id: &str| -> Option<String> {
+        Some(
+            json["paths"][id]["path"]
+                .as_array()?
+                .iter()
+                .map(|segment| segment.as_str())
+                .collect::<Option<Vec<_>>>()?
+                .join("::"),
+        )
+    };
+    let is_public = |item: &Value| item["crate_id"] == 0 && item["visibility"] == "public";
+
+    // The public structs and enums, by id
+    let types: BTreeMap<String, (String, &str, &Value)> = index
+        .iter()
+        .filter(|(_, item)| is_public(item))
+        .filter_map(|(id, item)| match item_kind(item)? {
+            (kind @ "struct", inner) | (kind @ "enum", inner) => {
+                Some((id.clone(), (path_of(id)?, kind, inner)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut nodes: BTreeMap<String, TypeNode> = types
+        .values()
+        .map(|(path, kind, _)| {
+            let node = TypeNode {
+                kind: kind.to_string(),
+                ..TypeNode::default()
+            };
+            (path.clone(), node)
+        })
+        .collect();
+    let type_paths = |ids: &BTreeSet<String>| -> Vec<String> {
+        ids.iter()
+            .filter_map(|id| types.get(id).map(|(path, _, _)| path.clone()))
+            .collect()
+    };
+
+    // The functions, with the id of the type they're a method of
+    let mut functions: Vec<(String, &Value, Option<&str>)> = index
+        .iter()
+        .filter(|(_, item)| is_public(item))
+        .filter_map(|(id, item)| match item_kind(item)? {
+            ("function", function) => Some((path_of(id)?, function_decl(function)?, None)),
+            _ => None,
+        })
+        .collect();
+
+    for (id, (path, _, inner)) in &types {
+        let mut fields = Vec::new();
+        member_types(index, inner, &mut fields);
+        let mut contained = BTreeSet::new();
+        for ty in fields {
+            referenced_ids(ty, None, &mut contained);
+        }
+        let node = nodes.get_mut(path).unwrap();
+        node.contains = type_paths(&contained);
+
+        let mut traits = BTreeSet::new();
+        let impls = inner["impls"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+        for implementation in impls.iter().filter_map(|id| index.get(&item_id(id)?)) {
+            let implementation = match item_kind(implementation) {
+                Some(("impl", implementation)) => implementation,
+                _ => continue,
+            };
+            if !implementation["trait"].is_null() {
+                traits.extend(implemented_trait(implementation));
+                continue;
+            }
+            // The methods of inherent impls
+            let items = implementation["items"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or(&[]);
+            for method in items.iter().filter_map(|id| index.get(&item_id(id)?)) {
+                if method["visibility"] != "public" {
+                    continue;
+                }
+                let decl = match item_kind(method) {
+                    Some(("function", method)) | Some(("method", method)) => function_decl(method),
+                    _ => None,
+                };
+                if let (Some(decl), Some(name)) = (decl, method["name"].as_str()) {
+                    functions.push((format!("{}::{}", path, name), decl, Some(id.as_str())));
+                }
+            }
+        }
+        node.implements = traits.into_iter().collect();
+    }
+
+    for (function, decl, self_id) in functions {
+        let mut consumed = BTreeSet::new();
+        referenced_ids(&decl["inputs"], self_id, &mut consumed);
+        let mut produced = BTreeSet::new();
+        referenced_ids(&decl["output"], self_id, &mut produced);
+
+        for path in type_paths(&consumed) {
+            let node = nodes.get_mut(&path).unwrap();
+            node.consumed_by.push(function.clone());
+        }
+        for path in type_paths(&produced) {
+            let node = nodes.get_mut(&path).unwrap();
+            node.produced_by.push(function.clone());
+        }
+    }
+    for node in nodes.values_mut() {
+        node.consumed_by.sort();
+        node.produced_by.sort();
+    }
+
+    TypeGraph { nodes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docbuilder::rustdoc_json::tests::sample_rustdoc_json;
+    use serde_json::json;
+
+    fn graph(edges: &[(&str, &[&str])]) -> TypeGraph {
+        TypeGraph {
+            nodes: edges
+                .iter()
+                .map(|(path, contains)| {
+                    let node = TypeNode {
+                        kind: "struct".into(),
+                        contains: contains.iter().map(|path| path.to_string()).collect(),
+                        ..TypeNode::default()
+                    };
+                    (path.to_string(), node)
+                })
+                .collect(),
+        }
+    }
+
+    fn paths(graph: &TypeGraph) -> Vec<&str> {
+        graph.nodes.keys().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn acyclic_graph_has_no_cycles() {
+        let graph = graph(&[
+            ("dummy::A", &["dummy::B", "dummy::C"]),
+            ("dummy::B", &["dummy::C"]),
+            ("dummy::C", &[]),
+        ]);
+        assert!(graph.cycles().is_empty());
+    }
+
+    #[test]
+    fn self_referencing_type_is_a_cycle() {
+        let graph = graph(&[
+            ("dummy::List", &["dummy::List", "dummy::Value"]),
+            ("dummy::Value", &[]),
+        ]);
+        assert_eq!(graph.cycles(), vec![vec!["dummy::List".to_string()]]);
+    }
+
+    #[test]
+    fn mutually_recursive_types_are_one_cycle() {
+        let graph = graph(&[
+            ("dummy::Expr", &["dummy::Block"]),
+            ("dummy::Block", &["dummy::Stmt"]),
+            ("dummy::Stmt", &["dummy::Expr", "dummy::Span"]),
+            ("dummy::Span", &[]),
+            ("dummy::Tree", &["dummy::Node"]),
+            ("dummy::Node", &["dummy::Tree"]),
+        ]);
+        assert_eq!(
+            graph.cycles(),
+            vec![
+                vec!["dummy::Block", "dummy::Expr", "dummy::Stmt"],
+                vec!["dummy::Node", "dummy::Tree"],
+            ]
+        );
+    }
+
+    #[test]
+    fn long_cycle_does_not_overflow_the_stack() {
+        let names: Vec<String> = (0..100_000).map(|i| format!("dummy::T{:06}", i)).collect();
+        let graph = TypeGraph {
+            nodes: names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let node = TypeNode {
+                        contains: vec![names[(i + 1) % names.len()].clone()],
+                        ..TypeNode::default()
+                    };
+                    (name.clone(), node)
+                })
+                .collect(),
+        };
+        let cycles = graph.cycles();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].len(), names.len());
+    }
+
+    #[test]
+    fn subgraph_stops_at_depth_and_cycles() {
+        let graph = graph(&[
+            ("dummy::A", &["dummy::B"]),
+            ("dummy::B", &["dummy::C", "dummy::A"]),
+            ("dummy::C", &["dummy::D"]),
+            ("dummy::D", &[]),
+            ("dummy::E", &["dummy::A"]),
+        ]);
+
+        let subgraph = graph.subgraph("dummy::A", 1);
+        assert_eq!(paths(&subgraph), vec!["dummy::A", "dummy::B", "dummy::E"]);
+        // Edges leaving the subgraph are removed
+        assert_eq!(subgraph.nodes["dummy::B"].contains, vec!["dummy::A"]);
+        assert_eq!(subgraph.cycles(), vec![vec!["dummy::A", "dummy::B"]]);
+
+        let subgraph = graph.subgraph("dummy::A", 2);
+        assert_eq!(
+            paths(&subgraph),
+            vec!["dummy::A", "dummy::B", "dummy::C", "dummy::E"]
+        );
+        assert_eq!(graph.subgraph("dummy::A", 10), graph);
+        assert_eq!(paths(&graph.subgraph("dummy::A", 0)), vec!["dummy::A"]);
+        assert!(graph.subgraph("dummy::Missing", 2).nodes.is_empty());
+    }
+
+    #[test]
+    fn resolve_type_names() {
+        let graph = graph(&[
+            ("dummy::Error", &[]),
+            ("dummy::io::Error", &[]),
+            ("dummy::Config", &[]),
+        ]);
+        assert_eq!(graph.resolve("dummy::io::Error"), Some("dummy::io::Error"));
+        assert_eq!(graph.resolve("Config"), Some("dummy::Config"));
+        // Ambiguous names have to be written as paths
+        assert_eq!(graph.resolve("Error"), None);
+        assert_eq!(graph.resolve("Missing"), None);
+    }
+
+    #[test]
+    fn build_type_graph() {
+        let resolved = |name: &str, id: &str, args: Vec<Value>| {
+            json!({
+                "kind": "resolved_path",
+                "inner": {
+                    "name": name,
+                    "id": id,
+                    "args": {"angle_bracketed": {
+                        "args": args.into_iter().map(|ty| json!({"type": ty})).collect::<Vec<_>>(),
+                        "bindings": [],
+                    }},
+                    "param_names": [],
+                },
+            })
+        };
+        let item = |kind: &str, inner: Value| json!({"crate_id": 0, "visibility": "public", "kind": kind, "inner": inner});
+        let self_type = json!({"kind": "generic", "inner": "Self"});
+
+        let mut sample = sample_rustdoc_json();
+        let index = &mut sample["index"];
+        // struct Node { next: Option<Box<Node>>, value: Value }
+        index["0:1"] = item(
+            "struct",
+            json!({"struct_type": "plain", "fields": ["0:2", "0:3"], "impls": ["0:6", "0:7", "0:8"]}),
+        );
+        index["0:2"] = item(
+            "struct_field",
+            resolved(
+                "Option",
+                "2:1",
+                vec![resolved(
+                    "Box",
+                    "2:2",
+                    vec![resolved("Node", "0:1", vec![])],
+                )],
+            ),
+        );
+        index["0:3"] = item("struct_field", resolved("Value", "0:4", vec![]));
+        // enum Value { Int(i64), Nested(Node) }
+        index["0:4"] = item("enum", json!({"variants": ["0:5"], "impls": []}));
+        index["0:5"] = item(
+            "variant",
+            json!({"variant_kind": "tuple", "variant_inner": [resolved("Node", "0:1", vec![])]}),
+        );
+        // impl Node { pub fn new(value: Value) -> Self }
+        index["0:6"] = item(
+            "impl",
+            json!({"trait": null, "items": ["0:9"], "blanket_impl": null}),
+        );
+        let mut new = item(
+            "method",
+            json!({"decl": {
+                "inputs": [["value", resolved("Value", "0:4", vec![])]],
+                "output": self_type,
+            }}),
+        );
+        new["name"] = json!("new");
+        index["0:9"] = new;
+        // impl Clone for Node, and the auto trait Send
+        index["0:7"] = item(
+            "impl",
+            json!({"trait": resolved("Clone", "2:3", vec![]), "blanket_impl": null}),
+        );
+        index["0:8"] = item(
+            "impl",
+            json!({"trait": resolved("Send", "2:4", vec![]), "synthetic": true, "blanket_impl": null}),
+        );
+        // pub fn parse(input: &str) -> Value
+        index["0:10"] = item(
+            "function",
+            json!({"decl": {
+                "inputs": [["input", {"kind": "primitive", "inner": "str"}]],
+                "output": resolved("Value", "0:4", vec![]),
+            }}),
+        );
+        // A private type isn't part of the graph
+        let mut private = item("struct", json!({"fields": [], "impls": []}));
+        private["visibility"] = json!("crate");
+        index["0:11"] = private;
+        for (id, path, kind) in &[
+            ("0:1", vec!["dummy", "Node"], "struct"),
+            ("0:4", vec!["dummy", "Value"], "enum"),
+            ("0:10", vec!["dummy", "parse"], "function"),
+            ("0:11", vec!["dummy", "Private"], "struct"),
+        ] {
+            sample["paths"][*id] = json!({"crate_id": 0, "path": path, "kind": kind});
+        }
+
+        let graph = type_graph(sample.to_string().as_bytes());
+        assert_eq!(paths(&graph), vec!["dummy::Node", "dummy::Value"]);
+        assert_eq!(
+            graph.nodes["dummy::Node"],
+            TypeNode {
+                kind: "struct".into(),
+                implements: vec!["Clone".into()],
+                contains: vec!["dummy::Node".into(), "dummy::Value".into()],
+                produced_by: vec!["dummy::Node::new".into()],
+                consumed_by: vec![],
+            }
+        );
+        assert_eq!(
+            graph.nodes["dummy::Value"],
+            TypeNode {
+                kind: "enum".into(),
+                implements: vec![],
+                contains: vec!["dummy::Node".into()],
+                produced_by: vec!["dummy::parse".into()],
+                consumed_by: vec!["dummy::Node::new".into()],
+            }
+        );
+        assert_eq!(
+            graph.cycles(),
+            vec![vec!["dummy::Node".to_string(), "dummy::Value".to_string()]]
+        );
+    }
+
+    #[test]
+    fn build_type_graph_from_newer_format() {
+        let mut sample = sample_rustdoc_json();
+        let index = &mut sample["index"];
+        let resolved =
+            |path: &str, id: u64| json!({"resolved_path": {"path": path, "id": id, "args": null}});
+        index["1"] = json!({
+            "crate_id": 0,
+            "visibility": "public",
+            "inner": {"struct": {"kind": {"tuple": [2]}, "generics": {}, "impls": [3]}},
+        });
+        index["2"] = json!({
+            "crate_id": 0,
+            "visibility": "public",
+            "inner": {"struct_field": {"borrowed_ref": {"type": resolved("Inner", 4)}}},
+        });
+        index["3"] = json!({
+            "crate_id": 0,
+            "visibility": "public",
+            "inner": {"impl": {"trait": {"path": "Debug", "id": 9, "args": null}, "is_synthetic": false, "blanket_impl": null}},
+        });
+        index["4"] = json!({
+            "crate_id": 0,
+            "visibility": "public",
+            "inner": {"enum": {"variants": [5], "impls": []}},
+        });
+        index["5"] = json!({
+            "crate_id": 0,
+            "visibility": "public",
+            "inner": {"variant": {"kind": "plain", "discriminant": null}},
+        });
+        sample["paths"]["1"] =
+            json!({"crate_id": 0, "path": ["dummy", "Wrapper"], "kind": "struct"});
+        sample["paths"]["4"] = json!({"crate_id": 0, "path": ["dummy", "Inner"], "kind": "enum"});
+
+        let graph = type_graph(sample.to_string().as_bytes());
+        assert_eq!(graph.nodes["dummy::Wrapper"].contains, vec!["dummy::Inner"]);
+        assert_eq!(graph.nodes["dummy::Wrapper"].implements, vec!["Debug"]);
+        assert!(graph.nodes["dummy::Inner"].contains.is_empty());
+        assert!(graph.cycles().is_empty());
+    }
+}
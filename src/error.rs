@@ -16,3 +16,36 @@ impl std::fmt::Display for SizeLimitReached {
         write!(f, "the size limit for the buffer was reached")
     }
 }
+
+/// The documentation generated for a crate exceeded the size limit in its [`Limits`].
+///
+/// [`Limits`]: crate::docbuilder::Limits
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct DocsTooLarge {
+    pub(crate) size: u64,
+    pub(crate) limit: u64,
+}
+
+impl std::error::Error for DocsTooLarge {}
+
+impl std::fmt::Display for DocsTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "documentation too large ({}, limit {})",
+            human_size(self.size),
+            human_size(self.limit),
+        )
+    }
+}
+
+/// Formats a size in bytes with the largest binary unit it's at least one of.
+fn human_size(bytes: u64) -> String {
+    const UNITS: &[(&str, u64)] = &[("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+
+    UNITS
+        .iter()
+        .find(|(_, size)| bytes >= *size)
+        .map(|(unit, size)| format!("{:.2} {}", bytes as f64 / *size as f64, unit))
+        .unwrap_or_else(|| format!("{} B", bytes))
+}
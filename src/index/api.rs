@@ -13,7 +13,7 @@ const APP_USER_AGENT: &str = concat!(
     include_str!(concat!(env!("OUT_DIR"), "/git_version"))
 );
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Api {
     api_base: Option<Url>,
     client: reqwest::blocking::Client,
@@ -47,10 +47,12 @@ pub struct CrateOwner {
     pub(crate) email: String,
     pub(crate) login: String,
     pub(crate) name: String,
+    /// Either `user` or `team`
+    pub(crate) kind: String,
 }
 
 impl Api {
-    pub(super) fn new(api_base: Option<Url>) -> Result<Self> {
+    pub(crate) fn new(api_base: Option<Url>) -> Result<Self> {
         let headers = vec![
             (USER_AGENT, HeaderValue::from_static(APP_USER_AGENT)),
             (ACCEPT, HeaderValue::from_static("application/json")),
@@ -65,6 +67,11 @@ impl Api {
         Ok(Self { api_base, client })
     }
 
+    /// Returns whether the registry has an API, which isn't required by alternative registries.
+    pub(crate) fn has_api_base(&self) -> bool {
+        self.api_base.is_some()
+    }
+
     fn api_base(&self) -> Result<Url> {
         self.api_base
             .clone()
@@ -133,6 +140,41 @@ impl Api {
         Ok((version.created_at, version.yanked, version.downloads))
     }
 
+    /// Returns the repository URL of the crate listed by the registry's API, or `None` if the
+    /// crate doesn't list one or doesn't exist.
+    pub(crate) fn get_repository(&self, name: &str) -> Result<Option<String>> {
+        let url = {
+            let mut url = self.api_base()?;
+            url.path_segments_mut()
+                .map_err(|()| err_msg("Invalid API url"))?
+                .extend(&["api", "v1", "crates", name]);
+            url
+        };
+
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(rename = "crate")]
+            krate: CrateResponse,
+        }
+
+        #[derive(Deserialize)]
+        struct CrateResponse {
+            #[serde(default)]
+            repository: Option<String>,
+        }
+
+        let response = self.client.get(url).send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: Response = response.error_for_status()?.json()?;
+
+        Ok(response
+            .krate
+            .repository
+            .filter(|repository| !repository.trim().is_empty()))
+    }
+
     /// Fetch owners from the registry's API
     fn get_owners(&self, name: &str) -> Result<Vec<CrateOwner>> {
         let url = {
@@ -158,6 +200,8 @@ impl Api {
             login: Option<String>,
             #[serde(default)]
             name: Option<String>,
+            #[serde(default)]
+            kind: Option<String>,
         }
 
         let response: Response = self.client.get(url).send()?.error_for_status()?.json()?;
@@ -177,6 +221,7 @@ impl Api {
                 email: data.email.unwrap_or_default(),
                 login: data.login.unwrap_or_default(),
                 name: data.name.unwrap_or_default(),
+                kind: data.kind.unwrap_or_else(|| "user".into()),
             })
             .collect();
 
@@ -10,6 +10,32 @@ impl Crates {
         Self { repo }
     }
 
+    /// Returns the entry of a single crate in the index, if it's published there.
+    pub(crate) fn find(&self, name: &str) -> Result<Option<Crate>, failure::Error> {
+        // crate names are always ASCII, and `crate_file_path` relies on it
+        if !name.is_ascii() {
+            return Ok(None);
+        }
+
+        let tree = self
+            .repo
+            .find_commit(self.repo.refname_to_id("refs/remotes/origin/master")?)?
+            .tree()?;
+        let entry = match tree.get_path(std::path::Path::new(&super::crate_file_path(name))) {
+            Ok(entry) => entry,
+            Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        match entry.to_object(&self.repo)?.as_blob() {
+            Some(blob) => Ok(Some(
+                Crate::from_slice(blob.content())
+                    .with_context(|_| format!("loading crate details of '{}'", name))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
     pub(crate) fn walk(&self, mut f: impl FnMut(Crate)) -> Result<(), failure::Error> {
         log::debug!("Walking crates in index");
         let tree = self
@@ -1,4 +1,7 @@
-use std::{path::PathBuf, process::Command};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use url::Url;
 
@@ -41,6 +44,52 @@ fn load_config(repo: &git2::Repository) -> Result<IndexConfig> {
     Ok(config)
 }
 
+/// Returns the path of the file listing the versions of a crate in the index, as specified by
+/// [the cargo documentation][index-format].
+///
+/// [index-format]: https://doc.rust-lang.org/cargo/reference/registries.html#index-format
+pub(crate) fn crate_file_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+fn has_version_locally(repo: &git2::Repository, name: &str, version: &str) -> Result<bool> {
+    // crate names are always ASCII, and `crate_file_path` relies on it
+    if !name.is_ascii() {
+        return Ok(false);
+    }
+
+    let tree = repo
+        .find_commit(repo.refname_to_id("refs/remotes/origin/master")?)?
+        .tree()?;
+    let entry = match tree.get_path(Path::new(&crate_file_path(name))) {
+        Ok(entry) => entry,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    let object = entry.to_object(repo)?;
+    let content = match object.as_blob() {
+        Some(blob) => blob.content(),
+        None => return Ok(false),
+    };
+
+    Ok(content.split(|&byte| byte == b'\n').any(|line| {
+        matches!(serde_json::from_slice::<IndexVersion>(line), Ok(line) if line.vers == version)
+    }))
+}
+
+/// A line of a crate file in the index, only containing the fields docs.rs needs
+#[derive(serde::Deserialize)]
+struct IndexVersion {
+    vers: String,
+}
+
 impl Index {
     pub fn from_url(path: PathBuf, repository_url: String) -> Result<Self> {
         let url = repository_url.clone();
@@ -96,6 +145,23 @@ impl Index {
         Ok(crates::Crates::new(git2::Repository::open(&self.path)?))
     }
 
+    /// Returns whether the index contains the given version of a crate.
+    ///
+    /// If the version is not in the local copy of the index, the latest changes are fetched from
+    /// the `origin` remote before checking again.
+    pub(crate) fn has_version(&self, name: &str, version: &str) -> Result<bool> {
+        let diff = self.diff()?;
+        let repo = diff.repository();
+        if has_version_locally(repo, name, version)? {
+            return Ok(true);
+        }
+
+        repo.find_remote("origin")?
+            .fetch(&["refs/heads/*:refs/remotes/origin/*"], None, None)
+            .context("fetching registry index")?;
+        has_version_locally(repo, name, version)
+    }
+
     pub fn api(&self) -> &Api {
         &self.api
     }
@@ -120,3 +186,17 @@ impl Index {
         self.repository_url.as_deref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::crate_file_path;
+
+    #[test]
+    fn test_crate_file_path() {
+        assert_eq!(crate_file_path("a"), "1/a");
+        assert_eq!(crate_file_path("ab"), "2/ab");
+        assert_eq!(crate_file_path("abc"), "3/a/abc");
+        assert_eq!(crate_file_path("Serde"), "se/rd/serde");
+        assert_eq!(crate_file_path("docs-rs"), "do/cs/docs-rs");
+    }
+}
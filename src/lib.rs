@@ -2,7 +2,8 @@
 //! documentation of crates for the Rust Programming Language.
 #![allow(clippy::cognitive_complexity)]
 
-pub use self::build_queue::BuildQueue;
+pub use self::build_queue::{BuildQueue, QueueDrain, QueueLock};
+pub use self::cdn::Cdn;
 pub use self::config::Config;
 pub use self::context::Context;
 pub use self::docbuilder::DocBuilder;
@@ -14,13 +15,15 @@ pub use self::storage::Storage;
 pub use self::web::Server;
 
 mod build_queue;
+pub mod cdn;
 mod config;
 mod context;
 pub mod db;
-mod docbuilder;
+pub mod docbuilder;
 mod error;
 pub mod index;
 mod metrics;
+mod notifications;
 pub mod storage;
 #[cfg(test)]
 mod test;
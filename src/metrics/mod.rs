@@ -2,7 +2,7 @@
 mod macros;
 
 use self::macros::MetricFromOpts;
-use crate::db::Pool;
+use crate::db::{summarize_builds, Pool};
 use crate::target::TargetAtom;
 use crate::BuildQueue;
 use dashmap::DashMap;
@@ -63,12 +63,33 @@ metrics! {
         pub(crate) failed_builds: IntCounter,
         /// Number of builds that did not complete due to not being a library
         pub(crate) non_library_builds: IntCounter,
+        /// Median and 95th percentile of the duration of the builds of the last day, in milliseconds
+        build_duration_ms: IntGaugeVec["quantile"],
+        /// Median and 95th percentile of the peak memory usage of the builds of the last day
+        build_peak_memory_bytes: IntGaugeVec["quantile"],
+        /// Median and 95th percentile of the number of files generated by the builds of the last day
+        build_files_count: IntGaugeVec["quantile"],
+        /// Median and 95th percentile of the size of the files generated by the builds of the last day
+        build_files_size_bytes: IntGaugeVec["quantile"],
+        /// Number of rebuilds queued through the admin API
+        pub(crate) build_triggered_by_admin: IntCounter,
+        /// Number of new releases queued through the webhook
+        pub(crate) build_triggered_by_webhook: IntCounter,
 
         /// Number of files uploaded to the storage backend
         pub(crate) uploaded_files_total: IntCounter,
+        /// Number of files not uploaded again by rebuilds, as they didn't change
+        pub(crate) build_cache_hits: IntCounter,
+        /// Number of new or changed files uploaded by builds
+        pub(crate) build_cache_misses: IntCounter,
+        /// Number of incremental builds skipped, as the sources of the release didn't change
+        pub(crate) skipped_incremental_builds: IntCounter,
 
         /// The number of attempted files that failed due to a memory limit
         pub(crate) html_rewrite_ooms: IntCounter,
+
+        /// Number of runs of the scheduled tasks of the daemon that failed or panicked
+        pub(crate) failed_scheduled_jobs: IntCounterVec["job"],
     }
 
     // The Rust prometheus library treats the namespace as the "prefix" of the metric name: a
@@ -156,12 +177,31 @@ impl Metrics {
         self.prioritized_crates_count
             .set(queue.prioritized_count()? as i64);
         self.failed_crates_count.set(queue.failed_count()? as i64);
+        self.gather_build_stats(&mut *pool.get()?)?;
 
         self.recently_accessed_releases.gather(self);
         self.gather_system_performance();
         Ok(self.registry.gather())
     }
 
+    fn gather_build_stats(&self, conn: &mut postgres::Client) -> Result<(), Error> {
+        let summary = summarize_builds(conn, 1)?;
+        for (metric, percentiles) in &[
+            (&self.build_duration_ms, summary.duration_ms),
+            (&self.build_peak_memory_bytes, summary.peak_memory_bytes),
+            (&self.build_files_count, summary.files_count),
+            (&self.build_files_size_bytes, summary.files_size_bytes),
+        ] {
+            for (quantile, value) in &[("0.5", percentiles.median), ("0.95", percentiles.p95)] {
+                metric
+                    .with_label_values(&[quantile])
+                    .set(value.unwrap_or(0.0).round() as i64);
+            }
+        }
+
+        Ok(())
+    }
+
     #[cfg(not(target_os = "linux"))]
     fn gather_system_performance(&self) {}
 
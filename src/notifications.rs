@@ -0,0 +1,393 @@
+//! Notifications sent to the owners of crates when the documentation of their releases fails to
+//! build, by email or with a webhook
+
+use crate::db::Pool;
+use crate::error::Result;
+use crate::Config;
+use failure::bail;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Message, SmtpTransport,
+    Transport,
+};
+use log::{debug, error, warn};
+use postgres::Client;
+use postgres_types::{FromSql, ToSql};
+use reqwest::blocking::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The event sent when the documentation of a release fails to build.
+pub(crate) const BUILD_FAILED: &str = "build_failed";
+/// The events crates can be subscribed to.
+pub(crate) const EVENTS: &[&str] = &[BUILD_FAILED];
+
+/// How many times sending a notification is attempted before it's dropped.
+const MAX_ATTEMPTS: i32 = 5;
+/// How many notifications are sent by each run of the dispatcher.
+const DISPATCH_BATCH_SIZE: i64 = 100;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// The port of SMTP servers encrypting the whole connection, the other ports upgrading it with
+/// STARTTLS.
+const SMTPS_PORT: u16 = 465;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSql, FromSql)]
+#[serde(rename_all = "lowercase")]
+#[postgres(name = "notification_method")]
+pub(crate) enum NotificationMethod {
+    /// Sends an email to the address in the endpoint
+    #[postgres(name = "email")]
+    Email,
+    /// Sends a POST request with a JSON body to the URL in the endpoint
+    #[postgres(name = "webhook")]
+    Webhook,
+}
+
+/// Queues the notifications of the subscriptions to the failures of the builds of the crate
+/// `name`, which are then sent by the dispatcher. Returns the number of queued notifications.
+pub(crate) fn queue_build_failure(conn: &mut Client, name: &str, version: &str) -> Result<u64> {
+    Ok(conn.execute(
+        "INSERT INTO pending_notifications (notification_id, event, name, version)
+         SELECT notifications.id, $3, crates.name, $2
+         FROM notifications
+         INNER JOIN crates ON crates.id = notifications.crate_id
+         WHERE crates.name = $1 AND notifications.events ? $3;",
+        &[&name, &version, &BUILD_FAILED],
+    )?)
+}
+
+/// Sends emails.
+pub(crate) trait Mailer: Send + Sync {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()>;
+}
+
+struct SmtpMailer {
+    transport: SmtpTransport,
+    sender: Mailbox,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        let message = Message::builder()
+            .from(self.sender.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body.to_string())?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
+
+/// Used when no SMTP server is configured, failing to send every email.
+struct DisabledMailer;
+
+impl Mailer for DisabledMailer {
+    fn send(&self, _to: &str, _subject: &str, _body: &str) -> Result<()> {
+        bail!("email notifications are disabled, as no SMTP server is configured");
+    }
+}
+
+/// Records the sent emails, only available in tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MockMailer {
+    /// The recipient, the subject and the body of each sent email
+    pub(crate) sent: std::sync::Mutex<Vec<(String, String, String)>>,
+}
+
+#[cfg(test)]
+impl Mailer for MockMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        self.sent
+            .lock()
+            .unwrap()
+            .push((to.into(), subject.into(), body.into()));
+        Ok(())
+    }
+}
+
+/// Sends the queued notifications.
+pub(crate) struct Notifier {
+    pool: Pool,
+    mailer: Arc<dyn Mailer>,
+    http: HttpClient,
+}
+
+impl Notifier {
+    pub(crate) fn new(config: &Config, pool: Pool) -> Result<Self> {
+        let mailer: Arc<dyn Mailer> = match &config.smtp_host {
+            Some(host) => {
+                let transport = if config.smtp_port == SMTPS_PORT {
+                    SmtpTransport::relay(host)?
+                } else {
+                    SmtpTransport::starttls_relay(host)?
+                };
+                let mut transport = transport.port(config.smtp_port);
+                if let (Some(username), Some(password)) =
+                    (&config.smtp_username, &config.smtp_password)
+                {
+                    transport =
+                        transport.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+                Arc::new(SmtpMailer {
+                    transport: transport.build(),
+                    sender: config.notifications_sender.parse()?,
+                })
+            }
+            None => Arc::new(DisabledMailer),
+        };
+
+        Ok(Notifier {
+            pool,
+            mailer,
+            http: HttpClient::builder().timeout(WEBHOOK_TIMEOUT).build()?,
+        })
+    }
+
+    /// Sends the emails with `mailer` instead of the configured SMTP server.
+    #[cfg(test)]
+    pub(crate) fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    /// Sends the oldest queued notifications, returning how many were sent.
+    ///
+    /// Notifications that fail to be sent are tried again by the next runs, and dropped after
+    /// `MAX_ATTEMPTS` attempts.
+    pub(crate) fn dispatch_pending(&self) -> Result<usize> {
+        let mut conn = self.pool.get()?;
+        let pending = conn.query(
+            "SELECT pending.id, pending.event, pending.name, pending.version,
+                 notifications.method, notifications.endpoint
+             FROM pending_notifications AS pending
+             INNER JOIN notifications ON notifications.id = pending.notification_id
+             ORDER BY pending.id
+             LIMIT $1;",
+            &[&DISPATCH_BATCH_SIZE],
+        )?;
+
+        let mut sent = 0;
+        for row in &pending {
+            let id: i32 = row.get("id");
+            let event: String = row.get("event");
+            let name: String = row.get("name");
+            let version: String = row.get("version");
+            let method: NotificationMethod = row.get("method");
+            let endpoint: String = row.get("endpoint");
+
+            match self.send(method, &endpoint, &event, &name, &version) {
+                Ok(()) => {
+                    debug!("sent {} notification of {} {}", event, name, version);
+                    conn.execute("DELETE FROM pending_notifications WHERE id = $1;", &[&id])?;
+                    sent += 1;
+                }
+                Err(err) => {
+                    let attempts: i32 = conn
+                        .query_one(
+                            "UPDATE pending_notifications
+                             SET attempts = attempts + 1
+                             WHERE id = $1
+                             RETURNING attempts;",
+                            &[&id],
+                        )?
+                        .get(0);
+                    if attempts >= MAX_ATTEMPTS {
+                        error!(
+                            "failed to send the {} notification of {} {} after {} attempts, \
+                             dropping it: {}",
+                            event, name, version, attempts, err
+                        );
+                        conn.execute("DELETE FROM pending_notifications WHERE id = $1;", &[&id])?;
+                    } else {
+                        warn!(
+                            "failed to send the {} notification of {} {} (attempt {}/{}): {}",
+                            event, name, version, attempts, MAX_ATTEMPTS, err
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    fn send(
+        &self,
+        method: NotificationMethod,
+        endpoint: &str,
+        event: &str,
+        name: &str,
+        version: &str,
+    ) -> Result<()> {
+        let builds_url = format!("https://docs.rs/crate/{}/{}/builds", name, version);
+        match method {
+            NotificationMethod::Email => self.mailer.send(
+                endpoint,
+                &format!("The documentation of {} {} failed to build", name, version),
+                &format!(
+                    "The documentation of {name} {version} failed to build on docs.rs.\n\n\
+                     The build logs are available at {url}\n\n\
+                     You're receiving this email because you subscribed to the build failures \
+                     of {name}.\n",
+                    name = name,
+                    version = version,
+                    url = builds_url,
+                ),
+            ),
+            NotificationMethod::Webhook => {
+                self.http
+                    .post(endpoint)
+                    .json(&json!({
+                        "event": event,
+                        "crate": name,
+                        "version": version,
+                        "builds_url": builds_url,
+                    }))
+                    .send()?
+                    .error_for_status()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment, TestServer};
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    /// Subscribes an owner of the crate `name` to its build failures.
+    fn subscribe(
+        env: &TestEnvironment,
+        name: &str,
+        method: NotificationMethod,
+        endpoint: &str,
+    ) -> Result<()> {
+        let mut conn = env.db().conn();
+        let owner: i32 = conn
+            .query_one(
+                "INSERT INTO owners (login, avatar, name) VALUES ('owner', '', '')
+                 ON CONFLICT (login) DO UPDATE SET login = EXCLUDED.login
+                 RETURNING id;",
+                &[],
+            )?
+            .get(0);
+        conn.execute(
+            "INSERT INTO notifications (user_id, crate_id, method, endpoint, events)
+             SELECT $1, id, $3, $4, $5 FROM crates WHERE name = $2;",
+            &[
+                &owner,
+                &name,
+                &method,
+                &endpoint,
+                &serde_json::json!([BUILD_FAILED]),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn pending_count(env: &TestEnvironment) -> Result<i64> {
+        Ok(env
+            .db()
+            .conn()
+            .query_one("SELECT COUNT(*) FROM pending_notifications;", &[])?
+            .get(0))
+    }
+
+    #[test]
+    fn email_notifications() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            env.fake_release().name("bar").version("1.0.0").create()?;
+            subscribe(env, "foo", NotificationMethod::Email, "owner@example.com")?;
+
+            // Only the subscribed crates are notified
+            assert_eq!(
+                queue_build_failure(&mut env.db().conn(), "bar", "1.0.0")?,
+                0
+            );
+            assert_eq!(
+                queue_build_failure(&mut env.db().conn(), "foo", "1.0.0")?,
+                1
+            );
+
+            let mailer = Arc::new(MockMailer::default());
+            let notifier =
+                Notifier::new(&env.config(), env.db().pool())?.with_mailer(mailer.clone());
+            assert_eq!(notifier.dispatch_pending()?, 1);
+            assert_eq!(pending_count(env)?, 0);
+
+            let sent = mailer.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            let (to, subject, body) = &sent[0];
+            assert_eq!(to, "owner@example.com");
+            assert_eq!(subject, "The documentation of foo 1.0.0 failed to build");
+            assert!(body.contains("https://docs.rs/crate/foo/1.0.0/builds"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn webhook_notifications() {
+        wrapper(|env| {
+            let received = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+            let handler = {
+                let received = received.clone();
+                move |req: &mut iron::Request| {
+                    let mut body = String::new();
+                    req.body.read_to_string(&mut body).unwrap();
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(serde_json::from_str(&body).unwrap());
+                    Ok(iron::Response::with(iron::status::NoContent))
+                }
+            };
+            let server = TestServer::start(handler)?;
+            let url = format!("{}/hook", server.url());
+
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            subscribe(env, "foo", NotificationMethod::Webhook, &url)?;
+            queue_build_failure(&mut env.db().conn(), "foo", "1.0.0")?;
+
+            let notifier = Notifier::new(&env.config(), env.db().pool())?;
+            assert_eq!(notifier.dispatch_pending()?, 1);
+            assert_eq!(
+                *received.lock().unwrap(),
+                vec![serde_json::json!({
+                    "event": "build_failed",
+                    "crate": "foo",
+                    "version": "1.0.0",
+                    "builds_url": "https://docs.rs/crate/foo/1.0.0/builds",
+                })]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failed_notifications_are_retried() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("1.0.0").create()?;
+            // No SMTP server is configured in the tests
+            subscribe(env, "foo", NotificationMethod::Email, "owner@example.com")?;
+            queue_build_failure(&mut env.db().conn(), "foo", "1.0.0")?;
+
+            let notifier = Notifier::new(&env.config(), env.db().pool())?;
+            for _ in 1..MAX_ATTEMPTS {
+                assert_eq!(notifier.dispatch_pending()?, 0);
+                assert_eq!(pending_count(env)?, 1);
+            }
+            assert_eq!(notifier.dispatch_pending()?, 0);
+            assert_eq!(pending_count(env)?, 0);
+
+            Ok(())
+        });
+    }
+}
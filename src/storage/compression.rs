@@ -57,6 +57,22 @@ impl Default for CompressionAlgorithm {
     }
 }
 
+impl CompressionAlgorithm {
+    /// Returns the name of the algorithm in the `Accept-Encoding` and `Content-Encoding` headers.
+    pub(crate) fn http_encoding(self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zstd => "zstd",
+        }
+    }
+
+    pub(crate) fn from_http_encoding(encoding: &str) -> Option<Self> {
+        match encoding {
+            "zstd" => Some(CompressionAlgorithm::Zstd),
+            _ => None,
+        }
+    }
+}
+
 // public for benchmarking
 pub fn compress(content: impl Read, algorithm: CompressionAlgorithm) -> Result<Vec<u8>, Error> {
     match algorithm {
@@ -1,11 +1,19 @@
-use super::{Blob, StorageTransaction};
+use super::{
+    compress, like_prefix, Blob, CompressionAlgorithm, StorageTransaction, StoreFileError,
+};
 use crate::db::Pool;
 use crate::Metrics;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use failure::Error;
 use postgres::Transaction;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
+/// The most content sent in a single statement inserting blobs, as a batch of large files would
+/// otherwise be sent as one huge array.
+const MAX_INSERT_SIZE: usize = 16 * 1024 * 1024;
+
 pub(crate) struct DatabaseBackend {
     pool: Pool,
     metrics: Arc<Metrics>,
@@ -30,14 +38,18 @@ impl DatabaseBackend {
         let max_size = max_size.min(std::i32::MAX as usize) as i32;
 
         // The size limit is checked at the database level, to avoid receiving data altogether if
-        // the limit is exceeded.
+        // the limit is exceeded. The content of the files stored before the deduplication is
+        // still in the `files` table.
         let rows = self.pool.get()?.query(
             "SELECT
-                 path, mime, date_updated, compression,
-                 (CASE WHEN LENGTH(content) <= $2 THEN content ELSE NULL END) AS content,
-                 (LENGTH(content) > $2) AS is_too_big
+                 files.path, files.mime, files.date_updated, files.compression,
+                 (CASE WHEN LENGTH(stored.content) <= $2 THEN stored.content ELSE NULL END)
+                    AS content,
+                 (LENGTH(stored.content) > $2) AS is_too_big
              FROM files
-             WHERE path = $1;",
+             LEFT JOIN blobs ON blobs.hash = files.content_hash
+             CROSS JOIN LATERAL (SELECT COALESCE(blobs.content, files.content) AS content) AS stored
+             WHERE files.path = $1;",
             &[&path, &(max_size)],
         )?;
 
@@ -68,6 +80,157 @@ impl DatabaseBackend {
         }
     }
 
+    pub(super) fn list_prefix(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        Ok(self
+            .pool
+            .get()?
+            .query(
+                "SELECT files.path, LENGTH(COALESCE(blobs.content, files.content))::BIGINT
+                 FROM files
+                 LEFT JOIN blobs ON blobs.hash = files.content_hash
+                 WHERE files.path LIKE $1 ESCAPE '\\'
+                 ORDER BY files.path;",
+                &[&like_prefix(prefix)],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+            .collect())
+    }
+
+    /// Returns the stored size of the files at `paths`, skipping the ones that don't exist.
+    pub(super) fn stored_sizes(&self, paths: &[String]) -> Result<HashMap<String, u64>, Error> {
+        Ok(self
+            .pool
+            .get()?
+            .query(
+                "SELECT files.path, LENGTH(COALESCE(blobs.content, files.content))::BIGINT
+                 FROM files
+                 LEFT JOIN blobs ON blobs.hash = files.content_hash
+                 WHERE files.path = ANY($1);",
+                &[&paths],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+            .collect())
+    }
+
+    /// Compresses up to `batch_size` of the files stored uncompressed with `algorithm`, returning
+    /// how many were compressed and how many bytes it saved. Their update date is kept, as their
+    /// content didn't change.
+    ///
+    /// The files deduplicated into the `blobs` table reference the compressed blob instead, and
+    /// the uncompressed one is deleted once no file references it anymore.
+    pub(super) fn compress_batch(
+        &self,
+        batch_size: i64,
+        algorithm: CompressionAlgorithm,
+    ) -> Result<(usize, i64), Error> {
+        let mut conn = self.pool.get()?;
+        let mut trans = conn.transaction()?;
+        let rows = trans.query(
+            "SELECT files.path, files.content_hash, COALESCE(blobs.content, files.content) AS content
+             FROM files
+             LEFT JOIN blobs ON blobs.hash = files.content_hash
+             WHERE files.compression IS NULL
+                 AND (files.content IS NOT NULL OR files.content_hash IS NOT NULL)
+             ORDER BY files.path
+             LIMIT $1
+             FOR UPDATE OF files SKIP LOCKED;",
+            &[&batch_size],
+        )?;
+
+        let compression = Some(algorithm as i32);
+        let mut blobs = Vec::new();
+        let mut released = Vec::new();
+        let mut saved_bytes = 0;
+        for row in &rows {
+            let path: &str = row.get("path");
+            let original = row.get::<_, &[u8]>("content");
+            let content = compress(original, algorithm)?;
+            saved_bytes += original.len() as i64 - content.len() as i64;
+            match row.get::<_, Option<String>>("content_hash") {
+                Some(hash) => {
+                    released.push(hash);
+                    blobs.push((blob_hash(&content, compression), path, content));
+                }
+                None => {
+                    trans.execute(
+                        "UPDATE files SET content = $2, compression = $3 WHERE path = $1;",
+                        &[&path, &content, &compression],
+                    )?;
+                }
+            }
+        }
+
+        // Locking the blobs in the same order everywhere prevents deadlocks
+        blobs.sort();
+        for (hash, path, content) in &blobs {
+            reference_blob(&mut trans, hash, content)?;
+            trans.execute(
+                "UPDATE files SET content_hash = $2, compression = $3 WHERE path = $1;",
+                &[path, hash, &compression],
+            )?;
+        }
+        release_blobs(&mut trans, &released)?;
+        trans.commit()?;
+
+        Ok((rows.len(), saved_bytes))
+    }
+
+    /// Moves the content of up to `batch_size` of the files stored before the deduplication to
+    /// the `blobs` table, returning how many files were moved and how many bytes they took before
+    /// and after being deduplicated.
+    ///
+    /// The content stored uncompressed is compressed with `algorithm` on the way, like the files
+    /// stored since then.
+    pub(super) fn deduplicate_batch(
+        &self,
+        batch_size: i64,
+        algorithm: CompressionAlgorithm,
+    ) -> Result<(usize, u64, u64), Error> {
+        let mut conn = self.pool.get()?;
+        let mut trans = conn.transaction()?;
+        let rows = trans.query(
+            "SELECT path, content, compression
+             FROM files
+             WHERE content IS NOT NULL
+             ORDER BY path
+             LIMIT $1
+             FOR UPDATE SKIP LOCKED;",
+            &[&batch_size],
+        )?;
+
+        let mut files = Vec::with_capacity(rows.len());
+        let mut size_before = 0;
+        for row in &rows {
+            let path: &str = row.get("path");
+            let content: &[u8] = row.get("content");
+            size_before += content.len() as u64;
+            let (content, compression) = match row.get::<_, Option<i32>>("compression") {
+                Some(compression) => (content.to_vec(), Some(compression)),
+                None => (compress(content, algorithm)?, Some(algorithm as i32)),
+            };
+            files.push((blob_hash(&content, compression), path, content, compression));
+        }
+        // Locking the blobs in the same order everywhere prevents deadlocks
+        files.sort();
+
+        let mut size_after = 0;
+        for (hash, path, content, compression) in &files {
+            if reference_blob(&mut trans, hash, content)? {
+                size_after += content.len() as u64;
+            }
+            trans.execute(
+                "UPDATE files SET content = NULL, content_hash = $2, compression = $3
+                 WHERE path = $1;",
+                &[path, hash, compression],
+            )?;
+        }
+        trans.commit()?;
+
+        Ok((files.len(), size_before, size_after))
+    }
+
     pub(super) fn start_connection(&self) -> Result<DatabaseClient, Error> {
         Ok(DatabaseClient {
             conn: self.pool.get()?,
@@ -99,26 +262,57 @@ pub(super) struct DatabaseStorageTransaction<'a> {
 
 impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
     fn store_batch(&mut self, batch: Vec<Blob>) -> Result<(), Error> {
-        for blob in batch {
-            let compression = blob.compression.map(|alg| alg as i32);
-            self.transaction.query(
-                "INSERT INTO files (path, mime, content, compression)
-                 VALUES ($1, $2, $3, $4)
-                 ON CONFLICT (path) DO UPDATE
-                    SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = EXCLUDED.compression",
-                &[&blob.path, &blob.mime, &blob.content, &compression],
-            )?;
-            self.metrics.uploaded_files_total.inc();
+        // Only the last blob stored at a path is kept, like when they're stored one by one
+        let mut positions = HashMap::new();
+        for (idx, blob) in batch.iter().enumerate() {
+            positions.insert(blob.path.clone(), idx);
         }
+        let batch: Vec<_> = batch
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, blob)| positions[&blob.path] == *idx)
+            .map(|(_, blob)| {
+                let compression = blob.compression.map(|alg| alg as i32);
+                (blob_hash(&blob.content, compression), compression, blob)
+            })
+            .collect();
+
+        // The whole batch is stored with a few statements, in a savepoint so that the batch can
+        // be stored again one file at a time to find the file failing
+        let mut savepoint = self.transaction.transaction()?;
+        if let Err(err) = insert_files(&mut savepoint, &batch) {
+            savepoint.rollback()?;
+            for file in &batch {
+                let mut savepoint = self.transaction.transaction()?;
+                let res = insert_files(&mut savepoint, std::slice::from_ref(file));
+                savepoint.rollback()?;
+                if let Err(file_err) = res {
+                    return Err(StoreFileError::new(&file.2.path, file_err));
+                }
+            }
+            return Err(err);
+        }
+        savepoint.commit()?;
+
+        self.metrics.uploaded_files_total.inc_by(batch.len() as i64);
         Ok(())
     }
 
     fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error> {
-        self.transaction.execute(
-            "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{}%", prefix.replace('%', "\\%"))],
-        )?;
-        Ok(())
+        delete_files(&mut self.transaction, prefix)
+    }
+
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        let hashes: Vec<String> = self
+            .transaction
+            .query(
+                "DELETE FROM files WHERE path = ANY($1) RETURNING content_hash;",
+                &[&paths],
+            )?
+            .into_iter()
+            .filter_map(|row| row.get(0))
+            .collect();
+        release_blobs(&mut self.transaction, &hashes)
     }
 
     fn complete(self: Box<Self>) -> Result<(), Error> {
@@ -127,5 +321,216 @@ impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
     }
 }
 
+/// Returns the key of a blob in the `blobs` table: the SHA-256 hash of its content as stored,
+/// and of its compression, so that identical files are only stored once.
+pub(super) fn blob_hash(content: &[u8], compression: Option<i32>) -> String {
+    let mut hasher = Sha256::new();
+    if let Some(compression) = compression {
+        hasher.update(compression.to_be_bytes());
+    }
+    hasher.update(content);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Stores the `(blob hash, compression, blob)` of files at distinct paths, with one statement per
+/// table whatever the number of files.
+fn insert_files(
+    trans: &mut Transaction<'_>,
+    files: &[(String, Option<i32>, Blob)],
+) -> Result<(), Error> {
+    // The blobs are referenced in the order of their hashes, and the files in the order of their
+    // paths, as locking the rows in the same order everywhere prevents deadlocks
+    let mut blobs: BTreeMap<&str, (i32, &[u8])> = BTreeMap::new();
+    for (hash, _, blob) in files {
+        blobs
+            .entry(hash.as_str())
+            .or_insert((0, blob.content.as_slice()))
+            .0 += 1;
+    }
+    let hashes: Vec<&str> = blobs.keys().copied().collect();
+    let counts: Vec<i32> = blobs.values().map(|(count, _)| *count).collect();
+
+    // Most blobs are already stored, so their content doesn't have to be sent again
+    trans.execute(
+        "SELECT hash FROM blobs WHERE hash = ANY($1) ORDER BY hash FOR UPDATE",
+        &[&hashes],
+    )?;
+    let referenced: HashSet<String> = trans
+        .query(
+            "UPDATE blobs SET refcount = blobs.refcount + referenced.count
+             FROM UNNEST($1::TEXT[], $2::INT[]) AS referenced(hash, count)
+             WHERE blobs.hash = referenced.hash
+             RETURNING blobs.hash",
+            &[&hashes, &counts],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let new_blobs: Vec<_> = blobs
+        .iter()
+        .filter(|(hash, _)| !referenced.contains(**hash))
+        .collect();
+    // The chunks keep the order of the hashes
+    for chunk in size_chunks(
+        &new_blobs,
+        |(_, (_, content))| content.len(),
+        MAX_INSERT_SIZE,
+    ) {
+        let hashes: Vec<&str> = chunk.iter().map(|(hash, _)| **hash).collect();
+        let contents: Vec<&[u8]> = chunk.iter().map(|(_, (_, content))| *content).collect();
+        let counts: Vec<i32> = chunk.iter().map(|(_, (count, _))| *count).collect();
+        // Concurrent uploads of a new blob conflict here, and all but the first one only
+        // reference it
+        trans.execute(
+            "INSERT INTO blobs (hash, content, refcount)
+             SELECT * FROM UNNEST($1::TEXT[], $2::BYTEA[], $3::INT[])
+             ON CONFLICT (hash) DO UPDATE SET refcount = blobs.refcount + EXCLUDED.refcount",
+            &[&hashes, &contents, &counts],
+        )?;
+    }
+
+    let mut files: Vec<_> = files.iter().collect();
+    files.sort_by(|(_, _, a), (_, _, b)| a.path.cmp(&b.path));
+    let paths: Vec<&str> = files
+        .iter()
+        .map(|(_, _, blob)| blob.path.as_str())
+        .collect();
+    let mimes: Vec<&str> = files
+        .iter()
+        .map(|(_, _, blob)| blob.mime.as_str())
+        .collect();
+    let compressions: Vec<Option<i32>> = files.iter().map(|(_, alg, _)| *alg).collect();
+    let hashes: Vec<&str> = files.iter().map(|(hash, _, _)| hash.as_str()).collect();
+
+    let replaced: Vec<String> = trans
+        .query(
+            "SELECT content_hash FROM files WHERE path = ANY($1) ORDER BY path FOR UPDATE",
+            &[&paths],
+        )?
+        .into_iter()
+        .filter_map(|row| row.get(0))
+        .collect();
+    trans.execute(
+        "INSERT INTO files (path, mime, content, compression, content_hash)
+         SELECT path, mime, NULL, compression, hash
+         FROM UNNEST($1::TEXT[], $2::TEXT[], $3::INT[], $4::TEXT[])
+            AS new_files(path, mime, compression, hash)
+         ON CONFLICT (path) DO UPDATE
+            SET mime = EXCLUDED.mime, content = NULL, compression = EXCLUDED.compression,
+                content_hash = EXCLUDED.content_hash, backend = 'database'",
+        &[&paths, &mimes, &compressions, &hashes],
+    )?;
+
+    release_blobs(trans, &replaced)
+}
+
+/// Splits `items` into consecutive chunks of at most `max_size`, as measured by `size`. An item
+/// larger than `max_size` gets a chunk of its own.
+fn size_chunks<T>(items: &[T], size: impl Fn(&T) -> usize, max_size: usize) -> Vec<&[T]> {
+    let mut chunks = Vec::new();
+    let (mut start, mut chunk_size) = (0, 0);
+    for (idx, item) in items.iter().enumerate() {
+        let item_size = size(item);
+        if idx > start && chunk_size + item_size > max_size {
+            chunks.push(&items[start..idx]);
+            start = idx;
+            chunk_size = 0;
+        }
+        chunk_size += item_size;
+    }
+    if start < items.len() {
+        chunks.push(&items[start..]);
+    }
+    chunks
+}
+
+/// Adds a reference to the blob with `hash`, storing `content` if it isn't stored yet. Returns
+/// whether the blob was stored.
+fn reference_blob(trans: &mut Transaction<'_>, hash: &str, content: &[u8]) -> Result<bool, Error> {
+    // Most blobs are already stored, so their content doesn't have to be sent again
+    let referenced = trans.execute(
+        "UPDATE blobs SET refcount = refcount + 1 WHERE hash = $1",
+        &[&hash],
+    )?;
+    if referenced > 0 {
+        return Ok(false);
+    }
+
+    // Concurrent uploads of a new blob conflict here, and all but the first one only reference it
+    let inserted = trans
+        .query_one(
+            "INSERT INTO blobs (hash, content, refcount)
+             VALUES ($1, $2, 1)
+             ON CONFLICT (hash) DO UPDATE SET refcount = blobs.refcount + 1
+             RETURNING (xmax = 0) AS inserted",
+            &[&hash, &content],
+        )?
+        .get("inserted");
+    Ok(inserted)
+}
+
+/// Removes a reference to each of the blobs in `hashes`, which can contain the same hash more
+/// than once, and deletes the blobs that aren't referenced anymore.
+fn release_blobs(trans: &mut Transaction<'_>, hashes: &[String]) -> Result<(), Error> {
+    if hashes.is_empty() {
+        return Ok(());
+    }
+
+    let unreferenced: Vec<String> = trans
+        .query(
+            "UPDATE blobs SET refcount = blobs.refcount - released.count
+             FROM (
+                SELECT hash, COUNT(*)::INT AS count
+                FROM UNNEST($1::TEXT[]) AS hash
+                GROUP BY hash
+             ) AS released
+             WHERE blobs.hash = released.hash
+             RETURNING blobs.hash, blobs.refcount",
+            &[&hashes],
+        )?
+        .into_iter()
+        .filter(|row| row.get::<_, i32>("refcount") <= 0)
+        .map(|row| row.get("hash"))
+        .collect();
+    trans.execute(
+        "DELETE FROM blobs WHERE hash = ANY($1) AND refcount <= 0",
+        &[&unreferenced],
+    )?;
+    Ok(())
+}
+
+/// Deletes the files whose path starts with `prefix`, and the blobs only they referenced.
+pub(crate) fn delete_files(trans: &mut Transaction<'_>, prefix: &str) -> Result<(), Error> {
+    let hashes: Vec<String> = trans
+        .query(
+            "DELETE FROM files WHERE path LIKE $1 ESCAPE '\\' RETURNING content_hash;",
+            &[&like_prefix(prefix)],
+        )?
+        .into_iter()
+        .filter_map(|row| row.get(0))
+        .collect();
+    release_blobs(trans, &hashes)
+}
+
 // The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please add
 // any test checking the public interface there.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_chunks() {
+        let chunks = |sizes: &[usize]| -> Vec<Vec<usize>> {
+            size_chunks(sizes, |size| *size, 10)
+                .into_iter()
+                .map(|chunk| chunk.to_vec())
+                .collect()
+        };
+        assert!(chunks(&[]).is_empty());
+        assert_eq!(chunks(&[4, 6, 1]), vec![vec![4, 6], vec![1]]);
+        // Larger items still get stored, on their own
+        assert_eq!(chunks(&[3, 15, 2, 2]), vec![vec![3], vec![15], vec![2, 2]]);
+    }
+}
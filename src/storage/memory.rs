@@ -0,0 +1,117 @@
+use super::{Blob, StorageTransaction};
+use crate::Metrics;
+use chrono::Utc;
+use failure::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// A fake backend keeping the files in memory, to test the storage without an external service.
+pub(super) struct MemoryBackend {
+    files: Mutex<BTreeMap<String, Blob>>,
+    metrics: Arc<Metrics>,
+}
+
+impl MemoryBackend {
+    pub(super) fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            files: Mutex::new(BTreeMap::new()),
+            metrics,
+        }
+    }
+
+    pub(super) fn exists(&self, path: &str) -> Result<bool, Error> {
+        Ok(self.files.lock().unwrap().contains_key(path))
+    }
+
+    pub(super) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        let files = self.files.lock().unwrap();
+        let blob = files.get(path).ok_or(super::PathNotFoundError)?;
+
+        let mut content = crate::utils::sized_buffer::SizedBuffer::new(max_size);
+        content.write_all(&blob.content)?;
+        Ok(Blob {
+            content: content.into_inner(),
+            ..blob.clone()
+        })
+    }
+
+    pub(super) fn list_prefix(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(path, _)| path.starts_with(prefix))
+            .map(|(path, blob)| (path.clone(), blob.content.len() as u64))
+            .collect())
+    }
+
+    pub(super) fn stored_sizes(&self, paths: &[String]) -> Result<HashMap<String, u64>, Error> {
+        let files = self.files.lock().unwrap();
+        Ok(paths
+            .iter()
+            .filter_map(|path| Some((path.clone(), files.get(path)?.content.len() as u64)))
+            .collect())
+    }
+
+    pub(super) fn start_storage_transaction(&self) -> MemoryStorageTransaction<'_> {
+        MemoryStorageTransaction {
+            backend: self,
+            changes: Vec::new(),
+        }
+    }
+}
+
+enum Change {
+    Store(Blob),
+    DeletePrefix(String),
+    DeletePaths(Vec<String>),
+}
+
+/// Applies the changes to the backend once completed, like a database transaction.
+pub(super) struct MemoryStorageTransaction<'a> {
+    backend: &'a MemoryBackend,
+    changes: Vec<Change>,
+}
+
+impl<'a> StorageTransaction for MemoryStorageTransaction<'a> {
+    fn store_batch(&mut self, batch: Vec<Blob>) -> Result<(), Error> {
+        self.changes.extend(batch.into_iter().map(Change::Store));
+        Ok(())
+    }
+
+    fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error> {
+        self.changes.push(Change::DeletePrefix(prefix.into()));
+        Ok(())
+    }
+
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        self.changes.push(Change::DeletePaths(paths.to_vec()));
+        Ok(())
+    }
+
+    fn complete(self: Box<Self>) -> Result<(), Error> {
+        let mut files = self.backend.files.lock().unwrap();
+        for change in self.changes {
+            match change {
+                Change::Store(mut blob) => {
+                    blob.date_updated = Utc::now();
+                    files.insert(blob.path.clone(), blob);
+                    self.backend.metrics.uploaded_files_total.inc();
+                }
+                Change::DeletePrefix(prefix) => files.retain(|path, _| !path.starts_with(&prefix)),
+                Change::DeletePaths(paths) => {
+                    for path in &paths {
+                        files.remove(path);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please add
+// any test checking the public interface there.
@@ -0,0 +1,163 @@
+//! Detection of the content type the stored files are served with
+
+use std::path::Path;
+
+/// The content types of the files generated by rustdoc and commonly found in crate sources, which
+/// take precedence over the guesses of `mime_guess`.
+static EXTENSIONS: &[(&str, &str)] = &[
+    ("css", "text/css"),
+    ("eot", "application/vnd.ms-fontobject"),
+    ("gif", "image/gif"),
+    ("html", "text/html"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript"),
+    ("json", "application/json"),
+    ("lock", "text/plain"),
+    ("markdown", "text/markdown"),
+    ("md", "text/markdown"),
+    ("otf", "font/otf"),
+    ("png", "image/png"),
+    ("rs", "text/rust"),
+    ("svg", "image/svg+xml"),
+    ("toml", "text/toml"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+];
+
+/// The magic bytes starting the binary formats recognized when the extension is unknown.
+static MAGIC_BYTES: &[(&[u8], &str)] = &[
+    (b"\0asm", "application/wasm"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"PK\x03\x04", "application/zip"),
+];
+
+const TEXT_PLAIN: &str = "text/plain";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Returns the content type of the file stored at `path` with `content`.
+///
+/// The type is looked up by extension, first in the types of rustdoc's output and then with
+/// `mime_guess`. Files with an unknown extension are recognized by their magic bytes, and the
+/// ones without extension in the crate sources (like `LICENSE`) are always plain text as long as
+/// they're valid UTF-8. Text types are served with their charset.
+pub(crate) fn detect_mime(path: &str, content: &[u8]) -> String {
+    let path = Path::new(path);
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    let mime = match &extension {
+        Some(extension) => EXTENSIONS
+            .iter()
+            .find(|(ext, _)| ext == extension)
+            .map(|(_, mime)| *mime)
+            .or_else(|| mime_guess::from_ext(extension).first_raw()),
+        None if path.starts_with("sources") && is_text(content) => Some(TEXT_PLAIN),
+        None => None,
+    };
+    let mime = mime.unwrap_or_else(|| sniff(content));
+
+    if mime.starts_with("text/") {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+/// Guesses the content type from the content of the file.
+fn sniff(content: &[u8]) -> &'static str {
+    MAGIC_BYTES
+        .iter()
+        .find(|(magic, _)| content.starts_with(magic))
+        .map(|(_, mime)| *mime)
+        .unwrap_or_else(|| {
+            if is_text(content) {
+                TEXT_PLAIN
+            } else {
+                OCTET_STREAM
+            }
+        })
+}
+
+/// Whether the content is UTF-8 text, which binary files rarely are because of their NUL bytes.
+fn is_text(content: &[u8]) -> bool {
+    !content.contains(&0) && std::str::from_utf8(content).is_ok()
+}
+
+/// Returns the type of a content type, without its parameters like the charset.
+pub(crate) fn essence(mime: &str) -> &str {
+    mime.split(';').next().unwrap_or(mime).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn fixtures() {
+        // Every fixture is stored either with the crate sources or with the documentation
+        let cases = &[
+            ("sources", ".gitignore", "text/plain; charset=utf-8"),
+            ("sources", "Cargo.lock", "text/plain; charset=utf-8"),
+            ("sources", "Cargo.toml", "text/toml; charset=utf-8"),
+            ("sources", "LICENSE", "text/plain; charset=utf-8"),
+            ("sources", "README.md", "text/markdown; charset=utf-8"),
+            ("sources", "lib.rs", "text/rust; charset=utf-8"),
+            ("sources", "logo", "image/png"),
+            ("sources", "blob", "application/octet-stream"),
+            ("rustdoc", "index.html", "text/html; charset=utf-8"),
+            ("rustdoc", "rustdoc.css", "text/css; charset=utf-8"),
+            ("rustdoc", "search-index.js", "application/javascript"),
+            ("rustdoc", "rust-logo.svg", "image/svg+xml"),
+            ("rustdoc", "rust-logo.png", "image/png"),
+            ("rustdoc", "FiraSans-Regular.woff", "font/woff"),
+            ("rustdoc", "SourceSerifPro-Regular.ttf.woff2", "font/woff2"),
+            ("rustdoc", "example_bg.wasm", "application/wasm"),
+            ("rustdoc", "unknown.bin-ext", "application/wasm"),
+            ("rustdoc", "notes.unknown-ext", "text/plain; charset=utf-8"),
+            ("rustdoc", "data.json", "application/json"),
+        ];
+
+        for (prefix, name, expected) in cases {
+            let content = fs::read(Path::new("tests/mime").join(name)).unwrap();
+            let path = format!("{}/dummy/0.1.0/{}", prefix, name);
+            assert_eq!(detect_mime(&path, &content), *expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn extensionless_files_outside_sources_are_sniffed() {
+        assert_eq!(
+            detect_mime("rustdoc/dummy/0.1.0/LICENSE", b"MIT"),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            detect_mime("rustdoc/dummy/0.1.0/logo", b"\x89PNG\r\n\x1a\n"),
+            "image/png"
+        );
+        assert_eq!(
+            detect_mime("sources/dummy/0.1.0/data", b"\0\x01"),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn essence_strips_parameters() {
+        assert_eq!(essence("text/rust; charset=utf-8"), "text/rust");
+        assert_eq!(essence("image/png"), "image/png");
+    }
+}
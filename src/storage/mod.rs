@@ -1,28 +1,85 @@
 mod compression;
 mod database;
+#[cfg(test)]
+mod memory;
+mod mime;
 mod s3;
 
 pub use self::compression::{compress, decompress, CompressionAlgorithm, CompressionAlgorithms};
+pub(crate) use self::database::delete_files;
 use self::database::DatabaseBackend;
+#[cfg(test)]
+use self::memory::MemoryBackend;
+use self::mime::detect_mime;
+pub(crate) use self::mime::essence as mime_essence;
 use self::s3::S3Backend;
 use crate::{db::Pool, Config, Metrics};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use failure::{err_msg, Error};
+use log::{debug, info, warn};
 use path_slash::PathExt;
+use postgres::Client;
+use sha2::{Digest, Sha256};
 use std::{
     collections::{HashMap, HashSet},
-    ffi::OsStr,
     fmt, fs,
+    io::Read,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Instant,
 };
 
-const MAX_CONCURRENT_UPLOADS: usize = 1000;
-
 #[derive(Debug, failure::Fail)]
 #[fail(display = "path not found")]
 pub(crate) struct PathNotFoundError;
 
+/// The error storing a file. The other files of its batch aren't stored either.
+#[derive(Debug, failure::Fail)]
+#[fail(display = "failed to store {}: {}", path, reason)]
+pub(crate) struct StoreFileError {
+    pub(crate) path: String,
+    pub(crate) reason: String,
+}
+
+impl StoreFileError {
+    fn new(path: &str, reason: impl fmt::Display) -> Error {
+        StoreFileError {
+            path: path.into(),
+            reason: reason.to_string(),
+        }
+        .into()
+    }
+}
+
+/// The files stored by [`Storage::store_changed`].
+#[derive(Debug)]
+pub(crate) struct StoredFiles {
+    /// The mime type of every file, by path relative to the stored directory
+    pub(crate) mimes: HashMap<PathBuf, String>,
+    pub(crate) algorithms: HashSet<CompressionAlgorithm>,
+    /// The SHA-256 hash of every file, by path in the backend
+    pub(crate) hashes: HashMap<String, String>,
+    /// How many files weren't stored again because they didn't change
+    pub(crate) unchanged: usize,
+}
+
+/// The outcome of [`Storage::deduplicate_files`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeduplicationStats {
+    /// How many files were deduplicated
+    pub files: usize,
+    /// The size of the content of the files before the deduplication, in bytes
+    pub size_before: u64,
+    /// The size of the blobs stored for them, in bytes
+    pub size_after: u64,
+}
+
+impl DeduplicationStats {
+    pub fn saved_bytes(&self) -> u64 {
+        self.size_before - self.size_after
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct Blob {
     pub(crate) path: String,
@@ -67,6 +124,34 @@ pub fn get_file_list<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
     Ok(files)
 }
 
+/// Returns the SHA-256 hash of every file in `root_dir`, by the path [`Storage::store_changed`]
+/// would store it at under `prefix`, skipping the files it would skip.
+pub(crate) fn hash_files(prefix: &str, root_dir: &Path) -> Result<HashMap<String, String>, Error> {
+    let mut hashes = HashMap::new();
+    for file_path in get_file_list(root_dir)? {
+        let mut file = match fs::File::open(root_dir.join(&file_path)) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        let bucket_path = Path::new(prefix).join(&file_path).to_slash().unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)
+            .map_err(|err| StoreFileError::new(&bucket_path, err))?;
+        hashes.insert(bucket_path, format!("{:x}", Sha256::digest(&content)));
+    }
+    Ok(hashes)
+}
+
+/// Returns the `LIKE` pattern matching the paths starting with `prefix`, escaping the wildcards
+/// and the backslashes it contains, for the queries using `ESCAPE '\'`.
+fn like_prefix(prefix: &str) -> String {
+    let escaped = prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("{}%", escaped)
+}
+
 #[derive(Debug, failure::Fail)]
 #[fail(display = "invalid storage backend")]
 pub(crate) struct InvalidStorageBackendError;
@@ -75,6 +160,9 @@ pub(crate) struct InvalidStorageBackendError;
 pub(crate) enum StorageKind {
     Database,
     S3,
+    /// Keeps the files in memory, only available in tests
+    #[cfg(test)]
+    Memory,
 }
 
 impl std::str::FromStr for StorageKind {
@@ -92,10 +180,16 @@ impl std::str::FromStr for StorageKind {
 enum StorageBackend {
     Database(DatabaseBackend),
     S3(Box<S3Backend>),
+    #[cfg(test)]
+    Memory(MemoryBackend),
 }
 
 pub struct Storage {
     backend: StorageBackend,
+    /// How many files are stored in each transaction
+    batch_size: usize,
+    /// The database recording how many files the backend stores, and their size
+    pool: Pool,
 }
 
 impl Storage {
@@ -103,28 +197,107 @@ impl Storage {
         Ok(Storage {
             backend: match config.storage_backend {
                 StorageKind::Database => {
-                    StorageBackend::Database(DatabaseBackend::new(pool, metrics))
+                    StorageBackend::Database(DatabaseBackend::new(pool.clone(), metrics))
                 }
                 StorageKind::S3 => StorageBackend::S3(Box::new(S3Backend::new(metrics, config)?)),
+                #[cfg(test)]
+                StorageKind::Memory => StorageBackend::Memory(MemoryBackend::new(metrics)),
             },
+            batch_size: config.upload_batch_size.max(1),
+            pool,
+        })
+    }
+
+    /// Creates a storage backed by S3, whatever the configured backend is.
+    pub fn new_s3(pool: Pool, metrics: Arc<Metrics>, config: &Config) -> Result<Self, Error> {
+        Ok(Storage {
+            backend: StorageBackend::S3(Box::new(S3Backend::new(metrics, config)?)),
+            batch_size: config.upload_batch_size.max(1),
+            pool,
         })
     }
 
+    /// The name of the backend, as recorded in the database.
+    fn backend_name(&self) -> &'static str {
+        match &self.backend {
+            StorageBackend::Database(_) => "database",
+            StorageBackend::S3(_) => "s3",
+            #[cfg(test)]
+            StorageBackend::Memory(_) => "memory",
+        }
+    }
+
     pub(crate) fn exists(&self, path: &str) -> Result<bool, Error> {
         match &self.backend {
             StorageBackend::Database(db) => db.exists(path),
             StorageBackend::S3(s3) => s3.exists(path),
+            #[cfg(test)]
+            StorageBackend::Memory(memory) => memory.exists(path),
+        }
+    }
+
+    // List the path and the stored size of every file under `prefix`, sorted by path. The size is
+    // the one of the content as stored, which can be compressed.
+    pub(crate) fn list_prefix(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.list_prefix(prefix),
+            StorageBackend::S3(s3) => s3.list_prefix(prefix),
+            #[cfg(test)]
+            StorageBackend::Memory(memory) => memory.list_prefix(prefix),
+        }
+    }
+
+    // Return the stored size of the files at `paths`, skipping the ones that don't exist.
+    fn stored_sizes(&self, paths: &[String]) -> Result<HashMap<String, u64>, Error> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.stored_sizes(paths),
+            StorageBackend::S3(s3) => s3.stored_sizes(paths),
+            #[cfg(test)]
+            StorageBackend::Memory(memory) => memory.stored_sizes(paths),
+        }
+    }
+
+    // Add `files` and `bytes` to the usage of the backend recorded in the `storage_usage` table,
+    // which keeps the size of the storage without going through all the files.
+    fn record_usage(&self, files: i64, bytes: i64) -> Result<(), Error> {
+        if files == 0 && bytes == 0 {
+            return Ok(());
         }
+        self.pool.get()?.execute(
+            "INSERT INTO storage_usage (backend, files, bytes) VALUES ($1, $2, $3)
+             ON CONFLICT (backend) DO UPDATE SET
+                files = storage_usage.files + EXCLUDED.files,
+                bytes = storage_usage.bytes + EXCLUDED.bytes;",
+            &[&self.backend_name(), &files, &bytes],
+        )?;
+        Ok(())
     }
 
     pub(crate) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        self.get_accepting(path, max_size, &CompressionAlgorithms::new())
+    }
+
+    // Like `get`, but keeps the content compressed if it's stored compressed with one of the
+    // `accepted` algorithms, for clients decompressing it themselves. Files stored uncompressed
+    // are returned as they are.
+    pub(crate) fn get_accepting(
+        &self,
+        path: &str,
+        max_size: usize,
+        accepted: &CompressionAlgorithms,
+    ) -> Result<Blob, Error> {
         let mut blob = match &self.backend {
             StorageBackend::Database(db) => db.get(path, max_size),
             StorageBackend::S3(s3) => s3.get(path, max_size),
+            #[cfg(test)]
+            StorageBackend::Memory(memory) => memory.get(path, max_size),
         }?;
-        if let Some(alg) = blob.compression {
-            blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
-            blob.compression = None;
+        match blob.compression {
+            Some(alg) if !accepted.contains(&alg) => {
+                blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
+                blob.compression = None;
+            }
+            _ => {}
         }
         Ok(blob)
     }
@@ -140,6 +313,8 @@ impl Storage {
                 Box::new(conn.start_storage_transaction()?)
             }
             StorageBackend::S3(s3) => Box::new(s3.start_storage_transaction()?),
+            #[cfg(test)]
+            StorageBackend::Memory(memory) => Box::new(memory.start_storage_transaction()),
         };
 
         let res = f(trans.as_mut())?;
@@ -158,9 +333,37 @@ impl Storage {
         prefix: &str,
         root_dir: &Path,
     ) -> Result<(HashMap<PathBuf, String>, HashSet<CompressionAlgorithm>), Error> {
+        let stored = self.store_files(prefix, root_dir, None)?;
+        Ok((stored.mimes, stored.algorithms))
+    }
+
+    // Like `store_all`, but skips the files whose SHA-256 hash is the one in `previous_hashes`
+    // for their path in the backend, as they're already stored.
+    //
+    // The returned mime types include the skipped files, unlike the compression algorithms.
+    pub(crate) fn store_changed(
+        &self,
+        prefix: &str,
+        root_dir: &Path,
+        previous_hashes: &HashMap<String, String>,
+    ) -> Result<StoredFiles, Error> {
+        self.store_files(prefix, root_dir, Some(previous_hashes))
+    }
+
+    fn store_files(
+        &self,
+        prefix: &str,
+        root_dir: &Path,
+        previous_hashes: Option<&HashMap<String, String>>,
+    ) -> Result<StoredFiles, Error> {
         let mut file_paths_and_mimes = HashMap::new();
         let mut algs = HashSet::with_capacity(1);
+        let mut hashes = HashMap::new();
+        let mut unchanged = 0;
+        let start = Instant::now();
 
+        // The files are only read when their batch is stored, so that a single batch is in
+        // memory at a time
         let blobs = get_file_list(root_dir)?
             .into_iter()
             .filter_map(|file_path| {
@@ -171,55 +374,279 @@ impl Storage {
                     .ok()
                     .map(|file| (file_path, file))
             })
-            .map(|(file_path, file)| -> Result<_, Error> {
-                let alg = CompressionAlgorithm::default();
-                let content = compress(file, alg)?;
+            .map(|(file_path, mut file)| -> Result<_, Error> {
                 let bucket_path = Path::new(prefix).join(&file_path).to_slash().unwrap();
+                let mut content = Vec::new();
+                file.read_to_end(&mut content)
+                    .map_err(|err| StoreFileError::new(&bucket_path, err))?;
+                let mime = detect_mime(&bucket_path, &content);
+                file_paths_and_mimes.insert(file_path, mime.clone());
+                if let Some(previous_hashes) = previous_hashes {
+                    let hash = format!("{:x}", Sha256::digest(&content));
+                    let is_unchanged = previous_hashes.get(&bucket_path) == Some(&hash);
+                    hashes.insert(bucket_path.clone(), hash);
+                    if is_unchanged {
+                        unchanged += 1;
+                        return Ok(None);
+                    }
+                }
 
-                let mime = detect_mime(&file_path)?;
-                file_paths_and_mimes.insert(file_path, mime.to_string());
+                let alg = CompressionAlgorithm::default();
+                let content = compress(content.as_slice(), alg)
+                    .map_err(|err| StoreFileError::new(&bucket_path, err))?;
                 algs.insert(alg);
 
-                Ok(Blob {
+                Ok(Some(Blob {
                     path: bucket_path,
-                    mime: mime.to_string(),
+                    mime,
                     content,
                     compression: Some(alg),
                     // this field is ignored by the backend
                     date_updated: Utc::now(),
-                })
-            });
+                }))
+            })
+            .filter_map(Result::transpose);
+
+        let stored = self.store_inner(blobs)?;
+        info!(
+            "stored {} files under {} in {:.2?} ({} unchanged)",
+            stored,
+            prefix,
+            start.elapsed(),
+            unchanged
+        );
+        Ok(StoredFiles {
+            mimes: file_paths_and_mimes,
+            algorithms: algs,
+            hashes,
+            unchanged,
+        })
+    }
 
-        self.store_inner(blobs)?;
-        Ok((file_paths_and_mimes, algs))
+    // Store a single file with the given content into the backend at `path`, returning the
+    // compression algorithm used.
+    pub(crate) fn store_one(
+        &self,
+        path: &str,
+        mime: &str,
+        content: &[u8],
+    ) -> Result<CompressionAlgorithm, Error> {
+        let alg = CompressionAlgorithm::default();
+        let blob = Blob {
+            path: path.into(),
+            mime: mime.into(),
+            content: compress(content, alg)?,
+            compression: Some(alg),
+            // this field is ignored by the backend
+            date_updated: Utc::now(),
+        };
+        self.store_inner(std::iter::once(Ok(blob)))?;
+        Ok(alg)
     }
 
     #[cfg(test)]
     pub(crate) fn store_blobs(&self, blobs: Vec<Blob>) -> Result<(), Error> {
-        self.store_inner(blobs.into_iter().map(Ok))
+        self.store_inner(blobs.into_iter().map(Ok))?;
+        Ok(())
     }
 
+    // Stores the blobs in batches of `batch_size`, each in its own transaction, returning how
+    // many were stored. When storing a batch fails, none of its blobs are stored, but the
+    // previous batches are.
+    //
+    // The files replaced by the blobs are looked up first, so that they aren't counted twice in
+    // the usage of the storage.
     fn store_inner(
         &self,
         mut blobs: impl Iterator<Item = Result<Blob, Error>>,
-    ) -> Result<(), Error> {
-        self.transaction(|trans| {
-            loop {
-                let batch: Vec<_> = blobs
-                    .by_ref()
-                    .take(MAX_CONCURRENT_UPLOADS)
-                    .collect::<Result<_, Error>>()?;
-                if batch.is_empty() {
-                    break;
-                }
-                trans.store_batch(batch)?;
+    ) -> Result<usize, Error> {
+        let mut stored = 0;
+        loop {
+            let batch: Vec<_> = blobs
+                .by_ref()
+                .take(self.batch_size)
+                .collect::<Result<_, Error>>()?;
+            if batch.is_empty() {
+                break;
             }
-            Ok(())
-        })
+
+            let count = batch.len();
+            // Only the last blob stored at a path is kept
+            let sizes: HashMap<String, u64> = batch
+                .iter()
+                .map(|blob| (blob.path.clone(), blob.content.len() as u64))
+                .collect();
+            let paths: Vec<String> = sizes.keys().cloned().collect();
+            // Failing to look them up only makes the usage less accurate, and the storing reports
+            // its own errors, like the invalid paths
+            let replaced = self.stored_sizes(&paths).unwrap_or_else(|err| {
+                warn!("failed to look up the files replaced by a batch: {}", err);
+                HashMap::new()
+            });
+
+            let start = Instant::now();
+            self.transaction(|trans| trans.store_batch(batch))?;
+            self.record_usage(
+                (sizes.len() - replaced.len()) as i64,
+                sizes.values().sum::<u64>() as i64 - replaced.values().sum::<u64>() as i64,
+            )?;
+            debug!(
+                "stored a batch of {} files in {:.2?}",
+                count,
+                start.elapsed()
+            );
+            stored += count;
+        }
+
+        Ok(stored)
     }
 
     pub(crate) fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
-        self.transaction(|trans| trans.delete_prefix(prefix))
+        let deleted = self.list_prefix(prefix)?;
+        self.transaction(|trans| trans.delete_prefix(prefix))?;
+        self.record_usage(
+            -(deleted.len() as i64),
+            -(deleted.iter().map(|(_, size)| size).sum::<u64>() as i64),
+        )
+    }
+
+    // Delete the files at exactly `paths`, ignoring the ones that don't exist.
+    pub(crate) fn delete_paths(&self, paths: &[String]) -> Result<(), Error> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let deleted = self.stored_sizes(paths)?;
+        self.transaction(|trans| trans.delete_paths(paths))?;
+        self.record_usage(
+            -(deleted.len() as i64),
+            -(deleted.values().sum::<u64>() as i64),
+        )
+    }
+
+    // Copy the files stored in the database under `prefix` into this storage, in batches, and
+    // record in the database which backend holds them.
+    //
+    // The files already copied are skipped, so an interrupted copy can be resumed. They're kept
+    // in the database, which keeps serving them until the backend is switched.
+    //
+    // This returns how many files were copied.
+    pub fn copy_from_database(&self, conn: &mut Client, prefix: &str) -> Result<usize, Error> {
+        use std::convert::TryInto;
+
+        if let StorageBackend::Database(_) = &self.backend {
+            failure::bail!("the files are already stored in the database");
+        }
+        let backend = self.backend_name();
+        let pattern = like_prefix(prefix);
+
+        let mut copied = 0;
+        loop {
+            let rows = conn.query(
+                "SELECT
+                    files.path, files.mime, files.date_updated, files.compression,
+                    COALESCE(blobs.content, files.content) AS content
+                 FROM files
+                 LEFT JOIN blobs ON blobs.hash = files.content_hash
+                 WHERE files.path LIKE $1 ESCAPE '\\' AND files.backend = 'database'
+                 ORDER BY files.path
+                 LIMIT $2",
+                &[&pattern, &(self.batch_size as i64)],
+            )?;
+            if rows.is_empty() {
+                break;
+            }
+
+            let blobs = rows
+                .into_iter()
+                .map(|row| {
+                    let compression = match row.get::<_, Option<i32>>("compression") {
+                        Some(alg) => Some(alg.try_into().map_err(|alg| {
+                            failure::format_err!("invalid compression algorithm {}", alg)
+                        })?),
+                        None => None,
+                    };
+                    Ok(Blob {
+                        path: row.get("path"),
+                        mime: row.get("mime"),
+                        date_updated: DateTime::from_utc(
+                            row.get::<_, NaiveDateTime>("date_updated"),
+                            Utc,
+                        ),
+                        content: row.get("content"),
+                        compression,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let paths: Vec<_> = blobs.iter().map(|blob| blob.path.clone()).collect();
+
+            self.store_inner(blobs.into_iter().map(Ok))?;
+            conn.execute(
+                "UPDATE files SET backend = $1 WHERE path = ANY($2)",
+                &[&backend, &paths],
+            )?;
+            copied += paths.len();
+            info!("copied {} files to {}", copied, backend);
+        }
+
+        Ok(copied)
+    }
+
+    // Compress the files stored uncompressed in the database, `batch_size` files at a time, like
+    // the ones stored before the files were compressed on upload.
+    //
+    // Every batch is committed on its own, so an interrupted backfill can be resumed.
+    //
+    // This returns how many files were compressed.
+    pub fn compress_files(&self, batch_size: i64) -> Result<usize, Error> {
+        let db = match &self.backend {
+            StorageBackend::Database(db) => db,
+            _ => failure::bail!("only the files stored in the database can be compressed"),
+        };
+
+        let mut compressed = 0;
+        loop {
+            let (batch, saved_bytes) =
+                db.compress_batch(batch_size, CompressionAlgorithm::default())?;
+            if batch == 0 {
+                break;
+            }
+            self.record_usage(0, -saved_bytes)?;
+            compressed += batch;
+            info!("compressed {} files", compressed);
+        }
+
+        Ok(compressed)
+    }
+
+    // Move the content of the files stored in the database before the deduplication to the
+    // `blobs` table, `batch_size` files at a time, so that identical files are only stored once.
+    // The files stored uncompressed are compressed too.
+    //
+    // Every batch is committed on its own, so an interrupted backfill can be resumed.
+    pub fn deduplicate_files(&self, batch_size: i64) -> Result<DeduplicationStats, Error> {
+        let db = match &self.backend {
+            StorageBackend::Database(db) => db,
+            _ => failure::bail!("only the files stored in the database can be deduplicated"),
+        };
+
+        let mut stats = DeduplicationStats::default();
+        loop {
+            let (files, size_before, size_after) =
+                db.deduplicate_batch(batch_size, CompressionAlgorithm::default())?;
+            if files == 0 {
+                break;
+            }
+            stats.files += files;
+            stats.size_before += size_before;
+            stats.size_after += size_after;
+            info!(
+                "deduplicated {} files, saving {} bytes",
+                stats.files,
+                stats.saved_bytes()
+            );
+        }
+
+        Ok(stats)
     }
 
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
@@ -239,6 +666,8 @@ impl std::fmt::Debug for Storage {
         match &self.backend {
             StorageBackend::Database(_) => write!(f, "database-backed storage"),
             StorageBackend::S3(_) => write!(f, "S3-backed storage"),
+            #[cfg(test)]
+            StorageBackend::Memory(_) => write!(f, "memory-backed storage"),
         }
     }
 }
@@ -246,31 +675,10 @@ impl std::fmt::Debug for Storage {
 trait StorageTransaction {
     fn store_batch(&mut self, batch: Vec<Blob>) -> Result<(), Error>;
     fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error>;
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error>;
     fn complete(self: Box<Self>) -> Result<(), Error>;
 }
 
-fn detect_mime(file_path: &Path) -> Result<&'static str, Error> {
-    let mime = mime_guess::from_path(file_path)
-        .first_raw()
-        .unwrap_or("text/plain");
-    Ok(match mime {
-        "text/plain" | "text/troff" | "text/x-markdown" | "text/x-rust" | "text/x-toml" => {
-            match file_path.extension().and_then(OsStr::to_str) {
-                Some("md") => "text/markdown",
-                Some("rs") => "text/rust",
-                Some("markdown") => "text/markdown",
-                Some("css") => "text/css",
-                Some("toml") => "text/toml",
-                Some("js") => "application/javascript",
-                Some("json") => "application/json",
-                _ => mime,
-            }
-        }
-        "image/svg" => "image/svg+xml",
-        _ => mime,
-    })
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -288,24 +696,351 @@ mod test {
     }
 
     #[test]
-    fn test_mime_types() {
-        check_mime(".gitignore", "text/plain");
-        check_mime("hello.toml", "text/toml");
-        check_mime("hello.css", "text/css");
-        check_mime("hello.js", "application/javascript");
-        check_mime("hello.html", "text/html");
-        check_mime("hello.hello.md", "text/markdown");
-        check_mime("hello.markdown", "text/markdown");
-        check_mime("hello.json", "application/json");
-        check_mime("hello.txt", "text/plain");
-        check_mime("file.rs", "text/rust");
-        check_mime("important.svg", "image/svg+xml");
-    }
-
-    fn check_mime(path: &str, expected_mime: &str) {
-        let detected_mime = detect_mime(Path::new(&path));
-        let detected_mime = detected_mime.expect("no mime was given");
-        assert_eq!(detected_mime, expected_mime);
+    fn test_like_prefix() {
+        assert_eq!(like_prefix("docs/"), "docs/%");
+        assert_eq!(like_prefix("a_b%c\\d"), "a\\_b\\%c\\\\d%");
+    }
+
+    #[test]
+    fn test_copy_from_database() {
+        crate::test::wrapper(|env| {
+            let database = env.storage();
+            let blobs = [
+                "docs/a.html",
+                "docs/b.html",
+                "docs-old/d.html",
+                "sources/c.rs",
+            ]
+            .iter()
+            .map(|path| {
+                Ok(Blob {
+                    path: (*path).to_string(),
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                    content: compress(path.as_bytes(), CompressionAlgorithm::default())?,
+                    compression: Some(CompressionAlgorithm::default()),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+            database.store_blobs(blobs)?;
+
+            let memory = Storage {
+                backend: StorageBackend::Memory(MemoryBackend::new(env.metrics())),
+                batch_size: env.config().upload_batch_size,
+                pool: env.db().pool(),
+            };
+            let mut conn = env.db().conn();
+            // The wildcards of the prefix are matched literally
+            assert_eq!(memory.copy_from_database(&mut conn, "docs_")?, 0);
+            assert_eq!(memory.copy_from_database(&mut conn, "docs%")?, 0);
+            assert_eq!(memory.copy_from_database(&mut conn, "docs/")?, 2);
+            assert_eq!(
+                memory.get("docs/a.html", usize::MAX)?.content,
+                b"docs/a.html"
+            );
+            assert!(!memory.exists("sources/c.rs")?);
+
+            let backends: Vec<(String, String)> = conn
+                .query("SELECT path, backend FROM files ORDER BY path", &[])?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(
+                backends,
+                vec![
+                    ("docs-old/d.html".into(), "database".into()),
+                    ("docs/a.html".into(), "memory".into()),
+                    ("docs/b.html".into(), "memory".into()),
+                    ("sources/c.rs".into(), "database".into()),
+                ]
+            );
+            // The files are still served from the database until the backend is switched
+            assert!(database.exists("docs/a.html")?);
+
+            // The files already copied are skipped
+            assert_eq!(memory.copy_from_database(&mut conn, "")?, 2);
+            assert!(memory.exists("sources/c.rs")?);
+
+            assert!(database.copy_from_database(&mut conn, "").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_compress_files() {
+        crate::test::wrapper(|env| {
+            let storage = env.storage();
+            let alg = CompressionAlgorithm::default();
+            let blob = |path: &str, compression| -> Result<Blob, Error> {
+                let content = format!("content of {}", path).into_bytes();
+                Ok(Blob {
+                    path: path.into(),
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                    content: match compression {
+                        Some(alg) => compress(content.as_slice(), alg)?,
+                        None => content,
+                    },
+                    compression,
+                })
+            };
+            storage.store_blobs(vec![
+                blob("a.txt", None)?,
+                blob("b.txt", Some(alg))?,
+                blob("c.txt", None)?,
+                blob("d.txt", None)?,
+            ])?;
+            let date_updated = |path: &str| -> Result<NaiveDateTime, Error> {
+                Ok(env
+                    .db()
+                    .conn()
+                    .query_one("SELECT date_updated FROM files WHERE path = $1", &[&path])?
+                    .get(0))
+            };
+            let a_updated = date_updated("a.txt")?;
+
+            // The files already compressed are left alone
+            assert_eq!(storage.compress_files(2)?, 3);
+            assert_eq!(storage.compress_files(2)?, 0);
+
+            let accepted = [alg].iter().copied().collect();
+            for path in &["a.txt", "b.txt", "c.txt", "d.txt"] {
+                let expected = format!("content of {}", path).into_bytes();
+                assert_eq!(storage.get(path, usize::MAX)?.content, expected);
+                let raw = storage.get_accepting(path, usize::MAX, &accepted)?;
+                assert_eq!(raw.compression, Some(alg));
+                assert_eq!(
+                    decompress(raw.content.as_slice(), alg, usize::MAX)?,
+                    expected
+                );
+            }
+            assert_eq!(date_updated("a.txt")?, a_updated);
+
+            let memory = Storage {
+                backend: StorageBackend::Memory(MemoryBackend::new(env.metrics())),
+                batch_size: env.config().upload_batch_size,
+                pool: env.db().pool(),
+            };
+            assert!(memory.compress_files(2).is_err());
+
+            Ok(())
+        });
+    }
+
+    /// Returns the reference count of every blob, sorted.
+    fn refcounts(conn: &mut Client) -> Result<Vec<i32>, Error> {
+        Ok(conn
+            .query("SELECT refcount FROM blobs ORDER BY refcount", &[])?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    fn text_blob(path: &str, content: &str) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+            content: content.into(),
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_identical_files_are_stored_once() {
+        crate::test::wrapper(|env| {
+            let storage = env.storage();
+            let mut conn = env.db().conn();
+            storage.store_blobs(vec![
+                text_blob("1.0.0/a.txt", "shared"),
+                text_blob("1.0.0/b.txt", "only in 1.0.0"),
+                text_blob("2.0.0/a.txt", "shared"),
+            ])?;
+            assert_eq!(refcounts(&mut conn)?, vec![1, 2]);
+
+            // Storing a file again keeps a single reference to its blob
+            storage.store_blobs(vec![text_blob("1.0.0/a.txt", "shared")])?;
+            assert_eq!(refcounts(&mut conn)?, vec![1, 2]);
+
+            // Replacing the content of a file releases its previous blob
+            storage.store_blobs(vec![text_blob("1.0.0/b.txt", "shared")])?;
+            assert_eq!(refcounts(&mut conn)?, vec![3]);
+
+            storage.delete_prefix("1.0.0/")?;
+            assert_eq!(refcounts(&mut conn)?, vec![1]);
+            assert_eq!(storage.get("2.0.0/a.txt", usize::MAX)?.content, b"shared");
+
+            storage.delete_prefix("2.0.0/")?;
+            assert_eq!(refcounts(&mut conn)?, Vec::<i32>::new());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_concurrent_uploads_of_the_same_blob() {
+        crate::test::wrapper(|env| {
+            const UPLOADS: usize = 8;
+
+            let storage = env.storage();
+            let barrier = Arc::new(std::sync::Barrier::new(UPLOADS));
+            let uploads: Vec<_> = (0..UPLOADS)
+                .map(|i| {
+                    let storage = storage.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        storage.store_blobs(vec![
+                            text_blob(&format!("{}/shared.txt", i), "shared"),
+                            text_blob(&format!("{}/own.txt", i), &format!("own {}", i)),
+                        ])
+                    })
+                })
+                .collect();
+            for upload in uploads {
+                upload.join().unwrap()?;
+            }
+
+            let mut expected = vec![1; UPLOADS];
+            expected.push(UPLOADS as i32);
+            assert_eq!(refcounts(&mut env.db().conn())?, expected);
+            for i in 0..UPLOADS {
+                let path = format!("{}/shared.txt", i);
+                assert_eq!(storage.get(&path, usize::MAX)?.content, b"shared");
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_store_large_directory_in_batches() {
+        crate::test::wrapper(|env| {
+            const FILES: usize = 2500;
+
+            env.override_config(|config| config.upload_batch_size = 1000);
+            let storage = env.storage();
+            let dir = tempfile::Builder::new()
+                .prefix("docs.rs-upload-test")
+                .tempdir()?;
+            for i in 0..FILES {
+                fs::write(
+                    dir.path().join(format!("{}.rs", i)),
+                    format!("const N: usize = {};", i),
+                )?;
+            }
+            // Identical files share a blob, even in different batches
+            fs::write(dir.path().join("copy.rs"), "const N: usize = 0;")?;
+
+            let start = Instant::now();
+            let (mimes, _) = storage.store_all("large/", dir.path())?;
+            info!("stored {} files in {:.2?}", FILES + 1, start.elapsed());
+
+            assert_eq!(mimes.len(), FILES + 1);
+            assert_eq!(env.metrics().uploaded_files_total.get(), FILES as i64 + 1);
+            let mut conn = env.db().conn();
+            let stored: i64 = conn
+                .query_one("SELECT COUNT(*) FROM files WHERE path LIKE 'large/%'", &[])?
+                .get(0);
+            assert_eq!(stored as usize, FILES + 1);
+            assert_eq!(refcounts(&mut conn)?.iter().max(), Some(&2));
+            assert_eq!(
+                storage.get("large/1234.rs", usize::MAX)?.content,
+                b"const N: usize = 1234;"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_failed_batch_is_rolled_back() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.upload_batch_size = 3);
+            let storage = env.storage();
+            let blobs: Vec<_> = (0..9)
+                .map(|i| {
+                    // PostgreSQL rejects the NUL character in text
+                    let path = if i == 4 {
+                        "4\0.txt".to_string()
+                    } else {
+                        format!("{}.txt", i)
+                    };
+                    text_blob(&path, &format!("content {}", i))
+                })
+                .collect();
+
+            let err = storage.store_blobs(blobs).unwrap_err();
+            let err = err
+                .downcast_ref::<StoreFileError>()
+                .expect("the failing file isn't reported");
+            assert_eq!(err.path, "4\0.txt");
+
+            // The batches before the failing one are stored, and none of the files after
+            for i in 0..9 {
+                assert_eq!(storage.exists(&format!("{}.txt", i))?, i < 3, "{}", i);
+            }
+            assert_eq!(refcounts(&mut env.db().conn())?, vec![1, 1, 1]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_deduplicate_files() {
+        crate::test::wrapper(|env| {
+            let storage = env.storage();
+            let mut conn = env.db().conn();
+            // Files stored before the deduplication have their content in the `files` table
+            for (path, content) in &[
+                ("a.txt", "shared"),
+                ("b.txt", "shared"),
+                ("c.txt", "unique"),
+                ("d.txt", "shared"),
+            ] {
+                conn.execute(
+                    "INSERT INTO files (path, mime, content) VALUES ($1, 'text/plain', $2)",
+                    &[path, &content.as_bytes()],
+                )?;
+            }
+            storage.store_one("e.txt", "text/plain", b"shared")?;
+
+            // The uncompressed content is compressed like the files stored since then, so it's
+            // the same blob as the one of `e.txt`
+            let unique_size =
+                compress(&b"unique"[..], CompressionAlgorithm::default())?.len() as u64;
+            let stats = storage.deduplicate_files(3)?;
+            assert_eq!(
+                stats,
+                DeduplicationStats {
+                    files: 4,
+                    size_before: 24,
+                    size_after: unique_size,
+                }
+            );
+            assert_eq!(stats.saved_bytes(), 24 - unique_size);
+            assert_eq!(storage.deduplicate_files(3)?, DeduplicationStats::default());
+            assert_eq!(refcounts(&mut conn)?, vec![1, 4]);
+            let uncompressed: i64 = conn
+                .query_one("SELECT COUNT(*) FROM files WHERE compression IS NULL", &[])?
+                .get(0);
+            assert_eq!(uncompressed, 0);
+
+            for path in &["a.txt", "b.txt", "d.txt", "e.txt"] {
+                assert_eq!(storage.get(path, usize::MAX)?.content, b"shared");
+            }
+            assert_eq!(storage.get("c.txt", usize::MAX)?.content, b"unique");
+
+            let memory = Storage {
+                backend: StorageBackend::Memory(MemoryBackend::new(env.metrics())),
+                batch_size: env.config().upload_batch_size,
+                pool: env.db().pool(),
+            };
+            assert!(memory.deduplicate_files(3).is_err());
+
+            Ok(())
+        });
     }
 }
 
@@ -362,6 +1097,40 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_get_accepting(storage: &Storage) -> Result<(), Error> {
+        let alg = CompressionAlgorithm::default();
+        let content = b"fn main() {}".to_vec();
+        storage.store_one("compressed.rs", "text/rust", &content)?;
+        storage.store_blobs(vec![Blob {
+            path: "uncompressed.rs".into(),
+            mime: "text/rust".into(),
+            date_updated: Utc::now(),
+            content: content.clone(),
+            compression: None,
+        }])?;
+
+        // Clients accepting the compression get the content as it's stored
+        let accepted = [alg].iter().copied().collect();
+        let compressed = storage.get_accepting("compressed.rs", usize::MAX, &accepted)?;
+        assert_eq!(compressed.compression, Some(alg));
+        assert_eq!(
+            decompress(compressed.content.as_slice(), alg, usize::MAX)?,
+            content
+        );
+        let uncompressed = storage.get_accepting("uncompressed.rs", usize::MAX, &accepted)?;
+        assert_eq!(uncompressed.compression, None);
+        assert_eq!(uncompressed.content, content);
+
+        // Other clients get the content decompressed
+        for path in &["compressed.rs", "uncompressed.rs"] {
+            let blob = storage.get_accepting(path, usize::MAX, &CompressionAlgorithms::new())?;
+            assert_eq!(blob.compression, None);
+            assert_eq!(blob.content, content);
+        }
+
+        Ok(())
+    }
+
     fn test_get_too_big(storage: &Storage) -> Result<(), Error> {
         const MAX_SIZE: usize = 1024;
 
@@ -429,6 +1198,17 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_store_one(storage: &Storage) -> Result<(), Error> {
+        let alg = storage.store_one("foo/bar.json", "application/json", b"{}")?;
+        assert_eq!(alg, CompressionAlgorithm::default());
+
+        let file = storage.get("foo/bar.json", usize::MAX)?;
+        assert_eq!(file.content, b"{}");
+        assert_eq!(file.mime, "application/json");
+
+        Ok(())
+    }
+
     fn test_store_all(storage: &Storage, metrics: &Metrics) -> Result<(), Error> {
         let dir = tempfile::Builder::new()
             .prefix("docs.rs-upload-test")
@@ -450,21 +1230,21 @@ mod backend_tests {
         }
         assert_eq!(
             stored_files.get(Path::new("Cargo.toml")).unwrap(),
-            "text/toml"
+            "text/toml; charset=utf-8"
         );
         assert_eq!(
             stored_files.get(Path::new("src/main.rs")).unwrap(),
-            "text/rust"
+            "text/rust; charset=utf-8"
         );
 
         let file = storage.get("prefix/Cargo.toml", std::usize::MAX)?;
         assert_eq!(file.content, b"data");
-        assert_eq!(file.mime, "text/toml");
+        assert_eq!(file.mime, "text/toml; charset=utf-8");
         assert_eq!(file.path, "prefix/Cargo.toml");
 
         let file = storage.get("prefix/src/main.rs", std::usize::MAX)?;
         assert_eq!(file.content, b"data");
-        assert_eq!(file.mime, "text/rust");
+        assert_eq!(file.mime, "text/rust; charset=utf-8");
         assert_eq!(file.path, "prefix/src/main.rs");
 
         let mut expected_algs = HashSet::new();
@@ -478,7 +1258,7 @@ mod backend_tests {
 
     fn test_batched_uploads(storage: &Storage) -> Result<(), Error> {
         let now = Utc::now();
-        let uploads: Vec<_> = (0..=MAX_CONCURRENT_UPLOADS + 1)
+        let uploads: Vec<_> = (0..=storage.batch_size + 1)
             .map(|i| {
                 let content = format!("const IDX: usize = {};", i).as_bytes().to_vec();
                 Blob {
@@ -529,6 +1309,76 @@ mod backend_tests {
         )
     }
 
+    fn test_delete_underscore(storage: &Storage) -> Result<(), Error> {
+        // "_" matches any single char in a `LIKE` pattern, so it has to be escaped too.
+        test_deletion(
+            storage,
+            "foo_bar/",
+            &["foo-bar/baz.txt", "foo_bar/baz.txt"],
+            &["foo-bar/baz.txt"],
+            &["foo_bar/baz.txt"],
+        )
+    }
+
+    fn test_delete_paths(storage: &Storage) -> Result<(), Error> {
+        let paths = ["foo/bar.txt", "foo/bar.txt.bak", "foo/baz.txt", "foo/%.txt"];
+        storage.store_blobs(
+            paths
+                .iter()
+                .map(|path| Blob {
+                    path: (*path).to_string(),
+                    content: b"foo\n".to_vec(),
+                    compression: None,
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                })
+                .collect(),
+        )?;
+
+        storage.delete_paths(&[
+            "foo/bar.txt".into(),
+            "foo/%.txt".into(),
+            "missing.txt".into(),
+        ])?;
+
+        assert!(!storage.exists("foo/bar.txt")?);
+        assert!(!storage.exists("foo/%.txt")?);
+        // Only the exact paths are deleted, not the paths they're a prefix of
+        assert!(storage.exists("foo/bar.txt.bak")?);
+        // Identical files stored at other paths are kept
+        assert_eq!(
+            storage.get("foo/baz.txt", std::usize::MAX)?.content,
+            b"foo\n"
+        );
+
+        Ok(())
+    }
+
+    fn test_list_prefix(storage: &Storage) -> Result<(), Error> {
+        storage.store_blobs(
+            ["foo/b.txt", "foo/a/c.txt", "foo.txt", "bar/d.txt"]
+                .iter()
+                .map(|path| Blob {
+                    path: (*path).to_string(),
+                    content: path.as_bytes().to_vec(),
+                    compression: None,
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                })
+                .collect(),
+        )?;
+
+        assert_eq!(
+            storage.list_prefix("foo/")?,
+            vec![("foo/a/c.txt".into(), 11), ("foo/b.txt".into(), 9)]
+        );
+        assert!(storage.list_prefix("baz/")?.is_empty());
+        // The wildcards of the prefix are matched literally
+        assert!(storage.list_prefix("fo_/")?.is_empty());
+
+        Ok(())
+    }
+
     fn test_deletion(
         storage: &Storage,
         prefix: &str,
@@ -617,15 +1467,21 @@ mod backend_tests {
         backends {
             s3 => StorageKind::S3,
             database => StorageKind::Database,
+            memory => StorageKind::Memory,
         }
 
         tests {
             test_batched_uploads,
             test_exists,
             test_get_object,
+            test_get_accepting,
             test_get_too_big,
+            test_store_one,
             test_delete_prefix,
             test_delete_percent,
+            test_delete_underscore,
+            test_delete_paths,
+            test_list_prefix,
         }
 
         tests_with_metrics {
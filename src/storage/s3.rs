@@ -12,7 +12,7 @@ use rusoto_s3::{
     DeleteObjectsRequest, GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest,
     ListObjectsV2Request, ObjectIdentifier, PutObjectRequest, S3Client, S3,
 };
-use std::{convert::TryInto, io::Write, sync::Arc};
+use std::{collections::HashMap, convert::TryInto, io::Write, sync::Arc};
 use tokio::runtime::Runtime;
 
 pub(super) struct S3Backend {
@@ -134,6 +134,72 @@ impl S3Backend {
         })
     }
 
+    pub(super) fn list_prefix(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        self.runtime.handle().block_on(async {
+            let mut files = Vec::new();
+            let mut continuation_token = None;
+            loop {
+                let list = self
+                    .client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.clone(),
+                        prefix: Some(prefix.into()),
+                        continuation_token,
+                        ..ListObjectsV2Request::default()
+                    })
+                    .await?;
+
+                files.extend(
+                    list.contents
+                        .unwrap_or_else(Vec::new)
+                        .into_iter()
+                        .filter_map(|o| Some((o.key?, o.size.unwrap_or(0) as u64))),
+                );
+
+                continuation_token = list.next_continuation_token;
+                if continuation_token.is_none() {
+                    // S3 already lists the keys in lexicographic order
+                    return Ok(files);
+                }
+            }
+        })
+    }
+
+    /// Returns the size of the objects at `paths`, skipping the ones that don't exist, with one
+    /// concurrent request per path.
+    pub(super) fn stored_sizes(&self, paths: &[String]) -> Result<HashMap<String, u64>, Error> {
+        self.runtime.handle().block_on(async {
+            let mut futures: FuturesUnordered<_> = paths
+                .iter()
+                .map(|path| async move {
+                    let resp = self
+                        .client
+                        .head_object(HeadObjectRequest {
+                            bucket: self.bucket.clone(),
+                            key: path.clone(),
+                            ..Default::default()
+                        })
+                        .await;
+                    match resp {
+                        Ok(head) => Ok(Some((
+                            path.clone(),
+                            head.content_length.unwrap_or(0) as u64,
+                        ))),
+                        Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+                        Err(RusotoError::Unknown(resp)) if resp.status == 404 => Ok(None),
+                        Err(other) => Err(Error::from(other)),
+                    }
+                })
+                .collect();
+
+            let mut sizes = HashMap::new();
+            while let Some(result) = futures.next().await {
+                sizes.extend(result?);
+            }
+            Ok(sizes)
+        })
+    }
+
     pub(super) fn start_storage_transaction(&self) -> Result<S3StorageTransaction, Error> {
         Ok(S3StorageTransaction { s3: self })
     }
@@ -270,6 +336,41 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
         })
     }
 
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        self.s3.runtime.handle().block_on(async {
+            // S3 deletes at most 1000 objects per request
+            for chunk in paths.chunks(1000) {
+                let resp = self
+                    .s3
+                    .client
+                    .delete_objects(DeleteObjectsRequest {
+                        bucket: self.s3.bucket.clone(),
+                        delete: rusoto_s3::Delete {
+                            objects: chunk
+                                .iter()
+                                .map(|path| ObjectIdentifier {
+                                    key: path.clone(),
+                                    version_id: None,
+                                })
+                                .collect(),
+                            quiet: None,
+                        },
+                        ..DeleteObjectsRequest::default()
+                    })
+                    .await?;
+
+                if let Some(errs) = resp.errors {
+                    for err in &errs {
+                        log::error!("error deleting file from s3: {:?}", err);
+                    }
+
+                    failure::bail!("deleting from s3 failed");
+                }
+            }
+            Ok(())
+        })
+    }
+
     fn complete(self: Box<Self>) -> Result<(), Error> {
         Ok(())
     }
@@ -1,13 +1,20 @@
 use super::TestDatabase;
-use crate::docbuilder::{BuildResult, DocCoverage};
+use crate::docbuilder::{
+    BuildResult, BuildStats, CompileTiming, DocCoverage, DocWarning, DocWarnings,
+    FunctionSignature, TypeGraph,
+};
 use crate::index::api::{CrateData, CrateOwner, ReleaseData};
 use crate::storage::Storage;
 use crate::utils::{Dependency, MetadataPackage, Target};
+use crate::Config;
 use chrono::{DateTime, Utc};
 use failure::Error;
 use postgres::Client;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
+use tempfile::TempDir;
 
 #[must_use = "FakeRelease does nothing until you call .create()"]
 pub(crate) struct FakeRelease<'a> {
@@ -19,7 +26,10 @@ pub(crate) struct FakeRelease<'a> {
     source_files: Vec<(&'a str, &'a [u8])>,
     /// name, content
     rustdoc_files: Vec<(&'a str, &'a [u8])>,
+    /// platform, name, content
+    platform_rustdoc_files: Vec<(&'a str, &'a str, &'a [u8])>,
     doc_targets: Vec<String>,
+    failed_targets: Vec<String>,
     default_target: Option<&'a str>,
     registry_crate_data: CrateData,
     registry_release_data: ReleaseData,
@@ -27,7 +37,15 @@ pub(crate) struct FakeRelease<'a> {
     has_examples: bool,
     /// This stores the content, while `package.readme` stores the filename
     readme: Option<&'a str>,
+    /// The `[package.metadata.docs.rs]` table of the manifest
+    docs_rs_metadata: Option<&'a str>,
     github_stats: Option<FakeGithubStats>,
+    compile_timings: Option<Vec<CompileTiming>>,
+    macro_expansions: Vec<(String, String)>,
+    function_signatures: Vec<FunctionSignature>,
+    type_graph: Option<TypeGraph>,
+    /// The warnings of rustdoc, only recorded if set
+    doc_warnings: Option<DocWarnings>,
 }
 
 const DEFAULT_CONTENT: &[u8] =
@@ -74,10 +92,13 @@ impl<'a> FakeRelease<'a> {
                 build_log: "It works!".into(),
                 successful: true,
                 doc_coverage: None,
+                stats: BuildStats::default(),
             },
             source_files: Vec::new(),
             rustdoc_files: Vec::new(),
+            platform_rustdoc_files: Vec::new(),
             doc_targets: Vec::new(),
+            failed_targets: Vec::new(),
             default_target: None,
             registry_crate_data: CrateData { owners: Vec::new() },
             registry_release_data: ReleaseData {
@@ -88,7 +109,13 @@ impl<'a> FakeRelease<'a> {
             has_docs: true,
             has_examples: false,
             readme: None,
+            docs_rs_metadata: None,
             github_stats: None,
+            compile_timings: None,
+            macro_expansions: Vec::new(),
+            function_signatures: Vec::new(),
+            type_graph: None,
+            doc_warnings: None,
         }
     }
 
@@ -129,12 +156,38 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn license(mut self, license: &str) -> Self {
+        self.package.license = Some(license.into());
+        self
+    }
+
+    pub(crate) fn add_dependency(mut self, name: &str, req: &str, kind: Option<&str>) -> Self {
+        self.package.dependencies.push(Dependency {
+            name: name.into(),
+            req: req.into(),
+            kind: kind.map(Into::into),
+            rename: None,
+            optional: false,
+        });
+        self
+    }
+
     pub(crate) fn build_result_successful(mut self, new: bool) -> Self {
         self.has_docs = new;
         self.build_result.successful = new;
         self
     }
 
+    pub(crate) fn build_stats(mut self, stats: BuildStats) -> Self {
+        self.build_result.stats = stats;
+        self
+    }
+
+    pub(crate) fn rustc_version(mut self, new: &str) -> Self {
+        self.build_result.rustc_version = new.into();
+        self
+    }
+
     pub(crate) fn yanked(mut self, new: bool) -> Self {
         self.registry_release_data.yanked = new;
         self
@@ -152,6 +205,18 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    /// Adds a rustdoc file only to the documentation of `platform`, which is either the default
+    /// target or one of the platforms added with `add_platform`.
+    pub(crate) fn platform_rustdoc_file(
+        mut self,
+        platform: &'a str,
+        path: &'a str,
+        data: &'a [u8],
+    ) -> Self {
+        self.platform_rustdoc_files.push((platform, path, data));
+        self
+    }
+
     pub(crate) fn source_file(mut self, path: &'a str, data: &'a [u8]) -> Self {
         self.source_files.push((path, data));
         self
@@ -168,6 +233,12 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    /// Records a target whose documentation failed to build.
+    pub(crate) fn failed_target(mut self, target: &str) -> Self {
+        self.failed_targets.push(target.into());
+        self
+    }
+
     pub(crate) fn binary(mut self, bin: bool) -> Self {
         self.has_docs = !bin;
         if bin {
@@ -178,6 +249,11 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn proc_macro(mut self) -> Self {
+        self.package.targets[0].crate_types = vec!["proc-macro".into()];
+        self
+    }
+
     pub(crate) fn add_platform<S: Into<String>>(mut self, platform: S) -> Self {
         let platform = platform.into();
         let name = self.package.targets[0].name.clone();
@@ -214,11 +290,67 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn compile_timings(mut self, compile_timings: Vec<CompileTiming>) -> Self {
+        self.compile_timings = Some(compile_timings);
+        self
+    }
+
+    pub(crate) fn macro_expansion(mut self, example: &str, expansion: &str) -> Self {
+        self.macro_expansions
+            .push((example.into(), expansion.into()));
+        self
+    }
+
+    /// Adds a public function with the signature `params -> return_type`, with the types in the
+    /// form of `normalize_type`.
+    pub(crate) fn function_signature(
+        mut self,
+        path: &str,
+        params: &[&str],
+        return_type: &str,
+    ) -> Self {
+        self.function_signatures.push(FunctionSignature {
+            path: path.into(),
+            param_types: params.iter().map(|param| param.to_string()).collect(),
+            return_type: return_type.into(),
+        });
+        self
+    }
+
+    pub(crate) fn type_graph(mut self, type_graph: TypeGraph) -> Self {
+        self.type_graph = Some(type_graph);
+        self
+    }
+
+    /// Adds a warning of rustdoc about a link to `item_path`, of the `broken_intra_doc_links`
+    /// lint. The warnings are only recorded if this is called, or `no_doc_warnings`.
+    pub(crate) fn doc_warning(mut self, item_path: &str, location: &str) -> Self {
+        let warnings = self.doc_warnings.get_or_insert_with(DocWarnings::default);
+        warnings.warnings.push(DocWarning {
+            warning_type: "broken_intra_doc_links".into(),
+            item_path: item_path.into(),
+            message: format!("unresolved link to `{}`", item_path),
+            location: Some(location.into()),
+        });
+        warnings.total += 1;
+        self
+    }
+
+    pub(crate) fn no_doc_warnings(mut self) -> Self {
+        self.doc_warnings = Some(DocWarnings::default());
+        self
+    }
+
     pub(crate) fn features(mut self, features: HashMap<String, Vec<String>>) -> Self {
         self.package.features = features;
         self
     }
 
+    pub(crate) fn docs_rs_metadata(mut self, toml: &'a str) -> Self {
+        self.docs_rs_metadata = Some(toml);
+        self
+    }
+
     pub(crate) fn github_stats(
         mut self,
         repo: impl Into<String>,
@@ -292,13 +424,27 @@ impl<'a> FakeRelease<'a> {
                 rustdoc_files.push((&index, DEFAULT_CONTENT));
             }
 
-            let (rustdoc_meta, new_algs) = upload_files("rustdoc", &rustdoc_files, None)?;
+            let platform_rustdoc_files = &self.platform_rustdoc_files;
+            let platform_files = |platform: &str| {
+                let mut files = rustdoc_files.clone();
+                files.extend(
+                    platform_rustdoc_files
+                        .iter()
+                        .filter(|(file_platform, _, _)| *file_platform == platform)
+                        .map(|&(_, path, data)| (path, data)),
+                );
+                files
+            };
+
+            let default_target = self.default_target.unwrap_or("x86_64-unknown-linux-gnu");
+            let (rustdoc_meta, new_algs) =
+                upload_files("rustdoc", &platform_files(default_target), None)?;
             algs.extend(new_algs);
             log::debug!("added rustdoc files {}", rustdoc_meta);
 
             for target in &package.targets[1..] {
                 let platform = target.src_path.as_ref().unwrap();
-                upload_files("rustdoc", &rustdoc_files, Some(platform))?;
+                upload_files("rustdoc", &platform_files(platform), Some(platform))?;
                 log::debug!("added platform files for {}", platform);
             }
         }
@@ -308,10 +454,25 @@ impl<'a> FakeRelease<'a> {
             None => None,
         };
 
+        let has_examples = self.has_examples
+            || self
+                .source_files
+                .iter()
+                .any(|(path, _)| path.starts_with("examples/"));
+
         let crate_dir = tempdir.path();
         if let Some(markdown) = self.readme {
             fs::write(crate_dir.join("README.md"), markdown)?;
         }
+        if let Some(metadata) = self.docs_rs_metadata {
+            fs::write(
+                crate_dir.join("Cargo.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\n\n[package.metadata.docs.rs]\n{}",
+                    package.name, metadata
+                ),
+            )?;
+        }
         let release_id = crate::db::add_package_into_database(
             &mut db.conn(),
             &package,
@@ -320,9 +481,10 @@ impl<'a> FakeRelease<'a> {
             self.default_target.unwrap_or("x86_64-unknown-linux-gnu"),
             source_meta,
             self.doc_targets,
+            self.failed_targets,
             &self.registry_release_data,
             self.has_docs,
-            self.has_examples,
+            has_examples,
             algs,
             github_repo,
         )?;
@@ -331,9 +493,43 @@ impl<'a> FakeRelease<'a> {
             &package.name,
             &self.registry_crate_data,
         )?;
+        crate::db::add_examples_into_database(
+            &mut db.conn(),
+            release_id,
+            &crate_dir.join("source"),
+        )?;
+        let inline_tests = crate::docbuilder::inline_tests::extract_inline_tests(
+            &crate_dir.join("source"),
+            &package.name,
+        )?;
+        crate::db::add_inline_tests(&mut db.conn(), release_id, &inline_tests)?;
         crate::db::add_build_into_database(&mut db.conn(), release_id, &self.build_result)?;
         if let Some(coverage) = self.build_result.doc_coverage {
             crate::db::add_doc_coverage(&mut db.conn(), release_id, coverage)?;
+            crate::db::coverage_regression::record_coverage_regression(&mut db.conn(), release_id)?;
+        }
+        if let Some(compile_timings) = &self.compile_timings {
+            crate::db::add_compile_timings(&mut db.conn(), release_id, compile_timings)?;
+        }
+        if !self.function_signatures.is_empty() {
+            crate::db::add_function_signatures(
+                &mut db.conn(),
+                release_id,
+                &self.function_signatures,
+            )?;
+        }
+        if let Some(type_graph) = &self.type_graph {
+            crate::db::add_type_graph(&mut db.conn(), release_id, type_graph)?;
+        }
+        if let Some(doc_warnings) = &self.doc_warnings {
+            crate::db::add_doc_warnings(&mut db.conn(), release_id, Some(doc_warnings))?;
+        }
+        if !self.macro_expansions.is_empty() {
+            crate::db::add_macro_expansions_into_database(
+                &mut db.conn(),
+                release_id,
+                &self.macro_expansions,
+            )?;
         }
 
         Ok(release_id)
@@ -355,7 +551,7 @@ impl FakeGithubStats {
         let id = base64::encode(format!("FAKE ID {}", existing_count));
 
         conn.execute(
-            "INSERT INTO github_repos (id, name, description, last_commit, stars, forks, issues, updated_at)
+            "INSERT INTO github_repos (id, name, description, last_commit, stars, forks, issues, github_last_attempt)
              VALUES ($1, $2, 'Fake description!', NOW(), $3, $4, $5, NOW());",
             &[&id, &self.repo, &self.stars, &self.forks, &self.issues],
         )?;
@@ -363,3 +559,110 @@ impl FakeGithubStats {
         Ok(id)
     }
 }
+
+/// A registry index in a local git repository, which docs.rs can clone and fetch from like the
+/// real one.
+pub(crate) struct FakeIndex {
+    repo: git2::Repository,
+    repo_dir: TempDir,
+    clone_dir: TempDir,
+}
+
+impl FakeIndex {
+    pub(crate) fn new() -> Result<Self, Error> {
+        let repo_dir = tempfile::tempdir()?;
+        let repo = git2::Repository::init(repo_dir.path())?;
+        let index = FakeIndex {
+            repo,
+            repo_dir,
+            clone_dir: tempfile::tempdir()?,
+        };
+        index.commit(&[("config.json", r#"{"dl": "https://example.com"}"#.into())])?;
+
+        Ok(index)
+    }
+
+    /// Configures docs.rs to clone and fetch this index.
+    pub(crate) fn configure(&self, config: &mut Config) {
+        config.registry_index_path = self.clone_dir.path().to_path_buf();
+        config.registry_url = Some(self.repo_dir.path().display().to_string());
+    }
+
+    /// Publishes the releases, in order, with a single commit.
+    pub(crate) fn publish(&self, releases: &[(&str, &str)]) -> Result<(), Error> {
+        let mut files: Vec<(String, String)> = Vec::new();
+        for (name, version) in releases {
+            let path = crate::index::crate_file_path(name);
+            let line = format!(
+                "{}\n",
+                serde_json::json!({
+                    "name": name,
+                    "vers": version,
+                    "deps": [],
+                    "cksum": "0000000000000000000000000000000000000000000000000000000000000000",
+                    "features": {},
+                    "yanked": false,
+                })
+            );
+
+            match files.iter_mut().find(|(file, _)| *file == path) {
+                Some((_, content)) => content.push_str(&line),
+                None => {
+                    let existing =
+                        fs::read_to_string(self.repo_dir.path().join(&path)).unwrap_or_default();
+                    files.push((path, existing + &line));
+                }
+            }
+        }
+
+        self.commit(&files)
+    }
+
+    /// Marks a published release as yanked, with a single commit.
+    #[cfg(feature = "consistency_check")]
+    pub(crate) fn yank(&self, name: &str, version: &str) -> Result<(), Error> {
+        let path = crate::index::crate_file_path(name);
+        let mut content = String::new();
+        for line in fs::read_to_string(self.repo_dir.path().join(&path))?.lines() {
+            let mut release: serde_json::Value = serde_json::from_str(line)?;
+            if release["vers"] == version {
+                release["yanked"] = true.into();
+            }
+            content.push_str(&format!("{}\n", release));
+        }
+
+        self.commit(&[(path, content)])
+    }
+
+    /// Commits the files to the `master` branch of the repository.
+    fn commit(&self, files: &[(impl AsRef<str>, String)]) -> Result<(), Error> {
+        let repo = &self.repo;
+        let mut index = repo.index()?;
+        for (path, content) in files {
+            let full_path = self.repo_dir.path().join(path.as_ref());
+            fs::create_dir_all(full_path.parent().unwrap())?;
+            fs::write(full_path, content)?;
+            index.add_path(Path::new(path.as_ref()))?;
+        }
+        index.write()?;
+
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = git2::Signature::now("docs.rs", "docs@example.com")?;
+        let parent = repo
+            .refname_to_id("refs/heads/master")
+            .ok()
+            .map(|id| repo.find_commit(id))
+            .transpose()?;
+        repo.commit(
+            Some("refs/heads/master"),
+            &signature,
+            &signature,
+            "update index",
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )?;
+        repo.set_head("refs/heads/master")?;
+
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
 mod fakes;
 
+pub(crate) use self::fakes::FakeIndex;
+
+use crate::cdn::CdnKind;
 use crate::db::{Pool, PoolClient};
 use crate::storage::{Storage, StorageKind};
 use crate::web::Server;
-use crate::{BuildQueue, Config, Context, Index, Metrics};
+use crate::{BuildQueue, Cdn, Config, Context, Index, Metrics};
 use failure::Error;
+use iron::{Handler, Iron, Listening};
 use log::error;
 use once_cell::unsync::OnceCell;
 use postgres::Client as Connection;
@@ -98,14 +102,17 @@ pub(crate) struct TestEnvironment {
     storage: OnceCell<Arc<Storage>>,
     index: OnceCell<Arc<Index>>,
     metrics: OnceCell<Arc<Metrics>>,
+    cdn: OnceCell<Arc<Cdn>>,
     frontend: OnceCell<TestFrontend>,
 }
 
 pub(crate) fn init_logger() {
-    // If this fails it's probably already initialized
-    let _ = env_logger::from_env(env_logger::Env::default().filter("DOCSRS_LOG"))
+    // Wrapped by rustwide's logger, so that tests can capture the logs with
+    // `rustwide::logging::capture`. Only the first call initializes it.
+    let logger = env_logger::from_env(env_logger::Env::default().filter("DOCSRS_LOG"))
         .is_test(true)
-        .try_init();
+        .build();
+    rustwide::logging::init_with(logger);
 }
 
 impl TestEnvironment {
@@ -118,6 +125,7 @@ impl TestEnvironment {
             storage: OnceCell::new(),
             index: OnceCell::new(),
             metrics: OnceCell::new(),
+            cdn: OnceCell::new(),
             frontend: OnceCell::new(),
         }
     }
@@ -133,7 +141,9 @@ impl TestEnvironment {
         }
     }
 
-    fn base_config(&self) -> Config {
+    /// The configuration of the tests, for the components needing several configurations in one
+    /// test, as `override_config` can only be called once.
+    pub(crate) fn base_config(&self) -> Config {
         let mut config = Config::from_env().expect("failed to get base config");
 
         // Use less connections for each test compared to production.
@@ -147,6 +157,12 @@ impl TestEnvironment {
         config.s3_bucket = format!("docsrs-test-bucket-{}", rand::random::<u64>());
         config.s3_bucket_is_temporary = true;
 
+        // Record the invalidated paths instead of sending them anywhere.
+        config.cdn_backend = CdnKind::Mock;
+
+        // Retry failed builds right away, most tests don't care about the backoff.
+        config.build_retry_delay = 0;
+
         config
     }
 
@@ -197,10 +213,20 @@ impl TestEnvironment {
     pub(crate) fn index(&self) -> Arc<Index> {
         self.index
             .get_or_init(|| {
-                Arc::new(
-                    Index::new(self.config().registry_index_path.clone())
-                        .expect("failed to initialize the index"),
-                )
+                let config = self.config();
+                let index = match &config.registry_url {
+                    Some(url) => Index::from_url(config.registry_index_path.clone(), url.clone()),
+                    None => Index::new(config.registry_index_path.clone()),
+                };
+                Arc::new(index.expect("failed to initialize the index"))
+            })
+            .clone()
+    }
+
+    pub(crate) fn cdn(&self) -> Arc<Cdn> {
+        self.cdn
+            .get_or_init(|| {
+                Arc::new(Cdn::new(&self.config()).expect("failed to initialize the CDN"))
             })
             .clone()
     }
@@ -244,6 +270,10 @@ impl Context for TestEnvironment {
     fn index(&self) -> Result<Arc<Index>, Error> {
         Ok(self.index())
     }
+
+    fn cdn(&self) -> Result<Arc<Cdn>, Error> {
+        Ok(TestEnvironment::cdn(self))
+    }
 }
 
 pub(crate) struct TestDatabase {
@@ -341,4 +371,38 @@ impl TestFrontend {
     pub(crate) fn get(&self, url: &str) -> RequestBuilder {
         self.build_request(Method::GET, url)
     }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        self.build_request(Method::POST, url)
+    }
+
+    pub(crate) fn delete(&self, url: &str) -> RequestBuilder {
+        self.build_request(Method::DELETE, url)
+    }
+}
+
+/// A fake HTTP API answering the requests of a test with a handler, like the API of the registry
+/// or of GitHub. The server is closed when it's dropped.
+pub(crate) struct TestServer {
+    listening: Listening,
+}
+
+impl TestServer {
+    pub(crate) fn start(handler: impl Handler) -> Result<Self, Error> {
+        Ok(Self {
+            listening: Iron::new(handler).http("127.0.0.1:0")?,
+        })
+    }
+
+    /// The base URL of the server, like `http://127.0.0.1:1234`.
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}", self.listening.socket)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Dropping the listener would wait for the server forever, see `Server::leak`
+        let _ = self.listening.close();
+    }
 }
@@ -0,0 +1,144 @@
+//! The order the GitHub updater updates the repositories needing an update in
+
+use std::str::FromStr;
+
+#[derive(Debug, failure::Fail)]
+#[fail(
+    display = "invalid GitHub updater priority `{}`, expected one of {}",
+    _0, _1
+)]
+pub(crate) struct InvalidPriority(String, String);
+
+/// Which repositories needing an update are updated first, configured with
+/// `DOCSRS_GITHUB_UPDATER_PRIORITY`, which matters when the rate limit or the maximum duration of
+/// a run don't allow updating all of them.
+///
+/// Whatever the priority, the repositories skipped by a previous run because the rate limit was
+/// reached always come first, and the ties are broken by the time of the last attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CandidatePriority {
+    /// The repositories updated the longest time ago, so that all of them are updated in turn
+    OldestUpdated,
+    /// The repositories with the most stars, whose stats are seen the most
+    MostStars,
+    /// The repositories of the crates released most recently, which are likely to be active
+    NewestRelease,
+}
+
+const PRIORITIES: &[(CandidatePriority, &str)] = &[
+    (CandidatePriority::OldestUpdated, "oldest-updated"),
+    (CandidatePriority::MostStars, "most-stars"),
+    (CandidatePriority::NewestRelease, "newest-release"),
+];
+
+impl CandidatePriority {
+    /// Returns the joins of `github_repos` needed by the `sort_key`, to add after its `FROM`.
+    ///
+    /// The newest release of every repository is aggregated once, rather than looked up again for
+    /// each row.
+    pub(crate) fn joins(self) -> &'static str {
+        match self {
+            CandidatePriority::OldestUpdated | CandidatePriority::MostStars => "",
+            CandidatePriority::NewestRelease => {
+                "LEFT JOIN (
+                    SELECT github_repo, MAX(release_time) AS release_time
+                    FROM releases
+                    WHERE github_repo IS NOT NULL
+                    GROUP BY github_repo
+                ) AS newest_releases ON newest_releases.github_repo = github_repos.id"
+            }
+        }
+    }
+
+    /// Returns an expression of the rows of `github_repos` sorting them ascending by priority,
+    /// or `None` if only the time of their last attempt matters.
+    ///
+    /// The keys are numbers, so that the order can be resumed from a row by comparing it with
+    /// the following rows.
+    pub(crate) fn sort_key(self) -> Option<&'static str> {
+        match self {
+            CandidatePriority::OldestUpdated => None,
+            CandidatePriority::MostStars => Some("(-COALESCE(github_repos.stars, 0))::FLOAT8"),
+            CandidatePriority::NewestRelease => {
+                Some("(-COALESCE(EXTRACT(EPOCH FROM newest_releases.release_time), 0))::FLOAT8")
+            }
+        }
+    }
+
+    /// Returns the `ORDER BY` clause of the queries loading the repositories needing an update.
+    pub(crate) fn order_by(self) -> String {
+        match self.sort_key() {
+            Some(key) => format!(
+                "rate_limited_at ASC NULLS LAST, {} ASC, github_last_attempt ASC, id ASC",
+                key
+            ),
+            None => "rate_limited_at ASC NULLS LAST, github_last_attempt ASC, id ASC".into(),
+        }
+    }
+}
+
+impl Default for CandidatePriority {
+    fn default() -> Self {
+        CandidatePriority::OldestUpdated
+    }
+}
+
+impl FromStr for CandidatePriority {
+    type Err = InvalidPriority;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        PRIORITIES
+            .iter()
+            .find(|(_, name)| *name == input.trim())
+            .map(|(priority, _)| *priority)
+            .ok_or_else(|| {
+                let names: Vec<_> = PRIORITIES.iter().map(|(_, name)| *name).collect();
+                InvalidPriority(input.into(), names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_priority() {
+        assert_eq!(
+            "oldest-updated".parse::<CandidatePriority>().unwrap(),
+            CandidatePriority::OldestUpdated
+        );
+        assert_eq!(
+            " most-stars".parse::<CandidatePriority>().unwrap(),
+            CandidatePriority::MostStars
+        );
+        assert_eq!(
+            "newest-release".parse::<CandidatePriority>().unwrap(),
+            CandidatePriority::NewestRelease
+        );
+        assert!("stars".parse::<CandidatePriority>().is_err());
+    }
+
+    #[test]
+    fn order_by() {
+        assert_eq!(
+            CandidatePriority::OldestUpdated.order_by(),
+            "rate_limited_at ASC NULLS LAST, github_last_attempt ASC, id ASC"
+        );
+        assert_eq!(
+            CandidatePriority::MostStars.order_by(),
+            "rate_limited_at ASC NULLS LAST, (-COALESCE(github_repos.stars, 0))::FLOAT8 ASC, \
+             github_last_attempt ASC, id ASC"
+        );
+        assert_eq!(
+            CandidatePriority::NewestRelease.order_by(),
+            "rate_limited_at ASC NULLS LAST, \
+             (-COALESCE(EXTRACT(EPOCH FROM newest_releases.release_time), 0))::FLOAT8 ASC, \
+             github_last_attempt ASC, id ASC"
+        );
+        assert_eq!(CandidatePriority::MostStars.joins(), "");
+        assert!(CandidatePriority::NewestRelease
+            .joins()
+            .contains("GROUP BY github_repo"));
+    }
+}
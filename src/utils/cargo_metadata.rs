@@ -1,11 +1,13 @@
 use crate::error::Result;
 use rustwide::{cmd::Command, Toolchain, Workspace};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 pub(crate) struct CargoMetadata {
     root: Package,
+    /// Whether any of the dependencies of the package is a procedural macro
+    uses_proc_macros: bool,
 }
 
 impl CargoMetadata {
@@ -30,21 +32,36 @@ impl CargoMetadata {
         };
 
         let root = metadata.resolve.root;
+        let resolved: HashSet<&str> = metadata
+            .resolve
+            .nodes
+            .iter()
+            .map(|node| node.id.as_str())
+            .collect();
+        let uses_proc_macros = metadata
+            .packages
+            .iter()
+            .any(|pkg| pkg.id != root && resolved.contains(pkg.id.as_str()) && pkg.is_proc_macro());
         Ok(CargoMetadata {
             root: metadata
                 .packages
                 .into_iter()
                 .find(|pkg| pkg.id == root)
                 .unwrap(),
+            uses_proc_macros,
         })
     }
 
     pub(crate) fn root(&self) -> &Package {
         &self.root
     }
+
+    pub(crate) fn uses_proc_macros(&self) -> bool {
+        self.uses_proc_macros
+    }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub(crate) struct Package {
     pub(crate) id: String,
     pub(crate) name: String,
@@ -73,6 +90,15 @@ impl Package {
         self.library_target().is_some()
     }
 
+    /// Whether the library of the package is a procedural macro, which is always compiled for the
+    /// host whatever the target is.
+    pub(crate) fn is_proc_macro(&self) -> bool {
+        matches!(
+            self.library_target(),
+            Some(target) if target.crate_types.iter().any(|kind| kind == "proc-macro")
+        )
+    }
+
     fn normalize_package_name(&self, name: &str) -> String {
         name.replace('-', "_")
     }
@@ -88,7 +114,7 @@ impl Package {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub(crate) struct Target {
     pub(crate) name: String,
     #[cfg(not(test))]
@@ -109,7 +135,7 @@ impl Target {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub(crate) struct Dependency {
     pub(crate) name: String,
     pub(crate) req: String,
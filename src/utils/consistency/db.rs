@@ -1,60 +1,58 @@
-use super::data::{Crate, CrateName, Data, Release, Version};
-use std::collections::BTreeMap;
-
-pub(crate) fn load(conn: &mut postgres::Client) -> Result<Data, failure::Error> {
-    let rows = conn.query(
-        "
-        SELECT
-            crates.name,
-            releases.version
-        FROM crates
-        INNER JOIN releases ON releases.crate_id = crates.id
-        ORDER BY crates.id, releases.id
-    ",
-        &[],
-    )?;
+use super::data::{CrateName, Data, Release, Version};
+
+/// The crates to load from the database
+pub(crate) enum Selection<'a> {
+    /// A single crate
+    Crate(&'a str),
+    /// The crates whose names sort after the first name, up to and including the second one if
+    /// given. Names are compared bytewise, in the same order as the crates loaded from the index.
+    Range(&'a str, Option<&'a str>),
+}
 
-    let mut data = Data {
-        crates: BTreeMap::new(),
+pub(crate) fn load(
+    conn: &mut postgres::Client,
+    selection: Selection<'_>,
+) -> Result<Data, failure::Error> {
+    let query = |filter: &str| {
+        format!(
+            "
+            SELECT
+                crates.name,
+                releases.version,
+                COALESCE(releases.yanked, FALSE) AS yanked
+            FROM crates
+            INNER JOIN releases ON releases.crate_id = crates.id
+            WHERE {}
+            ORDER BY crates.id, releases.id
+            ",
+            filter
+        )
     };
 
-    let mut rows = rows.iter();
-
-    struct Current {
-        name: CrateName,
-        krate: Crate,
-    }
-
-    let mut current = if let Some(row) = rows.next() {
-        Current {
-            name: CrateName(row.get("name")),
-            krate: Crate {
-                releases: {
-                    let mut releases = BTreeMap::new();
-                    releases.insert(Version(row.get("version")), Release {});
-                    releases
-                },
-            },
+    let rows = match selection {
+        Selection::Crate(name) => conn.query(query("crates.name = $1").as_str(), &[&name])?,
+        Selection::Range(after, Some(until)) => conn.query(
+            query(r#"crates.name COLLATE "C" > $1 AND crates.name COLLATE "C" <= $2"#).as_str(),
+            &[&after, &until],
+        )?,
+        Selection::Range(after, None) => {
+            conn.query(query(r#"crates.name COLLATE "C" > $1"#).as_str(), &[&after])?
         }
-    } else {
-        return Ok(data);
     };
 
-    for row in rows {
-        let name = row.get("name");
-        if current.name != name {
-            data.crates.insert(
-                std::mem::replace(&mut current.name, CrateName(name)),
-                std::mem::take(&mut current.krate),
-            );
-        }
-        current
-            .krate
+    let mut data = Data::default();
+    for row in &rows {
+        data.crates
+            .entry(CrateName(row.get("name")))
+            .or_default()
             .releases
-            .insert(Version(row.get("version")), Release::default());
+            .insert(
+                Version(row.get("version")),
+                Release {
+                    yanked: row.get("yanked"),
+                },
+            );
     }
 
-    data.crates.insert(current.name, current.krate);
-
     Ok(data)
 }
@@ -17,7 +17,10 @@ pub(crate) struct CrateDiff {
 }
 
 #[derive(Debug)]
-pub(crate) struct ReleaseDiff {}
+pub(crate) struct ReleaseDiff {
+    /// Whether the release is yanked on each side, if they disagree
+    pub(crate) yanked: Option<(bool, bool)>,
+}
 
 pub(crate) enum Diff<Key, Value: Diffable> {
     Both(Key, Value::Diff),
@@ -102,7 +105,13 @@ impl Diffable for Crate {
 impl Diffable for Release {
     type Diff = ReleaseDiff;
 
-    fn diff(self, _other: Self) -> Self::Diff {
-        ReleaseDiff {}
+    fn diff(self, other: Self) -> Self::Diff {
+        ReleaseDiff {
+            yanked: if self.yanked != other.yanked {
+                Some((self.yanked, other.yanked))
+            } else {
+                None
+            },
+        }
     }
 }
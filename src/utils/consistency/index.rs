@@ -1,21 +1,39 @@
 use super::data::{Crate, CrateName, Data, Release, Version};
 use crate::Index;
 
-pub(crate) fn load(index: &Index) -> Result<Data, failure::Error> {
+/// Loads the releases of every crate in the index, or only the ones of `krate` if given.
+pub(crate) fn load(index: &Index, krate: Option<&str>) -> Result<Data, failure::Error> {
     let mut data = Data::default();
+    let crates = index.crates()?;
 
-    index.crates()?.walk(|krate| {
+    let mut insert = |krate: crates_index::Crate| {
         data.crates.insert(
             CrateName(krate.name().into()),
             Crate {
                 releases: krate
                     .versions()
                     .iter()
-                    .map(|version| (Version(version.version().into()), Release::default()))
+                    .map(|version| {
+                        (
+                            Version(version.version().into()),
+                            Release {
+                                yanked: version.is_yanked(),
+                            },
+                        )
+                    })
                     .collect(),
             },
         );
-    })?;
+    };
+
+    match krate {
+        Some(name) => {
+            if let Some(krate) = crates.find(name)? {
+                insert(krate);
+            }
+        }
+        None => crates.walk(insert)?,
+    }
 
     Ok(data)
 }
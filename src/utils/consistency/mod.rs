@@ -1,58 +1,445 @@
-use self::diff::{Diff, Diffable};
-use crate::Index;
+use self::data::CrateName;
+use self::db::Selection;
+use self::diff::{DataDiff, Diff, Diffable, ReleaseDiff};
+use crate::utils::get_crate_priorities;
+use crate::{BuildQueue, Index};
 use failure::ResultExt;
+use std::collections::BTreeMap;
 
 mod data;
 mod db;
 mod diff;
 mod index;
 
+/// How many crates of the index are synchronized at a time
+const CHUNK_SIZE: usize = 1000;
+
+/// The inconsistencies found between the database and the index. Unless it's a dry run, the
+/// missing releases are queued and the yanked flags are fixed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Releases in the index that are neither in the database nor in the queue
+    pub queued: Vec<(String, String)>,
+    /// Releases whose yanked flag differs from the index, with the flag in the index
+    pub yanked: Vec<(String, String, bool)>,
+    /// Crates in the database that no longer exist in the index, which are never deleted
+    /// automatically
+    pub crates_missing_upstream: Vec<String>,
+    /// Releases in the database that no longer exist in the index
+    pub releases_missing_upstream: Vec<(String, String)>,
+}
+
+/// Synchronizes the database with the index, or only `krate` if given.
+///
+/// The index is walked in chunks of crates, each fixed on its own. Unless it's a dry run, the last
+/// crate of each chunk is saved in the `config` table, so an interrupted walk of the whole index
+/// resumes after it the next time.
 pub fn run_check(
     conn: &mut postgres::Client,
     index: &Index,
+    build_queue: &BuildQueue,
+    krate: Option<&str>,
     dry_run: bool,
-) -> Result<(), failure::Error> {
-    if !dry_run {
-        failure::bail!("TODO: only a --dry-run synchronization is supported currently");
-    }
-
-    log::info!("Loading data from database...");
-    let timer = std::time::Instant::now();
-    let db_data =
-        self::db::load(conn).context("Loading crate data from database for consistency check")?;
-    log::info!("...loaded in {:?}", timer.elapsed());
+) -> Result<Report, failure::Error> {
+    synchronize(conn, index, build_queue, krate, dry_run, CHUNK_SIZE)
+}
 
+fn synchronize(
+    conn: &mut postgres::Client,
+    index: &Index,
+    build_queue: &BuildQueue,
+    krate: Option<&str>,
+    dry_run: bool,
+    chunk_size: usize,
+) -> Result<Report, failure::Error> {
     log::info!("Loading data from index...");
     let timer = std::time::Instant::now();
-    let index_data =
-        self::index::load(index).context("Loading crate data from index for consistency check")?;
+    let index_data = self::index::load(index, krate)
+        .context("Loading crate data from index for consistency check")?;
     log::info!("...loaded in {:?}", timer.elapsed());
 
-    let diff = db_data.diff(index_data);
+    let mut report = Report::default();
+
+    if let Some(krate) = krate {
+        let db_data = self::db::load(conn, Selection::Crate(krate))
+            .context("Loading crate data from database for consistency check")?;
+        apply(
+            conn,
+            index,
+            build_queue,
+            db_data.diff(index_data),
+            dry_run,
+            &mut report,
+        )?;
+        return Ok(report);
+    }
+
+    let mut after = if dry_run {
+        String::new()
+    } else {
+        load_progress(conn)?.unwrap_or_default()
+    };
+    if !after.is_empty() {
+        log::info!("Resuming synchronization after {}", after);
+    }
+
+    let mut remaining = index_data;
+    remaining.crates = remaining.crates.split_off(&CrateName(after.clone()));
+    remaining.crates.remove(&CrateName(after.clone()));
+
+    loop {
+        let rest = match remaining.crates.keys().nth(chunk_size).cloned() {
+            Some(next) => remaining.crates.split_off(&next),
+            None => BTreeMap::new(),
+        };
+        let chunk = std::mem::replace(&mut remaining.crates, rest);
+        // The last chunk also covers the crates in the database sorting after the whole index
+        let until = if remaining.crates.is_empty() {
+            None
+        } else {
+            chunk.keys().next_back().map(|name| name.0.clone())
+        };
+
+        let db_data = self::db::load(conn, Selection::Range(&after, until.as_deref()))
+            .context("Loading crate data from database for consistency check")?;
+        let index_data = self::data::Data { crates: chunk };
+        apply(
+            conn,
+            index,
+            build_queue,
+            db_data.diff(index_data),
+            dry_run,
+            &mut report,
+        )?;
+
+        match until {
+            Some(until) => {
+                if !dry_run {
+                    save_progress(conn, &until)?;
+                }
+                after = until;
+            }
+            None => break,
+        }
+    }
+
+    if !dry_run {
+        conn.execute(
+            "DELETE FROM config WHERE name = 'synchronize_progress';",
+            &[],
+        )?;
+    }
+
+    Ok(report)
+}
+
+/// Records the differences in the report, and fixes them unless it's a dry run.
+fn apply(
+    conn: &mut postgres::Client,
+    index: &Index,
+    build_queue: &BuildQueue,
+    diff: DataDiff,
+    dry_run: bool,
+    report: &mut Report,
+) -> Result<(), failure::Error> {
+    let mut missing = Vec::new();
+    let mut yanked = Vec::new();
 
     for krate in diff.crates {
         match krate {
             Diff::Both(name, diff) => {
                 for release in diff.releases {
                     match release {
+                        Diff::Both(
+                            version,
+                            ReleaseDiff {
+                                yanked: Some((_, in_index)),
+                            },
+                        ) => {
+                            log::info!(
+                                "Release yanked in {} but not in {}: {} {}",
+                                if in_index { "index" } else { "db" },
+                                if in_index { "db" } else { "index" },
+                                name,
+                                version,
+                            );
+                            yanked.push((name.0.clone(), version.0, in_index));
+                        }
                         Diff::Both(_, _) => {}
                         Diff::Left(version, _) => {
                             log::info!("Release in db not in index: {} {}", name, version);
+                            report
+                                .releases_missing_upstream
+                                .push((name.0.clone(), version.0));
                         }
                         Diff::Right(version, _) => {
                             log::info!("Release in index not in db: {} {}", name, version);
+                            missing.push((name.0.clone(), version.0));
                         }
                     }
                 }
             }
             Diff::Left(name, _) => {
-                log::info!("Crate in db not in index: {}", name);
+                log::warn!("Crate in db not in index: {}", name);
+                report.crates_missing_upstream.push(name.0);
             }
-            Diff::Right(name, _) => {
+            Diff::Right(name, krate) => {
                 log::info!("Crate in index not in db: {}", name);
+                for version in krate.releases.into_iter().map(|(version, _)| version.0) {
+                    missing.push((name.0.clone(), version));
+                }
             }
         }
     }
 
+    // The releases might be waiting in the queue already
+    let known = build_queue.queued_or_built(&missing)?;
+    missing.retain(|release| !known.contains(release));
+    let names: Vec<&str> = missing.iter().map(|(name, _)| name.as_str()).collect();
+    let priorities = get_crate_priorities(conn, &names)?;
+    let queued: Vec<_> = missing
+        .into_iter()
+        .map(|(name, version)| {
+            let priority = priorities[&name];
+            (name, version, priority)
+        })
+        .collect();
+
+    if !dry_run {
+        let releases: Vec<_> = queued
+            .iter()
+            .map(|(name, version, priority)| (name.as_str(), version.as_str(), *priority))
+            .collect();
+        build_queue.add_crates(&releases, index.repository_url())?;
+
+        for (name, version, yanked) in &yanked {
+            conn.execute(
+                "UPDATE releases
+                 SET yanked = $3
+                 FROM crates
+                 WHERE crates.id = releases.crate_id AND crates.name = $1 AND releases.version = $2;",
+                &[name, version, yanked],
+            )?;
+        }
+    }
+
+    report
+        .queued
+        .extend(queued.into_iter().map(|(name, version, _)| (name, version)));
+    report.yanked.extend(yanked);
+
     Ok(())
 }
+
+fn load_progress(conn: &mut postgres::Client) -> Result<Option<String>, failure::Error> {
+    let row = conn.query_opt(
+        "SELECT value FROM config WHERE name = 'synchronize_progress';",
+        &[],
+    )?;
+    match row {
+        Some(row) => Ok(Some(serde_json::from_value(row.get(0))?)),
+        None => Ok(None),
+    }
+}
+
+fn save_progress(conn: &mut postgres::Client, after: &str) -> Result<(), failure::Error> {
+    conn.execute(
+        "INSERT INTO config (name, value) VALUES ('synchronize_progress', $1)
+         ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value;",
+        &[&serde_json::to_value(after)?],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, FakeIndex, TestEnvironment};
+
+    /// Publishes and stores releases drifting apart from each other in every possible way.
+    fn setup(env: &TestEnvironment) -> Result<FakeIndex, failure::Error> {
+        let index = FakeIndex::new()?;
+        env.override_config(|config| index.configure(config));
+        index.publish(&[
+            ("foo", "1.0.0"),
+            ("foo", "1.1.0"),
+            ("bar", "0.1.0"),
+            ("baz", "1.0.0"),
+        ])?;
+        index.yank("bar", "0.1.0")?;
+
+        env.fake_release().name("aaa").version("1.0.0").create()?;
+        env.fake_release().name("foo").version("0.9.0").create()?;
+        env.fake_release().name("foo").version("1.0.0").create()?;
+        env.fake_release().name("bar").version("0.1.0").create()?;
+        env.fake_release()
+            .name("baz")
+            .version("1.0.0")
+            .yanked(true)
+            .create()?;
+        env.fake_release().name("qux").version("1.0.0").create()?;
+
+        Ok(index)
+    }
+
+    fn sync(
+        env: &TestEnvironment,
+        krate: Option<&str>,
+        dry_run: bool,
+        chunk_size: usize,
+    ) -> Result<Report, failure::Error> {
+        synchronize(
+            &mut env.db().conn(),
+            &env.index(),
+            &env.build_queue(),
+            krate,
+            dry_run,
+            chunk_size,
+        )
+    }
+
+    fn release(name: &str, version: &str) -> (String, String) {
+        (name.into(), version.into())
+    }
+
+    fn full_report() -> Report {
+        Report {
+            queued: vec![release("foo", "1.1.0")],
+            yanked: vec![
+                ("bar".into(), "0.1.0".into(), true),
+                ("baz".into(), "1.0.0".into(), false),
+            ],
+            crates_missing_upstream: vec!["aaa".into(), "qux".into()],
+            releases_missing_upstream: vec![release("foo", "0.9.0")],
+        }
+    }
+
+    fn yanked(env: &TestEnvironment) -> Result<Vec<(String, String)>, failure::Error> {
+        Ok(env
+            .db()
+            .conn()
+            .query(
+                "SELECT crates.name, releases.version
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE releases.yanked
+                 ORDER BY crates.name, releases.version",
+                &[],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    fn queued(env: &TestEnvironment) -> Result<Vec<(String, String)>, failure::Error> {
+        Ok(env
+            .build_queue()
+            .queued_crates()?
+            .into_iter()
+            .map(|krate| (krate.name, krate.version))
+            .collect())
+    }
+
+    #[test]
+    fn dry_run() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+
+            assert_eq!(sync(env, None, true, CHUNK_SIZE)?, full_report());
+            assert!(queued(env)?.is_empty());
+            assert_eq!(yanked(env)?, vec![release("baz", "1.0.0")]);
+            assert_eq!(load_progress(&mut env.db().conn())?, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn synchronize_in_chunks() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+
+            for chunk_size in &[1, 2, CHUNK_SIZE] {
+                let report = sync(env, None, false, *chunk_size)?;
+                if *chunk_size == 1 {
+                    assert_eq!(report, full_report());
+                    assert_eq!(queued(env)?, vec![release("foo", "1.1.0")]);
+                    assert_eq!(yanked(env)?, vec![release("bar", "0.1.0")]);
+                } else {
+                    // Everything is fixed after the first run, except the crates and releases
+                    // that were removed from the index
+                    assert_eq!(
+                        report,
+                        Report {
+                            queued: Vec::new(),
+                            yanked: Vec::new(),
+                            ..full_report()
+                        }
+                    );
+                }
+                assert_eq!(load_progress(&mut env.db().conn())?, None);
+            }
+
+            // The crates removed from the index are never deleted
+            let crates: i64 = env
+                .db()
+                .conn()
+                .query_one("SELECT COUNT(*) FROM crates", &[])?
+                .get(0);
+            assert_eq!(crates, 5);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn synchronize_single_crate() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+
+            assert_eq!(
+                sync(env, Some("foo"), false, CHUNK_SIZE)?,
+                Report {
+                    queued: vec![release("foo", "1.1.0")],
+                    releases_missing_upstream: vec![release("foo", "0.9.0")],
+                    ..Report::default()
+                }
+            );
+            assert_eq!(queued(env)?, vec![release("foo", "1.1.0")]);
+            assert_eq!(yanked(env)?, vec![release("baz", "1.0.0")]);
+
+            assert_eq!(
+                sync(env, Some("qux"), false, CHUNK_SIZE)?,
+                Report {
+                    crates_missing_upstream: vec!["qux".into()],
+                    ..Report::default()
+                }
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn resume_synchronization() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+            // An earlier run was interrupted after the chunk ending with `baz`
+            save_progress(&mut env.db().conn(), "baz")?;
+
+            assert_eq!(
+                sync(env, None, false, 1)?,
+                Report {
+                    queued: vec![release("foo", "1.1.0")],
+                    crates_missing_upstream: vec!["qux".into()],
+                    releases_missing_upstream: vec![release("foo", "0.9.0")],
+                    ..Report::default()
+                }
+            );
+            assert_eq!(yanked(env)?, vec![release("baz", "1.0.0")]);
+            assert_eq!(load_progress(&mut env.db().conn())?, None);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,166 @@
+use crate::error::Result;
+use postgres::Client;
+
+/// How many crates have their dependencies paired in each transaction.
+const CHUNK_SIZE: i64 = 1000;
+
+/// Counts how many crates depend on each pair of crates, which is stored in `crate_cooccurrence`
+/// in both directions.
+///
+/// Only the normal dependencies of the latest release of each crate count, and crates depending
+/// on a crate several times, like with different targets, count once.
+pub fn update_crate_cooccurrence(conn: &mut Client) -> Result<()> {
+    update_in_chunks(conn, CHUNK_SIZE)
+}
+
+/// Pairs the dependencies of `chunk_size` crates at a time, each chunk in its own transaction, in
+/// a temporary table of the connection. The counts are then replaced at once, so the previous
+/// counts are served until the new ones are complete.
+fn update_in_chunks(conn: &mut Client, chunk_size: i64) -> Result<()> {
+    conn.batch_execute(
+        "CREATE TEMPORARY TABLE IF NOT EXISTS cooccurrence_counts (
+             name TEXT NOT NULL,
+             other TEXT NOT NULL,
+             count INT NOT NULL
+         );
+         TRUNCATE cooccurrence_counts;",
+    )?;
+
+    let mut last_crate = 0;
+    loop {
+        let chunk_end: Option<i32> = conn
+            .query_one(
+                "SELECT MAX(id) FROM (
+                     SELECT id FROM crates WHERE id > $1 ORDER BY id LIMIT $2
+                 ) AS chunk;",
+                &[&last_crate, &chunk_size],
+            )?
+            .get(0);
+        let chunk_end = match chunk_end {
+            Some(id) => id,
+            None => break,
+        };
+
+        conn.execute(
+            "WITH dependencies AS (
+                 SELECT DISTINCT crates.id AS dependent, dependency->>0 AS name
+                 FROM crates
+                 INNER JOIN releases ON releases.id = crates.latest_version_id
+                 CROSS JOIN json_array_elements(releases.dependencies) AS dependency
+                 WHERE crates.id > $1 AND crates.id <= $2
+                     AND COALESCE(dependency->>2, 'normal') = 'normal'
+             )
+             INSERT INTO cooccurrence_counts (name, other, count)
+             SELECT a.name, b.name, COUNT(*)
+             FROM dependencies AS a
+             INNER JOIN dependencies AS b ON b.dependent = a.dependent AND b.name <> a.name
+             GROUP BY a.name, b.name;",
+            &[&last_crate, &chunk_end],
+        )?;
+        last_crate = chunk_end;
+    }
+
+    let mut transaction = conn.transaction()?;
+    transaction.batch_execute(
+        "DELETE FROM crate_cooccurrence;
+         INSERT INTO crate_cooccurrence (name, other, count)
+         SELECT name, other, SUM(count)::INT
+         FROM cooccurrence_counts
+         GROUP BY name, other;",
+    )?;
+    transaction.commit()?;
+    conn.batch_execute("DROP TABLE cooccurrence_counts;")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    /// The counts, leaving out the dependency every fake release has.
+    fn cooccurrence(conn: &mut Client) -> Result<Vec<(String, String, i32)>> {
+        Ok(conn
+            .query(
+                "SELECT name, other, count
+                 FROM crate_cooccurrence
+                 WHERE 'fake-dependency' NOT IN (name, other)
+                 ORDER BY name, other",
+                &[],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect())
+    }
+
+    #[test]
+    fn count_cooccurrence() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("app")
+                .version("1.0.0")
+                .add_dependency("serde", "1", None)
+                .add_dependency("serde_json", "1", Some("normal"))
+                .add_dependency("log", "0.4", None)
+                .add_dependency("tempfile", "3", Some("dev"))
+                .create()?;
+            env.fake_release()
+                .name("cli")
+                .version("1.0.0")
+                .add_dependency("serde", "1", None)
+                .add_dependency("serde_json", "1", None)
+                // Depending twice, like for different targets, counts once
+                .add_dependency("serde_json", "1", None)
+                .add_dependency("tempfile", "3", Some("dev"))
+                .create()?;
+            // Only the latest release of each crate counts
+            env.fake_release()
+                .name("former")
+                .version("1.0.0")
+                .add_dependency("serde", "1", None)
+                .add_dependency("log", "0.4", None)
+                .create()?;
+            env.fake_release()
+                .name("former")
+                .version("2.0.0")
+                .add_dependency("serde", "1", None)
+                .create()?;
+
+            let mut conn = env.db().conn();
+            update_crate_cooccurrence(&mut conn)?;
+            let expected = vec![
+                ("log".to_string(), "serde".to_string(), 1),
+                ("log".into(), "serde_json".into(), 1),
+                ("serde".into(), "log".into(), 1),
+                ("serde".into(), "serde_json".into(), 2),
+                ("serde_json".into(), "log".into(), 1),
+                ("serde_json".into(), "serde".into(), 2),
+            ];
+            assert_eq!(cooccurrence(&mut conn)?, expected);
+            // The counts of the chunks add up
+            update_in_chunks(&mut conn, 1)?;
+            assert_eq!(cooccurrence(&mut conn)?, expected);
+
+            // Running again replaces the counts
+            env.fake_release()
+                .name("cli")
+                .version("2.0.0")
+                .add_dependency("serde", "1", None)
+                .create()?;
+            update_crate_cooccurrence(&mut conn)?;
+            assert_eq!(
+                cooccurrence(&mut conn)?,
+                expected
+                    .into_iter()
+                    .map(|(name, other, count)| {
+                        let count = if count == 2 { 1 } else { count };
+                        (name, other, count)
+                    })
+                    .collect::<Vec<_>>()
+            );
+
+            Ok(())
+        });
+    }
+}
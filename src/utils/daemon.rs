@@ -2,129 +2,806 @@
 //!
 //! This daemon will start web server, track new packages and build them
 
+use super::owners_updater::REQUEST_DELAY;
+use super::schedule::Schedule;
+use super::systemd;
 use crate::{
-    utils::{queue_builder, update_release_activity, GithubUpdater},
-    Context, DocBuilder, RustwideBuilder,
+    db::{
+        heartbeat::{record_job_start, record_job_success},
+        InstanceLock, InstanceRole, Pool,
+    },
+    notifications::Notifier,
+    utils::{
+        queue_builder, update_crate_cooccurrence, update_crate_owners, update_health_scores,
+        update_release_activity, GithubUpdater,
+    },
+    Context, DocBuilder, Metrics, RustwideBuilder,
 };
-use chrono::{Timelike, Utc};
+use chrono::Utc;
 use failure::Error;
-use log::{debug, error, info};
-use std::thread;
+use futures_util::future;
+use log::{debug, error, info, warn};
+use rand::Rng;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
 
-fn start_registry_watcher(context: &dyn Context) -> Result<(), Error> {
+/// How long a scheduled task waits before running again after panicking, doubled after each
+/// consecutive panic up to `MAX_RESTART_BACKOFF`, so a task crashing on every run doesn't spin.
+const RESTART_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// How often the daemon records that it still holds the builder lock.
+const LOCK_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// The longest random delay added to the first run of a scheduled task, so the tasks don't all
+/// start at once after a restart. It's also kept under a tenth of the time until the first run.
+const MAX_START_JITTER: Duration = Duration::from_secs(30);
+
+/// Schedules the polls of the registry index, setting `polled` once the first one finished.
+fn start_registry_watcher(
+    context: &dyn Context,
+    shutdown: Arc<AtomicBool>,
+    polled: Arc<AtomicBool>,
+) -> Result<Option<JoinHandle<()>>, Error> {
     let pool = context.pool()?;
     let build_queue = context.build_queue()?;
     let config = context.config()?;
     let index = context.index()?;
+    let schedule = config.registry_watcher_schedule.clone();
+    let last_gc = Mutex::new(Instant::now());
 
-    thread::Builder::new()
-        .name("registry index reader".to_string())
-        .spawn(move || {
-            // space this out to prevent it from clashing against the queue-builder thread on launch
-            thread::sleep(Duration::from_secs(30));
-
-            let mut last_gc = Instant::now();
-            loop {
-                let mut doc_builder =
-                    DocBuilder::new(config.clone(), pool.clone(), build_queue.clone());
-
-                if doc_builder.is_locked() {
-                    debug!("Lock file exists, skipping checking new crates");
-                } else {
-                    debug!("Checking new crates");
-                    match doc_builder.get_new_crates(&index) {
-                        Ok(n) => debug!("{} crates added to queue", n),
-                        Err(e) => error!("Failed to get new crates: {}", e),
-                    }
-                }
+    schedule_job(
+        context.pool()?,
+        context.metrics()?,
+        "registry index reader",
+        &schedule,
+        shutdown,
+        move || {
+            // the jitter keeps multiple instances from polling the index at the same time
+            let jitter = rand::thread_rng().gen_range(0, config.registry_poll_jitter + 1);
+            thread::sleep(Duration::from_secs(jitter));
 
-                if last_gc.elapsed().as_secs() >= config.registry_gc_interval {
-                    index.run_git_gc();
-                    last_gc = Instant::now();
-                }
-                thread::sleep(Duration::from_secs(60));
+            let mut doc_builder =
+                DocBuilder::new(config.clone(), pool.clone(), build_queue.clone());
+            let result = if doc_builder.is_locked() {
+                debug!("Lock file exists, skipping checking new crates");
+                Ok(())
+            } else {
+                debug!("Checking new crates");
+                doc_builder
+                    .get_new_crates(&index)
+                    .map(|n| debug!("{} crates added to queue", n))
+            };
+
+            // A panic while collecting the garbage doesn't stop the next runs
+            let mut last_gc = last_gc.lock().unwrap_or_else(PoisonError::into_inner);
+            if last_gc.elapsed().as_secs() >= config.registry_gc_interval {
+                index.run_git_gc();
+                *last_gc = Instant::now();
             }
-        })?;
 
-    Ok(())
+            polled.store(true, Ordering::SeqCst);
+            result
+        },
+    )
 }
 
 pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Result<(), Error> {
+    // Two daemons against the same database would build the same crates, so the daemon refuses
+    // to start while another one holds the lock
+    let mut lock = InstanceLock::acquire(&context.pool()?, InstanceRole::Builder)?;
+
     // Start the web server before doing anything more expensive
     // Please check with an administrator before changing this (see #1172 for context).
     info!("Starting web server");
     let server = crate::Server::start(None, false, context)?;
+    let requests = server.requests();
     let server_thread = thread::spawn(|| drop(server));
 
     let config = context.config()?;
+    let shutdown = Arc::new(AtomicBool::new(false));
 
+    // The daemon is only ready once it knows about the new releases
+    let registry_polled = Arc::new(AtomicBool::new(!enable_registry_watcher));
+    // The scheduled jobs, waited for during the shutdown
+    let mut jobs = Vec::new();
     if enable_registry_watcher {
-        // check new crates every minute
-        start_registry_watcher(context)?;
+        jobs.extend(start_registry_watcher(
+            context,
+            shutdown.clone(),
+            registry_polled.clone(),
+        )?);
     }
 
-    // build new crates every minute
-    let pool = context.pool()?;
+    // The builds a previous run of the daemon was doing when it crashed are built again, which
+    // also cleans up the files they stored before crashing
     let build_queue = context.build_queue()?;
-    let cloned_config = config.clone();
-    let rustwide_builder = RustwideBuilder::init(context)?;
-    thread::Builder::new()
-        .name("build queue reader".to_string())
-        .spawn(move || {
-            let doc_builder =
-                DocBuilder::new(cloned_config.clone(), pool.clone(), build_queue.clone());
-            queue_builder(doc_builder, rustwide_builder, build_queue).unwrap();
-        })
-        .unwrap();
+    let storage = context.storage()?;
+    let recovered = build_queue.recover_builds_at_startup(&storage)?;
+    if !recovered.is_empty() {
+        warn!("Recovered {} abandoned builds", recovered.len());
+    }
+
+    // build new crates every minute
+    let workers = start_build_workers(context, shutdown.clone())?;
+
+    jobs.extend(schedule_job(
+        context.pool()?,
+        context.metrics()?,
+        "stale builds recovery",
+        &config.build_recovery_schedule,
+        shutdown.clone(),
+        move || {
+            let recovered = build_queue.recover_stale_builds(&storage)?;
+            if !recovered.is_empty() {
+                info!("Recovered {} abandoned builds", recovered.len());
+            }
+            Ok(())
+        },
+    )?);
 
-    // update release activity everyday at 23:55
     let pool = context.pool()?;
-    cron(
+    jobs.extend(schedule_job(
+        context.pool()?,
+        context.metrics()?,
         "release activity updater",
-        Duration::from_secs(60),
+        &config.release_activity_schedule,
+        shutdown.clone(),
         move || {
-            let now = Utc::now();
-            if now.hour() == 23 && now.minute() == 55 {
-                info!("Updating release activity");
-                update_release_activity(&mut *pool.get()?)?;
-            }
+            info!("Updating release activity");
+            update_release_activity(&mut *pool.get()?)
+        },
+    )?);
+
+    let pool = context.pool()?;
+    jobs.extend(schedule_job(
+        context.pool()?,
+        context.metrics()?,
+        "crate co-occurrence updater",
+        &config.cooccurrence_schedule,
+        shutdown.clone(),
+        move || {
+            info!("Updating the crates used together");
+            update_crate_cooccurrence(&mut *pool.get()?)
+        },
+    )?);
+
+    let pool = context.pool()?;
+    jobs.extend(schedule_job(
+        context.pool()?,
+        context.metrics()?,
+        "crate health score updater",
+        &config.health_score_schedule,
+        shutdown.clone(),
+        move || {
+            info!("Updating the health scores of the crates");
+            let scored = update_health_scores(&mut *pool.get()?)?;
+            debug!("scored the health of {} crates", scored);
             Ok(())
         },
-    )?;
+    )?);
 
-    if let Some(github_updater) = GithubUpdater::new(config, context.pool()?)? {
-        cron(
+    let index = context.index()?;
+    if index.api().has_api_base() {
+        let pool = context.pool()?;
+        let batch_size = config.owners_update_batch_size;
+        jobs.extend(schedule_job(
+            context.pool()?,
+            context.metrics()?,
+            "crate owners updater",
+            &config.owners_updater_schedule,
+            shutdown.clone(),
+            move || {
+                update_crate_owners(&mut *pool.get()?, index.api(), batch_size, REQUEST_DELAY)?;
+                Ok(())
+            },
+        )?);
+    } else {
+        log::warn!("crate owners updater not started as the registry has no API");
+    }
+
+    if let Some(github_updater) = GithubUpdater::new(config.clone(), context.pool()?)? {
+        let github_updater = github_updater
+            .registry_fallback(&*context.index()?)
+            .stop_on(shutdown.clone());
+        jobs.extend(schedule_job(
+            context.pool()?,
+            context.metrics()?,
             "github stats updater",
-            Duration::from_secs(60 * 60),
+            &config.github_updater_schedule,
+            shutdown.clone(),
             move || {
                 github_updater.update_all_crates()?;
                 Ok(())
             },
-        )?;
+        )?);
     } else {
         log::warn!("GitHub stats updater not started as no token was provided");
     }
 
-    // Never returns; `server` blocks indefinitely when dropped
+    // Also refreshes the repositories of the new releases, even without the webhook
+    if let Some(github_updater) = GithubUpdater::new(config.clone(), context.pool()?)? {
+        let github_updater = github_updater.stop_on(shutdown.clone());
+        jobs.extend(schedule_job(
+            context.pool()?,
+            context.metrics()?,
+            "github webhook events",
+            &config.github_webhook_schedule,
+            shutdown.clone(),
+            move || {
+                let updated = github_updater.update_webhook_repositories()?;
+                if updated > 0 {
+                    debug!(
+                        "updated {} repositories sent to the GitHub webhook or queued by builds",
+                        updated
+                    );
+                }
+                Ok(())
+            },
+        )?);
+    }
+
+    let notifier = Notifier::new(&config, context.pool()?)?;
+    jobs.extend(schedule_job(
+        context.pool()?,
+        context.metrics()?,
+        "notifications dispatcher",
+        &config.notifications_schedule,
+        shutdown.clone(),
+        move || {
+            let sent = notifier.dispatch_pending()?;
+            if sent > 0 {
+                info!("sent {} notifications", sent);
+            }
+            Ok(())
+        },
+    )?);
+
+    // `server` blocks indefinitely when dropped, so this only returns when the web server panics
+    // or the daemon is asked to shut down.
     // NOTE: if a failure occurred earlier in `start_daemon`, the server will _not_ be joined -
     // instead it will get killed when the process exits.
-    server_thread
-        .join()
-        .map_err(|_| failure::err_msg("web server panicked"))
+    start_shutdown_listener(shutdown.clone())?;
+    let mut ready = false;
+    if !registry_polled.load(Ordering::SeqCst) {
+        systemd::notify_status("waiting for the first poll of the registry");
+    }
+    let watchdog_interval = systemd::watchdog_interval();
+    let mut last_watchdog = Instant::now();
+    let mut last_heartbeat = Instant::now();
+    // Acquiring the lock recorded its first heartbeat
+    let mut last_recorded_heartbeat = Instant::now();
+    while !shutdown.load(Ordering::SeqCst) {
+        if server_thread.is_finished() {
+            return server_thread
+                .join()
+                .map_err(|_| failure::err_msg("web server panicked"));
+        }
+        if !ready && registry_polled.load(Ordering::SeqCst) {
+            systemd::notify_ready("running");
+            ready = true;
+        }
+        if let Some(interval) = watchdog_interval {
+            // systemd restarts the daemon if it stops building, not only if it hangs
+            if last_watchdog.elapsed() >= interval
+                && builder_is_alive(last_recorded_heartbeat, &workers)
+            {
+                systemd::notify_watchdog();
+                last_watchdog = Instant::now();
+            }
+        }
+        if last_heartbeat.elapsed() >= LOCK_HEARTBEAT_INTERVAL {
+            match lock.heartbeat() {
+                Ok(()) => last_recorded_heartbeat = Instant::now(),
+                Err(err) => warn!(
+                    "failed to record the heartbeat of the builder lock: {}",
+                    err
+                ),
+            }
+            last_heartbeat = Instant::now();
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    info!("Shutting down, waiting for the builds, jobs and requests in progress to finish");
+    systemd::notify_stopping();
+    systemd::notify_status("waiting for the builds, jobs and requests in progress to finish");
+    let grace_period = Duration::from_secs(context.config()?.build_shutdown_grace_period);
+    let deadline = Instant::now() + grace_period;
+    // The web server is drained while the builds and the jobs finish, and all share the grace
+    // period
+    let draining = thread::spawn(move || requests.drain(grace_period));
+    let abandoned = join_until(workers, deadline);
+    if abandoned > 0 {
+        // The builds are abandoned when the process exits, and releasing their claims lets the
+        // other instances build them without waiting for the claims to time out.
+        let released = context.build_queue()?.release_claimed_builds()?;
+        warn!(
+            "Builds still running after {}s, abandoning {} of them",
+            grace_period.as_secs(),
+            released
+        );
+    }
+    let abandoned = join_until(jobs, deadline);
+    if abandoned > 0 {
+        warn!(
+            "Scheduled tasks still running after {}s, abandoning {} of them",
+            grace_period.as_secs(),
+            abandoned
+        );
+    }
+    match draining.join() {
+        Ok(0) => {}
+        Ok(unfinished) => warn!(
+            "Requests still in progress after {}s, abandoning {} of them",
+            grace_period.as_secs(),
+            unfinished
+        ),
+        Err(_) => error!("failed to drain the web server"),
+    }
+
+    lock.release()?;
+    Ok(())
+}
+
+/// Joins the threads finishing before `deadline`, and returns how many are still running then.
+fn join_until(threads: Vec<JoinHandle<()>>, deadline: Instant) -> usize {
+    while threads.iter().any(|thread| !thread.is_finished()) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+    let (finished, running): (Vec<_>, Vec<_>) =
+        threads.into_iter().partition(|thread| thread.is_finished());
+    for thread in finished {
+        let name = thread.thread().name().unwrap_or("unnamed").to_string();
+        if thread.join().is_err() {
+            error!("thread '{}' panicked", name);
+        }
+    }
+    running.len()
 }
 
-fn cron<F>(name: &'static str, interval: Duration, exec: F) -> Result<(), Error>
+/// Whether the daemon is still building: the heartbeat of the builder lock was recorded in the
+/// last two intervals, and none of the build workers stopped.
+fn builder_is_alive(last_recorded_heartbeat: Instant, workers: &[JoinHandle<()>]) -> bool {
+    last_recorded_heartbeat.elapsed() < LOCK_HEARTBEAT_INTERVAL * 2
+        && workers.iter().all(|worker| !worker.is_finished())
+}
+
+/// Spawns the build workers, which build the crates of the queue until `shutdown` is set.
+fn start_build_workers(
+    context: &dyn Context,
+    shutdown: Arc<AtomicBool>,
+) -> Result<Vec<JoinHandle<()>>, Error> {
+    let config = context.config()?;
+    let mut workers = Vec::new();
+    for worker in 0..config.build_workers.max(1) {
+        let pool = context.pool()?;
+        let build_queue = context.build_queue()?;
+        let cloned_config = config.clone();
+        let rustwide_builder = RustwideBuilder::init_worker(context, worker)?;
+        let shutdown = shutdown.clone();
+        let name = if worker == 0 {
+            "build queue reader".to_string()
+        } else {
+            format!("build queue reader {}", worker)
+        };
+
+        workers.push(thread::Builder::new().name(name).spawn(move || {
+            let doc_builder = DocBuilder::new(cloned_config, pool, build_queue.clone());
+            queue_builder(doc_builder, rustwide_builder, build_queue, shutdown).unwrap();
+        })?);
+    }
+
+    Ok(workers)
+}
+
+/// Sets `shutdown` when the process receives `SIGINT` or `SIGTERM`.
+fn start_shutdown_listener(shutdown: Arc<AtomicBool>) -> Result<(), Error> {
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_io()
+        .build()?;
+
+    thread::Builder::new()
+        .name("shutdown listener".into())
+        .spawn(move || {
+            runtime.block_on(async {
+                let mut terminate =
+                    signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+                let interrupt = Box::pin(tokio::signal::ctrl_c());
+                future::select(interrupt, Box::pin(terminate.recv())).await;
+            });
+            shutdown.store(true, Ordering::SeqCst);
+        })?;
+
+    Ok(())
+}
+
+/// Runs `exec` on `schedule` until `shutdown` is set, recording when each run started and
+/// succeeded in the heartbeats. Returns `None` without starting anything if the job is disabled.
+///
+/// Every job runs on its own thread, and a panicking run is logged and counted like a failed one,
+/// so it can't stop the other jobs nor the next runs of the job. The runs following a panic wait
+/// for at least `RESTART_BACKOFF` though.
+///
+/// A run in progress when `shutdown` is set is finished, so long jobs are expected to check it
+/// themselves.
+fn schedule_job<F>(
+    pool: Pool,
+    metrics: Arc<Metrics>,
+    name: &'static str,
+    schedule: &Schedule,
+    shutdown: Arc<AtomicBool>,
+    exec: F,
+) -> Result<Option<JoinHandle<()>>, Error>
 where
     F: Fn() -> Result<(), Error> + Send + 'static,
 {
-    thread::Builder::new()
+    let now = Utc::now();
+    let mut next = match schedule.next_run(now) {
+        Some(next) => next,
+        None => {
+            info!("scheduled task '{}' is disabled", name);
+            return Ok(None);
+        }
+    };
+    if let Ok(until_first_run) = (next - now).to_std() {
+        next = next + chrono::Duration::from_std(start_jitter(until_first_run))?;
+    }
+    info!(
+        "scheduled task '{}' runs {}, next at {}",
+        name, schedule, next
+    );
+
+    let schedule = schedule.clone();
+    let mut panics = 0;
+    let handle = thread::Builder::new()
         .name(name.into())
         .spawn(move || loop {
-            thread::sleep(interval);
-            if let Err(err) = exec() {
-                error!("failed to run scheduled task '{}': {:?}", name, err);
+            // sleep in small steps, to notice a shutdown quickly
+            while !shutdown.load(Ordering::SeqCst) {
+                match (next - Utc::now()).to_std() {
+                    Ok(remaining) if remaining > Duration::from_millis(0) => {
+                        thread::sleep(remaining.min(Duration::from_secs(1)))
+                    }
+                    _ => break,
+                }
+            }
+            if shutdown.load(Ordering::SeqCst) {
+                info!("Shutting down, stopping scheduled task '{}'", name);
+                return;
+            }
+
+            record_heartbeat(&pool, name, record_job_start);
+            match panic::catch_unwind(AssertUnwindSafe(&exec)) {
+                Ok(Ok(())) => {
+                    panics = 0;
+                    record_heartbeat(&pool, name, record_job_success);
+                }
+                Ok(Err(err)) => {
+                    panics = 0;
+                    metrics
+                        .failed_scheduled_jobs
+                        .with_label_values(&[name])
+                        .inc();
+                    error!("failed to run scheduled task '{}': {:?}", name, err);
+                }
+                Err(payload) => {
+                    panics += 1;
+                    metrics
+                        .failed_scheduled_jobs
+                        .with_label_values(&[name])
+                        .inc();
+                    error!(
+                        "scheduled task '{}' panicked: {}",
+                        name,
+                        panic_message(&*payload)
+                    );
+                }
+            }
+
+            let now = Utc::now();
+            next = match schedule.next_run(now) {
+                Some(next) => next,
+                None => return,
+            };
+            if panics > 0 {
+                let backoff = restart_backoff(panics);
+                warn!(
+                    "scheduled task '{}' panicked {} times in a row, waiting {}s to run it again",
+                    name,
+                    panics,
+                    backoff.as_secs()
+                );
+                // The backoff is always far from overflowing the date
+                next = next.max(now + chrono::Duration::from_std(backoff).unwrap());
             }
+            debug!("scheduled task '{}' runs next at {}", name, next);
         })?;
-    Ok(())
+    Ok(Some(handle))
+}
+
+/// How long to wait before running a task again after `panics` consecutive panics.
+fn restart_backoff(panics: u32) -> Duration {
+    RESTART_BACKOFF
+        .checked_mul(2u32.saturating_pow(panics.saturating_sub(1)))
+        .map_or(MAX_RESTART_BACKOFF, |backoff| {
+            backoff.min(MAX_RESTART_BACKOFF)
+        })
+}
+
+/// Returns a random delay for the first run of a task, `until_first_run` from now.
+fn start_jitter(until_first_run: Duration) -> Duration {
+    let max = (until_first_run / 10).min(MAX_START_JITTER);
+    Duration::from_millis(rand::thread_rng().gen_range(0, max.as_millis() as u64 + 1))
+}
+
+/// Returns the message a thread panicked with.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Records the heartbeat of a job, which is only logged on failure as the job itself is fine.
+fn record_heartbeat(
+    pool: &Pool,
+    name: &str,
+    record: fn(&mut postgres::Client, &str) -> Result<(), Error>,
+) {
+    let result = pool
+        .get()
+        .map_err(Error::from)
+        .and_then(|mut conn| record(&mut *conn, name));
+    if let Err(err) = result {
+        warn!(
+            "failed to record the heartbeat of scheduled task '{}': {}",
+            name, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::heartbeat::heartbeats;
+    use crate::test::wrapper;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn threads_are_joined_until_deadline() {
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let threads = vec![
+            thread::spawn(|| {}),
+            thread::spawn(move || {
+                let _ = stopped.recv();
+            }),
+        ];
+        let deadline = Instant::now() + Duration::from_millis(300);
+        assert_eq!(join_until(threads, deadline), 1);
+        assert!(Instant::now() >= deadline);
+        drop(stop);
+
+        let threads = vec![thread::spawn(|| thread::sleep(Duration::from_millis(50)))];
+        assert_eq!(
+            join_until(threads, Instant::now() + Duration::from_secs(10)),
+            0
+        );
+    }
+
+    #[test]
+    fn builder_liveness() {
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let mut workers = vec![thread::spawn(move || {
+            let _ = stopped.recv();
+        })];
+        assert!(builder_is_alive(Instant::now(), &workers));
+
+        // The heartbeat of the lock couldn't be recorded for a while
+        let stale = Instant::now() - LOCK_HEARTBEAT_INTERVAL * 3;
+        assert!(!builder_is_alive(stale, &workers));
+
+        // A worker stopped
+        workers.push(thread::spawn(|| {}));
+        while !workers[1].is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!builder_is_alive(Instant::now(), &workers));
+
+        drop(stop);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn job_stops_on_shutdown() {
+        wrapper(|env| {
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let runs = Arc::new(AtomicUsize::new(0));
+            let job_runs = runs.clone();
+            // A job lasting longer than its interval, still running when the shutdown starts
+            let handle = schedule_job(
+                env.db().pool(),
+                env.metrics(),
+                "long job",
+                &Schedule::Every(Duration::from_millis(10)),
+                shutdown.clone(),
+                move || {
+                    job_runs.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(200));
+                    Ok(())
+                },
+            )?
+            .expect("the job is disabled");
+
+            while runs.load(Ordering::SeqCst) == 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+            shutdown.store(true, Ordering::SeqCst);
+            handle.join().unwrap();
+            // The run in progress was finished, but no other one was started
+            assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+            let beats = heartbeats(&mut env.db().conn())?;
+            assert_eq!(beats.len(), 1);
+            assert_eq!(beats[0].job, "long job");
+            assert!(beats[0].last_start.is_some());
+            assert!(beats[0].last_success.is_some());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failed_runs_have_no_success() {
+        wrapper(|env| {
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let job_shutdown = shutdown.clone();
+            let handle = schedule_job(
+                env.db().pool(),
+                env.metrics(),
+                "failing job",
+                &Schedule::Every(Duration::from_millis(10)),
+                shutdown.clone(),
+                move || {
+                    job_shutdown.store(true, Ordering::SeqCst);
+                    failure::bail!("the job failed")
+                },
+            )?
+            .expect("the job is disabled");
+            handle.join().unwrap();
+
+            let beats = heartbeats(&mut env.db().conn())?;
+            assert!(beats[0].last_start.is_some());
+            assert_eq!(beats[0].last_success, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn panicking_job_is_isolated() {
+        wrapper(|env| {
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let (panicking_runs, other_runs) =
+                (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+            let runs = panicking_runs.clone();
+            let panicking = schedule_job(
+                env.db().pool(),
+                env.metrics(),
+                "panicking job",
+                &Schedule::Every(Duration::from_millis(10)),
+                shutdown.clone(),
+                move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    panic!("the job is broken");
+                },
+            )?
+            .expect("the job is disabled");
+            let runs = other_runs.clone();
+            let other = schedule_job(
+                env.db().pool(),
+                env.metrics(),
+                "other job",
+                &Schedule::Every(Duration::from_millis(10)),
+                shutdown.clone(),
+                move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                },
+            )?
+            .expect("the job is disabled");
+
+            let start = Instant::now();
+            while other_runs.load(Ordering::SeqCst) < 5 {
+                assert!(start.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(10));
+            }
+            shutdown.store(true, Ordering::SeqCst);
+            // The panic was caught, so the thread of the job stopped normally
+            panicking.join().unwrap();
+            other.join().unwrap();
+
+            // The other job kept running, while the panicking one waits for the backoff
+            assert_eq!(panicking_runs.load(Ordering::SeqCst), 1);
+            assert_eq!(
+                env.metrics()
+                    .failed_scheduled_jobs
+                    .with_label_values(&["panicking job"])
+                    .get(),
+                1
+            );
+            let beats = heartbeats(&mut env.db().conn())?;
+            let beat = beats
+                .iter()
+                .find(|beat| beat.job == "panicking job")
+                .unwrap();
+            assert!(beat.last_start.is_some());
+            assert_eq!(beat.last_success, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn restart_backoffs() {
+        assert_eq!(restart_backoff(1), Duration::from_secs(60));
+        assert_eq!(restart_backoff(2), Duration::from_secs(120));
+        assert_eq!(restart_backoff(3), Duration::from_secs(240));
+        assert_eq!(restart_backoff(10), MAX_RESTART_BACKOFF);
+        assert_eq!(restart_backoff(1000), MAX_RESTART_BACKOFF);
+
+        assert!(start_jitter(Duration::from_secs(60 * 60)) <= MAX_START_JITTER);
+        assert!(start_jitter(Duration::from_secs(10)) <= Duration::from_secs(1));
+        assert_eq!(start_jitter(Duration::from_secs(0)), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn job_sleeps_in_small_steps() {
+        wrapper(|env| {
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let handle = schedule_job(
+                env.db().pool(),
+                env.metrics(),
+                "hourly job",
+                &"1h".parse()?,
+                shutdown.clone(),
+                || panic!("the job shouldn't run before the shutdown"),
+            )?
+            .expect("the job is disabled");
+
+            let start = Instant::now();
+            shutdown.store(true, Ordering::SeqCst);
+            handle.join().unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn disabled_jobs_never_run() {
+        wrapper(|env| {
+            let handle = schedule_job(
+                env.db().pool(),
+                env.metrics(),
+                "disabled job",
+                &"off".parse()?,
+                Arc::new(AtomicBool::new(false)),
+                || panic!("disabled jobs shouldn't run"),
+            )?;
+            assert!(handle.is_none());
+            assert!(heartbeats(&mut env.db().conn())?.is_empty());
+
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,125 @@
+//! The optional parts of the repository stats the GitHub updater asks for
+
+use std::str::FromStr;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid GitHub field `{}`, expected one of {}", _0, _1)]
+pub(crate) struct InvalidField(String, String);
+
+bitflags::bitflags! {
+    /// The optional fields of the repository stats, each of which makes the queries of the
+    /// updater more expensive. Configured with `DOCSRS_GITHUB_FIELDS` as comma separated names,
+    /// like `license,fork_parent`, and empty to only ask for the base stats.
+    ///
+    /// The columns of the fields that aren't fetched are left as they are.
+    pub(crate) struct FieldSet: u8 {
+        /// The number of open issues labeled as good first issues or help wanted
+        const GOOD_FIRST_ISSUES = 1;
+        /// The SPDX identifier of the license and the URL of its text
+        const LICENSE = 1 << 1;
+        /// The repository a fork was forked from
+        const FORK_PARENT = 1 << 2;
+        /// The number of open issues labeled as bugs
+        const OPEN_BUGS = 1 << 3;
+        /// Whether the repository is archived
+        const ARCHIVED = 1 << 4;
+        /// The number of closed issues
+        const CLOSED_ISSUES = 1 << 5;
+        /// The first 20 topics of the repository
+        const TOPICS = 1 << 6;
+    }
+}
+
+/// The name of each field, and the fields of `RepositoryStats` it fills.
+const FIELDS: &[(FieldSet, &str, &[&str])] = &[
+    (
+        FieldSet::GOOD_FIRST_ISSUES,
+        "good_first_issues",
+        &["good_first_issues"],
+    ),
+    (FieldSet::LICENSE, "license", &["license", "license_url"]),
+    (FieldSet::FORK_PARENT, "fork_parent", &["fork_parent"]),
+    (FieldSet::OPEN_BUGS, "open_bugs", &["github_open_bugs"]),
+    (FieldSet::ARCHIVED, "archived", &["is_archived"]),
+    (FieldSet::CLOSED_ISSUES, "closed_issues", &["closed_issues"]),
+    (FieldSet::TOPICS, "topics", &["topics"]),
+];
+
+impl FieldSet {
+    /// Returns whether the field `stats_field` of `RepositoryStats` is fetched.
+    pub(crate) fn fetches(self, stats_field: &str) -> bool {
+        FIELDS
+            .iter()
+            .find(|(_, _, stats_fields)| stats_fields.contains(&stats_field))
+            .map_or(true, |(flag, _, _)| self.contains(*flag))
+    }
+}
+
+impl Default for FieldSet {
+    /// The fields needed by the pages and the health score of the crates
+    fn default() -> Self {
+        FieldSet::LICENSE
+            | FieldSet::FORK_PARENT
+            | FieldSet::ARCHIVED
+            | FieldSet::CLOSED_ISSUES
+            | FieldSet::TOPICS
+    }
+}
+
+impl FromStr for FieldSet {
+    type Err = InvalidField;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut fields = FieldSet::empty();
+        for name in input
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let (flag, _, _) = FIELDS
+                .iter()
+                .find(|(_, field, _)| *field == name)
+                .ok_or_else(|| {
+                    let names: Vec<_> = FIELDS.iter().map(|(_, field, _)| *field).collect();
+                    InvalidField(name.into(), names.join(", "))
+                })?;
+            fields |= *flag;
+        }
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!("".parse::<FieldSet>().unwrap(), FieldSet::empty());
+        assert_eq!("license".parse::<FieldSet>().unwrap(), FieldSet::LICENSE);
+        assert_eq!(
+            " good_first_issues, fork_parent ,license,open_bugs,archived, closed_issues,topics"
+                .parse::<FieldSet>()
+                .unwrap(),
+            FieldSet::all()
+        );
+        assert!("license,stars".parse::<FieldSet>().is_err());
+        assert!("contributors".parse::<FieldSet>().is_err());
+    }
+
+    #[test]
+    fn fetched_stats_fields() {
+        let fields = FieldSet::default();
+        assert!(fields.fetches("license"));
+        assert!(fields.fetches("license_url"));
+        assert!(fields.fetches("fork_parent"));
+        assert!(!fields.fetches("good_first_issues"));
+        assert!(!fields.fetches("github_open_bugs"));
+        assert!(fields.fetches("is_archived"));
+        assert!(fields.fetches("closed_issues"));
+        assert!(fields.fetches("topics"));
+        // The base stats are always fetched
+        assert!(FieldSet::empty().fetches("stars"));
+        assert!(!FieldSet::empty().fetches("license_url"));
+    }
+}
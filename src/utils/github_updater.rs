@@ -1,17 +1,35 @@
+use super::candidate_priority::CandidatePriority;
+use super::github_fields::FieldSet;
+use super::owners_updater::{is_rate_limited, REQUEST_DELAY};
+use super::repository_hosts::RepositoryHost;
+use crate::db::crate_metrics::record_repository_metrics;
+use crate::db::{InstanceLock, InstanceLockError, InstanceRole};
 use crate::error::Result;
+use crate::index::{api::Api, Index};
 use crate::{db::Pool, Config};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use log::{debug, info, trace, warn};
 use once_cell::sync::Lazy;
-use postgres::Client;
+use percent_encoding::percent_decode_str;
+use postgres::{types::ToSql, Client};
 use regex::Regex;
 use reqwest::{
     blocking::Client as HttpClient,
-    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
+    header::{
+        HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING,
+        USER_AGENT,
+    },
+    StatusCode,
 };
 use serde::Deserialize;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
 
 const APP_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
@@ -19,16 +37,41 @@ const APP_USER_AGENT: &str = concat!(
     include_str!(concat!(env!("OUT_DIR"), "/git_version"))
 );
 
-const GRAPHQL_UPDATE: &str = "query($ids: [ID!]!) {
+const GRAPHQL_UPDATE: &str = "query(
+    $ids: [ID!]!,
+    $goodFirstIssues: Boolean!,
+    $license: Boolean!,
+    $forkParent: Boolean!,
+    $openBugs: Boolean!,
+    $archived: Boolean!,
+    $closedIssues: Boolean!,
+    $topics: Boolean!
+) {
     nodes(ids: $ids) {
         ... on Repository {
             id
             nameWithOwner
+            createdAt
             pushedAt
+            updatedAt
             description
             stargazerCount
             forkCount
+            isFork
+            isArchived @include(if: $archived)
+            parent @include(if: $forkParent) { nameWithOwner }
             issues { totalCount }
+            closedIssues: issues(states: CLOSED) @include(if: $closedIssues) { totalCount }
+            goodFirstIssues: issues(
+                states: OPEN,
+                labels: [\"good first issue\", \"help wanted\"]
+            ) @include(if: $goodFirstIssues) { totalCount }
+            openBugs: issues(states: OPEN, labels: [\"bug\"]) @include(if: $openBugs) {
+                totalCount
+            }
+            licenseInfo @include(if: $license) { spdxId url }
+            repositoryTopics(first: 20) @include(if: $topics) { nodes { topic { name } } }
+            diskUsage
         }
     }
     rateLimit {
@@ -36,15 +79,41 @@ const GRAPHQL_UPDATE: &str = "query($ids: [ID!]!) {
     }
 }";
 
-const GRAPHQL_SINGLE: &str = "query($owner: String!, $repo: String!) {
+const GRAPHQL_SINGLE: &str = "query(
+    $owner: String!,
+    $repo: String!,
+    $goodFirstIssues: Boolean!,
+    $license: Boolean!,
+    $forkParent: Boolean!,
+    $openBugs: Boolean!,
+    $archived: Boolean!,
+    $closedIssues: Boolean!,
+    $topics: Boolean!
+) {
     repository(owner: $owner, name: $repo) {
         id
         nameWithOwner
+        createdAt
         pushedAt
+        updatedAt
         description
         stargazerCount
         forkCount
+        isFork
+        isArchived @include(if: $archived)
+        parent @include(if: $forkParent) { nameWithOwner }
         issues { totalCount }
+        closedIssues: issues(states: CLOSED) @include(if: $closedIssues) { totalCount }
+        goodFirstIssues: issues(
+            states: OPEN,
+            labels: [\"good first issue\", \"help wanted\"]
+        ) @include(if: $goodFirstIssues) { totalCount }
+        openBugs: issues(states: OPEN, labels: [\"bug\"]) @include(if: $openBugs) {
+            totalCount
+        }
+        licenseInfo @include(if: $license) { spdxId url }
+        repositoryTopics(first: 20) @include(if: $topics) { nodes { topic { name } } }
+        diskUsage
     }
 }";
 
@@ -52,100 +121,264 @@ const GRAPHQL_SINGLE: &str = "query($owner: String!, $repo: String!) {
 /// rejected by the GraphQL API.
 const UPDATE_CHUNK_SIZE: usize = 100;
 
+/// How often the repositories unavailable for legal reasons are checked again, in days, instead of
+/// every day like the other repositories.
+const BLOCKED_REFRESH_DAYS: i32 = 30;
+
+/// How many repositories to load at a time when loading all the repositories needing an update
+/// at once fails, for example on hosts with little memory.
+const FALLBACK_PAGE_SIZE: i64 = 1000;
+
+/// How often the registry is asked again for the repository of a crate that didn't list one, in
+/// days, as repositories are only added to crates by publishing new releases.
+const REGISTRY_RECHECK_DAYS: i32 = 30;
+
+/// How many crates without a repository are loaded at a time to be looked up in the registry.
+const REGISTRY_PAGE_SIZE: i64 = 1000;
+
+/// How many runs are kept in `github_update_runs`, to compare the next runs to.
+const RUNS_KEPT: i64 = 30;
+
+/// How long the stats of a repository are fresh enough not to be refreshed when a new release
+/// pointing to it is built, in seconds.
+const FRESH_STATS_SECS: f64 = 10.0 * 60.0;
+
+/// How long the events sent to the webhook are kept after they're processed, in days.
+const WEBHOOK_EVENTS_KEPT_DAYS: i32 = 7;
+
+type UpdateCallback = Box<dyn FnMut(&str, &RepositoryStats) + Send>;
+type ErrorCallback = Box<dyn FnMut(&str, &GithubUpdateError) + Send>;
+
 pub struct GithubUpdater {
     client: HttpClient,
+    /// Client for the hosts repositories moved to, which must never get the GitHub credentials
+    hosts_client: HttpClient,
+    graphql_url: String,
     pool: Pool,
     config: Arc<Config>,
+    on_update: Mutex<Option<UpdateCallback>>,
+    on_error: Mutex<Option<ErrorCallback>>,
+    /// Whether to only log the summary of each run, and not every repository
+    quiet: bool,
+    stats: Mutex<UpdateStats>,
+    /// When the current run started, to stop it after the maximum run duration
+    started: Mutex<Option<Instant>>,
+    /// Whether to only fill the columns of the stored repositories that are NULL
+    only_null: bool,
+    /// The query storing the stats of a repository, and the fields it stores in order
+    store_query: (String, Vec<&'static str>),
+    /// The registry API asked for the repository of the crates whose releases don't have one
+    registry_api: Option<Api>,
+    /// Delay between two requests to the registry API
+    registry_delay: Duration,
+    /// How many crates are looked up in the registry API per page
+    registry_page_size: i64,
+    /// Set when the daemon is shutting down, to stop the current run early
+    shutdown: Option<Arc<AtomicBool>>,
 }
 
 impl GithubUpdater {
     /// Returns `Err` if the access token has invalid syntax (but *not* if it isn't authorized).
-    /// Returns `Ok(None)` if there is no access token.
+    /// Returns `Ok(None)` if there is no access token, unless the requests are sent without
+    /// authentication to a proxy adding the credentials itself.
     pub fn new(config: Arc<Config>, pool: Pool) -> Result<Option<Self>> {
         let mut headers = HeaderMap::new();
         headers.insert(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
         headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let hosts_client = HttpClient::builder()
+            .default_headers(headers.clone())
+            .build()?;
+        if config.github_updater_gzip {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        }
 
-        if let Some(token) = &config.github_accesstoken {
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("token {}", token))?,
-            );
-        } else {
-            return Ok(None);
+        // The token isn't sent to proxies adding the credentials themselves, so that it can't leak
+        // through them
+        if !config.github_api_no_auth {
+            match &config.github_accesstoken {
+                Some(token) => {
+                    headers.insert(
+                        AUTHORIZATION,
+                        HeaderValue::from_str(&format!("token {}", token))?,
+                    );
+                }
+                None => return Ok(None),
+            }
         }
 
         let client = HttpClient::builder().default_headers(headers).build()?;
 
+        if let Some(mapping) = &config.github_column_mapping {
+            if let Some(field) = mapping
+                .keys()
+                .find(|field| !STATS_FIELDS.contains(&field.as_str()))
+            {
+                failure::bail!("unknown GitHub repository field `{}` in the mapping", field);
+            }
+        }
+        let store_query = store_stats_query(&column_mapping(&config), false);
+
         Ok(Some(GithubUpdater {
             client,
+            hosts_client,
+            graphql_url: format!("{}/graphql", config.github_api_base.trim_end_matches('/')),
             pool,
+            on_update: Mutex::new(None),
+            on_error: Mutex::new(None),
+            quiet: config.github_updater_quiet,
+            stats: Mutex::new(UpdateStats::default()),
+            started: Mutex::new(None),
+            only_null: false,
+            store_query,
+            registry_api: None,
+            registry_delay: REQUEST_DELAY,
+            registry_page_size: REGISTRY_PAGE_SIZE,
+            shutdown: None,
             config,
         }))
     }
 
+    /// Calls `f` with the ID and the new stats of every repository stored by the updater, after
+    /// they're written to the database.
+    pub fn on_update(self, f: impl FnMut(&str, &RepositoryStats) + Send + 'static) -> Self {
+        *self.on_update.lock().unwrap() = Some(Box::new(f));
+        self
+    }
+
+    /// Calls `f` with the ID of every repository the updater failed to update, and the reason.
+    pub fn on_error(self, f: impl FnMut(&str, &GithubUpdateError) + Send + 'static) -> Self {
+        *self.on_error.lock().unwrap() = Some(Box::new(f));
+        self
+    }
+
+    /// Only logs the summary of each run and the errors stopping it, like for scheduled runs.
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Only updates the repositories with NULL columns, and only fills those columns, leaving
+    /// the other stats as they were last refreshed. Useful after adding a column.
+    pub fn only_null(mut self) -> Self {
+        self.only_null = true;
+        self.store_query = store_stats_query(&column_mapping(&self.config), true);
+        self
+    }
+
+    /// Asks the registry API of `index` for the repository of the crates whose releases don't
+    /// have one at the start of every run, storing it in the releases before loading its stats.
+    /// Does nothing unless enabled in the config, or if the registry has no API.
+    pub fn registry_fallback(mut self, index: &Index) -> Self {
+        if self.config.github_updater_registry_fallback && index.api().has_api_base() {
+            self.registry_api = Some(index.api().clone());
+        }
+        self
+    }
+
+    /// Stops the current run between two chunks of repositories once `shutdown` is set, leaving
+    /// the remaining repositories for the next run.
+    pub fn stop_on(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
     pub fn backfill_repositories(&self) -> Result<()> {
         info!("started backfilling GitHub repository stats");
 
         let mut conn = self.pool.get()?;
         let needs_backfilling = conn.query(
-            "SELECT releases.id, crates.name, releases.version, releases.repository_url
+            "SELECT releases.id, releases.crate_id, crates.name, releases.version,
+                 releases.repository_url
              FROM releases
              INNER JOIN crates ON (crates.id = releases.crate_id)
-             WHERE github_repo IS NULL AND repository_url LIKE '%github.com%';",
+             WHERE github_repo IS NULL
+                 AND repository_url LIKE '%github.com%'
+                 AND repository_url NOT LIKE '%gist.github.com%';",
             &[],
         )?;
 
         let mut missing_urls = HashSet::new();
+        let mut backfilled = 0;
         for row in &needs_backfilling {
             let id: i32 = row.get("id");
+            let crate_id: i32 = row.get("crate_id");
             let name: String = row.get("name");
             let version: String = row.get("version");
             let url: String = row.get("repository_url");
 
-            if missing_urls.contains(&url) {
-                debug!("{} {} points to a known missing repo", name, version);
-            } else if let Some(node_id) = self.load_repository(&mut conn, &url)? {
+            let node_id = if missing_urls.contains(&url) {
+                if !self.quiet {
+                    debug!("{} {} points to a known missing repo", name, version);
+                }
+                None
+            } else {
+                let node_id = self.load_repository(&mut conn, &url)?;
+                if node_id.is_none() {
+                    if !self.quiet {
+                        debug!("{} {} does not point to a GitHub repository", name, version);
+                    }
+                    missing_urls.insert(url);
+                }
+                node_id
+            };
+            let node_id = match node_id {
+                Some(node_id) => Some(node_id),
+                None => self.load_moved_repository(&mut conn, crate_id)?,
+            };
+
+            if let Some(node_id) = node_id {
                 conn.execute(
                     "UPDATE releases SET github_repo = $1 WHERE id = $2;",
                     &[&node_id, &id],
                 )?;
-                info!("backfilled GitHub repository for {} {}", name, version);
-            } else {
-                debug!("{} {} does not point to a GitHub repository", name, version);
-                missing_urls.insert(url);
+                backfilled += 1;
+                if !self.quiet {
+                    info!("backfilled GitHub repository for {} {}", name, version);
+                }
             }
         }
 
+        info!(
+            "finished backfilling GitHub repository stats: {} of {} releases backfilled",
+            backfilled,
+            needs_backfilling.len()
+        );
         Ok(())
     }
 
     pub(crate) fn load_repository(&self, conn: &mut Client, url: &str) -> Result<Option<String>> {
-        let name = match RepositoryName::from_url(url) {
-            Some(name) => name,
+        let path = match RepoPath::from_url(url) {
+            Some(path) => path,
             None => return Ok(None),
         };
 
         // Avoid querying the GitHub API for repositories we already loaded.
         if let Some(row) = conn.query_opt(
-            "SELECT id FROM github_repos WHERE name = $1 LIMIT 1;",
-            &[&format!("{}/{}", name.owner, name.repo)],
+            "SELECT id FROM github_repos WHERE name = $1 AND id NOT LIKE '%:%' LIMIT 1;",
+            &[&path.to_string()],
         )? {
             return Ok(Some(row.get("id")));
         }
 
         // Fetch the latest information from the GitHub API.
-        let response: GraphResponse<GraphRepositoryNode> = self.graphql(
-            GRAPHQL_SINGLE,
-            serde_json::json!({
-                "owner": name.owner,
-                "repo": name.repo,
-            }),
-        )?;
+        let response: GraphResponse<GraphRepositoryNode> = match self.with_retries(|| {
+            self.graphql(
+                GRAPHQL_SINGLE,
+                self.query_variables(serde_json::json!({
+                    "owner": path.owner,
+                    "repo": path.repo,
+                })),
+            )
+        }) {
+            Ok(response) => response,
+            // There are no stats to store for blocked repositories
+            Err(err) if err.downcast_ref::<RepositoryBlocked>().is_some() => return Ok(None),
+            Err(err) => return Err(err),
+        };
         if let Some(repo) = response.data.repository {
             self.store_repository(conn, &repo)?;
             Ok(Some(repo.id))
-        } else if let Some(error) = response.errors.get(0) {
+        } else if let Some(error) = response.errors.first() {
             use GraphErrorPath::*;
             match (error.error_type.as_str(), error.path.as_slice()) {
                 ("NOT_FOUND", [Segment(repository)]) if repository == "repository" => Ok(None),
@@ -156,46 +389,664 @@ impl GithubUpdater {
         }
     }
 
-    /// Updates github fields in crates table
-    pub fn update_all_crates(&self) -> Result<()> {
-        info!("started updating GitHub repository stats");
+    /// Loads the repositories the registry lists for the crates whose releases have none, if the
+    /// registry fallback is enabled. Each crate is only checked again after
+    /// `REGISTRY_RECHECK_DAYS`, and the requests stop as soon as the registry rate limits us.
+    fn load_registry_repositories(&self) -> Result<()> {
+        let api = match &self.registry_api {
+            Some(api) => api,
+            None => return Ok(()),
+        };
 
         let mut conn = self.pool.get()?;
-        let needs_update = conn
-            .query(
-                "SELECT id FROM github_repos WHERE updated_at < NOW() - INTERVAL '1 day';",
+        let (mut checked, mut loaded) = (0, 0);
+        // The crates looked up are marked as checked, so each page starts with the crates not
+        // looked up yet
+        'pages: loop {
+            let crates = conn.query(
+                "SELECT crates.id, crates.name
+                 FROM crates
+                 WHERE NOT EXISTS (
+                         SELECT 1 FROM releases
+                         WHERE releases.crate_id = crates.id
+                             AND releases.repository_url IS NOT NULL
+                     )
+                     AND (
+                         registry_repository_checked_at IS NULL
+                         OR registry_repository_checked_at < NOW() - make_interval(days => $1)
+                     )
+                 ORDER BY registry_repository_checked_at ASC NULLS FIRST, crates.id ASC
+                 LIMIT $2;",
+                &[&REGISTRY_RECHECK_DAYS, &self.registry_page_size],
+            )?;
+            if crates.is_empty() {
+                break;
+            }
+
+            for row in &crates {
+                if self.should_stop() {
+                    break 'pages;
+                }
+                if checked > 0 {
+                    thread::sleep(self.registry_delay);
+                }
+                let crate_id: i32 = row.get(0);
+                let name: String = row.get(1);
+
+                let url = match api.get_repository(&name) {
+                    Ok(url) => url,
+                    Err(err) if is_rate_limited(&err) => {
+                        warn!("rate limited by the registry API, stopping the repository lookups");
+                        break 'pages;
+                    }
+                    Err(err) => {
+                        warn!("failed to look up the repository of {}: {}", name, err);
+                        None
+                    }
+                };
+                conn.execute(
+                    "UPDATE crates SET registry_repository_checked_at = NOW() WHERE id = $1;",
+                    &[&crate_id],
+                )?;
+                checked += 1;
+                let url = match url {
+                    Some(url) => url,
+                    None => continue,
+                };
+
+                conn.execute(
+                    "UPDATE releases SET repository_url = $1
+                     WHERE crate_id = $2 AND repository_url IS NULL;",
+                    &[&url, &crate_id],
+                )?;
+                // Releases whose stats fail to load are picked up by the next backfill, now that
+                // they have a repository
+                match self.load_repository(&mut conn, &url) {
+                    Ok(Some(node_id)) => {
+                        conn.execute(
+                            "UPDATE releases SET github_repo = $1
+                             WHERE crate_id = $2 AND github_repo IS NULL;",
+                            &[&node_id, &crate_id],
+                        )?;
+                        loaded += 1;
+                        if !self.quiet {
+                            info!("loaded the repository of {} listed by the registry", name);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        warn!("failed to load the repository {} of {}: {}", url, name, err)
+                    }
+                }
+            }
+        }
+
+        info!(
+            "loaded {} repositories listed by the registry for {} crates without one",
+            loaded, checked
+        );
+        Ok(())
+    }
+
+    /// Loads the repository a crate moved to from GitHub, returning the ID it's stored with.
+    ///
+    /// The repository is the one referenced by the newest releases of the crate, if they point to
+    /// one of the other supported hosts: crates whose newest releases still point to GitHub
+    /// didn't move.
+    fn load_moved_repository(&self, conn: &mut Client, crate_id: i32) -> Result<Option<String>> {
+        let urls = conn.query(
+            "SELECT repository_url
+             FROM releases
+             WHERE crate_id = $1 AND repository_url IS NOT NULL
+             ORDER BY release_time DESC;",
+            &[&crate_id],
+        )?;
+        let mut moved_to = None;
+        for row in &urls {
+            let url: String = row.get(0);
+            if RepoPath::from_url(&url).is_some() {
+                break;
+            } else if let Some(repo) = RepositoryHost::from_url(&url) {
+                moved_to = Some(repo);
+                break;
+            }
+        }
+        let (host, name) = match moved_to {
+            Some(moved_to) => moved_to,
+            None => return Ok(None),
+        };
+
+        // Avoid querying the host for repositories we already loaded.
+        if let Some(row) = conn.query_opt(
+            "SELECT id FROM github_repos WHERE name = $1 AND id LIKE $2 LIMIT 1;",
+            &[&name, &host.id_pattern()],
+        )? {
+            return Ok(Some(row.get(0)));
+        }
+
+        match host.fetch_by_name(&self.hosts_client, &self.config, &name)? {
+            Some(repo) => {
+                if !self.quiet {
+                    info!("the repository of crate {} moved to {}", crate_id, name);
+                }
+                self.store_stats(conn, &repo.id, &repo.stats)?;
+                Ok(Some(repo.id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Points the releases of the GitHub repository `id`, which doesn't exist anymore, to the
+    /// repositories their crates moved to. Returns whether any of the crates moved.
+    fn move_repository(&self, conn: &mut Client, id: &str) -> Result<bool> {
+        let crates = conn.query(
+            "SELECT DISTINCT crate_id FROM releases WHERE github_repo = $1;",
+            &[&id],
+        )?;
+
+        let mut moved = false;
+        for row in &crates {
+            let crate_id: i32 = row.get(0);
+            if let Some(new_id) = self.load_moved_repository(conn, crate_id)? {
+                conn.execute(
+                    "UPDATE releases SET github_repo = $1 WHERE crate_id = $2 AND github_repo = $3;",
+                    &[&new_id, &crate_id, &id],
+                )?;
+                moved = true;
+            }
+        }
+
+        Ok(moved)
+    }
+
+    /// Updates the repositories of the latest releases of the named crates, however recently
+    /// they were updated, instead of picking the repositories needing an update. Returns the
+    /// result for each crate, in the same order.
+    pub fn update_crates(&self, conn: &mut Client, names: &[&str]) -> Vec<CrateUpdate> {
+        names
+            .iter()
+            .map(|name| {
+                let crate_id =
+                    match conn.query_opt("SELECT id FROM crates WHERE name = $1;", &[name]) {
+                        Ok(Some(row)) => row.get(0),
+                        Ok(None) => return CrateUpdate::UnknownCrate,
+                        Err(err) => return CrateUpdate::Failed(err.into()),
+                    };
+                match self.update_crate(conn, crate_id) {
+                    Ok(Some(repository)) => CrateUpdate::Updated { repository },
+                    Ok(None) => CrateUpdate::NoRepository,
+                    Err(err) => CrateUpdate::Failed(err),
+                }
+            })
+            .collect()
+    }
+
+    /// Updates the repository of the latest release of a crate, loading it first if the release
+    /// isn't pointed to one yet.
+    fn update_crate(&self, conn: &mut Client, crate_id: i32) -> Result<Option<String>> {
+        let row = match conn.query_opt(
+            "SELECT releases.id, releases.github_repo, releases.repository_url
+             FROM crates
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             WHERE crates.id = $1;",
+            &[&crate_id],
+        )? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let release_id: i32 = row.get(0);
+        let id = match (
+            row.get::<_, Option<String>>(1),
+            row.get::<_, Option<String>>(2),
+        ) {
+            (Some(id), _) => id,
+            (None, Some(url)) => match self.load_repository(conn, &url)? {
+                Some(id) => {
+                    conn.execute(
+                        "UPDATE releases SET github_repo = $1 WHERE id = $2;",
+                        &[&id, &release_id],
+                    )?;
+                    id
+                }
+                None => return Ok(None),
+            },
+            (None, None) => return Ok(None),
+        };
+
+        let ids = std::slice::from_ref(&id);
+        if RepositoryHost::from_id(&id).is_some() {
+            self.update_host_ids(conn, ids)?;
+        } else {
+            self.update_repositories(conn, ids)?;
+        }
+
+        Ok(Some(id))
+    }
+
+    /// Updates the repositories GitHub sent events about to the webhook since the last call, and
+    /// the ones queued by `queue_repository_refresh`, returning how many were updated. Active
+    /// repositories are updated as they change instead of waiting for the next run, which is also
+    /// cheaper than updating every repository more often.
+    ///
+    /// Only the repositories already stored are updated. They're matched by name too, as the
+    /// webhook can send another format of node ID than the GraphQL API. The events of the
+    /// repositories left out when the rate limit is reached or the run stops are left for the
+    /// next call.
+    pub fn update_webhook_repositories(&self) -> Result<usize> {
+        let mut conn = self.pool.get()?;
+        let last_event: Option<i32> = conn
+            .query_one(
+                "SELECT MAX(id) FROM webhook_events WHERE processed_at IS NULL;",
                 &[],
             )?
+            .get(0);
+        let last_event = match last_event {
+            Some(id) => id,
+            None => return Ok(0),
+        };
+
+        let ids: Vec<String> = conn
+            .query(
+                "SELECT DISTINCT github_repos.id
+                 FROM webhook_events
+                 INNER JOIN github_repos ON
+                     github_repos.id = webhook_events.repository_id
+                     OR github_repos.name = webhook_events.repository_name
+                 WHERE webhook_events.processed_at IS NULL AND webhook_events.id <= $1
+                 ORDER BY github_repos.id;",
+                &[&last_event],
+            )?
             .into_iter()
             .map(|row| row.get(0))
-            .collect::<Vec<String>>();
+            .collect();
+        // The workers updating the repositories take their own connections
+        drop(conn);
+        let updated = self.update_ids(&ids)?;
+
+        // The events of repositories that aren't stored are processed too, as there's nothing to
+        // update for them
+        let mut conn = self.pool.get()?;
+        conn.execute(
+            "UPDATE webhook_events SET processed_at = NOW()
+             WHERE processed_at IS NULL AND id <= $1
+                 AND NOT EXISTS (
+                     SELECT 1 FROM github_repos
+                     WHERE (
+                             github_repos.id = webhook_events.repository_id
+                             OR github_repos.name = webhook_events.repository_name
+                         )
+                         AND github_repos.id <> ALL($2)
+                 );",
+            &[&last_event, &updated],
+        )?;
+        conn.execute(
+            "DELETE FROM webhook_events
+             WHERE processed_at < NOW() - make_interval(days => $1);",
+            &[&WEBHOOK_EVENTS_KEPT_DAYS],
+        )?;
+        Ok(updated.len())
+    }
+
+    /// Updates github fields in crates table
+    ///
+    /// Only one run updates the repositories at a time, the others return right away without
+    /// updating anything, with `already_running` set in their stats.
+    pub fn update_all_crates(&self) -> Result<UpdateStats> {
+        let lock = match InstanceLock::acquire(&self.pool, InstanceRole::GithubUpdater) {
+            Ok(lock) => lock,
+            Err(err) => match err.downcast::<InstanceLockError>() {
+                Ok(err) => {
+                    info!("not updating GitHub repository stats: {}", err);
+                    return Ok(UpdateStats {
+                        already_running: true,
+                        ..UpdateStats::default()
+                    });
+                }
+                Err(err) => return Err(err),
+            },
+        };
+
+        info!("started updating GitHub repository stats");
+        *self.stats.lock().unwrap() = UpdateStats::default();
+        *self.started.lock().unwrap() = Some(Instant::now());
+
+        if self.only_null {
+            self.update_null_columns()?;
+        } else {
+            self.load_registry_repositories()?;
+            self.update_needing_update()?;
+            self.update_other_hosts()?;
+        }
+
+        *self.started.lock().unwrap() = None;
+        let stats = *self.stats.lock().unwrap();
+        info!("finished updating GitHub repository stats: {}", stats);
+        // Filling the NULL columns doesn't update every repository like the regular runs
+        if !self.only_null {
+            if let Some(previous) = record_run(&mut *self.pool.get()?, &stats)? {
+                let diff = StatsDiff {
+                    previous,
+                    current: stats,
+                };
+                if diff.regressed() {
+                    warn!(
+                        "GitHub repository stats compared to the previous run: {}",
+                        diff
+                    );
+                } else {
+                    info!(
+                        "GitHub repository stats compared to the previous run: {}",
+                        diff
+                    );
+                }
+            }
+        }
+        lock.release()?;
+        Ok(stats)
+    }
+
+    /// Whether the current run should stop before updating more repositories, because the daemon
+    /// is shutting down or the run is out of time.
+    fn should_stop(&self) -> bool {
+        let shutting_down = self
+            .shutdown
+            .as_ref()
+            .map_or(false, |shutdown| shutdown.load(Ordering::SeqCst));
+        if shutting_down {
+            let mut stats = self.stats.lock().unwrap();
+            if !stats.interrupted {
+                info!("shutting down, stopping the GitHub repository stats updater");
+                stats.interrupted = true;
+            }
+            return true;
+        }
+
+        self.out_of_time()
+    }
+
+    /// Whether the current run lasted longer than the maximum run duration, after which no more
+    /// repositories are updated. The remaining ones are left for the next run.
+    fn out_of_time(&self) -> bool {
+        let (max_duration, started) = match (
+            self.config.github_updater_max_run_duration,
+            *self.started.lock().unwrap(),
+        ) {
+            (Some(max_duration), Some(started)) => (max_duration, started),
+            _ => return false,
+        };
+        if started.elapsed() < max_duration {
+            return false;
+        }
+
+        let mut stats = self.stats.lock().unwrap();
+        if !stats.timed_out {
+            warn!(
+                "reached the maximum run duration of {}s, stopping the GitHub repository stats updater",
+                max_duration.as_secs()
+            );
+            stats.timed_out = true;
+        }
+        true
+    }
+
+    fn update_needing_update(&self) -> Result<()> {
+        let needs_update = match repositories_needing_update(
+            &mut *self.pool.get()?,
+            self.config.github_updater_priority,
+        ) {
+            Ok(needs_update) => needs_update,
+            Err(err) => {
+                warn!(
+                    "failed to load the GitHub repositories to update, retrying {} at a time: {}",
+                    FALLBACK_PAGE_SIZE, err
+                );
+                return self.update_in_pages();
+            }
+        };
 
         if needs_update.is_empty() {
             info!("no GitHub repository stats needed to be updated");
             return Ok(());
         }
 
-        for chunk in needs_update.chunks(UPDATE_CHUNK_SIZE) {
-            if let Err(err) = self.update_repositories(&mut conn, &chunk) {
-                if err.downcast_ref::<RateLimitReached>().is_some() {
-                    warn!("rate limit reached, blocked the GitHub repository stats updater");
-                    return Ok(());
+        self.update_ids(&needs_update)?;
+        Ok(())
+    }
+
+    /// Updates the repositories with a NULL in any of the columns the stats are stored in.
+    fn update_null_columns(&self) -> Result<()> {
+        let (github_ids, other_ids): (Vec<String>, Vec<String>) =
+            repositories_with_null_columns(&mut *self.pool.get()?, &column_mapping(&self.config))?
+                .into_iter()
+                .partition(|id| !id.contains(':'));
+        if github_ids.is_empty() && other_ids.is_empty() {
+            info!("no repository has NULL columns to fill");
+            return Ok(());
+        }
+
+        self.update_ids(&github_ids)?;
+        self.update_host_ids(&mut *self.pool.get()?, &other_ids)
+    }
+
+    /// Updates the repositories of the other hosts whose stats are older than a day, one at a
+    /// time as their APIs can't fetch many repositories at once.
+    fn update_other_hosts(&self) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        let ids: Vec<String> = conn
+            .query(
+                "SELECT id
+                 FROM github_repos
+                 WHERE github_last_attempt < NOW() - CASE
+                         WHEN github_status = 'blocked' THEN make_interval(days => $1)
+                         ELSE INTERVAL '1 day'
+                     END
+                     AND id LIKE '%:%'
+                 ORDER BY github_last_attempt ASC, id ASC;",
+                &[&BLOCKED_REFRESH_DAYS],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        self.update_host_ids(&mut conn, &ids)
+    }
+
+    fn update_host_ids(&self, conn: &mut Client, ids: &[String]) -> Result<()> {
+        for id in ids {
+            if self.should_stop() {
+                break;
+            }
+            let (host, host_id) = match RepositoryHost::from_id(id) {
+                Some(host) => host,
+                None => continue,
+            };
+            match self.with_retries(|| host.fetch_by_id(&self.hosts_client, &self.config, host_id))
+            {
+                Ok(Some(repo)) => self.store_stats(conn, &repo.id, &repo.stats)?,
+                Ok(None) => {
+                    self.delete_repository(conn, id)?;
+                    self.report_errors(std::slice::from_ref(id), &GithubUpdateError::NotFound);
                 }
-                return Err(err);
+                Err(err) if err.downcast_ref::<RepositoryBlocked>().is_some() => {
+                    self.mark_blocked(conn, id)?
+                }
+                Err(err) => self.handle_errors(
+                    conn,
+                    std::slice::from_ref(id),
+                    &GithubUpdateError::from_request_error(&err),
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the repositories needing an update one page at a time, in the same order as
+    /// `update_needing_update`.
+    ///
+    /// Each page is loaded with its own connection, which is returned to the pool before the
+    /// workers updating the repositories take theirs.
+    fn update_in_pages(&self) -> Result<()> {
+        let mut after = None;
+        loop {
+            if self.should_stop() {
+                break;
+            }
+            let page = repositories_needing_update_page(
+                &mut *self.pool.get()?,
+                self.config.github_updater_priority,
+                after.as_ref(),
+                FALLBACK_PAGE_SIZE,
+            )?;
+            let ids = page.iter().map(|repo| repo.id.clone()).collect::<Vec<_>>();
+            after = match page.into_iter().last() {
+                Some(last) => Some(last),
+                None => break,
+            };
+
+            // The rate limit was reached or the run stopped
+            if self.update_ids(&ids)?.len() < ids.len() {
+                return Ok(());
             }
         }
 
-        info!("finished updating GitHub repository stats");
         Ok(())
     }
 
+    /// Updates the repositories, returning the ones that were updated, in no particular order:
+    /// all of them, unless the rate limit was reached or the run stopped first.
+    fn update_ids(&self, ids: &[String]) -> Result<Vec<String>> {
+        let updated = Mutex::new(Vec::with_capacity(ids.len()));
+        let res = self.for_each_chunk(ids, |conn, chunk| {
+            self.update_repositories(conn, chunk)?;
+            updated.lock().unwrap().extend_from_slice(chunk);
+            Ok(())
+        });
+        if let Err(err) = res {
+            if err.downcast_ref::<RateLimitReached>().is_some() {
+                warn!("rate limit reached, blocked the GitHub repository stats updater");
+                let mut conn = self.pool.get()?;
+                let skipped = mark_rate_limited(&mut conn, ids)?;
+                self.handle_errors(&mut conn, &skipped, &GithubUpdateError::RateLimited)?;
+            } else {
+                return Err(err);
+            }
+        }
+
+        Ok(updated.into_inner().unwrap())
+    }
+
+    /// Calls `f` for every chunk of `node_ids`, spreading the chunks between as many workers as
+    /// the configured concurrency. Each worker checks out its own database connection from the
+    /// pool, so the concurrency is capped to the size of the pool, minus the connection holding
+    /// the lock of the run.
+    ///
+    /// After the first error no more chunks are processed, and that error is returned once the
+    /// chunks already being processed are done.
+    fn for_each_chunk<F>(&self, node_ids: &[String], f: F) -> Result<()>
+    where
+        F: Fn(&mut Client, &[String]) -> Result<()> + Sync,
+    {
+        let chunks = Mutex::new(node_ids.chunks(UPDATE_CHUNK_SIZE));
+        let failed = AtomicBool::new(false);
+        let first_error = Mutex::new(None);
+        let workers = self
+            .config
+            .github_updater_concurrency
+            .min((self.pool.max_size() as usize).saturating_sub(1))
+            .max(1);
+
+        let process = || -> Result<()> {
+            let mut conn = self.pool.get()?;
+            while !failed.load(Ordering::SeqCst) {
+                if self.should_stop() {
+                    break;
+                }
+                let chunk = match chunks.lock().unwrap().next() {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                f(&mut conn, chunk)?;
+            }
+            Ok(())
+        };
+        let worker = || {
+            if let Err(err) = process() {
+                // The other workers can fail too before they see the first error
+                failed.store(true, Ordering::SeqCst);
+                first_error.lock().unwrap().get_or_insert(err);
+            }
+        };
+
+        if workers == 1 {
+            worker();
+        } else {
+            thread::scope(|scope| {
+                for handle in (0..workers)
+                    .map(|_| scope.spawn(worker))
+                    .collect::<Vec<_>>()
+                {
+                    handle.join().expect("GitHub updater worker panicked");
+                }
+            });
+        }
+
+        match first_error.into_inner().unwrap() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
     fn update_repositories(&self, conn: &mut Client, node_ids: &[String]) -> Result<()> {
-        let response: GraphResponse<GraphNodes<Option<GraphRepository>>> = self.graphql(
-            GRAPHQL_UPDATE,
-            serde_json::json!({
-                "ids": node_ids,
-            }),
-        )?;
+        let response = self.with_retries(|| {
+            self.graphql(
+                GRAPHQL_UPDATE,
+                self.query_variables(serde_json::json!({ "ids": node_ids })),
+            )
+        });
+        self.store_update_response(conn, node_ids, response)
+    }
+
+    /// Adds the variables choosing the optional fields the queries ask for to `variables`.
+    fn query_variables(&self, mut variables: serde_json::Value) -> serde_json::Value {
+        let fields = self.config.github_fields;
+        variables["goodFirstIssues"] = fields.contains(FieldSet::GOOD_FIRST_ISSUES).into();
+        variables["license"] = fields.contains(FieldSet::LICENSE).into();
+        variables["forkParent"] = fields.contains(FieldSet::FORK_PARENT).into();
+        variables["openBugs"] = fields.contains(FieldSet::OPEN_BUGS).into();
+        variables["archived"] = fields.contains(FieldSet::ARCHIVED).into();
+        variables["closedIssues"] = fields.contains(FieldSet::CLOSED_ISSUES).into();
+        variables["topics"] = fields.contains(FieldSet::TOPICS).into();
+        variables
+    }
+
+    /// Stores the result of the request updating `node_ids`.
+    fn store_update_response(
+        &self,
+        conn: &mut Client,
+        node_ids: &[String],
+        response: Result<GraphResponse<GraphNodes<Option<GraphRepository>>>>,
+    ) -> Result<()> {
+        let response = match response {
+            Ok(response) => response,
+            Err(err) if err.downcast_ref::<RepositoryBlocked>().is_some() => {
+                if let [id] = node_ids {
+                    return self.mark_blocked(conn, id);
+                }
+                // The whole request is rejected, so the blocked repositories are found by
+                // updating the repositories one at a time
+                for id in node_ids {
+                    self.update_repositories(conn, std::slice::from_ref(id))?;
+                }
+                return Ok(());
+            }
+            // The repositories left are recorded as rate limited once the workers stopped
+            Err(err) if err.downcast_ref::<RateLimitReached>().is_some() => return Err(err),
+            Err(err) => {
+                self.handle_errors(conn, node_ids, &GithubUpdateError::from_request_error(&err))?;
+                return Err(err);
+            }
+        };
 
         // The error is returned *before* we reach the rate limit, to ensure we always have an
         // amount of API calls we can make at any time.
@@ -219,90 +1070,631 @@ impl GithubUpdater {
             use GraphErrorPath::*;
             match (error.error_type.as_str(), error.path.as_slice()) {
                 ("NOT_FOUND", [Segment(nodes), Index(idx)]) if nodes == "nodes" => {
-                    self.delete_repository(conn, &node_ids[*idx as usize])?;
+                    let id = &node_ids[*idx as usize];
+                    // The releases that didn't move lose their stats along with the repository
+                    let moved = self.move_repository(conn, id)?;
+                    self.delete_repository(conn, id)?;
+                    if !moved {
+                        self.report_errors(std::slice::from_ref(id), &GithubUpdateError::NotFound);
+                    }
+                }
+                _ => {
+                    let err = GithubUpdateError::Request(error.message.clone());
+                    self.handle_errors(conn, node_ids, &err)?;
+                    failure::bail!("error updating repositories: {}", error.message);
                 }
-                _ => failure::bail!("error updating repositories: {}", error.message),
             }
         }
 
         Ok(())
     }
 
+    /// Calls `f` until it succeeds or the retry policy of its error doesn't allow more attempts,
+    /// waiting longer before each retry. Blocked repositories are never retried, and neither is
+    /// anything once the rate limit is reached, as it won't reset before the next run.
+    fn with_retries<T>(&self, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let err = match f() {
+                Ok(value) => return Ok(value),
+                Err(err)
+                    if err.downcast_ref::<RepositoryBlocked>().is_some()
+                        || err.downcast_ref::<RateLimitReached>().is_some() =>
+                {
+                    return Err(err)
+                }
+                Err(err) => err,
+            };
+            let policy = self
+                .config
+                .github_retry_policies
+                .get(&GithubUpdateError::from_request_error(&err));
+            attempt += 1;
+            if attempt > policy.retries || self.should_stop() {
+                return Err(err);
+            }
+            if !self.quiet {
+                debug!(
+                    "request to update GitHub repositories failed, retrying ({}/{}): {}",
+                    attempt, policy.retries, err
+                );
+            }
+            thread::sleep(policy.delay(attempt));
+        }
+    }
+
     fn graphql<T: serde::de::DeserializeOwned>(
         &self,
         query: &str,
         variables: impl serde::Serialize,
     ) -> Result<GraphResponse<T>> {
-        Ok(self
+        let response = self
             .client
-            .post("https://api.github.com/graphql")
+            .post(&self.graphql_url)
             .json(&serde_json::json!({
                 "query": query,
                 "variables": variables,
             }))
-            .send()?
-            .error_for_status()?
-            .json()?)
+            .send()?;
+        // Repositories taken down by a DMCA notice will never return any data
+        if response.status() == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS {
+            return Err(RepositoryBlocked.into());
+        }
+        check_rate_limit(&response)?;
+        let response = response.error_for_status()?;
+
+        let gzipped = matches!(
+            response.headers().get(CONTENT_ENCODING),
+            Some(encoding) if encoding == "gzip"
+        );
+        let body = if gzipped {
+            let mut body = String::new();
+            flate2::read::GzDecoder::new(&response.bytes()?[..]).read_to_string(&mut body)?;
+            body
+        } else {
+            response.text()?
+        };
+
+        parse_graphql_response(&body)
     }
 
     fn store_repository(&self, conn: &mut Client, repo: &GraphRepository) -> Result<()> {
-        trace!(
-            "storing GitHub repository stats for {}",
-            repo.name_with_owner
-        );
-        conn.execute(
-            "INSERT INTO github_repos (
-                 id, name, description, last_commit, stars, forks, issues, updated_at
-             ) VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
-             ON CONFLICT (id) DO
-             UPDATE SET
-                 name = $2,
-                 description = $3,
-                 last_commit = $4,
-                 stars = $5,
-                 forks = $6,
-                 issues = $7,
-                 updated_at = NOW();",
-            &[
-                &repo.id,
-                &repo.name_with_owner,
-                &repo.description,
-                &repo.pushed_at.map(|time| time.naive_utc()),
-                &(repo.stargazer_count as i32),
-                &(repo.fork_count as i32),
-                &(repo.issues.total_count as i32),
-            ],
-        )?;
+        self.store_stats(conn, &repo.id, &repo.stats())?;
+        if self.config.github_updater_readme && !self.only_null {
+            self.update_readme(conn, &repo.id, &repo.name_with_owner)?;
+        }
         Ok(())
     }
 
-    fn delete_repository(&self, conn: &mut Client, id: &str) -> Result<()> {
-        trace!("removing GitHub repository stats for ID {}", id);
-        conn.execute("DELETE FROM github_repos WHERE id = $1;", &[&id])?;
+    /// Stores the README of a repository, or clears it if the repository doesn't have one.
+    /// Failing to fetch it doesn't fail the update of the stats, and keeps the previous README.
+    fn update_readme(&self, conn: &mut Client, id: &str, name: &str) -> Result<()> {
+        let readme = match self.with_retries(|| self.fetch_readme(name)) {
+            Ok(readme) => readme,
+            Err(err) => {
+                if !self.quiet {
+                    debug!("failed to fetch the README of {}: {}", name, err);
+                }
+                return Ok(());
+            }
+        };
+        conn.execute(
+            "UPDATE github_repos SET github_readme = $2 WHERE id = $1;",
+            &[&id, &readme],
+        )?;
         Ok(())
     }
-}
 
-#[derive(Debug, Eq, PartialEq)]
-struct RepositoryName<'a> {
-    owner: &'a str,
-    repo: &'a str,
-}
+    /// Fetches the README of a repository from the REST API, truncated to the configured size,
+    /// or `None` if the repository doesn't have one.
+    fn fetch_readme(&self, name: &str) -> Result<Option<String>> {
+        let response = self
+            .client
+            .get(&format!(
+                "{}/repos/{}/readme",
+                self.config.github_api_base.trim_end_matches('/'),
+                name
+            ))
+            .header(ACCEPT, "application/vnd.github.raw")
+            .header(ACCEPT_ENCODING, "identity")
+            .send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        check_rate_limit(&response)?;
+        let mut body = Vec::new();
+        response
+            .error_for_status()?
+            .take(self.config.github_readme_max_size as u64)
+            .read_to_end(&mut body)?;
 
-impl<'a> RepositoryName<'a> {
-    fn from_url(url: &'a str) -> Option<Self> {
-        static RE: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"https?://(www.)?github\.com/(?P<owner>[\w\._-]+)/(?P<repo>[\w\._-]+)")
-                .unwrap()
-        });
+        Ok(Some(truncate_readme(body)))
+    }
 
-        match RE.captures(url) {
-            Some(cap) => {
+    fn store_stats(&self, conn: &mut Client, id: &str, stats: &RepositoryStats) -> Result<()> {
+        if !self.quiet {
+            trace!("storing GitHub repository stats for {}", stats.name);
+        }
+        let (query, fields) = &self.store_query;
+        let values = stats.to_db_params();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&id];
+        for field in fields {
+            let (_, value) = values
+                .iter()
+                .find(|(name, _)| name == field)
+                .expect("missing repository field");
+            params.push(value.as_ref());
+        }
+        conn.execute(query.as_str(), &params)?;
+        // The stats that weren't stored aren't recorded either
+        if !self.only_null {
+            record_repository_metrics(conn, id, stats.stars, stats.forks, stats.issues)?;
+        }
+
+        self.stats.lock().unwrap().updated += 1;
+        if let Some(on_update) = &mut *self.on_update.lock().unwrap() {
+            on_update(id, stats);
+        }
+        Ok(())
+    }
+
+    fn report_errors(&self, ids: &[String], err: &GithubUpdateError) {
+        {
+            let mut stats = self.stats.lock().unwrap();
+            let count = match err {
+                GithubUpdateError::NotFound => &mut stats.not_found,
+                GithubUpdateError::RateLimited => &mut stats.rate_limited,
+                GithubUpdateError::Blocked => &mut stats.blocked,
+                GithubUpdateError::Transient(_) | GithubUpdateError::Request(_) => {
+                    &mut stats.failed
+                }
+            };
+            *count += ids.len();
+        }
+        if !self.quiet {
+            for id in ids {
+                debug!("failed to update GitHub repository {}: {}", id, err);
+            }
+        }
+        if let Some(on_error) = &mut *self.on_error.lock().unwrap() {
+            for id in ids {
+                on_error(id, err);
+            }
+        }
+    }
+
+    /// Reports the errors, and records the attempt if the retry policy of the error says so, so
+    /// that the repositories are only tried again once their stats are outdated. Their last
+    /// successful update is kept.
+    fn handle_errors(
+        &self,
+        conn: &mut Client,
+        ids: &[String],
+        err: &GithubUpdateError,
+    ) -> Result<()> {
+        self.report_errors(ids, err);
+        if self
+            .config
+            .github_retry_policies
+            .get(err)
+            .advance_last_attempt
+        {
+            conn.execute(
+                "UPDATE github_repos
+                 SET github_last_attempt = NOW(), rate_limited_at = NULL
+                 WHERE id = ANY($1);",
+                &[&ids],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Marks a repository as unavailable for legal reasons, so it's only checked again after
+    /// `BLOCKED_REFRESH_DAYS` unless its retry policy doesn't advance `github_last_attempt`. Its
+    /// stats are kept, as the repository can be reinstated.
+    fn mark_blocked(&self, conn: &mut Client, id: &str) -> Result<()> {
+        conn.execute(
+            "UPDATE github_repos
+             SET github_status = 'blocked', rate_limited_at = NULL
+             WHERE id = $1;",
+            &[&id],
+        )?;
+        self.handle_errors(
+            conn,
+            std::slice::from_ref(&id.to_string()),
+            &GithubUpdateError::Blocked,
+        )
+    }
+
+    fn delete_repository(&self, conn: &mut Client, id: &str) -> Result<()> {
+        if !self.quiet {
+            trace!("removing GitHub repository stats for ID {}", id);
+        }
+        conn.execute("DELETE FROM github_repos WHERE id = $1;", &[&id])?;
+        Ok(())
+    }
+}
+
+/// Decodes a README truncated to its maximum size, dropping the character the truncation might
+/// have split.
+fn truncate_readme(body: Vec<u8>) -> String {
+    match String::from_utf8(body) {
+        Ok(readme) => readme,
+        Err(err) => {
+            let valid = err.utf8_error().valid_up_to();
+            let mut body = err.into_bytes();
+            body.truncate(valid);
+            String::from_utf8(body).expect("the prefix is valid UTF-8")
+        }
+    }
+}
+
+/// Queues the GitHub repository of a release that was just built, for its stats to be refreshed
+/// with the repositories sent to the webhook instead of waiting for the next run of the updater.
+/// Returns whether it was queued.
+///
+/// Repositories updated less than `FRESH_STATS_SECS` ago, like the ones loaded when the release
+/// was added, and the ones already queued aren't queued again.
+pub(crate) fn queue_repository_refresh(conn: &mut Client, id: &str) -> Result<bool> {
+    let queued = conn.execute(
+        "INSERT INTO webhook_events (event, repository_id, repository_name)
+         SELECT 'release', id, name
+         FROM github_repos
+         WHERE id = $1
+             AND id NOT LIKE '%:%'
+             AND github_last_attempt < NOW() - make_interval(secs => $2)
+             AND NOT EXISTS (
+                 SELECT 1 FROM webhook_events
+                 WHERE repository_id = $1 AND processed_at IS NULL
+             );",
+        &[&id, &FRESH_STATS_SECS],
+    )?;
+    Ok(queued > 0)
+}
+
+/// Returns the IDs of the GitHub repositories whose stats are older than a day, or than
+/// `BLOCKED_REFRESH_DAYS` for the repositories unavailable for legal reasons, in the order of the
+/// `priority`.
+///
+/// Repositories that couldn't be updated in previous runs because the rate limit was reached come
+/// first, starting from the ones waiting for the longest time, so they're not starved by the
+/// repositories that happen to be updated earlier in every run.
+fn repositories_needing_update(
+    conn: &mut Client,
+    priority: CandidatePriority,
+) -> Result<Vec<String>> {
+    Ok(conn
+        .query(
+            format!(
+                "SELECT id
+                 FROM github_repos
+                 {}
+                 WHERE github_last_attempt < NOW() - CASE
+                         WHEN github_status = 'blocked' THEN make_interval(days => $1)
+                         ELSE INTERVAL '1 day'
+                     END
+                     AND id NOT LIKE '%:%'
+                 ORDER BY {};",
+                priority.joins(),
+                priority.order_by()
+            )
+            .as_str(),
+            &[&BLOCKED_REFRESH_DAYS],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// The position of a repository in the order of `repositories_needing_update`.
+#[derive(Debug, Clone)]
+struct RepositoryPosition {
+    id: String,
+    rate_limited_at: Option<NaiveDateTime>,
+    /// The sort key of the `CandidatePriority`, or 0 if it has none
+    priority: f64,
+    last_attempt: NaiveDateTime,
+}
+
+/// Returns up to `limit` of the repositories `repositories_needing_update` returns, starting
+/// after the `after` repository.
+///
+/// Pages are keyed on the position of their last repository rather than on an offset, so
+/// updating the repositories of a page doesn't shift the following pages.
+fn repositories_needing_update_page(
+    conn: &mut Client,
+    priority: CandidatePriority,
+    after: Option<&RepositoryPosition>,
+    limit: i64,
+) -> Result<Vec<RepositoryPosition>> {
+    let sort_key = priority.sort_key().unwrap_or("0::FLOAT8");
+    Ok(conn
+        .query(
+            format!(
+                "SELECT id, rate_limited_at, {key}, github_last_attempt
+                 FROM github_repos
+                 {joins}
+                 WHERE github_last_attempt < NOW() - CASE
+                         WHEN github_status = 'blocked' THEN make_interval(days => $5)
+                         ELSE INTERVAL '1 day'
+                     END
+                     AND id NOT LIKE '%:%'
+                     AND ($2::TIMESTAMP IS NULL OR CASE
+                         WHEN $1::TIMESTAMP IS NULL THEN
+                             rate_limited_at IS NULL
+                             AND ({key}, github_last_attempt, id) > ($6::FLOAT8, $2, $3)
+                         ELSE
+                             rate_limited_at IS NULL
+                             OR (rate_limited_at, {key}, github_last_attempt, id)
+                                 > ($1, $6::FLOAT8, $2, $3)
+                     END)
+                 ORDER BY {order_by}
+                 LIMIT $4;",
+                key = sort_key,
+                joins = priority.joins(),
+                order_by = priority.order_by(),
+            )
+            .as_str(),
+            &[
+                &after.and_then(|repo| repo.rate_limited_at),
+                &after.map(|repo| repo.last_attempt),
+                &after.map(|repo| repo.id.as_str()),
+                &limit,
+                &BLOCKED_REFRESH_DAYS,
+                &after.map(|repo| repo.priority),
+            ],
+        )?
+        .into_iter()
+        .map(|row| RepositoryPosition {
+            id: row.get(0),
+            rate_limited_at: row.get(1),
+            priority: row.get(2),
+            last_attempt: row.get(3),
+        })
+        .collect())
+}
+
+/// Marks the repositories that were not updated before the rate limit was reached, keeping the
+/// time they were first skipped at. Returns the IDs of the marked repositories.
+fn mark_rate_limited(conn: &mut Client, ids: &[String]) -> Result<Vec<String>> {
+    Ok(conn
+        .query(
+            "UPDATE github_repos
+             SET rate_limited_at = COALESCE(rate_limited_at, NOW())
+             WHERE id = ANY($1) AND github_last_attempt < NOW() - INTERVAL '1 day'
+             RETURNING id;",
+            &[&ids],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// Records the totals of a run, and returns the ones of the previous run to compare them to.
+///
+/// The runs that stopped early didn't get to every repository, so they're neither compared nor
+/// compared to.
+fn record_run(conn: &mut Client, stats: &UpdateStats) -> Result<Option<UpdateStats>> {
+    let previous = conn
+        .query_opt(
+            "SELECT updated, not_found, rate_limited, blocked, failed
+             FROM github_update_runs
+             WHERE NOT timed_out AND NOT interrupted
+             ORDER BY id DESC
+             LIMIT 1;",
+            &[],
+        )?
+        .map(|row| UpdateStats {
+            updated: row.get::<_, i32>(0) as usize,
+            not_found: row.get::<_, i32>(1) as usize,
+            rate_limited: row.get::<_, i32>(2) as usize,
+            blocked: row.get::<_, i32>(3) as usize,
+            failed: row.get::<_, i32>(4) as usize,
+            ..UpdateStats::default()
+        });
+
+    conn.execute(
+        "INSERT INTO github_update_runs
+             (updated, not_found, rate_limited, blocked, failed, timed_out, interrupted)
+         VALUES ($1, $2, $3, $4, $5, $6, $7);",
+        &[
+            &(stats.updated as i32),
+            &(stats.not_found as i32),
+            &(stats.rate_limited as i32),
+            &(stats.blocked as i32),
+            &(stats.failed as i32),
+            &stats.timed_out,
+            &stats.interrupted,
+        ],
+    )?;
+    conn.execute(
+        "DELETE FROM github_update_runs
+         WHERE id NOT IN (SELECT id FROM github_update_runs ORDER BY id DESC LIMIT $1);",
+        &[&RUNS_KEPT],
+    )?;
+
+    if stats.timed_out || stats.interrupted {
+        return Ok(None);
+    }
+    Ok(previous)
+}
+
+/// The fields of `RepositoryStats` stored in the database, which are also their default columns.
+const STATS_FIELDS: &[&str] = &[
+    "name",
+    "description",
+    "last_commit",
+    "github_updated_at",
+    "stars",
+    "forks",
+    "issues",
+    "good_first_issues",
+    "license",
+    "license_url",
+    "size_kb",
+    "is_fork",
+    "is_archived",
+    "closed_issues",
+    "fork_parent",
+    "github_open_bugs",
+    "topics",
+];
+
+/// The fields of the stats that GitHub can leave out of a response, for example when it serves
+/// an older cached copy of the repository. The stored values of these fields are only replaced
+/// when they're present, even if they're empty.
+const KEPT_WHEN_ABSENT: &[&str] = &["topics"];
+
+/// Returns the column of `github_repos` each field of the stats is stored in, without the
+/// optional fields that aren't fetched.
+fn column_mapping(config: &Config) -> HashMap<String, String> {
+    let mapping = match &config.github_column_mapping {
+        Some(mapping) => mapping.clone(),
+        None => STATS_FIELDS
+            .iter()
+            .map(|field| (field.to_string(), field.to_string()))
+            .collect(),
+    };
+    mapping
+        .into_iter()
+        .filter(|(field, _)| config.github_fields.fetches(field))
+        .collect()
+}
+
+/// Builds the query storing the stats of a repository in the columns of `github_repos` the
+/// fields are mapped to, skipping the fields that aren't mapped. With `only_null`, only the
+/// columns of a stored repository that are NULL are set, and it isn't marked as updated: the
+/// columns are marked as checked instead, as GitHub has no value for the ones still NULL.
+///
+/// Returns the query, whose first parameter is the ID of the repository, and the fields whose
+/// values are the following parameters, in order.
+fn store_stats_query(
+    mapping: &HashMap<String, String>,
+    only_null: bool,
+) -> (String, Vec<&'static str>) {
+    let mut fields = Vec::new();
+    let mut columns = Vec::new();
+    for field in STATS_FIELDS {
+        if let Some(column) = mapping.get(*field) {
+            fields.push(*field);
+            columns.push(column.as_str());
+        }
+    }
+
+    if only_null {
+        let updates: Vec<_> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{} = COALESCE({}, ${})", column, column, i + 2))
+            .chain(std::iter::once("null_columns_checked_at = NOW()".into()))
+            .collect();
+        let query = format!(
+            "UPDATE github_repos SET {} WHERE id = $1;",
+            updates.join(", ")
+        );
+        return (query, fields);
+    }
+
+    let mut insert_columns = vec!["id"];
+    insert_columns.extend(&columns);
+    insert_columns.push("github_last_attempt");
+    insert_columns.push("github_last_success");
+    let mut values: Vec<_> = (1..=columns.len() + 1).map(|i| format!("${}", i)).collect();
+    values.push("NOW()".into());
+    values.push("NOW()".into());
+    let mut updates: Vec<_> = fields
+        .iter()
+        .zip(&columns)
+        .map(|(field, column)| {
+            if KEPT_WHEN_ABSENT.contains(field) {
+                format!(
+                    "{} = COALESCE(EXCLUDED.{}, github_repos.{})",
+                    column, column, column
+                )
+            } else {
+                format!("{} = EXCLUDED.{}", column, column)
+            }
+        })
+        .collect();
+    updates.push("github_last_attempt = NOW()".into());
+    updates.push("github_last_success = NOW()".into());
+    updates.push("rate_limited_at = NULL".into());
+    updates.push("github_status = NULL".into());
+
+    let query = format!(
+        "INSERT INTO github_repos ({}) VALUES ({})
+         ON CONFLICT (id) DO UPDATE SET {};",
+        insert_columns.join(", "),
+        values.join(", "),
+        updates.join(", "),
+    );
+    (query, fields)
+}
+
+/// Returns the IDs of the repositories with a NULL in any of the columns of the `mapping`, which
+/// weren't already filled by a previous run.
+fn repositories_with_null_columns(
+    conn: &mut Client,
+    mapping: &HashMap<String, String>,
+) -> Result<Vec<String>> {
+    let conditions: Vec<_> = STATS_FIELDS
+        .iter()
+        .filter_map(|field| mapping.get(*field))
+        .map(|column| format!("{} IS NULL", column))
+        .collect();
+    if conditions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(conn
+        .query(
+            format!(
+                "SELECT id
+                 FROM github_repos
+                 WHERE null_columns_checked_at IS NULL AND ({})
+                 ORDER BY id;",
+                conditions.join(" OR ")
+            )
+            .as_str(),
+            &[],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// The path of a GitHub repository, made of the owner and the name of the repository.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub(crate) struct RepoPath {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+}
+
+impl RepoPath {
+    /// Parses the path of the GitHub repository at `url`, returning `None` for the repositories
+    /// hosted elsewhere.
+    ///
+    /// Some URLs are stored percent-encoded, like `%2F` for the slashes of branch paths, so they
+    /// are decoded first. URLs containing NUL bytes once decoded are rejected, and so are gists,
+    /// whose `user/id` paths look like the ones of repositories.
+    pub(crate) fn from_url(url: &str) -> Option<Self> {
+        static RE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"https?://(www.)?github\.com/(?P<owner>[\w\._-]+)/(?P<repo>[\w\._-]+)")
+                .unwrap()
+        });
+
+        let url = percent_decode_str(url).decode_utf8_lossy();
+        if url.contains('\0') || is_gist(&url) {
+            return None;
+        }
+
+        match RE.captures(&url) {
+            Some(cap) => {
                 let owner = cap.name("owner").expect("missing group 'owner'").as_str();
                 let repo = cap.name("repo").expect("missing group 'repo'").as_str();
                 Some(Self {
-                    owner,
-                    repo: repo.strip_suffix(".git").unwrap_or(repo),
+                    owner: owner.into(),
+                    repo: repo.strip_suffix(".git").unwrap_or(repo).into(),
                 })
             }
             None => None,
@@ -310,10 +1702,283 @@ impl<'a> RepositoryName<'a> {
     }
 }
 
+/// Returns whether the (decoded) `url` points to a GitHub Gist rather than to a repository.
+fn is_gist(url: &str) -> bool {
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?i)^([\w+]+://)?(www\.)?gist\.github\.com([/:]|$)").unwrap());
+    RE.is_match(url.trim())
+}
+
+impl std::fmt::Display for RepoPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.owner, self.repo)
+    }
+}
+
+/// Parses the body of a GraphQL response.
+///
+/// Proxies and edge caches sometimes return a `200 OK` with a `{"message": "..."}` error body
+/// instead of the actual response: those are treated as errors rather than as missing data.
+fn parse_graphql_response<T: serde::de::DeserializeOwned>(body: &str) -> Result<GraphResponse<T>> {
+    let value: serde_json::Value = serde_json::from_str(body)?;
+    if value.get("data").is_none() {
+        if let Some(message) = value.get("message") {
+            let message = message
+                .as_str()
+                .map_or_else(|| message.to_string(), String::from);
+            return Err(GithubErrorBody { message }.into());
+        }
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// The stats of a GitHub repository, as stored by the updater.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepositoryStats {
+    /// The name of the repository, as `owner/repo`
+    pub name: String,
+    pub description: Option<String>,
+    /// When the repository was last pushed to
+    pub last_commit: Option<DateTime<Utc>>,
+    /// When anything about the repository last changed, including its metadata and stars, unlike
+    /// `last_commit` which only tracks code activity. Stored in `github_updated_at`.
+    pub updated_at: Option<DateTime<Utc>>,
+    pub stars: i32,
+    pub forks: i32,
+    pub issues: i32,
+    /// How many open issues are labeled as good first issues or help wanted, if they were counted
+    pub good_first_issues: Option<i32>,
+    /// The SPDX identifier of the license, if GitHub recognized it
+    pub license: Option<String>,
+    pub license_url: Option<String>,
+    /// Approximate size of the repository in kilobytes
+    pub size_kb: i64,
+    /// Whether the repository is a fork, whose stars don't mean as much
+    pub is_fork: bool,
+    /// Whether the repository is archived, and not maintained anymore
+    pub is_archived: bool,
+    /// How many issues were closed, if the host counts them
+    pub closed_issues: Option<i32>,
+    /// The name of the forked repository, as `owner/repo`, unless it's private or deleted
+    pub fork_parent: Option<String>,
+    /// How many open issues are labeled as bugs, if they were counted. Stored in
+    /// `github_open_bugs`.
+    pub open_bugs: Option<i32>,
+    /// The topics of the repository, or `None` if the response didn't include them, in which
+    /// case the stored ones are kept. An empty list replaces them.
+    pub topics: Option<Vec<String>>,
+}
+
+impl RepositoryStats {
+    /// Returns the value of each of the `STATS_FIELDS`, by name, as query parameters.
+    fn to_db_params(&self) -> Vec<(&'static str, Box<dyn ToSql + Sync>)> {
+        vec![
+            ("name", Box::new(self.name.clone())),
+            ("description", Box::new(self.description.clone())),
+            (
+                "last_commit",
+                Box::new(self.last_commit.map(|time| time.naive_utc())),
+            ),
+            (
+                "github_updated_at",
+                Box::new(self.updated_at.map(|time| time.naive_utc())),
+            ),
+            ("stars", Box::new(self.stars)),
+            ("forks", Box::new(self.forks)),
+            ("issues", Box::new(self.issues)),
+            ("good_first_issues", Box::new(self.good_first_issues)),
+            ("license", Box::new(self.license.clone())),
+            ("license_url", Box::new(self.license_url.clone())),
+            ("size_kb", Box::new(self.size_kb)),
+            ("is_fork", Box::new(self.is_fork)),
+            ("is_archived", Box::new(self.is_archived)),
+            ("closed_issues", Box::new(self.closed_issues)),
+            ("fork_parent", Box::new(self.fork_parent.clone())),
+            ("github_open_bugs", Box::new(self.open_bugs)),
+            ("topics", Box::new(self.topics.clone())),
+        ]
+    }
+}
+
+/// How many repositories a run of the updater updated, or failed to update.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpdateStats {
+    pub updated: usize,
+    /// Repositories deleted or made private, whose stats were removed
+    pub not_found: usize,
+    /// Repositories skipped because the rate limit was reached
+    pub rate_limited: usize,
+    /// Repositories unavailable for legal reasons, which are checked again less often
+    pub blocked: usize,
+    pub failed: usize,
+    /// Whether the run stopped after the maximum run duration, before updating every repository
+    pub timed_out: bool,
+    /// Whether the run stopped because the daemon was shutting down
+    pub interrupted: bool,
+    /// Whether the run didn't start because another run was in progress
+    pub already_running: bool,
+}
+
+impl std::fmt::Display for UpdateStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} updated, {} not found, {} rate limited, {} blocked, {} failed",
+            self.updated, self.not_found, self.rate_limited, self.blocked, self.failed
+        )?;
+        if self.timed_out {
+            write!(f, ", stopped after the maximum run duration")?;
+        }
+        if self.interrupted {
+            write!(f, ", stopped by the shutdown")?;
+        }
+        if self.already_running {
+            write!(f, ", skipped as another run was in progress")?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of updating the repository of a crate named with `GithubUpdater::update_crates`.
+#[derive(Debug)]
+pub enum CrateUpdate {
+    /// The repository was updated, or loaded if the release wasn't pointed to it yet
+    Updated {
+        repository: String,
+    },
+    /// The latest release of the crate has no repository, or one that couldn't be loaded
+    NoRepository,
+    /// No crate has this name
+    UnknownCrate,
+    Failed(failure::Error),
+}
+
+/// How the totals of a run changed since the previous run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StatsDiff {
+    previous: UpdateStats,
+    current: UpdateStats,
+}
+
+impl StatsDiff {
+    fn totals(&self) -> [(&'static str, usize, usize); 5] {
+        let (previous, current) = (&self.previous, &self.current);
+        [
+            ("updated", previous.updated, current.updated),
+            ("not found", previous.not_found, current.not_found),
+            ("rate limited", previous.rate_limited, current.rate_limited),
+            ("blocked", previous.blocked, current.blocked),
+            ("failed", previous.failed, current.failed),
+        ]
+    }
+
+    /// Whether the failures or the repositories not found at least doubled, which usually means
+    /// something broke rather than GitHub having a bad day.
+    fn regressed(&self) -> bool {
+        let doubled = |previous: usize, current: usize| current >= 2 * previous.max(1);
+        doubled(self.previous.failed, self.current.failed)
+            || doubled(self.previous.not_found, self.current.not_found)
+    }
+}
+
+impl std::fmt::Display for StatsDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let changes: Vec<String> = self
+            .totals()
+            .iter()
+            .filter(|(_, previous, current)| previous != current)
+            .map(|&(name, previous, current)| {
+                if previous == 0 {
+                    format!("{} up from 0 to {}", name, current)
+                } else if current == 2 * previous {
+                    format!("{} doubled ({} to {})", name, previous, current)
+                } else if current > previous {
+                    let percent = (current - previous) * 100 / previous;
+                    format!("{} up {}% ({} to {})", name, percent, previous, current)
+                } else {
+                    let percent = (previous - current) * 100 / previous;
+                    format!("{} down {}% ({} to {})", name, percent, previous, current)
+                }
+            })
+            .collect();
+        if changes.is_empty() {
+            write!(f, "no change")
+        } else {
+            write!(f, "{}", changes.join(", "))
+        }
+    }
+}
+
+/// Why the updater failed to update a repository.
+#[derive(Debug, Clone, PartialEq, Eq, failure::Fail)]
+pub enum GithubUpdateError {
+    /// The repository was deleted or made private, so its stats were removed
+    #[fail(display = "the repository doesn't exist anymore")]
+    NotFound,
+    /// The rate limit was reached before the repository was updated
+    #[fail(display = "rate limit reached")]
+    RateLimited,
+    /// The repository is unavailable for legal reasons, like a DMCA takedown
+    #[fail(display = "the repository is unavailable for legal reasons")]
+    Blocked,
+    /// The request updating the repository timed out, couldn't connect or got a server error
+    #[fail(display = "request failed temporarily: {}", _0)]
+    Transient(String),
+    /// The request updating the repository failed
+    #[fail(display = "request failed: {}", _0)]
+    Request(String),
+}
+
+impl GithubUpdateError {
+    /// Classifies the error of a failed request.
+    fn from_request_error(err: &failure::Error) -> Self {
+        if err.downcast_ref::<RateLimitReached>().is_some() {
+            return GithubUpdateError::RateLimited;
+        }
+        let transient = err.downcast_ref::<reqwest::Error>().map_or(false, |err| {
+            // Connection errors are the ones that aren't about the request nor its response
+            err.is_timeout()
+                || !(err.is_builder() || err.is_redirect() || err.is_status())
+                || err
+                    .status()
+                    .map_or(false, |status| status.is_server_error())
+        });
+        if transient {
+            GithubUpdateError::Transient(err.to_string())
+        } else {
+            GithubUpdateError::Request(err.to_string())
+        }
+    }
+}
+
 #[derive(Debug, failure::Fail)]
 #[fail(display = "rate limit reached")]
 struct RateLimitReached;
 
+/// Returns `RateLimitReached` if GitHub refused the request because of the rate limit, which it
+/// answers with `403 Forbidden` and no remaining requests, or with `429 Too Many Requests`.
+fn check_rate_limit(response: &reqwest::blocking::Response) -> Result<()> {
+    let exhausted =
+        response.headers().get("x-ratelimit-remaining") == Some(&HeaderValue::from_static("0"));
+    match response.status() {
+        StatusCode::TOO_MANY_REQUESTS => Err(RateLimitReached.into()),
+        StatusCode::FORBIDDEN if exhausted => Err(RateLimitReached.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The host answered `451 Unavailable For Legal Reasons`.
+#[derive(Debug, failure::Fail)]
+#[fail(display = "the repository is unavailable for legal reasons")]
+pub(super) struct RepositoryBlocked;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "GitHub returned an error instead of data: {}", message)]
+struct GithubErrorBody {
+    message: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct GraphResponse<T> {
     data: T,
@@ -358,11 +2023,109 @@ struct GraphRepositoryNode {
 struct GraphRepository {
     id: String,
     name_with_owner: String,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
     pushed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
     description: Option<String>,
     stargazer_count: i64,
     fork_count: i64,
     issues: GraphIssues,
+    /// Only requested with `FieldSet::GOOD_FIRST_ISSUES`
+    #[serde(default)]
+    good_first_issues: Option<GraphIssues>,
+    /// Only requested with `FieldSet::OPEN_BUGS`
+    #[serde(default)]
+    open_bugs: Option<GraphIssues>,
+    license_info: Option<GraphLicense>,
+    /// Approximate size of the repository in kilobytes
+    #[serde(default)]
+    disk_usage: Option<i64>,
+    #[serde(default)]
+    is_fork: bool,
+    /// Only requested with `FieldSet::ARCHIVED`
+    #[serde(default)]
+    is_archived: bool,
+    /// Only requested with `FieldSet::CLOSED_ISSUES`
+    #[serde(default)]
+    closed_issues: Option<GraphIssues>,
+    #[serde(default)]
+    parent: Option<GraphParent>,
+    /// Only requested with `FieldSet::TOPICS`, and left out of the older copies of the repository
+    /// GitHub sometimes serves from its cache
+    #[serde(default)]
+    repository_topics: Option<GraphTopics>,
+}
+
+impl GraphRepository {
+    fn size_kb(&self) -> i64 {
+        self.disk_usage.unwrap_or(0)
+    }
+
+    /// Returns when the repository was last pushed to, never before it was created.
+    ///
+    /// Repositories imported or mirrored from elsewhere can report the time of their last push
+    /// before the import, which would show activity before the repository existed.
+    fn last_activity(&self) -> Option<DateTime<Utc>> {
+        match (self.created_at, self.pushed_at) {
+            (Some(created_at), Some(pushed_at)) if pushed_at < created_at => {
+                warn!(
+                    "{} was pushed to at {}, before it was created at {}",
+                    self.name_with_owner, pushed_at, created_at
+                );
+                Some(created_at)
+            }
+            (_, pushed_at) => pushed_at,
+        }
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        let license = self.license_info.as_ref();
+        RepositoryStats {
+            name: self.name_with_owner.clone(),
+            description: self.description.clone(),
+            last_commit: self.last_activity(),
+            updated_at: self.updated_at,
+            stars: self.stargazer_count as i32,
+            forks: self.fork_count as i32,
+            issues: self.issues.total_count as i32,
+            good_first_issues: self
+                .good_first_issues
+                .as_ref()
+                .map(|issues| issues.total_count as i32),
+            license: license.and_then(GraphLicense::spdx_id).map(String::from),
+            license_url: license.and_then(|license| license.url.clone()),
+            size_kb: self.size_kb(),
+            is_fork: self.is_fork,
+            is_archived: self.is_archived,
+            closed_issues: self
+                .closed_issues
+                .as_ref()
+                .map(|issues| issues.total_count as i32),
+            fork_parent: self
+                .parent
+                .as_ref()
+                .map(|parent| parent.name_with_owner.clone()),
+            open_bugs: self
+                .open_bugs
+                .as_ref()
+                .map(|issues| issues.total_count as i32),
+            topics: self.repository_topics.as_ref().map(|topics| {
+                topics
+                    .nodes
+                    .iter()
+                    .map(|node| node.topic.name.clone())
+                    .collect()
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphParent {
+    name_with_owner: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -371,32 +2134,2448 @@ struct GraphIssues {
     total_count: i64,
 }
 
+#[derive(Debug, Deserialize)]
+struct GraphTopics {
+    nodes: Vec<GraphTopicNode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphTopicNode {
+    topic: GraphTopic,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphTopic {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphLicense {
+    spdx_id: Option<String>,
+    url: Option<String>,
+}
+
+impl GraphLicense {
+    /// GitHub uses `NOASSERTION` as the SPDX identifier of licenses it doesn't recognize.
+    fn spdx_id(&self) -> Option<&str> {
+        self.spdx_id.as_deref().filter(|id| *id != "NOASSERTION")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::test::{wrapper, TestEnvironment, TestServer};
+    use log::LevelFilter;
+    use rustwide::logging::{self, LogStorage};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[test]
+    fn queries_request_each_field_once() {
+        for query in &[GRAPHQL_UPDATE, GRAPHQL_SINGLE] {
+            for alias in &["closedIssues", "goodFirstIssues", "openBugs"] {
+                let field = format!("{}: issues(", alias);
+                assert_eq!(query.matches(&field).count(), 1, "{} in {}", alias, query);
+            }
+        }
+    }
+
+    #[test]
+    fn store_stats_query_only_mapped_fields() {
+        let mapping = [("stars", "stargazers"), ("name", "full_name")]
+            .iter()
+            .map(|(field, column)| (field.to_string(), column.to_string()))
+            .collect();
+        let (query, fields) = store_stats_query(&mapping, false);
+
+        assert_eq!(fields, vec!["name", "stars"]);
+        assert!(
+            query.contains("(id, full_name, stargazers, github_last_attempt, github_last_success)")
+        );
+        assert!(query.contains("VALUES ($1, $2, $3, NOW(), NOW())"));
+        assert!(query.contains("full_name = EXCLUDED.full_name"));
+        assert!(query.contains("stargazers = EXCLUDED.stargazers"));
+        for column in &["description", "forks", "issues", "license", "stars ="] {
+            assert!(!query.contains(column), "{} is not mapped", column);
+        }
+    }
+
+    #[test]
+    fn store_stats_query_default_mapping() {
+        let mapping = STATS_FIELDS
+            .iter()
+            .map(|field| (field.to_string(), field.to_string()))
+            .collect();
+        let (query, fields) = store_stats_query(&mapping, false);
+
+        assert_eq!(fields, STATS_FIELDS);
+        assert!(query.contains("fork_parent = EXCLUDED.fork_parent"));
+        assert!(query.contains("$18, NOW(), NOW()"));
+        assert!(query.contains("topics = COALESCE(EXCLUDED.topics, github_repos.topics)"));
+    }
+
+    #[test]
+    fn store_stats_query_only_null() {
+        let mapping = [("stars", "stars"), ("license", "license")]
+            .iter()
+            .map(|(field, column)| (field.to_string(), column.to_string()))
+            .collect();
+        let (query, fields) = store_stats_query(&mapping, true);
+
+        assert_eq!(fields, vec!["stars", "license"]);
+        // Only stored repositories have NULL columns to fill
+        assert!(query.starts_with("UPDATE github_repos SET"));
+        assert!(query.contains("stars = COALESCE(stars, $2)"));
+        assert!(query.contains("license = COALESCE(license, $3)"));
+        assert!(query.contains("null_columns_checked_at = NOW()"));
+        assert!(!query.contains("github_last_attempt = NOW()"));
+        assert!(!query.contains("github_last_success = NOW()"));
+    }
+
+    #[test]
+    fn test_only_null_columns_are_filled() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_column_mapping = Some(
+                    ["name", "description", "license", "stars"]
+                        .iter()
+                        .map(|field| (field.to_string(), field.to_string()))
+                        .collect(),
+                );
+            });
+            let updater = GithubUpdater::new(env.config(), env.db().pool())?
+                .expect("missing GitHub updater")
+                .only_null();
+            let mut conn = env.db().conn();
+            conn.execute(
+                "INSERT INTO github_repos (id, name, description, license, stars, forks, issues,
+                                           github_last_attempt)
+                 VALUES ('partial', 'owner/partial', 'kept', NULL, 10, 0, 0,
+                         NOW() - INTERVAL '2 hours'),
+                        ('unlicensed', 'owner/unlicensed', 'kept', NULL, 10, 0, 0, NOW()),
+                        ('complete', 'owner/complete', 'kept', 'MIT', 10, 0, 0, NOW());",
+                &[],
+            )?;
+            assert_eq!(
+                repositories_with_null_columns(&mut conn, &column_mapping(&env.config()))?,
+                vec!["partial", "unlicensed"]
+            );
+
+            let stats = RepositoryStats {
+                name: "owner/partial".into(),
+                description: Some("replaced".into()),
+                last_commit: None,
+                updated_at: None,
+                stars: 20,
+                forks: 0,
+                issues: 0,
+                good_first_issues: None,
+                license: Some("Apache-2.0".into()),
+                license_url: None,
+                size_kb: 0,
+                is_fork: false,
+                is_archived: false,
+                closed_issues: None,
+                fork_parent: None,
+                open_bugs: None,
+                topics: None,
+            };
+            updater.store_stats(&mut conn, "partial", &stats)?;
+            updater.store_stats(
+                &mut conn,
+                "unlicensed",
+                &RepositoryStats {
+                    name: "owner/unlicensed".into(),
+                    license: None,
+                    ..stats.clone()
+                },
+            )?;
+
+            let row = conn.query_one(
+                "SELECT description, license, stars, github_last_attempt < NOW() - INTERVAL '1 hour'
+                 FROM github_repos WHERE id = 'partial'",
+                &[],
+            )?;
+            assert_eq!(row.get::<_, Option<String>>(0).as_deref(), Some("kept"));
+            assert_eq!(
+                row.get::<_, Option<String>>(1).as_deref(),
+                Some("Apache-2.0")
+            );
+            assert_eq!(row.get::<_, i32>(2), 10);
+            // The other stats weren't refreshed
+            assert!(row.get::<_, bool>(3));
+
+            // The column GitHub has no value for isn't filled again by the next runs
+            assert!(
+                repositories_with_null_columns(&mut conn, &column_mapping(&env.config()))?
+                    .is_empty()
+            );
+
+            Ok(())
+        });
+    }
 
     #[test]
-    fn test_repository_name() {
-        macro_rules! assert_name {
-            ($url:expr => ($owner:expr, $repo: expr)) => {
+    fn test_repo_path() {
+        macro_rules! assert_path {
+            ($url:expr => $path:expr) => {
                 assert_eq!(
-                    RepositoryName::from_url($url),
-                    Some(RepositoryName {
-                        owner: $owner,
-                        repo: $repo
-                    })
+                    RepoPath::from_url($url).map(|path| path.to_string()),
+                    Some($path.to_string())
                 );
             };
         }
 
-        assert_name!("https://github.com/onur/cratesfyi" => ("onur", "cratesfyi"));
-        assert_name!("http://github.com/onur/cratesfyi" => ("onur", "cratesfyi"));
-        assert_name!("https://www.github.com/onur/cratesfyi" => ("onur", "cratesfyi"));
-        assert_name!("http://www.github.com/onur/cratesfyi" => ("onur", "cratesfyi"));
-        assert_name!("https://github.com/onur/cratesfyi.git" => ("onur", "cratesfyi"));
-        assert_name!("https://github.com/docopt/docopt.rs" => ("docopt", "docopt.rs"));
-        assert_name!("https://github.com/onur23cmD_M_R_L_/crates_fy-i" => (
-            "onur23cmD_M_R_L_", "crates_fy-i"
-        ));
+        assert_path!("https://github.com/onur/cratesfyi" => "onur/cratesfyi");
+        assert_path!("http://github.com/onur/cratesfyi" => "onur/cratesfyi");
+        assert_path!("https://www.github.com/onur/cratesfyi" => "onur/cratesfyi");
+        assert_path!("http://www.github.com/onur/cratesfyi" => "onur/cratesfyi");
+        assert_path!("https://github.com/onur/cratesfyi.git" => "onur/cratesfyi");
+        assert_path!("https://github.com/docopt/docopt.rs" => "docopt/docopt.rs");
+        assert_path!("https://github.com/onur23cmD_M_R_L_/crates_fy-i" => "onur23cmD_M_R_L_/crates_fy-i");
+        assert_eq!(
+            RepoPath::from_url("https://gitlab.com/onur/cratesfyi"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_repo_path_percent_encoded() {
+        let path = |url| RepoPath::from_url(url).map(|path| path.to_string());
+
+        assert_eq!(
+            path("https://github.com/onur%2Fcratesfyi"),
+            Some("onur/cratesfyi".into())
+        );
+        assert_eq!(
+            path("https://github.com/onur/cratesfyi/tree/feature%2Fbranch"),
+            Some("onur/cratesfyi".into())
+        );
+        assert_eq!(
+            path("https%3A%2F%2Fgithub.com%2Fonur%2Fcratesfyi"),
+            Some("onur/cratesfyi".into())
+        );
+        assert_eq!(
+            path("https://github.com/onur/cratesfyi%40v1.0"),
+            Some("onur/cratesfyi".into())
+        );
+        assert_eq!(
+            path("git%2Bhttps://github.com/onur/cratesfyi.git"),
+            Some("onur/cratesfyi".into())
+        );
+        // Invalid UTF-8 is replaced instead of failing the whole URL
+        assert_eq!(
+            path("https://github.com/onur/cratesfyi/%FF"),
+            Some("onur/cratesfyi".into())
+        );
+
+        assert_eq!(path("https://github.com/onur/crates%00fyi"), None);
+        assert_eq!(path("https://github.com/onur%00/cratesfyi"), None);
+        assert_eq!(path("https://github.com/onur/cratesfyi%00"), None);
+        assert_eq!(path("https://gitlab.com%2Fonur%2Fcratesfyi"), None);
+    }
+
+    #[test]
+    fn test_gist_urls() {
+        for url in &[
+            "https://gist.github.com/onur/cratesfyi",
+            "https://gist.github.com/onur/0123456789abcdef0123456789abcdef",
+            "http://gist.github.com/0123456789abcdef",
+            "https://www.gist.github.com/onur/cratesfyi",
+            "git+https://gist.github.com/onur/0123456789abcdef.git",
+            "https%3A%2F%2Fgist.github.com%2Fonur%2Fcratesfyi",
+            "HTTPS://GIST.GITHUB.COM/onur/cratesfyi",
+            "gist.github.com/onur/cratesfyi",
+        ] {
+            assert!(
+                is_gist(&percent_decode_str(url).decode_utf8_lossy()),
+                "{} isn't a gist",
+                url
+            );
+            assert_eq!(
+                RepoPath::from_url(url),
+                None,
+                "{} parsed as a repository",
+                url
+            );
+        }
+
+        for url in &[
+            "https://github.com/onur/gist",
+            "https://github.com/gist/cratesfyi",
+            "https://github.com/onur/gist.github.com",
+        ] {
+            assert!(!is_gist(url), "{} is a gist", url);
+            assert!(RepoPath::from_url(url).is_some());
+        }
+    }
+
+    #[test]
+    fn test_gists_are_not_loaded() {
+        wrapper(|env| {
+            // Any request to the API fails, as nothing listens on the port
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_api_base = "http://127.0.0.1:1".into();
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            let release = env
+                .fake_release()
+                .name("gisted")
+                .version("0.1.0")
+                .repo("https://gist.github.com/onur/0123456789abcdef")
+                .create()?;
+            let mut conn = env.db().conn();
+            assert_eq!(
+                updater
+                    .load_repository(&mut conn, "https://gist.github.com/onur/0123456789abcdef")?,
+                None
+            );
+
+            updater.backfill_repositories()?;
+            let repo: Option<String> = conn
+                .query_one(
+                    "SELECT github_repo FROM releases WHERE id = $1",
+                    &[&release],
+                )?
+                .get(0);
+            assert_eq!(repo, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_repo_path_display() {
+        let path = RepoPath {
+            owner: "rust-lang".into(),
+            repo: "docs.rs".into(),
+        };
+        assert_eq!(path.to_string(), "rust-lang/docs.rs");
+        assert_eq!(
+            RepoPath::from_url("https://github.com/rust-lang/docs.rs.git"),
+            Some(path)
+        );
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_updater_concurrency = 4;
+                config.max_pool_size = 4;
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            let ids = (0..UPDATE_CHUNK_SIZE * 8)
+                .map(|id| format!("repo-{}", id))
+                .collect::<Vec<_>>();
+            let store = |conn: &mut Client, chunk: &[String]| {
+                for id in chunk {
+                    updater.store_repository(
+                        conn,
+                        &GraphRepository {
+                            id: id.clone(),
+                            name_with_owner: format!("owner/{}", id),
+                            created_at: None,
+                            pushed_at: None,
+                            updated_at: None,
+                            description: None,
+                            stargazer_count: 10,
+                            fork_count: 0,
+                            issues: GraphIssues { total_count: 0 },
+                            license_info: None,
+                            disk_usage: None,
+                            is_fork: false,
+                            is_archived: false,
+                            closed_issues: None,
+                            good_first_issues: None,
+                            open_bugs: None,
+                            parent: None,
+                            repository_topics: None,
+                        },
+                    )?;
+                }
+                Ok(())
+            };
+
+            // Store every repository twice, to have the workers race on the same rows.
+            updater.for_each_chunk(&ids, store)?;
+            updater.for_each_chunk(&ids, store)?;
+
+            let mut conn = env.db().conn();
+            let row = conn.query_one("SELECT COUNT(*), SUM(stars) FROM github_repos;", &[])?;
+            assert_eq!(row.get::<_, i64>(0) as usize, ids.len());
+            assert_eq!(row.get::<_, i64>(1) as usize, ids.len() * 10);
+
+            // The first error stops the processing and is returned, even when the chunks the
+            // other workers were processing fail afterwards
+            let processed = AtomicUsize::new(0);
+            let err = updater
+                .for_each_chunk(&ids, |_, chunk| {
+                    processed.fetch_add(1, Ordering::SeqCst);
+                    if chunk[0] == ids[0] {
+                        return Err(RateLimitReached.into());
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                    Err(failure::err_msg("failed later"))
+                })
+                .unwrap_err();
+            assert!(err.downcast_ref::<RateLimitReached>().is_some());
+            assert!(processed.load(Ordering::SeqCst) <= 4);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_update_callbacks() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let updates = Arc::new(Mutex::new(Vec::new()));
+            let errors = Arc::new(Mutex::new(Vec::new()));
+            let updater = {
+                let (updates, errors) = (updates.clone(), errors.clone());
+                GithubUpdater::new(env.config(), env.db().pool())?
+                    .expect("missing GitHub updater")
+                    .on_update(move |id, stats| {
+                        updates
+                            .lock()
+                            .unwrap()
+                            .push((id.to_string(), stats.clone()))
+                    })
+                    .on_error(move |id, err| {
+                        errors.lock().unwrap().push((id.to_string(), err.clone()))
+                    })
+            };
+
+            let ids = vec!["found".to_string(), "deleted".to_string()];
+            let response = parse_graphql_response(
+                r#"{"data": {
+                    "nodes": [
+                        {
+                            "id": "found",
+                            "nameWithOwner": "owner/found",
+                            "pushedAt": null,
+                            "description": "a repository",
+                            "stargazerCount": 10,
+                            "forkCount": 2,
+                            "issues": {"totalCount": 3},
+                            "licenseInfo": {"spdxId": "MIT", "url": "https://mit-license.org"},
+                            "diskUsage": 42
+                        },
+                        null
+                    ],
+                    "rateLimit": {"remaining": 5000}
+                }, "errors": [
+                    {"type": "NOT_FOUND", "path": ["nodes", 1], "message": "not found"}
+                ]}"#,
+            );
+            let mut conn = env.db().conn();
+            updater.store_update_response(&mut conn, &ids, response)?;
+
+            assert_eq!(
+                *updates.lock().unwrap(),
+                vec![(
+                    "found".to_string(),
+                    RepositoryStats {
+                        name: "owner/found".into(),
+                        description: Some("a repository".into()),
+                        last_commit: None,
+                        updated_at: None,
+                        stars: 10,
+                        forks: 2,
+                        issues: 3,
+                        good_first_issues: None,
+                        license: Some("MIT".into()),
+                        license_url: Some("https://mit-license.org".into()),
+                        size_kb: 42,
+                        is_fork: false,
+                        is_archived: false,
+                        closed_issues: None,
+                        fork_parent: None,
+                        open_bugs: None,
+                        topics: None,
+                    }
+                )]
+            );
+            assert_eq!(
+                *errors.lock().unwrap(),
+                vec![("deleted".to_string(), GithubUpdateError::NotFound)]
+            );
+
+            // A failed request is reported for every repository it was updating
+            errors.lock().unwrap().clear();
+            updater
+                .store_update_response(&mut conn, &ids, Err(failure::err_msg("timed out")))
+                .unwrap_err();
+            let failed = GithubUpdateError::Request("timed out".into());
+            assert_eq!(
+                *errors.lock().unwrap(),
+                vec![
+                    ("found".to_string(), failed.clone()),
+                    ("deleted".to_string(), failed),
+                ]
+            );
+            assert_eq!(updates.lock().unwrap().len(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_blocked_repositories() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let errors = Arc::new(Mutex::new(Vec::new()));
+            let mut updater = {
+                let errors = errors.clone();
+                GithubUpdater::new(env.config(), env.db().pool())?
+                    .expect("missing GitHub updater")
+                    .on_error(move |id, err| {
+                        errors.lock().unwrap().push((id.to_string(), err.clone()))
+                    })
+            };
+
+            // GitHub rejects the whole request if any of the repositories is blocked
+            let handler = |req: &mut iron::Request| {
+                let mut body = String::new();
+                req.body.read_to_string(&mut body).unwrap();
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let ids = body["variables"]["ids"].as_array().unwrap();
+                if ids.iter().any(|id| id == "blocked") {
+                    return Ok(iron::Response::with(
+                        iron::status::UnavailableForLegalReasons,
+                    ));
+                }
+                let nodes: Vec<_> = ids
+                    .iter()
+                    .map(|id| {
+                        serde_json::json!({
+                            "id": id,
+                            "nameWithOwner": format!("owner/{}", id.as_str().unwrap()),
+                            "pushedAt": null,
+                            "description": null,
+                            "stargazerCount": 1,
+                            "forkCount": 0,
+                            "issues": {"totalCount": 0},
+                            "licenseInfo": null,
+                            "diskUsage": 1
+                        })
+                    })
+                    .collect();
+                let body = serde_json::json!({
+                    "data": {"nodes": nodes, "rateLimit": {"remaining": 5000}}
+                });
+                Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+            };
+            let server = TestServer::start(handler)?;
+            updater.graphql_url = format!("{}/graphql", server.url());
+
+            let mut conn = env.db().conn();
+            for id in &["blocked", "fine"] {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                     VALUES ($1, $1, 5, 0, 0, NOW() - INTERVAL '2 days');",
+                    &[id],
+                )?;
+            }
+            let ids = ["blocked".to_string(), "fine".to_string()];
+            assert_eq!(updater.update_ids(&ids)?, ids);
+
+            let status = |conn: &mut Client, id: &str| -> Result<Option<String>> {
+                Ok(conn
+                    .query_one(
+                        "SELECT github_status FROM github_repos WHERE id = $1",
+                        &[&id],
+                    )?
+                    .get(0))
+            };
+            assert_eq!(status(&mut conn, "blocked")?.as_deref(), Some("blocked"));
+            assert_eq!(status(&mut conn, "fine")?, None);
+            assert_eq!(
+                *errors.lock().unwrap(),
+                vec![("blocked".to_string(), GithubUpdateError::Blocked)]
+            );
+            assert_eq!(updater.stats.lock().unwrap().blocked, 1);
+            // The stats of the blocked repository are kept
+            let stars: i32 = conn
+                .query_one("SELECT stars FROM github_repos WHERE id = 'blocked'", &[])?
+                .get(0);
+            assert_eq!(stars, 5);
+
+            // Blocked repositories are only checked again after a while
+            conn.execute(
+                "UPDATE github_repos SET github_last_attempt = NOW() - INTERVAL '3 days'
+                 WHERE id = 'fine'",
+                &[],
+            )?;
+            conn.execute(
+                "UPDATE github_repos SET github_last_attempt = NOW() - make_interval(days => $1 + 1)
+                 WHERE id = 'blocked'",
+                &[&BLOCKED_REFRESH_DAYS],
+            )?;
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["blocked", "fine"]
+            );
+            conn.execute(
+                "UPDATE github_repos SET github_last_attempt = NOW() - INTERVAL '2 days'
+                 WHERE id = 'blocked'",
+                &[],
+            )?;
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["fine"]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_last_attempt_and_success() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            let mut conn = env.db().conn();
+            for id in &["blocked", "fine"] {
+                conn.execute(
+                    "INSERT INTO github_repos
+                        (id, name, stars, forks, issues, github_last_attempt, github_last_success)
+                     VALUES ($1, $1, 5, 0, 0, NOW() - INTERVAL '2 days', NOW() - INTERVAL '2 days');",
+                    &[id],
+                )?;
+            }
+            updater.handle_errors(
+                &mut conn,
+                &["blocked".to_string()],
+                &GithubUpdateError::Blocked,
+            )?;
+            let stats = RepositoryStats {
+                name: "owner/fine".into(),
+                description: None,
+                last_commit: None,
+                updated_at: None,
+                stars: 6,
+                forks: 0,
+                issues: 0,
+                good_first_issues: None,
+                license: None,
+                license_url: None,
+                size_kb: 0,
+                is_fork: false,
+                is_archived: false,
+                closed_issues: None,
+                fork_parent: None,
+                open_bugs: None,
+                topics: None,
+            };
+            updater.store_stats(&mut conn, "fine", &stats)?;
+
+            let recent = |conn: &mut Client, id: &str| -> Result<(bool, bool)> {
+                let row = conn.query_one(
+                    "SELECT github_last_attempt > NOW() - INTERVAL '1 hour',
+                            github_last_success > NOW() - INTERVAL '1 hour'
+                     FROM github_repos WHERE id = $1",
+                    &[&id],
+                )?;
+                Ok((row.get(0), row.get(1)))
+            };
+            // The failed attempt isn't a successful update
+            assert_eq!(recent(&mut conn, "blocked")?, (true, false));
+            assert_eq!(recent(&mut conn, "fine")?, (true, true));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_absent_topics_are_kept() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+            let mut conn = env.db().conn();
+
+            let stats = |topics: Option<Vec<String>>| RepositoryStats {
+                name: "owner/repo".into(),
+                description: None,
+                last_commit: None,
+                updated_at: None,
+                stars: 1,
+                forks: 0,
+                issues: 0,
+                good_first_issues: None,
+                license: None,
+                license_url: None,
+                size_kb: 0,
+                is_fork: false,
+                is_archived: false,
+                closed_issues: None,
+                fork_parent: None,
+                open_bugs: None,
+                topics,
+            };
+            let topics = |conn: &mut Client| -> Result<Option<Vec<String>>> {
+                Ok(conn
+                    .query_one("SELECT topics FROM github_repos WHERE id = 'repo'", &[])?
+                    .get(0))
+            };
+
+            updater.store_stats(
+                &mut conn,
+                "repo",
+                &stats(Some(vec!["rust".into(), "docs".into()])),
+            )?;
+            assert_eq!(
+                topics(&mut conn)?,
+                Some(vec!["rust".to_string(), "docs".to_string()])
+            );
+
+            // A cached response without the topics doesn't remove them
+            updater.store_stats(&mut conn, "repo", &stats(None))?;
+            assert_eq!(
+                topics(&mut conn)?,
+                Some(vec!["rust".to_string(), "docs".to_string()])
+            );
+
+            // But a response with no topics does
+            updater.store_stats(&mut conn, "repo", &stats(Some(Vec::new())))?;
+            assert_eq!(topics(&mut conn)?, Some(Vec::new()));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_fetch_readme() {
+        wrapper(|env| {
+            let handler = |req: &mut iron::Request| {
+                let raw = req
+                    .headers
+                    .get_raw("Accept")
+                    .map_or(false, |accept| accept[0] == b"application/vnd.github.raw");
+                match req.url.path().join("/").as_str() {
+                    "repos/owner/long/readme" if raw => {
+                        Ok(iron::Response::with((iron::status::Ok, "# éééé")))
+                    }
+                    "repos/owner/flaky/readme" => Ok(iron::Response::with(iron::status::Forbidden)),
+                    _ => Ok(iron::Response::with(iron::status::NotFound)),
+                }
+            };
+            let server = TestServer::start(handler)?;
+            let api_base = server.url();
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_api_base = api_base;
+                config.github_updater_readme = true;
+                // Truncates the README in the middle of the second `é`
+                config.github_readme_max_size = 5;
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            assert_eq!(updater.fetch_readme("owner/long")?.as_deref(), Some("# é"));
+            assert_eq!(updater.fetch_readme("owner/missing")?, None);
+            assert!(updater.fetch_readme("owner/flaky").is_err());
+
+            let mut conn = env.db().conn();
+            for id in &["long", "missing", "flaky"] {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt,
+                                               github_readme)
+                     VALUES ($1, $1, 0, 0, 0, NOW(), 'previous');",
+                    &[id],
+                )?;
+                updater.update_readme(&mut conn, id, &format!("owner/{}", id))?;
+            }
+            let readme = |conn: &mut Client, id: &str| -> Result<Option<String>> {
+                Ok(conn
+                    .query_one(
+                        "SELECT github_readme FROM github_repos WHERE id = $1",
+                        &[&id],
+                    )?
+                    .get(0))
+            };
+            assert_eq!(readme(&mut conn, "long")?.as_deref(), Some("# é"));
+            assert_eq!(readme(&mut conn, "missing")?, None);
+            // Failing to fetch a README keeps the previous one
+            assert_eq!(readme(&mut conn, "flaky")?.as_deref(), Some("previous"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_retry_policies() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_retry_policies =
+                    "transient=2/0s/false,request=0/0s/true".parse().unwrap();
+            });
+            let mut updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            // The first two requests for `flaky` fail with a server error, the ones for `broken`
+            // are always rejected, and the ones for `limited` exhausted the rate limit
+            let requests = Arc::new(Mutex::new(HashMap::<String, usize>::new()));
+            let handler = {
+                let requests = requests.clone();
+                move |req: &mut iron::Request| {
+                    let mut body = String::new();
+                    req.body.read_to_string(&mut body).unwrap();
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    let id = body["variables"]["ids"][0].as_str().unwrap().to_string();
+                    let attempt = {
+                        let mut requests = requests.lock().unwrap();
+                        let count = requests.entry(id.clone()).or_default();
+                        *count += 1;
+                        *count
+                    };
+                    if id == "broken" {
+                        return Ok(iron::Response::with(iron::status::BadRequest));
+                    }
+                    if id == "limited" {
+                        let mut response = iron::Response::with(iron::status::Forbidden);
+                        response
+                            .headers
+                            .set_raw("X-RateLimit-Remaining", vec![b"0".to_vec()]);
+                        return Ok(response);
+                    }
+                    if attempt <= 2 {
+                        return Ok(iron::Response::with(iron::status::ServiceUnavailable));
+                    }
+                    let body = serde_json::json!({
+                        "data": {
+                            "nodes": [{
+                                "id": id,
+                                "nameWithOwner": "owner/flaky",
+                                "pushedAt": null,
+                                "description": null,
+                                "stargazerCount": 7,
+                                "forkCount": 0,
+                                "issues": {"totalCount": 0},
+                                "licenseInfo": null,
+                                "diskUsage": 1
+                            }],
+                            "rateLimit": {"remaining": 5000}
+                        }
+                    });
+                    Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+                }
+            };
+            let server = TestServer::start(handler)?;
+            updater.graphql_url = format!("{}/graphql", server.url());
+
+            let mut conn = env.db().conn();
+            for id in &["flaky", "broken", "limited"] {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                     VALUES ($1, $1, 5, 0, 0, NOW() - INTERVAL '2 days');",
+                    &[id],
+                )?;
+            }
+
+            // Transient errors are retried
+            assert_eq!(updater.update_ids(&["flaky".to_string()])?.len(), 1);
+            assert_eq!(requests.lock().unwrap()["flaky"], 3);
+            let stars: i32 = conn
+                .query_one("SELECT stars FROM github_repos WHERE id = 'flaky'", &[])?
+                .get(0);
+            assert_eq!(stars, 7);
+
+            // Other errors aren't, but the repository is marked as updated anyway
+            updater.update_ids(&["broken".to_string()]).unwrap_err();
+            assert_eq!(requests.lock().unwrap()["broken"], 1);
+            assert_eq!(updater.stats.lock().unwrap().failed, 1);
+
+            // Reaching the rate limit is never retried, as it won't be reset by then
+            assert!(updater.update_ids(&["limited".to_string()])?.is_empty());
+            assert_eq!(requests.lock().unwrap()["limited"], 1);
+            assert_eq!(updater.stats.lock().unwrap().rate_limited, 1);
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["limited".to_string()]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        wrapper(|env| {
+            let variables = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+            let handler = {
+                let variables = variables.clone();
+                move |req: &mut iron::Request| {
+                    let mut body = String::new();
+                    req.body.read_to_string(&mut body).unwrap();
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    variables.lock().unwrap().push(body["variables"].clone());
+
+                    // The optional fields are missing when they aren't asked for
+                    let body = serde_json::json!({
+                        "data": {
+                            "nodes": [{
+                                "id": "repo",
+                                "nameWithOwner": "owner/repo",
+                                "pushedAt": null,
+                                "description": null,
+                                "stargazerCount": 7,
+                                "forkCount": 0,
+                                "issues": {"totalCount": 0},
+                                "diskUsage": 1
+                            }],
+                            "rateLimit": {"remaining": 5000}
+                        }
+                    });
+                    Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+                }
+            };
+            let server = TestServer::start(handler)?;
+            let graphql_url = format!("{}/graphql", server.url());
+
+            let mut conn = env.db().conn();
+            conn.execute(
+                "INSERT INTO github_repos (id, name, stars, forks, issues, license, fork_parent,
+                                           github_last_attempt)
+                 VALUES ('repo', 'owner/repo', 5, 0, 0, 'MIT', 'owner/original', NOW());",
+                &[],
+            )?;
+
+            let update = |fields: FieldSet| -> Result<serde_json::Value> {
+                let mut config = env.base_config();
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_fields = fields;
+                let mut updater = GithubUpdater::new(Arc::new(config), env.db().pool())?
+                    .expect("missing GitHub updater");
+                updater.graphql_url = graphql_url.clone();
+                assert_eq!(updater.update_ids(&["repo".to_string()])?.len(), 1);
+                Ok(variables.lock().unwrap().pop().expect("missing request"))
+            };
+
+            // A minimal deployment only asks for the base stats, and keeps the optional ones
+            let sent = update(FieldSet::empty())?;
+            assert_eq!(sent["goodFirstIssues"], false);
+            assert_eq!(sent["license"], false);
+            assert_eq!(sent["forkParent"], false);
+            assert_eq!(sent["openBugs"], false);
+            assert_eq!(sent["archived"], false);
+            assert_eq!(sent["closedIssues"], false);
+            assert_eq!(sent["topics"], false);
+            let row = conn.query_one(
+                "SELECT stars, license, fork_parent FROM github_repos WHERE id = 'repo'",
+                &[],
+            )?;
+            assert_eq!(row.get::<_, i32>(0), 7);
+            assert_eq!(row.get::<_, Option<String>>(1).as_deref(), Some("MIT"));
+            assert_eq!(
+                row.get::<_, Option<String>>(2).as_deref(),
+                Some("owner/original")
+            );
+
+            let sent = update(FieldSet::LICENSE | FieldSet::GOOD_FIRST_ISSUES)?;
+            assert_eq!(sent["goodFirstIssues"], true);
+            assert_eq!(sent["license"], true);
+            assert_eq!(sent["forkParent"], false);
+            assert_eq!(sent["archived"], false);
+            let row = conn.query_one(
+                "SELECT license, fork_parent FROM github_repos WHERE id = 'repo'",
+                &[],
+            )?;
+            assert_eq!(row.get::<_, Option<String>>(0), None);
+            assert_eq!(
+                row.get::<_, Option<String>>(1).as_deref(),
+                Some("owner/original")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_queue_repository_refresh() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("stale")
+                .github_stats("owner/stale", 1, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("fresh")
+                .github_stats("owner/fresh", 1, 0, 0)
+                .create()?;
+            let mut conn = env.db().conn();
+            conn.execute(
+                "UPDATE github_repos SET github_last_attempt = NOW() - INTERVAL '1 day'
+                 WHERE name = 'owner/stale';",
+                &[],
+            )?;
+            conn.execute(
+                "UPDATE github_repos SET github_last_attempt = NOW() WHERE name = 'owner/fresh';",
+                &[],
+            )?;
+            conn.execute(
+                "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                 VALUES ('gitlab:1', 'owner/other', 0, 0, 0, NOW() - INTERVAL '1 day');",
+                &[],
+            )?;
+            let id = |conn: &mut Client, name: &str| -> Result<String> {
+                Ok(conn
+                    .query_one("SELECT id FROM github_repos WHERE name = $1", &[&name])?
+                    .get(0))
+            };
+            let queued = |conn: &mut Client| -> Result<Vec<String>> {
+                Ok(conn
+                    .query(
+                        "SELECT repository_name FROM webhook_events
+                         WHERE event = 'release' AND processed_at IS NULL",
+                        &[],
+                    )?
+                    .into_iter()
+                    .map(|row| row.get(0))
+                    .collect())
+            };
+
+            let stale = id(&mut conn, "owner/stale")?;
+            assert!(queue_repository_refresh(&mut conn, &stale)?);
+            // Already queued
+            assert!(!queue_repository_refresh(&mut conn, &stale)?);
+            let fresh = id(&mut conn, "owner/fresh")?;
+            assert!(!queue_repository_refresh(&mut conn, &fresh)?);
+            // Only the GitHub repositories are updated with the webhook events
+            assert!(!queue_repository_refresh(&mut conn, "gitlab:1")?);
+            assert!(!queue_repository_refresh(&mut conn, "unknown")?);
+            assert_eq!(queued(&mut conn)?, vec!["owner/stale"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_update_named_crates() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            // Both repositories were just updated, so they wouldn't need an update yet
+            for name in &["first", "second", "skipped"] {
+                env.fake_release()
+                    .name(name)
+                    .version("1.0.0")
+                    .github_stats(format!("owner/{}", name), 1, 0, 0)
+                    .create()?;
+            }
+            env.fake_release().name("local").version("0.1.0").create()?;
+
+            let mut conn = env.db().conn();
+            let names: HashMap<String, String> = conn
+                .query("SELECT id, name FROM github_repos", &[])?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            let id = |name: &str| {
+                names
+                    .iter()
+                    .find(|(_, repo)| *repo == name)
+                    .map(|(id, _)| id.clone())
+                    .unwrap()
+            };
+
+            let requested = Arc::new(Mutex::new(Vec::<String>::new()));
+            let handler = {
+                let requested = requested.clone();
+                let names = names.clone();
+                move |req: &mut iron::Request| {
+                    let mut body = String::new();
+                    req.body.read_to_string(&mut body).unwrap();
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    let nodes: Vec<_> = body["variables"]["ids"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|id| {
+                            let id = id.as_str().unwrap();
+                            requested.lock().unwrap().push(names[id].clone());
+                            serde_json::json!({
+                                "id": id,
+                                "nameWithOwner": names[id],
+                                "pushedAt": null,
+                                "description": null,
+                                "stargazerCount": 42,
+                                "forkCount": 1,
+                                "issues": {"totalCount": 2},
+                                "diskUsage": 1,
+                                "licenseInfo": null,
+                            })
+                        })
+                        .collect();
+                    let body = serde_json::json!({
+                        "data": {"nodes": nodes, "rateLimit": {"remaining": 5000}},
+                    });
+                    Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+                }
+            };
+            let server = TestServer::start(handler)?;
+            let graphql_url = format!("{}/graphql", server.url());
+
+            let mut updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+            updater.graphql_url = graphql_url;
+
+            let results =
+                updater.update_crates(&mut conn, &["first", "second", "local", "missing"]);
+            let repositories: Vec<_> = results
+                .iter()
+                .map(|result| match result {
+                    CrateUpdate::Updated { repository } => Some(repository.clone()),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(
+                repositories,
+                vec![
+                    Some(id("owner/first")),
+                    Some(id("owner/second")),
+                    None,
+                    None
+                ]
+            );
+            assert!(matches!(results[2], CrateUpdate::NoRepository));
+            assert!(matches!(results[3], CrateUpdate::UnknownCrate));
+
+            let mut requested = requested.lock().unwrap().clone();
+            requested.sort();
+            assert_eq!(requested, vec!["owner/first", "owner/second"]);
+            let stars: Vec<(String, i32)> = conn
+                .query("SELECT name, stars FROM github_repos ORDER BY name", &[])?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(
+                stars,
+                vec![
+                    ("owner/first".to_string(), 42),
+                    ("owner/second".to_string(), 42),
+                    ("owner/skipped".to_string(), 1),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_update_webhook_repositories() {
+        wrapper(|env| {
+            let requested = Arc::new(Mutex::new(Vec::<String>::new()));
+            let handler = {
+                let requested = requested.clone();
+                move |req: &mut iron::Request| {
+                    let mut body = String::new();
+                    req.body.read_to_string(&mut body).unwrap();
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    let ids: Vec<String> =
+                        serde_json::from_value(body["variables"]["ids"].clone()).unwrap();
+                    requested.lock().unwrap().extend(ids.iter().cloned());
+
+                    let body = serde_json::json!({
+                        "data": {
+                            "nodes": ids.iter().map(|id| serde_json::json!({
+                                "id": id,
+                                "nameWithOwner": format!("owner/{}", id),
+                                "pushedAt": null,
+                                "description": null,
+                                "stargazerCount": 100,
+                                "forkCount": 0,
+                                "issues": {"totalCount": 0},
+                                "diskUsage": 1,
+                            })).collect::<Vec<_>>(),
+                            "rateLimit": {"remaining": 5000},
+                        },
+                    });
+                    Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+                }
+            };
+            let server = TestServer::start(handler)?;
+            let graphql_url = format!("{}/graphql", server.url());
+
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let mut updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+            updater.graphql_url = graphql_url;
+
+            let mut conn = env.db().conn();
+            conn.batch_execute(
+                "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                 VALUES ('starred', 'owner/starred', 1, 0, 0, NOW()),
+                        ('pushed', 'owner/pushed', 1, 0, 0, NOW()),
+                        ('quiet', 'owner/quiet', 1, 0, 0, NOW());
+                 INSERT INTO webhook_events (event, repository_id, repository_name)
+                 VALUES ('star', 'starred', 'owner/starred'),
+                        ('push', 'legacy-id', 'owner/pushed'),
+                        ('push', 'legacy-id', 'owner/pushed'),
+                        ('star', 'unknown', 'owner/unknown');",
+            )?;
+            let pending = |conn: &mut Client| -> Result<i64> {
+                Ok(conn
+                    .query_one(
+                        "SELECT COUNT(*) FROM webhook_events WHERE processed_at IS NULL",
+                        &[],
+                    )?
+                    .get(0))
+            };
+
+            // A run stopped before updating anything only processes the events of the
+            // repositories that aren't stored
+            let stopped = GithubUpdater::new(env.config(), env.db().pool())?
+                .expect("missing GitHub updater")
+                .stop_on(Arc::new(AtomicBool::new(true)));
+            assert_eq!(stopped.update_webhook_repositories()?, 0);
+            assert!(requested.lock().unwrap().is_empty());
+            assert_eq!(pending(&mut conn)?, 3);
+
+            // Only the repositories with events are updated, once each
+            assert_eq!(updater.update_webhook_repositories()?, 2);
+            assert_eq!(
+                *requested.lock().unwrap(),
+                vec!["pushed".to_string(), "starred".to_string()]
+            );
+            let stars: Vec<(String, i32)> = conn
+                .query("SELECT id, stars FROM github_repos ORDER BY id", &[])?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(
+                stars,
+                vec![
+                    ("pushed".into(), 100),
+                    ("quiet".into(), 1),
+                    ("starred".into(), 100)
+                ]
+            );
+            assert_eq!(pending(&mut conn)?, 0);
+
+            // The processed events aren't processed again
+            assert_eq!(updater.update_webhook_repositories()?, 0);
+            assert_eq!(requested.lock().unwrap().len(), 2);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_compare_to_previous_run() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            let first = UpdateStats {
+                updated: 100,
+                not_found: 2,
+                rate_limited: 0,
+                blocked: 1,
+                failed: 1,
+                ..UpdateStats::default()
+            };
+            assert_eq!(record_run(&mut conn, &first)?, None);
+
+            // Runs that stopped early aren't compared to
+            let interrupted = UpdateStats {
+                updated: 3,
+                interrupted: true,
+                ..UpdateStats::default()
+            };
+            assert_eq!(record_run(&mut conn, &interrupted)?, None);
+
+            let second = UpdateStats {
+                updated: 50,
+                not_found: 4,
+                rate_limited: 7,
+                blocked: 1,
+                failed: 4,
+                ..UpdateStats::default()
+            };
+            let previous = record_run(&mut conn, &second)?.expect("missing previous run");
+            assert_eq!(previous, first);
+            let diff = StatsDiff {
+                previous,
+                current: second,
+            };
+            assert_eq!(
+                diff.to_string(),
+                "updated down 50% (100 to 50), not found doubled (2 to 4), \
+                 rate limited up from 0 to 7, failed up 300% (1 to 4)"
+            );
+            assert!(diff.regressed());
+
+            // The next run is compared to the last one
+            assert_eq!(record_run(&mut conn, &second)?, Some(second));
+            let diff = StatsDiff {
+                previous: second,
+                current: second,
+            };
+            assert_eq!(diff.to_string(), "no change");
+            assert!(!diff.regressed());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_single_run_at_a_time() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                // The locks hold connections
+                config.max_pool_size = 4;
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+            let runs = || -> Result<i64> {
+                Ok(env
+                    .db()
+                    .conn()
+                    .query_one("SELECT COUNT(*) FROM github_update_runs;", &[])?
+                    .get(0))
+            };
+
+            // Another run, like one started from the command line, is in progress
+            let running = InstanceLock::acquire(&env.db().pool(), InstanceRole::GithubUpdater)?;
+            let stats = updater.update_all_crates()?;
+            assert!(stats.already_running);
+            assert_eq!(stats.updated, 0);
+            assert_eq!(runs()?, 0);
+
+            running.release()?;
+            // The daemon running the updater holds the lock of the builder
+            let daemon = InstanceLock::acquire(&env.db().pool(), InstanceRole::Builder)?;
+            assert!(!updater.update_all_crates()?.already_running);
+            assert_eq!(runs()?, 1);
+            drop(daemon);
+            // The lock is released at the end of each run
+            assert!(!updater.update_all_crates()?.already_running);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_max_run_duration() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_updater_max_run_duration = Some(Duration::from_millis(100));
+            });
+            let mut updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            // Every request lasts longer than the maximum run duration
+            let handler = |req: &mut iron::Request| {
+                thread::sleep(Duration::from_millis(300));
+                let mut body = String::new();
+                req.body.read_to_string(&mut body).unwrap();
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let nodes: Vec<_> = body["variables"]["ids"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|id| {
+                        serde_json::json!({
+                            "id": id,
+                            "nameWithOwner": format!("owner/{}", id.as_str().unwrap()),
+                            "pushedAt": null,
+                            "description": null,
+                            "stargazerCount": 1,
+                            "forkCount": 0,
+                            "issues": {"totalCount": 0},
+                            "licenseInfo": null,
+                            "diskUsage": 1
+                        })
+                    })
+                    .collect();
+                let body = serde_json::json!({
+                    "data": {"nodes": nodes, "rateLimit": {"remaining": 5000}}
+                });
+                Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+            };
+            let server = TestServer::start(handler)?;
+            updater.graphql_url = format!("{}/graphql", server.url());
+
+            let mut conn = env.db().conn();
+            for idx in 0..UPDATE_CHUNK_SIZE * 3 {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                     VALUES ($1, $1, 0, 0, 0, NOW() - INTERVAL '2 days');",
+                    &[&format!("repo-{}", idx)],
+                )?;
+            }
+            // The run needs the connections of the pool
+            drop(conn);
+
+            // Only the first chunk is updated, the run stops before the next one
+            let stats = updater.update_all_crates()?;
+            assert!(stats.timed_out);
+            assert_eq!(stats.updated, UPDATE_CHUNK_SIZE);
+            assert_eq!(
+                repositories_needing_update(&mut env.db().conn(), CandidatePriority::default())?
+                    .len(),
+                UPDATE_CHUNK_SIZE * 2
+            );
+
+            // The next run starts over with its own duration
+            let stats = updater.update_all_crates()?;
+            assert!(stats.timed_out);
+            assert_eq!(stats.updated, UPDATE_CHUNK_SIZE);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_stop_on_shutdown() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let mut updater = GithubUpdater::new(env.config(), env.db().pool())?
+                .expect("missing GitHub updater")
+                .stop_on(shutdown.clone());
+
+            // The daemon starts shutting down during the first request
+            let handler = move |req: &mut iron::Request| {
+                shutdown.store(true, Ordering::SeqCst);
+                let mut body = String::new();
+                req.body.read_to_string(&mut body).unwrap();
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let nodes: Vec<_> = body["variables"]["ids"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|id| {
+                        serde_json::json!({
+                            "id": id,
+                            "nameWithOwner": format!("owner/{}", id.as_str().unwrap()),
+                            "pushedAt": null,
+                            "description": null,
+                            "stargazerCount": 1,
+                            "forkCount": 0,
+                            "issues": {"totalCount": 0},
+                            "licenseInfo": null,
+                            "diskUsage": 1
+                        })
+                    })
+                    .collect();
+                let body = serde_json::json!({
+                    "data": {"nodes": nodes, "rateLimit": {"remaining": 5000}}
+                });
+                Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+            };
+            let server = TestServer::start(handler)?;
+            updater.graphql_url = format!("{}/graphql", server.url());
+
+            let mut conn = env.db().conn();
+            for idx in 0..UPDATE_CHUNK_SIZE * 3 {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                     VALUES ($1, $1, 0, 0, 0, NOW() - INTERVAL '2 days');",
+                    &[&format!("repo-{}", idx)],
+                )?;
+            }
+            // The run needs the connections of the pool
+            drop(conn);
+
+            // The chunk in progress is finished, but the run stops before the next one
+            let stats = updater.update_all_crates()?;
+            assert!(stats.interrupted);
+            assert!(!stats.timed_out);
+            assert_eq!(stats.updated, UPDATE_CHUNK_SIZE);
+            assert_eq!(
+                repositories_needing_update(&mut env.db().conn(), CandidatePriority::default())?
+                    .len(),
+                UPDATE_CHUNK_SIZE * 2
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_quiet() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let response = || {
+                parse_graphql_response(
+                    r#"{"data": {
+                        "nodes": [
+                            {
+                                "id": "found",
+                                "nameWithOwner": "owner/found",
+                                "pushedAt": null,
+                                "description": null,
+                                "stargazerCount": 10,
+                                "forkCount": 2,
+                                "issues": {"totalCount": 3},
+                                "licenseInfo": null
+                            },
+                            null
+                        ],
+                        "rateLimit": {"remaining": 5000}
+                    }, "errors": [
+                        {"type": "NOT_FOUND", "path": ["nodes", 1], "message": "not found"}
+                    ]}"#,
+                )
+            };
+            let ids = vec!["found".to_string(), "deleted".to_string()];
+            let mut conn = env.db().conn();
+
+            let update = |updater: &GithubUpdater, conn: &mut Client| -> Result<String> {
+                let logs = LogStorage::new(LevelFilter::Trace);
+                logging::capture(&logs, || {
+                    updater.store_update_response(conn, &ids, response())
+                })?;
+                Ok(logs.to_string())
+            };
+
+            let updater = GithubUpdater::new(env.config(), env.db().pool())?.unwrap();
+            let logs = update(&updater, &mut conn)?;
+            assert!(logs.contains("storing GitHub repository stats for owner/found"));
+            assert!(logs.contains("failed to update GitHub repository deleted"));
+
+            let updater = GithubUpdater::new(env.config(), env.db().pool())?
+                .unwrap()
+                .quiet();
+            let logs = update(&updater, &mut conn)?;
+            assert!(!logs.contains("owner/found"));
+            assert!(!logs.contains("GitHub repository deleted"));
+            // The summary is still complete
+            assert_eq!(
+                *updater.stats.lock().unwrap(),
+                UpdateStats {
+                    updated: 1,
+                    not_found: 1,
+                    rate_limited: 0,
+                    blocked: 0,
+                    failed: 0,
+                    timed_out: false,
+                    interrupted: false,
+                    already_running: false,
+                }
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_paginated_repositories_needing_update() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            for idx in 0..25 {
+                // Some repositories share the same timestamps, so the pages also rely on the IDs
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt,
+                                               rate_limited_at)
+                     VALUES ($1, $1, 0, 0, 0, NOW() - make_interval(days => $2),
+                             CASE WHEN $3 THEN NOW() - make_interval(hours => $2) END);",
+                    &[&format!("repo-{}", idx), &(2 + idx % 4), &(idx % 3 == 0)],
+                )?;
+            }
+            conn.execute(
+                "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                 VALUES ('fresh', 'fresh', 0, 0, 0, NOW());",
+                &[],
+            )?;
+            let expected = repositories_needing_update(&mut conn, CandidatePriority::default())?;
+            assert_eq!(expected.len(), 25);
+
+            let mut paginated = Vec::new();
+            let mut after = None;
+            loop {
+                let page = repositories_needing_update_page(
+                    &mut conn,
+                    CandidatePriority::default(),
+                    after.as_ref(),
+                    7,
+                )?;
+                assert!(page.len() <= 7);
+                let ids = page.iter().map(|repo| repo.id.clone()).collect::<Vec<_>>();
+                after = match page.into_iter().last() {
+                    Some(last) => Some(last),
+                    None => break,
+                };
+
+                // Updating the repositories of a page doesn't affect the following pages
+                conn.execute(
+                    "UPDATE github_repos SET github_last_attempt = NOW(), rate_limited_at = NULL
+                     WHERE id = ANY($1);",
+                    &[&ids],
+                )?;
+                paginated.extend(ids);
+            }
+            assert_eq!(paginated, expected);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_candidate_priorities() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            for (id, stars, days) in &[
+                ("popular", 100, 2),
+                ("unreleased", 50, 3),
+                ("old", 10, 4),
+                ("limited", 1, 5),
+            ] {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                     VALUES ($1, $1, $2, 0, 0, NOW() - make_interval(days => $3));",
+                    &[id, stars, days],
+                )?;
+            }
+            // Skipped by the previous run, so it still comes first
+            conn.execute(
+                "UPDATE github_repos SET rate_limited_at = NOW() WHERE id = 'limited';",
+                &[],
+            )?;
+            for (name, repo, released) in &[
+                ("popular", "popular", "2020-01-01T00:00:00Z"),
+                ("old", "old", "2021-06-01T00:00:00Z"),
+                ("old-sibling", "old", "2019-01-01T00:00:00Z"),
+                ("limited", "limited", "2018-01-01T00:00:00Z"),
+            ] {
+                let release_id = env.fake_release().name(name).create()?;
+                conn.execute(
+                    "UPDATE releases SET github_repo = $2, release_time = $3 WHERE id = $1;",
+                    &[
+                        &release_id,
+                        repo,
+                        &DateTime::parse_from_rfc3339(released)?.naive_utc(),
+                    ],
+                )?;
+            }
+
+            for (priority, expected) in &[
+                (
+                    CandidatePriority::OldestUpdated,
+                    ["limited", "old", "unreleased", "popular"],
+                ),
+                (
+                    CandidatePriority::MostStars,
+                    ["limited", "popular", "unreleased", "old"],
+                ),
+                (
+                    CandidatePriority::NewestRelease,
+                    ["limited", "old", "popular", "unreleased"],
+                ),
+            ] {
+                assert_eq!(
+                    repositories_needing_update(&mut conn, *priority)?,
+                    expected,
+                    "{:?}",
+                    priority
+                );
+
+                // The pages follow the same order
+                let mut paginated = Vec::new();
+                let mut after = None;
+                loop {
+                    let page =
+                        repositories_needing_update_page(&mut conn, *priority, after.as_ref(), 1)?;
+                    paginated.extend(page.iter().map(|repo| repo.id.clone()));
+                    after = match page.into_iter().last() {
+                        Some(last) => Some(last),
+                        None => break,
+                    };
+                }
+                assert_eq!(paginated, expected, "{:?}", priority);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_rate_limited_repositories_come_first() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+            let mut conn = env.db().conn();
+
+            for (id, days) in &[("oldest", 4), ("older", 3), ("old", 2), ("fresh", 0)] {
+                conn.execute(
+                    "INSERT INTO github_repos (id, name, stars, forks, issues, github_last_attempt)
+                     VALUES ($1, $1, 0, 0, 0, NOW() - make_interval(days => $2));",
+                    &[id, days],
+                )?;
+            }
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["oldest", "older", "old"]
+            );
+
+            // Only the repositories that still need an update are marked
+            mark_rate_limited(&mut conn, &["old".into(), "fresh".into()])?;
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["old", "oldest", "older"]
+            );
+            let fresh: Option<chrono::NaiveDateTime> = conn
+                .query_one(
+                    "SELECT rate_limited_at FROM github_repos WHERE id = 'fresh';",
+                    &[],
+                )?
+                .get(0);
+            assert!(fresh.is_none());
+
+            // The repositories skipped first stay ahead of the ones skipped later
+            conn.execute(
+                "UPDATE github_repos SET rate_limited_at = NOW() - INTERVAL '1 hour'
+                 WHERE id = 'old';",
+                &[],
+            )?;
+            mark_rate_limited(&mut conn, &["older".into(), "old".into()])?;
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["old", "older", "oldest"]
+            );
+
+            // Updating a repository clears the mark
+            updater.store_repository(
+                &mut conn,
+                &GraphRepository {
+                    id: "old".into(),
+                    name_with_owner: "owner/old".into(),
+                    created_at: None,
+                    pushed_at: None,
+                    updated_at: None,
+                    description: None,
+                    stargazer_count: 0,
+                    fork_count: 0,
+                    issues: GraphIssues { total_count: 0 },
+                    license_info: None,
+                    disk_usage: None,
+                    is_fork: false,
+                    is_archived: false,
+                    closed_issues: None,
+                    good_first_issues: None,
+                    open_bugs: None,
+                    parent: None,
+                    repository_topics: None,
+                },
+            )?;
+            assert_eq!(
+                repositories_needing_update(&mut conn, CandidatePriority::default())?,
+                vec!["older", "oldest"]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_license() {
+        let response = parse_graphql_response::<GraphRepositoryNode>(
+            r#"{"data":{"repository":{
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 3},
+                "licenseInfo": {
+                    "spdxId": "MIT",
+                    "url": "http://choosealicense.com/licenses/mit/"
+                }
+            }}}"#,
+        )
+        .unwrap();
+        let license = response.data.repository.unwrap().license_info.unwrap();
+        assert_eq!(license.spdx_id(), Some("MIT"));
+        assert_eq!(
+            license.url.as_deref(),
+            Some("http://choosealicense.com/licenses/mit/")
+        );
+
+        let license: GraphLicense =
+            serde_json::from_str(r#"{"spdxId": "NOASSERTION", "url": null}"#).unwrap();
+        assert_eq!(license.spdx_id(), None);
+        assert_eq!(license.url, None);
+
+        let response = parse_graphql_response::<GraphRepositoryNode>(
+            r#"{"data":{"repository":{
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 3},
+                "licenseInfo": null
+            }}}"#,
+        )
+        .unwrap();
+        assert!(response.data.repository.unwrap().license_info.is_none());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let response = parse_graphql_response::<GraphRepositoryNode>(
+            r#"{"data":{"repository":{
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 3},
+                "licenseInfo": null,
+                "diskUsage": 12345
+            }}}"#,
+        )
+        .unwrap();
+        assert_eq!(response.data.repository.unwrap().size_kb(), 12345);
+
+        let response = parse_graphql_response::<GraphRepositoryNode>(
+            r#"{"data":{"repository":{
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 3},
+                "licenseInfo": null
+            }}}"#,
+        )
+        .unwrap();
+        assert_eq!(response.data.repository.unwrap().size_kb(), 0);
+    }
+
+    #[test]
+    fn test_parse_good_first_issues() {
+        let parse = |good_first_issues: Option<serde_json::Value>| -> RepositoryStats {
+            let mut repository = serde_json::json!({
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 3},
+                "licenseInfo": null,
+            });
+            if let Some(good_first_issues) = good_first_issues {
+                repository["goodFirstIssues"] = good_first_issues;
+            }
+            let response = parse_graphql_response::<GraphRepositoryNode>(
+                &serde_json::json!({ "data": { "repository": repository } }).to_string(),
+            )
+            .unwrap();
+            response.data.repository.unwrap().stats()
+        };
+
+        let stats = parse(Some(serde_json::json!({"totalCount": 7})));
+        assert_eq!(stats.good_first_issues, Some(7));
+        // Repositories without the labels have no issues labeled with them
+        let stats = parse(Some(serde_json::json!({"totalCount": 0})));
+        assert_eq!(stats.good_first_issues, Some(0));
+        // The count isn't requested unless it's enabled
+        assert_eq!(parse(None).good_first_issues, None);
+    }
+
+    #[test]
+    fn test_parse_open_bugs() {
+        let parse = |open_bugs: Option<serde_json::Value>| -> RepositoryStats {
+            let mut repository = serde_json::json!({
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 30},
+                "licenseInfo": null,
+            });
+            if let Some(open_bugs) = open_bugs {
+                repository["openBugs"] = open_bugs;
+            }
+            let response = parse_graphql_response::<GraphRepositoryNode>(
+                &serde_json::json!({ "data": { "repository": repository } }).to_string(),
+            )
+            .unwrap();
+            response.data.repository.unwrap().stats()
+        };
+
+        let stats = parse(Some(serde_json::json!({"totalCount": 4})));
+        assert_eq!(stats.open_bugs, Some(4));
+        assert_eq!(stats.issues, 30);
+        // Repositories without a `bug` label have no open bugs
+        let stats = parse(Some(serde_json::json!({"totalCount": 0})));
+        assert_eq!(stats.open_bugs, Some(0));
+        // The count isn't requested unless it's enabled
+        assert_eq!(parse(None).open_bugs, None);
+    }
+
+    #[test]
+    fn test_parse_topics() {
+        let parse = |topics: Option<serde_json::Value>| -> RepositoryStats {
+            let mut repository = serde_json::json!({
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "rust-lang/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 30},
+                "licenseInfo": null,
+            });
+            if let Some(topics) = topics {
+                repository["repositoryTopics"] = topics;
+            }
+            let response = parse_graphql_response::<GraphRepositoryNode>(
+                &serde_json::json!({ "data": { "repository": repository } }).to_string(),
+            )
+            .unwrap();
+            response.data.repository.unwrap().stats()
+        };
+
+        let stats = parse(Some(serde_json::json!({
+            "nodes": [{"topic": {"name": "rust"}}, {"topic": {"name": "documentation"}}],
+        })));
+        assert_eq!(
+            stats.topics,
+            Some(vec!["rust".to_string(), "documentation".to_string()])
+        );
+        assert_eq!(
+            parse(Some(serde_json::json!({"nodes": []}))).topics,
+            Some(Vec::new())
+        );
+        // Older cached responses leave them out
+        assert_eq!(parse(None).topics, None);
+    }
+
+    #[test]
+    fn test_parse_fork() {
+        let parse = |fields: serde_json::Value| -> RepositoryStats {
+            let mut repository = serde_json::json!({
+                "id": "MDEwOlJlcG9zaXRvcnkx",
+                "nameWithOwner": "someone/docs.rs",
+                "pushedAt": null,
+                "description": null,
+                "stargazerCount": 1,
+                "forkCount": 2,
+                "issues": {"totalCount": 3},
+                "licenseInfo": null,
+            });
+            for (key, value) in fields.as_object().unwrap() {
+                repository[key] = value.clone();
+            }
+            serde_json::from_value::<GraphRepository>(repository)
+                .unwrap()
+                .stats()
+        };
+
+        let fork = parse(serde_json::json!({
+            "isFork": true,
+            "parent": {"nameWithOwner": "rust-lang/docs.rs"},
+        }));
+        assert!(fork.is_fork);
+        assert_eq!(fork.fork_parent.as_deref(), Some("rust-lang/docs.rs"));
+
+        let repository = parse(serde_json::json!({"isFork": false, "parent": null}));
+        assert!(!repository.is_fork);
+        assert_eq!(repository.fork_parent, None);
+
+        // Forks of private or deleted repositories have no parent
+        let orphan = parse(serde_json::json!({"isFork": true, "parent": null}));
+        assert!(orphan.is_fork);
+        assert_eq!(orphan.fork_parent, None);
+    }
+
+    #[test]
+    fn test_parse_archived() {
+        let parse = |repository: serde_json::Value| -> RepositoryStats {
+            serde_json::from_value::<GraphRepository>(repository)
+                .unwrap()
+                .stats()
+        };
+        let mut repository = serde_json::json!({
+            "id": "MDEwOlJlcG9zaXRvcnkx",
+            "nameWithOwner": "someone/archived",
+            "pushedAt": null,
+            "description": null,
+            "stargazerCount": 1,
+            "forkCount": 2,
+            "issues": {"totalCount": 3},
+            "licenseInfo": null,
+        });
+        let stats = parse(repository.clone());
+        assert!(!stats.is_archived);
+        assert_eq!(stats.closed_issues, None);
+
+        repository["isArchived"] = true.into();
+        repository["closedIssues"] = serde_json::json!({"totalCount": 12});
+        let stats = parse(repository);
+        assert!(stats.is_archived);
+        assert_eq!(stats.closed_issues, Some(12));
+    }
+
+    #[test]
+    fn test_error_body_with_success_status() {
+        let err = parse_graphql_response::<GraphRepositoryNode>(r#"{"message":"Not Found"}"#)
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<GithubErrorBody>()
+            .expect("wrong error type");
+        assert_eq!(err.message, "Not Found");
+
+        let response = parse_graphql_response::<GraphRepositoryNode>(
+            r#"{"data":{"repository":null},"message":"ignored"}"#,
+        )
+        .unwrap();
+        assert!(response.data.repository.is_none());
+    }
+
+    #[test]
+    fn test_pushed_before_created() {
+        let parse = |created_at: &str, pushed_at: &str| -> GraphRepository {
+            serde_json::from_value(serde_json::json!({
+                "id": "mirror",
+                "nameWithOwner": "owner/mirror",
+                "createdAt": created_at,
+                "pushedAt": pushed_at,
+                "description": null,
+                "stargazerCount": 0,
+                "forkCount": 0,
+                "issues": {"totalCount": 0},
+                "licenseInfo": null,
+            }))
+            .unwrap()
+        };
+        let time = |time: &str| Some(time.parse::<DateTime<Utc>>().unwrap());
+
+        // Imported repositories keep the time of the last push before the import
+        let imported = parse("2020-06-01T00:00:00Z", "2015-03-12T10:00:00Z");
+        assert_eq!(imported.stats().last_commit, time("2020-06-01T00:00:00Z"));
+
+        let repository = parse("2015-03-12T10:00:00Z", "2020-06-01T00:00:00Z");
+        assert_eq!(repository.stats().last_commit, time("2020-06-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_parse_pushed_and_updated_at() {
+        let repository: GraphRepository = serde_json::from_value(serde_json::json!({
+            "id": "starred",
+            "nameWithOwner": "owner/starred",
+            "createdAt": "2015-03-12T10:00:00Z",
+            "pushedAt": "2020-06-01T00:00:00Z",
+            "updatedAt": "2020-11-20T15:30:00Z",
+            "description": null,
+            "stargazerCount": 0,
+            "forkCount": 0,
+            "issues": {"totalCount": 0},
+            "licenseInfo": null,
+        }))
+        .unwrap();
+        let time = |time: &str| Some(time.parse::<DateTime<Utc>>().unwrap());
+
+        // Starring the repository updates it without any push
+        let stats = repository.stats();
+        assert_eq!(stats.last_commit, time("2020-06-01T00:00:00Z"));
+        assert_eq!(stats.updated_at, time("2020-11-20T15:30:00Z"));
+    }
+
+    /// Queries a fake GraphQL API, returning whether the updater asked for a gzip compressed
+    /// response.
+    fn query_mock_api(env: &TestEnvironment, gzip: bool) -> Result<bool> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        env.override_config(|config| {
+            config.github_accesstoken = Some("not-a-real-token".into());
+            config.github_updater_gzip = gzip;
+        });
+        let mut updater =
+            GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+        let asked_for_gzip = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let asked_for_gzip = asked_for_gzip.clone();
+            move |req: &mut iron::Request| {
+                let body = r#"{"data": {"nodes": [], "rateLimit": {"remaining": 5000}}}"#;
+                let gzip = req
+                    .headers
+                    .get_raw("Accept-Encoding")
+                    .into_iter()
+                    .flatten()
+                    .any(|value| String::from_utf8_lossy(value).contains("gzip"));
+                asked_for_gzip.store(gzip, Ordering::SeqCst);
+
+                if gzip {
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(body.as_bytes()).unwrap();
+                    let mut response =
+                        iron::Response::with((iron::status::Ok, encoder.finish().unwrap()));
+                    response
+                        .headers
+                        .set_raw("Content-Encoding", vec![b"gzip".to_vec()]);
+                    Ok(response)
+                } else {
+                    Ok(iron::Response::with((iron::status::Ok, body)))
+                }
+            }
+        };
+        let server = TestServer::start(handler)?;
+        updater.graphql_url = format!("{}/graphql", server.url());
+
+        let response: GraphResponse<GraphNodes<Option<GraphRepository>>> =
+            updater.graphql(GRAPHQL_UPDATE, serde_json::json!({ "ids": [] }))?;
+        assert!(response.data.nodes.is_empty());
+        assert_eq!(response.data.rate_limit.remaining, 5000);
+
+        Ok(asked_for_gzip.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_gzip_responses() {
+        wrapper(|env| {
+            assert!(query_mock_api(env, true)?);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_gzip_disabled() {
+        wrapper(|env| {
+            assert!(!query_mock_api(env, false)?);
+            Ok(())
+        });
+    }
+
+    /// Queries a fake GraphQL API through `github_api_base`, returning whether the updater sent
+    /// its access token.
+    fn query_mock_proxy(env: &TestEnvironment, no_auth: bool) -> Result<bool> {
+        let sent_token = Arc::new(AtomicBool::new(false));
+        let handler = {
+            let sent_token = sent_token.clone();
+            move |req: &mut iron::Request| {
+                assert_eq!(req.url.path(), vec!["api", "graphql"]);
+                sent_token.store(
+                    req.headers.get_raw("Authorization").is_some(),
+                    Ordering::SeqCst,
+                );
+                let body = r#"{"data": {"nodes": [], "rateLimit": {"remaining": 5000}}}"#;
+                Ok(iron::Response::with((iron::status::Ok, body)))
+            }
+        };
+        let server = TestServer::start(handler)?;
+        let base = format!("{}/api/", server.url());
+
+        env.override_config(|config| {
+            config.github_accesstoken = Some("not-a-real-token".into());
+            config.github_api_base = base;
+            config.github_api_no_auth = no_auth;
+        });
+        let updater =
+            GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+        let response: GraphResponse<GraphNodes<Option<GraphRepository>>> =
+            updater.graphql(GRAPHQL_UPDATE, serde_json::json!({ "ids": [] }))?;
+        assert!(response.data.nodes.is_empty());
+
+        Ok(sent_token.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_api_base_with_auth() {
+        wrapper(|env| {
+            assert!(query_mock_proxy(env, false)?);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_api_base_without_auth() {
+        wrapper(|env| {
+            assert!(!query_mock_proxy(env, true)?);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_no_auth_without_token() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_accesstoken = None;
+                config.github_api_no_auth = true;
+            });
+            assert!(GithubUpdater::new(env.config(), env.db().pool())?.is_some());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_repository_moved_to_gitlab() {
+        wrapper(|env| {
+            // Fake GitHub API not finding any repository, and fake GitLab API with the repository
+            // of `moved`, which gains a star every time it's fetched
+            let stars = Arc::new(Mutex::new(20));
+            let handler = {
+                let stars = stars.clone();
+                move |req: &mut iron::Request| {
+                    let path = req.url.path().join("/");
+                    let body = if path == "github/graphql" {
+                        let mut body = String::new();
+                        req.body.read_to_string(&mut body).unwrap();
+                        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                        let ids = body["variables"]["ids"].as_array().unwrap();
+                        serde_json::json!({
+                            "data": {
+                                "nodes": ids.iter().map(|_| None::<()>).collect::<Vec<_>>(),
+                                "rateLimit": {"remaining": 5000},
+                            },
+                            "errors": (0..ids.len()).map(|idx| serde_json::json!({
+                                "type": "NOT_FOUND",
+                                "path": ["nodes", idx],
+                                "message": "not found",
+                            })).collect::<Vec<_>>(),
+                        })
+                    } else if path == "gitlab/projects/new%2Fmoved" || path == "gitlab/projects/42"
+                    {
+                        let mut stars = stars.lock().unwrap();
+                        *stars += 1;
+                        serde_json::json!({
+                            "id": 42,
+                            "path_with_namespace": "new/moved",
+                            "description": "",
+                            "star_count": *stars,
+                            "forks_count": 4,
+                            "open_issues_count": 5,
+                            "last_activity_at": "2020-06-01T00:00:00Z",
+                        })
+                    } else {
+                        return Ok(iron::Response::with((iron::status::NotFound, path)));
+                    };
+                    Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+                }
+            };
+            let server = TestServer::start(handler)?;
+            let base = server.url();
+
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_api_base = format!("{}/github", base);
+                config.gitlab_api_base = format!("{}/gitlab", base);
+                // The test keeps a connection while the updater runs
+                config.max_pool_size = 4;
+            });
+            let updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+
+            let days_ago = |days| Utc::now() - chrono::Duration::days(days);
+            let old = env
+                .fake_release()
+                .name("moved")
+                .version("0.1.0")
+                .release_time(days_ago(3))
+                .repo("https://github.com/old/moved")
+                .github_stats("old/moved", 10, 2, 3)
+                .create()?;
+            env.fake_release()
+                .name("moved")
+                .version("0.2.0")
+                .release_time(days_ago(2))
+                .repo("https://gitlab.com/new/moved")
+                .create()?;
+            // The newest release of `stayed` is back on GitHub, so it didn't move
+            let stayed = env
+                .fake_release()
+                .name("stayed")
+                .version("0.1.0")
+                .release_time(days_ago(3))
+                .repo("https://gitlab.com/new/stayed")
+                .github_stats("old/stayed", 10, 2, 3)
+                .create()?;
+            env.fake_release()
+                .name("stayed")
+                .version("0.2.0")
+                .release_time(days_ago(2))
+                .repo("https://github.com/old/stayed")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let outdate = |conn: &mut Client| {
+                conn.execute(
+                    "UPDATE github_repos SET github_last_attempt = NOW() - INTERVAL '2 days';",
+                    &[],
+                )
+            };
+            let repo_of = |conn: &mut Client, release_id: i32| -> Result<Option<(String, i32)>> {
+                Ok(conn
+                    .query_opt(
+                        "SELECT github_repos.id, github_repos.stars
+                         FROM releases
+                         INNER JOIN github_repos ON github_repos.id = releases.github_repo
+                         WHERE releases.id = $1;",
+                        &[&release_id],
+                    )?
+                    .map(|row| (row.get(0), row.get(1))))
+            };
+
+            outdate(&mut conn)?;
+            let stats = updater.update_all_crates()?;
+            assert_eq!(stats.updated, 1);
+            assert_eq!(stats.not_found, 1);
+            assert_eq!(repo_of(&mut conn, old)?, Some(("gitlab:42".into(), 21)));
+            assert_eq!(repo_of(&mut conn, stayed)?, None);
+            let repos: i64 = conn
+                .query_one("SELECT COUNT(*) FROM github_repos;", &[])?
+                .get(0);
+            assert_eq!(repos, 1);
+
+            // The moved repository is then updated from GitLab
+            outdate(&mut conn)?;
+            let stats = updater.update_all_crates()?;
+            assert_eq!(stats.updated, 1);
+            assert_eq!(stats.not_found, 0);
+            assert_eq!(repo_of(&mut conn, old)?, Some(("gitlab:42".into(), 22)));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_registry_fallback() {
+        wrapper(|env| {
+            // Fake registry API listing the GitHub repository of `listed`, and fake GitHub API
+            // with that repository
+            let handler = |req: &mut iron::Request| {
+                let path = req.url.path().join("/");
+                let body = match path.as_str() {
+                    "registry/api/v1/crates/listed" => serde_json::json!({
+                        "crate": {"name": "listed", "repository": "https://github.com/owner/listed"},
+                    }),
+                    "registry/api/v1/crates/unlisted" => serde_json::json!({
+                        "crate": {"name": "unlisted", "repository": null},
+                    }),
+                    "github/graphql" => serde_json::json!({
+                        "data": {
+                            "repository": {
+                                "id": "listed-id",
+                                "nameWithOwner": "owner/listed",
+                                "pushedAt": null,
+                                "description": "listed on the registry",
+                                "stargazerCount": 10,
+                                "forkCount": 2,
+                                "issues": {"totalCount": 3},
+                                "licenseInfo": null,
+                            },
+                        },
+                    }),
+                    _ => return Ok(iron::Response::with((iron::status::NotFound, path))),
+                };
+                Ok(iron::Response::with((iron::status::Ok, body.to_string())))
+            };
+            let server = TestServer::start(handler)?;
+            let base = server.url();
+
+            env.override_config(|config| {
+                config.github_accesstoken = Some("not-a-real-token".into());
+                config.github_api_base = format!("{}/github", base);
+                // The test keeps a connection while the updater runs
+                config.max_pool_size = 4;
+            });
+            let api = Api::new(Some(format!("{}/registry", base).parse()?))?;
+            let mut updater =
+                GithubUpdater::new(env.config(), env.db().pool())?.expect("missing GitHub updater");
+            updater.registry_api = Some(api);
+            updater.registry_delay = Duration::from_millis(0);
+            // The two crates are looked up in two pages
+            updater.registry_page_size = 1;
+
+            let listed = env
+                .fake_release()
+                .name("listed")
+                .version("0.1.0")
+                .create()?;
+            let unlisted = env
+                .fake_release()
+                .name("unlisted")
+                .version("0.1.0")
+                .create()?;
+            let mut conn = env.db().conn();
+            conn.execute("UPDATE releases SET repository_url = NULL;", &[])?;
+            let repo_of =
+                |conn: &mut Client, release_id: i32| -> Result<(Option<String>, Option<i32>)> {
+                    let row = conn.query_one(
+                        "SELECT releases.repository_url, github_repos.stars
+                     FROM releases
+                     LEFT JOIN github_repos ON github_repos.id = releases.github_repo
+                     WHERE releases.id = $1;",
+                        &[&release_id],
+                    )?;
+                    Ok((row.get(0), row.get(1)))
+                };
+
+            updater.update_all_crates()?;
+            assert_eq!(
+                repo_of(&mut conn, listed)?,
+                (Some("https://github.com/owner/listed".into()), Some(10))
+            );
+            assert_eq!(repo_of(&mut conn, unlisted)?, (None, None));
+            let checked: i64 = conn
+                .query_one(
+                    "SELECT COUNT(*) FROM crates WHERE registry_repository_checked_at IS NOT NULL;",
+                    &[],
+                )?
+                .get(0);
+            assert_eq!(checked, 2);
+
+            // Without the fallback the registry isn't asked
+            conn.execute(
+                "UPDATE crates SET registry_repository_checked_at = NULL;",
+                &[],
+            )?;
+            GithubUpdater::new(env.config(), env.db().pool())?
+                .unwrap()
+                .update_all_crates()?;
+            let checked: i64 = conn
+                .query_one(
+                    "SELECT COUNT(*) FROM crates WHERE registry_repository_checked_at IS NOT NULL;",
+                    &[],
+                )?
+                .get(0);
+            assert_eq!(checked, 0);
+
+            Ok(())
+        });
     }
 }
@@ -0,0 +1,328 @@
+//! A score summarizing the health of each crate, from signals about its documentation, its tests
+//! and its repository
+
+use crate::error::Result;
+use chrono::{NaiveDateTime, Utc};
+use postgres::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const DOCUMENTATION_WEIGHT: f64 = 0.3;
+const TESTS_WEIGHT: f64 = 0.2;
+const LAST_COMMIT_WEIGHT: f64 = 0.2;
+const CI_WEIGHT: f64 = 0.1;
+const NOT_ARCHIVED_WEIGHT: f64 = 0.1;
+const CLOSED_ISSUES_WEIGHT: f64 = 0.1;
+
+/// Repositories committed to in the last `FRESH_COMMIT_DAYS` get the full score for their last
+/// commit, which then decreases linearly to 0 at `STALE_COMMIT_DAYS`.
+const FRESH_COMMIT_DAYS: f64 = 180.0;
+const STALE_COMMIT_DAYS: f64 = 730.0;
+
+/// The files configuring continuous integration services, matched as prefixes of the paths of
+/// the source files.
+const CI_FILES: &[&str] = &[
+    ".github/workflows/",
+    ".gitlab-ci.yml",
+    ".travis.yml",
+    ".circleci/",
+    ".cirrus.yml",
+    "appveyor.yml",
+    ".appveyor.yml",
+    "azure-pipelines.yml",
+];
+
+/// The health score of a crate, stored in `crates.health_score` and
+/// `crates.health_score_breakdown`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthScore {
+    /// The weighted sum of the signals, from 0 to 1
+    pub score: f64,
+    pub breakdown: HealthBreakdown,
+}
+
+/// The signals of the health score, each from 0 to 1. The signals about the repository are 0 for
+/// the crates without a known repository.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HealthBreakdown {
+    /// The share of the items of the latest release that are documented
+    pub documentation: f64,
+    /// Whether the latest release has tests, inline or in `tests/`
+    pub tests: f64,
+    /// How recently the repository was committed to
+    pub last_commit: f64,
+    /// Whether the sources of the latest release configure continuous integration
+    pub ci: f64,
+    /// Whether the repository is still maintained rather than archived
+    pub not_archived: f64,
+    /// The share of the issues of the repository that are closed, 1 without any issue
+    pub closed_issues: f64,
+}
+
+impl HealthBreakdown {
+    fn score(&self) -> f64 {
+        self.documentation * DOCUMENTATION_WEIGHT
+            + self.tests * TESTS_WEIGHT
+            + self.last_commit * LAST_COMMIT_WEIGHT
+            + self.ci * CI_WEIGHT
+            + self.not_archived * NOT_ARCHIVED_WEIGHT
+            + self.closed_issues * CLOSED_ISSUES_WEIGHT
+    }
+}
+
+/// Computes the health score of a crate from its latest release and its repository.
+pub(crate) fn compute_health_score(conn: &mut Client, crate_id: i32) -> Result<HealthScore> {
+    let row = conn
+        .query_opt(
+            "SELECT
+                releases.files,
+                COALESCE(releases.inline_test_count, 0) AS inline_tests,
+                doc_coverage.total_items,
+                doc_coverage.documented_items,
+                github_repos.id AS repository,
+                github_repos.last_commit,
+                github_repos.is_archived,
+                github_repos.issues,
+                github_repos.closed_issues
+             FROM crates
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
+             LEFT JOIN github_repos ON github_repos.id = releases.github_repo
+             WHERE crates.id = $1;",
+            &[&crate_id],
+        )?
+        .ok_or_else(|| failure::format_err!("crate {} has no release", crate_id))?;
+
+    let files = source_files(row.get("files"));
+    let mut breakdown = HealthBreakdown {
+        documentation: match (
+            row.get::<_, Option<i32>>("total_items"),
+            row.get::<_, Option<i32>>("documented_items"),
+        ) {
+            (Some(total), Some(documented)) if total > 0 => {
+                (documented as f64 / total as f64).min(1.0)
+            }
+            _ => 0.0,
+        },
+        tests: score_if(
+            row.get::<_, i32>("inline_tests") > 0
+                || files.iter().any(|path| path.starts_with("tests/")),
+        ),
+        ci: score_if(
+            files
+                .iter()
+                .any(|path| CI_FILES.iter().any(|ci| path.starts_with(ci))),
+        ),
+        ..HealthBreakdown::default()
+    };
+
+    if row.get::<_, Option<String>>("repository").is_some() {
+        breakdown.last_commit =
+            row.get::<_, Option<NaiveDateTime>>("last_commit")
+                .map_or(0.0, |last_commit| {
+                    let days = (Utc::now().naive_utc() - last_commit).num_days() as f64;
+                    ((STALE_COMMIT_DAYS - days) / (STALE_COMMIT_DAYS - FRESH_COMMIT_DAYS))
+                        .clamp(0.0, 1.0)
+                });
+        breakdown.not_archived = score_if(!row.get::<_, bool>("is_archived"));
+        let open = row.get::<_, i32>("issues").max(0) as f64;
+        let closed = row
+            .get::<_, Option<i32>>("closed_issues")
+            .unwrap_or(0)
+            .max(0) as f64;
+        breakdown.closed_issues = if open + closed > 0.0 {
+            closed / (open + closed)
+        } else {
+            1.0
+        };
+    }
+
+    Ok(HealthScore {
+        score: breakdown.score(),
+        breakdown,
+    })
+}
+
+/// Computes and stores the health score of every crate. Returns the number of crates scored.
+pub fn update_health_scores(conn: &mut Client) -> Result<usize> {
+    let crates: Vec<i32> = conn
+        .query(
+            "SELECT crates.id
+             FROM crates
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             ORDER BY crates.id;",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    for crate_id in &crates {
+        let health = compute_health_score(conn, *crate_id)?;
+        conn.execute(
+            "UPDATE crates SET health_score = $2, health_score_breakdown = $3 WHERE id = $1;",
+            &[
+                crate_id,
+                &health.score,
+                &serde_json::to_value(&health.breakdown)?,
+            ],
+        )?;
+    }
+
+    Ok(crates.len())
+}
+
+fn score_if(condition: bool) -> f64 {
+    if condition {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Returns the paths of the source files listed in `releases.files`, as `[mime, path]` pairs.
+fn source_files(files: Option<Value>) -> Vec<String> {
+    match files {
+        Some(Value::Array(files)) => files
+            .iter()
+            .filter_map(|file| file.get(1)?.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn crate_id(conn: &mut Client, name: &str) -> Result<i32> {
+        Ok(conn
+            .query_one("SELECT id FROM crates WHERE name = $1", &[&name])?
+            .get(0))
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn healthy_crate() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("healthy")
+                .version("1.0.0")
+                .coverage(8, 10, 0, 0)
+                .source_file("tests/it.rs", b"#[test] fn it() {}")
+                .source_file(".github/workflows/ci.yml", b"on: push")
+                .github_stats("owner/healthy", 10, 1, 1)
+                .create()?;
+            let mut conn = env.db().conn();
+            conn.execute(
+                "UPDATE github_repos SET closed_issues = 3 WHERE name = 'owner/healthy'",
+                &[],
+            )?;
+
+            let id = crate_id(&mut conn, "healthy")?;
+            let health = compute_health_score(&mut conn, id)?;
+            assert_eq!(
+                health.breakdown,
+                HealthBreakdown {
+                    documentation: 0.8,
+                    tests: 1.0,
+                    last_commit: 1.0,
+                    ci: 1.0,
+                    not_archived: 1.0,
+                    closed_issues: 0.75,
+                }
+            );
+            assert_close(health.score, 0.8 * 0.3 + 0.2 + 0.2 + 0.1 + 0.1 + 0.075);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn unmaintained_crate() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("abandoned")
+                .version("1.0.0")
+                .github_stats("owner/abandoned", 10, 1, 0)
+                .create()?;
+            let mut conn = env.db().conn();
+            // Last committed to halfway between the fresh and stale ages
+            conn.execute(
+                "UPDATE github_repos
+                 SET is_archived = TRUE, last_commit = NOW() - INTERVAL '455 days'",
+                &[],
+            )?;
+            let id = crate_id(&mut conn, "abandoned")?;
+            let health = compute_health_score(&mut conn, id)?;
+            assert_eq!(health.breakdown.not_archived, 0.0);
+            assert_close(health.breakdown.last_commit, 0.5);
+            // Repositories without issues have none left open
+            assert_eq!(health.breakdown.closed_issues, 1.0);
+            assert_eq!(health.breakdown.documentation, 0.0);
+            assert_eq!(health.breakdown.tests, 0.0);
+            assert_eq!(health.breakdown.ci, 0.0);
+            assert_close(health.score, 0.5 * 0.2 + 0.1);
+
+            // Crates without a repository only score on their release
+            env.fake_release()
+                .name("no-repo")
+                .version("1.0.0")
+                .coverage(10, 10, 0, 0)
+                .create()?;
+            let id = crate_id(&mut conn, "no-repo")?;
+            let health = compute_health_score(&mut conn, id)?;
+            assert_close(health.score, 0.3);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn store_health_scores() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("documented")
+                .version("1.0.0")
+                .coverage(5, 10, 0, 0)
+                .create()?;
+            // Only the latest release counts
+            env.fake_release()
+                .name("regressed")
+                .version("1.0.0")
+                .coverage(10, 10, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("regressed")
+                .version("2.0.0")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            assert_eq!(update_health_scores(&mut conn)?, 2);
+            let scores: Vec<(String, f64, Value)> = conn
+                .query(
+                    "SELECT name, health_score, health_score_breakdown FROM crates ORDER BY name",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2)))
+                .collect();
+            assert_eq!(scores[0].0, "documented");
+            assert_close(scores[0].1, 0.15);
+            assert_eq!(scores[0].2["documentation"], 0.5);
+            assert_eq!(scores[1].0, "regressed");
+            assert_close(scores[1].1, 0.0);
+
+            Ok(())
+        });
+    }
+}
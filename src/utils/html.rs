@@ -2,30 +2,74 @@ use crate::web::page::TemplateData;
 use lol_html::errors::RewritingError;
 use tera::Context;
 
+/// A script or stylesheet included in the pages, with the Subresource Integrity hash the
+/// browsers check it against, for the ones loaded from other origins.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Subresource<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) integrity: Option<&'a str>,
+}
+
+impl Subresource<'_> {
+    /// The attributes checking the integrity of the resource, if it has a hash.
+    pub(super) fn integrity_attributes(&self) -> String {
+        match self.integrity {
+            Some(integrity) => format!(r#" integrity="{}" crossorigin="anonymous""#, integrity),
+            None => String::new(),
+        }
+    }
+
+    pub(crate) fn script_tag(&self) -> String {
+        format!(
+            r#"<script type="text/javascript" src="{}"{}></script>"#,
+            self.url,
+            self.integrity_attributes()
+        )
+    }
+}
+
 /// Rewrite a rustdoc page to have the docs.rs topbar
 ///
 /// Given a rustdoc HTML page and a context to serialize it with,
 /// render the `rustdoc/` templates with the `html`.
 /// The output is an HTML page which has not yet been UTF-8 validated.
 /// In practice, the output should always be valid UTF-8.
+///
+/// With a `mermaid_script`, the `mermaid` code blocks are turned into `<div class="mermaid">`
+/// elements, rendered as diagrams by that Mermaid.js script. The script is only included in the
+/// pages with diagrams.
+///
+/// With `math_assets`, like the ones of [`katex_assets`](super::katex_assets), the math of the
+/// documentation blocks is wrapped in `<span>`s to be rendered by the assets added to the head.
+/// The code and the diagrams are left as they are.
+///
+/// The test functions of the module documented by the page, if any, are appended to its main
+/// section.
 pub(crate) fn rewrite_lol(
     html: &[u8],
     max_allowed_memory_usage: usize,
     ctx: Context,
     templates: &TemplateData,
+    mermaid_script: Option<Subresource<'_>>,
+    math_assets: Option<&str>,
 ) -> Result<Vec<u8>, RewritingError> {
-    use lol_html::html_content::{ContentType, Element};
+    use lol_html::html_content::{ContentType, Element, TextChunk};
     use lol_html::{ElementContentHandlers, HtmlRewriter, MemorySettings, Settings};
+    use std::cell::{Cell, RefCell};
 
     let templates = templates.templates.load();
     let tera_head = templates.render("rustdoc/head.html", &ctx).unwrap();
     let tera_vendored_css = templates.render("rustdoc/vendored.html", &ctx).unwrap();
     let tera_body = templates.render("rustdoc/body.html", &ctx).unwrap();
     let tera_rustdoc_topbar = templates.render("rustdoc/topbar.html", &ctx).unwrap();
+    let tera_inline_tests = templates.render("rustdoc/inline_tests.html", &ctx).unwrap();
 
     // Append `style.css` stylesheet after all head elements.
     let head_handler = |head: &mut Element| {
         head.append(&tera_head, ContentType::Html);
+        if let Some(assets) = math_assets {
+            head.append(assets, ContentType::Html);
+        }
 
         Ok(())
     };
@@ -72,12 +116,84 @@ pub(crate) fn rewrite_lol(
         Ok(())
     };
 
+    // Older versions of rustdoc use `section#main`, newer ones `section#main-content`
+    let main_handler = |main: &mut Element| {
+        main.append(&tera_inline_tests, ContentType::Html);
+
+        Ok(())
+    };
+
+    // Before: <pre class="language-mermaid"><code>graph TD; A-->B;</code></pre>
+    // After: <div class="mermaid">graph TD; A-->B;</div>
+    // The script is added after the first diagram, and renders all of them once the page loads.
+    let mermaid_included = Cell::new(false);
+    let mermaid_handler = |block: &mut Element| {
+        block.set_tag_name("div")?;
+        block.set_attribute("class", "mermaid")?;
+        if let Some(script) = mermaid_script {
+            if !mermaid_included.replace(true) {
+                block.after(
+                    &format!(
+                        r#"{}<script type="text/javascript">mermaid.initialize({{ startOnLoad: true }});</script>"#,
+                        script.script_tag()
+                    ),
+                    ContentType::Html,
+                );
+            }
+        }
+
+        Ok(())
+    };
+    // Mermaid.js reads the source of the diagram from the content of the element
+    let mermaid_code_handler = |code: &mut Element| {
+        code.remove_and_keep_content();
+
+        Ok(())
+    };
+
+    // The text of the skipped elements is handled by `skipped_text_handler` first, which tells
+    // `math_handler` to leave it as it is. The text can be split in several chunks, which are
+    // buffered until the last one, as the math can span them.
+    let skipped_text = Cell::new(false);
+    let skipped_text_handler = |_: &mut TextChunk| {
+        skipped_text.set(true);
+
+        Ok(())
+    };
+    let pending_text = RefCell::new(String::new());
+    let math_handler = |text: &mut TextChunk| {
+        if skipped_text.replace(false) {
+            return Ok(());
+        }
+        pending_text.borrow_mut().push_str(text.as_str());
+        if !text.last_in_text_node() {
+            text.remove();
+            return Ok(());
+        }
+        let content = pending_text.replace(String::new());
+        match super::math::mark_math(content.as_bytes()) {
+            Some(marked) => text.replace(&String::from_utf8(marked)?, ContentType::Html),
+            // The text is replaced all the same, as its first chunks were removed
+            None => text.replace(&content, ContentType::Html),
+        }
+
+        Ok(())
+    };
+
     let (head_selector, body_selector, first_stylesheet_selector) = (
         "head".parse().unwrap(),
         "body".parse().unwrap(),
         "link[type='text/css'][href*='rustdoc']".parse().unwrap(),
     );
-    let element_content_handlers = vec![
+    let (main_selector, main_content_selector) = (
+        "section#main".parse().unwrap(),
+        "section#main-content".parse().unwrap(),
+    );
+    let (mermaid_selector, mermaid_code_selector) = (
+        "pre.language-mermaid".parse().unwrap(),
+        "pre.language-mermaid > code".parse().unwrap(),
+    );
+    let mut element_content_handlers = vec![
         (
             &head_selector,
             ElementContentHandlers::default().element(head_handler),
@@ -90,7 +206,45 @@ pub(crate) fn rewrite_lol(
             &first_stylesheet_selector,
             ElementContentHandlers::default().element(first_stylesheet_handler),
         ),
+        (
+            &main_selector,
+            ElementContentHandlers::default().element(main_handler),
+        ),
+        (
+            &main_content_selector,
+            ElementContentHandlers::default().element(main_handler),
+        ),
     ];
+    let skipped_selectors: Vec<_> = super::math::SKIPPED_ELEMENTS
+        .iter()
+        .map(|element| format!(".docblock {}", element).parse().unwrap())
+        .collect();
+    let docblock_selector = ".docblock".parse().unwrap();
+    if math_assets.is_some() {
+        // The skipped elements come first, so that their handler is called first
+        for selector in &skipped_selectors {
+            element_content_handlers.push((
+                selector,
+                ElementContentHandlers::default().text(skipped_text_handler),
+            ));
+        }
+        element_content_handlers.push((
+            &docblock_selector,
+            ElementContentHandlers::default().text(math_handler),
+        ));
+    }
+    if mermaid_script.is_some() {
+        element_content_handlers.extend(vec![
+            (
+                &mermaid_selector,
+                ElementContentHandlers::default().element(mermaid_handler),
+            ),
+            (
+                &mermaid_code_selector,
+                ElementContentHandlers::default().element(mermaid_code_handler),
+            ),
+        ]);
+    }
     let settings = Settings {
         element_content_handlers,
         memory_settings: MemorySettings {
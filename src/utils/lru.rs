@@ -0,0 +1,90 @@
+//! A cache keeping the most recently used entries, for a limited time
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Keeps up to `capacity` entries for `ttl` each, dropping the least recently used entry when
+/// it's full.
+#[derive(Debug)]
+pub(crate) struct LruCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<K, (Instant, V)>,
+    /// The keys of the entries, from the least to the most recently used
+    order: VecDeque<K>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        LruCache {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns the entry if it hasn't expired, marking it as the most recently used.
+    pub(crate) fn get(&mut self, key: &K) -> Option<V> {
+        let expired = self.entries.get(key)?.0.elapsed() >= self.ttl;
+        self.forget(key);
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+        self.order.push_back(key.clone());
+        self.entries.get(key).map(|(_, value)| value.clone())
+    }
+
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        self.forget(&key);
+        self.order.push_back(key.clone());
+        self.entries.insert(key, (Instant::now(), value));
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Removes the key from the order of use.
+    fn forget(&mut self, key: &K) {
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            self.order.remove(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2, TTL);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        // Replacing an entry doesn't evict another one
+        cache.insert("c", 4);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(4));
+    }
+
+    #[test]
+    fn entries_expire() {
+        let mut cache = LruCache::new(2, Duration::from_secs(0));
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), None);
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+//! Rendering of the math in the documentation of the crates opting in with
+//! `[package.metadata.docs.rs] math = true`
+
+use super::Subresource;
+
+/// The elements of the documentation whose text is never math, like code using `$` for macro
+/// variables, or the source of Mermaid.js diagrams.
+pub(crate) const SKIPPED_ELEMENTS: &[&str] =
+    &["code", "pre", "script", "style", "textarea", ".mermaid"];
+
+/// Returns the KaTeX stylesheet and script, and the script rendering the
+/// `<span class="math-inline">` and `<span class="math-display">` elements of the page with
+/// them, to add to the head of the pages.
+pub(crate) fn katex_assets(script: Subresource<'_>, stylesheet: Subresource<'_>) -> String {
+    format!(
+        r#"<link rel="stylesheet" type="text/css" href="{}"{}><script defer src="{}"{}></script><script type="text/javascript">document.addEventListener("DOMContentLoaded", function () {{ document.querySelectorAll(".math-inline, .math-display").forEach(function (element) {{ katex.render(element.textContent, element, {{ displayMode: element.classList.contains("math-display"), throwOnError: false }}); }}); }});</script>"#,
+        stylesheet.url,
+        stylesheet.integrity_attributes(),
+        script.url,
+        script.integrity_attributes(),
+    )
+}
+
+/// Wraps the `$inline$` and `$$display$$` math of a text node in `<span>`s, or returns `None` if it
+/// has no math. The text is still escaped, as it is in the HTML.
+///
+/// Like in Pandoc, an inline formula can't start or end with a space, nor be directly followed by
+/// a digit, so that prices like `$5 and $10` aren't mistaken for math.
+pub(crate) fn mark_math(text: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut copied = 0;
+    let mut pos = 0;
+    while pos < text.len() {
+        match text[pos] {
+            // `\$` is a literal dollar sign
+            b'\\' => {
+                pos += 2;
+                continue;
+            }
+            b'$' => {}
+            _ => {
+                pos += 1;
+                continue;
+            }
+        }
+
+        let math = if text.get(pos + 1) == Some(&b'$') {
+            display_math_end(text, pos + 2).map(|end| (pos + 2, end, 2, "math-display"))
+        } else {
+            inline_math_end(text, pos + 1).map(|end| (pos + 1, end, 1, "math-inline"))
+        };
+        match math {
+            Some((start, end, delimiter_len, class)) => {
+                output.extend_from_slice(&text[copied..pos]);
+                output.extend_from_slice(format!(r#"<span class="{}">"#, class).as_bytes());
+                output.extend_from_slice(&text[start..end]);
+                output.extend_from_slice(b"</span>");
+                pos = end + delimiter_len;
+                copied = pos;
+            }
+            // Unbalanced `$$` are skipped together, so that the second one doesn't open inline
+            // math
+            None if text.get(pos + 1) == Some(&b'$') => pos += 2,
+            None => pos += 1,
+        }
+    }
+
+    if copied == 0 {
+        return None;
+    }
+    output.extend_from_slice(&text[copied..]);
+    Some(output)
+}
+
+/// Returns where the display math starting at `start` ends, at the closing `$$`.
+fn display_math_end(text: &[u8], start: usize) -> Option<usize> {
+    let end = find_dollar(text, start, |end| text.get(end + 1) == Some(&b'$'))?;
+    if text[start..end].iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        Some(end)
+    }
+}
+
+/// Returns where the inline math starting at `start` ends, at the closing `$`.
+fn inline_math_end(text: &[u8], start: usize) -> Option<usize> {
+    match text.get(start) {
+        Some(byte) if !byte.is_ascii_whitespace() && *byte != b'$' => {}
+        _ => return None,
+    }
+    find_dollar(text, start, |end| {
+        !text[end - 1].is_ascii_whitespace() && !text.get(end + 1).map_or(false, u8::is_ascii_digit)
+    })
+}
+
+/// Returns the position of the first unescaped `$` from `start` accepted by `is_end`.
+fn find_dollar(text: &[u8], start: usize, is_end: impl Fn(usize) -> bool) -> Option<usize> {
+    let mut pos = start;
+    while pos < text.len() {
+        match text[pos] {
+            b'\\' => pos += 2,
+            b'$' if is_end(pos) => return Some(pos),
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marked(text: &str) -> Option<String> {
+        mark_math(text.as_bytes()).map(|html| String::from_utf8(html).unwrap())
+    }
+
+    #[test]
+    fn inline_math() {
+        assert_eq!(
+            marked("Euler: $e^{i\\pi} + 1 = 0$.").as_deref(),
+            Some(r#"Euler: <span class="math-inline">e^{i\pi} + 1 = 0</span>."#)
+        );
+        assert_eq!(
+            marked("$a$ and $b$").as_deref(),
+            Some(r#"<span class="math-inline">a</span> and <span class="math-inline">b</span>"#)
+        );
+        // The text stays escaped
+        assert_eq!(
+            marked("$a &lt; b$").as_deref(),
+            Some(r#"<span class="math-inline">a &lt; b</span>"#)
+        );
+    }
+
+    #[test]
+    fn display_math() {
+        assert_eq!(
+            marked("$$\\sum_{i=0}^n i = \\frac{n(n+1)}{2}$$").as_deref(),
+            Some(r#"<span class="math-display">\sum_{i=0}^n i = \frac{n(n+1)}{2}</span>"#)
+        );
+        assert_eq!(
+            marked("where\n$$\nx^2\n$$\nholds").as_deref(),
+            Some("where\n<span class=\"math-display\">\nx^2\n</span>\nholds")
+        );
+        assert_eq!(
+            marked("$$x$$ then $y$").as_deref(),
+            Some(r#"<span class="math-display">x</span> then <span class="math-inline">y</span>"#)
+        );
+    }
+
+    #[test]
+    fn not_math() {
+        for text in &[
+            "no math here",
+            "costs $5 and $10",
+            "from $5 to $ 10",
+            "$ a$",
+            "$a $",
+            "an empty $$$$ block",
+            "$$ $$",
+            "a single $",
+            "an unclosed $$x",
+            "escaped \\$a\\$",
+        ] {
+            assert_eq!(marked(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn assets() {
+        let assets = katex_assets(
+            Subresource {
+                url: "https://example.com/katex/katex.min.js",
+                integrity: Some("sha384-js"),
+            },
+            Subresource {
+                url: "/-/static/katex.min.css",
+                integrity: None,
+            },
+        );
+        assert!(assets.starts_with(
+            r#"<link rel="stylesheet" type="text/css" href="/-/static/katex.min.css"><script defer src="https://example.com/katex/katex.min.js" integrity="sha384-js" crossorigin="anonymous"></script>"#
+        ));
+    }
+}
@@ -1,28 +1,53 @@
 //! Various utilities for docs.rs
 
 pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage};
+pub use self::cooccurrence_updater::update_crate_cooccurrence;
 pub(crate) use self::copy::copy_doc_dir;
 pub use self::daemon::start_daemon;
-pub use self::github_updater::GithubUpdater;
-pub(crate) use self::html::rewrite_lol;
+pub(crate) use self::github_updater::queue_repository_refresh;
+pub use self::github_updater::{
+    CrateUpdate, GithubUpdateError, GithubUpdater, RepositoryStats, UpdateStats,
+};
+pub use self::health_score::update_health_scores;
+pub(crate) use self::html::{rewrite_lol, Subresource};
+pub(crate) use self::math::katex_assets;
+pub use self::owners_updater::update_crate_owners;
 pub use self::queue::{get_crate_priority, remove_crate_priority, set_crate_priority};
+pub(crate) use self::queue::{ADMIN_PRIORITIES, REBUILD_PRIORITY};
 pub use self::queue_builder::queue_builder;
 pub use self::release_activity_updater::update_release_activity;
 pub(crate) use self::rustc_version::parse_rustc_version;
+pub(crate) use self::spdx::{are_compatible, is_restrictive, license_alternatives};
 
 #[cfg(test)]
 pub(crate) use self::cargo_metadata::{Dependency, Target};
+#[cfg(feature = "consistency_check")]
+pub(crate) use self::queue::get_crate_priorities;
 
+pub(crate) mod candidate_priority;
 mod cargo_metadata;
 #[cfg(feature = "consistency_check")]
 pub mod consistency;
+mod cooccurrence_updater;
 mod copy;
 mod daemon;
+pub(crate) mod github_fields;
 mod github_updater;
+mod health_score;
 mod html;
+pub(crate) mod lru;
+mod math;
+pub mod output;
+mod owners_updater;
 mod pubsubhubbub;
 mod queue;
 mod queue_builder;
 mod release_activity_updater;
+mod repository_hosts;
+pub(crate) mod retry_policy;
 mod rustc_version;
+pub(crate) mod schedule;
 pub(crate) mod sized_buffer;
+mod spdx;
+pub(crate) mod systemd;
+pub(crate) mod zip_writer;
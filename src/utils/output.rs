@@ -0,0 +1,300 @@
+//! The data the CLI commands print with `--json`, in a shape that stays stable for scripts and
+//! that the web API can serve as is.
+//!
+//! The lists of builds in the queue are printed as the `PendingBuild`s and `FailedCrate`s of the
+//! build queue, the overview of the instance as its `InstanceOverview`, and the crates whose
+//! build limits are overridden as the `SandboxOverride`s of the docbuilder.
+
+use crate::db::{BuildStatsSummary, Percentiles};
+use crate::utils::CrateUpdate;
+use serde::Serialize;
+
+/// The statistics about recent builds printed by `cratesfyi stats builds`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildStatsOutput {
+    /// How many days of builds were summarized
+    pub days: i32,
+    pub builds: i64,
+    pub duration_ms: Percentiles,
+    pub peak_memory_bytes: Percentiles,
+    pub files_count: Percentiles,
+    pub files_size_bytes: Percentiles,
+}
+
+impl BuildStatsOutput {
+    pub fn new(days: i32, summary: &BuildStatsSummary) -> Self {
+        BuildStatsOutput {
+            days,
+            builds: summary.builds,
+            duration_ms: summary.duration_ms,
+            peak_memory_bytes: summary.peak_memory_bytes,
+            files_count: summary.files_count,
+            files_size_bytes: summary.files_size_bytes,
+        }
+    }
+}
+
+/// The crates on the blacklist, printed by `cratesfyi database blacklist list`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlacklistOutput {
+    pub crates: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrateUpdateStatus {
+    Updated,
+    NoRepository,
+    UnknownCrate,
+    Failed,
+}
+
+/// The update of the repository of a crate, printed by
+/// `cratesfyi database update-github-fields --crate`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrateUpdateOutput {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub status: CrateUpdateStatus,
+    pub repository: Option<String>,
+    pub error: Option<String>,
+}
+
+impl CrateUpdateOutput {
+    pub fn new(name: &str, update: &CrateUpdate) -> Self {
+        let (status, repository, error) = match update {
+            CrateUpdate::Updated { repository } => {
+                (CrateUpdateStatus::Updated, Some(repository.clone()), None)
+            }
+            CrateUpdate::NoRepository => (CrateUpdateStatus::NoRepository, None, None),
+            CrateUpdate::UnknownCrate => (CrateUpdateStatus::UnknownCrate, None, None),
+            CrateUpdate::Failed(err) => (CrateUpdateStatus::Failed, None, Some(err.to_string())),
+        };
+        CrateUpdateOutput {
+            krate: name.into(),
+            status,
+            repository,
+            error,
+        }
+    }
+}
+
+/// The error a command failed with, printed to stderr when its output is JSON.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorOutput {
+    pub error: String,
+    /// The errors that caused it, from the closest to the root cause
+    pub causes: Vec<String>,
+}
+
+impl ErrorOutput {
+    pub fn new(err: &failure::Error) -> Self {
+        ErrorOutput {
+            error: err.to_string(),
+            causes: err.iter_causes().map(|cause| cause.to_string()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_queue::{FailedCrate, PendingBuild, QueuedCrate};
+    use crate::db::{BackendSize, CoverageAverages, InstanceOverview, LastRun, QueueDepth};
+    use crate::docbuilder::SandboxOverride;
+    use chrono::{TimeZone, Utc};
+    use failure::ResultExt;
+    use serde_json::json;
+
+    fn snapshot<T: Serialize>(value: &T) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn queue_list() {
+        let krate = QueuedCrate {
+            id: 1,
+            name: "foo".into(),
+            version: "0.1.0".into(),
+            priority: 0,
+            registry: None,
+        };
+        let pending = PendingBuild {
+            position: 1,
+            krate: krate.clone(),
+            added_at: Utc.ymd(2020, 1, 2).and_hms(3, 4, 5),
+            attempts: 1,
+            ready: false,
+        };
+        assert_eq!(
+            snapshot(&vec![pending.clone()]),
+            json!([{
+                "position": 1,
+                "name": "foo",
+                "version": "0.1.0",
+                "priority": 0,
+                "registry": null,
+                "added_at": "2020-01-02T03:04:05Z",
+                "attempts": 1,
+                "ready": false,
+            }])
+        );
+        // The builds are listed with the same fields as the queued crates
+        let pending = snapshot(&pending);
+        for (field, value) in snapshot(&krate).as_object().unwrap() {
+            assert_eq!(&pending[field], value, "{}", field);
+        }
+
+        let failed = FailedCrate {
+            name: "bar".into(),
+            version: "1.0.0".into(),
+            attempts: 5,
+            last_attempt: None,
+        };
+        assert_eq!(
+            snapshot(&vec![failed]),
+            json!([{
+                "name": "bar",
+                "version": "1.0.0",
+                "attempts": 5,
+                "last_attempt": null,
+            }])
+        );
+    }
+
+    #[test]
+    fn stats_builds() {
+        let summary = BuildStatsSummary {
+            builds: 3,
+            duration_ms: Percentiles {
+                median: Some(1000.0),
+                p95: Some(2000.0),
+            },
+            ..BuildStatsSummary::default()
+        };
+        assert_eq!(
+            snapshot(&BuildStatsOutput::new(7, &summary)),
+            json!({
+                "days": 7,
+                "builds": 3,
+                "duration_ms": {"median": 1000.0, "p95": 2000.0},
+                "peak_memory_bytes": {"median": null, "p95": null},
+                "files_count": {"median": null, "p95": null},
+                "files_size_bytes": {"median": null, "p95": null},
+            })
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let at = Utc.ymd(2020, 1, 2).and_hms(3, 4, 5);
+        let overview = InstanceOverview {
+            crates: 2,
+            releases: 3,
+            successful_builds: 2,
+            failed_builds: 1,
+            queue: vec![QueueDepth {
+                priority: 0,
+                builds: 4,
+            }],
+            storage: vec![BackendSize {
+                backend: "database".into(),
+                files: 10,
+                bytes: 100,
+            }],
+            coverage: CoverageAverages {
+                documented: Some(80.0),
+                with_examples: None,
+            },
+            last_registry_poll: Some(LastRun { at, age_secs: 60 }),
+            last_github_sweep: None,
+            last_toolchain_update: None,
+        };
+        assert_eq!(
+            snapshot(&overview),
+            json!({
+                "crates": 2,
+                "releases": 3,
+                "successful_builds": 2,
+                "failed_builds": 1,
+                "queue": [{"priority": 0, "builds": 4}],
+                "storage": [{"backend": "database", "files": 10, "bytes": 100}],
+                "coverage": {"documented": 80.0, "with_examples": null},
+                "last_registry_poll": {"at": "2020-01-02T03:04:05Z", "age_secs": 60},
+                "last_github_sweep": null,
+                "last_toolchain_update": null,
+            })
+        );
+    }
+
+    #[test]
+    fn blacklist_list() {
+        let blacklist = BlacklistOutput {
+            crates: vec!["bar".into(), "foo".into()],
+        };
+        assert_eq!(snapshot(&blacklist), json!({"crates": ["bar", "foo"]}));
+    }
+
+    #[test]
+    fn limits_list() {
+        let limits = SandboxOverride {
+            krate: "foo".into(),
+            max_memory_bytes: None,
+            timeout_seconds: Some(1200),
+            max_targets: Some(1),
+            max_docs_size_bytes: None,
+        };
+        assert_eq!(
+            snapshot(&vec![limits]),
+            json!([{
+                "crate": "foo",
+                "max_memory_bytes": null,
+                "timeout_seconds": 1200,
+                "max_targets": 1,
+                "max_docs_size_bytes": null,
+            }])
+        );
+    }
+
+    #[test]
+    fn update_github_fields() {
+        let updates = vec![
+            CrateUpdateOutput::new(
+                "foo",
+                &CrateUpdate::Updated {
+                    repository: "owner/foo".into(),
+                },
+            ),
+            CrateUpdateOutput::new("bar", &CrateUpdate::NoRepository),
+            CrateUpdateOutput::new("baz", &CrateUpdate::UnknownCrate),
+            CrateUpdateOutput::new(
+                "qux",
+                &CrateUpdate::Failed(failure::err_msg("rate limited")),
+            ),
+        ];
+        assert_eq!(
+            snapshot(&updates),
+            json!([
+                {"crate": "foo", "status": "updated", "repository": "owner/foo", "error": null},
+                {"crate": "bar", "status": "no_repository", "repository": null, "error": null},
+                {"crate": "baz", "status": "unknown_crate", "repository": null, "error": null},
+                {"crate": "qux", "status": "failed", "repository": null, "error": "rate limited"},
+            ])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err: failure::Error = Err::<(), _>(failure::err_msg("connection refused"))
+            .context("failed to list crates on blacklist")
+            .unwrap_err()
+            .into();
+        assert_eq!(
+            snapshot(&ErrorOutput::new(&err)),
+            json!({
+                "error": "failed to list crates on blacklist",
+                "causes": ["connection refused"],
+            })
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Keeps the owners of the crates up to date with the registry
+
+use crate::db::update_crate_data_in_database;
+use crate::error::Result;
+use crate::index::api::Api;
+use log::{info, warn};
+use postgres::Client;
+use reqwest::StatusCode;
+use std::thread;
+use std::time::Duration;
+
+/// Delay between two requests to the registry API, as required by the crawler policy of crates.io.
+pub(crate) const REQUEST_DELAY: Duration = Duration::from_secs(1);
+
+/// Refreshes the owners of the `limit` crates whose owners were updated the longest time ago,
+/// waiting `delay` between two requests to the registry API.
+///
+/// Building a new release already updates the owners of its crate, so this catches the ownership
+/// changes happening between releases. The update stops as soon as the registry rate limits us.
+/// Returns how many crates were updated.
+pub fn update_crate_owners(
+    conn: &mut Client,
+    api: &Api,
+    limit: i64,
+    delay: Duration,
+) -> Result<usize> {
+    let crates: Vec<String> = conn
+        .query(
+            "SELECT name FROM crates ORDER BY owners_updated_at ASC NULLS FIRST, id LIMIT $1",
+            &[&limit],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let mut updated = 0;
+    for (i, name) in crates.iter().enumerate() {
+        if i > 0 {
+            thread::sleep(delay);
+        }
+
+        match api.get_crate_data(name) {
+            Ok(crate_data) => {
+                update_crate_data_in_database(conn, name, &crate_data)?;
+                updated += 1;
+            }
+            Err(err) if is_rate_limited(&err) => {
+                warn!("rate limited by the registry API, stopping the owners update");
+                break;
+            }
+            Err(err) => {
+                warn!("failed to update the owners of {}: {}", name, err);
+                // Don't retry the crate before the others, as it was probably removed from the
+                // registry.
+                conn.execute(
+                    "UPDATE crates SET owners_updated_at = NOW() WHERE name = $1",
+                    &[name],
+                )?;
+            }
+        }
+    }
+
+    info!("updated the owners of {} crates", updated);
+    Ok(updated)
+}
+
+pub(crate) fn is_rate_limited(err: &failure::Error) -> bool {
+    err.iter_chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            == Some(StatusCode::TOO_MANY_REQUESTS)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestServer};
+    use chrono::NaiveDateTime;
+    use iron::{status, Request, Response};
+    use std::collections::HashMap;
+
+    /// Starts a fake registry API answering with the owners of the `owners` crates, and
+    /// rate limiting requests for `rate_limited`. The API only answers while the server is kept.
+    fn mock_registry_api(
+        owners: HashMap<&'static str, &'static str>,
+        rate_limited: &'static str,
+    ) -> (Api, TestServer) {
+        let handler = move |req: &mut Request| {
+            let path = req.url.path();
+            let response = match path.as_slice() {
+                ["api", "v1", "crates", name, "owners"] if *name == rate_limited => {
+                    Response::with(status::TooManyRequests)
+                }
+                ["api", "v1", "crates", name, "owners"] => match owners.get(*name) {
+                    Some(body) => Response::with((status::Ok, *body)),
+                    None => Response::with(status::NotFound),
+                },
+                _ => Response::with(status::NotFound),
+            };
+
+            Ok(response)
+        };
+        let server = TestServer::start(handler).unwrap();
+        let api = Api::new(Some(server.url().parse().unwrap())).unwrap();
+        (api, server)
+    }
+
+    fn owners(conn: &mut Client, name: &str) -> Result<Vec<(String, String)>> {
+        Ok(conn
+            .query(
+                "SELECT owners.login, owners.kind
+                 FROM owners
+                 INNER JOIN owner_rels ON owner_rels.oid = owners.id
+                 INNER JOIN crates ON crates.id = owner_rels.cid
+                 WHERE crates.name = $1
+                 ORDER BY owners.login",
+                &[&name],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    fn updated_at(conn: &mut Client, name: &str) -> Result<Option<NaiveDateTime>> {
+        Ok(conn
+            .query_one(
+                "SELECT owners_updated_at FROM crates WHERE name = $1",
+                &[&name],
+            )?
+            .get(0))
+    }
+
+    #[test]
+    fn test_update_crate_owners() {
+        wrapper(|env| {
+            env.fake_release().name("foo").create()?;
+            env.fake_release().name("removed").create()?;
+
+            let mut owners_responses = HashMap::new();
+            owners_responses.insert(
+                "foo",
+                r#"{"users": [
+                    {"id": 1, "login": "foobar", "kind": "user", "name": "Foo Bar",
+                     "avatar": "https://example.org/foobar"},
+                    {"id": 2, "login": "github:org:team", "kind": "team", "name": "Team",
+                     "avatar": "https://example.org/team"}
+                ]}"#,
+            );
+            let (api, _server) = mock_registry_api(owners_responses, "rate-limited");
+
+            let mut conn = env.db().conn();
+            let removed_updated_at = updated_at(&mut conn, "removed")?;
+            assert_eq!(
+                update_crate_owners(&mut conn, &api, 10, Duration::from_millis(0))?,
+                1
+            );
+            assert_eq!(
+                owners(&mut conn, "foo")?,
+                vec![
+                    ("foobar".to_string(), "user".to_string()),
+                    ("github:org:team".to_string(), "team".to_string()),
+                ]
+            );
+
+            // Crates missing from the registry aren't retried before the other crates
+            assert!(updated_at(&mut conn, "removed")? > removed_updated_at);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_update_crate_owners_rate_limited() {
+        wrapper(|env| {
+            env.fake_release().name("rate-limited").create()?;
+            env.fake_release().name("foo").create()?;
+            let mut conn = env.db().conn();
+            conn.execute(
+                "UPDATE crates SET owners_updated_at = NULL WHERE name = 'rate-limited'",
+                &[],
+            )?;
+
+            let mut owners_responses = HashMap::new();
+            owners_responses.insert("foo", r#"{"users": []}"#);
+            let (api, _server) = mock_registry_api(owners_responses, "rate-limited");
+
+            // Nothing is updated after being rate limited
+            assert_eq!(
+                update_crate_owners(&mut conn, &api, 10, Duration::from_millis(0))?,
+                0
+            );
+            assert_eq!(updated_at(&mut conn, "rate-limited")?, None);
+
+            Ok(())
+        });
+    }
+}
@@ -2,23 +2,55 @@
 
 use crate::error::Result;
 use postgres::Client;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
-const DEFAULT_PRIORITY: i32 = 0;
+// Priorities of the builds in the queue, builds with a lower priority being built first.
 
-/// Get the build queue priority for a crate
+/// Priority of the first release of a crate
+const DEFAULT_PRIORITY: i32 = 0;
+/// Priority of the new releases of a crate that was already published
+pub(crate) const UPDATED_CRATE_PRIORITY: i32 = 10;
+/// Priority of the rebuilds of releases that were already built
+pub(crate) const REBUILD_PRIORITY: i32 = 50;
+/// Priorities admins can assign to the rebuilds they trigger
+pub(crate) const ADMIN_PRIORITIES: RangeInclusive<i32> = 1..=99;
+
+/// Get the build queue priority for a new release of a crate
+///
+/// Crates matching a pattern in the `crate_priorities` table get the priority of the pattern,
+/// otherwise the first release of a crate is built before the releases of existing crates.
 pub fn get_crate_priority(conn: &mut Client, name: &str) -> Result<i32> {
-    // Search the `priority` table for a priority where the crate name matches the stored pattern
-    let query = conn.query(
-        "SELECT priority FROM crate_priorities WHERE $1 LIKE pattern LIMIT 1",
-        &[&name],
-    )?;
+    Ok(get_crate_priorities(conn, &[name])?
+        .remove(name)
+        .unwrap_or(DEFAULT_PRIORITY))
+}
 
-    // If no match is found, return the default priority
-    if let Some(row) = query.get(0) {
-        Ok(row.get(0))
-    } else {
-        Ok(DEFAULT_PRIORITY)
-    }
+/// Get the build queue priorities for new releases of several crates at once, by crate name, like
+/// [`get_crate_priority`].
+pub(crate) fn get_crate_priorities(
+    conn: &mut Client,
+    names: &[&str],
+) -> Result<HashMap<String, i32>> {
+    // Search the `priority` table for a priority where the crate name matches the stored pattern,
+    // and if no match is found, use the default priority for new or existing crates
+    Ok(conn
+        .query(
+            "SELECT
+                 names.name,
+                 COALESCE(
+                     (SELECT priority FROM crate_priorities WHERE names.name LIKE pattern LIMIT 1),
+                     CASE
+                         WHEN EXISTS(SELECT 1 FROM crates WHERE crates.name = names.name) THEN $2::INT
+                         ELSE $3::INT
+                     END
+                 )
+             FROM unnest($1::TEXT[]) AS names(name)",
+            &[&names, &UPDATED_CRATE_PRIORITY, &DEFAULT_PRIORITY],
+        )?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect())
 }
 
 /// Set all crates that match [`pattern`] to have a certain priority
@@ -138,6 +170,25 @@ mod tests {
         })
     }
 
+    #[test]
+    fn get_priority_of_existing_crates() {
+        wrapper(|env| {
+            let db = env.db();
+            env.fake_release().name("existing").create()?;
+
+            assert_eq!(
+                get_crate_priority(&mut db.conn(), "existing")?,
+                UPDATED_CRATE_PRIORITY
+            );
+            assert_eq!(get_crate_priority(&mut db.conn(), "new")?, DEFAULT_PRIORITY);
+
+            set_crate_priority(&mut db.conn(), "exist%", -100)?;
+            assert_eq!(get_crate_priority(&mut db.conn(), "existing")?, -100);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn get_default_priority() {
         wrapper(|env| {
@@ -1,16 +1,25 @@
-use crate::{docbuilder::RustwideBuilder, utils::pubsubhubbub, BuildQueue, DocBuilder};
+use crate::{
+    docbuilder::RustwideBuilder,
+    utils::{pubsubhubbub, systemd},
+    BuildQueue, DocBuilder,
+};
 use failure::Error;
 use log::{debug, error, info, warn};
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-// TODO: change to `fn() -> Result<!, Error>` when never _finally_ stabilizes
+/// Builds the crates of the queue one after the other, until `shutdown` is set.
+///
+/// The daemon runs one of these for each build worker. The build in progress when `shutdown` is
+/// set is finished before returning.
 pub fn queue_builder(
     mut doc_builder: DocBuilder,
     mut builder: RustwideBuilder,
     build_queue: Arc<BuildQueue>,
+    shutdown: Arc<AtomicBool>,
 ) -> Result<(), Error> {
     /// Represents the current state of the builder thread.
     enum BuilderState {
@@ -18,7 +27,7 @@ pub fn queue_builder(
         Fresh,
         /// The builder has just seen an empty build queue.
         EmptyQueue,
-        /// The builder has just seen the lock file.
+        /// The builder has just seen the lock file, or the locked queue.
         Locked,
         /// The builder has just finished building a crate. The enclosed count is the number of
         /// crates built since the caches have been refreshed.
@@ -26,10 +35,19 @@ pub fn queue_builder(
     }
 
     let mut status = BuilderState::Fresh;
+    let mut last_nightly_update: Option<Instant> = None;
 
     loop {
         if !status.is_in_progress() {
-            thread::sleep(Duration::from_secs(60));
+            // sleep in small steps, to notice a shutdown quickly
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(60) && !shutdown.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+        if shutdown.load(Ordering::SeqCst) {
+            info!("Shutting down, no more crates will be built by this worker");
+            return Ok(());
         }
 
         // check lock file
@@ -38,6 +56,33 @@ pub fn queue_builder(
             status = BuilderState::Locked;
             continue;
         }
+        match build_queue.lock_state() {
+            Ok(Some(lock)) => {
+                warn!(
+                    "Build queue locked since {} ({}), skipping building new crates",
+                    lock.locked_at,
+                    lock.reason.as_deref().unwrap_or("no reason given")
+                );
+                systemd::notify_status("build queue locked");
+                status = BuilderState::Locked;
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to check whether the build queue is locked: {}", e),
+        }
+
+        // Switch to new nightlies between builds, as the toolchain can't change during a build
+        if let Some(interval) = builder.nightly_update_interval() {
+            if !matches!(last_nightly_update, Some(last) if last.elapsed() < interval) {
+                last_nightly_update = Some(Instant::now());
+                systemd::notify_status("updating the nightly toolchain");
+                match builder.update_nightly() {
+                    Ok(true) => info!("switched to the latest nightly"),
+                    Ok(false) => debug!("already using the latest nightly"),
+                    Err(e) => error!("Failed to update the nightly toolchain: {}", e),
+                }
+            }
+        }
 
         if status.count() >= 10 {
             // periodically, ping the hubs
@@ -66,7 +111,14 @@ pub fn queue_builder(
                         Ok(n) => debug!("Succesfully pinged {} hubs", n),
                     }
                 }
+                // Audit the releases against the new security advisories while idle
+                match builder.reaudit_releases() {
+                    Ok(0) => {}
+                    Ok(n) => info!("audited {} releases against new advisories", n),
+                    Err(e) => error!("Failed to audit releases against new advisories: {}", e),
+                }
                 debug!("Queue is empty, going back to sleep");
+                systemd::notify_status("idle");
                 status = BuilderState::EmptyQueue;
                 continue;
             }
@@ -86,11 +138,9 @@ pub fn queue_builder(
         let res = catch_unwind(AssertUnwindSafe(|| {
             match doc_builder.build_next_queue_package(&mut builder) {
                 Err(e) => error!("Failed to build crate from queue: {}", e),
-                Ok(crate_built) => {
-                    if crate_built {
-                        status.increment();
-                    }
-                }
+                Ok(true) => status.increment(),
+                // the remaining crates are being built by other workers
+                Ok(false) => status = BuilderState::EmptyQueue,
             }
         }));
 
@@ -0,0 +1,309 @@
+//! The hosts other than GitHub repositories move to, whose stats are stored alongside the GitHub
+//! ones.
+//!
+//! The repositories of those hosts are stored with their host as a prefix of their ID, like
+//! `gitlab:1234`, which can't clash with the IDs of the GitHub nodes.
+
+use super::github_updater::RepositoryBlocked;
+use super::RepositoryStats;
+use crate::error::Result;
+use crate::Config;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::{blocking::Client as HttpClient, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RepositoryHost {
+    GitLab,
+    Codeberg,
+}
+
+/// A repository loaded from one of the hosts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct HostedRepository {
+    /// The ID the repository is stored with, including the host prefix
+    pub(super) id: String,
+    pub(super) stats: RepositoryStats,
+}
+
+impl RepositoryHost {
+    const ALL: &'static [RepositoryHost] = &[RepositoryHost::GitLab, RepositoryHost::Codeberg];
+
+    fn prefix(self) -> &'static str {
+        match self {
+            RepositoryHost::GitLab => "gitlab",
+            RepositoryHost::Codeberg => "codeberg",
+        }
+    }
+
+    fn api_base(self, config: &Config) -> &str {
+        match self {
+            RepositoryHost::GitLab => &config.gitlab_api_base,
+            RepositoryHost::Codeberg => &config.codeberg_api_base,
+        }
+    }
+
+    /// Parses the URL of a repository on one of the hosts, returning the host and the name of
+    /// the repository, as `owner/repo`.
+    pub(super) fn from_url(url: &str) -> Option<(Self, String)> {
+        static RE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(
+                r"https?://(www.)?(?P<host>gitlab\.com|codeberg\.org)/(?P<owner>[\w\._-]+)/(?P<repo>[\w\._-]+)",
+            )
+            .unwrap()
+        });
+
+        let cap = RE.captures(url)?;
+        let host = match &cap["host"] {
+            "gitlab.com" => RepositoryHost::GitLab,
+            _ => RepositoryHost::Codeberg,
+        };
+        let repo = &cap["repo"];
+        let name = format!(
+            "{}/{}",
+            &cap["owner"],
+            repo.strip_suffix(".git").unwrap_or(repo)
+        );
+        Some((host, name))
+    }
+
+    /// Returns the host of a stored repository and its ID on that host, or `None` for GitHub
+    /// repositories.
+    pub(super) fn from_id(id: &str) -> Option<(Self, &str)> {
+        let (prefix, host_id) = id.split_once(':')?;
+        let host = Self::ALL.iter().find(|host| host.prefix() == prefix)?;
+        Some((*host, host_id))
+    }
+
+    /// Returns the prefix matching the IDs of all the repositories of the host in a `LIKE`.
+    pub(super) fn id_pattern(self) -> String {
+        format!("{}:%", self.prefix())
+    }
+
+    /// Fetches a repository from its name, as `owner/repo`, returning `None` if it doesn't exist.
+    pub(super) fn fetch_by_name(
+        self,
+        client: &HttpClient,
+        config: &Config,
+        name: &str,
+    ) -> Result<Option<HostedRepository>> {
+        match self {
+            // The name is a single segment of the path, with the slash encoded
+            RepositoryHost::GitLab => {
+                self.fetch::<GitLabProject>(client, config, &["projects", name])
+            }
+            RepositoryHost::Codeberg => {
+                let mut segments = vec!["repos"];
+                segments.extend(name.splitn(2, '/'));
+                self.fetch::<GiteaRepository>(client, config, &segments)
+            }
+        }
+    }
+
+    /// Fetches a repository from its ID on the host, returning `None` if it doesn't exist
+    /// anymore.
+    pub(super) fn fetch_by_id(
+        self,
+        client: &HttpClient,
+        config: &Config,
+        host_id: &str,
+    ) -> Result<Option<HostedRepository>> {
+        match self {
+            RepositoryHost::GitLab => {
+                self.fetch::<GitLabProject>(client, config, &["projects", host_id])
+            }
+            RepositoryHost::Codeberg => {
+                self.fetch::<GiteaRepository>(client, config, &["repositories", host_id])
+            }
+        }
+    }
+
+    fn fetch<T: DeserializeOwned + HostResponse>(
+        self,
+        client: &HttpClient,
+        config: &Config,
+        segments: &[&str],
+    ) -> Result<Option<HostedRepository>> {
+        let mut url = Url::parse(self.api_base(config))?;
+        url.path_segments_mut()
+            .map_err(|_| failure::format_err!("invalid API base for {}", self.prefix()))?
+            .pop_if_empty()
+            .extend(segments);
+
+        let response = client.get(url).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if response.status() == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS {
+            return Err(RepositoryBlocked.into());
+        }
+        let repo: T = response.error_for_status()?.json()?;
+
+        Ok(Some(HostedRepository {
+            id: format!("{}:{}", self.prefix(), repo.id()),
+            stats: repo.stats(),
+        }))
+    }
+}
+
+trait HostResponse {
+    fn id(&self) -> i64;
+    fn stats(&self) -> RepositoryStats;
+}
+
+/// The hosts return an empty description when there's none.
+fn description(description: &Option<String>) -> Option<String> {
+    description
+        .clone()
+        .filter(|description| !description.is_empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabProject {
+    id: i64,
+    path_with_namespace: String,
+    description: Option<String>,
+    star_count: i64,
+    forks_count: i64,
+    /// Missing when the issues of the project are disabled
+    #[serde(default)]
+    open_issues_count: i64,
+    last_activity_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    forked_from_project: Option<GitLabParent>,
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabParent {
+    path_with_namespace: String,
+}
+
+impl HostResponse for GitLabProject {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats {
+            name: self.path_with_namespace.clone(),
+            description: description(&self.description),
+            last_commit: self.last_activity_at,
+            updated_at: None,
+            stars: self.star_count as i32,
+            forks: self.forks_count as i32,
+            issues: self.open_issues_count as i32,
+            good_first_issues: None,
+            // Neither the license nor the size are returned without authentication
+            license: None,
+            license_url: None,
+            size_kb: 0,
+            is_fork: self.forked_from_project.is_some(),
+            is_archived: self.archived,
+            closed_issues: None,
+            fork_parent: self
+                .forked_from_project
+                .as_ref()
+                .map(|parent| parent.path_with_namespace.clone()),
+            open_bugs: None,
+            topics: None,
+        }
+    }
+}
+
+/// A repository of Codeberg, which runs Gitea.
+#[derive(Debug, Deserialize)]
+struct GiteaRepository {
+    id: i64,
+    full_name: String,
+    description: Option<String>,
+    stars_count: i64,
+    forks_count: i64,
+    open_issues_count: i64,
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    fork: bool,
+    #[serde(default)]
+    parent: Option<GiteaParent>,
+    /// Size of the repository in kilobytes
+    #[serde(default)]
+    size: i64,
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaParent {
+    full_name: String,
+}
+
+impl HostResponse for GiteaRepository {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats {
+            name: self.full_name.clone(),
+            description: description(&self.description),
+            last_commit: self.updated_at,
+            updated_at: self.updated_at,
+            stars: self.stars_count as i32,
+            forks: self.forks_count as i32,
+            issues: self.open_issues_count as i32,
+            good_first_issues: None,
+            license: None,
+            license_url: None,
+            size_kb: self.size,
+            is_fork: self.fork,
+            is_archived: self.archived,
+            closed_issues: None,
+            fork_parent: self.parent.as_ref().map(|parent| parent.full_name.clone()),
+            open_bugs: None,
+            topics: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_url() {
+        assert_eq!(
+            RepositoryHost::from_url("https://gitlab.com/owner/repo"),
+            Some((RepositoryHost::GitLab, "owner/repo".into()))
+        );
+        assert_eq!(
+            RepositoryHost::from_url("https://gitlab.com/owner/repo.git"),
+            Some((RepositoryHost::GitLab, "owner/repo".into()))
+        );
+        assert_eq!(
+            RepositoryHost::from_url("http://www.codeberg.org/owner/repo.rs/src/branch/main"),
+            Some((RepositoryHost::Codeberg, "owner/repo.rs".into()))
+        );
+        assert_eq!(
+            RepositoryHost::from_url("https://github.com/owner/repo"),
+            None
+        );
+        assert_eq!(RepositoryHost::from_url("https://gitlab.com/owner"), None);
+    }
+
+    #[test]
+    fn test_from_id() {
+        assert_eq!(
+            RepositoryHost::from_id("gitlab:1234"),
+            Some((RepositoryHost::GitLab, "1234"))
+        );
+        assert_eq!(
+            RepositoryHost::from_id("codeberg:56"),
+            Some((RepositoryHost::Codeberg, "56"))
+        );
+        assert_eq!(RepositoryHost::from_id("MDEwOlJlcG9zaXRvcnkx"), None);
+        assert_eq!(RepositoryHost::from_id("bitbucket:1"), None);
+    }
+}
@@ -0,0 +1,245 @@
+//! How the GitHub updater handles each kind of error
+
+use super::github_updater::GithubUpdateError;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid retry policy `{}`: {}", _0, _1)]
+pub(crate) struct InvalidRetryPolicy(String, String);
+
+/// How the updater handles an error updating repositories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    /// How many times the failed request is sent again
+    pub(crate) retries: u32,
+    /// How long to wait before the first retry, doubled before each of the next ones
+    pub(crate) base_delay: Duration,
+    /// Whether the attempt is recorded anyway, so that the repositories are only tried again
+    /// when their stats are outdated instead of by the next run
+    pub(crate) advance_last_attempt: bool,
+}
+
+impl RetryPolicy {
+    /// How long to wait before the retry following `attempt` failed attempts.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .unwrap_or(self.base_delay)
+    }
+}
+
+/// The retry policy of each kind of `GithubUpdateError`, configured with
+/// `DOCSRS_GITHUB_RETRY_POLICIES` as comma separated `kind=retries/delay/advance` entries, like
+/// `transient=5/2s/false`. The kinds that aren't listed keep their default policy.
+///
+/// Only failed requests can be retried: the other errors are answers from GitHub that sending the
+/// request again wouldn't change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RetryPolicies {
+    not_found: RetryPolicy,
+    rate_limited: RetryPolicy,
+    blocked: RetryPolicy,
+    transient: RetryPolicy,
+    request: RetryPolicy,
+}
+
+impl RetryPolicies {
+    /// Returns the policy handling `err`.
+    pub(crate) fn get(&self, err: &GithubUpdateError) -> &RetryPolicy {
+        match err {
+            GithubUpdateError::NotFound => &self.not_found,
+            GithubUpdateError::RateLimited => &self.rate_limited,
+            GithubUpdateError::Blocked => &self.blocked,
+            GithubUpdateError::Transient(_) => &self.transient,
+            GithubUpdateError::Request(_) => &self.request,
+        }
+    }
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        let never = RetryPolicy {
+            retries: 0,
+            base_delay: Duration::from_secs(0),
+            advance_last_attempt: false,
+        };
+        RetryPolicies {
+            // The stats of the repositories are deleted anyway
+            not_found: never,
+            // The repositories are tried first by the next run, once the rate limit is reset
+            rate_limited: never,
+            // The repositories are only tried again after `BLOCKED_REFRESH_DAYS`
+            blocked: RetryPolicy {
+                advance_last_attempt: true,
+                ..never
+            },
+            transient: RetryPolicy {
+                retries: 2,
+                base_delay: Duration::from_secs(1),
+                advance_last_attempt: false,
+            },
+            request: never,
+        }
+    }
+}
+
+impl FromStr for RetryPolicies {
+    type Err = InvalidRetryPolicy;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut policies = RetryPolicies::default();
+        for entry in input
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid = |reason: &str| InvalidRetryPolicy(entry.into(), reason.into());
+            let (kind, policy) = entry
+                .split_once('=')
+                .ok_or_else(|| invalid("expected `kind=retries/delay/advance`"))?;
+            let policy = parse_policy(policy.trim()).ok_or_else(|| {
+                invalid("expected a number of retries, a delay like `2s` and `true` or `false`")
+            })?;
+
+            let retryable = match kind.trim() {
+                "not_found" => &mut policies.not_found,
+                "rate_limited" => &mut policies.rate_limited,
+                "blocked" => &mut policies.blocked,
+                "transient" => &mut policies.transient,
+                "request" => &mut policies.request,
+                _ => return Err(invalid("unknown kind of error")),
+            };
+            *retryable = policy;
+        }
+
+        for policy in &[policies.not_found, policies.rate_limited, policies.blocked] {
+            if policy.retries > 0 {
+                return Err(InvalidRetryPolicy(
+                    input.into(),
+                    "only transient and request errors can be retried".into(),
+                ));
+            }
+        }
+        Ok(policies)
+    }
+}
+
+/// Parses a `retries/delay/advance` policy, like `3/1s/false`.
+fn parse_policy(policy: &str) -> Option<RetryPolicy> {
+    let mut parts = policy.split('/').map(str::trim);
+    let retries = parts.next()?.parse().ok()?;
+    let base_delay = Duration::from_secs(parts.next()?.strip_suffix('s')?.parse().ok()?);
+    let advance_last_attempt = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(RetryPolicy {
+        retries,
+        base_delay,
+        advance_last_attempt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policies() {
+        let policies = RetryPolicies::default();
+
+        let transient = policies.get(&GithubUpdateError::Transient("timed out".into()));
+        assert_eq!(transient.retries, 2);
+        assert_eq!(transient.base_delay, Duration::from_secs(1));
+        assert!(!transient.advance_last_attempt);
+
+        let rate_limited = policies.get(&GithubUpdateError::RateLimited);
+        assert_eq!(rate_limited.retries, 0);
+        assert!(!rate_limited.advance_last_attempt);
+
+        let not_found = policies.get(&GithubUpdateError::NotFound);
+        assert_eq!(not_found.retries, 0);
+
+        assert!(
+            policies
+                .get(&GithubUpdateError::Blocked)
+                .advance_last_attempt
+        );
+        assert_eq!(
+            policies
+                .get(&GithubUpdateError::Request("bad request".into()))
+                .retries,
+            0
+        );
+    }
+
+    #[test]
+    fn configured_policies() {
+        let policies: RetryPolicies =
+            "transient=5/2s/true, rate_limited=0/0s/true,not_found=0/0s/true"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            *policies.get(&GithubUpdateError::Transient("timed out".into())),
+            RetryPolicy {
+                retries: 5,
+                base_delay: Duration::from_secs(2),
+                advance_last_attempt: true,
+            }
+        );
+        assert!(
+            policies
+                .get(&GithubUpdateError::RateLimited)
+                .advance_last_attempt
+        );
+        assert!(
+            policies
+                .get(&GithubUpdateError::NotFound)
+                .advance_last_attempt
+        );
+        // The kinds that aren't listed keep their default policy
+        assert_eq!(
+            policies.get(&GithubUpdateError::Blocked),
+            RetryPolicies::default().get(&GithubUpdateError::Blocked)
+        );
+        assert_eq!(
+            "".parse::<RetryPolicies>().unwrap(),
+            RetryPolicies::default()
+        );
+    }
+
+    #[test]
+    fn invalid_policies() {
+        for input in &[
+            "transient",
+            "transient=5",
+            "transient=5/2/true",
+            "transient=5/2s/maybe",
+            "transient=5/2s/true/1",
+            "transient=-1/2s/true",
+            "unknown=1/1s/false",
+            "rate_limited=1/1s/false",
+            "not_found=2/0s/false",
+        ] {
+            assert!(
+                input.parse::<RetryPolicies>().is_err(),
+                "{} should be invalid",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn exponential_delays() {
+        let policy = RetryPolicy {
+            retries: 3,
+            base_delay: Duration::from_secs(1),
+            advance_last_attempt: false,
+        };
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,323 @@
+//! When the periodic jobs of the daemon run
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Timelike, Utc};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How far in the future the next run of a cron expression is searched for. Expressions that
+/// can't match within that time, like `0 0 30 2 *`, are rejected.
+const MAX_SEARCH_DAYS: i64 = 4 * 366;
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid schedule `{}`: {}", _0, _1)]
+pub(crate) struct InvalidSchedule(String, String);
+
+/// When a periodic job runs, configured as either:
+///
+/// * `off`, to disable the job
+/// * an interval between the end of a run and the start of the next one, like `30s`, `15m`,
+///   `1h` or `1d`
+/// * a cron expression in UTC, with the minute, hour, day of the month, month and day of the week
+///   (0 or 7 being Sunday), like `55 23 * * *` for every day at 23:55
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Schedule {
+    Disabled,
+    Every(Duration),
+    Cron(Box<CronExpression>),
+}
+
+impl Schedule {
+    /// Returns when the job runs next if it finished a run at `now`, or `None` if it's disabled.
+    pub(crate) fn next_run(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Disabled => None,
+            Schedule::Every(interval) => ChronoDuration::from_std(*interval)
+                .ok()
+                .and_then(|interval| now.checked_add_signed(interval)),
+            Schedule::Cron(expression) => expression.next_run(now),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = InvalidSchedule;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        let invalid = |reason: &str| InvalidSchedule(input.into(), reason.into());
+
+        if input == "off" {
+            return Ok(Schedule::Disabled);
+        }
+        if input.split_whitespace().count() == 1 {
+            return parse_interval(input)
+                .map(Schedule::Every)
+                .ok_or_else(|| invalid("expected `off`, an interval or a cron expression"));
+        }
+
+        let expression = input
+            .parse::<CronExpression>()
+            .map_err(|err| invalid(&err))?;
+        if expression.next_run(Utc::now()).is_none() {
+            return Err(invalid("the expression never matches"));
+        }
+        Ok(Schedule::Cron(Box::new(expression)))
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Schedule::Disabled => write!(f, "off"),
+            Schedule::Every(interval) => write!(f, "every {}s", interval.as_secs()),
+            Schedule::Cron(expression) => write!(f, "{}", expression.source),
+        }
+    }
+}
+
+/// Parses an interval made of a number and a unit, like `15m`.
+fn parse_interval(input: &str) -> Option<Duration> {
+    let unit_start = input.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = input.split_at(unit_start);
+    let amount: u64 = amount.parse().ok()?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    if amount == 0 {
+        return None;
+    }
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+/// A cron expression, as the values allowed for each of its fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CronExpression {
+    source: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    /// Whether the days of the month or of the week are unrestricted. When both are restricted,
+    /// a day matching either of them matches, like in the usual cron implementations.
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronExpression {
+    fn next_run(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        // Runs start at the beginning of the minute, and never during the current one
+        let mut time = now.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = now + ChronoDuration::days(MAX_SEARCH_DAYS);
+
+        while time < limit {
+            if !self.matches_day(time) {
+                time = (time + ChronoDuration::days(1))
+                    .with_hour(0)?
+                    .with_minute(0)?;
+            } else if !self.hours[time.hour() as usize] {
+                time = (time + ChronoDuration::hours(1)).with_minute(0)?;
+            } else if !self.minutes[time.minute() as usize] {
+                time = time + ChronoDuration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        if !self.months[time.month() as usize] {
+            return false;
+        }
+        let day_of_month = self.days_of_month[time.day() as usize];
+        let day_of_week = self.days_of_week[time.weekday().num_days_from_sunday() as usize];
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+impl FromStr for CronExpression {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = input.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "expected 5 fields in the cron expression, found {}",
+                fields.len()
+            ));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+
+        Ok(CronExpression {
+            source: fields.join(" "),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            any_day_of_month: fields[2] == "*",
+            any_day_of_week: fields[4] == "*",
+        })
+    }
+}
+
+/// Parses a field of a cron expression, made of comma separated values, ranges like `1-5`, and
+/// `*`, each optionally followed by a step like `*/15`. Returns whether each value between 0 and
+/// `max` is allowed.
+fn parse_field(field: &str, min: usize, max: usize) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max + 1];
+    for part in field.split(',') {
+        let invalid = || format!("invalid field `{}`", field);
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start.parse().map_err(|_| invalid())?,
+                end.parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let value = range.parse().map_err(|_| invalid())?;
+            // A single value with a step, like `5/15`, runs from that value to the maximum
+            (value, if step > 1 { max } else { value })
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(format!(
+                "invalid field `{}`, values must be between {} and {}",
+                field, min, max
+            ));
+        }
+        for value in (start..=end).step_by(step) {
+            allowed[value] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2021-03-01 is a Monday
+        Utc.ymd(2021, 3, day).and_hms(hour, minute, 0)
+    }
+
+    fn next(schedule: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        schedule.parse::<Schedule>().unwrap().next_run(now)
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(
+            next("30s", at(1, 10, 0)),
+            Some(at(1, 10, 0) + ChronoDuration::seconds(30))
+        );
+        assert_eq!(next("15m", at(1, 10, 0)), Some(at(1, 10, 15)));
+        assert_eq!(next("1h", at(1, 10, 5)), Some(at(1, 11, 5)));
+        assert_eq!(next("2d", at(1, 10, 5)), Some(at(3, 10, 5)));
+    }
+
+    #[test]
+    fn cron_expressions() {
+        // Every day at 23:55
+        assert_eq!(next("55 23 * * *", at(1, 10, 0)), Some(at(1, 23, 55)));
+        assert_eq!(next("55 23 * * *", at(1, 23, 55)), Some(at(2, 23, 55)));
+        // Every 15 minutes
+        assert_eq!(next("*/15 * * * *", at(1, 10, 1)), Some(at(1, 10, 15)));
+        assert_eq!(next("*/15 * * * *", at(1, 10, 59)), Some(at(1, 11, 0)));
+        // Off peak hours, from 1:00 to 5:59
+        assert_eq!(next("0 1-5 * * *", at(1, 12, 0)), Some(at(2, 1, 0)));
+        assert_eq!(next("0 1-5 * * *", at(2, 1, 0)), Some(at(2, 2, 0)));
+        // Lists of values
+        assert_eq!(next("0,30 6,18 * * *", at(1, 6, 30)), Some(at(1, 18, 0)));
+        // On Saturdays, the 6th, and on Sundays, with both 0 and 7
+        assert_eq!(next("0 3 * * 6", at(1, 12, 0)), Some(at(6, 3, 0)));
+        assert_eq!(next("0 3 * * 0", at(1, 12, 0)), Some(at(7, 3, 0)));
+        assert_eq!(next("0 3 * * 7", at(1, 12, 0)), Some(at(7, 3, 0)));
+        // On the 4th of the month or on Tuesdays, whichever comes first
+        assert_eq!(next("0 0 4 * 2", at(1, 12, 0)), Some(at(2, 0, 0)));
+        assert_eq!(next("0 0 4 * 2", at(2, 12, 0)), Some(at(4, 0, 0)));
+        // In another month
+        assert_eq!(
+            next("0 0 1 5 *", at(1, 12, 0)),
+            Some(Utc.ymd(2021, 5, 1).and_hms(0, 0, 0))
+        );
+        // Leap days
+        assert_eq!(
+            next("0 0 29 2 *", at(1, 12, 0)),
+            Some(Utc.ymd(2024, 2, 29).and_hms(0, 0, 0))
+        );
+        // Seconds are ignored, and the run is never in the current minute
+        assert_eq!(
+            next("* * * * *", at(1, 10, 0) + ChronoDuration::seconds(59)),
+            Some(at(1, 10, 1))
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        assert_eq!("off".parse::<Schedule>().unwrap(), Schedule::Disabled);
+        assert_eq!(next("off", at(1, 10, 0)), None);
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        for schedule in &[
+            "",
+            "never",
+            "0s",
+            "15",
+            "1w",
+            "h",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "0 0 30 2 *",
+        ] {
+            assert!(
+                schedule.parse::<Schedule>().is_err(),
+                "{} should be invalid",
+                schedule
+            );
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Schedule::Disabled.to_string(), "off");
+        assert_eq!("1h".parse::<Schedule>().unwrap().to_string(), "every 3600s");
+        assert_eq!(
+            " 55  23 * * * ".parse::<Schedule>().unwrap().to_string(),
+            "55 23 * * *"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Parsing of the SPDX license expressions of the crates, and the compatibility of the licenses
+
+/// The most alternatives an expression is expanded to, as `AND`s of `OR`s multiply them.
+const MAX_ALTERNATIVES: usize = 64;
+
+const GPL_2_ONLY: &[&str] = &["GPL-2.0-only"];
+const GPL_3: &[&str] = &[
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+];
+const GPL: &[&str] = &[
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+];
+/// The strong copyleft licenses, whose terms extend to the code depending on the licensed one
+const RESTRICTIVE: &[&str] = &[
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+    "SSPL-1.0",
+];
+const GPL_INCOMPATIBLE: &[&str] = &["CDDL-1.0", "CDDL-1.1", "EPL-1.0", "MPL-1.1", "EUPL-1.1"];
+
+/// Pairs of groups of licenses, where no license of one group can be combined with a license of
+/// the other.
+const INCOMPATIBLE: &[(&[&str], &[&str])] = &[
+    // The patent clauses of Apache-2.0 are additional restrictions GPL-2.0 doesn't allow
+    (GPL_2_ONLY, &["Apache-2.0"]),
+    (GPL_2_ONLY, GPL_3),
+    (GPL, GPL_INCOMPATIBLE),
+];
+
+/// Parses an SPDX license expression like `MIT OR Apache-2.0`, also accepting the `/` separator
+/// of the older crates, into the alternative sets of licenses the code can be used under.
+///
+/// For example, `MIT AND (Apache-2.0 OR BSD-3-Clause)` returns `[[MIT, Apache-2.0], [MIT,
+/// BSD-3-Clause]]`. The deprecated identifiers are normalized, so `GPL-3.0+` is returned as
+/// `GPL-3.0-or-later`. Returns `None` if the expression is invalid.
+pub(crate) fn license_alternatives(expression: &str) -> Option<Vec<Vec<String>>> {
+    let tokens = tokenize(expression);
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+    };
+    let alternatives = parser.or_expression()?;
+    if parser.position == tokens.len() {
+        Some(alternatives)
+    } else {
+        None
+    }
+}
+
+/// Returns whether code under the `a` license can be combined with code under the `b` license,
+/// which must be normalized identifiers like the ones returned by `license_alternatives`.
+///
+/// Only the incompatibilities of the common licenses are known, the other licenses are
+/// considered compatible.
+pub(crate) fn are_compatible(a: &str, b: &str) -> bool {
+    // Exceptions only grant more permissions
+    let a = base_license(a);
+    let b = base_license(b);
+    !INCOMPATIBLE.iter().any(|(first, second)| {
+        (first.contains(&a) && second.contains(&b)) || (first.contains(&b) && second.contains(&a))
+    })
+}
+
+/// Returns whether every alternative of a parsed license expression includes a restrictive license
+/// like the GPL, AGPL or SSPL, so the licensed code can't be used without its terms.
+pub(crate) fn is_restrictive(alternatives: &[Vec<String>]) -> bool {
+    !alternatives.is_empty()
+        && alternatives.iter().all(|licenses| {
+            licenses
+                .iter()
+                .any(|license| RESTRICTIVE.contains(&base_license(license)))
+        })
+}
+
+fn base_license(license: &str) -> &str {
+    license.split(" WITH ").next().unwrap_or(license)
+}
+
+fn normalize(license: &str) -> String {
+    let (id, or_later) = match license.strip_suffix('+') {
+        Some(id) => (id, true),
+        None => (license, false),
+    };
+    let gnu = [
+        "GPL-2.0", "GPL-3.0", "LGPL-2.0", "LGPL-2.1", "LGPL-3.0", "AGPL-3.0",
+    ];
+    if gnu.contains(&id) {
+        format!("{}-{}", id, if or_later { "or-later" } else { "only" })
+    } else {
+        license.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    And,
+    Or,
+    With,
+    License(&'a str),
+}
+
+fn tokenize(expression: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in expression.char_indices() {
+        if c.is_whitespace() || "()/".contains(c) {
+            if let Some(start) = start.take() {
+                tokens.push(word(&expression[start..i]));
+            }
+            match c {
+                '(' => tokens.push(Token::Open),
+                ')' => tokens.push(Token::Close),
+                '/' => tokens.push(Token::Or),
+                _ => {}
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(word(&expression[start..]));
+    }
+    tokens
+}
+
+fn word(word: &str) -> Token<'_> {
+    match word.to_ascii_uppercase().as_str() {
+        "AND" => Token::And,
+        "OR" => Token::Or,
+        "WITH" => Token::With,
+        _ => Token::License(word),
+    }
+}
+
+struct Parser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    position: usize,
+}
+
+impl Parser<'_, '_> {
+    fn next_if(&mut self, token: &Token<'_>) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or_expression(&mut self) -> Option<Vec<Vec<String>>> {
+        let mut alternatives = self.and_expression()?;
+        while self.next_if(&Token::Or) {
+            alternatives.extend(self.and_expression()?);
+        }
+        alternatives.truncate(MAX_ALTERNATIVES);
+        Some(alternatives)
+    }
+
+    fn and_expression(&mut self) -> Option<Vec<Vec<String>>> {
+        let mut alternatives = self.term()?;
+        while self.next_if(&Token::And) {
+            let right = self.term()?;
+            alternatives = alternatives
+                .iter()
+                .flat_map(|left| {
+                    right.iter().map(move |right| {
+                        let mut combined = left.clone();
+                        combined.extend(right.iter().filter(|l| !left.contains(*l)).cloned());
+                        combined
+                    })
+                })
+                .take(MAX_ALTERNATIVES)
+                .collect();
+        }
+        Some(alternatives)
+    }
+
+    fn term(&mut self) -> Option<Vec<Vec<String>>> {
+        if self.next_if(&Token::Open) {
+            let alternatives = self.or_expression()?;
+            return if self.next_if(&Token::Close) {
+                Some(alternatives)
+            } else {
+                None
+            };
+        }
+        let license = match self.tokens.get(self.position)? {
+            Token::License(license) => normalize(license),
+            _ => return None,
+        };
+        self.position += 1;
+        if self.next_if(&Token::With) {
+            match self.tokens.get(self.position)? {
+                Token::License(exception) => {
+                    self.position += 1;
+                    Some(vec![vec![format!("{} WITH {}", license, exception)]])
+                }
+                _ => None,
+            }
+        } else {
+            Some(vec![vec![license]])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(alternatives: &[&[&str]]) -> Option<Vec<Vec<String>>> {
+        Some(
+            alternatives
+                .iter()
+                .map(|licenses| licenses.iter().map(|l| l.to_string()).collect())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_expressions() {
+        assert_eq!(license_alternatives("MIT"), owned(&[&["MIT"]]));
+        assert_eq!(
+            license_alternatives("MIT OR Apache-2.0"),
+            owned(&[&["MIT"], &["Apache-2.0"]])
+        );
+        assert_eq!(
+            license_alternatives("MIT/Apache-2.0"),
+            owned(&[&["MIT"], &["Apache-2.0"]])
+        );
+        assert_eq!(
+            license_alternatives("MIT AND (Apache-2.0 or BSD-3-Clause)"),
+            owned(&[&["MIT", "Apache-2.0"], &["MIT", "BSD-3-Clause"]])
+        );
+        assert_eq!(
+            license_alternatives("GPL-2.0+ OR GPL-3.0 WITH Classpath-exception-2.0"),
+            owned(&[
+                &["GPL-2.0-or-later"],
+                &["GPL-3.0-only WITH Classpath-exception-2.0"]
+            ])
+        );
+
+        for invalid in &["", "MIT OR", "(MIT", "MIT Apache-2.0", "MIT WITH", "AND"] {
+            assert_eq!(license_alternatives(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn compatibility() {
+        assert!(are_compatible("MIT", "GPL-3.0-only"));
+        assert!(are_compatible("Apache-2.0", "GPL-3.0-or-later"));
+        assert!(are_compatible("Apache-2.0", "GPL-2.0-or-later"));
+        assert!(are_compatible("GPL-2.0-or-later", "GPL-3.0-only"));
+        assert!(are_compatible("MPL-2.0", "GPL-2.0-only"));
+
+        assert!(!are_compatible("GPL-2.0-only", "Apache-2.0"));
+        assert!(!are_compatible("Apache-2.0", "GPL-2.0-only"));
+        assert!(!are_compatible("GPL-2.0-only", "GPL-3.0-only"));
+        assert!(!are_compatible("CDDL-1.0", "GPL-3.0-or-later"));
+        assert!(!are_compatible(
+            "GPL-2.0-only WITH Classpath-exception-2.0",
+            "Apache-2.0"
+        ));
+    }
+
+    #[test]
+    fn restrictive_licenses() {
+        let restrictive = |expression| is_restrictive(&license_alternatives(expression).unwrap());
+        assert!(restrictive("GPL-3.0"));
+        assert!(restrictive("AGPL-3.0-or-later"));
+        assert!(restrictive("SSPL-1.0"));
+        assert!(restrictive(
+            "MIT AND GPL-2.0-only WITH Classpath-exception-2.0"
+        ));
+        assert!(restrictive("GPL-2.0 OR AGPL-3.0"));
+
+        assert!(!restrictive("MIT OR Apache-2.0"));
+        assert!(!restrictive("MIT OR GPL-3.0"));
+        assert!(!restrictive("LGPL-2.1"));
+        assert!(!restrictive("MPL-2.0"));
+    }
+}
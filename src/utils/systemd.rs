@@ -0,0 +1,163 @@
+//! Notifications to systemd about the state of the daemon, following `sd_notify(3)`
+//!
+//! systemd sets `NOTIFY_SOCKET` for the services with `Type=notify`, and `WATCHDOG_USEC` for the
+//! ones with `WatchdogSec`. Without them every notification is a no-op, and failing to send one
+//! is only logged, so running outside systemd is unaffected.
+
+use log::debug;
+use once_cell::sync::Lazy;
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::Duration;
+
+static NOTIFIER: Lazy<SdNotifier> = Lazy::new(SdNotifier::from_env);
+
+/// Tells systemd that the daemon finished starting up.
+pub(crate) fn notify_ready(status: &str) {
+    NOTIFIER.send(&[("READY", "1"), ("STATUS", status)]);
+}
+
+/// Describes what the daemon is doing, as shown by `systemctl status`.
+pub(crate) fn notify_status(status: &str) {
+    NOTIFIER.send(&[("STATUS", status)]);
+}
+
+/// Tells the watchdog of systemd that the daemon is still alive.
+pub(crate) fn notify_watchdog() {
+    NOTIFIER.send(&[("WATCHDOG", "1")]);
+}
+
+/// Tells systemd that the daemon is shutting down.
+pub(crate) fn notify_stopping() {
+    NOTIFIER.send(&[("STOPPING", "1")]);
+}
+
+/// How often the watchdog has to be notified, which is half the timeout configured with
+/// `WatchdogSec` as recommended by systemd, or `None` if the watchdog isn't enabled.
+pub(crate) fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    // The watchdog applies to another process of the service
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    match usec?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec / 2)),
+    }
+}
+
+/// The socket the notifications are sent to, if any.
+struct SdNotifier {
+    socket: Option<(UnixDatagram, PathBuf)>,
+}
+
+impl SdNotifier {
+    fn from_env() -> Self {
+        let path = match env::var_os("NOTIFY_SOCKET") {
+            Some(path) => PathBuf::from(path),
+            None => return SdNotifier { socket: None },
+        };
+        // The sockets in the abstract namespace, starting with `@`, aren't supported
+        if !path.is_absolute() {
+            debug!("ignoring the unsupported NOTIFY_SOCKET {}", path.display());
+            return SdNotifier { socket: None };
+        }
+        Self::new(path)
+    }
+
+    fn new(path: PathBuf) -> Self {
+        let socket = UnixDatagram::unbound().and_then(|socket| {
+            // Sending never blocks the daemon, even if systemd stops reading the notifications
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        });
+        match socket {
+            Ok(socket) => SdNotifier {
+                socket: Some((socket, path)),
+            },
+            Err(err) => {
+                debug!("failed to create the socket notifying systemd: {}", err);
+                SdNotifier { socket: None }
+            }
+        }
+    }
+
+    fn send(&self, fields: &[(&str, &str)]) {
+        if let Some((socket, path)) = &self.socket {
+            if let Err(err) = socket.send_to(format_message(fields).as_bytes(), path) {
+                debug!("failed to notify systemd: {}", err);
+            }
+        }
+    }
+}
+
+/// Formats the `KEY=value` lines of a notification. The values are on a single line, so line
+/// breaks are replaced with spaces.
+fn format_message(fields: &[(&str, &str)]) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value.replace('\n', " ")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_format() {
+        assert_eq!(format_message(&[("WATCHDOG", "1")]), "WATCHDOG=1\n");
+        assert_eq!(
+            format_message(&[("READY", "1"), ("STATUS", "building foo 1.0.0\nand more")]),
+            "READY=1\nSTATUS=building foo 1.0.0 and more\n"
+        );
+    }
+
+    #[test]
+    fn send_to_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = SdNotifier::new(path.clone());
+        notifier.send(&[("READY", "1"), ("STATUS", "idle")]);
+        notifier.send(&[("WATCHDOG", "1")]);
+
+        let mut buf = [0; 256];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=idle\n");
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1\n");
+
+        // Nothing listening anymore doesn't fail nor block
+        drop(systemd);
+        notifier.send(&[("STATUS", "idle")]);
+        SdNotifier { socket: None }.send(&[("STATUS", "idle")]);
+    }
+
+    #[test]
+    fn watchdog() {
+        assert_eq!(parse_watchdog(None, None, 1), None);
+        assert_eq!(
+            parse_watchdog(Some("30000000"), None, 1),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog(Some("30000000"), Some("1"), 1),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(parse_watchdog(Some("30000000"), Some("2"), 1), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 1), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 1), None);
+    }
+}
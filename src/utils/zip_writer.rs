@@ -0,0 +1,191 @@
+//! A minimal zip writer streaming the archive to any `Write`, without seeking back into it.
+//!
+//! Every file is compressed in memory before being written, so the memory used is bounded by the
+//! size of the largest file rather than by the size of the archive.
+
+use flate2::{write::DeflateEncoder, Compression, Crc};
+use std::io::{self, Write};
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+/// Zip 2.0, the first version supporting deflate
+const VERSION: u16 = 20;
+/// The names of the files are encoded in UTF-8
+const FLAGS: u16 = 1 << 11;
+const METHOD_DEFLATE: u16 = 8;
+/// 1980-01-01 00:00, the earliest date of the MS-DOS format, as the files have no meaningful
+/// modification time
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+/// The most files an archive can contain, as the zip64 extensions aren't supported
+pub(crate) const MAX_FILES: usize = u16::MAX as usize;
+
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+pub(crate) struct ZipWriter<W: Write> {
+    inner: W,
+    entries: Vec<Entry>,
+    written: u64,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            entries: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Adds a file named `name`, which can contain `/` to be in a directory.
+    ///
+    /// Archives are limited to [`MAX_FILES`] files of 4GB each, as the zip64 extensions aren't
+    /// supported.
+    pub(crate) fn add_file(&mut self, name: &str, content: &[u8]) -> io::Result<()> {
+        if self.entries.len() >= MAX_FILES {
+            return Err(too_big("too many files"));
+        }
+        let mut crc = Crc::new();
+        crc.update(content);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content)?;
+        let compressed = encoder.finish()?;
+
+        let entry = Entry {
+            name: name.into(),
+            crc: crc.sum(),
+            compressed_size: to_u32(compressed.len() as u64)?,
+            size: to_u32(content.len() as u64)?,
+            offset: to_u32(self.written)?,
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(&LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+        header.extend(&VERSION.to_le_bytes());
+        header.extend(&FLAGS.to_le_bytes());
+        header.extend(&METHOD_DEFLATE.to_le_bytes());
+        header.extend(&DOS_TIME.to_le_bytes());
+        header.extend(&DOS_DATE.to_le_bytes());
+        header.extend(&entry.crc.to_le_bytes());
+        header.extend(&entry.compressed_size.to_le_bytes());
+        header.extend(&entry.size.to_le_bytes());
+        header.extend(&(name.len() as u16).to_le_bytes());
+        // No extra field
+        header.extend(&0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+
+        self.write(&header)?;
+        self.write(&compressed)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory listing the files, completing the archive.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let start = to_u32(self.written)?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend(&CENTRAL_DIRECTORY_HEADER_SIGNATURE.to_le_bytes());
+            // Version made by, then version needed to extract
+            directory.extend(&VERSION.to_le_bytes());
+            directory.extend(&VERSION.to_le_bytes());
+            directory.extend(&FLAGS.to_le_bytes());
+            directory.extend(&METHOD_DEFLATE.to_le_bytes());
+            directory.extend(&DOS_TIME.to_le_bytes());
+            directory.extend(&DOS_DATE.to_le_bytes());
+            directory.extend(&entry.crc.to_le_bytes());
+            directory.extend(&entry.compressed_size.to_le_bytes());
+            directory.extend(&entry.size.to_le_bytes());
+            directory.extend(&(entry.name.len() as u16).to_le_bytes());
+            // Extra field length, comment length, disk number, internal and external attributes
+            directory.extend(&[0; 12]);
+            directory.extend(&entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let size = to_u32(directory.len() as u64)?;
+        let count = self.entries.len() as u16;
+
+        directory.extend(&END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        // Disk number, disk with the central directory
+        directory.extend(&[0; 4]);
+        directory.extend(&count.to_le_bytes());
+        directory.extend(&count.to_le_bytes());
+        directory.extend(&size.to_le_bytes());
+        directory.extend(&start.to_le_bytes());
+        // No comment
+        directory.extend(&0u16.to_le_bytes());
+
+        self.write(&directory)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+}
+
+fn to_u32(size: u64) -> io::Result<u32> {
+    if size > u64::from(u32::MAX) {
+        Err(too_big("archive bigger than 4GB"))
+    } else {
+        Ok(size as u32)
+    }
+}
+
+fn too_big(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("zip64 isn't supported: {}", message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn readable_archive() {
+        let mut writer = ZipWriter::new(Vec::new());
+        writer
+            .add_file("index.html", b"<html>index</html>")
+            .unwrap();
+        writer.add_file("dir/empty.txt", b"").unwrap();
+        writer.add_file("dir/ünicode.js", &[b'a'; 10_000]).unwrap();
+        let archive = writer.finish().unwrap();
+        // The repeated content is compressed
+        assert!(archive.len() < 1_000);
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut read = |name: &str| {
+            let mut content = Vec::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut content)
+                .unwrap();
+            content
+        };
+        assert_eq!(read("index.html"), b"<html>index</html>");
+        assert_eq!(read("dir/empty.txt"), b"");
+        assert_eq!(read("dir/ünicode.js"), vec![b'a'; 10_000]);
+    }
+
+    #[test]
+    fn empty_archive() {
+        let archive = ZipWriter::new(Vec::new()).finish().unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        assert_eq!(archive.len(), 0);
+    }
+}
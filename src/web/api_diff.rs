@@ -0,0 +1,487 @@
+//! The changes to the public API between two releases, computed from their rustdoc JSON output
+
+use super::{error::Nope, json_response, page::WebPage, MetaData};
+use crate::db::Pool;
+use crate::docbuilder::rustdoc_json::{is_rustdoc_json, rustdoc_json_path};
+use crate::error::Result;
+use crate::storage::PathNotFoundError;
+use crate::{impl_webpage, Config, Storage};
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// Keys of the rustdoc JSON items referring to other items by id. The ids aren't stable between
+/// builds, so they're ignored when comparing items.
+const ID_KEYS: &[&str] = &[
+    "id",
+    "items",
+    "impls",
+    "implementations",
+    "implementors",
+    "links",
+];
+
+/// The parts of the items compared to describe how they changed, checked in this order.
+const SIGNATURE_PARTS: &[(&str, &str)] = &[
+    ("output", "return type changed"),
+    ("inputs", "parameters changed"),
+    ("generics", "generics changed"),
+];
+
+/// The rustdoc JSON output of a release. Only the parts needed to compare public APIs are parsed,
+/// as the rest of the format changes between toolchains.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RustdocJson {
+    index: HashMap<String, RustdocItem>,
+    paths: HashMap<String, RustdocPath>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RustdocItem {
+    #[serde(default)]
+    visibility: Value,
+    #[serde(default)]
+    inner: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct RustdocPath {
+    crate_id: u64,
+    path: Vec<String>,
+    kind: String,
+}
+
+impl RustdocJson {
+    pub(crate) fn parse(content: &[u8]) -> Result<Self> {
+        if !is_rustdoc_json(content) {
+            failure::bail!("invalid rustdoc JSON");
+        }
+
+        Ok(serde_json::from_slice(content)?)
+    }
+
+    /// Returns the signatures of the public items defined by the crate, by their kind and path.
+    fn public_items(&self) -> BTreeMap<String, Value> {
+        self.paths
+            .iter()
+            // Items from other crates are only part of the API if they're re-exported
+            .filter(|(_, path)| path.crate_id == 0 && path.path.len() > 1)
+            .filter_map(|(id, path)| {
+                let item = self.index.get(id)?;
+                if item.visibility != "public" {
+                    return None;
+                }
+
+                let name = format!("{} {}", short_kind(&path.kind), path.path[1..].join("::"));
+                Some((name, without_ids(&item.inner)))
+            })
+            .collect()
+    }
+}
+
+/// The public items added, removed and changed between two releases.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ApiDiff {
+    pub(crate) added: Vec<String>,
+    pub(crate) removed: Vec<String>,
+    pub(crate) changed: Vec<ChangedItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ChangedItem {
+    pub(crate) item: String,
+    pub(crate) change: String,
+}
+
+/// Compares the public API of the release documented by `a` to the one of the release documented
+/// by `b`.
+pub(crate) fn diff_rustdoc_json(a: &RustdocJson, b: &RustdocJson) -> ApiDiff {
+    let old = a.public_items();
+    let new = b.public_items();
+
+    let mut diff = ApiDiff::default();
+    for (item, signature) in &new {
+        match old.get(item) {
+            None => diff.added.push(item.clone()),
+            Some(old_signature) if old_signature != signature => diff.changed.push(ChangedItem {
+                item: item.clone(),
+                change: describe_change(old_signature, signature).into(),
+            }),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .keys()
+        .filter(|item| !new.contains_key(*item))
+        .cloned()
+        .collect();
+
+    diff
+}
+
+fn short_kind(kind: &str) -> &str {
+    match kind {
+        "function" => "fn",
+        "module" => "mod",
+        "constant" => "const",
+        "typedef" | "type_alias" => "type",
+        "proc_attribute" | "proc_derive" => "macro",
+        kind => kind,
+    }
+}
+
+fn without_ids(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(key, _)| !ID_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), without_ids(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(without_ids).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Returns the first value of `key` found in `value`, searching depth first.
+fn find_key<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map
+            .get(key)
+            .or_else(|| map.values().find_map(|value| find_key(value, key))),
+        Value::Array(values) => values.iter().find_map(|value| find_key(value, key)),
+        _ => None,
+    }
+}
+
+fn describe_change(old: &Value, new: &Value) -> &'static str {
+    SIGNATURE_PARTS
+        .iter()
+        .find(|(key, _)| find_key(old, key) != find_key(new, key))
+        .map_or("signature changed", |(_, change)| change)
+}
+
+/// Returns the rustdoc JSON output of a release, if it was generated.
+fn load_rustdoc_json(
+    storage: &Storage,
+    config: &Config,
+    name: &str,
+    version: &str,
+) -> Result<Option<RustdocJson>> {
+    match storage.get(&rustdoc_json_path(name, version), config.max_file_size) {
+        Ok(blob) => Ok(Some(RustdocJson::parse(&blob.content)?)),
+        Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Handler for `GET /api/v1/crates/:name/versions/:a/diff/:b`.
+///
+/// Lists the public items added, removed and changed from version `a` to version `b`.
+pub fn api_diff_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let versions = [
+        cexpect!(req, router.find("a")),
+        cexpect!(req, router.find("b")),
+    ];
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let mut apis = Vec::new();
+    for version in &versions {
+        match ctry!(req, load_rustdoc_json(storage, config, name, version)) {
+            Some(api) => apis.push(api),
+            None => {
+                return Ok(json_response(
+                    status::NotFound,
+                    json!({
+                        "error": format!("no rustdoc JSON was generated for {} {}", name, version)
+                    }),
+                ))
+            }
+        }
+    }
+
+    Ok(json_response(
+        status::Ok,
+        json!(diff_rustdoc_json(&apis[0], &apis[1])),
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ApiDiffPage {
+    metadata: MetaData,
+    other_version: String,
+    /// `None` if the rustdoc JSON output of one of the releases wasn't generated
+    diff: Option<ApiDiff>,
+}
+
+impl_webpage! {
+    ApiDiffPage = "crate/diff.html",
+}
+
+/// Handler for `GET /crate/:name/:version/diff/:other`, showing the changes to the public API from
+/// `version` to `other`.
+pub fn api_diff_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+    let other_version = cexpect!(req, router.find("other"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let metadata = match MetaData::from_crate(&mut conn, name, version) {
+        Some(metadata) => metadata,
+        None => return Err(Nope::VersionNotFound.into()),
+    };
+    if MetaData::from_crate(&mut conn, name, other_version).is_none() {
+        return Err(Nope::VersionNotFound.into());
+    }
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let old = ctry!(req, load_rustdoc_json(storage, config, name, version));
+    let new = ctry!(req, load_rustdoc_json(storage, config, name, other_version));
+    let diff = match (old, new) {
+        (Some(old), Some(new)) => Some(diff_rustdoc_json(&old, &new)),
+        _ => None,
+    };
+
+    ApiDiffPage {
+        metadata,
+        other_version: other_version.to_string(),
+        diff,
+    }
+    .into_response(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docbuilder::rustdoc_json::tests::sample_rustdoc_json;
+    use crate::test::{assert_success, wrapper, TestEnvironment};
+    use reqwest::StatusCode;
+
+    /// Adds a public item to a rustdoc JSON output.
+    fn add_item(json: &mut Value, id: &str, name: &str, kind: &str, inner: Value) {
+        json["index"][id] = json!({
+            "id": id,
+            "crate_id": 0,
+            "name": name,
+            "visibility": "public",
+            "docs": null,
+            "links": {},
+            "attrs": [],
+            "deprecation": null,
+            "kind": kind,
+            "inner": inner,
+        });
+        json["paths"][id] = json!({"crate_id": 0, "path": ["dummy", name], "kind": kind});
+    }
+
+    fn function(output: Value) -> Value {
+        json!({
+            "decl": {"inputs": [["value", {"kind": "primitive", "inner": "u32"}]], "output": output},
+            "generics": {"params": [], "where_predicates": []},
+            "header": [],
+            "abi": "\"Rust\"",
+        })
+    }
+
+    /// The rustdoc JSON outputs of two versions of a crate, with an added, a removed and two changed
+    /// functions.
+    fn versions() -> (Value, Value) {
+        let u32_type = json!({"kind": "primitive", "inner": "u32"});
+        let struct_type = |id: &str| json!({"kind": "resolved_path", "inner": {"name": "Dummy", "id": id, "args": null}});
+
+        let mut old = sample_rustdoc_json();
+        add_item(
+            &mut old,
+            "0:1",
+            "old_function",
+            "function",
+            function(json!(null)),
+        );
+        add_item(
+            &mut old,
+            "0:2",
+            "existing",
+            "function",
+            function(u32_type.clone()),
+        );
+        add_item(
+            &mut old,
+            "0:3",
+            "unchanged",
+            "function",
+            function(struct_type("0:4")),
+        );
+        add_item(
+            &mut old,
+            "0:4",
+            "Dummy",
+            "struct",
+            json!({"fields": [], "impls": ["0:9"]}),
+        );
+        let mut generic = function(json!(null));
+        generic["generics"]["params"] = json!([{"name": "T", "kind": {"type": {}}}]);
+        add_item(&mut old, "0:5", "generic", "function", generic);
+        add_item(
+            &mut old,
+            "0:6",
+            "private",
+            "function",
+            function(json!(null)),
+        );
+        old["index"]["0:6"]["visibility"] = json!("crate");
+        old["paths"]["1:0"] = json!({"crate_id": 1, "path": ["std", "fmt"], "kind": "module"});
+
+        let mut new = sample_rustdoc_json();
+        add_item(
+            &mut new,
+            "0:11",
+            "new_function",
+            "function",
+            function(json!(null)),
+        );
+        add_item(
+            &mut new,
+            "0:12",
+            "existing",
+            "function",
+            function(json!(null)),
+        );
+        // The ids of the items change between builds
+        add_item(
+            &mut new,
+            "0:13",
+            "unchanged",
+            "function",
+            function(struct_type("0:14")),
+        );
+        add_item(
+            &mut new,
+            "0:14",
+            "Dummy",
+            "struct",
+            json!({"fields": [], "impls": ["0:19"]}),
+        );
+        add_item(
+            &mut new,
+            "0:15",
+            "generic",
+            "function",
+            function(json!(null)),
+        );
+
+        (old, new)
+    }
+
+    fn parse(json: &Value) -> RustdocJson {
+        RustdocJson::parse(json.to_string().as_bytes()).unwrap()
+    }
+
+    fn store_versions(env: &TestEnvironment) -> Result<()> {
+        let (old, new) = versions();
+        for (version, json) in &[("0.1.0", old), ("0.2.0", new)] {
+            env.fake_release().name("dummy").version(version).create()?;
+            env.storage().store_one(
+                &rustdoc_json_path("dummy", version),
+                "application/json",
+                json.to_string().as_bytes(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_rustdoc_json() {
+        let (old, new) = versions();
+        let diff = diff_rustdoc_json(&parse(&old), &parse(&new));
+        assert_eq!(diff.added, vec!["fn new_function"]);
+        assert_eq!(diff.removed, vec!["fn old_function"]);
+        assert_eq!(
+            diff.changed,
+            vec![
+                ChangedItem {
+                    item: "fn existing".into(),
+                    change: "return type changed".into(),
+                },
+                ChangedItem {
+                    item: "fn generic".into(),
+                    change: "generics changed".into(),
+                },
+            ]
+        );
+
+        let diff = diff_rustdoc_json(&parse(&new), &parse(&new));
+        assert_eq!(diff, ApiDiff::default());
+    }
+
+    #[test]
+    fn test_parse_invalid_rustdoc_json() {
+        assert!(RustdocJson::parse(b"{\"index\": {}, \"paths\": {}}").is_err());
+        assert!(RustdocJson::parse(b"not json").is_err());
+    }
+
+    #[test]
+    fn api_diff_api() {
+        wrapper(|env| {
+            store_versions(env)?;
+
+            let diff: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/versions/0.1.0/diff/0.2.0")
+                .send()?
+                .json()?;
+            assert_eq!(
+                diff,
+                json!({
+                    "added": ["fn new_function"],
+                    "removed": ["fn old_function"],
+                    "changed": [
+                        {"item": "fn existing", "change": "return type changed"},
+                        {"item": "fn generic", "change": "generics changed"},
+                    ],
+                })
+            );
+
+            for path in &[
+                "/api/v1/crates/dummy/versions/0.1.0/diff/0.3.0",
+                "/api/v1/crates/missing/versions/0.1.0/diff/0.2.0",
+            ] {
+                let resp = env.frontend().get(path).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_page() {
+        wrapper(|env| {
+            store_versions(env)?;
+            env.fake_release().name("dummy").version("0.3.0").create()?;
+
+            let web = env.frontend();
+            assert_success("/crate/dummy/0.1.0/diff/0.2.0", web)?;
+            let page = web.get("/crate/dummy/0.1.0/diff/0.2.0").send()?.text()?;
+            assert!(page.contains("fn new_function"));
+            assert!(page.contains("fn old_function"));
+            assert!(page.contains("return type changed"));
+
+            // The rustdoc JSON of 0.3.0 wasn't generated
+            let page = web.get("/crate/dummy/0.1.0/diff/0.3.0").send()?.text()?;
+            assert!(page.contains("not available"));
+
+            let resp = web.get("/crate/dummy/0.1.0/diff/0.4.0").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,406 @@
+//! Archives of the documentation of the releases, to browse it offline
+
+use super::error::Nope;
+use crate::db::Pool;
+use crate::docbuilder::essential_file_names;
+use crate::storage::PathNotFoundError;
+use crate::utils::zip_writer::{ZipWriter, MAX_FILES};
+use crate::{Config, Storage};
+use iron::headers::ContentType;
+use iron::response::WriteBody;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+const ZIP_MIME: &str = "application/zip";
+
+/// The prefix the archives of a release are cached under in the storage.
+fn archives_prefix(name: &str, version: &str) -> String {
+    format!("archives/{}/{}/", name, version)
+}
+
+/// Handler for `GET /crate/:name/:version/download`.
+///
+/// Serves a zip archive of the documentation of a release, with the static files it uses, in a
+/// `<name>-<version>` directory. The archive is assembled from the storage while it's sent, and
+/// cached until the next build of the release.
+pub fn download_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let release = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.rustdoc_status,
+                    releases.doc_rustc_version,
+                    (SELECT MAX(builds.id) FROM builds WHERE builds.rid = releases.id)
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let (rustdoc_status, rustc_version, build_id): (bool, String, Option<i32>) = match release {
+        Some(row) => (row.get(0), row.get(1), row.get(2)),
+        None => return Err(Nope::VersionNotFound.into()),
+    };
+    if !rustdoc_status {
+        return Err(Nope::ResourceNotFound.into());
+    }
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    // The cached archive is replaced when the release is rebuilt
+    let cache_path = build_id.map(|id| format!("{}{}.zip", archives_prefix(name, version), id));
+    if let Some(cache_path) = &cache_path {
+        match storage.get(cache_path, config.max_archive_size) {
+            Ok(blob) => return Ok(archive_response(name, version, blob.content)),
+            Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {}
+            Err(err) => ctry!(req, Err(err)),
+        }
+    }
+
+    let prefix = format!("rustdoc/{}/{}/", name, version);
+    let files = ctry!(req, storage.list_prefix(&prefix));
+    let essential_files = ctry!(req, essential_file_names(&rustc_version));
+    let size: u64 = files.iter().map(|(_, size)| size).sum();
+    let too_large = if size > config.max_archive_size as u64 {
+        Some("is too big")
+    } else if files.len() + essential_files.len() > MAX_FILES {
+        // Checked before the archive is streamed, as it can't fail cleanly once it's started
+        Some("has too many files")
+    } else {
+        None
+    };
+    if let Some(reason) = too_large {
+        return Ok(Response::with((
+            status::PayloadTooLarge,
+            format!(
+                "the documentation of {} {} {} to be downloaded as an archive",
+                name, version, reason
+            ),
+        )));
+    }
+
+    let body = ArchiveBody {
+        storage: storage.clone(),
+        max_file_size: config.max_file_size,
+        max_archive_size: config.max_archive_size,
+        root: format!("{}-{}", name, version),
+        prefix,
+        files: files.into_iter().map(|(path, _)| path).collect(),
+        essential_files,
+        cache: cache_path.map(|path| (archives_prefix(name, version), path)),
+    };
+    let body: Box<dyn WriteBody> = Box::new(body);
+    Ok(archive_response(name, version, body))
+}
+
+fn archive_response(
+    name: &str,
+    version: &str,
+    body: impl iron::modifier::Modifier<Response>,
+) -> Response {
+    let mut resp = Response::with((status::Ok, body));
+    resp.headers.set(ContentType(ZIP_MIME.parse().unwrap()));
+    resp.headers.set_raw(
+        "Content-Disposition",
+        vec![format!("attachment; filename=\"{}-{}.zip\"", name, version).into_bytes()],
+    );
+    resp
+}
+
+/// Writes the archive of the documentation while reading the files from the storage one by one,
+/// so only one of them is kept in memory at a time.
+struct ArchiveBody {
+    storage: Arc<Storage>,
+    max_file_size: usize,
+    /// The largest archive cached, as the cached archives are read in memory to be stored and
+    /// served
+    max_archive_size: usize,
+    /// The directory the files are in inside the archive
+    root: String,
+    /// The prefix of the documentation in the storage
+    prefix: String,
+    files: Vec<String>,
+    /// The static files shared by all the releases, stored at the root of the storage
+    essential_files: Vec<String>,
+    /// The prefix of the cached archives of the release, and the path to cache this one at
+    cache: Option<(String, String)>,
+}
+
+impl ArchiveBody {
+    fn write_archive(&self, out: impl Write) -> io::Result<()> {
+        let mut zip = ZipWriter::new(out);
+        for path in &self.files {
+            let blob = self.storage.get(path, self.max_file_size).map_err(to_io)?;
+            let name = format!("{}/{}", self.root, &path[self.prefix.len()..]);
+            zip.add_file(&name, &blob.content)?;
+        }
+        for file in &self.essential_files {
+            match self.storage.get(file, self.max_file_size) {
+                Ok(blob) => zip.add_file(&format!("{}/{}", self.root, file), &blob.content)?,
+                // Very old releases use essential files which were never stored
+                Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+                    log::warn!("essential file {} is missing from the storage", file);
+                }
+                Err(err) => return Err(to_io(err)),
+            }
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    fn store_in_cache(&self, mut archive: File) -> Result<(), failure::Error> {
+        let (prefix, path) = match &self.cache {
+            Some(cache) => cache,
+            None => return Ok(()),
+        };
+        // The documentation fits, but the static files and the headers of the archive can make
+        // it bigger than the limit
+        let size = archive.metadata()?.len();
+        if size > self.max_archive_size as u64 {
+            log::warn!(
+                "not caching the archive of {}, as its {} bytes are over the limit",
+                self.root,
+                size
+            );
+            return Ok(());
+        }
+
+        let mut content = Vec::with_capacity(size as usize);
+        archive.seek(SeekFrom::Start(0))?;
+        archive.read_to_end(&mut content)?;
+
+        self.storage.delete_prefix(prefix)?;
+        self.storage.store_one(path, ZIP_MIME, &content)?;
+        Ok(())
+    }
+}
+
+impl WriteBody for ArchiveBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut cache = BufWriter::new(tempfile::tempfile()?);
+        self.write_archive(Tee(res, &mut cache))?;
+
+        let archive = cache.into_inner()?;
+        if let Err(err) = self.store_in_cache(archive) {
+            log::error!("failed to cache the archive of {}: {}", self.root, err);
+        }
+        Ok(())
+    }
+}
+
+/// Writes everything to both writers.
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+fn to_io(err: failure::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.compat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+    use rand::Rng;
+    use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+    use reqwest::StatusCode;
+    use std::io::Cursor;
+
+    const RUSTC_VERSION: &str = "rustc 2.0.0-nightly (000000000 1970-01-01)";
+    const INDEX: &str = r#"<html>
+        <link rel="stylesheet" href="../rustdoc-19700101-2.0.0-nightly-000000000.css">
+        <a href="struct.Foo.html">Foo</a>
+    </html>"#;
+
+    fn download(env: &TestEnvironment, url: &str) -> zip::ZipArchive<Cursor<Vec<u8>>> {
+        let resp = env.frontend().get(url).send().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_TYPE], ZIP_MIME);
+        assert_eq!(
+            resp.headers()[CONTENT_DISPOSITION],
+            "attachment; filename=\"dummy-0.1.0.zip\""
+        );
+        let body = resp.bytes().unwrap().to_vec();
+        zip::ZipArchive::new(Cursor::new(body)).unwrap()
+    }
+
+    fn read(archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .unwrap_or_else(|_| panic!("{} is missing from the archive", name))
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    fn fake_release(env: &TestEnvironment) -> Result<(), failure::Error> {
+        env.fake_release()
+            .name("dummy")
+            .version("0.1.0")
+            .rustc_version(RUSTC_VERSION)
+            .rustdoc_file_with("dummy/index.html", INDEX.as_bytes())
+            .rustdoc_file_with("dummy/struct.Foo.html", b"<html>Foo</html>")
+            .create()?;
+        for file in essential_file_names(RUSTC_VERSION)? {
+            env.storage()
+                .store_one(&file, "text/plain", file.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn download_documentation() {
+        wrapper(|env| {
+            fake_release(env)?;
+
+            let mut archive = download(env, "/crate/dummy/0.1.0/download");
+            let index = read(&mut archive, "dummy-0.1.0/dummy/index.html");
+            assert_eq!(index, INDEX);
+            // The files referenced by the index are next to it
+            assert_eq!(
+                read(&mut archive, "dummy-0.1.0/dummy/struct.Foo.html"),
+                "<html>Foo</html>"
+            );
+            assert_eq!(
+                read(
+                    &mut archive,
+                    "dummy-0.1.0/rustdoc-19700101-2.0.0-nightly-000000000.css"
+                ),
+                "rustdoc-19700101-2.0.0-nightly-000000000.css"
+            );
+            assert_eq!(
+                archive.len(),
+                2 + essential_file_names(RUSTC_VERSION)?.len()
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn download_cached_archive() {
+        wrapper(|env| {
+            fake_release(env)?;
+            download(env, "/crate/dummy/0.1.0/download");
+
+            let cached = env.storage().list_prefix("archives/dummy/0.1.0/")?;
+            assert_eq!(cached.len(), 1);
+            // The cached archive is still served without the files it was created from
+            env.storage()
+                .delete_prefix("rustdoc/dummy/0.1.0/dummy/struct.Foo.html")?;
+            let mut archive = download(env, "/crate/dummy/0.1.0/download");
+            assert_eq!(
+                read(&mut archive, "dummy-0.1.0/dummy/struct.Foo.html"),
+                "<html>Foo</html>"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn archive_too_big() {
+        wrapper(|env| {
+            env.override_config(|config| config.max_archive_size = 1);
+            fake_release(env)?;
+
+            let resp = env.frontend().get("/crate/dummy/0.1.0/download").send()?;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert!(env.storage().list_prefix("archives/")?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn archive_with_too_many_files() {
+        wrapper(|env| {
+            fake_release(env)?;
+            env.storage().store_blobs(
+                (0..MAX_FILES)
+                    .map(|i| crate::storage::Blob {
+                        path: format!("rustdoc/dummy/0.1.0/dummy/{}.html", i),
+                        mime: "text/html".into(),
+                        date_updated: chrono::Utc::now(),
+                        content: Vec::new(),
+                        compression: None,
+                    })
+                    .collect(),
+            )?;
+
+            let resp = env.frontend().get("/crate/dummy/0.1.0/download").send()?;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert!(env.storage().list_prefix("archives/")?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn archive_too_big_to_cache() {
+        wrapper(|env| {
+            env.override_config(|config| config.max_archive_size = 4096);
+            fake_release(env)?;
+            // Only the documentation is counted before the archive is assembled
+            let mut css = vec![0; 8192];
+            rand::thread_rng().fill(&mut css[..]);
+            env.storage().store_one(
+                "rustdoc-19700101-2.0.0-nightly-000000000.css",
+                "text/css",
+                &css,
+            )?;
+
+            let mut archive = download(env, "/crate/dummy/0.1.0/download");
+            assert_eq!(read(&mut archive, "dummy-0.1.0/dummy/index.html"), INDEX);
+            assert!(env.storage().list_prefix("archives/")?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn missing_documentation() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .build_result_successful(false)
+                .create()?;
+
+            let web = env.frontend();
+            for url in &[
+                "/crate/dummy/0.1.0/download",
+                "/crate/dummy/0.2.0/download",
+                "/crate/missing/0.1.0/download",
+            ] {
+                assert_eq!(
+                    web.get(url).send()?.status(),
+                    StatusCode::NOT_FOUND,
+                    "{}",
+                    url
+                );
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,256 @@
+//! The security advisories affecting the dependencies of releases, found by `cargo audit`
+
+use super::json_response;
+use crate::db::Pool;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+/// An advisory of the RustSec advisory database affecting a dependency of a release.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SecurityAdvisory {
+    pub(crate) advisory_id: String,
+    pub(crate) severity: Option<String>,
+    pub(crate) affected_crate: String,
+    pub(crate) affected_version: String,
+    pub(crate) patched_versions: Vec<String>,
+    pub(crate) url: String,
+}
+
+/// Returns the advisories affecting the dependencies of the release with `release_id`, as of its
+/// latest audit.
+pub(crate) fn release_advisories(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<Vec<SecurityAdvisory>, failure::Error> {
+    Ok(conn
+        .query(
+            "SELECT advisory_id, severity, affected_crate, affected_version, patched_versions
+             FROM audit_results
+             WHERE release_id = $1
+             ORDER BY advisory_id, affected_crate, affected_version",
+            &[&release_id],
+        )?
+        .into_iter()
+        .map(|row| {
+            let advisory_id: String = row.get(0);
+            SecurityAdvisory {
+                url: format!("https://rustsec.org/advisories/{}", advisory_id),
+                advisory_id,
+                severity: row.get(1),
+                affected_crate: row.get(2),
+                affected_version: row.get(3),
+                patched_versions: row.get(4),
+            }
+        })
+        .collect())
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/audit`.
+///
+/// Lists the security advisories affecting the dependencies locked by the release, and when they
+/// were last audited. Releases built before the audits were enabled are reported as not audited.
+pub fn audit_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.id, audit_runs.advisory_db_commit, audit_runs.audited_at
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             LEFT JOIN audit_runs ON audit_runs.release_id = releases.id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let row = match row {
+        Some(row) => row,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+    let audited_at: Option<DateTime<Utc>> = row
+        .get::<_, Option<NaiveDateTime>>(2)
+        .map(|audited_at| DateTime::from_utc(audited_at, Utc));
+    let advisories = ctry!(req, release_advisories(&mut conn, row.get(0)));
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "crate": name,
+            "version": version,
+            "audited": audited_at.is_some(),
+            "audited_at": audited_at,
+            "advisory_db_commit": row.get::<_, Option<String>>(1),
+            "advisories": advisories,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::add_audit_results;
+    use crate::docbuilder::AuditFinding;
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    fn finding(advisory_id: &str, affected_crate: &str) -> AuditFinding {
+        AuditFinding {
+            advisory_id: advisory_id.into(),
+            severity: Some("high".into()),
+            affected_crate: affected_crate.into(),
+            affected_version: "0.1.0".into(),
+            patched_versions: vec![">=0.2.0".into()],
+        }
+    }
+
+    #[test]
+    fn api_lists_advisories() {
+        wrapper(|env| {
+            let release_id = env.fake_release().name("dummy").version("0.1.0").create()?;
+            add_audit_results(
+                &mut env.db().conn(),
+                release_id,
+                &[
+                    finding("RUSTSEC-2021-0002", "smallvec"),
+                    finding("RUSTSEC-2020-0071", "time"),
+                ],
+                Some("0123456789abcdef0123456789abcdef01234567"),
+            )?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/audit")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(body["audited"], true);
+            assert!(body["audited_at"].is_string());
+            assert_eq!(
+                body["advisory_db_commit"],
+                "0123456789abcdef0123456789abcdef01234567"
+            );
+            assert_eq!(
+                body["advisories"],
+                json!([
+                    {
+                        "advisory_id": "RUSTSEC-2020-0071",
+                        "severity": "high",
+                        "affected_crate": "time",
+                        "affected_version": "0.1.0",
+                        "patched_versions": [">=0.2.0"],
+                        "url": "https://rustsec.org/advisories/RUSTSEC-2020-0071",
+                    },
+                    {
+                        "advisory_id": "RUSTSEC-2021-0002",
+                        "severity": "high",
+                        "affected_crate": "smallvec",
+                        "affected_version": "0.1.0",
+                        "patched_versions": [">=0.2.0"],
+                        "url": "https://rustsec.org/advisories/RUSTSEC-2021-0002",
+                    },
+                ])
+            );
+
+            // A new audit replaces the previous results
+            add_audit_results(&mut env.db().conn(), release_id, &[], None)?;
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/audit")
+                .send()?
+                .json()?;
+            assert_eq!(body["audited"], true);
+            assert_eq!(body["advisories"], json!([]));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_unaudited_and_missing_releases() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/audit")
+                .send()?
+                .json()?;
+            assert_eq!(body["audited"], false);
+            assert_eq!(body["audited_at"], Value::Null);
+            assert_eq!(body["advisories"], json!([]));
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.2.0/audit")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn advisories_warning_in_nav() {
+        wrapper(|env| {
+            let has_warning = |path: &str| -> Result<bool, failure::Error> {
+                let data = env.frontend().get(path).send()?.text()?;
+                Ok(kuchiki::parse_html()
+                    .one(data)
+                    .select("form > ul > li > .warn")
+                    .expect("invalid selector")
+                    .any(|el| el.text_contents().contains("security advisor")))
+            };
+
+            let vulnerable = env
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            let patched = env
+                .fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            add_audit_results(
+                &mut env.db().conn(),
+                vulnerable,
+                &[finding("RUSTSEC-2020-0071", "time")],
+                None,
+            )?;
+            add_audit_results(&mut env.db().conn(), patched, &[], None)?;
+
+            assert!(has_warning("/dummy/0.1.0/dummy/")?);
+            assert!(!has_warning("/dummy/0.2.0/dummy/")?);
+
+            // The warning links to the description of the advisories
+            let data = env.frontend().get("/dummy/0.1.0/dummy/").send()?.text()?;
+            let links: Vec<String> = kuchiki::parse_html()
+                .one(data)
+                .select("form > ul > li > .warn + ul a")
+                .expect("invalid selector")
+                .map(|el| el.attributes.borrow().get("href").unwrap().to_string())
+                .collect();
+            assert_eq!(
+                links,
+                vec!["https://rustsec.org/advisories/RUSTSEC-2020-0071".to_string()]
+            );
+
+            Ok(())
+        });
+    }
+}
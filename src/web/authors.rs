@@ -0,0 +1,136 @@
+//! The crates each author listed in `Cargo.toml` worked on
+
+use super::json_response;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct AuthorCrate {
+    name: String,
+    /// The latest version of the crate
+    version: String,
+    description: Option<String>,
+    role: String,
+}
+
+/// Handler for `GET /api/v1/authors/:name/crates`.
+///
+/// `name` is the slug of the author, as linked from the crate pages. Lists every crate any
+/// release of which lists the author, sorted by name.
+pub fn author_crates_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let slug = cexpect!(req, router.find("name"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let author = ctry!(
+        req,
+        conn.query_opt("SELECT id, name FROM authors WHERE slug = $1", &[&slug])
+    );
+    let author = match author {
+        Some(author) => author,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("author {} not found", slug) }),
+            ))
+        }
+    };
+
+    let crates: Vec<AuthorCrate> = ctry!(
+        req,
+        conn.query(
+            "SELECT crates.name, releases.version, releases.description, crate_authors.role
+             FROM crate_authors
+             INNER JOIN crates ON crates.id = crate_authors.crate_id
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             WHERE crate_authors.author_id = $1
+             ORDER BY crates.name",
+            &[&author.get::<_, i32>("id")],
+        )
+    )
+    .into_iter()
+    .map(|row| AuthorCrate {
+        name: row.get(0),
+        version: row.get(1),
+        description: row.get(2),
+        role: row.get(3),
+    })
+    .collect();
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "name": author.get::<_, Option<String>>("name"),
+            "crates": crates,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn api_lists_author_crates() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .author("Fake Person <fake@example.com>")
+                .create()?;
+            // Crates the author only worked on in previous releases are included too
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .author("Fake Person <fake@example.com>")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.2.0")
+                .author("Other Person")
+                .description("the latest bar")
+                .create()?;
+            env.fake_release()
+                .name("baz")
+                .version("0.1.0")
+                .author("Other Person")
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/authors/fake-person/crates")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(body["name"], "Fake Person");
+            assert_eq!(
+                body["crates"],
+                json!([
+                    {
+                        "name": "bar",
+                        "version": "0.2.0",
+                        "description": "the latest bar",
+                        "role": "author",
+                    },
+                    {
+                        "name": "foo",
+                        "version": "0.1.0",
+                        "description": "Fake package",
+                        "role": "author",
+                    },
+                ])
+            );
+            assert!(!body.to_string().contains("fake@example.com"));
+
+            let resp = env.frontend().get("/api/v1/authors/nobody/crates").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
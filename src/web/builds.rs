@@ -24,12 +24,21 @@ pub(crate) struct Build {
     output: Option<String>,
 }
 
+/// The release was queued again automatically after a toolchain update.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ToolchainRequeue {
+    old_rustc_version: String,
+    new_rustc_version: String,
+    requeued_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct BuildsPage {
     metadata: MetaData,
     builds: Vec<Build>,
     build_details: Option<Build>,
     limits: Limits,
+    toolchain_requeue: Option<ToolchainRequeue>,
 }
 
 impl_webpage! {
@@ -110,12 +119,58 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
 
         Ok(resp)
     } else {
+        let toolchain_requeue = ctry!(
+            req,
+            conn.query_opt(
+                "SELECT old_rustc_version, new_rustc_version, requeued_at
+                 FROM toolchain_requeues
+                 WHERE name = $1 AND version = $2
+                 ORDER BY requeued_at DESC
+                 LIMIT 1",
+                &[&name, &version]
+            )
+        )
+        .map(|row| ToolchainRequeue {
+            old_rustc_version: row.get("old_rustc_version"),
+            new_rustc_version: row.get("new_rustc_version"),
+            requeued_at: DateTime::from_utc(row.get::<_, NaiveDateTime>("requeued_at"), Utc),
+        });
+
         BuildsPage {
             metadata: cexpect!(req, MetaData::from_crate(&mut conn, &name, &version)),
             builds,
             build_details,
             limits,
+            toolchain_requeue,
         }
         .into_response(req)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+
+    #[test]
+    fn toolchain_requeue_is_shown() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let page = |path| -> Result<String, failure::Error> {
+                Ok(env.frontend().get(path).send()?.text()?)
+            };
+            assert!(!page("/crate/foo/0.1.0/builds")?.contains("automatically queued"));
+
+            env.db().conn().execute(
+                "INSERT INTO toolchain_requeues (name, version, old_rustc_version, new_rustc_version)
+                 VALUES ('foo', '0.1.0', 'rustc old', 'rustc new');",
+                &[],
+            )?;
+            let body = page("/crate/foo/0.1.0/builds")?;
+            assert!(body.contains("automatically queued"));
+            assert!(body.contains("rustc new"));
+
+            Ok(())
+        });
+    }
+}
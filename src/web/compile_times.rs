@@ -0,0 +1,245 @@
+//! How long compiling the releases and their dependencies takes
+
+use super::json_response;
+use crate::db::Pool;
+use crate::docbuilder::CompileTiming;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// The number of dependencies listed by the endpoints.
+const SLOWEST_LIMIT: i64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct SlowDependency {
+    name: String,
+    /// The number of crates whose latest build compiled the dependency
+    crates: i64,
+    average_secs: f64,
+    total_secs: f64,
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/compile-times`.
+///
+/// Returns the total time spent compiling the release and its dependencies, and the dependencies
+/// that took the longest, with the features they were compiled with.
+pub fn compile_times_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.compile_timings
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let compile_timings: Option<Value> = match row {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+    let timings: Vec<CompileTiming> = match compile_timings {
+        Some(timings) => ctry!(req, serde_json::from_value(timings)),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({
+                    "error": format!("no compile times were recorded for {} {}", name, version)
+                }),
+            ))
+        }
+    };
+
+    let total_secs: f64 = timings.iter().map(|timing| timing.duration_secs).sum();
+    let (own, dependencies): (Vec<_>, Vec<_>) = timings
+        .into_iter()
+        .partition(|timing| timing.name == name && timing.version == version);
+    let slowest: Vec<_> = dependencies
+        .into_iter()
+        .take(SLOWEST_LIMIT as usize)
+        .collect();
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "total_secs": total_secs,
+            "crate_secs": own.first().map(|timing| timing.duration_secs),
+            "slowest_dependencies": slowest,
+        }),
+    ))
+}
+
+/// Handler for `GET /api/v1/compile-times/slowest`.
+///
+/// Aggregates the compile times of the latest build of every crate, to find the dependencies
+/// slowing down the builds of the whole ecosystem.
+pub fn slowest_dependencies_api_handler(req: &mut Request) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+    let slowest: Vec<SlowDependency> = ctry!(
+        req,
+        conn.query(
+            "SELECT
+                timing->>'name' AS name,
+                COUNT(*) AS crates,
+                AVG((timing->>'duration_secs')::FLOAT8) AS average_secs,
+                SUM((timing->>'duration_secs')::FLOAT8) AS total_secs
+             FROM (
+                SELECT DISTINCT ON (releases.crate_id)
+                    crates.name AS crate_name,
+                    releases.compile_timings
+                FROM releases
+                INNER JOIN crates ON crates.id = releases.crate_id
+                WHERE releases.compile_timings IS NOT NULL
+                ORDER BY releases.crate_id, releases.release_time DESC
+             ) AS latest
+             CROSS JOIN jsonb_array_elements(latest.compile_timings) AS timing
+             WHERE timing->>'name' <> latest.crate_name
+             GROUP BY timing->>'name'
+             ORDER BY total_secs DESC, name
+             LIMIT $1",
+            &[&SLOWEST_LIMIT],
+        )
+    )
+    .into_iter()
+    .map(|row| SlowDependency {
+        name: row.get("name"),
+        crates: row.get("crates"),
+        average_secs: row.get("average_secs"),
+        total_secs: row.get("total_secs"),
+    })
+    .collect();
+
+    Ok(json_response(
+        status::Ok,
+        json!({ "slowest_dependencies": slowest }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docbuilder::CompileTiming;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    fn timing(name: &str, version: &str, duration_secs: f64) -> CompileTiming {
+        CompileTiming {
+            name: name.into(),
+            version: version.into(),
+            features: vec!["default".into()],
+            duration_secs,
+        }
+    }
+
+    #[test]
+    fn api_compile_times() {
+        wrapper(|env| {
+            let mut timings = vec![timing("dummy", "0.1.0", 1.5)];
+            for i in 0..12 {
+                timings.push(timing(&format!("dep{:02}", i), "1.0.0", 20.0 - i as f64));
+            }
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .compile_timings(timings)
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/compile-times")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(body["total_secs"], json!(175.5));
+            assert_eq!(body["crate_secs"], json!(1.5));
+            let slowest = body["slowest_dependencies"].as_array().unwrap();
+            assert_eq!(slowest.len(), 10);
+            assert_eq!(
+                slowest[0],
+                json!({
+                    "name": "dep00",
+                    "version": "1.0.0",
+                    "features": ["default"],
+                    "duration_secs": 20.0,
+                })
+            );
+            assert_eq!(slowest[9]["name"], "dep09");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_compile_times_missing() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            for path in &[
+                "/api/v1/crates/dummy/0.1.0/compile-times",
+                "/api/v1/crates/dummy/0.2.0/compile-times",
+            ] {
+                let resp = env.frontend().get(path).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_slowest_dependencies() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .compile_timings(vec![
+                    timing("foo", "0.1.0", 50.0),
+                    timing("syn", "1.0.0", 1.0),
+                ])
+                .create()?;
+            // Only the latest release of each crate is counted
+            env.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .compile_timings(vec![
+                    timing("foo", "0.2.0", 50.0),
+                    timing("syn", "1.0.0", 10.0),
+                    timing("libc", "0.2.0", 2.0),
+                ])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .compile_timings(vec![
+                    timing("bar", "0.1.0", 1.0),
+                    timing("syn", "1.0.0", 6.0),
+                ])
+                .create()?;
+
+            let resp = env.frontend().get("/api/v1/compile-times/slowest").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({
+                    "slowest_dependencies": [
+                        {"name": "syn", "crates": 2, "average_secs": 8.0, "total_secs": 16.0},
+                        {"name": "libc", "crates": 1, "average_secs": 2.0, "total_secs": 2.0},
+                    ],
+                })
+            );
+
+            Ok(())
+        });
+    }
+}
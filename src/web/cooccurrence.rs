@@ -0,0 +1,150 @@
+//! The crates most often depended on together with a crate
+
+use super::json_response;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde_json::json;
+
+/// The number of crates returned when the request doesn't specify one.
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+
+/// Handler for `GET /api/v1/crates/:name/co-occurrence?limit=:limit`.
+///
+/// Returns the crates most often found next to the crate in the normal dependencies of the
+/// latest releases, with the number of crates depending on both. The counts are updated by a
+/// periodic job of the daemon.
+pub fn cooccurrence_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name")).to_string();
+
+    let url: iron::url::Url = req.url.clone().into();
+    let limit = match url
+        .query_pairs()
+        .find(|(key, _)| key == "limit")
+        .map(|(_, limit)| limit.parse::<i64>())
+    {
+        None => DEFAULT_LIMIT,
+        Some(Ok(limit)) if (1..=MAX_LIMIT).contains(&limit) => limit,
+        Some(_) => {
+            return Ok(json_response(
+                status::BadRequest,
+                json!({
+                    "error": format!("invalid limit, expected a number from 1 to {}", MAX_LIMIT)
+                }),
+            ))
+        }
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    let exists = ctry!(
+        req,
+        conn.query_opt("SELECT 1 FROM crates WHERE name = $1", &[&name])
+    )
+    .is_some();
+    if !exists {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("crate {} not found", name) }),
+        ));
+    }
+
+    let crates: Vec<_> = ctry!(
+        req,
+        conn.query(
+            "SELECT other, count
+             FROM crate_cooccurrence
+             WHERE name = $1
+             ORDER BY count DESC, other ASC
+             LIMIT $2",
+            &[&name, &limit],
+        )
+    )
+    .into_iter()
+    .map(|row| {
+        json!({
+            "name": row.get::<_, String>(0),
+            "count": row.get::<_, i32>(1),
+        })
+    })
+    .collect();
+
+    Ok(json_response(
+        status::Ok,
+        json!({ "name": name, "crates": crates }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use crate::utils::update_crate_cooccurrence;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn cooccurrence_api() {
+        wrapper(|env| {
+            for (name, dependencies) in &[
+                ("one", &["serde", "log", "rand"][..]),
+                ("two", &["serde", "log"][..]),
+                ("three", &["serde", "rand", "log"][..]),
+                ("four", &["serde", "regex"][..]),
+            ] {
+                let mut release = env.fake_release().name(name).version("1.0.0");
+                for dependency in dependencies.iter() {
+                    release = release.add_dependency(dependency, "1", None);
+                }
+                release.create()?;
+            }
+            env.fake_release().name("serde").version("1.0.0").create()?;
+            update_crate_cooccurrence(&mut env.db().conn())?;
+
+            let get = |url: &str| -> Result<(StatusCode, Value), failure::Error> {
+                let response = env.frontend().get(url).send()?;
+                Ok((response.status(), response.json()?))
+            };
+            let (status, body) = get("/api/v1/crates/serde/co-occurrence")?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body,
+                json!({
+                    "name": "serde",
+                    "crates": [
+                        // Every fake release depends on it
+                        {"name": "fake-dependency", "count": 4},
+                        {"name": "log", "count": 3},
+                        {"name": "rand", "count": 2},
+                        {"name": "regex", "count": 1},
+                    ],
+                })
+            );
+
+            let (_, body) = get("/api/v1/crates/serde/co-occurrence?limit=2")?;
+            assert_eq!(
+                body["crates"],
+                json!([{"name": "fake-dependency", "count": 4}, {"name": "log", "count": 3}])
+            );
+
+            // Crates nothing depends on have no co-occurring crates
+            let (status, body) = get("/api/v1/crates/one/co-occurrence")?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["crates"], json!([]));
+
+            for url in &[
+                "/api/v1/crates/serde/co-occurrence?limit=0",
+                "/api/v1/crates/serde/co-occurrence?limit=101",
+                "/api/v1/crates/serde/co-occurrence?limit=many",
+            ] {
+                assert_eq!(get(url)?.0, StatusCode::BAD_REQUEST, "{}", url);
+            }
+            assert_eq!(
+                get("/api/v1/crates/missing/co-occurrence")?.0,
+                StatusCode::NOT_FOUND
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,176 @@
+//! The documentation coverage of releases, and whether it regressed since the previous version
+
+use super::json_response;
+use crate::db::coverage_regression::release_coverage_regression;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde_json::json;
+
+/// Handler for `GET /api/v1/crates/:name/:version/coverage`.
+///
+/// Returns how many items of the release are documented, and the coverage regression compared to
+/// the previous version if it dropped significantly. The items are `null` for releases built
+/// without coverage data.
+pub fn coverage_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.id, doc_coverage.total_items, doc_coverage.documented_items
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let row = match row {
+        Some(row) => row,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+    let total_items: Option<i32> = row.get(1);
+    let documented_items: Option<i32> = row.get(2);
+    let percent = match (documented_items, total_items) {
+        (Some(documented), Some(total)) if total > 0 => {
+            Some(documented as f32 * 100.0 / total as f32)
+        }
+        _ => None,
+    };
+    let regression = ctry!(req, release_coverage_regression(&mut conn, row.get(0)));
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "crate": name,
+            "version": version,
+            "total_items": total_items,
+            "documented_items": documented_items,
+            "percent": percent,
+            "regression": regression,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn api_reports_regressions() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .coverage(9, 10, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .coverage(3, 4, 0, 0)
+                .create()?;
+
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.2.0/coverage")
+                .send()?
+                .json()?;
+            assert_eq!(body["total_items"], 4);
+            assert_eq!(body["documented_items"], 3);
+            assert_eq!(body["percent"], 75.0);
+            assert_eq!(
+                body["regression"],
+                json!({
+                    "previous_version": "0.1.0",
+                    "previous_percent": 90.0,
+                    "new_version": "0.2.0",
+                    "new_percent": 75.0,
+                })
+            );
+
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/coverage")
+                .send()?
+                .json()?;
+            assert_eq!(body["regression"], Value::Null);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_without_coverage_and_missing_releases() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/coverage")
+                .send()?
+                .json()?;
+            assert_eq!(body["total_items"], Value::Null);
+            assert_eq!(body["percent"], Value::Null);
+            assert_eq!(body["regression"], Value::Null);
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.2.0/coverage")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn regression_warning_in_nav() {
+        wrapper(|env| {
+            let has_warning = |path: &str| -> Result<bool, failure::Error> {
+                let data = env.frontend().get(path).send()?.text()?;
+                Ok(kuchiki::parse_html()
+                    .one(data)
+                    .select("form > ul > li > .warn")
+                    .expect("invalid selector")
+                    .any(|el| el.text_contents().contains("less documented")))
+            };
+
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .coverage(10, 10, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .rustdoc_file("dummy/index.html")
+                .coverage(5, 10, 0, 0)
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.3.0")
+                .rustdoc_file("dummy/index.html")
+                .coverage(6, 10, 0, 0)
+                .create()?;
+
+            assert!(!has_warning("/dummy/0.1.0/dummy/")?);
+            assert!(has_warning("/dummy/0.2.0/dummy/")?);
+            assert!(!has_warning("/dummy/0.3.0/dummy/")?);
+
+            Ok(())
+        });
+    }
+}
@@ -1,6 +1,8 @@
+use super::crate_metrics::{sparkline_points, DEFAULT_PERIOD_DAYS};
 use super::{match_version, redirect_base, render_markdown, MatchSemver, MetaData};
+use crate::db::crate_metrics::{crate_metrics, Granularity};
 use crate::{db::Pool, impl_webpage, web::page::WebPage};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use iron::prelude::*;
 use iron::Url;
 use postgres::Client;
@@ -42,10 +44,18 @@ pub struct CrateDetails {
     documented_items: Option<f32>,
     total_items_needing_examples: Option<f32>,
     items_with_examples: Option<f32>,
+    /// Time spent compiling the crate and its dependencies, if it was recorded
+    compile_time_secs: Option<f64>,
     /// Database id for this crate
     pub(crate) crate_id: i32,
     /// Database id for this release
     pub(crate) release_id: i32,
+    /// Whether the math of the documentation is rendered, as the crate opted in
+    pub(crate) math: bool,
+    /// How many warnings rustdoc reported about the intra-doc links, if they were recorded
+    doc_warning_count: Option<i32>,
+    /// The ids of the advisories affecting the dependencies, as of the latest audit
+    pub(crate) security_advisories: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -87,7 +97,8 @@ impl CrateDetails {
                 releases.description,
                 releases.authors,
                 releases.dependencies,
-                releases.readme,
+                -- The README of the repository, if enabled, for the releases without one
+                COALESCE(releases.readme, github_repos.github_readme) AS readme,
                 releases.description_long,
                 releases.release_time,
                 releases.build_status,
@@ -105,13 +116,27 @@ impl CrateDetails {
                 releases.is_library,
                 releases.yanked,
                 releases.doc_targets,
+                releases.failed_targets,
+                releases.is_proc_macro,
+                releases.math,
+                releases.doc_warning_count,
                 releases.license,
                 releases.documentation_url,
                 releases.default_target,
                 doc_coverage.total_items,
                 doc_coverage.documented_items,
                 doc_coverage.total_items_needing_examples,
-                doc_coverage.items_with_examples
+                doc_coverage.items_with_examples,
+                (
+                    SELECT SUM((timing->>'duration_secs')::FLOAT8)
+                    FROM jsonb_array_elements(releases.compile_timings) AS timing
+                ) AS compile_time_secs,
+                ARRAY(
+                    SELECT DISTINCT advisory_id
+                    FROM audit_results
+                    WHERE audit_results.release_id = releases.id
+                    ORDER BY advisory_id
+                ) AS security_advisories
             FROM releases
             INNER JOIN crates ON releases.crate_id = crates.id
             LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
@@ -163,7 +188,9 @@ impl CrateDetails {
             target_name: krate.get("target_name"),
             default_target: krate.get("default_target"),
             doc_targets: MetaData::parse_doc_targets(krate.get("doc_targets")),
+            failed_targets: krate.get("failed_targets"),
             yanked: krate.get("yanked"),
+            is_proc_macro: krate.get("is_proc_macro"),
         };
 
         let documented_items: Option<i32> = krate.get("documented_items");
@@ -200,8 +227,12 @@ impl CrateDetails {
             total_items: total_items.map(|v| v as f32),
             total_items_needing_examples: total_items_needing_examples.map(|v| v as f32),
             items_with_examples: items_with_examples.map(|v| v as f32),
+            compile_time_secs: krate.get("compile_time_secs"),
             crate_id,
             release_id,
+            math: krate.get("math"),
+            doc_warning_count: krate.get("doc_warning_count"),
+            security_advisories: krate.get("security_advisories"),
         };
 
         // get authors
@@ -289,6 +320,8 @@ fn map_to_release(conn: &mut Client, crate_id: i32, version: semver::Version) ->
 #[derive(Debug, Clone, PartialEq, Serialize)]
 struct CrateDetailsPage {
     details: CrateDetails,
+    /// The points of the sparkline of the weekly stars of the last year
+    stars_sparkline: Option<String>,
 }
 
 impl_webpage! {
@@ -307,7 +340,24 @@ pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
         MatchSemver::Exact((version, _)) => {
             let details = cexpect!(req, CrateDetails::new(&mut conn, &name, &version));
 
-            CrateDetailsPage { details }.into_response(req)
+            let to = Utc::today().naive_utc();
+            let metrics = ctry!(
+                req,
+                crate_metrics(
+                    &mut conn,
+                    name,
+                    to - Duration::days(DEFAULT_PERIOD_DAYS),
+                    to,
+                    Granularity::Weekly
+                )
+            );
+            let stars_sparkline = sparkline_points(&metrics.stars);
+
+            CrateDetailsPage {
+                details,
+                stars_sparkline,
+            }
+            .into_response(req)
         }
 
         MatchSemver::Semver((version, _)) => {
@@ -673,6 +723,7 @@ mod tests {
                     avatar: "https://example.org/foobar".into(),
                     name: "Foo Bar".into(),
                     email: "foobar@example.org".into(),
+                    kind: "user".into(),
                 })
                 .create()?;
 
@@ -691,12 +742,14 @@ mod tests {
                     avatar: "https://example.org/foobarv2".into(),
                     name: "Foo Bar".into(),
                     email: "foobar@example.org".into(),
+                    kind: "user".into(),
                 })
                 .add_owner(CrateOwner {
                     login: "barfoo".into(),
                     avatar: "https://example.org/barfoo".into(),
                     name: "Bar Foo".into(),
                     email: "foobar@example.org".into(),
+                    kind: "user".into(),
                 })
                 .create()?;
 
@@ -720,6 +773,7 @@ mod tests {
                     avatar: "https://example.org/barfoo".into(),
                     name: "Bar Foo".into(),
                     email: "foobar@example.org".into(),
+                    kind: "user".into(),
                 })
                 .create()?;
 
@@ -738,6 +792,7 @@ mod tests {
                     avatar: "https://example.org/barfoov2".into(),
                     name: "Bar Foo".into(),
                     email: "foobar@example.org".into(),
+                    kind: "user".into(),
                 })
                 .create()?;
 
@@ -878,4 +933,113 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn stars_sparkline() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .github_stats("rust-lang/dummy", 10, 2, 3)
+                .create()?;
+
+            let sparkline = |env: &crate::test::TestEnvironment| -> Result<_, Error> {
+                let page = kuchiki::parse_html()
+                    .one(env.frontend().get("/crate/dummy/0.1.0").send()?.text()?);
+                Ok(page
+                    .select_first("svg.sparkline polyline")
+                    .ok()
+                    .and_then(|polyline| {
+                        polyline.attributes.borrow().get("points").map(Into::into)
+                    }))
+            };
+
+            // A single week of stars isn't enough to draw a line
+            let mut conn = env.db().conn();
+            conn.execute(
+                "INSERT INTO crate_metrics_snapshots (crate_id, date, stars)
+                 SELECT id, CURRENT_DATE - 14, 5 FROM crates WHERE name = 'dummy'",
+                &[],
+            )?;
+            assert_eq!(sparkline(env)?, None::<String>);
+
+            conn.execute(
+                "INSERT INTO crate_metrics_snapshots (crate_id, date, stars)
+                 SELECT id, CURRENT_DATE, 10 FROM crates WHERE name = 'dummy'",
+                &[],
+            )?;
+            assert_eq!(sparkline(env)?.as_deref(), Some("0.0,20.0 100.0,0.0"));
+
+            Ok(())
+        });
+    }
+    #[test]
+    fn compile_time_estimate() {
+        wrapper(|env| {
+            let timing = |name: &str, duration_secs| crate::docbuilder::CompileTiming {
+                name: name.into(),
+                version: "0.1.0".into(),
+                features: Vec::new(),
+                duration_secs,
+            };
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .compile_timings(vec![timing("dummy", 1.5), timing("dep", 2.25)])
+                .create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+
+            let estimate = |version: &str| -> Result<_, Error> {
+                let page = kuchiki::parse_html().one(
+                    env.frontend()
+                        .get(&format!("/crate/dummy/{}", version))
+                        .send()?
+                        .text()?,
+                );
+                Ok(page
+                    .select_first(".compile-time")
+                    .ok()
+                    .map(|node| node.text_contents()))
+            };
+            assert_eq!(estimate("0.1.0")?.as_deref(), Some("~4s"));
+            assert_eq!(estimate("0.2.0")?, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn doc_warning_count() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .doc_warning("Gadget", "src/lib.rs:3:66")
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .no_doc_warnings()
+                .create()?;
+            env.fake_release().name("dummy").version("0.3.0").create()?;
+
+            let badge = |version: &str| -> Result<_, Error> {
+                let page = kuchiki::parse_html().one(
+                    env.frontend()
+                        .get(&format!("/crate/dummy/{}", version))
+                        .send()?
+                        .text()?,
+                );
+                Ok(page.select_first(".doc-warnings").ok().map(|node| {
+                    let warned = node.attributes.borrow().get("class") == Some("doc-warnings warn");
+                    (node.text_contents(), warned)
+                }))
+            };
+            assert_eq!(badge("0.1.0")?, Some(("1".into(), true)));
+            assert_eq!(badge("0.2.0")?, Some(("0".into(), false)));
+            assert_eq!(badge("0.3.0")?, None);
+
+            Ok(())
+        });
+    }
 }
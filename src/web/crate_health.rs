@@ -0,0 +1,97 @@
+//! The health scores of the crates
+
+use super::json_response;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde_json::{json, Value};
+
+/// Handler for `GET /api/v1/crates/:name/health`.
+///
+/// Returns the health score of the crate and the signals it's computed from, as of the last run
+/// of the health score updater. Both are `null` for the crates it didn't score yet.
+pub fn crate_health_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = match ctry!(
+        req,
+        conn.query_opt(
+            "SELECT name, health_score, health_score_breakdown FROM crates WHERE name = $1",
+            &[&name],
+        )
+    ) {
+        Some(row) => row,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("crate {} not found", name) }),
+            ))
+        }
+    };
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "name": row.get::<_, String>("name"),
+            "score": row.get::<_, Option<f64>>("health_score"),
+            "breakdown": row.get::<_, Option<Value>>("health_score_breakdown"),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use crate::utils::update_health_scores;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn crate_health() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("1.0.0")
+                .coverage(10, 10, 0, 0)
+                .source_file("tests/it.rs", b"#[test] fn it() {}")
+                .create()?;
+
+            // Not scored yet
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/health")
+                .send()?
+                .json()?;
+            assert_eq!(
+                body,
+                json!({ "name": "dummy", "score": null, "breakdown": null })
+            );
+
+            update_health_scores(&mut *env.db().conn())?;
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/dummy/health")
+                .send()?
+                .json()?;
+            assert_eq!(body["score"], 0.5);
+            assert_eq!(
+                body["breakdown"],
+                json!({
+                    "documentation": 1.0,
+                    "tests": 1.0,
+                    "last_commit": 0.0,
+                    "ci": 0.0,
+                    "not_archived": 0.0,
+                    "closed_issues": 0.0,
+                })
+            );
+
+            let resp = env.frontend().get("/api/v1/crates/missing/health").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
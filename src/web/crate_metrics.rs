@@ -0,0 +1,219 @@
+//! The metrics of the crates over time
+
+use super::json_response;
+use crate::db::crate_metrics::{crate_metrics, Granularity};
+use crate::db::Pool;
+use chrono::{Duration, NaiveDate, Utc};
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde_json::json;
+
+/// The period returned when the request doesn't specify one.
+pub(super) const DEFAULT_PERIOD_DAYS: i64 = 365;
+
+/// The range and granularity of the metrics requested in the query string.
+#[derive(Debug, PartialEq)]
+struct MetricsQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    granularity: Granularity,
+}
+
+fn metrics_query(req: &Request, today: NaiveDate) -> Result<MetricsQuery, String> {
+    let url: iron::url::Url = req.url.clone().into();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let date = |name: &str| {
+        param(name)
+            .map(|value| {
+                value
+                    .parse::<NaiveDate>()
+                    .map_err(|_| format!("invalid {} date, expected YYYY-MM-DD", name))
+            })
+            .transpose()
+    };
+
+    let to = date("to")?.unwrap_or(today);
+    let from = date("from")?.unwrap_or_else(|| to - Duration::days(DEFAULT_PERIOD_DAYS));
+    if from > to {
+        return Err("the from date is after the to date".into());
+    }
+    let granularity = match param("granularity") {
+        Some(granularity) => granularity.parse().map_err(|err| format!("{}", err))?,
+        None => Granularity::Weekly,
+    };
+
+    Ok(MetricsQuery {
+        from,
+        to,
+        granularity,
+    })
+}
+
+/// The size of the sparklines, whose `viewBox` in `crate/details.html` is padded for the stroke.
+const SPARKLINE_WIDTH: f32 = 100.0;
+const SPARKLINE_HEIGHT: f32 = 20.0;
+
+/// Returns the points of an SVG polyline charting `values`, which are spread over the width of
+/// the sparkline and scaled between their minimum and maximum. Missing values are skipped, and
+/// `None` is returned when there aren't at least two values to chart.
+pub(super) fn sparkline_points(values: &[Option<i32>]) -> Option<String> {
+    let values: Vec<i32> = values.iter().filter_map(|value| *value).collect();
+    if values.len() < 2 {
+        return None;
+    }
+
+    let min = *values.iter().min()?;
+    let max = *values.iter().max()?;
+    let range = (max - min).max(1) as f32;
+    let step = SPARKLINE_WIDTH / (values.len() - 1) as f32;
+
+    let points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            let x = idx as f32 * step;
+            let y = SPARKLINE_HEIGHT - (value - min) as f32 * SPARKLINE_HEIGHT / range;
+            format!("{:.1},{:.1}", x, y)
+        })
+        .collect();
+    Some(points.join(" "))
+}
+
+/// Handler for `GET /api/v1/crates/:name/metrics`.
+///
+/// Returns the stars, forks, open issues, documentation coverage and build time of the crate
+/// over time, as arrays with one value per period. The `from` and `to` dates default to the last
+/// year, and the `granularity` can be `daily`, `weekly` (the default) or `monthly`.
+pub fn crate_metrics_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+
+    let query = match metrics_query(req, Utc::today().naive_utc()) {
+        Ok(query) => query,
+        Err(error) => return Ok(json_response(status::BadRequest, json!({ "error": error }))),
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    if ctry!(
+        req,
+        conn.query_opt("SELECT id FROM crates WHERE name = $1", &[&name])
+    )
+    .is_none()
+    {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("crate {} not found", name) }),
+        ));
+    }
+
+    let metrics = ctry!(
+        req,
+        crate_metrics(&mut conn, name, query.from, query.to, query.granularity)
+    );
+
+    Ok(json_response(status::Ok, json!(metrics)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sparkline_points;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_sparkline_points() {
+        assert_eq!(sparkline_points(&[]), None);
+        assert_eq!(sparkline_points(&[Some(1), None]), None);
+        assert_eq!(
+            sparkline_points(&[Some(10), None, Some(15), Some(20)]).as_deref(),
+            Some("0.0,20.0 50.0,10.0 100.0,0.0")
+        );
+        assert_eq!(
+            sparkline_points(&[Some(3), Some(3)]).as_deref(),
+            Some("0.0,20.0 100.0,20.0")
+        );
+    }
+
+    #[test]
+    fn serves_metrics() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").create()?;
+            let mut conn = env.db().conn();
+            for (date, stars, build_time) in &[
+                ("2024-01-01", 10, 20.0f32),
+                ("2024-01-03", 12, 40.0),
+                ("2024-01-10", 15, 10.0),
+                ("2025-01-01", 20, 10.0),
+            ] {
+                conn.execute(
+                    "INSERT INTO crate_metrics_snapshots (crate_id, date, stars, build_time_seconds)
+                     SELECT id, $1::TEXT::DATE, $2, $3 FROM crates WHERE name = 'dummy'",
+                    &[date, stars, build_time],
+                )?;
+            }
+
+            let resp = env
+                .frontend()
+                .get(
+                    "/api/v1/crates/dummy/metrics?from=2024-01-01&to=2024-12-31&granularity=weekly",
+                )
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(body["granularity"], "weekly");
+            assert_eq!(body["dates"], json!(["2024-01-01", "2024-01-08"]));
+            assert_eq!(body["stars"], json!([12, 15]));
+            assert_eq!(body["forks"], json!([null, null]));
+            assert_eq!(body["build_time_seconds"], json!([30.0, 10.0]));
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/metrics?from=2024-01-01&granularity=monthly")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(body["dates"], json!(["2024-01-01", "2025-01-01"]));
+            assert_eq!(body["stars"], json!([15, 20]));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_metrics_requests() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").create()?;
+
+            for (path, expected) in &[
+                ("/api/v1/crates/missing/metrics", StatusCode::NOT_FOUND),
+                (
+                    "/api/v1/crates/dummy/metrics?from=yesterday",
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    "/api/v1/crates/dummy/metrics?to=2024-13-01",
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    "/api/v1/crates/dummy/metrics?from=2024-02-01&to=2024-01-01",
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    "/api/v1/crates/dummy/metrics?granularity=hourly",
+                    StatusCode::BAD_REQUEST,
+                ),
+                ("/api/v1/crates/dummy/metrics", StatusCode::OK),
+            ] {
+                let resp = env.frontend().get(path).send()?;
+                assert_eq!(resp.status(), *expected, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,272 @@
+//! The licenses of the transitive dependencies of a release
+
+use super::dependency_tree::{resolve_dependency_tree, resolve_dependency_tree_with_dev};
+use super::{json_response, match_version};
+use crate::db::Pool;
+use crate::utils::{is_restrictive, license_alternatives};
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// Why the license of a dependency needs a closer look
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum LicenseFlag {
+    /// A strong copyleft license like the GPL, AGPL or SSPL, without a permissive alternative
+    Restrictive,
+    /// A missing license, or one that isn't an SPDX expression
+    Unrecognized,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct DependencyLicense {
+    #[serde(rename = "crate")]
+    krate: String,
+    version: String,
+    license: Option<String>,
+    is_dev: bool,
+    flag: Option<LicenseFlag>,
+}
+
+fn license_flag(license: Option<&str>) -> Option<LicenseFlag> {
+    match license.and_then(license_alternatives) {
+        Some(alternatives) if is_restrictive(&alternatives) => Some(LicenseFlag::Restrictive),
+        Some(_) => None,
+        None => Some(LicenseFlag::Unrecognized),
+    }
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/dependencies/licenses`.
+///
+/// Lists the licenses of the normal dependencies of the release, direct and transitive, resolved
+/// like the dependency tree of its SBOM. With `?include_dev=true`, the development dependencies
+/// and the crates they need are listed too. The dependencies are also grouped by license, and the
+/// ones with a restrictive or unrecognized license are flagged. The dependencies unknown to
+/// docs.rs are listed in `unresolved`, and `truncated` tells if the tree was too large to resolve.
+pub fn dependency_licenses_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let url: iron::url::Url = req.url.clone().into();
+    let include_dev = url
+        .query_pairs()
+        .any(|(key, value)| key == "include_dev" && value == "true");
+
+    let mut conn = extension!(req, Pool).get()?;
+    let (version, release_id) = match match_version(&mut conn, name, Some(version))
+        .and_then(|matched| matched.assume_exact())
+    {
+        Ok(matched) => matched.into_parts(),
+        Err(_) => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+
+    let tree = if include_dev {
+        ctry!(req, resolve_dependency_tree_with_dev(&mut conn, release_id))
+    } else {
+        ctry!(req, resolve_dependency_tree(&mut conn, release_id))
+    };
+
+    let dependencies: Vec<DependencyLicense> = tree
+        .crates
+        .into_iter()
+        .skip(1)
+        .map(|krate| DependencyLicense {
+            flag: license_flag(krate.license.as_deref()),
+            krate: krate.name,
+            version: krate.version,
+            license: krate.license,
+            is_dev: krate.is_dev,
+        })
+        .collect();
+
+    let mut by_license: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for dependency in &dependencies {
+        by_license
+            .entry(dependency.license.as_deref().unwrap_or("unknown"))
+            .or_default()
+            .push(&dependency.krate);
+    }
+    let flagged: Vec<&DependencyLicense> = dependencies
+        .iter()
+        .filter(|dependency| dependency.flag.is_some())
+        .collect();
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "crate": name,
+            "version": version,
+            "dependencies": dependencies,
+            "by_license": by_license,
+            "flagged": flagged,
+            "unresolved": tree.unresolved,
+            "truncated": tree.truncated,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn mixed_licenses() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("serde")
+                .version("1.0.196")
+                .license("MIT OR Apache-2.0")
+                .create()?;
+            env.fake_release()
+                .name("copyleft")
+                .version("0.2.0")
+                .license("GPL-3.0")
+                .add_dependency("serde", "1", None)
+                .create()?;
+            // A permissive alternative is enough
+            env.fake_release()
+                .name("dual")
+                .version("1.0.0")
+                .license("MIT OR AGPL-3.0")
+                .create()?;
+            env.fake_release()
+                .name("custom")
+                .version("0.1.0")
+                .license("Some custom license")
+                .create()?;
+            env.fake_release()
+                .name("server-side")
+                .version("3.0.0")
+                .license("SSPL-1.0")
+                .add_dependency("serde", "1", None)
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .license("MIT")
+                .add_dependency("copyleft", "0.2", None)
+                .add_dependency("dual", "1", None)
+                .add_dependency("custom", "0.1", None)
+                .add_dependency("server-side", "3", Some("dev"))
+                .add_dependency("unpublished", "0.1", None)
+                .create()?;
+
+            let web = env.frontend();
+            let resp = web
+                .get("/api/v1/crates/foo/0.1.0/dependencies/licenses")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(
+                body,
+                json!({
+                    "crate": "foo",
+                    "version": "0.1.0",
+                    "dependencies": [
+                        {
+                            "crate": "copyleft",
+                            "version": "0.2.0",
+                            "license": "GPL-3.0",
+                            "is_dev": false,
+                            "flag": "restrictive",
+                        },
+                        {
+                            "crate": "dual",
+                            "version": "1.0.0",
+                            "license": "MIT OR AGPL-3.0",
+                            "is_dev": false,
+                            "flag": null,
+                        },
+                        {
+                            "crate": "custom",
+                            "version": "0.1.0",
+                            "license": "Some custom license",
+                            "is_dev": false,
+                            "flag": "unrecognized",
+                        },
+                        {
+                            "crate": "serde",
+                            "version": "1.0.196",
+                            "license": "MIT OR Apache-2.0",
+                            "is_dev": false,
+                            "flag": null,
+                        },
+                    ],
+                    "by_license": {
+                        "GPL-3.0": ["copyleft"],
+                        "MIT OR AGPL-3.0": ["dual"],
+                        "MIT OR Apache-2.0": ["serde"],
+                        "Some custom license": ["custom"],
+                    },
+                    "flagged": [
+                        {
+                            "crate": "copyleft",
+                            "version": "0.2.0",
+                            "license": "GPL-3.0",
+                            "is_dev": false,
+                            "flag": "restrictive",
+                        },
+                        {
+                            "crate": "custom",
+                            "version": "0.1.0",
+                            "license": "Some custom license",
+                            "is_dev": false,
+                            "flag": "unrecognized",
+                        },
+                    ],
+                    "unresolved": [
+                        { "crate": "fake-dependency", "req": "^1.0.0", "is_dev": false },
+                        { "crate": "unpublished", "req": "0.1", "is_dev": false },
+                    ],
+                    "truncated": false,
+                })
+            );
+
+            let body: Value = web
+                .get("/api/v1/crates/foo/0.1.0/dependencies/licenses?include_dev=true")
+                .send()?
+                .json()?;
+            assert_eq!(
+                body["dependencies"][4],
+                json!({
+                    "crate": "server-side",
+                    "version": "3.0.0",
+                    "license": "SSPL-1.0",
+                    "is_dev": true,
+                    "flag": "restrictive",
+                })
+            );
+            assert_eq!(body["by_license"]["SSPL-1.0"], json!(["server-side"]));
+            assert_eq!(body["flagged"].as_array().unwrap().len(), 3);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn missing_release() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            for url in &[
+                "/api/v1/crates/foo/0.2.0/dependencies/licenses",
+                "/api/v1/crates/missing/0.1.0/dependencies/licenses",
+            ] {
+                assert_eq!(web.get(url).send()?.status(), StatusCode::NOT_FOUND);
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,359 @@
+//! Resolution of the dependency trees of releases from the dependencies they were built with
+
+use super::error::Nope;
+use super::match_version;
+use postgres::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The most crates of a dependency tree that are resolved.
+const MAX_CRATES: usize = 500;
+
+/// A crate of a dependency tree, resolved to one of its releases.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ResolvedCrate {
+    pub(super) name: String,
+    pub(super) version: String,
+    pub(super) license: Option<String>,
+    pub(super) repository_url: Option<String>,
+    /// Whether the crate is only needed by the development dependencies of the release
+    pub(super) is_dev: bool,
+    /// The indexes of the dependencies of the crate in the tree
+    pub(super) dependencies: Vec<usize>,
+}
+
+/// A dependency without any release known to docs.rs matching its requirement.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(super) struct UnresolvedDependency {
+    #[serde(rename = "crate")]
+    pub(super) name: String,
+    pub(super) req: String,
+    pub(super) is_dev: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(super) struct DependencyTree {
+    /// The resolved crates, starting with the release
+    pub(super) crates: Vec<ResolvedCrate>,
+    /// The dependencies that were skipped, in the order they were found
+    pub(super) unresolved: Vec<UnresolvedDependency>,
+    /// Whether dependencies were skipped because the tree has the most crates resolved
+    pub(super) truncated: bool,
+}
+
+/// Returns the release with `release_id` and its normal dependencies, direct and transitive, in
+/// breadth-first order starting with the release.
+///
+/// Every dependency is resolved to its newest release matching the requirement of the first
+/// crate depending on it, like cargo unifies the versions. The dependencies unknown to docs.rs
+/// are skipped, and listed in `unresolved`.
+pub(super) fn resolve_dependency_tree(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<DependencyTree, failure::Error> {
+    let mut resolver = Resolver::new(release_id, MAX_CRATES);
+    resolver.resolve(conn)?;
+    Ok(resolver.tree)
+}
+
+/// Like `resolve_dependency_tree`, also resolving the development dependencies of the release and
+/// their own normal dependencies once all the normal ones are. The crates only needed by the
+/// development dependencies are marked with `is_dev`.
+pub(super) fn resolve_dependency_tree_with_dev(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<DependencyTree, failure::Error> {
+    let mut resolver = Resolver::new(release_id, MAX_CRATES);
+    resolver.resolve(conn)?;
+
+    let dependencies = conn
+        .query_one(
+            "SELECT dependencies FROM releases WHERE id = $1",
+            &[&release_id],
+        )?
+        .get(0);
+    for (dependency, req) in dependencies_of_kind(dependencies, "dev") {
+        if let Some(index) = resolver.add(conn, dependency, &req, true)? {
+            resolver.tree.crates[0].dependencies.push(index);
+        }
+    }
+    resolver.resolve(conn)?;
+
+    Ok(resolver.tree)
+}
+
+struct Resolver {
+    tree: DependencyTree,
+    max_crates: usize,
+    indexes: HashMap<String, usize>,
+    /// The names of the unresolved dependencies, to only look them up once
+    unresolved: HashSet<String>,
+    /// The releases to add to the tree, and whether they're only development dependencies
+    queue: VecDeque<(i32, bool)>,
+}
+
+impl Resolver {
+    fn new(release_id: i32, max_crates: usize) -> Self {
+        let mut queue = VecDeque::new();
+        queue.push_back((release_id, false));
+        Resolver {
+            tree: DependencyTree::default(),
+            max_crates,
+            indexes: HashMap::new(),
+            unresolved: HashSet::new(),
+            queue,
+        }
+    }
+
+    /// Adds the queued releases to the tree, queueing their dependencies in turn.
+    fn resolve(&mut self, conn: &mut Client) -> Result<(), failure::Error> {
+        while let Some((release_id, is_dev)) = self.queue.pop_front() {
+            let row = conn.query_one(
+                "SELECT crates.name, releases.version, releases.license, releases.repository_url,
+                        releases.dependencies
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE releases.id = $1",
+                &[&release_id],
+            )?;
+            let name: String = row.get(0);
+            let index = self.tree.crates.len();
+            self.indexes.insert(name.clone(), index);
+
+            let mut dependencies = Vec::new();
+            for (dependency, req) in dependencies_of_kind(row.get(4), "normal") {
+                if let Some(index) = self.add(conn, dependency, &req, is_dev)? {
+                    dependencies.push(index);
+                }
+            }
+
+            self.tree.crates.push(ResolvedCrate {
+                name,
+                version: row.get(1),
+                license: row.get(2),
+                repository_url: row.get(3),
+                is_dev,
+                dependencies,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of the dependency in the tree, queueing its newest release matching the
+    /// requirement if it's not in the tree yet. Returns `None`, and records why, if the
+    /// dependency can't be resolved or the tree is full.
+    fn add(
+        &mut self,
+        conn: &mut Client,
+        dependency: String,
+        req: &str,
+        is_dev: bool,
+    ) -> Result<Option<usize>, failure::Error> {
+        if let Some(&index) = self.indexes.get(&dependency) {
+            return Ok(Some(index));
+        }
+        if self.unresolved.contains(&dependency) {
+            return Ok(None);
+        }
+        if self.indexes.len() >= self.max_crates {
+            self.tree.truncated = true;
+            return Ok(None);
+        }
+        match match_version(conn, &dependency, Some(req)) {
+            Ok(matched) => {
+                // The dependencies are added to the tree in the order they're queued
+                let index = self.indexes.len();
+                self.indexes.insert(dependency, index);
+                self.queue
+                    .push_back((matched.version.into_parts().1, is_dev));
+                Ok(Some(index))
+            }
+            Err(Nope::InternalServerError) => Err(Nope::InternalServerError.into()),
+            Err(_) => {
+                self.unresolved.insert(dependency.clone());
+                self.tree.unresolved.push(UnresolvedDependency {
+                    name: dependency,
+                    req: req.into(),
+                    is_dev,
+                });
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Returns the dependencies of a kind, like `normal` or `dev`, of the `dependencies` of a
+/// release, as `(name, version requirement)`.
+fn dependencies_of_kind(dependencies: Option<Value>, kind: &str) -> Vec<(String, String)> {
+    dependencies
+        .as_ref()
+        .and_then(Value::as_array)
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .filter_map(|dependency| {
+                    let name = dependency.get(0)?.as_str()?;
+                    let req = dependency.get(1)?.as_str()?;
+                    // The kind is missing from the releases added before it was recorded
+                    let dependency_kind = dependency
+                        .get(2)
+                        .and_then(Value::as_str)
+                        .unwrap_or("normal");
+                    if dependency_kind == kind {
+                        Some((name.to_string(), req.to_string()))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn resolve_tree() {
+        wrapper(|env| {
+            env.fake_release().name("leaf").version("1.0.0").create()?;
+            env.fake_release().name("leaf").version("1.1.0").create()?;
+            env.fake_release().name("leaf").version("2.0.0").create()?;
+            env.fake_release()
+                .name("middle")
+                .version("0.2.0")
+                .add_dependency("leaf", "^1", None)
+                .create()?;
+            let root = env
+                .fake_release()
+                .name("root")
+                .version("0.1.0")
+                .add_dependency("middle", "0.2", None)
+                // Resolved to the version required by `middle`, which comes first
+                .add_dependency("leaf", "^2", None)
+                .add_dependency("test-helper", "1", Some("dev"))
+                .add_dependency("unpublished", "0.1", None)
+                .create()?;
+            env.fake_release()
+                .name("test-helper")
+                .version("1.0.0")
+                .create()?;
+
+            let tree = resolve_dependency_tree(&mut env.db().conn(), root)?;
+            let crates: Vec<_> = tree
+                .crates
+                .iter()
+                .map(|krate| (krate.name.as_str(), krate.version.as_str()))
+                .collect();
+            assert_eq!(
+                crates,
+                vec![("root", "0.1.0"), ("middle", "0.2.0"), ("leaf", "2.0.0")]
+            );
+            assert_eq!(tree.crates[0].dependencies, vec![1, 2]);
+            assert_eq!(tree.crates[1].dependencies, vec![2]);
+            assert!(tree.crates[2].dependencies.is_empty());
+            // The fake releases all depend on `fake-dependency`, which is only listed once
+            let unresolved: Vec<_> = tree
+                .unresolved
+                .iter()
+                .map(|dependency| (dependency.name.as_str(), dependency.req.as_str()))
+                .collect();
+            assert_eq!(
+                unresolved,
+                vec![("fake-dependency", "^1.0.0"), ("unpublished", "0.1")]
+            );
+            assert!(!tree.truncated);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn truncated_tree() {
+        wrapper(|env| {
+            env.fake_release().name("leaf").version("1.0.0").create()?;
+            env.fake_release()
+                .name("middle")
+                .version("1.0.0")
+                .add_dependency("leaf", "1", None)
+                .create()?;
+            let root = env
+                .fake_release()
+                .name("root")
+                .version("0.1.0")
+                .add_dependency("middle", "1", None)
+                .create()?;
+
+            let mut resolver = Resolver::new(root, 2);
+            resolver.resolve(&mut env.db().conn())?;
+            let crates: Vec<_> = resolver
+                .tree
+                .crates
+                .iter()
+                .map(|krate| krate.name.as_str())
+                .collect();
+            assert_eq!(crates, vec!["root", "middle"]);
+            assert!(resolver.tree.truncated);
+            // Skipped dependencies are only unresolved if they're unknown
+            assert!(resolver
+                .tree
+                .unresolved
+                .iter()
+                .all(|dependency| dependency.name != "leaf"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn resolve_tree_with_dev_dependencies() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("shared")
+                .version("1.0.0")
+                .create()?;
+            env.fake_release()
+                .name("test-helper")
+                .version("1.0.0")
+                .add_dependency("shared", "1", None)
+                .add_dependency("helper-dep", "1", None)
+                .create()?;
+            env.fake_release()
+                .name("helper-dep")
+                .version("1.0.0")
+                .create()?;
+            let root = env
+                .fake_release()
+                .name("root")
+                .version("0.1.0")
+                .add_dependency("shared", "1", None)
+                .add_dependency("test-helper", "1", Some("dev"))
+                .create()?;
+
+            let tree = resolve_dependency_tree_with_dev(&mut env.db().conn(), root)?;
+            let crates: Vec<_> = tree
+                .crates
+                .iter()
+                .map(|krate| (krate.name.as_str(), krate.is_dev))
+                .collect();
+            assert_eq!(
+                crates,
+                vec![
+                    ("root", false),
+                    // Also a normal dependency
+                    ("shared", false),
+                    ("test-helper", true),
+                    ("helper-dep", true),
+                ]
+            );
+            assert_eq!(tree.crates[0].dependencies, vec![1, 2]);
+            assert_eq!(tree.crates[2].dependencies, vec![1, 3]);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,391 @@
+//! The crates depending on a crate, directly or transitively
+
+use super::json_response;
+use crate::db::Pool;
+use crate::utils::lru::LruCache;
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The depth of the graph when the request doesn't specify one.
+const DEFAULT_DEPTH: i32 = 3;
+/// The deepest graph returned, as the dependents of popular crates cover most of the registry
+/// after a few levels.
+const MAX_DEPTH: i32 = 5;
+/// The most crates of a graph, including the crate itself.
+const MAX_NODES: usize = 500;
+/// The most edges read from the database for a graph. Crates depending on several crates of the
+/// graph add an edge for each of them, so there are more edges than crates.
+const MAX_EDGES: i64 = 20 * MAX_NODES as i64;
+/// How long a graph is served from the cache.
+const CACHE_DURATION: Duration = Duration::from_secs(6 * 60 * 60);
+/// The most graphs kept in the cache, as the graphs of popular crates are large.
+const CACHE_CAPACITY: usize = 100;
+
+/// The graphs recently returned, by crate and depth.
+#[derive(Debug)]
+pub(crate) struct DependentsCache {
+    graphs: Mutex<LruCache<(String, i32), Value>>,
+}
+
+impl Default for DependentsCache {
+    fn default() -> Self {
+        DependentsCache {
+            graphs: Mutex::new(LruCache::new(CACHE_CAPACITY, CACHE_DURATION)),
+        }
+    }
+}
+
+impl DependentsCache {
+    fn get(&self, name: &str, depth: i32) -> Option<Value> {
+        self.graphs.lock().unwrap().get(&(name.to_string(), depth))
+    }
+
+    fn insert(&self, name: &str, depth: i32, graph: Value) {
+        self.graphs
+            .lock()
+            .unwrap()
+            .insert((name.to_string(), depth), graph);
+    }
+}
+
+/// A crate of the graph, `depth` dependencies away from the crate the graph was requested for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct DependentNode {
+    name: String,
+    depth: i32,
+}
+
+/// The latest release of `from` depends on `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+struct DependentEdge {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct DependentsGraph {
+    nodes: Vec<DependentNode>,
+    edges: Vec<DependentEdge>,
+    /// The crates depending on each other, each cycle starting and ending with the same crate
+    cycles: Vec<Vec<String>>,
+    /// Whether crates were left out of the graph because it reached `MAX_NODES`
+    truncated: bool,
+}
+
+/// Handler for `GET /api/v1/crates/:name/dependents/graph?depth=:depth`.
+///
+/// Returns the crates whose latest release depends on the crate, then the crates depending on
+/// those, up to `depth` levels, with every kind of dependency. The graphs are cached for six
+/// hours.
+pub fn dependents_graph_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name")).to_string();
+
+    let url: iron::url::Url = req.url.clone().into();
+    let depth = match url
+        .query_pairs()
+        .find(|(key, _)| key == "depth")
+        .map(|(_, depth)| depth.parse::<i32>())
+    {
+        None => DEFAULT_DEPTH,
+        Some(Ok(depth)) if (1..=MAX_DEPTH).contains(&depth) => depth,
+        Some(_) => {
+            return Ok(json_response(
+                status::BadRequest,
+                json!({
+                    "error": format!("invalid depth, expected a number from 1 to {}", MAX_DEPTH)
+                }),
+            ))
+        }
+    };
+
+    let cache = extension!(req, DependentsCache);
+    if let Some(graph) = cache.get(&name, depth) {
+        return Ok(json_response(status::Ok, graph));
+    }
+
+    let mut conn = extension!(req, Pool).get()?;
+    let exists = ctry!(
+        req,
+        conn.query_opt("SELECT 1 FROM crates WHERE name = $1", &[&name])
+    )
+    .is_some();
+    if !exists {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("crate {} not found", name) }),
+        ));
+    }
+
+    let graph = ctry!(req, dependents_graph(&mut conn, &name, depth, MAX_NODES));
+    let graph = json!({
+        "name": name,
+        "depth": depth,
+        "nodes": graph.nodes,
+        "edges": graph.edges,
+        "cycles": graph.cycles,
+        "truncated": graph.truncated,
+    });
+    cache.insert(&name, depth, graph.clone());
+    Ok(json_response(status::Ok, graph))
+}
+
+/// Walks the dependents of `name` breadth-first, up to `depth` levels and `max_nodes` crates.
+fn dependents_graph(
+    conn: &mut Client,
+    name: &str,
+    depth: i32,
+    max_nodes: usize,
+) -> Result<DependentsGraph, failure::Error> {
+    // The recursive query returns the edges one level after the other, so the outer LIMIT stops
+    // it before it walks the whole registry. `UNION` drops the edges already found at the same
+    // depth, and the depth limit stops the cycles.
+    let rows = conn.query(
+        "WITH RECURSIVE graph(dependent, dependency, depth) AS (
+             SELECT crates.name, crate_dependents.dependency, 1
+             FROM crate_dependents
+             INNER JOIN crates ON crates.id = crate_dependents.dependent_id
+             WHERE crate_dependents.dependency = $1
+             UNION
+             SELECT crates.name, crate_dependents.dependency, graph.depth + 1
+             FROM graph
+             INNER JOIN crate_dependents ON crate_dependents.dependency = graph.dependent
+             INNER JOIN crates ON crates.id = crate_dependents.dependent_id
+             WHERE graph.depth < $2
+         )
+         SELECT dependent, dependency, depth FROM graph LIMIT $3",
+        &[&name, &depth, &MAX_EDGES],
+    )?;
+
+    let mut graph = DependentsGraph {
+        nodes: vec![DependentNode {
+            name: name.to_string(),
+            depth: 0,
+        }],
+        truncated: rows.len() as i64 >= MAX_EDGES,
+        ..DependentsGraph::default()
+    };
+    let mut known: HashSet<String> = std::iter::once(name.to_string()).collect();
+    let mut edges = HashSet::new();
+    for row in rows {
+        let (from, to, depth): (String, String, i32) = (row.get(0), row.get(1), row.get(2));
+        // The dependency was left out of the graph
+        if !known.contains(&to) {
+            continue;
+        }
+        if !known.contains(&from) {
+            if known.len() >= max_nodes {
+                graph.truncated = true;
+                continue;
+            }
+            known.insert(from.clone());
+            graph.nodes.push(DependentNode {
+                name: from.clone(),
+                depth,
+            });
+        }
+
+        let edge = DependentEdge { from, to };
+        if edges.insert(edge.clone()) {
+            // A crate already in the graph can depend on one of its own dependents
+            if let Some(mut cycle) = dependency_path(&graph.edges, &edge.to, &edge.from) {
+                cycle.push(edge.to.clone());
+                graph.cycles.push(cycle);
+            }
+            graph.edges.push(edge);
+        }
+    }
+
+    // The edges of a level come in no particular order
+    graph
+        .nodes
+        .sort_by(|a, b| (a.depth, &a.name).cmp(&(b.depth, &b.name)));
+    graph
+        .edges
+        .sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+    Ok(graph)
+}
+
+/// Returns the crates from `from` to `to` following the edges from the dependents to their
+/// dependencies, if `from` depends on `to`.
+fn dependency_path(edges: &[DependentEdge], from: &str, to: &str) -> Option<Vec<String>> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue: VecDeque<&str> = std::iter::once(from).collect();
+    while let Some(current) = queue.pop_front() {
+        if current == to {
+            let mut path = vec![to.to_string()];
+            let mut current = to;
+            while current != from {
+                current = previous[current];
+                path.push(current.to_string());
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for edge in edges.iter().filter(|edge| edge.from == current) {
+            if edge.to != from && !previous.contains_key(edge.to.as_str()) {
+                previous.insert(&edge.to, current);
+                queue.push_back(&edge.to);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+
+    fn names(graph: &DependentsGraph) -> Vec<(&str, i32)> {
+        graph
+            .nodes
+            .iter()
+            .map(|node| (node.name.as_str(), node.depth))
+            .collect()
+    }
+
+    #[test]
+    fn depth_limit() {
+        wrapper(|env| {
+            env.fake_release().name("base").version("1.0.0").create()?;
+            env.fake_release()
+                .name("middle")
+                .version("1.0.0")
+                .add_dependency("base", "1", None)
+                .create()?;
+            env.fake_release()
+                .name("top")
+                .version("1.0.0")
+                .add_dependency("middle", "1", None)
+                .add_dependency("base", "1", Some("dev"))
+                .create()?;
+            // Only the latest release of each crate counts
+            env.fake_release()
+                .name("former")
+                .version("1.0.0")
+                .add_dependency("base", "1", None)
+                .create()?;
+            env.fake_release()
+                .name("former")
+                .version("2.0.0")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let graph = dependents_graph(&mut conn, "base", 1, MAX_NODES)?;
+            assert_eq!(names(&graph), vec![("base", 0), ("middle", 1), ("top", 1)]);
+            assert_eq!(graph.edges.len(), 2);
+
+            let graph = dependents_graph(&mut conn, "base", 2, MAX_NODES)?;
+            assert_eq!(names(&graph), vec![("base", 0), ("middle", 1), ("top", 1)]);
+            assert!(graph.edges.contains(&DependentEdge {
+                from: "top".into(),
+                to: "middle".into(),
+            }));
+            assert_eq!(graph.edges.len(), 3);
+            assert!(graph.cycles.is_empty());
+            assert!(!graph.truncated);
+
+            // The graph stops at the maximum number of crates
+            let graph = dependents_graph(&mut conn, "base", 2, 2)?;
+            assert_eq!(graph.nodes.len(), 2);
+            assert!(graph.truncated);
+
+            // Deleting the latest release brings back the dependencies of the previous one
+            crate::db::delete_version(&mut conn, &env.storage(), "former", "2.0.0")?;
+            let graph = dependents_graph(&mut conn, "base", 1, MAX_NODES)?;
+            assert_eq!(
+                names(&graph),
+                vec![("base", 0), ("former", 1), ("middle", 1), ("top", 1)]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn cycles() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("a")
+                .version("1.0.0")
+                .add_dependency("c", "1", Some("dev"))
+                .create()?;
+            env.fake_release()
+                .name("b")
+                .version("1.0.0")
+                .add_dependency("a", "1", None)
+                .create()?;
+            env.fake_release()
+                .name("c")
+                .version("1.0.0")
+                .add_dependency("b", "1", None)
+                .create()?;
+
+            let graph = dependents_graph(&mut env.db().conn(), "a", 5, MAX_NODES)?;
+            assert_eq!(names(&graph), vec![("a", 0), ("b", 1), ("c", 2)]);
+            assert_eq!(graph.edges.len(), 3);
+            assert_eq!(graph.cycles, vec![vec!["c", "b", "a", "c"]]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn dependents_graph_api() {
+        wrapper(|env| {
+            env.fake_release().name("base").version("1.0.0").create()?;
+            env.fake_release()
+                .name("user")
+                .version("1.0.0")
+                .add_dependency("base", "1", None)
+                .create()?;
+
+            let get = |url: &str| -> Result<(StatusCode, Value), failure::Error> {
+                let response = env.frontend().get(url).send()?;
+                Ok((response.status(), response.json()?))
+            };
+            let (status, body) = get("/api/v1/crates/base/dependents/graph")?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body,
+                json!({
+                    "name": "base",
+                    "depth": 3,
+                    "nodes": [{"name": "base", "depth": 0}, {"name": "user", "depth": 1}],
+                    "edges": [{"from": "user", "to": "base"}],
+                    "cycles": [],
+                    "truncated": false,
+                })
+            );
+
+            // The graph is cached
+            env.fake_release()
+                .name("other-user")
+                .version("1.0.0")
+                .add_dependency("base", "1", None)
+                .create()?;
+            assert_eq!(get("/api/v1/crates/base/dependents/graph")?.1, body);
+            let (_, body) = get("/api/v1/crates/base/dependents/graph?depth=1")?;
+            assert_eq!(body["nodes"].as_array().unwrap().len(), 3);
+
+            for depth in &["0", "6", "deep"] {
+                let url = format!("/api/v1/crates/base/dependents/graph?depth={}", depth);
+                assert_eq!(get(&url)?.0, StatusCode::BAD_REQUEST, "{}", url);
+            }
+            assert_eq!(
+                get("/api/v1/crates/missing/dependents/graph")?.0,
+                StatusCode::NOT_FOUND
+            );
+
+            Ok(())
+        });
+    }
+}
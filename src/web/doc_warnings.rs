@@ -0,0 +1,189 @@
+//! The warnings of rustdoc about the intra-doc links of releases, recorded during the build
+
+use super::json_response;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(super) struct DocWarning {
+    pub(super) warning_type: String,
+    pub(super) item_path: String,
+    pub(super) message: String,
+    pub(super) location: Option<String>,
+}
+
+/// The documentation warnings of a release, and how many there are. The count includes the
+/// warnings left out of the list, which stops at a thousand, and is `None` for the releases built
+/// without documentation or before the warnings were recorded.
+pub(super) fn get_doc_warnings(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<(Option<i32>, Vec<DocWarning>), failure::Error> {
+    let count = conn
+        .query_one(
+            "SELECT doc_warning_count FROM releases WHERE id = $1",
+            &[&release_id],
+        )?
+        .get(0);
+    let warnings = conn
+        .query(
+            "SELECT warning_type, item_path, message, location
+             FROM doc_warnings
+             WHERE release_id = $1
+             ORDER BY id",
+            &[&release_id],
+        )?
+        .into_iter()
+        .map(|row| DocWarning {
+            warning_type: row.get(0),
+            item_path: row.get(1),
+            message: row.get(2),
+            location: row.get(3),
+        })
+        .collect();
+    Ok((count, warnings))
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/intra-doc-links`.
+///
+/// Lists the warnings of rustdoc about the intra-doc links of the release, like the links to
+/// items that don't exist or that are private, in the order rustdoc reported them.
+pub fn doc_warnings_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.id
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let release_id: i32 = match row {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+
+    let (count, warnings) = ctry!(req, get_doc_warnings(&mut conn, release_id));
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "warning_count": count,
+            "warnings": warnings,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::add_doc_warnings;
+    use crate::docbuilder::{DocWarning, DocWarnings};
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn api_lists_doc_warnings() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .doc_warning("Gadget", "src/lib.rs:3:66")
+                .doc_warning("crate::shop::sell", "src/lib.rs:5:55")
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .no_doc_warnings()
+                .create()?;
+            env.fake_release().name("dummy").version("0.3.0").create()?;
+
+            let get = |url: &str| -> Result<(StatusCode, Value), failure::Error> {
+                let response = env.frontend().get(url).send()?;
+                Ok((response.status(), response.json()?))
+            };
+            let (status, body) = get("/api/v1/crates/dummy/0.1.0/intra-doc-links")?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body,
+                json!({
+                    "warning_count": 2,
+                    "warnings": [
+                        {
+                            "warning_type": "broken_intra_doc_links",
+                            "item_path": "Gadget",
+                            "message": "unresolved link to `Gadget`",
+                            "location": "src/lib.rs:3:66",
+                        },
+                        {
+                            "warning_type": "broken_intra_doc_links",
+                            "item_path": "crate::shop::sell",
+                            "message": "unresolved link to `crate::shop::sell`",
+                            "location": "src/lib.rs:5:55",
+                        },
+                    ],
+                })
+            );
+
+            let (_, body) = get("/api/v1/crates/dummy/0.2.0/intra-doc-links")?;
+            assert_eq!(body, json!({ "warning_count": 0, "warnings": [] }));
+
+            // Built before the warnings were recorded
+            let (_, body) = get("/api/v1/crates/dummy/0.3.0/intra-doc-links")?;
+            assert_eq!(body, json!({ "warning_count": null, "warnings": [] }));
+
+            let (status, _) = get("/api/v1/crates/dummy/0.4.0/intra-doc-links")?;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_counts_warnings_left_out() {
+        wrapper(|env| {
+            let release_id = env
+                .fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .no_doc_warnings()
+                .create()?;
+            let warnings = DocWarnings {
+                warnings: vec![DocWarning {
+                    warning_type: "broken_intra_doc_links".into(),
+                    item_path: "Gadget".into(),
+                    message: "unresolved link to `Gadget`".into(),
+                    location: None,
+                }],
+                total: 1500,
+            };
+            add_doc_warnings(&mut env.db().conn(), release_id, Some(&warnings))?;
+
+            let url = "/api/v1/crates/dummy/0.1.0/intra-doc-links";
+            let body: Value = env.frontend().get(url).send()?.json()?;
+            assert_eq!(body["warning_count"], 1500);
+            assert_eq!(body["warnings"].as_array().unwrap().len(), 1);
+
+            // A rebuild without documentation has no warnings
+            add_doc_warnings(&mut env.db().conn(), release_id, None)?;
+            let body: Value = env.frontend().get(url).send()?.json()?;
+            assert_eq!(body, json!({ "warning_count": null, "warnings": [] }));
+
+            Ok(())
+        });
+    }
+}
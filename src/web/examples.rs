@@ -0,0 +1,387 @@
+//! Examples shipped with a release, extracted from its `examples/` directory during the build
+
+use super::{json_response, render_markdown};
+use crate::{
+    db::Pool,
+    impl_webpage,
+    web::{error::Nope, page::WebPage, MetaData},
+};
+use iron::{headers::ContentType, status, IronError, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Example {
+    name: String,
+    /// The `//!` doc comment of the example, in markdown
+    description: Option<String>,
+    source_code: String,
+    /// The source code with its macros expanded, only for the crates using procedural macros
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expansion: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ExamplesPage {
+    metadata: MetaData,
+    examples: Vec<Example>,
+}
+
+impl_webpage! {
+    ExamplesPage = "crate/examples.html",
+}
+
+/// Returns the id of a release, or `None` if the release doesn't exist.
+fn get_release_id(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Option<i32>, failure::Error> {
+    let release = conn.query_opt(
+        "SELECT releases.id
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE crates.name = $1 AND releases.version = $2",
+        &[&name, &version],
+    )?;
+    Ok(release.map(|row| row.get(0)))
+}
+
+/// Returns the examples of a release, or `None` if the release doesn't exist.
+fn get_examples(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Option<Vec<Example>>, failure::Error> {
+    let release_id = match get_release_id(conn, name, version)? {
+        Some(release_id) => release_id,
+        None => return Ok(None),
+    };
+
+    let examples = conn
+        .query(
+            "SELECT examples.name, examples.description, examples.source_code,
+                    macro_expansions.expansion
+             FROM examples
+             LEFT JOIN macro_expansions
+                ON macro_expansions.release_id = examples.release_id
+                AND macro_expansions.example_name = examples.name
+             WHERE examples.release_id = $1
+             ORDER BY examples.name",
+            &[&release_id],
+        )?
+        .into_iter()
+        .map(|row| Example {
+            name: row.get(0),
+            description: row.get(1),
+            source_code: row.get(2),
+            expansion: row.get(3),
+        })
+        .collect();
+
+    Ok(Some(examples))
+}
+
+pub fn examples_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let examples = match ctry!(req, get_examples(&mut conn, name, version)) {
+        Some(examples) => examples,
+        None => return Err(IronError::new(Nope::VersionNotFound, status::NotFound)),
+    };
+
+    ExamplesPage {
+        metadata: cexpect!(req, MetaData::from_crate(&mut conn, name, version)),
+        examples: examples
+            .into_iter()
+            .map(|example| Example {
+                description: example.description.as_deref().map(render_markdown),
+                ..example
+            })
+            .collect(),
+    }
+    .into_response(req)
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/examples`.
+pub fn examples_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    match ctry!(req, get_examples(&mut conn, name, version)) {
+        Some(examples) => Ok(json_response(status::Ok, json!(examples))),
+        None => Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("release {} {} not found", name, version) }),
+        )),
+    }
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/macro-expansions`.
+///
+/// Lists the examples of the release whose macros were expanded.
+pub fn macro_expansions_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let release_id = match ctry!(req, get_release_id(&mut conn, name, version)) {
+        Some(release_id) => release_id,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+    let examples: Vec<String> = ctry!(
+        req,
+        conn.query(
+            "SELECT example_name
+             FROM macro_expansions
+             WHERE release_id = $1
+             ORDER BY example_name",
+            &[&release_id],
+        )
+    )
+    .into_iter()
+    .map(|row| row.get(0))
+    .collect();
+
+    Ok(json_response(status::Ok, json!({ "examples": examples })))
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/expand/:example`.
+///
+/// Returns the formatted source code of the example with its macros expanded, as plain text.
+pub fn macro_expansion_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+    let example = cexpect!(req, router.find("example"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT macro_expansions.expansion
+             FROM macro_expansions
+             INNER JOIN releases ON releases.id = macro_expansions.release_id
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2
+                AND macro_expansions.example_name = $3",
+            &[&name, &version, &example],
+        )
+    );
+    let expansion: String = match row {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({
+                    "error": format!(
+                        "no macro expansion of example {} for {} {}",
+                        example, name, version
+                    )
+                }),
+            ))
+        }
+    };
+
+    let mut resp = Response::with((status::Ok, expansion));
+    resp.headers.set(ContentType::plaintext());
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{assert_success, wrapper, TestEnvironment};
+    use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    const HELLO: &[u8] = b"//! Prints a *greeting*.\n//!\n//!Run it with `cargo run`.\n\nfn main() {\n    println!(\"hello\");\n}\n";
+    const SERVER: &[u8] = b"fn main() {\n    // not a description\n}\n";
+    const EXPANDED_HELLO: &str = "#![feature(prelude_import)]\n#[prelude_import]\nuse std::prelude::v1::*;\nfn main() {\n    {\n        ::std::io::_print(::core::fmt::Arguments::new_v1(&[\"hello\\n\"], &[]));\n    };\n}";
+
+    fn release_with_examples(env: &TestEnvironment) -> Result<(), failure::Error> {
+        env.fake_release()
+            .name("dummy")
+            .version("0.1.0")
+            .source_file("examples/hello.rs", HELLO)
+            .source_file("examples/server/main.rs", SERVER)
+            .source_file("examples/server/routes.rs", b"pub fn routes() {}")
+            .source_file("examples/README.md", b"# Examples")
+            .create()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn api_lists_examples() {
+        wrapper(|env| {
+            release_with_examples(env)?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/examples")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(
+                body,
+                json!([
+                    {
+                        "name": "hello",
+                        "description": "Prints a *greeting*.\n\nRun it with `cargo run`.\n",
+                        "source_code": std::str::from_utf8(HELLO)?,
+                    },
+                    {
+                        "name": "server",
+                        "description": null,
+                        "source_code": std::str::from_utf8(SERVER)?,
+                    },
+                ])
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_without_examples() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            let resp = web.get("/api/v1/crates/dummy/0.1.0/examples").send()?;
+            assert_eq!(resp.json::<Value>()?, json!([]));
+
+            let resp = web.get("/api/v1/crates/dummy/0.2.0/examples").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn examples_page() {
+        wrapper(|env| {
+            release_with_examples(env)?;
+
+            let web = env.frontend();
+            assert_success("/crate/dummy/0.1.0/examples", web)?;
+            let page =
+                kuchiki::parse_html().one(web.get("/crate/dummy/0.1.0/examples").send()?.text()?);
+
+            let hello = page
+                .select_first("#example-hello")
+                .expect("missing example");
+            assert_eq!(hello.text_contents().trim(), "hello");
+            let description = page
+                .select_first("[data-id=\"example-hello\"] .example-description em")
+                .expect("description not rendered");
+            assert_eq!(description.text_contents(), "greeting");
+            assert!(page.select_first("#example-server").is_ok());
+            assert!(page.select_first("#example-routes").is_err());
+
+            let code = page
+                .select_first("[data-id=\"example-server\"] code")
+                .unwrap();
+            assert_eq!(code.text_contents(), std::str::from_utf8(SERVER)?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_macro_expansions() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .source_file("examples/hello.rs", HELLO)
+                .source_file("examples/server/main.rs", SERVER)
+                .macro_expansion("hello", EXPANDED_HELLO)
+                .create()?;
+
+            let web = env.frontend();
+            let resp = web
+                .get("/api/v1/crates/dummy/0.1.0/macro-expansions")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.json::<Value>()?, json!({ "examples": ["hello"] }));
+
+            let resp = web.get("/api/v1/crates/dummy/0.1.0/expand/hello").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+            assert_eq!(resp.text()?, EXPANDED_HELLO);
+
+            // The examples listed by the API include their expansion when there is one
+            let resp = web.get("/api/v1/crates/dummy/0.1.0/examples").send()?;
+            let body: Value = resp.json()?;
+            assert_eq!(body[0]["expansion"], EXPANDED_HELLO);
+            assert!(body[1].get("expansion").is_none());
+
+            for path in &[
+                "/api/v1/crates/dummy/0.1.0/expand/server",
+                "/api/v1/crates/dummy/0.2.0/expand/hello",
+                "/api/v1/crates/dummy/0.2.0/macro-expansions",
+            ] {
+                let resp = web.get(path).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn examples_page_expand_macros_toggle() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .source_file("examples/hello.rs", HELLO)
+                .source_file("examples/server/main.rs", SERVER)
+                .macro_expansion("hello", EXPANDED_HELLO)
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/dummy/0.1.0/examples")
+                    .send()?
+                    .text()?,
+            );
+            let expansion = page
+                .select_first("[data-id=\"example-hello\"] .macro-expansion code")
+                .expect("missing macro expansion");
+            assert_eq!(expansion.text_contents(), EXPANDED_HELLO);
+            assert!(page
+                .select_first("[data-id=\"example-server\"] .macro-expansion")
+                .is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn examples_page_missing_release() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let resp = env.frontend().get("/crate/dummy/0.2.0/examples").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
@@ -1,3 +1,4 @@
+use crate::web::dependents::DependentsCache;
 use crate::web::page::TemplateData;
 use crate::{db::Pool, BuildQueue, Config, Context, Metrics, Storage};
 use failure::Error;
@@ -12,6 +13,7 @@ pub(super) struct InjectExtensions {
     storage: Arc<Storage>,
     metrics: Arc<Metrics>,
     template_data: Arc<TemplateData>,
+    dependents_cache: Arc<DependentsCache>,
 }
 
 impl InjectExtensions {
@@ -26,6 +28,7 @@ impl InjectExtensions {
             storage: context.storage()?,
             metrics: context.metrics()?,
             template_data,
+            dependents_cache: Arc::new(DependentsCache::default()),
         })
     }
 }
@@ -40,6 +43,8 @@ impl BeforeMiddleware for InjectExtensions {
         req.extensions.insert::<Metrics>(self.metrics.clone());
         req.extensions
             .insert::<TemplateData>(self.template_data.clone());
+        req.extensions
+            .insert::<DependentsCache>(self.dependents_cache.clone());
 
         Ok(())
     }
@@ -59,3 +64,4 @@ key!(Config => Arc<Config>);
 key!(Storage => Arc<Storage>);
 key!(Metrics => Arc<Metrics>);
 key!(TemplateData => Arc<TemplateData>);
+key!(DependentsCache => Arc<DependentsCache>);
@@ -1,13 +1,16 @@
+use super::json_response;
 use crate::db::types::Feature;
 use crate::{
     db::Pool,
     impl_webpage,
     web::{page::WebPage, MetaData},
 };
-use iron::{IronResult, Request, Response};
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
 use router::Router;
 use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
 const DEFAULT_NAME: &str = "default";
 
@@ -57,6 +60,130 @@ pub fn build_features_handler(req: &mut Request) -> IronResult<Response> {
     .into_response(req)
 }
 
+/// The features of a release, and the ones its documentation was built with.
+struct ReleaseFeatures {
+    features: Vec<Feature>,
+    /// `None` for releases built before the enabled features were recorded
+    enabled: Option<Vec<String>>,
+    /// The path of the documentation, if it was built
+    docs_path: Option<String>,
+}
+
+/// Returns the features of the release, or `None` if the release doesn't exist.
+fn get_release_features(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Option<ReleaseFeatures>, failure::Error> {
+    let row = conn.query_opt(
+        "SELECT releases.features, releases.enabled_features, releases.rustdoc_status,
+                releases.target_name
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE crates.name = $1 AND releases.version = $2",
+        &[&name, &version],
+    )?;
+
+    Ok(row.map(|row| {
+        let target_name: String = row.get(3);
+        ReleaseFeatures {
+            features: row.get::<_, Option<Vec<Feature>>>(0).unwrap_or_default(),
+            enabled: row.get(1),
+            docs_path: if row.get(2) {
+                Some(format!("/{}/{}/{}/", name, version, target_name))
+            } else {
+                None
+            },
+        }
+    }))
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/features`.
+///
+/// Optional dependencies are listed as features enabling nothing, like cargo does for them.
+pub fn features_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let release = match ctry!(req, get_release_features(&mut conn, name, version)) {
+        Some(release) => release,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+
+    let mut default = Vec::new();
+    let mut features = BTreeMap::new();
+    for feature in release.features {
+        if feature.name == DEFAULT_NAME {
+            default = feature.subfeatures;
+        } else {
+            features.insert(feature.name, feature.subfeatures);
+        }
+    }
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "default": default,
+            "features": features,
+            "enabled": release.enabled,
+        }),
+    ))
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/features/:feature/docs`, linking to the
+/// documentation built with the feature enabled.
+pub fn feature_docs_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+    let feature = cexpect!(req, router.find("feature"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let release = match ctry!(req, get_release_features(&mut conn, name, version)) {
+        Some(release) => release,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+
+    if !release.features.iter().any(|f| f.name == feature) {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("{} {} has no feature {}", name, version, feature) }),
+        ));
+    }
+
+    let enabled = matches!(
+        &release.enabled,
+        Some(enabled) if enabled.iter().any(|f| f == feature)
+    );
+    match release.docs_path {
+        Some(docs_path) if enabled => Ok(json_response(
+            status::Ok,
+            json!({ "feature": feature, "docs_url": docs_path }),
+        )),
+        _ => Ok(json_response(
+            status::NotFound,
+            json!({
+                "error": format!(
+                    "no documentation of {} {} was built with feature {}",
+                    name, version, feature
+                ),
+            }),
+        )),
+    }
+}
+
 fn order_features_and_count_default_len(raw: Vec<Feature>) -> (Vec<Feature>, usize) {
     let mut feature_map = get_feature_map(raw);
     let mut features = get_tree_structure_from_default(&mut feature_map);
@@ -100,10 +227,136 @@ fn get_feature_map(raw: Vec<Feature>) -> HashMap<String, Feature> {
 #[cfg(test)]
 mod tests {
     use crate::db::types::Feature;
+    use crate::test::{wrapper, TestEnvironment};
     use crate::web::features::{
         get_feature_map, get_tree_structure_from_default, order_features_and_count_default_len,
         DEFAULT_NAME,
     };
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    /// Creates a release whose documentation is built with `async` and the default features.
+    fn release_with_features(env: &TestEnvironment) -> Result<(), failure::Error> {
+        let features: HashMap<String, Vec<String>> = vec![
+            ("default", vec!["std"]),
+            ("std", vec!["alloc"]),
+            ("alloc", vec![]),
+            ("full", vec!["std", "async", "serde"]),
+            ("async", vec!["runtime", "alloc"]),
+            ("runtime", vec!["async"]),
+            ("serde", vec![]),
+            ("_private", vec![]),
+        ]
+        .into_iter()
+        .map(|(name, enables)| {
+            (
+                name.to_string(),
+                enables.into_iter().map(String::from).collect(),
+            )
+        })
+        .collect();
+
+        env.fake_release()
+            .name("dummy")
+            .version("0.1.0")
+            .features(features)
+            .docs_rs_metadata(r#"features = ["async"]"#)
+            .create()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn api_lists_features() {
+        wrapper(|env| {
+            release_with_features(env)?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/features")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({
+                    "default": ["std"],
+                    "features": {
+                        "_private": [],
+                        "alloc": [],
+                        "async": ["runtime", "alloc"],
+                        "full": ["std", "async", "serde"],
+                        "runtime": ["async"],
+                        "serde": [],
+                        "std": ["alloc"],
+                    },
+                    "enabled": ["alloc", "async", "default", "runtime", "std"],
+                })
+            );
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.2.0/features")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_features_of_old_builds() {
+        wrapper(|env| {
+            release_with_features(env)?;
+            env.db()
+                .conn()
+                .execute("UPDATE releases SET enabled_features = NULL;", &[])?;
+
+            let web = env.frontend();
+            let resp = web.get("/api/v1/crates/dummy/0.1.0/features").send()?;
+            assert_eq!(resp.json::<Value>()?["enabled"], Value::Null);
+            let resp = web
+                .get("/api/v1/crates/dummy/0.1.0/features/std/docs")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_feature_docs() {
+        wrapper(|env| {
+            release_with_features(env)?;
+            let web = env.frontend();
+
+            for feature in &["runtime", "alloc", "default"] {
+                let resp = web
+                    .get(&format!(
+                        "/api/v1/crates/dummy/0.1.0/features/{}/docs",
+                        feature
+                    ))
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert_eq!(
+                    resp.json::<Value>()?,
+                    json!({ "feature": feature, "docs_url": "/dummy/0.1.0/dummy/" })
+                );
+            }
+
+            for feature in &["full", "serde", "missing"] {
+                let resp = web
+                    .get(&format!(
+                        "/api/v1/crates/dummy/0.1.0/features/{}/docs",
+                        feature
+                    ))
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            }
+
+            Ok(())
+        });
+    }
 
     #[test]
     fn test_feature_map_filters_private() {
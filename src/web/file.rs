@@ -1,6 +1,6 @@
 //! Database based file handler
 
-use crate::storage::{Blob, Storage};
+use crate::storage::{Blob, CompressionAlgorithm, CompressionAlgorithms, Storage};
 use crate::{error::Result, Config, Metrics};
 use iron::{status, Handler, IronResult, Request, Response};
 
@@ -10,13 +10,40 @@ pub(crate) struct File(pub(crate) Blob);
 impl File {
     /// Gets file from database
     pub fn from_path(storage: &Storage, path: &str, config: &Config) -> Result<File> {
+        Self::from_path_accepting(storage, path, config, &CompressionAlgorithms::new())
+    }
+
+    /// Gets file from database, keeping it compressed if the client accepts its compression
+    /// algorithm as a content encoding, unless it requested a range of the file.
+    pub(crate) fn from_path_for(
+        req: &Request,
+        storage: &Storage,
+        path: &str,
+        config: &Config,
+    ) -> Result<File> {
+        // The ranges are of the decompressed content, as the encoded one can't be sliced. The
+        // requests for multiple ranges get the whole decompressed file too.
+        let accepted = if req.headers.get_raw("Range").is_some() {
+            CompressionAlgorithms::new()
+        } else {
+            accepted_compressions(req)
+        };
+        Self::from_path_accepting(storage, path, config, &accepted)
+    }
+
+    fn from_path_accepting(
+        storage: &Storage,
+        path: &str,
+        config: &Config,
+        accepted: &CompressionAlgorithms,
+    ) -> Result<File> {
         let max_size = if path.ends_with(".html") {
             config.max_file_size_html
         } else {
             config.max_file_size
         };
 
-        Ok(File(storage.get(path, max_size)?))
+        Ok(File(storage.get_accepting(path, max_size, accepted)?))
     }
 
     /// Consumes File and creates a iron response
@@ -32,6 +59,19 @@ impl File {
             .headers
             .set(ContentType(self.0.mime.parse().unwrap()));
         response.headers.set(CacheControl(cache));
+        // The content is only compressed when the client accepted it
+        if let Some(alg) = self.0.compression {
+            response
+                .headers
+                .set_raw("Content-Encoding", vec![alg.http_encoding().into()]);
+        } else {
+            response
+                .headers
+                .set_raw("Accept-Ranges", vec![b"bytes".to_vec()]);
+        }
+        response
+            .headers
+            .set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
         // FIXME: This is so horrible
         response.headers.set(LastModified(HttpDate(
             time::strptime(
@@ -43,12 +83,117 @@ impl File {
         response
     }
 
+    /// Like `serve`, but only serves the range of the file requested with the `Range` header, if
+    /// any. Requests for multiple ranges get the whole file.
+    pub(crate) fn serve_for(mut self, req: &Request) -> Response {
+        let range = match requested_range(req) {
+            // The range would be of the encoded content
+            Some(range) if self.0.compression.is_none() => range,
+            _ => return self.serve(),
+        };
+
+        let len = self.0.content.len() as u64;
+        match satisfiable_range(range, len) {
+            Some((first, last)) => {
+                self.0.content.truncate(last as usize + 1);
+                self.0.content.drain(..first as usize);
+                let mut response = self.serve();
+                response.status = Some(status::PartialContent);
+                response.headers.set_raw(
+                    "Content-Range",
+                    vec![format!("bytes {}-{}/{}", first, last, len).into_bytes()],
+                );
+                response
+            }
+            None => {
+                let mut response = Response::with(status::RangeNotSatisfiable);
+                response.headers.set_raw(
+                    "Content-Range",
+                    vec![format!("bytes */{}", len).into_bytes()],
+                );
+                response
+            }
+        }
+    }
+
     /// Checks if mime type of file is "application/x-empty"
     pub fn is_empty(&self) -> bool {
         self.0.mime == "application/x-empty"
     }
 }
 
+/// Returns the compression algorithms the client accepts as content encodings.
+fn accepted_compressions(req: &Request) -> CompressionAlgorithms {
+    req.headers
+        .get_raw("Accept-Encoding")
+        .into_iter()
+        .flatten()
+        .filter_map(|value| std::str::from_utf8(value).ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let alg = CompressionAlgorithm::from_http_encoding(parts.next()?)?;
+            // Encodings with a zero quality are explicitly refused
+            let refused = parts.any(|param| {
+                matches!(param.strip_prefix("q="), Some(quality) if quality.parse() == Ok(0.0))
+            });
+            if refused {
+                None
+            } else {
+                Some(alg)
+            }
+        })
+        .collect()
+}
+
+/// A range of bytes requested with the `Range` header, as the first and last positions. Only
+/// the last position is set for the ranges of the end of the file, like `bytes=-500`.
+type ByteRange = (Option<u64>, Option<u64>);
+
+/// Returns the single range of bytes requested by the client, ignoring the invalid headers and
+/// the requests for multiple ranges.
+fn requested_range(req: &Request) -> Option<ByteRange> {
+    let header = req.headers.get_raw("Range")?;
+    match header {
+        [value] => parse_range(std::str::from_utf8(value).ok()?),
+        _ => None,
+    }
+}
+
+fn parse_range(header: &str) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let mut positions = spec.splitn(2, '-').map(str::trim);
+    let position = |position: &str| -> Option<Option<u64>> {
+        if position.is_empty() {
+            Some(None)
+        } else {
+            position.parse().ok().map(Some)
+        }
+    };
+    let first = position(positions.next()?)?;
+    let last = position(positions.next()?)?;
+    match (first, last) {
+        (None, None) => None,
+        (Some(first), Some(last)) if last < first => None,
+        range => Some(range),
+    }
+}
+
+/// Returns the first and last positions of the `range` in content of `len` bytes, or `None` if
+/// no byte of the content is in it.
+fn satisfiable_range(range: ByteRange, len: u64) -> Option<(u64, u64)> {
+    match range {
+        (Some(first), _) if first >= len => None,
+        (Some(first), last) => Some((first, last.map_or(len - 1, |last| last.min(len - 1)))),
+        (None, Some(suffix)) if suffix == 0 || len == 0 => None,
+        (None, Some(suffix)) => Some((len - suffix.min(len), len - 1)),
+        (None, None) => None,
+    }
+}
+
 /// Database based file handler for iron
 ///
 /// This is similar to staticfile crate, but its using getting files from database.
@@ -59,7 +204,7 @@ impl Handler for DatabaseFileHandler {
         let path = req.url.path().join("/");
         let storage = extension!(req, Storage);
         let config = extension!(req, Config);
-        if let Ok(file) = File::from_path(&storage, &path, &config) {
+        if let Ok(file) = File::from_path_for(req, &storage, &path, &config) {
             let metrics = extension!(req, Metrics);
 
             // Because all requests that don't hit another handler go through here, we will get all
@@ -69,7 +214,7 @@ impl Handler for DatabaseFileHandler {
                 .routes_visited
                 .with_label_values(&["database success"])
                 .inc();
-            Ok(file.serve())
+            Ok(file.serve_for(req))
         } else {
             Err(super::error::Nope::CrateNotFound.into())
         }
@@ -81,6 +226,7 @@ mod tests {
     use super::*;
     use crate::test::wrapper;
     use chrono::Utc;
+    use reqwest::StatusCode;
 
     #[test]
     fn file_roundtrip() {
@@ -159,4 +305,161 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99"), Some((Some(0), Some(99))));
+        assert_eq!(parse_range("bytes=100-"), Some((Some(100), None)));
+        assert_eq!(parse_range("bytes=-50"), Some((None, Some(50))));
+        for invalid in &[
+            "bytes=-",
+            "bytes=5-1",
+            "bytes=0-1,5-6",
+            "items=0-1",
+            "bytes=a-b",
+        ] {
+            assert_eq!(parse_range(invalid), None, "{}", invalid);
+        }
+
+        assert_eq!(satisfiable_range((Some(0), Some(99)), 10), Some((0, 9)));
+        assert_eq!(satisfiable_range((Some(9), None), 10), Some((9, 9)));
+        assert_eq!(satisfiable_range((None, Some(3)), 10), Some((7, 9)));
+        assert_eq!(satisfiable_range((None, Some(30)), 10), Some((0, 9)));
+        assert_eq!(satisfiable_range((Some(10), None), 10), None);
+        assert_eq!(satisfiable_range((None, Some(0)), 10), None);
+        assert_eq!(satisfiable_range((None, Some(5)), 0), None);
+    }
+
+    #[test]
+    fn range_requests() {
+        wrapper(|env| {
+            let content = b"0123456789abcdefghij";
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with("dummy/search-index.js", content)
+                .create()?;
+
+            let get = |range: Option<&str>| {
+                let mut req = env
+                    .frontend()
+                    .get("/dummy/0.1.0/dummy/search-index.js")
+                    // The file is stored compressed, and the ranges are of its content
+                    .header("Accept-Encoding", "zstd");
+                if let Some(range) = range {
+                    req = req.header("Range", range);
+                }
+                req.send()
+            };
+            let header = |resp: &reqwest::blocking::Response, name: &str| {
+                resp.headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+
+            let resp = get(Some("bytes=0-3"))?;
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(
+                header(&resp, "Content-Range").as_deref(),
+                Some("bytes 0-3/20")
+            );
+            assert_eq!(header(&resp, "Content-Encoding"), None);
+            assert_eq!(&resp.bytes()?[..], b"0123");
+
+            let resp = get(Some("bytes=-5"))?;
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(
+                header(&resp, "Content-Range").as_deref(),
+                Some("bytes 15-19/20")
+            );
+            assert_eq!(&resp.bytes()?[..], b"fghij");
+
+            let resp = get(Some("bytes=18-100"))?;
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(&resp.bytes()?[..], b"ij");
+
+            let resp = get(Some("bytes=20-"))?;
+            assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+            assert_eq!(
+                header(&resp, "Content-Range").as_deref(),
+                Some("bytes */20")
+            );
+
+            // Multiple ranges get the whole file
+            let resp = get(Some("bytes=0-1,4-5"))?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(header(&resp, "Accept-Ranges").as_deref(), Some("bytes"));
+            assert_eq!(&resp.bytes()?[..], content);
+
+            // Without a range, the compressed content can't be sliced
+            let resp = get(None)?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(header(&resp, "Content-Encoding").as_deref(), Some("zstd"));
+            assert_eq!(header(&resp, "Accept-Ranges"), None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn content_encoding_negotiation() {
+        wrapper(|env| {
+            let content = b"function dummy() {}";
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with("dummy/new.js", content)
+                .create()?;
+            // Files stored before the compression on upload are stored as they are
+            env.storage().store_blobs(vec![Blob {
+                path: "rustdoc/dummy/0.1.0/dummy/old.js".into(),
+                mime: "application/javascript".into(),
+                date_updated: Utc::now(),
+                content: content.to_vec(),
+                compression: None,
+            }])?;
+
+            let get = |path: &str, accept_encoding: Option<&str>| {
+                let mut req = env.frontend().get(path);
+                if let Some(accept_encoding) = accept_encoding {
+                    req = req.header("Accept-Encoding", accept_encoding);
+                }
+                let resp = req.send()?;
+                assert!(resp.status().is_success());
+                if path.ends_with(".js") {
+                    assert_eq!(resp.headers()["Vary"], "Accept-Encoding");
+                }
+                let encoding = resp
+                    .headers()
+                    .get("Content-Encoding")
+                    .map(|value| value.to_str().unwrap().to_string());
+                Ok::<_, failure::Error>((encoding, resp.bytes()?.to_vec()))
+            };
+
+            let (encoding, body) = get("/dummy/0.1.0/dummy/new.js", Some("gzip, zstd"))?;
+            assert_eq!(encoding.as_deref(), Some("zstd"));
+            assert_eq!(
+                crate::storage::decompress(body.as_slice(), CompressionAlgorithm::Zstd, 1024)?,
+                content
+            );
+
+            for (path, accept_encoding) in &[
+                ("/dummy/0.1.0/dummy/new.js", None),
+                ("/dummy/0.1.0/dummy/new.js", Some("gzip")),
+                ("/dummy/0.1.0/dummy/new.js", Some("zstd;q=0")),
+                ("/dummy/0.1.0/dummy/old.js", Some("zstd")),
+                ("/dummy/0.1.0/dummy/old.js", None),
+            ] {
+                let (encoding, body) = get(path, *accept_encoding)?;
+                assert_eq!(encoding, None, "{} {:?}", path, accept_encoding);
+                assert_eq!(body, content, "{} {:?}", path, accept_encoding);
+            }
+
+            // HTML pages are rewritten, so they're always decompressed
+            let (encoding, _) = get("/dummy/0.1.0/dummy/", Some("zstd"))?;
+            assert_eq!(encoding, None);
+
+            Ok(())
+        });
+    }
 }
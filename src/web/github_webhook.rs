@@ -0,0 +1,265 @@
+//! Webhook called by GitHub with the events of repositories, to update their stats without
+//! waiting for the next run of the GitHub updater
+
+use super::json_response;
+use crate::{db::Pool, Config};
+use hmac::{Hmac, Mac, NewMac};
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::io::Read;
+
+const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+const EVENT_HEADER: &str = "X-GitHub-Event";
+const DELIVERY_HEADER: &str = "X-GitHub-Delivery";
+/// The largest payload GitHub sends.
+const MAX_BODY_SIZE: u64 = 25 * 1024 * 1024;
+
+/// The events changing the stats of a repository.
+const EVENTS: &[&str] = &["push", "star", "watch", "fork", "issues", "repository"];
+
+#[derive(Debug, Deserialize)]
+struct WebhookPayload {
+    repository: PayloadRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct PayloadRepository {
+    node_id: String,
+    full_name: String,
+}
+
+/// Handler for `POST /webhook/github/:secret`.
+///
+/// The configured secret must be both in the URL and used to sign the payload, as GitHub does in
+/// the `X-Hub-Signature-256` header. The events changing the stats of a repository are stored,
+/// and the repositories they're about are updated by the next run of the webhook job of the
+/// daemon. The other events are acknowledged and dropped.
+pub fn github_webhook_handler(req: &mut Request) -> IronResult<Response> {
+    // The body is always read, as leaving it in the connection breaks the following requests.
+    let mut body = Vec::new();
+    ctry!(
+        req,
+        req.body.by_ref().take(MAX_BODY_SIZE).read_to_end(&mut body)
+    );
+
+    let secret = match &extension!(req, Config).github_webhook_secret {
+        Some(secret) if !secret.is_empty() => secret.clone(),
+        _ => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": "the GitHub webhook is disabled" }),
+            ))
+        }
+    };
+    let url_secret = cexpect!(req, extension!(req, Router).find("secret"));
+    let signature = header(req, SIGNATURE_HEADER);
+    if url_secret != secret || !has_valid_signature(&secret, &body, signature.as_deref()) {
+        return Ok(json_response(
+            status::Unauthorized,
+            json!({ "error": "a valid secret and signature are required" }),
+        ));
+    }
+
+    let event = header(req, EVENT_HEADER).unwrap_or_default();
+    if !EVENTS.contains(&event.as_str()) {
+        return Ok(json_response(status::Ok, json!({ "stored": false })));
+    }
+    let payload: WebhookPayload = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(err) => {
+            return Ok(json_response(
+                status::BadRequest,
+                json!({ "error": format!("invalid payload: {}", err) }),
+            ))
+        }
+    };
+
+    let delivery = header(req, DELIVERY_HEADER);
+    let mut conn = extension!(req, Pool).get()?;
+    ctry!(
+        req,
+        conn.execute(
+            "INSERT INTO webhook_events (delivery_id, event, repository_id, repository_name)
+             VALUES ($1, $2, $3, $4);",
+            &[
+                &delivery,
+                &event,
+                &payload.repository.node_id,
+                &payload.repository.full_name,
+            ],
+        )
+    );
+
+    Ok(json_response(status::Accepted, json!({ "stored": true })))
+}
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+        .get_raw(name)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+/// Checks that `signature`, as `sha256=<hex digest>`, is the HMAC-SHA256 of the body with the
+/// secret. The digests are compared in constant time.
+fn has_valid_signature(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let digest = match signature
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .and_then(decode_hex)
+    {
+        Some(digest) => digest,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_varkey(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    mac.verify(&digest).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+    use reqwest::StatusCode;
+
+    const SECRET: &str = "github-secret";
+
+    fn sign(body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(SECRET.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("sha256={}", hex)
+    }
+
+    fn send(
+        env: &TestEnvironment,
+        secret: &str,
+        event: &str,
+        body: &str,
+        signature: Option<String>,
+    ) -> Result<StatusCode, failure::Error> {
+        let mut request = env
+            .frontend()
+            .post(&format!("/webhook/github/{}", secret))
+            .header(EVENT_HEADER, event)
+            .header(DELIVERY_HEADER, "delivery")
+            .body(body.to_string());
+        if let Some(signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        Ok(request.send()?.status())
+    }
+
+    fn stored_events(env: &TestEnvironment) -> Result<Vec<(String, String)>, failure::Error> {
+        Ok(env
+            .db()
+            .conn()
+            .query(
+                "SELECT event, repository_id FROM webhook_events ORDER BY id",
+                &[],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    const PAYLOAD: &str =
+        r#"{"action":"created","repository":{"node_id":"MDEw","full_name":"owner/repo"}}"#;
+
+    #[test]
+    fn signatures() {
+        assert!(has_valid_signature(SECRET, b"{}", Some(&sign("{}"))));
+        assert!(!has_valid_signature(SECRET, b"{ }", Some(&sign("{}"))));
+        assert!(!has_valid_signature("other", b"{}", Some(&sign("{}"))));
+        assert!(!has_valid_signature(SECRET, b"{}", None));
+        assert!(!has_valid_signature(
+            SECRET,
+            b"{}",
+            Some(sign("{}").trim_start_matches("sha256="))
+        ));
+        assert!(!has_valid_signature(SECRET, b"{}", Some("sha256=zz")));
+        assert!(!has_valid_signature(SECRET, b"{}", Some("sha256=abc")));
+        assert!(!has_valid_signature(SECRET, b"{}", Some("sha256=")));
+    }
+
+    #[test]
+    fn store_signed_events() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_webhook_secret = Some(SECRET.into());
+            });
+
+            assert_eq!(
+                send(env, SECRET, "star", PAYLOAD, Some(sign(PAYLOAD)))?,
+                StatusCode::ACCEPTED
+            );
+            // The events not changing the stats aren't stored
+            assert_eq!(
+                send(env, SECRET, "ping", PAYLOAD, Some(sign(PAYLOAD)))?,
+                StatusCode::OK
+            );
+            let invalid = r#"{"zen":"Keep it logically awesome."}"#;
+            assert_eq!(
+                send(env, SECRET, "push", invalid, Some(sign(invalid)))?,
+                StatusCode::BAD_REQUEST
+            );
+
+            assert_eq!(stored_events(env)?, vec![("star".into(), "MDEw".into())]);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_tampered_payloads() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.github_webhook_secret = Some(SECRET.into());
+            });
+
+            let tampered = PAYLOAD.replace("owner/repo", "owner/other");
+            assert_eq!(
+                send(env, SECRET, "push", &tampered, Some(sign(PAYLOAD)))?,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                send(env, SECRET, "push", PAYLOAD, None)?,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                send(env, "wrong", "push", PAYLOAD, Some(sign(PAYLOAD)))?,
+                StatusCode::UNAUTHORIZED
+            );
+            assert!(stored_events(env)?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn disabled_without_secret() {
+        wrapper(|env| {
+            assert_eq!(
+                send(env, SECRET, "push", PAYLOAD, Some(sign(PAYLOAD)))?,
+                StatusCode::NOT_FOUND
+            );
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,86 @@
+//! The health of the periodic jobs of the daemon
+
+use super::json_response;
+use crate::db::{heartbeat::heartbeats, Pool};
+use crate::BuildQueue;
+use iron::{status, IronResult, Request, Response};
+use serde_json::json;
+
+/// Handler for `GET /api/v1/health`.
+///
+/// Returns when each periodic job of the daemon last started and last succeeded, for monitoring
+/// to alert on jobs that stopped running or keep failing, and whether the build queue is locked.
+pub fn health_handler(req: &mut Request) -> IronResult<Response> {
+    let jobs = ctry!(req, heartbeats(&mut *extension!(req, Pool).get()?));
+    let lock = ctry!(req, extension!(req, BuildQueue).lock_state());
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "jobs": jobs,
+            "queue": {
+                "locked": lock.is_some(),
+                "reason": lock.as_ref().and_then(|lock| lock.reason.as_ref()),
+                "locked_at": lock.as_ref().map(|lock| lock.locked_at),
+            },
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::heartbeat::{record_job_start, record_job_success};
+    use crate::test::wrapper;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn jobs_heartbeats() {
+        wrapper(|env| {
+            let get = || -> Result<Value, failure::Error> {
+                Ok(env.frontend().get("/api/v1/health").send()?.json()?)
+            };
+            assert_eq!(get()?["jobs"], json!([]));
+
+            let mut conn = env.db().conn();
+            record_job_start(&mut conn, "github stats updater")?;
+            record_job_start(&mut conn, "crate owners updater")?;
+            record_job_success(&mut conn, "crate owners updater")?;
+
+            let body = get()?;
+            let jobs = body["jobs"].as_array().unwrap();
+            assert_eq!(jobs.len(), 2);
+            assert_eq!(jobs[0]["job"], "crate owners updater");
+            assert!(jobs[0]["last_start"].is_string());
+            assert!(jobs[0]["last_success"].is_string());
+            assert_eq!(jobs[1]["job"], "github stats updater");
+            assert!(jobs[1]["last_start"].is_string());
+            assert_eq!(jobs[1]["last_success"], Value::Null);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queue_lock() {
+        wrapper(|env| {
+            let get = || -> Result<Value, failure::Error> {
+                Ok(env.frontend().get("/api/v1/health").send()?.json()?)
+            };
+            assert_eq!(
+                get()?["queue"],
+                json!({ "locked": false, "reason": null, "locked_at": null })
+            );
+
+            env.build_queue().lock(Some("storage outage"))?;
+            let queue = get()?["queue"].clone();
+            assert_eq!(queue["locked"], true);
+            assert_eq!(queue["reason"], "storage outage");
+            assert!(queue["locked_at"].is_string());
+
+            env.build_queue().unlock()?;
+            assert_eq!(get()?["queue"]["locked"], false);
+
+            Ok(())
+        });
+    }
+}
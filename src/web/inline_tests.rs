@@ -0,0 +1,179 @@
+//! The test functions written in the sources of releases, extracted during the build
+
+use super::json_response;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use postgres::{Client, Row};
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(super) struct InlineTest {
+    pub(super) module_path: String,
+    pub(super) name: String,
+    pub(super) source_code: String,
+}
+
+impl InlineTest {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            module_path: row.get("module_path"),
+            name: row.get("test_name"),
+            source_code: row.get("source_code"),
+        }
+    }
+}
+
+/// The test functions of a release.
+fn get_inline_tests(conn: &mut Client, release_id: i32) -> Result<Vec<InlineTest>, failure::Error> {
+    Ok(conn
+        .query(
+            "SELECT module_path, test_name, source_code
+             FROM inline_tests
+             WHERE release_id = $1
+             ORDER BY module_path, id",
+            &[&release_id],
+        )?
+        .iter()
+        .map(InlineTest::from_row)
+        .collect())
+}
+
+/// The test functions shown in the rustdoc page at `inner_path`, which are the ones of the module
+/// it documents and of its `tests` or `test` child module, as that private module has no page of
+/// its own. The pages of the other items have no tests.
+pub(super) fn module_inline_tests(
+    conn: &mut Client,
+    release_id: i32,
+    inner_path: &str,
+) -> Result<Vec<InlineTest>, failure::Error> {
+    let module_path = match module_path(inner_path) {
+        Some(module_path) => module_path,
+        None => return Ok(Vec::new()),
+    };
+    Ok(conn
+        .query(
+            "SELECT module_path, test_name, source_code
+             FROM inline_tests
+             WHERE release_id = $1
+                AND module_path IN ($2, $2 || '::tests', $2 || '::test')
+             ORDER BY module_path, id",
+            &[&release_id, &module_path],
+        )?
+        .iter()
+        .map(InlineTest::from_row)
+        .collect())
+}
+
+/// Returns the path of the module documented in the rustdoc page at `inner_path`.
+fn module_path(inner_path: &str) -> Option<String> {
+    let module = inner_path.strip_suffix("/index.html")?;
+    if module.is_empty() {
+        return None;
+    }
+    Some(module.replace('/', "::"))
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/inline-tests`.
+///
+/// Lists the `#[test]` functions of the library with the modules they're in. Releases without any
+/// are flagged as `untested`, and the ones with too many to keep them all as `truncated`.
+pub fn inline_tests_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.id
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let release_id: i32 = match row {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+
+    let row = ctry!(
+        req,
+        conn.query_one(
+            "SELECT inline_test_count, inline_tests_truncated FROM releases WHERE id = $1",
+            &[&release_id],
+        )
+    );
+    let (count, truncated): (Option<i32>, bool) = (row.get(0), row.get(1));
+    let tests = ctry!(req, get_inline_tests(&mut conn, release_id));
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "test_count": count,
+            "untested": count == Some(0),
+            "truncated": truncated,
+            "tests": tests,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    const LIB: &[u8] = b"pub fn one() -> i32 {\n    1\n}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn returns_one() {\n        assert_eq!(super::one(), 1);\n    }\n}\n";
+
+    #[test]
+    fn api_lists_inline_tests() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .source_file("src/lib.rs", LIB)
+                .create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+
+            let get = |url: &str| -> Result<(StatusCode, Value), failure::Error> {
+                let response = env.frontend().get(url).send()?;
+                Ok((response.status(), response.json()?))
+            };
+            let (status, body) = get("/api/v1/crates/dummy/0.1.0/inline-tests")?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body,
+                json!({
+                    "test_count": 1,
+                    "untested": false,
+                    "truncated": false,
+                    "tests": [{
+                        "module_path": "dummy::tests",
+                        "name": "returns_one",
+                        "source_code":
+                            "#[test]\nfn returns_one() {\n    assert_eq!(super::one(), 1);\n}",
+                    }],
+                })
+            );
+
+            let (_, body) = get("/api/v1/crates/dummy/0.2.0/inline-tests")?;
+            assert_eq!(
+                body,
+                json!({ "test_count": 0, "untested": true, "truncated": false, "tests": [] })
+            );
+
+            let (status, _) = get("/api/v1/crates/dummy/0.3.0/inline-tests")?;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,222 @@
+//! Whether the licenses of the dependencies of a crate can be combined
+
+use super::dependency_tree::resolve_dependency_tree;
+use super::{json_response, match_version};
+use crate::db::Pool;
+use crate::utils::{are_compatible, license_alternatives};
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct LicenseIssue {
+    #[serde(rename = "crate")]
+    krate: String,
+    license: String,
+    issue: String,
+    /// The crate whose license conflicts with this one
+    conflicts_with: String,
+}
+
+/// A crate of the dependency tree, with the alternative sets of licenses it can be used under.
+struct LicensedCrate {
+    name: String,
+    alternatives: Vec<Vec<String>>,
+}
+
+/// Returns the first pair of licenses making the licenses of `a` and `b` incompatible, or `None`
+/// if they can be combined with one of their alternatives.
+fn incompatibility<'a>(a: &'a LicensedCrate, b: &'a LicensedCrate) -> Option<(&'a str, &'a str)> {
+    let mut first_conflict = None;
+    for a_licenses in &a.alternatives {
+        for b_licenses in &b.alternatives {
+            let conflict = a_licenses.iter().find_map(|a_license| {
+                b_licenses
+                    .iter()
+                    .find(|b_license| !are_compatible(a_license, b_license))
+                    .map(|b_license| (a_license.as_str(), b_license.as_str()))
+            });
+            match conflict {
+                None => return None,
+                Some(conflict) => {
+                    first_conflict.get_or_insert(conflict);
+                }
+            }
+        }
+    }
+    first_conflict
+}
+
+/// Handler for `GET /api/v1/crates/:name/license-compatibility`.
+///
+/// Walks the normal dependencies of the latest release of the crate, using the newest release of
+/// each dependency matching the requirement, and checks whether the licenses of every pair of
+/// crates of the tree can be combined. Dependencies unknown to docs.rs and licenses that aren't
+/// SPDX expressions are skipped.
+pub fn license_compatibility_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let (version, release_id) =
+        match match_version(&mut conn, name, None).and_then(|matched| matched.assume_exact()) {
+            Ok(matched) => matched.into_parts(),
+            Err(_) => {
+                return Ok(json_response(
+                    status::NotFound,
+                    json!({ "error": format!("crate {} not found", name) }),
+                ))
+            }
+        };
+
+    let mut crates = Vec::new();
+    let mut licenses = BTreeSet::new();
+    for krate in ctry!(req, resolve_dependency_tree(&mut conn, release_id)).crates {
+        if let Some(license) = krate.license {
+            if let Some(alternatives) = license_alternatives(&license) {
+                licenses.insert(license);
+                crates.push(LicensedCrate {
+                    name: krate.name,
+                    alternatives,
+                });
+            }
+        }
+    }
+
+    let mut issues = Vec::new();
+    for (i, krate) in crates.iter().enumerate() {
+        for dependency in &crates[i + 1..] {
+            if let Some((license, other)) = incompatibility(krate, dependency) {
+                issues.push(LicenseIssue {
+                    krate: dependency.name.clone(),
+                    license: other.to_string(),
+                    issue: format!("incompatible with {}", license),
+                    conflicts_with: krate.name.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "crate": name,
+            "version": version,
+            "licenses": licenses,
+            "compatible": issues.is_empty(),
+            "issues": issues,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn compatible_dependencies() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dep")
+                .version("1.2.0")
+                .license("MIT OR Apache-2.0")
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .license("GPL-2.0")
+                .add_dependency("dep", "^1.0", None)
+                // Development dependencies aren't part of the crate
+                .add_dependency("dev-dep", "^1.0", Some("dev"))
+                .create()?;
+            env.fake_release()
+                .name("dev-dep")
+                .version("1.0.0")
+                .license("Apache-2.0")
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/foo/license-compatibility")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            assert_eq!(
+                body,
+                json!({
+                    "crate": "foo",
+                    "version": "0.1.0",
+                    "licenses": ["GPL-2.0", "MIT OR Apache-2.0"],
+                    "compatible": true,
+                    "issues": [],
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn incompatible_dependency_chain() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("apache")
+                .version("1.0.0")
+                .license("Apache-2.0")
+                .create()?;
+            // Only the newest release matching the requirement is checked
+            env.fake_release()
+                .name("apache")
+                .version("2.0.0")
+                .license("MIT")
+                .create()?;
+            env.fake_release()
+                .name("middle")
+                .version("0.3.1")
+                .license("MIT")
+                .add_dependency("apache", "^1", None)
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .license("GPL-2.0-only")
+                .add_dependency("middle", "0.3", None)
+                .create()?;
+
+            let body: Value = env
+                .frontend()
+                .get("/api/v1/crates/foo/license-compatibility")
+                .send()?
+                .json()?;
+            assert_eq!(body["compatible"], false);
+            assert_eq!(
+                body["issues"],
+                json!([{
+                    "crate": "apache",
+                    "license": "Apache-2.0",
+                    "issue": "incompatible with GPL-2.0-only",
+                    "conflicts_with": "foo",
+                }])
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn missing_crate() {
+        wrapper(|env| {
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/missing/license-compatibility")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,286 @@
+//! Middleware shared between multiple routes
+
+use crate::Config;
+use iron::{headers::Connection, status, Handler, IronResult, Request, Response};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CSP_HEADER: &str = "Content-Security-Policy";
+
+/// Builds the value of the `Content-Security-Policy` header from the configuration.
+///
+/// Directives configured with an empty source list are left out of the policy, which allows
+/// deployers to drop a directive entirely instead of only relaxing it.
+pub(super) fn content_security_policy(config: &Config) -> String {
+    // The Mermaid.js script is loaded by the pages with diagrams, and the KaTeX script,
+    // stylesheet and fonts by the pages with math. A source ending with a slash allows all the
+    // files under it.
+    let katex_src = format!("{}/", config.katex_url.trim_end_matches('/'));
+    let mut script_src = vec![config.csp_script_src.as_str()];
+    let mut style_src = vec![config.csp_style_src.as_str()];
+    let mut font_src = None;
+    if config.enable_mermaid {
+        script_src.push(config.mermaid_script_url.as_str());
+    }
+    if config.enable_math {
+        script_src.push(katex_src.as_str());
+        style_src.push(katex_src.as_str());
+        font_src = Some(vec![config.csp_default_src.as_str(), katex_src.as_str()]);
+    }
+    // Sources are only added to the directives that aren't dropped
+    let extend = |sources: Vec<&str>| {
+        if sources[0].trim().is_empty() {
+            String::new()
+        } else {
+            sources.join(" ")
+        }
+    };
+    let (script_src, style_src, font_src) =
+        (extend(script_src), extend(style_src), font_src.map(extend));
+    let directives = [
+        ("default-src", Some(config.csp_default_src.as_str())),
+        ("script-src", Some(script_src.as_str())),
+        ("style-src", Some(style_src.as_str())),
+        ("img-src", Some(config.csp_img_src.as_str())),
+        ("font-src", font_src.as_deref()),
+        ("report-uri", config.csp_report_uri.as_deref()),
+    ];
+
+    directives
+        .iter()
+        .filter_map(|(name, sources)| match sources.map(str::trim) {
+            Some(sources) if !sources.is_empty() => Some(format!("{} {}", name, sources)),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Wraps a handler serving documentation, adding a `Content-Security-Policy` header to all of
+/// its responses.
+///
+/// Documentation pages include arbitrary content from doc comments, so the policy restricts
+/// what that content is allowed to load and execute.
+pub(super) struct ContentSecurityPolicy {
+    handler: Box<dyn Handler>,
+}
+
+impl ContentSecurityPolicy {
+    pub(super) fn new(handler: impl Handler) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+}
+
+impl Handler for ContentSecurityPolicy {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let policy = content_security_policy(extension!(req, Config));
+
+        match self.handler.handle(req) {
+            Ok(mut response) => {
+                response
+                    .headers
+                    .set_raw(CSP_HEADER, vec![policy.into_bytes()]);
+                Ok(response)
+            }
+            Err(mut err) => {
+                err.response
+                    .headers
+                    .set_raw(CSP_HEADER, vec![policy.into_bytes()]);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The requests in progress in the web server, which stops handling new requests once the daemon
+/// is shutting down.
+#[derive(Debug, Default)]
+pub(crate) struct InFlightRequests {
+    draining: AtomicBool,
+    count: AtomicUsize,
+}
+
+impl InFlightRequests {
+    /// Rejects the new requests, and waits up to `timeout` for the ones in progress to finish.
+    /// Returns how many requests were still in progress after the timeout.
+    pub(crate) fn drain(&self, timeout: Duration) -> usize {
+        self.draining.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        loop {
+            let count = self.count.load(Ordering::SeqCst);
+            if count == 0 || start.elapsed() >= timeout {
+                return count;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Decrements the count of requests in progress when dropped, even if the handler panicked.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wraps the handler of the web server, counting the requests in progress and answering the new
+/// ones with `503 Service Unavailable` while draining.
+///
+/// Iron can't stop accepting connections, so the new requests are rejected instead, and their
+/// connections closed so that clients and load balancers retry elsewhere.
+pub(super) struct Drain {
+    handler: Box<dyn Handler>,
+    requests: Arc<InFlightRequests>,
+}
+
+impl Drain {
+    pub(super) fn new(handler: impl Handler, requests: Arc<InFlightRequests>) -> Self {
+        Self {
+            handler: Box::new(handler),
+            requests,
+        }
+    }
+}
+
+impl Handler for Drain {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        // The request is counted before checking whether the server is draining, so that
+        // `InFlightRequests::drain` can't miss it
+        self.requests.count.fetch_add(1, Ordering::SeqCst);
+        let _in_flight = InFlight(&self.requests.count);
+
+        if self.requests.draining.load(Ordering::SeqCst) {
+            let mut response =
+                Response::with((status::ServiceUnavailable, "the server is shutting down"));
+            response.headers.set(Connection::close());
+            return Ok(response);
+        }
+        self.handler.handle(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestServer};
+
+    fn csp_header(path: &str, web: &crate::test::TestFrontend) -> Option<String> {
+        let response = web.get(path).send().unwrap();
+        response
+            .headers()
+            .get(CSP_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn default_policy_on_rustdoc_pages() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let header = csp_header("/dummy/0.1.0/dummy/", env.frontend())
+                .expect("missing Content-Security-Policy header");
+            let directives: Vec<&str> = header.split("; ").collect();
+
+            assert_eq!(
+                directives,
+                vec![
+                    "default-src 'self'",
+                    "script-src 'self' 'unsafe-inline'",
+                    "style-src 'self' 'unsafe-inline'",
+                    "img-src 'self' data: https:",
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn policy_on_all_rustdoc_routes() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            assert!(csp_header("/dummy/badge.svg", web).is_some());
+            assert!(csp_header("/dummy/0.1.0/dummy/index.html", web).is_some());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn no_policy_on_internal_pages() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            assert!(csp_header("/crate/dummy/0.1.0", env.frontend()).is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn configured_policy() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.csp_script_src = "'self' https://play.rust-lang.org".into();
+                config.csp_img_src = String::new();
+                config.csp_report_uri = Some("https://example.com/csp-report".into());
+            });
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            let header = csp_header("/dummy/0.1.0/dummy/", env.frontend())
+                .expect("missing Content-Security-Policy header");
+            assert_eq!(
+                header,
+                "default-src 'self'; \
+                 script-src 'self' https://play.rust-lang.org; \
+                 style-src 'self' 'unsafe-inline'; \
+                 report-uri https://example.com/csp-report"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn drain_waits_for_requests_in_progress() {
+        let requests = Arc::new(InFlightRequests::default());
+        let handler = Drain::new(
+            |_: &mut Request| {
+                thread::sleep(Duration::from_millis(500));
+                Ok(Response::with((status::Ok, "slow")))
+            },
+            requests.clone(),
+        );
+        let server = TestServer::start(handler).unwrap();
+        let url = format!("{}/", server.url());
+
+        let in_progress = {
+            let url = url.clone();
+            thread::spawn(move || reqwest::blocking::get(&url).unwrap().status())
+        };
+        while requests.count.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(requests.drain(Duration::from_secs(10)), 0);
+        assert_eq!(in_progress.join().unwrap(), reqwest::StatusCode::OK);
+        let response = reqwest::blocking::get(&url).unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn drain_times_out() {
+        let requests = InFlightRequests::default();
+        requests.count.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(requests.drain(Duration::from_millis(200)), 1);
+    }
+}
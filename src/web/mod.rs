@@ -77,19 +77,49 @@ macro_rules! extension {
     }};
 }
 
+mod api_diff;
+mod archive;
+mod audit;
+mod authors;
 mod builds;
+mod compile_times;
+mod cooccurrence;
+mod coverage;
 mod crate_details;
+mod crate_health;
+mod crate_metrics;
+mod dependency_licenses;
+mod dependency_tree;
+mod dependents;
+mod doc_warnings;
 mod error;
+mod examples;
 mod extensions;
 mod features;
 mod file;
+mod github_webhook;
+mod health;
+mod inline_tests;
+mod license_compatibility;
 pub(crate) mod metrics;
+mod middleware;
+mod new_crates;
+mod notifications;
+mod owners;
+mod platforms;
+mod readme;
+mod rebuild;
 mod releases;
 mod routes;
 mod rustdoc;
+mod rustdoc_json;
+mod sbom;
 mod sitemap;
 mod source;
 mod statics;
+mod type_graph;
+mod type_search;
+mod webhook;
 
 use crate::{impl_webpage, Context};
 use chrono::{DateTime, Utc};
@@ -98,13 +128,14 @@ use extensions::InjectExtensions;
 use failure::Error;
 use iron::{
     self,
-    headers::{Expires, HttpDate},
+    headers::{CacheControl, CacheDirective, ContentType, Expires, HttpDate},
     modifiers::Redirect,
     status,
     status::Status,
     Chain, Handler, Iron, IronError, IronResult, Listening, Request, Response, Url,
 };
 use metrics::RequestRecorder;
+use middleware::InFlightRequests;
 use page::TemplateData;
 use postgres::Client;
 use router::NoRoute;
@@ -405,6 +436,7 @@ fn render_markdown(text: &str) -> String {
 #[must_use = "`Server` blocks indefinitely when dropped"]
 pub struct Server {
     inner: Listening,
+    requests: Arc<InFlightRequests>,
 }
 
 impl Server {
@@ -430,17 +462,23 @@ impl Server {
         context: &dyn Context,
     ) -> Result<Self, Error> {
         let cratesfyi = CratesfyiHandler::new(template_data, context)?;
-        let inner = Iron::new(cratesfyi)
+        let requests = Arc::new(InFlightRequests::default());
+        let inner = Iron::new(middleware::Drain::new(cratesfyi, requests.clone()))
             .http(addr)
             .unwrap_or_else(|_| panic!("Failed to bind to socket on {}", addr));
 
-        Ok(Server { inner })
+        Ok(Server { inner, requests })
     }
 
     pub(crate) fn addr(&self) -> SocketAddr {
         self.inner.socket
     }
 
+    /// The requests in progress, to drain them when shutting down.
+    pub(crate) fn requests(&self) -> Arc<InFlightRequests> {
+        self.requests.clone()
+    }
+
     /// Iron is bugged, and it never closes the server even when the listener is dropped. To
     /// avoid never-ending tests this method forgets about the server, leaking it and allowing the
     /// program to end.
@@ -491,6 +529,31 @@ fn redirect(url: Url) -> Response {
     resp
 }
 
+/// Creates an uncached JSON `Response`, used by the API endpoints.
+fn json_response(status: Status, body: Value) -> Response {
+    let mut resp = Response::with((status, body.to_string()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(Expires(HttpDate(time::now())));
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::NoCache,
+        CacheDirective::NoStore,
+        CacheDirective::MustRevalidate,
+    ]));
+
+    resp
+}
+
+/// Compares a secret sent with a request to the expected one in constant time, so the time taken
+/// doesn't tell how much of the secret was guessed.
+fn secrets_match(sent: &[u8], expected: &[u8]) -> bool {
+    sent.len() == expected.len()
+        && sent
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
 fn redirect_base(req: &Request) -> String {
     // Try to get the scheme from CloudFront first, and then from iron
     let scheme = req
@@ -520,7 +583,11 @@ pub(crate) struct MetaData {
     pub(crate) rustdoc_status: bool,
     pub(crate) default_target: String,
     pub(crate) doc_targets: Vec<String>,
+    /// The targets whose documentation failed to build
+    pub(crate) failed_targets: Vec<String>,
     pub(crate) yanked: bool,
+    /// Procedural macros are only documented for the host, so they don't offer other targets
+    pub(crate) is_proc_macro: bool,
 }
 
 impl MetaData {
@@ -534,7 +601,9 @@ impl MetaData {
                        releases.rustdoc_status,
                        releases.default_target,
                        releases.doc_targets,
-                       releases.yanked
+                       releases.yanked,
+                       releases.failed_targets,
+                       releases.is_proc_macro
                 FROM releases
                 INNER JOIN crates ON crates.id = releases.crate_id
                 WHERE crates.name = $1 AND releases.version = $2",
@@ -552,7 +621,9 @@ impl MetaData {
             rustdoc_status: row.get(4),
             default_target: row.get(5),
             doc_targets: MetaData::parse_doc_targets(row.get(6)),
+            failed_targets: row.get(8),
             yanked: row.get(7),
+            is_proc_macro: row.get(9),
         })
     }
 
@@ -591,6 +662,14 @@ mod test {
     use kuchiki::traits::TendrilSink;
     use serde_json::json;
 
+    #[test]
+    fn test_secrets_match() {
+        assert!(secrets_match(b"secret", b"secret"));
+        assert!(!secrets_match(b"secreT", b"secret"));
+        assert!(!secrets_match(b"secret", b"secret-but-longer"));
+        assert!(!secrets_match(b"", b"secret"));
+    }
+
     fn release(version: &str, env: &TestEnvironment) -> i32 {
         env.fake_release()
             .name("foo")
@@ -874,7 +953,9 @@ mod test {
                 "x86_64-unknown-linux-gnu".to_string(),
                 "arm64-unknown-linux-gnu".to_string(),
             ],
+            failed_targets: vec!["thumbv7em-none-eabihf".to_string()],
             yanked: false,
+            is_proc_macro: false,
         };
 
         let correct_json = json!({
@@ -888,7 +969,9 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "failed_targets": ["thumbv7em-none-eabihf"],
             "yanked": false,
+            "is_proc_macro": false,
         });
 
         assert_eq!(correct_json, serde_json::to_value(&metadata).unwrap());
@@ -905,7 +988,9 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "failed_targets": ["thumbv7em-none-eabihf"],
             "yanked": false,
+            "is_proc_macro": false,
         });
 
         assert_eq!(correct_json, serde_json::to_value(&metadata).unwrap());
@@ -922,7 +1007,9 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "failed_targets": ["thumbv7em-none-eabihf"],
             "yanked": false,
+            "is_proc_macro": false,
         });
 
         assert_eq!(correct_json, serde_json::to_value(&metadata).unwrap());
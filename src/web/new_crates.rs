@@ -0,0 +1,330 @@
+//! The crates documented for the first time recently
+
+use super::json_response;
+use crate::db::Pool;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use iron::headers::{CacheControl, CacheDirective, ContentType};
+use iron::{status, IronResult, Request, Response};
+use serde::Serialize;
+use serde_json::json;
+
+/// The period covered when the request doesn't specify one, in hours.
+const DEFAULT_PERIOD_HOURS: i64 = 24;
+/// The number of crates in each page.
+const PAGE_SIZE: i64 = 100;
+/// How long the responses can be cached, in seconds.
+const CACHE_DURATION: u32 = 5 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct NewCrate {
+    name: String,
+    /// The latest version of the crate
+    version: String,
+    description: Option<String>,
+    stars: i32,
+    /// When the first successful build of the crate finished
+    documented_at: DateTime<Utc>,
+    /// Orders the crates documented at the same time, for the cursor of the next page
+    #[serde(skip)]
+    crate_id: i32,
+}
+
+/// The range of the crates requested in the query string.
+#[derive(Debug, PartialEq)]
+struct NewCratesQuery {
+    since: DateTime<Utc>,
+    /// Only the crates after this one are returned, which is the `documented_at` and the id of
+    /// the last crate of the previous page
+    cursor: Option<(DateTime<Utc>, i32)>,
+}
+
+fn new_crates_query(req: &Request, now: DateTime<Utc>) -> Result<NewCratesQuery, String> {
+    let url: iron::url::Url = req.url.clone().into();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let since = param("since")
+        .map(|since| {
+            DateTime::parse_from_rfc3339(&since)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|_| "invalid since, expected a time like 2024-01-15T00:00:00Z".to_string())
+        })
+        .transpose()?;
+    let cursor =
+        match param("cursor") {
+            Some(cursor) => Some(parse_cursor(&cursor).ok_or_else(|| {
+                "invalid cursor, expected the one of the `Link` header".to_string()
+            })?),
+            None => None,
+        };
+
+    Ok(NewCratesQuery {
+        since: since.unwrap_or_else(|| now - Duration::hours(DEFAULT_PERIOD_HOURS)),
+        cursor,
+    })
+}
+
+/// Parses a cursor like `2024-01-15T00:00:00+00:00,1234`, the time a crate was documented and
+/// its id.
+fn parse_cursor(cursor: &str) -> Option<(DateTime<Utc>, i32)> {
+    let (time, id) = cursor.rsplit_once(',')?;
+    let time = DateTime::parse_from_rfc3339(time).ok()?;
+    Some((time.with_timezone(&Utc), id.parse().ok()?))
+}
+
+/// Handler for `GET /api/v1/crates/new?since=:time&cursor=:time`.
+///
+/// Returns the crates whose first successful build finished since `since`, the past day by
+/// default, most recently documented first. When there are more crates than fit in a page, the
+/// URL of the next page, whose `cursor` points after the last crate, is in the `Link` header.
+pub fn new_crates_api_handler(req: &mut Request) -> IronResult<Response> {
+    let query = match new_crates_query(req, Utc::now()) {
+        Ok(query) => query,
+        Err(err) => return Ok(json_response(status::BadRequest, json!({ "error": err }))),
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    // Only the builds in the range are aggregated, and the crates built successfully before
+    // are filtered out afterwards
+    let crates: Vec<NewCrate> = ctry!(
+        req,
+        conn.query(
+            "SELECT
+                crates.id,
+                crates.name,
+                releases.version,
+                releases.description,
+                COALESCE(github_repos.stars, 0) AS stars,
+                first_builds.documented_at
+             FROM (
+                SELECT releases.crate_id, MIN(builds.build_time) AS documented_at
+                FROM builds
+                INNER JOIN releases ON releases.id = builds.rid
+                WHERE builds.build_status = TRUE
+                    AND builds.build_time >= $1
+                    AND ($2::TIMESTAMP IS NULL OR builds.build_time <= $2)
+                GROUP BY releases.crate_id
+             ) AS first_builds
+             INNER JOIN crates ON crates.id = first_builds.crate_id
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             LEFT JOIN github_repos ON github_repos.id = releases.github_repo
+             WHERE NOT EXISTS (
+                SELECT 1
+                FROM builds
+                INNER JOIN releases AS built ON built.id = builds.rid
+                WHERE built.crate_id = first_builds.crate_id
+                    AND builds.build_status = TRUE
+                    AND builds.build_time < first_builds.documented_at
+             )
+             -- The crates documented at the time of the cursor are ordered by id, so that none
+             -- is skipped nor repeated by the next page
+             AND ($2::TIMESTAMP IS NULL OR (first_builds.documented_at, crates.id) < ($2, $3))
+             ORDER BY first_builds.documented_at DESC, crates.id DESC
+             LIMIT $4",
+            &[
+                &query.since.naive_utc(),
+                &query.cursor.map(|(time, _)| time.naive_utc()),
+                &query.cursor.map(|(_, id)| id),
+                &PAGE_SIZE,
+            ],
+        )
+    )
+    .into_iter()
+    .map(|row| NewCrate {
+        name: row.get("name"),
+        version: row.get("version"),
+        description: row.get("description"),
+        stars: row.get("stars"),
+        documented_at: DateTime::from_utc(row.get::<_, NaiveDateTime>("documented_at"), Utc),
+        crate_id: row.get("id"),
+    })
+    .collect();
+
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&crates).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(CACHE_DURATION),
+    ]));
+    if crates.len() as i64 == PAGE_SIZE {
+        if let Some(last) = crates.last() {
+            let mut next: iron::url::Url = req.url.clone().into();
+            next.query_pairs_mut()
+                .clear()
+                .append_pair("since", &query.since.to_rfc3339())
+                .append_pair(
+                    "cursor",
+                    &format!("{},{}", last.documented_at.to_rfc3339(), last.crate_id),
+                );
+            resp.headers.set_raw(
+                "Link",
+                vec![format!("<{}>; rel=\"next\"", next).into_bytes()],
+            );
+        }
+    }
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+    use reqwest::StatusCode;
+    use serde_json::Value;
+
+    fn set_build_time(
+        env: &TestEnvironment,
+        release_id: i32,
+        time: &str,
+    ) -> Result<(), failure::Error> {
+        env.db().conn().execute(
+            "UPDATE builds SET build_time = $2::TEXT::TIMESTAMP WHERE rid = $1",
+            &[&release_id, &time],
+        )?;
+        Ok(())
+    }
+
+    fn crate_id(env: &TestEnvironment, name: &str) -> Result<i32, failure::Error> {
+        Ok(env
+            .db()
+            .conn()
+            .query_one("SELECT id FROM crates WHERE name = $1", &[&name])?
+            .get(0))
+    }
+
+    fn names(body: &Value) -> Vec<&str> {
+        body.as_array()
+            .unwrap()
+            .iter()
+            .map(|krate| krate["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn list_new_crates() {
+        wrapper(|env| {
+            let id = env
+                .fake_release()
+                .name("fresh")
+                .version("0.1.0")
+                .github_stats("fresh/fresh", 12, 1, 0)
+                .create()?;
+            set_build_time(env, id, "2024-01-15 12:00:00")?;
+            let id = env
+                .fake_release()
+                .name("fresher")
+                .version("0.1.0")
+                .create()?;
+            set_build_time(env, id, "2024-01-15 18:00:00")?;
+            // Crates documented before the range aren't new, even with new releases
+            let id = env.fake_release().name("old").version("1.0.0").create()?;
+            set_build_time(env, id, "2023-06-01 00:00:00")?;
+            let id = env.fake_release().name("old").version("1.1.0").create()?;
+            set_build_time(env, id, "2024-01-15 13:00:00")?;
+            // Crates whose builds failed aren't documented
+            let id = env
+                .fake_release()
+                .name("broken")
+                .version("0.1.0")
+                .build_result_successful(false)
+                .create()?;
+            set_build_time(env, id, "2024-01-15 14:00:00")?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/new?since=2024-01-15T00:00:00Z")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers()["Cache-Control"].to_str()?,
+                "public, max-age=300"
+            );
+            assert!(resp.headers().get("Link").is_none());
+            let body: Value = resp.json()?;
+            assert_eq!(names(&body), vec!["fresher", "fresh"]);
+            assert_eq!(
+                body[1],
+                json!({
+                    "name": "fresh",
+                    "version": "0.1.0",
+                    "description": "Fake package",
+                    "stars": 12,
+                    "documented_at": "2024-01-15T12:00:00Z",
+                })
+            );
+            assert_eq!(body[0]["stars"], 0);
+
+            // The next pages only have the crates after the cursor
+            let url = format!(
+                "/api/v1/crates/new?since=2024-01-15T00:00:00Z&cursor=2024-01-15T18:00:00Z,{}",
+                crate_id(env, "fresher")?
+            );
+            let body: Value = env.frontend().get(&url).send()?.json()?;
+            assert_eq!(names(&body), vec!["fresh"]);
+
+            // The past day is listed by default
+            let body: Value = env.frontend().get("/api/v1/crates/new").send()?.json()?;
+            assert_eq!(names(&body), Vec::<&str>::new());
+
+            for url in &[
+                "/api/v1/crates/new?since=yesterday",
+                "/api/v1/crates/new?since=2024-01-15",
+                "/api/v1/crates/new?cursor=2024-01-15T18:00:00",
+                "/api/v1/crates/new?cursor=2024-01-15T18:00:00Z",
+                "/api/v1/crates/new?cursor=2024-01-15T18:00:00Z,fresher",
+            ] {
+                let resp = env.frontend().get(url).send()?;
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", url);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn paginate_new_crates() {
+        wrapper(|env| {
+            // The crates documented at the same time are split between the pages
+            for idx in 0..PAGE_SIZE + 1 {
+                let id = env
+                    .fake_release()
+                    .name(&format!("krate-{}", idx))
+                    .version("0.1.0")
+                    .create()?;
+                env.db().conn().execute(
+                    "UPDATE builds SET build_time = date_trunc('second', NOW()) WHERE rid = $1",
+                    &[&id],
+                )?;
+            }
+
+            let resp = env.frontend().get("/api/v1/crates/new").send()?;
+            let link = resp.headers()["Link"].to_str()?.to_string();
+            let body: Value = resp.json()?;
+            assert_eq!(body.as_array().unwrap().len(), PAGE_SIZE as usize);
+            let mut listed: Vec<String> = names(&body).into_iter().map(String::from).collect();
+
+            let next = link
+                .strip_prefix('<')
+                .and_then(|link| link.strip_suffix(">; rel=\"next\""))
+                .expect("invalid Link header");
+            let next: iron::url::Url = iron::url::Url::parse(next)?;
+            let body: Value = env
+                .frontend()
+                .get(&format!("{}?{}", next.path(), next.query().unwrap()))
+                .send()?
+                .json()?;
+            assert_eq!(body.as_array().unwrap().len(), 1);
+            listed.extend(names(&body).into_iter().map(String::from));
+
+            // Every crate is listed once
+            listed.sort();
+            listed.dedup();
+            assert_eq!(listed.len(), PAGE_SIZE as usize + 1);
+
+            Ok(())
+        });
+    }
+}
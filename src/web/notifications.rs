@@ -0,0 +1,391 @@
+//! Admin endpoints subscribing the owners of crates to the failures of their builds
+
+use super::{json_response, rebuild::admin_api_key};
+use crate::{
+    db::Pool,
+    notifications::{NotificationMethod, BUILD_FAILED, EVENTS},
+    Config,
+};
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::io::Read;
+
+const MAX_BODY_SIZE: u64 = 16 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct SubscribeRequest {
+    /// The login of the owner of the crate subscribing
+    owner: String,
+    method: NotificationMethod,
+    /// The email address or the URL of the webhook
+    endpoint: String,
+    #[serde(default = "all_events")]
+    events: Vec<String>,
+}
+
+fn all_events() -> Vec<String> {
+    vec![BUILD_FAILED.into()]
+}
+
+#[derive(Debug, Deserialize)]
+struct UnsubscribeRequest {
+    method: NotificationMethod,
+    endpoint: String,
+}
+
+/// Handler for `POST /api/v1/crates/:name/notifications`.
+///
+/// Requests must include one of the configured admin API keys in the `Authorization` header, and
+/// a JSON body with the `owner` subscribing, which must be an owner of the crate, the `method`,
+/// either `email` or `webhook`, the `endpoint` the notifications are sent to, and optionally the
+/// `events` to be notified of. Subscribing the same endpoint again replaces its events.
+pub fn subscribe_handler(req: &mut Request) -> IronResult<Response> {
+    let body = ctry!(req, read_body(req));
+    if admin_api_key(req, extension!(req, Config)).is_none() {
+        return Ok(unauthorized());
+    }
+    let subscription: SubscribeRequest = match parse_body(&body) {
+        Ok(subscription) => subscription,
+        Err(response) => return Ok(response),
+    };
+
+    if subscription.events.is_empty() {
+        return Ok(bad_request("at least one event is required".into()));
+    }
+    if let Some(event) = subscription
+        .events
+        .iter()
+        .find(|event| !EVENTS.contains(&event.as_str()))
+    {
+        return Ok(bad_request(format!(
+            "unknown event `{}`, expected one of {}",
+            event,
+            EVENTS.join(", ")
+        )));
+    }
+    if let Err(err) = validate_endpoint(subscription.method, &subscription.endpoint) {
+        return Ok(bad_request(err));
+    }
+
+    let name = cexpect!(req, extension!(req, Router).find("name"));
+    let mut conn = extension!(req, Pool).get()?;
+    let crate_id = match ctry!(req, crate_id(&mut conn, name)) {
+        Some(id) => id,
+        None => return Ok(crate_not_found(name)),
+    };
+    let owner = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT owners.id
+             FROM owners
+             INNER JOIN owner_rels ON owner_rels.oid = owners.id
+             WHERE owner_rels.cid = $1 AND owners.login = $2;",
+            &[&crate_id, &subscription.owner],
+        )
+    );
+    let user_id: i32 = match owner {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(bad_request(format!(
+                "{} isn't an owner of {}",
+                subscription.owner, name
+            )))
+        }
+    };
+
+    let id: i32 = ctry!(
+        req,
+        conn.query_one(
+            "INSERT INTO notifications (user_id, crate_id, method, endpoint, events)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (crate_id, method, endpoint) DO UPDATE
+                 SET user_id = EXCLUDED.user_id, events = EXCLUDED.events
+             RETURNING id;",
+            &[
+                &user_id,
+                &crate_id,
+                &subscription.method,
+                &subscription.endpoint,
+                &json!(subscription.events),
+            ],
+        )
+    )
+    .get(0);
+
+    Ok(json_response(
+        status::Created,
+        json!({
+            "id": id,
+            "method": subscription.method,
+            "endpoint": subscription.endpoint,
+            "events": subscription.events,
+        }),
+    ))
+}
+
+/// Handler for `DELETE /api/v1/crates/:name/notifications`.
+///
+/// Requests must include one of the configured admin API keys in the `Authorization` header, and
+/// a JSON body with the `method` and the `endpoint` of the subscription to remove.
+pub fn unsubscribe_handler(req: &mut Request) -> IronResult<Response> {
+    let body = ctry!(req, read_body(req));
+    if admin_api_key(req, extension!(req, Config)).is_none() {
+        return Ok(unauthorized());
+    }
+    let subscription: UnsubscribeRequest = match parse_body(&body) {
+        Ok(subscription) => subscription,
+        Err(response) => return Ok(response),
+    };
+
+    let name = cexpect!(req, extension!(req, Router).find("name"));
+    let mut conn = extension!(req, Pool).get()?;
+    let crate_id = match ctry!(req, crate_id(&mut conn, name)) {
+        Some(id) => id,
+        None => return Ok(crate_not_found(name)),
+    };
+    let deleted = ctry!(
+        req,
+        conn.execute(
+            "DELETE FROM notifications WHERE crate_id = $1 AND method = $2 AND endpoint = $3;",
+            &[&crate_id, &subscription.method, &subscription.endpoint],
+        )
+    );
+    if deleted == 0 {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("{} has no such subscription", name) }),
+        ));
+    }
+
+    Ok(json_response(status::Ok, json!({ "deleted": deleted })))
+}
+
+/// Reads the body of the request, which is always done as leaving it in the connection breaks
+/// the following requests.
+fn read_body(req: &mut Request) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    req.body
+        .by_ref()
+        .take(MAX_BODY_SIZE)
+        .read_to_end(&mut body)?;
+    Ok(body)
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, Response> {
+    serde_json::from_slice(body)
+        .map_err(|err| bad_request(format!("invalid request body: {}", err)))
+}
+
+/// Checks that the endpoint is an email address or an HTTP(S) URL, depending on the method.
+fn validate_endpoint(method: NotificationMethod, endpoint: &str) -> Result<(), String> {
+    match method {
+        NotificationMethod::Email => endpoint
+            .parse::<lettre::Address>()
+            .map(|_| ())
+            .map_err(|_| format!("invalid email address: {}", endpoint)),
+        NotificationMethod::Webhook => match url::Url::parse(endpoint) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+            _ => Err(format!("invalid webhook URL: {}", endpoint)),
+        },
+    }
+}
+
+fn crate_id(conn: &mut Client, name: &str) -> Result<Option<i32>, postgres::Error> {
+    Ok(conn
+        .query_opt("SELECT id FROM crates WHERE name = $1;", &[&name])?
+        .map(|row| row.get(0)))
+}
+
+fn unauthorized() -> Response {
+    json_response(
+        status::Unauthorized,
+        json!({ "error": "a valid admin API key is required" }),
+    )
+}
+
+fn bad_request(error: String) -> Response {
+    json_response(status::BadRequest, json!({ "error": error }))
+}
+
+fn crate_not_found(name: &str) -> Response {
+    json_response(
+        status::NotFound,
+        json!({ "error": format!("crate {} not found", name) }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::api::CrateOwner;
+    use crate::test::{wrapper, TestEnvironment};
+    use reqwest::{blocking::RequestBuilder, StatusCode};
+    use serde_json::{json, Value};
+
+    const ADMIN_KEY: &str = "very-secret-key";
+    const URL: &str = "/api/v1/crates/dummy/notifications";
+
+    fn setup(env: &TestEnvironment) -> Result<(), failure::Error> {
+        env.override_config(|config| {
+            config.admin_api_keys = vec![ADMIN_KEY.into()];
+        });
+        env.fake_release()
+            .name("dummy")
+            .version("0.1.0")
+            .add_owner(CrateOwner {
+                login: "foobar".into(),
+                avatar: "https://example.org/foobar".into(),
+                name: "Foo Bar".into(),
+                email: "foobar@example.org".into(),
+                kind: "user".into(),
+            })
+            .create()?;
+        Ok(())
+    }
+
+    fn send(request: RequestBuilder, body: Value) -> Result<(StatusCode, Value), failure::Error> {
+        let response = request
+            .header("Authorization", format!("Bearer {}", ADMIN_KEY))
+            .body(body.to_string())
+            .send()?;
+        Ok((response.status(), response.json()?))
+    }
+
+    fn subscriptions(env: &TestEnvironment) -> Result<Vec<(String, Value)>, failure::Error> {
+        Ok(env
+            .db()
+            .conn()
+            .query(
+                "SELECT endpoint, events FROM notifications ORDER BY id",
+                &[],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe() {
+        wrapper(|env| {
+            setup(env)?;
+            let web = env.frontend();
+
+            let (status, body) = send(
+                web.post(URL),
+                json!({
+                    "owner": "foobar",
+                    "method": "email",
+                    "endpoint": "foobar@example.org",
+                }),
+            )?;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(body["events"], json!(["build_failed"]));
+            let (status, _) = send(
+                web.post(URL),
+                json!({
+                    "owner": "foobar",
+                    "method": "webhook",
+                    "endpoint": "https://example.org/hook",
+                    "events": ["build_failed"],
+                }),
+            )?;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(subscriptions(env)?.len(), 2);
+
+            // Subscribing again doesn't duplicate the subscription
+            send(
+                web.post(URL),
+                json!({
+                    "owner": "foobar",
+                    "method": "email",
+                    "endpoint": "foobar@example.org",
+                }),
+            )?;
+            assert_eq!(subscriptions(env)?.len(), 2);
+
+            let (status, body) = send(
+                web.delete(URL),
+                json!({ "method": "email", "endpoint": "foobar@example.org" }),
+            )?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["deleted"], 1);
+            assert_eq!(
+                subscriptions(env)?,
+                vec![("https://example.org/hook".into(), json!(["build_failed"]))]
+            );
+
+            let (status, _) = send(
+                web.delete(URL),
+                json!({ "method": "email", "endpoint": "foobar@example.org" }),
+            )?;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_subscriptions() {
+        wrapper(|env| {
+            setup(env)?;
+            let web = env.frontend();
+
+            let response = web
+                .post(URL)
+                .body(
+                    json!({
+                        "owner": "foobar",
+                        "method": "email",
+                        "endpoint": "foobar@example.org",
+                    })
+                    .to_string(),
+                )
+                .send()?;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            for (body, expected) in &[
+                (json!({"owner": "foobar"}), StatusCode::BAD_REQUEST),
+                (
+                    json!({"owner": "foobar", "method": "sms", "endpoint": "123"}),
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    json!({"owner": "foobar", "method": "email", "endpoint": "not-an-address"}),
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    json!({"owner": "foobar", "method": "webhook", "endpoint": "ftp://example.org"}),
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    json!({
+                        "owner": "foobar",
+                        "method": "email",
+                        "endpoint": "foobar@example.org",
+                        "events": ["build_succeeded"],
+                    }),
+                    StatusCode::BAD_REQUEST,
+                ),
+                // Only the owners of the crate can subscribe
+                (
+                    json!({"owner": "someone", "method": "email", "endpoint": "a@example.org"}),
+                    StatusCode::BAD_REQUEST,
+                ),
+            ] {
+                let (status, _) = send(web.post(URL), body.clone())?;
+                assert_eq!(status, *expected, "{}", body);
+            }
+
+            let (status, _) = send(
+                web.post("/api/v1/crates/missing/notifications"),
+                json!({"owner": "foobar", "method": "email", "endpoint": "a@example.org"}),
+            )?;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert!(subscriptions(env)?.is_empty());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,120 @@
+//! The owners of the crates on the registry
+
+use super::json_response;
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Owner {
+    login: String,
+    name: Option<String>,
+    avatar: Option<String>,
+    /// Either `user` or `team`
+    kind: String,
+}
+
+/// Handler for `GET /api/v1/crates/:name/owners`.
+///
+/// Lists the owners of the crate on the registry, as of the last time they were fetched from the
+/// registry API.
+pub fn owners_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let crate_id: i32 = match ctry!(
+        req,
+        conn.query_opt("SELECT id FROM crates WHERE name = $1", &[&name])
+    ) {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("crate {} not found", name) }),
+            ))
+        }
+    };
+
+    let owners: Vec<Owner> = ctry!(
+        req,
+        conn.query(
+            "SELECT owners.login, owners.name, owners.avatar, owners.kind
+             FROM owners
+             INNER JOIN owner_rels ON owner_rels.oid = owners.id
+             WHERE owner_rels.cid = $1
+             ORDER BY owners.login",
+            &[&crate_id],
+        )
+    )
+    .into_iter()
+    .map(|row| Owner {
+        login: row.get(0),
+        name: row.get(1),
+        avatar: row.get(2),
+        kind: row.get(3),
+    })
+    .collect();
+
+    Ok(json_response(status::Ok, json!({ "owners": owners })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::api::CrateOwner;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn api_lists_owners() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .add_owner(CrateOwner {
+                    login: "foobar".into(),
+                    avatar: "https://example.org/foobar".into(),
+                    name: "Foo Bar".into(),
+                    email: "foobar@example.org".into(),
+                    kind: "user".into(),
+                })
+                .add_owner(CrateOwner {
+                    login: "github:org:team".into(),
+                    avatar: "https://example.org/team".into(),
+                    name: "Team".into(),
+                    email: String::new(),
+                    kind: "team".into(),
+                })
+                .create()?;
+
+            let resp = env.frontend().get("/api/v1/crates/dummy/owners").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({
+                    "owners": [
+                        {
+                            "login": "foobar",
+                            "name": "Foo Bar",
+                            "avatar": "https://example.org/foobar",
+                            "kind": "user",
+                        },
+                        {
+                            "login": "github:org:team",
+                            "name": "Team",
+                            "avatar": "https://example.org/team",
+                            "kind": "team",
+                        },
+                    ],
+                })
+            );
+
+            let resp = env.frontend().get("/api/v1/crates/missing/owners").send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,201 @@
+//! The targets the documentation of a release is built for
+
+use super::{json_response, MetaData};
+use crate::db::Pool;
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum BuildStatus {
+    Built,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Platform {
+    target: String,
+    status: BuildStatus,
+    /// The documentation for the target, if it was built
+    docs_url: Option<String>,
+}
+
+/// Returns the platforms of the release, starting with its default target.
+fn release_platforms(metadata: MetaData) -> Vec<Platform> {
+    let MetaData {
+        name,
+        version,
+        target_name,
+        default_target,
+        mut doc_targets,
+        failed_targets,
+        ..
+    } = metadata;
+    let target_name = target_name.unwrap_or_default();
+    doc_targets.sort_by_key(|target| *target != default_target);
+
+    let built = doc_targets.into_iter().map(|target| {
+        let docs_url = if target == default_target {
+            format!("/{}/{}/{}/", name, version, target_name)
+        } else {
+            format!("/{}/{}/{}/{}/", name, version, target, target_name)
+        };
+
+        Platform {
+            target,
+            status: BuildStatus::Built,
+            docs_url: Some(docs_url),
+        }
+    });
+    let failed = failed_targets.into_iter().map(|target| Platform {
+        target,
+        status: BuildStatus::Failed,
+        docs_url: None,
+    });
+
+    built.chain(failed).collect()
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/platforms`.
+///
+/// Lists the targets the documentation was built for, and the ones it failed to build for.
+pub fn platforms_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let metadata = match MetaData::from_crate(&mut conn, name, version) {
+        Some(metadata) => metadata,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "default_target": metadata.default_target.clone(),
+            "platforms": release_platforms(metadata),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{assert_success, wrapper};
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn api_lists_platforms() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .add_platform("x86_64-pc-windows-msvc")
+                .default_target("x86_64-unknown-linux-gnu")
+                .failed_target("thumbv7em-none-eabihf")
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/platforms")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({
+                    "default_target": "x86_64-unknown-linux-gnu",
+                    "platforms": [
+                        {
+                            "target": "x86_64-unknown-linux-gnu",
+                            "status": "built",
+                            "docs_url": "/dummy/0.1.0/dummy/",
+                        },
+                        {
+                            "target": "x86_64-pc-windows-msvc",
+                            "status": "built",
+                            "docs_url": "/dummy/0.1.0/x86_64-pc-windows-msvc/dummy/",
+                        },
+                        {
+                            "target": "thumbv7em-none-eabihf",
+                            "status": "failed",
+                            "docs_url": null,
+                        },
+                    ],
+                })
+            );
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.2.0/platforms")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn cfg_gated_items_per_platform() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .add_platform("x86_64-pc-windows-msvc")
+                .default_target("x86_64-unknown-linux-gnu")
+                .platform_rustdoc_file(
+                    "x86_64-unknown-linux-gnu",
+                    "dummy/fn.linux_only.html",
+                    b"linux",
+                )
+                .platform_rustdoc_file(
+                    "x86_64-pc-windows-msvc",
+                    "dummy/fn.windows_only.html",
+                    b"windows",
+                )
+                .create()?;
+
+            let web = env.frontend();
+            assert_success("/dummy/0.1.0/dummy/fn.linux_only.html", web)?;
+            assert_success(
+                "/dummy/0.1.0/x86_64-pc-windows-msvc/dummy/fn.windows_only.html",
+                web,
+            )?;
+
+            for path in &[
+                "/dummy/0.1.0/dummy/fn.windows_only.html",
+                "/dummy/0.1.0/x86_64-pc-windows-msvc/dummy/fn.linux_only.html",
+            ] {
+                let resp = web.get(path).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failed_platforms_in_dropdown() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .default_target("x86_64-unknown-linux-gnu")
+                .failed_target("thumbv7em-none-eabihf")
+                .create()?;
+
+            let page = env.frontend().get("/dummy/0.1.0/dummy/").send()?.text()?;
+            assert!(page.contains("thumbv7em-none-eabihf (build failed)"));
+            assert!(!page.contains("target-redirect/thumbv7em-none-eabihf"));
+
+            Ok(())
+        });
+    }
+}
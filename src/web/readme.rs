@@ -0,0 +1,214 @@
+//! The README of the releases, rendered on a page of its own
+
+use super::{match_version, redirect, redirect_base, render_markdown, MetaData};
+use crate::{
+    db::Pool,
+    impl_webpage,
+    web::{error::Nope, page::WebPage},
+};
+use iron::headers::{CacheControl, CacheDirective};
+use iron::{status, IronError, IronResult, Request, Response, Url};
+use router::Router;
+use serde::Serialize;
+
+/// How long the rendered READMEs can be cached, in seconds. The README of a release only
+/// changes when it's rebuilt.
+const README_CACHE_DURATION: u32 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ReadmePage {
+    metadata: MetaData,
+    /// The README rendered as HTML, or `None` if the release doesn't have one
+    readme: Option<String>,
+}
+
+impl_webpage! {
+    ReadmePage = "crate/readme.html",
+}
+
+/// Handler for `GET /crate/:name/:version/readme`.
+///
+/// Renders the README of the release, falling back to the README of its repository fetched by the
+/// GitHub updater, or its description for the releases without either.
+pub fn readme_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let readme: Option<String> = match ctry!(
+        req,
+        conn.query_opt(
+            "SELECT COALESCE(releases.readme, github_repos.github_readme)
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             LEFT JOIN github_repos ON github_repos.id = releases.github_repo
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    ) {
+        Some(row) => row.get(0),
+        None => return Err(IronError::new(Nope::VersionNotFound, status::NotFound)),
+    };
+
+    let mut resp = ReadmePage {
+        metadata: cexpect!(req, MetaData::from_crate(&mut conn, name, version)),
+        readme: readme.as_deref().map(render_markdown),
+    }
+    .into_response(req)?;
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(README_CACHE_DURATION),
+    ]));
+    Ok(resp)
+}
+
+/// Handler for `GET /:crate/readme` and `GET /api/v1/crates/:name/README`, redirecting to the
+/// README of the latest release.
+pub fn readme_redirect_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    // The pages under `/:crate` and the API name the parameter differently
+    let name = cexpect!(req, router.find("crate").or_else(|| router.find("name")));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let matched = match_version(&mut conn, name, None)
+        .map_err(|err| IronError::new(err, status::NotFound))?;
+    let name = matched.corrected_name.unwrap_or_else(|| name.to_string());
+    let (version, _) = matched.version.into_parts();
+
+    let url = ctry!(
+        req,
+        Url::parse(&format!(
+            "{}/crate/{}/{}/readme",
+            redirect_base(req),
+            name,
+            version
+        )),
+    );
+    Ok(redirect(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{assert_redirect, assert_success, wrapper};
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn markdown_extensions() {
+        let html = render_markdown(
+            "| a | b |\n|---|---|\n| 1 | 2 |\n\n\
+             - [x] done\n- [ ] todo\n\n\
+             ~~gone~~ x^2^ https://docs.rs\n\n\
+             <script>alert(1)</script>",
+        );
+        assert!(html.contains("<table>"), "{}", html);
+        assert!(html.contains("<td>1</td>"), "{}", html);
+        assert!(
+            html.contains(r#"<input type="checkbox" disabled="" checked="" />"#),
+            "{}",
+            html
+        );
+        assert!(html.contains("<del>gone</del>"), "{}", html);
+        assert!(html.contains("x<sup>2</sup>"), "{}", html);
+        assert!(
+            html.contains(r#"<a href="https://docs.rs">https://docs.rs</a>"#),
+            "{}",
+            html
+        );
+        // Raw HTML isn't rendered
+        assert!(!html.contains("<script>"), "{}", html);
+    }
+
+    #[test]
+    fn render_readme() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("1.0.0")
+                .readme("# Dummy\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n~~old~~")
+                .create()?;
+
+            let resp = env.frontend().get("/crate/dummy/1.0.0/readme").send()?;
+            assert_eq!(
+                resp.headers()["Cache-Control"].to_str()?,
+                "public, max-age=86400"
+            );
+            let page = kuchiki::parse_html().one(resp.text()?);
+            let main = page.select_first("#main").expect("missing readme");
+            assert_eq!(
+                main.as_node().select_first("h1").unwrap().text_contents(),
+                "Dummy"
+            );
+            assert!(main.as_node().select_first("table").is_ok());
+            assert_eq!(
+                main.as_node().select_first("del").unwrap().text_contents(),
+                "old"
+            );
+            let tab = page
+                .select_first(".pure-menu-active")
+                .expect("missing the active tab");
+            assert_eq!(
+                tab.attributes.borrow().get("href"),
+                Some("/crate/dummy/1.0.0/readme")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn description_without_readme() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("1.0.0").create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/dummy/1.0.0/readme")
+                    .send()?
+                    .text()?,
+            );
+            let main = page.select_first("#main").expect("missing readme");
+            let text = main.text_contents();
+            assert!(text.contains("Fake package"), "{}", text);
+            assert!(text.contains("No README available."), "{}", text);
+
+            assert_eq!(
+                env.frontend()
+                    .get("/crate/dummy/2.0.0/readme")
+                    .send()?
+                    .status(),
+                reqwest::StatusCode::NOT_FOUND
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn redirect_to_latest_readme() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("1.0.0").create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("1.1.0")
+                .readme("# Dummy")
+                .create()?;
+
+            let web = env.frontend();
+            assert_redirect("/dummy/readme", "/crate/dummy/1.1.0/readme", web)?;
+            assert_redirect(
+                "/api/v1/crates/dummy/README",
+                "/crate/dummy/1.1.0/readme",
+                web,
+            )?;
+            assert_success("/crate/dummy/1.0.0/readme", web)?;
+            assert_eq!(
+                web.get("/missing/readme").send()?.status(),
+                reqwest::StatusCode::NOT_FOUND
+            );
+
+            Ok(())
+        });
+    }
+}
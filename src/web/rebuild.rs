@@ -0,0 +1,391 @@
+//! Admin endpoint to queue a new build of a release
+
+use super::{json_response, secrets_match};
+use crate::{
+    db::Pool,
+    utils::{get_crate_priority, ADMIN_PRIORITIES},
+    BuildQueue, Config, Metrics,
+};
+use iron::{status, Handler, IronResult, Request, Response};
+use router::Router;
+use serde_json::json;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How many rebuilds a single admin key can trigger in `RATE_LIMIT_WINDOW`.
+const RATE_LIMIT: usize = 10;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Rough duration of a single build, used to estimate how long a queued rebuild will wait.
+const ESTIMATED_BUILD_DURATION: u64 = 60;
+
+/// Handler for `POST /api/v1/crates/:name/:version/rebuild`.
+///
+/// Requests must include one of the configured admin API keys in the `Authorization` header, as
+/// `Authorization: Bearer <key>`. The priority of the rebuild can be set with the `priority`
+/// query parameter, and defaults to the priority of a new release of the crate. Builds from the
+/// queue always replace the existing documentation, so the queued rebuild is forced even if the
+/// release was built successfully.
+///
+/// Rebuilding a release that's already waiting in the queue raises the priority of its pending
+/// build instead, and rebuilding it while it's being built queues one more build.
+pub(super) struct RebuildHandler {
+    /// When each admin key recently triggered a rebuild, used for rate limiting.
+    recent_rebuilds: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RebuildHandler {
+    pub(super) fn new() -> Self {
+        Self {
+            recent_rebuilds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a rebuild triggered by the key, unless it already triggered the most rebuilds
+    /// allowed in the rate limit window. Checking and recording under the same lock keeps
+    /// concurrent requests from going over the limit together.
+    fn try_record_rebuild(&self, key: &str) -> bool {
+        let mut recent = self.recent_rebuilds.lock().unwrap();
+        let rebuilds = recent.entry(key.to_string()).or_default();
+        while let Some(oldest) = rebuilds.front() {
+            if oldest.elapsed() < RATE_LIMIT_WINDOW {
+                break;
+            }
+            rebuilds.pop_front();
+        }
+
+        if rebuilds.len() >= RATE_LIMIT {
+            return false;
+        }
+        rebuilds.push_back(Instant::now());
+        true
+    }
+}
+
+impl Handler for RebuildHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let key = match admin_api_key(req, extension!(req, Config)) {
+            Some(key) => key,
+            None => {
+                return Ok(json_response(
+                    status::Unauthorized,
+                    json!({ "error": "a valid admin API key is required" }),
+                ))
+            }
+        };
+
+        let router = extension!(req, Router);
+        let name = cexpect!(req, router.find("name"));
+        let version = cexpect!(req, router.find("version"));
+
+        let mut conn = extension!(req, Pool).get()?;
+        let release = ctry!(
+            req,
+            conn.query_opt(
+                "SELECT releases.id
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE crates.name = $1 AND releases.version = $2;",
+                &[&name, &version],
+            ),
+        );
+        if release.is_none() {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ));
+        }
+
+        let queue = extension!(req, BuildQueue);
+        let priority = match requested_priority(req) {
+            Ok(Some(priority)) => priority,
+            Ok(None) => ctry!(req, get_crate_priority(&mut conn, name)),
+            Err(()) => {
+                return Ok(json_response(
+                    status::BadRequest,
+                    json!({
+                        "error": format!(
+                            "the priority must be between {} and {}",
+                            ADMIN_PRIORITIES.start(),
+                            ADMIN_PRIORITIES.end(),
+                        ),
+                    }),
+                ))
+            }
+        };
+        drop(conn);
+
+        if !self.try_record_rebuild(&key) {
+            return Ok(json_response(
+                status::TooManyRequests,
+                json!({
+                    "error": format!("at most {} rebuilds can be triggered per hour", RATE_LIMIT),
+                }),
+            ));
+        }
+        ctry!(req, queue.add_crate(name, version, priority, None));
+        extension!(req, Metrics).build_triggered_by_admin.inc();
+
+        let queue_position = ctry!(req, queue.queue_position(name, version)).unwrap_or(0);
+
+        Ok(json_response(
+            status::Accepted,
+            json!({
+                "queue_position": queue_position,
+                "estimated_wait_seconds":
+                    queue_position.saturating_sub(1) as u64 * ESTIMATED_BUILD_DURATION,
+            }),
+        ))
+    }
+}
+
+/// Returns the priority requested with the `priority` query parameter, if any, or an error if
+/// it's not one of the priorities admins can assign.
+fn requested_priority(req: &Request) -> Result<Option<i32>, ()> {
+    let url: iron::url::Url = req.url.clone().into();
+    let priority = match url.query_pairs().find(|(key, _)| key == "priority") {
+        Some((_, priority)) => priority,
+        None => return Ok(None),
+    };
+
+    match priority.parse() {
+        Ok(priority) if ADMIN_PRIORITIES.contains(&priority) => Ok(Some(priority)),
+        _ => Err(()),
+    }
+}
+
+/// Returns the admin API key sent with the request, if it's one of the configured keys.
+pub(super) fn admin_api_key(req: &Request, config: &Config) -> Option<String> {
+    let header = req.headers.get_raw("Authorization")?.first()?;
+    let key = std::str::from_utf8(header)
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+
+    if config
+        .admin_api_keys
+        .iter()
+        .any(|admin_key| secrets_match(key.as_bytes(), admin_key.as_bytes()))
+    {
+        Some(key.to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RebuildHandler, RATE_LIMIT};
+    use crate::test::{wrapper, TestEnvironment};
+    use reqwest::StatusCode;
+    use serde_json::Value;
+    use std::sync::Arc;
+    use std::thread;
+
+    const ADMIN_KEY: &str = "very-secret-key";
+
+    fn setup(env: &TestEnvironment) -> Result<(), failure::Error> {
+        env.override_config(|config| {
+            config.admin_api_keys = vec![ADMIN_KEY.into()];
+        });
+        env.fake_release().name("dummy").version("0.1.0").create()?;
+
+        Ok(())
+    }
+
+    fn rebuild(
+        env: &TestEnvironment,
+        name: &str,
+        version: &str,
+        key: Option<&str>,
+    ) -> reqwest::blocking::Response {
+        rebuild_url(
+            env,
+            &format!("/api/v1/crates/{}/{}/rebuild", name, version),
+            key,
+        )
+    }
+
+    fn rebuild_url(
+        env: &TestEnvironment,
+        url: &str,
+        key: Option<&str>,
+    ) -> reqwest::blocking::Response {
+        let mut request = env.frontend().post(url);
+        if let Some(key) = key {
+            request = request.header("Authorization", format!("Bearer {}", key));
+        }
+
+        request.send().unwrap()
+    }
+
+    #[test]
+    fn missing_or_invalid_key() {
+        wrapper(|env| {
+            setup(env)?;
+
+            let resp = rebuild(env, "dummy", "0.1.0", None);
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let resp = rebuild(env, "dummy", "0.1.0", Some("wrong-key"));
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let resp = rebuild(env, "dummy", "0.1.0", Some("very-secret-key-2"));
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn missing_release() {
+        wrapper(|env| {
+            setup(env)?;
+
+            let resp = rebuild(env, "dummy", "0.2.0", Some(ADMIN_KEY));
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            let resp = rebuild(env, "not-dummy", "0.1.0", Some(ADMIN_KEY));
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn already_queued() {
+        wrapper(|env| {
+            setup(env)?;
+            let queue = env.build_queue();
+            queue.add_crate("other", "1.0.0", 0, None)?;
+            queue.add_crate("dummy", "0.1.0", 10, None)?;
+
+            // The pending build is moved up the queue instead of being duplicated
+            let resp = rebuild_url(
+                env,
+                "/api/v1/crates/dummy/0.1.0/rebuild?priority=5",
+                Some(ADMIN_KEY),
+            );
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            let body: Value = resp.json()?;
+            assert_eq!(body["queue_position"], 2);
+
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 2);
+            assert_eq!(queued[1].name, "dummy");
+            assert_eq!(queued[1].priority, 5);
+
+            // A lower priority doesn't move it down
+            let resp = rebuild_url(
+                env,
+                "/api/v1/crates/dummy/0.1.0/rebuild?priority=20",
+                Some(ADMIN_KEY),
+            );
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            assert_eq!(queue.queued_crates()?[1].priority, 5);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rebuild_during_build() {
+        wrapper(|env| {
+            setup(env)?;
+            let queue = env.build_queue();
+            queue.add_crate("dummy", "0.1.0", 0, None)?;
+
+            queue.process_next_crate(|_| {
+                let resp = rebuild(env, "dummy", "0.1.0", Some(ADMIN_KEY));
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+                Ok(())
+            })?;
+
+            // The release is built again after the build that was in progress
+            assert!(queue.has_build_queued("dummy", "0.1.0")?);
+            assert_eq!(queue.pending_count()?, 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rebuild_queued() {
+        wrapper(|env| {
+            setup(env)?;
+            env.build_queue().add_crate("other", "1.0.0", 0, None)?;
+
+            let resp = rebuild(env, "dummy", "0.1.0", Some(ADMIN_KEY));
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            let body: Value = resp.json()?;
+            assert_eq!(body["queue_position"], 2);
+            assert_eq!(body["estimated_wait_seconds"], 60);
+
+            assert!(env.build_queue().has_build_queued("dummy", "0.1.0")?);
+            assert_eq!(env.metrics().build_triggered_by_admin.get(), 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rebuild_priority() {
+        wrapper(|env| {
+            setup(env)?;
+            let url = "/api/v1/crates/dummy/0.1.0/rebuild";
+
+            for invalid in &["0", "100", "-1", "high"] {
+                let resp = rebuild_url(
+                    env,
+                    &format!("{}?priority={}", url, invalid),
+                    Some(ADMIN_KEY),
+                );
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            }
+            assert_eq!(env.build_queue().pending_count()?, 0);
+
+            let resp = rebuild_url(env, &format!("{}?priority=5", url), Some(ADMIN_KEY));
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            assert_eq!(env.build_queue().queued_crates()?[0].priority, 5);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rate_limited() {
+        wrapper(|env| {
+            setup(env)?;
+            for version in 0..RATE_LIMIT {
+                let version = format!("1.0.{}", version);
+                env.fake_release()
+                    .name("dummy")
+                    .version(&version)
+                    .create()?;
+                let resp = rebuild(env, "dummy", &version, Some(ADMIN_KEY));
+                assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            }
+
+            let resp = rebuild(env, "dummy", "0.1.0", Some(ADMIN_KEY));
+            assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert!(!env.build_queue().has_build_queued("dummy", "0.1.0")?);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rate_limit_with_concurrent_requests() {
+        let handler = Arc::new(RebuildHandler::new());
+        let threads: Vec<_> = (0..RATE_LIMIT * 2)
+            .map(|_| {
+                let handler = handler.clone();
+                thread::spawn(move || handler.try_record_rebuild(ADMIN_KEY))
+            })
+            .collect();
+        let recorded = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .filter(|recorded| *recorded)
+            .count();
+        assert_eq!(recorded, RATE_LIMIT);
+    }
+}
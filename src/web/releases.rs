@@ -1,7 +1,7 @@
 //! Releases web handlers
 
 use crate::{
-    build_queue::QueuedCrate,
+    build_queue::{FailedCrate, QueuedCrate},
     db::Pool,
     impl_webpage,
     web::{error::Nope, match_version, page::WebPage, redirect_base},
@@ -230,6 +230,8 @@ fn get_search_results(
     mut query: &str,
     page: i64,
     limit: i64,
+    min_score: Option<f64>,
+    max_warnings: Option<i32>,
 ) -> Result<(i64, Vec<Release>), failure::Error> {
     query = query.trim();
     if query.is_empty() {
@@ -263,8 +265,12 @@ fn get_search_results(
         INNER JOIN releases ON latest_release.id = releases.id
         LEFT JOIN github_repos ON releases.github_repo = github_repos.id
         WHERE
-            ((char_length($1)::float - levenshtein(crates.name, $1)::float) / char_length($1)::float) >= 0.65
-            OR crates.name ILIKE CONCAT('%', $1, '%')
+            (
+                ((char_length($1)::float - levenshtein(crates.name, $1)::float) / char_length($1)::float) >= 0.65
+                OR crates.name ILIKE CONCAT('%', $1, '%')
+            )
+            AND ($4::FLOAT8 IS NULL OR crates.health_score >= $4)
+            AND ($5::INT IS NULL OR releases.doc_warning_count <= $5)
         GROUP BY crates.id, releases.id, github_repos.stars
         ORDER BY
             levenshtein(crates.name, $1) ASC,
@@ -272,7 +278,10 @@ fn get_search_results(
             releases.downloads DESC
         LIMIT $2 OFFSET $3";
 
-    let rows = conn.query(statement, &[&query, &limit, &offset])?;
+    let rows = conn.query(
+        statement,
+        &[&query, &limit, &offset, &min_score, &max_warnings],
+    )?;
 
     // Each row contains the total number of possible/valid results, just get it once
     let total_results = rows
@@ -337,6 +346,8 @@ struct ViewReleases {
     show_previous_page: bool,
     page_number: i64,
     author: Option<String>,
+    /// The builds that failed all their attempts, listed on the first page of the recent failures
+    failed: Vec<FailedCrate>,
 }
 
 impl_webpage! {
@@ -385,6 +396,12 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         page_number != 1,
     );
 
+    let failed = if release_type == ReleaseType::RecentFailures && page_number == 1 {
+        ctry!(req, extension!(req, BuildQueue).failed_crates())
+    } else {
+        Vec::new()
+    };
+
     ViewReleases {
         releases,
         description: description.into(),
@@ -393,6 +410,7 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         show_previous_page,
         page_number,
         author: None,
+        failed,
     }
     .into_response(req)
 }
@@ -462,6 +480,7 @@ pub fn author_handler(req: &mut Request) -> IronResult<Response> {
         show_previous_page,
         page_number,
         author: Some(author.into()),
+        failed: Vec::new(),
     }
     .into_response(req)
 }
@@ -505,6 +524,30 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
     let url = req.url.as_ref();
     let mut params = url.query_pairs();
     let query = params.find(|(key, _)| key == "query");
+    // Only the crates with at least this health score are listed
+    let min_score = match url.query_pairs().find(|(key, _)| key == "min_score") {
+        Some((_, score)) => match score.parse::<f64>() {
+            Ok(min_score) if (0.0..=1.0).contains(&min_score) => Some(min_score),
+            _ => {
+                return Search {
+                    title: format!(
+                        "Invalid minimum score '{}', expected a number between 0 and 1",
+                        score
+                    ),
+                    search_query: query.map(|(_, query)| query.into_owned()),
+                    status: status::BadRequest,
+                    ..Default::default()
+                }
+                .into_response(req)
+            }
+        },
+        None => None,
+    };
+    // Only the crates with at most this many documentation warnings are listed
+    let max_warnings = url
+        .query_pairs()
+        .find(|(key, _)| key == "warnings")
+        .and_then(|(_, warnings)| warnings.parse::<i32>().ok());
     let mut conn = extension!(req, Pool).get()?;
 
     if let Some((_, query)) = query {
@@ -610,7 +653,14 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
 
         let (_, results) = ctry!(
             req,
-            get_search_results(&mut conn, &query, 1, RELEASES_IN_RELEASES)
+            get_search_results(
+                &mut conn,
+                &query,
+                1,
+                RELEASES_IN_RELEASES,
+                min_score,
+                max_warnings,
+            )
         );
         let title = if results.is_empty() {
             format!("No results found for '{}'", query)
@@ -694,6 +744,7 @@ mod tests {
     use chrono::TimeZone;
     use failure::Error;
     use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
     use std::collections::HashSet;
 
     #[test]
@@ -759,7 +810,8 @@ mod tests {
                 .version("0.0.0")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "foo", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "foo", 1, 100, None, None)?;
             assert_eq!(num_results, 4);
 
             let mut results = results.into_iter();
@@ -787,8 +839,14 @@ mod tests {
             let near_matches = ["Regex", "rEgex", "reGex", "regEx", "regeX"];
 
             for name in near_matches.iter() {
-                let (num_results, mut results) =
-                    dbg!(get_search_results(&mut db.conn(), *name, 1, 100))?;
+                let (num_results, mut results) = dbg!(get_search_results(
+                    &mut db.conn(),
+                    *name,
+                    1,
+                    100,
+                    None,
+                    None
+                ))?;
                 assert_eq!(num_results, 3);
 
                 for name in releases.iter() {
@@ -811,7 +869,8 @@ mod tests {
                 .build_result_successful(false)
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "regex", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "regex", 1, 100, None, None)?;
             assert_eq!(num_results, 0);
 
             let results = results.into_iter();
@@ -831,7 +890,8 @@ mod tests {
                 .yanked(true)
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "regex", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "regex", 1, 100, None, None)?;
             assert_eq!(num_results, 0);
 
             let results = results.into_iter();
@@ -847,7 +907,8 @@ mod tests {
             let db = env.db();
             env.fake_release().name("regex").version("0.0.0").create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "redex", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "redex", 1, 100, None, None)?;
             assert_eq!(num_results, 1);
 
             let mut results = results.into_iter();
@@ -869,7 +930,7 @@ mod tests {
     //             .create()?;
     //
     //         let (num_results, results) =
-    //             get_search_results(&mut db.conn(), "supercalifragilisticexpialidocious", 1, 100)?;
+    //             get_search_results(&mut db.conn(), "supercalifragilisticexpialidocious", 1, 100, None, None)?;
     //         assert_eq!(num_results, 1);
     //
     //         let mut results = results.into_iter();
@@ -895,7 +956,8 @@ mod tests {
                 .name("something_completely_unrelated")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "something", 1, 2)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "something", 1, 2, None, None)?;
             assert_eq!(num_results, 4);
 
             let mut results = results.into_iter();
@@ -918,7 +980,8 @@ mod tests {
                 .name("something_completely_unrelated")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "something", 2, 2)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "something", 2, 2, None, None)?;
             assert_eq!(num_results, 4);
 
             let mut results = results.into_iter();
@@ -962,7 +1025,8 @@ mod tests {
                 .version("0.0.0")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "somethang", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "somethang", 1, 100, None, None)?;
             assert_eq!(num_results, 1);
 
             let mut results = results.into_iter();
@@ -994,7 +1058,7 @@ mod tests {
     //             .create()?;
     //
     //         let (num_results, results) =
-    //             get_search_results(&mut db.conn(), "name_better_than_description", 1, 100)?;
+    //             get_search_results(&mut db.conn(), "name_better_than_description", 1, 100, None, None)?;
     //         assert_eq!(num_results, 2);
     //
     //         let mut results = results.into_iter();
@@ -1027,7 +1091,8 @@ mod tests {
                 .name("i_am_useless_and_mean_nothing")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "match", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "match", 1, 100, None, None)?;
             assert_eq!(num_results, 3);
 
             let mut results = results.into_iter();
@@ -1048,7 +1113,8 @@ mod tests {
             env.fake_release().name("matcb").downloads(10).create()?;
             env.fake_release().name("matcc").downloads(1).create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "match", 1, 100)?;
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "match", 1, 100, None, None)?;
             assert_eq!(num_results, 3);
 
             let mut results = results.into_iter();
@@ -1061,6 +1127,102 @@ mod tests {
         })
     }
 
+    #[test]
+    fn filter_by_health_score() {
+        wrapper(|env| {
+            let db = env.db();
+            env.fake_release().name("match").create()?;
+            env.fake_release().name("matcher").create()?;
+            env.fake_release().name("matchest").create()?;
+            db.conn().execute(
+                "UPDATE crates SET health_score = CASE name
+                    WHEN 'match' THEN 0.9
+                    WHEN 'matcher' THEN 0.5
+                    ELSE NULL
+                 END",
+                &[],
+            )?;
+
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "match", 1, 100, Some(0.7), None)?;
+            assert_eq!(num_results, 1);
+            assert_eq!(results[0].name, "match");
+
+            // The crates not scored yet are only listed without a minimum score
+            let (num_results, _) =
+                get_search_results(&mut db.conn(), "match", 1, 100, Some(0.0), None)?;
+            assert_eq!(num_results, 2);
+            let (num_results, _) = get_search_results(&mut db.conn(), "match", 1, 100, None, None)?;
+            assert_eq!(num_results, 3);
+
+            let web = env.frontend();
+            let page = kuchiki::parse_html().one(
+                web.get("/releases/search?query=match&min_score=0.7")
+                    .send()?
+                    .text()?,
+            );
+            let releases: Vec<_> = page
+                .select("a.release")
+                .expect("missing releases")
+                .map(|release| release.attributes.borrow().get("href").unwrap().to_string())
+                .collect();
+            assert_eq!(releases, vec!["/match/1.0.0/match"]);
+
+            for score in &["high", "1.5", "-1", "NaN"] {
+                let response = web
+                    .get(&format!("/releases/search?query=match&min_score={}", score))
+                    .send()?;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+                assert!(response.text()?.contains("Invalid minimum score"));
+            }
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn filter_by_doc_warnings() {
+        wrapper(|env| {
+            let db = env.db();
+            env.fake_release()
+                .name("match")
+                .no_doc_warnings()
+                .create()?;
+            env.fake_release()
+                .name("matcher")
+                .doc_warning("Gadget", "src/lib.rs:3:66")
+                .doc_warning("Widget::new", "src/lib.rs:9:32")
+                .create()?;
+            env.fake_release().name("matchest").create()?;
+
+            let (num_results, results) =
+                get_search_results(&mut db.conn(), "match", 1, 100, None, Some(0))?;
+            assert_eq!(num_results, 1);
+            assert_eq!(results[0].name, "match");
+
+            // The crates whose warnings weren't recorded are only listed without a maximum
+            let (num_results, _) =
+                get_search_results(&mut db.conn(), "match", 1, 100, None, Some(2))?;
+            assert_eq!(num_results, 2);
+            let (num_results, _) = get_search_results(&mut db.conn(), "match", 1, 100, None, None)?;
+            assert_eq!(num_results, 3);
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/releases/search?query=match&warnings=0")
+                    .send()?
+                    .text()?,
+            );
+            let releases: Vec<_> = page
+                .select("a.release")
+                .expect("missing releases")
+                .collect();
+            assert_eq!(releases.len(), 1);
+
+            Ok(())
+        })
+    }
+
     fn releases_link_test(path: &str, env: &TestEnvironment) -> Result<(), Error> {
         env.fake_release()
             .name("crate_that_succeeded")
@@ -1199,6 +1361,56 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_releases_queue_failed() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.build_attempts = 2;
+                config.build_retry_delay = 0;
+            });
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            for _ in 0..2 {
+                queue.process_next_crate(|krate| {
+                    assert_eq!(krate.name, "foo");
+                    failure::bail!("simulate a failure");
+                })?;
+            }
+            queue.add_crate("bar", "0.1.0", 0, None)?;
+
+            let page = kuchiki::parse_html().one(web.get("/releases/queue").send()?.text()?);
+            let queued = page
+                .select(".queue-list > li")
+                .expect("missing list items")
+                .collect::<Vec<_>>();
+            assert_eq!(queued.len(), 1);
+            assert!(queued[0].text_contents().contains("bar"));
+            assert!(page.select_first(".queue-failed-list").is_err());
+
+            // The failed builds are only listed on the first page of the recent failures
+            let page = kuchiki::parse_html().one(web.get("/releases/failures").send()?.text()?);
+            assert!(page.select_first(".queue-failed-list").is_err());
+
+            let page =
+                kuchiki::parse_html().one(web.get("/releases/recent-failures").send()?.text()?);
+            let failed = page
+                .select(".queue-failed-list > li")
+                .expect("missing list items")
+                .collect::<Vec<_>>();
+            assert_eq!(failed.len(), 1);
+            let link = failed[0].as_node().select_first("a").expect("missing link");
+            assert_eq!(
+                link.attributes.borrow().get("href"),
+                Some("/crate/foo/1.0.0/builds")
+            );
+            assert!(failed[0].text_contents().contains("2 attempts"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn authors_page() {
         wrapper(|env| {
@@ -1331,7 +1543,8 @@ mod tests {
     fn test_empty_query() {
         wrapper(|env| {
             let mut conn = env.db().conn();
-            let (num_results, results) = get_search_results(&mut conn, "", 0, 0).unwrap();
+            let (num_results, results) =
+                get_search_results(&mut conn, "", 0, 0, None, None).unwrap();
             assert_eq!(num_results, 0);
             assert!(results.is_empty());
             Ok(())
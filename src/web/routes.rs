@@ -1,4 +1,5 @@
 use super::metrics::RequestRecorder;
+use super::middleware::ContentSecurityPolicy;
 use iron::middleware::Handler;
 use router::Router;
 use std::collections::HashSet;
@@ -87,10 +88,26 @@ pub(super) fn build_routes() -> Routes {
         "/crate/:name/:version/builds/:id",
         super::builds::build_list_handler,
     );
+    routes.static_resource(
+        "/crate/:name/:version/download",
+        super::archive::download_handler,
+    );
     routes.internal_page(
         "/crate/:name/:version/features",
         super::features::build_features_handler,
     );
+    routes.internal_page(
+        "/crate/:name/:version/diff/:other",
+        super::api_diff::api_diff_handler,
+    );
+    routes.internal_page(
+        "/crate/:name/:version/examples",
+        super::examples::examples_handler,
+    );
+    routes.internal_page(
+        "/crate/:name/:version/readme",
+        super::readme::readme_handler,
+    );
     routes.internal_page(
         "/crate/:name/:version/source",
         SimpleRedirect::new(|url| url.set_path(&format!("{}/", url.path()))),
@@ -111,6 +128,7 @@ pub(super) fn build_routes() -> Routes {
     routes.rustdoc_page("/:crate", super::rustdoc::rustdoc_redirector_handler);
     routes.rustdoc_page("/:crate/", super::rustdoc::rustdoc_redirector_handler);
     routes.rustdoc_page("/:crate/badge.svg", super::rustdoc::badge_handler);
+    routes.rustdoc_page("/:crate/readme", super::readme::readme_redirect_handler);
     routes.rustdoc_page(
         "/:crate/:version",
         super::rustdoc::rustdoc_redirector_handler,
@@ -140,6 +158,133 @@ pub(super) fn build_routes() -> Routes {
         super::rustdoc::rustdoc_html_server_handler,
     );
 
+    routes.api_get("/api/v1/health", super::health::health_handler);
+    routes.api_get(
+        "/api/v1/authors/:name/crates",
+        super::authors::author_crates_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/compile-times/slowest",
+        super::compile_times::slowest_dependencies_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/new",
+        super::new_crates::new_crates_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/co-occurrence",
+        super::cooccurrence::cooccurrence_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/README",
+        super::readme::readme_redirect_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/health",
+        super::crate_health::crate_health_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/metrics",
+        super::crate_metrics::crate_metrics_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/dependents/graph",
+        super::dependents::dependents_graph_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/license-compatibility",
+        super::license_compatibility::license_compatibility_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/owners",
+        super::owners::owners_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/search/type",
+        super::type_search::type_search_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/compile-times",
+        super::compile_times::compile_times_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/examples",
+        super::examples::examples_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/expand/:example",
+        super::examples::macro_expansion_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/inline-tests",
+        super::inline_tests::inline_tests_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/intra-doc-links",
+        super::doc_warnings::doc_warnings_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/macro-expansions",
+        super::examples::macro_expansions_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/features",
+        super::features::features_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/features/:feature/docs",
+        super::features::feature_docs_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/platforms",
+        super::platforms::platforms_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/rustdoc.json",
+        super::rustdoc_json::rustdoc_json_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/sbom.json",
+        super::sbom::sbom_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/dependencies/licenses",
+        super::dependency_licenses::dependency_licenses_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/audit",
+        super::audit::audit_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/coverage",
+        super::coverage::coverage_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/:version/type-graph",
+        super::type_graph::type_graph_api_handler,
+    );
+    routes.api_get(
+        "/api/v1/crates/:name/versions/:a/diff/:b",
+        super::api_diff::api_diff_api_handler,
+    );
+    routes.api_post(
+        "/api/v1/crates/:name/:version/rebuild",
+        super::rebuild::RebuildHandler::new(),
+    );
+    routes.api_post("/api/v1/queue", super::webhook::queue_webhook_handler);
+    routes.api_post(
+        "/webhook/github/:secret",
+        super::github_webhook::github_webhook_handler,
+    );
+    routes.api_post(
+        "/api/v1/crates/:name/notifications",
+        super::notifications::subscribe_handler,
+    );
+    routes.api_delete(
+        "/api/v1/crates/:name/notifications",
+        super::notifications::unsubscribe_handler,
+    );
+
     for redirect in DOC_RUST_LANG_ORG_REDIRECTS {
         routes.internal_page(
             &format!("/{}", redirect),
@@ -161,6 +306,10 @@ pub(super) fn build_routes() -> Routes {
 pub(super) struct Routes {
     /// Normal GET routes.
     get: Vec<(String, Box<dyn Handler>)>,
+    /// POST routes of the API.
+    post: Vec<(String, Box<dyn Handler>)>,
+    /// DELETE routes of the API.
+    delete: Vec<(String, Box<dyn Handler>)>,
     /// GET routes serving rustdoc content. The BlockBlacklistedPrefixes middleware is added
     /// automatically to all of them.
     rustdoc_get: Vec<(String, Box<dyn Handler>)>,
@@ -173,6 +322,8 @@ impl Routes {
     fn new() -> Self {
         Self {
             get: Vec::new(),
+            post: Vec::new(),
+            delete: Vec::new(),
             rustdoc_get: Vec::new(),
             page_prefixes: HashSet::new(),
         }
@@ -187,6 +338,16 @@ impl Routes {
         for (pattern, handler) in self.get.drain(..) {
             router.get(&pattern, handler, calculate_id(&pattern));
         }
+        for (pattern, handler) in self.post.drain(..) {
+            router.post(&pattern, handler, format!("post{}", calculate_id(&pattern)));
+        }
+        for (pattern, handler) in self.delete.drain(..) {
+            router.delete(
+                &pattern,
+                handler,
+                format!("delete{}", calculate_id(&pattern)),
+            );
+        }
 
         // All rustdoc pages have the prefixes of other docs.rs pages blacklisted. This prevents,
         // for example, a crate named "about" from hijacking /about/0.1.0/index.html.
@@ -250,13 +411,50 @@ impl Routes {
         }
     }
 
+    /// API endpoints accepting GET requests. Like internal pages, the first component of the
+    /// URL is registered as a page prefix, to prevent crates from hijacking it.
+    fn api_get(&mut self, pattern: &str, handler: impl Handler) {
+        self.get.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(handler, pattern)),
+        ));
+        self.register_api_prefix(pattern);
+    }
+
+    /// API endpoints accepting POST requests, registered like the GET ones.
+    fn api_post(&mut self, pattern: &str, handler: impl Handler) {
+        self.post.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(handler, pattern)),
+        ));
+        self.register_api_prefix(pattern);
+    }
+
+    /// API endpoints accepting DELETE requests, registered like the GET ones.
+    fn api_delete(&mut self, pattern: &str, handler: impl Handler) {
+        self.delete.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(handler, pattern)),
+        ));
+        self.register_api_prefix(pattern);
+    }
+
+    fn register_api_prefix(&mut self, pattern: &str) {
+        if let Some(first_component) = pattern.trim_matches('/').split('/').next() {
+            self.page_prefixes.insert(first_component.to_string());
+        }
+    }
+
     /// A rustdoc page is a page serving generated documentation. It's similar to a static
     /// resource, but path prefixes are automatically blacklisted (see internal pages to learn more
-    /// about page prefixes).
+    /// about page prefixes). All responses are served with a `Content-Security-Policy` header.
     fn rustdoc_page(&mut self, pattern: &str, handler: impl Handler) {
         self.get.push((
             pattern.to_string(),
-            Box::new(RequestRecorder::new(handler, "rustdoc page")),
+            Box::new(RequestRecorder::new(
+                ContentSecurityPolicy::new(handler),
+                "rustdoc page",
+            )),
         ));
     }
 }
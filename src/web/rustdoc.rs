@@ -1,11 +1,19 @@
 //! rustdoc handler
 
 use crate::{
-    db::Pool,
+    db::{
+        coverage_regression::{release_coverage_regression, CoverageRegression},
+        Pool,
+    },
     utils,
     web::{
-        crate_details::CrateDetails, error::Nope, file::File, match_version,
-        metrics::RenderingTimesRecorder, redirect_base, MatchSemver, MetaData,
+        crate_details::CrateDetails,
+        error::Nope,
+        file::File,
+        inline_tests::{module_inline_tests, InlineTest},
+        match_version,
+        metrics::RenderingTimesRecorder,
+        redirect_base, MatchSemver, MetaData,
     },
     Config, Metrics, Storage,
 };
@@ -109,8 +117,8 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
 
             let path = req.url.path();
             let path = path.join("/");
-            return match File::from_path(&storage, &path, &config) {
-                Ok(f) => Ok(f.serve()),
+            return match File::from_path_for(req, &storage, &path, &config) {
+                Ok(f) => Ok(f.serve_for(req)),
                 Err(..) => Err(Nope::ResourceNotFound.into()),
             };
         }
@@ -186,6 +194,12 @@ struct RustdocPage {
     is_prerelease: bool,
     krate: CrateDetails,
     metadata: MetaData,
+    /// The drop of documentation coverage since the previous version, shown as a warning
+    coverage_regression: Option<CoverageRegression>,
+    /// The struct or enum documented by the page, when the type graph around it is available
+    type_graph_root: Option<String>,
+    /// The test functions of the module documented by the page, shown collapsed after its items
+    inline_tests: Vec<InlineTest>,
 }
 
 impl RustdocPage {
@@ -208,15 +222,46 @@ impl RustdocPage {
             .get::<crate::Metrics>()
             .expect("missing Metrics from the request extensions");
 
+        let math = self.krate.math;
         // Build the page of documentation
         let ctx = ctry!(req, tera::Context::from_serialize(self));
+        let config = extension!(req, Config);
+        let mermaid_script = if config.enable_mermaid {
+            Some(utils::Subresource {
+                url: &config.mermaid_script_url,
+                integrity: config.mermaid_script_integrity.as_deref(),
+            })
+        } else {
+            None
+        };
+        let math_assets = if config.enable_math && math {
+            let katex_url = config.katex_url.trim_end_matches('/');
+            Some(utils::katex_assets(
+                utils::Subresource {
+                    url: &format!("{}/katex.min.js", katex_url),
+                    integrity: config.katex_script_integrity.as_deref(),
+                },
+                utils::Subresource {
+                    url: &format!("{}/katex.min.css", katex_url),
+                    integrity: config.katex_stylesheet_integrity.as_deref(),
+                },
+            ))
+        } else {
+            None
+        };
         // Extract the head and body of the rustdoc file so that we can insert it into our own html
         // while logging OOM errors from html rewriting
-        let html = match utils::rewrite_lol(rustdoc_html, max_parse_memory, ctx, templates) {
+        let html = match utils::rewrite_lol(
+            rustdoc_html,
+            max_parse_memory,
+            ctx,
+            templates,
+            mermaid_script,
+            math_assets.as_deref(),
+        ) {
             Err(RewritingError::MemoryLimitExceeded(..)) => {
                 metrics.html_rewrite_ooms.inc();
 
-                let config = extension!(req, Config);
                 let err = failure::err_msg(format!(
                     "Failed to serve the rustdoc file '{}' because rewriting it surpassed the memory limit of {} bytes",
                     file_path, config.max_parse_memory,
@@ -332,8 +377,14 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     }
     let mut path = ctry!(req, percent_decode(path.as_bytes()).decode_utf8());
 
-    // Attempt to load the file from the database
-    let file = match File::from_path(&storage, &path, &config) {
+    // Attempt to load the file from the database, keeping the assets compressed for the clients
+    // accepting it as they're served as they are
+    let file = if path.ends_with(".html") {
+        File::from_path(storage, &path, config)
+    } else {
+        File::from_path_for(req, storage, &path, config)
+    };
+    let file = match file {
         Ok(file) => file,
         Err(err) => {
             log::debug!("got error serving {}: {}", path, err);
@@ -353,7 +404,7 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     if !path.ends_with(".html") {
         rendering_time.step("serve asset");
 
-        return Ok(file.serve());
+        return Ok(file.serve_for(req));
     }
 
     rendering_time.step("find latest path");
@@ -433,6 +484,19 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         format!("{}/", target)
     };
 
+    let coverage_regression = ctry!(
+        req,
+        release_coverage_regression(&mut conn, krate.release_id)
+    );
+    let type_graph_root = ctry!(
+        req,
+        super::type_graph::type_graph_root(&mut conn, krate.release_id, &inner_path)
+    );
+    let inline_tests = ctry!(
+        req,
+        module_inline_tests(&mut conn, krate.release_id, &inner_path)
+    );
+
     rendering_time.step("rewrite html");
     RustdocPage {
         latest_path,
@@ -443,6 +507,9 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         is_prerelease,
         metadata: krate.metadata.clone(),
         krate,
+        coverage_regression,
+        type_graph_root,
+        inline_tests,
     }
     .into_response(&file.0.content, config.max_parse_memory, req, &path)
 }
@@ -641,8 +708,8 @@ impl Handler for SharedResourceHandler {
             let storage = extension!(req, Storage);
             let config = extension!(req, Config);
 
-            if let Ok(file) = File::from_path(&storage, filename, &config) {
-                return Ok(file.serve());
+            if let Ok(file) = File::from_path_for(req, &storage, filename, &config) {
+                return Ok(file.serve_for(req));
             }
         }
 
@@ -1411,6 +1478,26 @@ mod test {
         });
     }
 
+    #[test]
+    fn proc_macros_have_no_platform_menu() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .add_target("x86_64-unknown-linux-gnu")
+                .proc_macro()
+                .create()?;
+
+            let web = env.frontend();
+            let data = web.get("/dummy/0.1.0/dummy/").send()?.text()?;
+            let dom = kuchiki::parse_html().one(data);
+            assert!(dom.select_first(r#"a[aria-label="Platform"]"#).is_err());
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_target_redirect_not_found() {
         wrapper(|env| {
@@ -1659,4 +1746,175 @@ mod test {
             Ok(())
         })
     }
+
+    #[test]
+    fn mermaid_diagrams() {
+        const PAGE: &[u8] = br#"<html><head></head><body><div class="docblock">
+<div class="example-wrap"><pre class="language-mermaid"><code>graph TD;
+    A--&gt;B;</code></pre></div>
+<div class="example-wrap"><pre class="language-mermaid"><code>graph LR; C--&gt;D;</code></pre></div>
+<div class="example-wrap"><pre class="rust rust-example-rendered"><code>fn main() {}</code></pre></div>
+</div></body></html>"#;
+
+        // The code blocks are left as they are by default
+        wrapper(|env| {
+            env.fake_release()
+                .name("diagrams")
+                .version("0.1.0")
+                .rustdoc_file_with("diagrams/index.html", PAGE)
+                .create()?;
+
+            let page = env
+                .frontend()
+                .get("/diagrams/0.1.0/diagrams/")
+                .send()?
+                .text()?;
+            assert!(page.contains(r#"<pre class="language-mermaid"><code>graph TD;"#));
+            assert!(!page.contains("mermaid.min.js"));
+
+            Ok(())
+        });
+
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.enable_mermaid = true;
+                config.mermaid_script_url = "https://example.com/mermaid.min.js".into();
+                config.mermaid_script_integrity = Some("sha384-abc".into());
+            });
+            env.fake_release()
+                .name("diagrams")
+                .version("0.1.0")
+                .rustdoc_file_with("diagrams/index.html", PAGE)
+                .create()?;
+
+            let resp = env.frontend().get("/diagrams/0.1.0/diagrams/").send()?;
+            let csp = resp.headers()["Content-Security-Policy"].to_str()?;
+            assert!(csp
+                .contains("script-src 'self' 'unsafe-inline' https://example.com/mermaid.min.js;"));
+            let page = resp.text()?;
+
+            assert!(page.contains("<div class=\"mermaid\">graph TD;\n    A--&gt;B;</div>"));
+            assert!(page.contains(r#"<div class="mermaid">graph LR; C--&gt;D;</div>"#));
+            assert!(!page.contains("language-mermaid"));
+            // Other code blocks are untouched
+            assert!(page.contains(
+                r#"<pre class="rust rust-example-rendered"><code>fn main() {}</code></pre>"#
+            ));
+            // The script is only included once
+            let script = r#"<script type="text/javascript" src="https://example.com/mermaid.min.js" integrity="sha384-abc" crossorigin="anonymous"></script>"#;
+            assert_eq!(page.matches(script).count(), 1);
+            assert!(page.contains("mermaid.initialize({ startOnLoad: true });"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn math() {
+        const PAGE: &[u8] = br#"<html><head></head><body><h1 class="fqn">The $price$ module</h1><div class="docblock">
+<p>The norm is $\sqrt{x^2 + y^2}$, and costs $5 to $10.</p>
+<p>$$\sum_{i=1}^n i$$</p>
+<pre class="rust rust-example-rendered"><code>macro_rules! m { ($a:expr) =&gt; { $a } }</code></pre>
+<p>See <code>$x$</code>, as $a &lt; b$.</p>
+<div class="mermaid">graph TD; A[$a$]</div>
+</div></body></html>"#;
+
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.enable_math = true;
+                config.katex_url = "https://example.com/katex".into();
+                config.katex_script_integrity = Some("sha384-js".into());
+                config.katex_stylesheet_integrity = Some("sha384-css".into());
+            });
+            env.fake_release()
+                .name("equations")
+                .version("0.1.0")
+                .rustdoc_file_with("equations/index.html", PAGE)
+                .create()?;
+            env.fake_release()
+                .name("prices")
+                .version("0.1.0")
+                .rustdoc_file_with("prices/index.html", PAGE)
+                .create()?;
+            env.db().conn().execute(
+                "UPDATE releases SET math = TRUE
+                 FROM crates
+                 WHERE crates.id = releases.crate_id AND crates.name = 'equations'",
+                &[],
+            )?;
+
+            let resp = env.frontend().get("/equations/0.1.0/equations/").send()?;
+            let csp = resp.headers()["Content-Security-Policy"].to_str()?;
+            assert!(csp.contains("style-src 'self' 'unsafe-inline' https://example.com/katex/;"));
+            assert!(csp.contains("font-src 'self' https://example.com/katex/"));
+            let page = resp.text()?;
+            assert!(page.contains(
+                r#"The norm is <span class="math-inline">\sqrt{x^2 + y^2}</span>, and costs $5 to $10."#
+            ));
+            assert!(page.contains(r#"<p><span class="math-display">\sum_{i=1}^n i</span></p>"#));
+            assert!(page.contains("macro_rules! m { ($a:expr) =&gt; { $a } }"));
+            assert!(page.contains(
+                r#"See <code>$x$</code>, as <span class="math-inline">a &lt; b</span>."#
+            ));
+            assert!(page.contains(r#"<div class="mermaid">graph TD; A[$a$]</div>"#));
+            // Only the documentation blocks have math
+            assert!(page.contains("The $price$ module"));
+            assert!(page.contains(
+                r#"<link rel="stylesheet" type="text/css" href="https://example.com/katex/katex.min.css" integrity="sha384-css" crossorigin="anonymous">"#
+            ));
+            assert!(page.contains(
+                r#"<script defer src="https://example.com/katex/katex.min.js" integrity="sha384-js" crossorigin="anonymous">"#
+            ));
+
+            // The crates that didn't opt in are left as they are
+            let page = env.frontend().get("/prices/0.1.0/prices/").send()?.text()?;
+            assert!(page.contains(r#"The norm is $\sqrt{x^2 + y^2}$"#));
+            assert!(!page.contains("katex.min.js"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn inline_tests_on_module_pages() {
+        const LIB: &[u8] = b"pub mod parser;\n\n#[test]\nfn top() {}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn works() {}\n\n    mod nested {\n        #[test]\n        fn hidden() {}\n    }\n}\n";
+        const PARSER: &[u8] = b"#[test]\nfn parses() {}\n";
+        const NEW_PAGE: &[u8] =
+            br#"<html><head></head><body><section id="main-content" class="content"><h1>Crate</h1></section></body></html>"#;
+        const OLD_PAGE: &[u8] =
+            br#"<html><head></head><body><section id="main" class="content"><h1>Module</h1></section></body></html>"#;
+
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .source_file("src/lib.rs", LIB)
+                .source_file("src/parser.rs", PARSER)
+                .rustdoc_file_with("dummy/index.html", NEW_PAGE)
+                .rustdoc_file_with("dummy/parser/index.html", OLD_PAGE)
+                .rustdoc_file_with("dummy/struct.Foo.html", NEW_PAGE)
+                .create()?;
+            let tests = |path: &str| -> Result<Vec<String>, failure::Error> {
+                let page = kuchiki::parse_html().one(env.frontend().get(path).send()?.text()?);
+                Ok(page
+                    .select("section.content > #inline-tests details.inline-test summary")
+                    .expect("invalid selector")
+                    .map(|summary| summary.text_contents())
+                    .collect())
+            };
+
+            // The tests of the module and of its child modules
+            assert_eq!(
+                tests("/dummy/0.1.0/dummy/")?,
+                vec!["dummy::top", "dummy::tests::works"]
+            );
+            assert_eq!(
+                tests("/dummy/0.1.0/dummy/parser/")?,
+                vec!["dummy::parser::parses"]
+            );
+            assert!(tests("/dummy/0.1.0/dummy/struct.Foo.html")?.is_empty());
+
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,145 @@
+//! The rustdoc JSON output of the releases, describing their public API
+
+use super::json_response;
+use crate::db::Pool;
+use crate::docbuilder::rustdoc_json_path;
+use crate::storage::PathNotFoundError;
+use crate::{Config, Storage};
+use iron::headers::{CacheControl, CacheDirective, ContentType, ETag, EntityTag, IfNoneMatch};
+use iron::{status, IronResult, Request, Response};
+use router::Router;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Handler for `GET /api/v1/crates/:name/:version/rustdoc.json`.
+///
+/// Serves the rustdoc JSON output of the default target, which is only generated by the builds
+/// using a toolchain supporting it. Clients can revalidate the response with its `ETag`.
+pub fn rustdoc_json_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let mut conn = extension!(req, Pool).get()?;
+    let release = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.id
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    if release.is_none() {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("release {} {} not found", name, version) }),
+        ));
+    }
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let blob = match storage.get(&rustdoc_json_path(name, version), config.max_file_size) {
+        Ok(blob) => blob,
+        Err(err) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({
+                    "error": format!("no rustdoc JSON was generated for {} {}", name, version)
+                }),
+            ))
+        }
+        Err(err) => ctry!(req, Err(err)),
+    };
+
+    let etag = EntityTag::strong(format!("{:x}", Sha256::digest(&blob.content)));
+    let not_modified = match req.headers.get::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+
+    let mut resp = if not_modified {
+        Response::with(status::NotModified)
+    } else {
+        Response::with((status::Ok, blob.content))
+    };
+    resp.headers.set(ContentType::json());
+    resp.headers.set(ETag(etag));
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::NoCache,
+    ]));
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docbuilder::rustdoc_json::{
+        is_rustdoc_json, rustdoc_json_path, tests::sample_rustdoc_json,
+    };
+    use crate::test::wrapper;
+    use reqwest::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+    use reqwest::StatusCode;
+
+    #[test]
+    fn serves_rustdoc_json() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+            let json = sample_rustdoc_json().to_string();
+            env.storage().store_one(
+                &rustdoc_json_path("dummy", "0.1.0"),
+                "application/json",
+                json.as_bytes(),
+            )?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/rustdoc.json")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+            let etag = resp.headers()[ETAG].clone();
+            let body = resp.bytes()?;
+            assert!(is_rustdoc_json(&body));
+            assert_eq!(&body[..], json.as_bytes());
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/rustdoc.json")
+                .header(IF_NONE_MATCH, etag.clone())
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(resp.headers()[ETAG], etag);
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/crates/dummy/0.1.0/rustdoc.json")
+                .header(IF_NONE_MATCH, "\"outdated\"")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn missing_rustdoc_json() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+
+            for path in &[
+                "/api/v1/crates/dummy/0.1.0/rustdoc.json",
+                "/api/v1/crates/dummy/0.2.0/rustdoc.json",
+                "/api/v1/crates/missing/0.1.0/rustdoc.json",
+            ] {
+                let resp = env.frontend().get(path).send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+}
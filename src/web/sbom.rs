@@ -0,0 +1,422 @@
+//! Software bills of materials of the releases, listing the crates they're built from
+
+use super::dependency_tree::{resolve_dependency_tree, ResolvedCrate};
+use super::json_response;
+use crate::db::Pool;
+use crate::utils::license_alternatives;
+use chrono::{SecondsFormat, Utc};
+use failure::Fail;
+use iron::headers::ContentType;
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde_json::{json, Value};
+
+#[derive(Debug, Fail)]
+#[fail(display = "release {} {} not found", _0, _1)]
+pub(crate) struct ReleaseNotFound(String, String);
+
+/// Returns a CycloneDX 1.4 SBOM, as JSON, of the release `version` of `crate_name` and its
+/// normal dependencies, direct and transitive.
+pub(crate) fn generate_cyclonedx_sbom(
+    conn: &mut Client,
+    crate_name: &str,
+    version: &str,
+) -> Result<String, failure::Error> {
+    let tree = release_dependency_tree(conn, crate_name, version)?;
+    let component = |krate: &ResolvedCrate| {
+        let mut component = json!({
+            "type": "library",
+            "bom-ref": purl(krate),
+            "name": krate.name,
+            "version": krate.version,
+            "purl": purl(krate),
+        });
+        if let Some(license) = krate.license.as_deref().and_then(spdx_expression) {
+            component["licenses"] = json!([{ "expression": license }]);
+        }
+        if let Some(url) = &krate.repository_url {
+            component["externalReferences"] = json!([{ "type": "vcs", "url": url }]);
+        }
+        component
+    };
+    let dependencies: Vec<Value> = tree
+        .iter()
+        .map(|krate| {
+            json!({
+                "ref": purl(krate),
+                "dependsOn": krate
+                    .dependencies
+                    .iter()
+                    .map(|&dependency| purl(&tree[dependency]))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    let sbom = json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(),
+            "tools": [{ "vendor": "docs.rs", "name": "docs.rs", "version": crate::BUILD_VERSION }],
+            "component": component(&tree[0]),
+        },
+        "components": tree[1..].iter().map(component).collect::<Vec<_>>(),
+        "dependencies": dependencies,
+    });
+    Ok(serde_json::to_string(&sbom)?)
+}
+
+/// Returns an SPDX 2.3 document, as JSON, of the release `version` of `crate_name` and its
+/// normal dependencies, direct and transitive.
+pub(crate) fn generate_spdx_sbom(
+    conn: &mut Client,
+    crate_name: &str,
+    version: &str,
+) -> Result<String, failure::Error> {
+    let tree = release_dependency_tree(conn, crate_name, version)?;
+    let packages: Vec<Value> = tree
+        .iter()
+        .map(|krate| {
+            let mut package = json!({
+                "SPDXID": spdx_id(krate),
+                "name": krate.name,
+                "versionInfo": krate.version,
+                "downloadLocation": format!(
+                    "https://crates.io/api/v1/crates/{}/{}/download",
+                    krate.name, krate.version
+                ),
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": krate
+                    .license
+                    .as_deref()
+                    .and_then(spdx_expression)
+                    .unwrap_or_else(|| "NOASSERTION".into()),
+                "copyrightText": "NOASSERTION",
+                "filesAnalyzed": false,
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": purl(krate),
+                }],
+            });
+            if let Some(url) = &krate.repository_url {
+                package["homepage"] = json!(url);
+            }
+            package
+        })
+        .collect();
+
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": spdx_id(&tree[0]),
+    })];
+    for krate in &tree {
+        for &dependency in &krate.dependencies {
+            relationships.push(json!({
+                "spdxElementId": spdx_id(krate),
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": spdx_id(&tree[dependency]),
+            }));
+        }
+    }
+
+    let sbom = json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("{}-{}", crate_name, version),
+        "documentNamespace": format!(
+            "https://docs.rs/spdx/{}/{}/{}",
+            crate_name,
+            version,
+            Utc::now().timestamp()
+        ),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": [format!("Tool: docs.rs-{}", crate::BUILD_VERSION.replace(' ', "-"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    });
+    Ok(serde_json::to_string(&sbom)?)
+}
+
+fn release_dependency_tree(
+    conn: &mut Client,
+    crate_name: &str,
+    version: &str,
+) -> Result<Vec<ResolvedCrate>, failure::Error> {
+    let release = conn.query_opt(
+        "SELECT releases.id
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE crates.name = $1 AND releases.version = $2",
+        &[&crate_name, &version],
+    )?;
+    match release {
+        Some(row) => Ok(resolve_dependency_tree(conn, row.get(0))?.crates),
+        None => Err(ReleaseNotFound(crate_name.into(), version.into()).into()),
+    }
+}
+
+fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn purl(krate: &ResolvedCrate) -> String {
+    format!("pkg:cargo/{}@{}", krate.name, krate.version)
+}
+
+/// SPDX identifiers can only contain letters, numbers, `.` and `-`.
+fn spdx_id(krate: &ResolvedCrate) -> String {
+    let id: String = format!("{}-{}", krate.name, krate.version)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-Package-{}", id)
+}
+
+/// Returns the license of a crate as a valid SPDX expression, replacing the `/` separator of the
+/// older crates, or `None` if it's not an expression.
+fn spdx_expression(license: &str) -> Option<String> {
+    license_alternatives(license)?;
+    Some(
+        license
+            .replace('/', " OR ")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Handler for `GET /api/v1/crates/:name/:version/sbom.json`.
+///
+/// Serves a CycloneDX SBOM of the release, or an SPDX one with `?format=spdx`. The dependencies
+/// are resolved like the license compatibility check does, from the releases known to docs.rs.
+pub fn sbom_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let url: iron::url::Url = req.url.clone().into();
+    let format = url
+        .query_pairs()
+        .find(|(key, _)| key == "format")
+        .map(|(_, value)| value.into_owned());
+
+    let mut conn = extension!(req, Pool).get()?;
+    let sbom = match format.as_deref() {
+        None | Some("cyclonedx") => generate_cyclonedx_sbom(&mut conn, name, version),
+        Some("spdx") => generate_spdx_sbom(&mut conn, name, version),
+        Some(format) => {
+            return Ok(json_response(
+                status::BadRequest,
+                json!({
+                    "error": format!("unknown SBOM format {}, expected cyclonedx or spdx", format)
+                }),
+            ))
+        }
+    };
+    let sbom = match sbom {
+        Ok(sbom) => sbom,
+        Err(err) if err.downcast_ref::<ReleaseNotFound>().is_some() => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": err.to_string() }),
+            ))
+        }
+        Err(err) => ctry!(req, Err(err)),
+    };
+
+    let mut resp = Response::with((status::Ok, sbom));
+    resp.headers.set(ContentType::json());
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{wrapper, TestEnvironment};
+    use reqwest::StatusCode;
+    use serde_json::Value;
+    use std::collections::HashSet;
+
+    fn fake_releases(env: &TestEnvironment) -> Result<(), failure::Error> {
+        env.fake_release()
+            .name("leaf")
+            .version("1.0.3")
+            .license("MIT/Apache-2.0")
+            .create()?;
+        env.fake_release()
+            .name("middle_crate")
+            .version("0.2.0")
+            .license("MIT")
+            .repo("https://github.com/example/middle")
+            .add_dependency("leaf", "^1", None)
+            .create()?;
+        env.fake_release()
+            .name("foo")
+            .version("0.1.0")
+            .license("Apache-2.0")
+            .add_dependency("middle_crate", "0.2", None)
+            .add_dependency("leaf", "1.0", None)
+            .create()?;
+        Ok(())
+    }
+
+    fn get_json(env: &TestEnvironment, url: &str) -> Value {
+        let resp = env.frontend().get(url).send().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json().unwrap()
+    }
+
+    /// Checks the fields the CycloneDX 1.4 JSON schema requires, and that the references point to
+    /// components of the SBOM.
+    fn assert_cyclonedx_valid(sbom: &Value) {
+        assert_eq!(sbom["bomFormat"], "CycloneDX");
+        assert_eq!(sbom["specVersion"], "1.4");
+        assert!(sbom["version"].as_u64().unwrap() >= 1);
+        assert!(sbom["metadata"]["timestamp"].is_string());
+
+        let components: Vec<&Value> = std::iter::once(&sbom["metadata"]["component"])
+            .chain(sbom["components"].as_array().unwrap())
+            .collect();
+        let mut refs = HashSet::new();
+        for component in &components {
+            assert_eq!(component["type"], "library");
+            assert!(component["name"].is_string());
+            assert!(component["purl"]
+                .as_str()
+                .unwrap()
+                .starts_with("pkg:cargo/"));
+            assert!(refs.insert(component["bom-ref"].as_str().unwrap()));
+            for license in component["licenses"].as_array().into_iter().flatten() {
+                let expression = license["expression"].as_str().unwrap();
+                assert!(crate::utils::license_alternatives(expression).is_some());
+                assert!(!expression.contains('/'));
+            }
+        }
+        for dependency in sbom["dependencies"].as_array().unwrap() {
+            assert!(refs.contains(dependency["ref"].as_str().unwrap()));
+            for depends_on in dependency["dependsOn"].as_array().unwrap() {
+                assert!(refs.contains(depends_on.as_str().unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn cyclonedx_sbom() {
+        wrapper(|env| {
+            fake_releases(env)?;
+
+            let sbom = get_json(env, "/api/v1/crates/foo/0.1.0/sbom.json");
+            assert_cyclonedx_valid(&sbom);
+            assert_eq!(sbom["metadata"]["component"]["purl"], "pkg:cargo/foo@0.1.0");
+            let components: Vec<_> = sbom["components"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|component| component["purl"].as_str().unwrap())
+                .collect();
+            assert_eq!(
+                components,
+                vec!["pkg:cargo/middle_crate@0.2.0", "pkg:cargo/leaf@1.0.3"]
+            );
+            assert_eq!(
+                sbom["components"][0]["externalReferences"][0]["url"],
+                "https://github.com/example/middle"
+            );
+            assert_eq!(
+                sbom["components"][1]["licenses"][0]["expression"],
+                "MIT OR Apache-2.0"
+            );
+            assert_eq!(
+                sbom["dependencies"][0]["dependsOn"],
+                serde_json::json!(["pkg:cargo/middle_crate@0.2.0", "pkg:cargo/leaf@1.0.3"])
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn spdx_sbom() {
+        wrapper(|env| {
+            fake_releases(env)?;
+
+            let sbom = get_json(env, "/api/v1/crates/foo/0.1.0/sbom.json?format=spdx");
+            assert_eq!(sbom["spdxVersion"], "SPDX-2.3");
+            assert_eq!(sbom["dataLicense"], "CC0-1.0");
+            assert_eq!(sbom["SPDXID"], "SPDXRef-DOCUMENT");
+            assert!(sbom["documentNamespace"]
+                .as_str()
+                .unwrap()
+                .starts_with("https://"));
+            assert!(sbom["creationInfo"]["created"].is_string());
+            assert!(!sbom["creationInfo"]["creators"]
+                .as_array()
+                .unwrap()
+                .is_empty());
+
+            let mut ids = HashSet::new();
+            for package in sbom["packages"].as_array().unwrap() {
+                let id = package["SPDXID"].as_str().unwrap();
+                assert!(id.starts_with("SPDXRef-"));
+                assert!(id[8..]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-'));
+                assert!(ids.insert(id));
+                for field in &["name", "downloadLocation", "licenseDeclared"] {
+                    assert!(package[field].is_string(), "{} is missing", field);
+                }
+            }
+            assert_eq!(ids.len(), 3);
+            assert!(ids.contains("SPDXRef-Package-middle-crate-0.2.0"));
+
+            let relationships = sbom["relationships"].as_array().unwrap();
+            assert_eq!(relationships[0]["relationshipType"], "DESCRIBES");
+            assert_eq!(
+                relationships[0]["relatedSpdxElement"],
+                "SPDXRef-Package-foo-0.1.0"
+            );
+            for relationship in relationships {
+                assert!(ids.contains(relationship["relatedSpdxElement"].as_str().unwrap()));
+            }
+            assert_eq!(relationships.len(), 1 + 3);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn missing_release() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            for url in &[
+                "/api/v1/crates/foo/0.2.0/sbom.json",
+                "/api/v1/crates/missing/0.1.0/sbom.json",
+            ] {
+                assert_eq!(web.get(url).send()?.status(), StatusCode::NOT_FOUND);
+            }
+            assert_eq!(
+                web.get("/api/v1/crates/foo/0.1.0/sbom.json?format=xml")
+                    .send()?
+                    .status(),
+                StatusCode::BAD_REQUEST
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{db::Pool, docbuilder::Limits, impl_webpage, web::page::WebPage};
+use crate::{
+    db::Pool, docbuilder::Limits, impl_webpage, web::page::WebPage, BuildQueue, QueueLock,
+};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use iron::{
     headers::ContentType,
@@ -54,6 +56,8 @@ struct AboutBuilds {
     rustc_version: Option<String>,
     /// The default crate build limits
     limits: Limits,
+    /// Why the build queue is locked, if it is
+    queue_lock: Option<QueueLock>,
     /// Just for the template, since this isn't shared with AboutPage
     active_tab: &'static str,
 }
@@ -75,9 +79,12 @@ pub fn about_builds_handler(req: &mut Request) -> IronResult<Response> {
         }
     });
 
+    let queue_lock = ctry!(req, extension!(req, BuildQueue).lock_state());
+
     AboutBuilds {
         rustc_version,
         limits: Limits::default(),
+        queue_lock,
         active_tab: "builds",
     }
     .into_response(req)
@@ -158,6 +165,22 @@ mod tests {
         })
     }
 
+    #[test]
+    fn about_builds_queue_lock() {
+        wrapper(|env| {
+            let web = env.frontend();
+            let page = web.get("/about/builds").send()?.text()?;
+            assert!(!page.contains("New builds are currently paused"));
+
+            env.build_queue().lock(Some("storage outage"))?;
+            let page = web.get("/about/builds").send()?.text()?;
+            assert!(page.contains("New builds are currently paused"));
+            assert!(page.contains("storage outage"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn robots_txt() {
         wrapper(|env| {
@@ -3,6 +3,7 @@
 use crate::{
     db::Pool,
     impl_webpage,
+    storage::mime_essence,
     web::{error::Nope, file::File as DbFile, page::WebPage, MetaData},
     Config, Storage,
 };
@@ -58,7 +59,9 @@ impl FileList {
                         releases.files,
                         releases.default_target,
                         releases.doc_targets,
-                        releases.yanked
+                        releases.yanked,
+                        releases.failed_targets,
+                        releases.is_proc_macro
                 FROM releases
                 LEFT OUTER JOIN crates ON crates.id = releases.crate_id
                 WHERE crates.name = $1 AND releases.version = $2",
@@ -92,11 +95,12 @@ impl FileList {
                         let path = path.replace(&req_path, "");
                         let path_splited: Vec<&str> = path.split('/').collect();
 
-                        // if path have '/' it is a directory
+                        // if path have '/' it is a directory, the charset of text files isn't
+                        // needed to pick their icon
                         let mime = if path_splited.len() > 1 {
                             "dir".to_owned()
                         } else {
-                            mime.to_owned()
+                            mime_essence(mime).to_owned()
                         };
 
                         let file = File {
@@ -136,7 +140,9 @@ impl FileList {
                     rustdoc_status: rows[0].get(4),
                     default_target: rows[0].get(6),
                     doc_targets: MetaData::parse_doc_targets(rows[0].get(7)),
+                    failed_targets: rows[0].get(9),
                     yanked: rows[0].get(8),
+                    is_proc_macro: rows[0].get(10),
                 },
                 files: file_list,
             })
@@ -204,7 +210,7 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     let (file_content, is_rust_source) = if let Some(file) = file {
         // serve the file with DatabaseFileHandler if file isn't text and not empty
         if !file.0.mime.starts_with("text") && !file.is_empty() {
-            return Ok(file.serve());
+            return Ok(file.serve_for(req));
         } else if file.0.mime.starts_with("text") && !file.is_empty() {
             (
                 String::from_utf8(file.0.content).ok(),
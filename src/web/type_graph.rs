@@ -0,0 +1,301 @@
+//! The relationships between the public types of the releases
+
+use super::json_response;
+use crate::db::Pool;
+use crate::docbuilder::TypeGraph;
+use iron::{status, IronResult, Request, Response};
+use postgres::Client;
+use router::Router;
+use serde_json::{json, Value};
+
+/// The depth of the subgraph when the request doesn't specify one.
+const DEFAULT_DEPTH: usize = 2;
+/// The deepest subgraph returned, as the graphs of big crates quickly become too big to render.
+const MAX_DEPTH: usize = 5;
+
+/// Handler for `GET /api/v1/crates/:name/:version/type-graph?root=:type&depth=:depth`.
+///
+/// Returns the public types at most `depth` fields away from the `root` type, which is either a
+/// path like `dummy::Foo` or an unambiguous name, with the traits they implement and the functions
+/// producing or consuming them. The types containing each other are listed as `cycles`.
+pub fn type_graph_api_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+
+    let url: iron::url::Url = req.url.clone().into();
+    let param = |key: &str| {
+        url.query_pairs()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.into_owned())
+    };
+    let bad_request =
+        |error: &str| Ok(json_response(status::BadRequest, json!({ "error": error })));
+    let root = match param("root") {
+        Some(root) if !root.is_empty() => root,
+        _ => return bad_request("missing root type"),
+    };
+    let depth = match param("depth").map(|depth| depth.parse::<usize>()) {
+        None => DEFAULT_DEPTH,
+        Some(Ok(depth)) if depth <= MAX_DEPTH => depth,
+        Some(_) => {
+            return bad_request(&format!(
+                "invalid depth, expected a number up to {}",
+                MAX_DEPTH
+            ))
+        }
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    let row = ctry!(
+        req,
+        conn.query_opt(
+            "SELECT releases.type_graph
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )
+    );
+    let type_graph: Option<Value> = match row {
+        Some(row) => row.get(0),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("release {} {} not found", name, version) }),
+            ))
+        }
+    };
+    let type_graph: TypeGraph = match type_graph {
+        Some(type_graph) => ctry!(req, serde_json::from_value(type_graph)),
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({
+                    "error": format!("no type graph was generated for {} {}", name, version)
+                }),
+            ))
+        }
+    };
+    let root = match type_graph.resolve(&root) {
+        Some(root) => root,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({ "error": format!("no public struct or enum named {}", root) }),
+            ))
+        }
+    };
+
+    let subgraph = type_graph.subgraph(root, depth);
+    Ok(json_response(
+        status::Ok,
+        json!({
+            "root": root,
+            "depth": depth,
+            "cycles": subgraph.cycles(),
+            "types": subgraph,
+        }),
+    ))
+}
+
+/// Returns the path of the struct or enum documented at `inner_path` in a release with a type
+/// graph, like `dummy::foo::Bar` for `dummy/foo/struct.Bar.html`.
+pub(super) fn type_graph_root(
+    conn: &mut Client,
+    release_id: i32,
+    inner_path: &str,
+) -> Result<Option<String>, failure::Error> {
+    let root = match type_path(inner_path) {
+        Some(root) => root,
+        None => return Ok(None),
+    };
+    let row = conn.query_one(
+        "SELECT type_graph IS NOT NULL FROM releases WHERE id = $1",
+        &[&release_id],
+    )?;
+    Ok(if row.get(0) { Some(root) } else { None })
+}
+
+/// Returns the path of the struct or enum documented in the rustdoc page at `inner_path`.
+fn type_path(inner_path: &str) -> Option<String> {
+    let mut segments: Vec<&str> = inner_path.split('/').collect();
+    let page = segments.pop()?;
+    let name = page
+        .strip_prefix("struct.")
+        .or_else(|| page.strip_prefix("enum."))?
+        .strip_suffix(".html")?;
+    if segments.is_empty() || name.is_empty() {
+        return None;
+    }
+    segments.push(name);
+    Some(segments.join("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::type_path;
+    use crate::docbuilder::type_graph::{TypeGraph, TypeNode};
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    fn node(contains: &[&str]) -> TypeNode {
+        TypeNode {
+            kind: "struct".into(),
+            implements: vec!["Clone".into()],
+            contains: contains.iter().map(|path| path.to_string()).collect(),
+            produced_by: vec![],
+            consumed_by: vec![],
+        }
+    }
+
+    fn sample_graph() -> TypeGraph {
+        TypeGraph {
+            nodes: vec![
+                ("dummy::List", node(&["dummy::List", "dummy::Item"])),
+                ("dummy::Item", node(&["dummy::Tag"])),
+                ("dummy::Tag", node(&[])),
+                ("dummy::other::Tag", node(&[])),
+            ]
+            .into_iter()
+            .map(|(path, node)| (path.to_string(), node))
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn type_paths() {
+        assert_eq!(
+            type_path("dummy/foo/struct.Bar.html").as_deref(),
+            Some("dummy::foo::Bar")
+        );
+        assert_eq!(
+            type_path("dummy/enum.Kind.html").as_deref(),
+            Some("dummy::Kind")
+        );
+        assert_eq!(type_path("dummy/fn.foo.html"), None);
+        assert_eq!(type_path("dummy/index.html"), None);
+        assert_eq!(type_path("struct.Bar.html"), None);
+    }
+
+    #[test]
+    fn subgraph_around_type() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .type_graph(sample_graph())
+                .create()?;
+            let get = |url: &str| -> Result<Value, failure::Error> {
+                Ok(env.frontend().get(url).send()?.json()?)
+            };
+
+            let body = get("/api/v1/crates/dummy/0.1.0/type-graph?root=List&depth=1")?;
+            assert_eq!(body["root"], "dummy::List");
+            assert_eq!(body["depth"], 1);
+            assert_eq!(body["cycles"], json!([["dummy::List"]]));
+            assert_eq!(
+                body["types"],
+                json!({
+                    "dummy::List": {
+                        "kind": "struct",
+                        "implements": ["Clone"],
+                        "contains": ["dummy::List", "dummy::Item"],
+                        "produced_by": [],
+                        "consumed_by": [],
+                    },
+                    "dummy::Item": {
+                        "kind": "struct",
+                        "implements": ["Clone"],
+                        "contains": [],
+                        "produced_by": [],
+                        "consumed_by": [],
+                    },
+                })
+            );
+
+            let body = get("/api/v1/crates/dummy/0.1.0/type-graph?root=dummy::Tag")?;
+            assert_eq!(body["depth"], 2);
+            let types = body["types"].as_object().unwrap();
+            assert_eq!(
+                types.keys().collect::<Vec<_>>(),
+                vec!["dummy::Item", "dummy::List", "dummy::Tag"]
+            );
+            assert_eq!(body["cycles"], json!([["dummy::List"]]));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_requests() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .type_graph(sample_graph())
+                .create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+            let status = |url: &str| -> Result<StatusCode, failure::Error> {
+                Ok(env.frontend().get(url).send()?.status())
+            };
+
+            for url in &[
+                "/api/v1/crates/dummy/0.1.0/type-graph",
+                "/api/v1/crates/dummy/0.1.0/type-graph?root=List&depth=6",
+                "/api/v1/crates/dummy/0.1.0/type-graph?root=List&depth=deep",
+            ] {
+                assert_eq!(status(url)?, StatusCode::BAD_REQUEST, "{}", url);
+            }
+            for url in &[
+                // Ambiguous names have to be written as paths
+                "/api/v1/crates/dummy/0.1.0/type-graph?root=Tag",
+                "/api/v1/crates/dummy/0.1.0/type-graph?root=Missing",
+                "/api/v1/crates/dummy/0.2.0/type-graph?root=List",
+                "/api/v1/crates/dummy/0.3.0/type-graph?root=List",
+            ] {
+                assert_eq!(status(url)?, StatusCode::NOT_FOUND, "{}", url);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn link_on_type_pages() {
+        wrapper(|env| {
+            let link = |path: &str| -> Result<Option<String>, failure::Error> {
+                let data = env.frontend().get(path).send()?.text()?;
+                Ok(kuchiki::parse_html()
+                    .one(data)
+                    .select_first("#type-graph-link")
+                    .ok()
+                    .and_then(|el| el.attributes.borrow().get("href").map(String::from)))
+            };
+
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .rustdoc_file("dummy/struct.List.html")
+                .type_graph(sample_graph())
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .rustdoc_file("dummy/struct.List.html")
+                .create()?;
+
+            assert_eq!(
+                link("/dummy/0.1.0/dummy/struct.List.html")?.as_deref(),
+                Some("/api/v1/crates/dummy/0.1.0/type-graph?root=dummy::List")
+            );
+            assert_eq!(link("/dummy/0.1.0/dummy/")?, None);
+            assert_eq!(link("/dummy/0.2.0/dummy/struct.List.html")?, None);
+
+            Ok(())
+        });
+    }
+}
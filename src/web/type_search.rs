@@ -0,0 +1,290 @@
+//! Search for functions by their type signature, like Haskell's Hoogle
+
+use super::json_response;
+use crate::db::Pool;
+use crate::docbuilder::rustdoc_json::normalize_type;
+use iron::{status, IronResult, Request, Response};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// The number of functions returned by a search.
+const RESULTS_LIMIT: i64 = 50;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct FunctionMatch {
+    #[serde(rename = "crate")]
+    krate: String,
+    version: String,
+    path: String,
+    params: Value,
+    return_type: String,
+}
+
+/// Splits a type signature query like `Vec<T>, usize -> Option<T>` into the types of the
+/// parameters and the return type, normalized with `normalize_type`. The parameters or the
+/// return type can be omitted, like in `-> Option<String>`.
+///
+/// Returns `None` if the query has neither, or if all its types are type parameters, which are
+/// normalized to `_` and would match nearly every generic function.
+fn parse_query(query: &str) -> Option<(Vec<String>, Option<String>)> {
+    let (params, return_type) = match split_top_level(query, "->").as_slice() {
+        [params] => (*params, None),
+        [params @ .., return_type] => {
+            let end = params.iter().map(|param| param.len() + 2).sum::<usize>() - 2;
+            (&query[..end], Some(normalize_type(return_type)))
+        }
+        [] => unreachable!("splitting always returns at least one part"),
+    };
+    let params: Vec<_> = split_top_level(params, ",")
+        .into_iter()
+        .map(normalize_type)
+        .filter(|param| !param.is_empty())
+        .collect();
+    let return_type = return_type.filter(|ty| !ty.is_empty());
+
+    if params.iter().chain(&return_type).all(|ty| ty == "_") {
+        None
+    } else {
+        Some((params, return_type))
+    }
+}
+
+/// Splits `ty` on the occurrences of `separator` that aren't nested in brackets.
+fn split_top_level<'a>(ty: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < ty.len() {
+        let rest = &ty[i..];
+        if depth == 0 && rest.starts_with(separator) {
+            parts.push(&ty[start..i]);
+            i += separator.len();
+            start = i;
+            continue;
+        }
+        if rest.starts_with("->") {
+            // The arrow of function types doesn't close a bracket
+            i += 2;
+            continue;
+        }
+        match rest.as_bytes()[0] {
+            b'<' | b'(' | b'[' => depth += 1,
+            b'>' | b')' | b']' => depth -= 1,
+            _ => {}
+        }
+        i += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    parts.push(&ty[start..]);
+    parts
+}
+
+/// Handler for `GET /api/v1/search/type?q=<signature>`.
+///
+/// Searches the public functions of the latest release of every crate whose parameters include
+/// the types of the parameters of the signature and which return its return type. Type
+/// parameters like `T` match the type parameters of the functions, whatever their name, and a
+/// signature needs at least one other type. The functions with the fewest other parameters come
+/// first, then the ones of the most downloaded crates.
+pub fn type_search_api_handler(req: &mut Request) -> IronResult<Response> {
+    let query = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "q")
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default();
+    let (params, return_type) = match parse_query(&query) {
+        Some(parsed) => parsed,
+        None => {
+            return Ok(json_response(
+                status::BadRequest,
+                json!({
+                    "error": "q must be a type signature naming a type, like `Vec<T> -> T`"
+                }),
+            ))
+        }
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    let matches: Vec<FunctionMatch> = ctry!(
+        req,
+        conn.query(
+            "SELECT
+                crates.name,
+                releases.version,
+                function_signatures.item_path,
+                function_signatures.param_types,
+                function_signatures.return_type
+             FROM function_signatures
+             INNER JOIN crates ON crates.id = function_signatures.crate_id
+             INNER JOIN releases ON releases.id = function_signatures.release_id
+             WHERE crates.latest_version_id = releases.id
+                AND function_signatures.param_types @> $1
+                -- containment ignores how many times a type is repeated, so the functions
+                -- need to have each type at least as many times as the query
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM jsonb_array_elements_text($1) AS wanted (ty)
+                    GROUP BY wanted.ty
+                    HAVING COUNT(*) > (
+                        SELECT COUNT(*)
+                        FROM jsonb_array_elements_text(function_signatures.param_types)
+                            AS param (ty)
+                        WHERE param.ty = wanted.ty
+                    )
+                )
+                AND ($2::TEXT IS NULL OR function_signatures.return_type = $2)
+             ORDER BY
+                jsonb_array_length(function_signatures.param_types) ASC,
+                releases.downloads DESC NULLS LAST,
+                crates.name,
+                function_signatures.item_path
+             LIMIT $3",
+            &[&json!(params), &return_type, &RESULTS_LIMIT],
+        )
+    )
+    .into_iter()
+    .map(|row| FunctionMatch {
+        krate: row.get(0),
+        version: row.get(1),
+        path: row.get(2),
+        params: row.get(3),
+        return_type: row.get(4),
+    })
+    .collect();
+
+    Ok(json_response(status::Ok, json!({ "results": matches })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn test_parse_query() {
+        let owned = |types: &[&str]| types.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            parse_query("Vec<T> -> T"),
+            Some((owned(&["Vec<_>"]), Some("_".into())))
+        );
+        assert_eq!(
+            parse_query("-> Option<String>"),
+            Some((Vec::new(), Some("Option<String>".into())))
+        );
+        assert_eq!(
+            parse_query("HashMap<K, V>, &K -> Option<&V>"),
+            Some((owned(&["HashMap<_, _>", "&_"]), Some("Option<&_>".into())))
+        );
+        assert_eq!(
+            parse_query("Box<dyn Fn(u8) -> u16>, usize"),
+            Some((owned(&["Box<dyn Fn(u8) -> u16>", "usize"]), None))
+        );
+        assert_eq!(parse_query(""), None);
+        assert_eq!(parse_query(" -> "), None);
+        // Type parameters alone would match any generic function
+        assert_eq!(parse_query("_"), None);
+        assert_eq!(parse_query("T, U -> T"), None);
+        assert_eq!(
+            parse_query("T -> usize"),
+            Some((owned(&["_"]), Some("usize".into())))
+        );
+    }
+
+    #[test]
+    fn search_by_return_type() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .downloads(10)
+                .function_signature("foo::name", &["&str"], "Option<String>")
+                .function_signature("foo::len", &["&str"], "usize")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .downloads(100)
+                .function_signature("bar::lookup", &["&str", "usize"], "Option<String>")
+                .function_signature("bar::first", &["&[String]"], "Option<String>")
+                .create()?;
+
+            let resp = env
+                .frontend()
+                .get("/api/v1/search/type?q=->+Option<String>")
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Value = resp.json()?;
+            let paths: Vec<_> = body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|result| result["path"].as_str().unwrap())
+                .collect();
+            assert_eq!(paths, vec!["bar::first", "foo::name", "bar::lookup"]);
+            assert_eq!(
+                body["results"][1],
+                json!({
+                    "crate": "foo",
+                    "version": "0.1.0",
+                    "path": "foo::name",
+                    "params": ["&str"],
+                    "return_type": "Option<String>",
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn search_by_params() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .function_signature("foo::pop", &["Vec<_>"], "_")
+                .function_signature("foo::nth", &["Vec<_>", "usize"], "_")
+                .function_signature("foo::sum", &["Vec<u8>"], "u8")
+                .create()?;
+            // Only the latest release of each crate is searched
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .function_signature("bar::last", &["Vec<_>"], "_")
+                .create()?;
+            env.fake_release().name("bar").version("0.2.0").create()?;
+
+            let web = env.frontend();
+            let body: Value = web
+                .get("/api/v1/search/type?q=Vec<T>+->+T")
+                .send()?
+                .json()?;
+            let paths: Vec<_> = body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|result| result["path"].as_str().unwrap())
+                .collect();
+            assert_eq!(paths, vec!["foo::pop", "foo::nth"]);
+
+            let body: Value = web
+                .get("/api/v1/search/type?q=Vec<T>,+Vec<T>")
+                .send()?
+                .json()?;
+            assert_eq!(body["results"], json!([]));
+
+            for query in &["", "_", "->+_"] {
+                let resp = web
+                    .get(&format!("/api/v1/search/type?q={}", query))
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,329 @@
+//! Webhook called by the registry to queue new releases as soon as they're published
+
+use super::{json_response, secrets_match};
+use crate::{BuildQueue, Config, Index, Metrics};
+use iron::{status, IronResult, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::Read;
+
+const SECRET_HEADER: &str = "X-Webhook-Secret";
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// Priority of the releases queued through the webhook, ahead of the rest of the queue.
+const WEBHOOK_PRIORITY: i32 = -1000;
+
+#[derive(Debug, Deserialize)]
+struct QueueRequest {
+    name: String,
+    version: String,
+}
+
+/// Handler for `POST /api/v1/queue`.
+///
+/// Requests must include the configured secret in the `X-Webhook-Secret` header, and a JSON
+/// body with the `name` and `version` of the published release. The release is only queued if
+/// it's in the registry index, and submitting a release that's already queued or built doesn't
+/// queue it again. The index is still polled as usual, so releases missed by the webhook are
+/// queued anyway.
+pub fn queue_webhook_handler(req: &mut Request) -> IronResult<Response> {
+    // The body is always read, as leaving it in the connection breaks the following requests.
+    let mut body = Vec::new();
+    ctry!(
+        req,
+        req.body.by_ref().take(MAX_BODY_SIZE).read_to_end(&mut body)
+    );
+
+    let config = extension!(req, Config).clone();
+    if !has_valid_secret(req, &config) {
+        return Ok(json_response(
+            status::Unauthorized,
+            json!({ "error": "a valid webhook secret is required" }),
+        ));
+    }
+
+    let release: QueueRequest = match serde_json::from_slice(&body) {
+        Ok(release) => release,
+        Err(err) => {
+            return Ok(json_response(
+                status::BadRequest,
+                json!({ "error": format!("invalid request body: {}", err) }),
+            ))
+        }
+    };
+    let (name, version) = (release.name.as_str(), release.version.as_str());
+    if !is_valid_crate_name(name) {
+        return Ok(json_response(
+            status::BadRequest,
+            json!({ "error": format!("invalid crate name: {}", name) }),
+        ));
+    }
+    if semver::Version::parse(version).is_err() {
+        return Ok(json_response(
+            status::BadRequest,
+            json!({ "error": format!("invalid version: {}", version) }),
+        ));
+    }
+
+    let queue = extension!(req, BuildQueue).clone();
+    if ctry!(req, queue.queue_position(name, version)).is_some() {
+        // Move a pending build up the queue. Builds in progress already build the release as
+        // published, so they don't need another build.
+        if ctry!(req, queue.has_build_queued(name, version)) {
+            ctry!(
+                req,
+                queue.add_crate(
+                    name,
+                    version,
+                    WEBHOOK_PRIORITY,
+                    config.registry_url.as_deref()
+                )
+            );
+        }
+
+        return Ok(json_response(
+            status::Ok,
+            json!({
+                "queued": false,
+                "queue_position": ctry!(req, queue.queue_position(name, version)),
+            }),
+        ));
+    }
+    if ctry!(req, queue.is_queued_or_built(name, version)) {
+        return Ok(json_response(
+            status::Ok,
+            json!({ "queued": false, "queue_position": null }),
+        ));
+    }
+
+    let index = ctry!(
+        req,
+        match &config.registry_url {
+            Some(registry_url) =>
+                Index::from_url(config.registry_index_path.clone(), registry_url.clone()),
+            None => Index::new(config.registry_index_path.clone()),
+        }
+    );
+    if !ctry!(req, index.has_version(name, version)) {
+        return Ok(json_response(
+            status::NotFound,
+            json!({ "error": format!("{} {} is not in the registry index", name, version) }),
+        ));
+    }
+
+    ctry!(
+        req,
+        queue.add_crate(name, version, WEBHOOK_PRIORITY, index.repository_url())
+    );
+    extension!(req, Metrics).build_triggered_by_webhook.inc();
+
+    Ok(json_response(
+        status::Accepted,
+        json!({
+            "queued": true,
+            "queue_position": ctry!(req, queue.queue_position(name, version)),
+        }),
+    ))
+}
+
+fn has_valid_secret(req: &Request, config: &Config) -> bool {
+    let secret = match &config.queue_webhook_secret {
+        Some(secret) if !secret.is_empty() => secret,
+        _ => return false,
+    };
+
+    match req
+        .headers
+        .get_raw(SECRET_HEADER)
+        .and_then(|values| values.first())
+    {
+        Some(value) => secrets_match(value, secret.as_bytes()),
+        None => false,
+    }
+}
+
+fn is_valid_crate_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SECRET_HEADER;
+    use crate::test::{wrapper, FakeIndex, TestEnvironment};
+    use failure::Error;
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+
+    const SECRET: &str = "webhook-secret";
+
+    /// Creates a registry index containing `dummy 0.1.0` and configures docs.rs to use it.
+    fn setup(env: &TestEnvironment) -> Result<FakeIndex, Error> {
+        let index = FakeIndex::new()?;
+        index.publish(&[("dummy", "0.1.0")])?;
+        env.override_config(|config| {
+            config.queue_webhook_secret = Some(SECRET.into());
+            index.configure(config);
+        });
+
+        Ok(index)
+    }
+
+    fn submit(
+        env: &TestEnvironment,
+        body: Value,
+        secret: Option<&str>,
+    ) -> Result<reqwest::blocking::Response, Error> {
+        let mut request = env.frontend().post("/api/v1/queue").json(&body);
+        if let Some(secret) = secret {
+            request = request.header(SECRET_HEADER, secret);
+        }
+
+        Ok(request.send()?)
+    }
+
+    #[test]
+    fn missing_or_invalid_secret() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+            let body = json!({ "name": "dummy", "version": "0.1.0" });
+
+            let resp = submit(env, body.clone(), None)?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let resp = submit(env, body.clone(), Some("wrong-secret"))?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let resp = submit(env, body, Some("webhook-secret-but-longer"))?;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn unknown_version() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+
+            for body in &[
+                json!({ "name": "dummy", "version": "0.2.0" }),
+                json!({ "name": "not-dummy", "version": "0.1.0" }),
+            ] {
+                let resp = submit(env, body.clone(), Some(SECRET))?;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", body);
+            }
+
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_release() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+
+            for body in &[
+                json!({ "name": "dummy" }),
+                json!({ "name": "dummy", "version": "latest" }),
+                json!({ "name": "../dummy", "version": "0.1.0" }),
+            ] {
+                let resp = submit(env, body.clone(), Some(SECRET))?;
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", body);
+            }
+
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queued_at_top_priority() {
+        wrapper(|env| {
+            let index = setup(env)?;
+            env.build_queue().add_crate("other", "1.0.0", 0, None)?;
+
+            let resp = submit(
+                env,
+                json!({ "name": "dummy", "version": "0.1.0" }),
+                Some(SECRET),
+            )?;
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({ "queued": true, "queue_position": 1 })
+            );
+
+            // Versions published after the index was last fetched are found too
+            index.publish(&[("dummy", "0.2.0")])?;
+            let resp = submit(
+                env,
+                json!({ "name": "dummy", "version": "0.2.0" }),
+                Some(SECRET),
+            )?;
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+            let queued = env.build_queue().queued_crates()?;
+            assert_eq!(queued.len(), 3);
+            assert_eq!(queued[2].name, "other");
+            assert_eq!(env.metrics().build_triggered_by_webhook.get(), 2);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn already_queued_by_the_poller() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+            let queue = env.build_queue();
+            queue.add_crate("other", "1.0.0", 0, None)?;
+            queue.add_crate("dummy", "0.1.0", 10, None)?;
+
+            let resp = submit(
+                env,
+                json!({ "name": "dummy", "version": "0.1.0" }),
+                Some(SECRET),
+            )?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({ "queued": false, "queue_position": 1 })
+            );
+            assert_eq!(queue.pending_count()?, 2);
+            assert_eq!(queue.queued_crates()?[0].priority, super::WEBHOOK_PRIORITY);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn duplicate_submissions() {
+        wrapper(|env| {
+            let _index = setup(env)?;
+            let body = json!({ "name": "dummy", "version": "0.1.0" });
+
+            let resp = submit(env, body.clone(), Some(SECRET))?;
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            let resp = submit(env, body.clone(), Some(SECRET))?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({ "queued": false, "queue_position": 1 })
+            );
+            assert_eq!(env.build_queue().pending_count()?, 1);
+
+            // Once built, the release isn't queued again either
+            env.build_queue().process_next_crate(|_| Ok(()))?;
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+            let resp = submit(env, body, Some(SECRET))?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.json::<Value>()?,
+                json!({ "queued": false, "queue_position": null })
+            );
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            Ok(())
+        });
+    }
+}
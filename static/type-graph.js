@@ -0,0 +1,173 @@
+// Renders the graph of the types related to the struct or enum of the current page, as returned by
+// `/api/v1/crates/:name/:version/type-graph`, when its link in the navigation bar is clicked.
+(function() {
+    var link = document.getElementById("type-graph-link");
+    if (!link) {
+        return;
+    }
+    var SVG = "http://www.w3.org/2000/svg";
+    var WIDTH = 800;
+    var HEIGHT = 600;
+    var RING = 110;
+
+    function svgElement(name, attributes) {
+        var element = document.createElementNS(SVG, name);
+        for (var key in attributes) {
+            element.setAttribute(key, attributes[key]);
+        }
+        return element;
+    }
+
+    function shortName(path) {
+        var segments = path.split("::");
+        return segments[segments.length - 1];
+    }
+
+    // The url of the documentation of a type, like `dummy/foo/struct.Bar.html` for `dummy::foo::Bar`
+    function docUrl(path, kind) {
+        var segments = path.split("::");
+        var name = segments.pop();
+        return link.dataset.docBase + segments.join("/") + "/" + kind + "." + name + ".html";
+    }
+
+    // Places the root in the center and the other types on rings, by distance from the root
+    function layout(root, types) {
+        var distances = {};
+        distances[root] = 0;
+        var queue = [root];
+        var rings = [];
+        while (queue.length > 0) {
+            var path = queue.shift();
+            var distance = distances[path];
+            (rings[distance] = rings[distance] || []).push(path);
+            var neighbours = types[path].contains.slice();
+            for (var other in types) {
+                if (types[other].contains.indexOf(path) !== -1) {
+                    neighbours.push(other);
+                }
+            }
+            neighbours.forEach(function(neighbour) {
+                if (types[neighbour] && distances[neighbour] === undefined) {
+                    distances[neighbour] = distance + 1;
+                    queue.push(neighbour);
+                }
+            });
+        }
+
+        var positions = {};
+        rings.forEach(function(paths, distance) {
+            paths.forEach(function(path, i) {
+                var angle = 2 * Math.PI * i / paths.length + distance;
+                positions[path] = {
+                    x: WIDTH / 2 + Math.cos(angle) * RING * distance,
+                    y: HEIGHT / 2 + Math.sin(angle) * RING * distance,
+                };
+            });
+        });
+        return positions;
+    }
+
+    function render(graph) {
+        var inCycle = {};
+        graph.cycles.forEach(function(cycle) {
+            cycle.forEach(function(path) { inCycle[path] = true; });
+        });
+        var positions = layout(graph.root, graph.types);
+        var svg = svgElement("svg", { viewBox: "0 0 " + WIDTH + " " + HEIGHT });
+
+        var defs = svgElement("defs", {});
+        var marker = svgElement("marker", {
+            id: "type-graph-arrow", viewBox: "0 0 10 10", refX: 22, refY: 5,
+            markerWidth: 6, markerHeight: 6, orient: "auto",
+        });
+        marker.appendChild(svgElement("path", { d: "M 0 0 L 10 5 L 0 10 z" }));
+        defs.appendChild(marker);
+        svg.appendChild(defs);
+
+        Object.keys(graph.types).forEach(function(path) {
+            var from = positions[path];
+            graph.types[path].contains.forEach(function(contained) {
+                var to = positions[contained];
+                if (contained === path) {
+                    // A type containing itself, like the node of a linked list
+                    svg.appendChild(svgElement("circle", {
+                        cx: from.x, cy: from.y - 18, r: 14, "class": "edge cycle",
+                    }));
+                    return;
+                }
+                svg.appendChild(svgElement("line", {
+                    x1: from.x, y1: from.y, x2: to.x, y2: to.y,
+                    "class": inCycle[path] && inCycle[contained] ? "edge cycle" : "edge",
+                    "marker-end": "url(#type-graph-arrow)",
+                }));
+            });
+        });
+
+        Object.keys(graph.types).forEach(function(path) {
+            var type = graph.types[path];
+            var position = positions[path];
+            var node = svgElement("a", { href: docUrl(path, type.kind) });
+            var title = svgElement("title", {});
+            title.textContent = path +
+                (type.implements.length ? "\nimplements " + type.implements.join(", ") : "") +
+                (type.produced_by.length ? "\nproduced by " + type.produced_by.join(", ") : "") +
+                (type.consumed_by.length ? "\nconsumed by " + type.consumed_by.join(", ") : "");
+            node.appendChild(title);
+            node.appendChild(svgElement("circle", {
+                cx: position.x, cy: position.y, r: 8,
+                "class": "node " + type.kind + (path === graph.root ? " root" : ""),
+            }));
+            var label = svgElement("text", { x: position.x, y: position.y + 24 });
+            label.textContent = shortName(path);
+            node.appendChild(label);
+            svg.appendChild(node);
+        });
+        return svg;
+    }
+
+    function close() {
+        var overlay = document.getElementById("type-graph");
+        if (overlay) {
+            overlay.remove();
+        }
+    }
+
+    function show(content) {
+        close();
+        var overlay = document.createElement("div");
+        overlay.id = "type-graph";
+        overlay.addEventListener("click", function(event) {
+            if (event.target === overlay) {
+                close();
+            }
+        });
+        var panel = document.createElement("div");
+        panel.className = "type-graph-panel";
+        panel.appendChild(content);
+        overlay.appendChild(panel);
+        document.body.appendChild(overlay);
+    }
+
+    document.addEventListener("keydown", function(event) {
+        if (event.key === "Escape") {
+            close();
+        }
+    });
+
+    link.addEventListener("click", function(event) {
+        event.preventDefault();
+        fetch(link.href)
+            .then(function(response) { return response.json(); })
+            .then(function(graph) {
+                if (graph.error) {
+                    throw new Error(graph.error);
+                }
+                show(render(graph));
+            })
+            .catch(function(error) {
+                var message = document.createElement("p");
+                message.textContent = "Failed to load the type graph: " + error.message;
+                show(message);
+            });
+    });
+})();
@@ -0,0 +1,24 @@
+//! Crate used to test recording the broken intra-doc links of the documentation.
+//!
+//! Links to [`Vec`] and [`Widget`] resolve, unlike the link to [`Gadget`].
+
+/// A widget, built by [`Widget::build`] and sold by [`crate::shop::sell`].
+pub struct Widget;
+
+impl Widget {
+    /// Builds a widget, like [`Widget::new`] would.
+    pub fn build() -> Self {
+        Widget
+    }
+}
+
+/// Uses the [`Helper`] to do its job.
+pub fn work() {
+    Helper.help();
+}
+
+struct Helper;
+
+impl Helper {
+    fn help(&self) {}
+}
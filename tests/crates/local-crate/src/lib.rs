@@ -0,0 +1,8 @@
+//! Crate used to test building documentation from a local directory.
+
+/// Always documented.
+pub fn always() {}
+
+/// Only documented if the features in `package.metadata.docs.rs` are enabled.
+#[cfg(feature = "extra")]
+pub fn extra() {}
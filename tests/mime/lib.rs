@@ -0,0 +1,3 @@
+//! A crate.
+
+pub fn dummy() {}